@@ -0,0 +1,423 @@
+//! Alerts for consensus anomalies
+//!
+//! The node raises an alert when something suspicious happens: a reorg deeper
+//! than the configured depth, a block timestamped far in the future, a peer
+//! sending invalid blocks in bulk, no new block for a long time, or every
+//! connected peer sitting in one IP subnet, which is what an eclipse attack looks
+//! like. A pending transaction double-spending another one in the mempool is
+//! raised for merchants accepting unconfirmed payments. Revealing or exporting
+//! the wallet seed and overriding a wallet's spend limits are recorded the same
+//! way, as an audit trail. Alerts are appended to a file in the data directory
+//! so `list-alerts` and the `getalerts` RPC can show them after the fact.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::block::Block;
+use crate::blockchain::events::ChainEvent;
+use crate::mempool::MempoolEvent;
+
+/// File in the data directory holding raised alerts
+pub const ALERTS_FILE: &str = "alerts.json";
+
+/// Oldest alerts are dropped beyond this many
+pub const MAX_ALERTS: usize = 1000;
+
+/// How often the watcher checks for a stall while no events arrive
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Current time in seconds since the Unix epoch
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// What an alert is about
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    DeepReorg,
+    FutureBlock,
+    InvalidBlockSpam,
+    BlockStall,
+    PeerEclipse,
+    SeedRevealed,
+    KeyRevealed,
+    SpendLimitOverride,
+    DoubleSpend,
+}
+
+impl AlertKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertKind::DeepReorg => "deep_reorg",
+            AlertKind::FutureBlock => "future_block",
+            AlertKind::InvalidBlockSpam => "invalid_block_spam",
+            AlertKind::BlockStall => "block_stall",
+            AlertKind::PeerEclipse => "peer_eclipse",
+            AlertKind::SeedRevealed => "seed_revealed",
+            AlertKind::KeyRevealed => "key_revealed",
+            AlertKind::SpendLimitOverride => "spend_limit_override",
+            AlertKind::DoubleSpend => "double_spend",
+        }
+    }
+}
+
+/// One raised alert
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub message: String,
+    /// Seconds since the Unix epoch
+    pub raised_at: u64,
+}
+
+/// Thresholds at which alerts are raised
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertConfig {
+    /// Reorgs disconnecting at least this many blocks
+    pub reorg_depth: u64,
+    /// Blocks timestamped more than this many seconds ahead of our clock
+    pub max_future_secs: u64,
+    /// Invalid blocks from one peer within `invalid_block_window`
+    pub invalid_block_limit: usize,
+    pub invalid_block_window: Duration,
+    /// Time without a new block
+    pub stall_timeout: Duration,
+    /// Connected peers from which all of them sharing one subnet is reported
+    pub eclipse_min_peers: usize,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            reorg_depth: 3,
+            max_future_secs: 2 * 60 * 60,
+            invalid_block_limit: 10,
+            invalid_block_window: Duration::from_secs(10 * 60),
+            stall_timeout: Duration::from_secs(60 * 60),
+            eclipse_min_peers: 3,
+        }
+    }
+}
+
+/// Raised alerts, kept in memory and written to the alerts file. Clones share
+/// the list, so one log can be handed to every component that raises alerts.
+#[derive(Clone, Default)]
+pub struct AlertLog {
+    path: Option<PathBuf>,
+    alerts: Arc<Mutex<Vec<Alert>>>,
+}
+
+impl AlertLog {
+    /// Log that is not written to disk
+    pub fn new() -> Self {
+        AlertLog::default()
+    }
+
+    /// Path of the alerts file inside a data directory
+    pub fn path_in<P: AsRef<Path>>(data_dir: P) -> PathBuf {
+        data_dir.as_ref().join(ALERTS_FILE)
+    }
+
+    /// Open the alerts file of a data directory, starting empty when it does not exist yet
+    pub fn open<P: AsRef<Path>>(data_dir: P) -> Result<Self, String> {
+        let path = Self::path_in(data_dir);
+        let alerts = if path.exists() {
+            let data = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read alerts: {}", e))?;
+            serde_json::from_str(&data)
+                .map_err(|e| format!("Failed to parse alerts: {}", e))?
+        } else {
+            Vec::new()
+        };
+        Ok(AlertLog { path: Some(path), alerts: Arc::new(Mutex::new(alerts)) })
+    }
+
+    /// Record an alert and write the log out
+    pub fn raise(&self, kind: AlertKind, message: String) {
+        eprintln!("ALERT [{}]: {}", kind.name(), message);
+        let mut alerts = self.alerts.lock().unwrap();
+        alerts.push(Alert { kind, message, raised_at: now_secs() });
+        if alerts.len() > MAX_ALERTS {
+            let excess = alerts.len() - MAX_ALERTS;
+            alerts.drain(..excess);
+        }
+        if let Err(e) = self.save(&alerts) {
+            eprintln!("Warning: Failed to save alerts: {}", e);
+        }
+    }
+
+    fn save(&self, alerts: &[Alert]) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let data = serde_json::to_string_pretty(alerts)
+            .map_err(|e| format!("Failed to serialize alerts: {}", e))?;
+        fs::write(path, data)
+            .map_err(|e| format!("Failed to write alerts: {}", e))
+    }
+
+    /// All alerts, oldest first
+    pub fn alerts(&self) -> Vec<Alert> {
+        self.alerts.lock().unwrap().clone()
+    }
+}
+
+/// Subnet a peer address belongs to for diversity checks: the /16 of an IPv4
+/// address and the /32 of an IPv6 one
+pub fn peer_subnet(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!("{}.{}.0.0/16", octets[0], octets[1])
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => peer_subnet(IpAddr::V4(ip)),
+            None => {
+                let segments = ip.segments();
+                format!("{:x}:{:x}::/32", segments[0], segments[1])
+            },
+        },
+    }
+}
+
+/// Time of the last connected block, for stall detection
+struct StallState {
+    last_block: Instant,
+    raised: bool,
+}
+
+/// Checks chain events and peer blocks against the alert thresholds
+#[derive(Clone)]
+pub struct AlertMonitor {
+    config: AlertConfig,
+    log: AlertLog,
+    /// Times invalid blocks were received, by peer address
+    invalid_blocks: Arc<Mutex<HashMap<String, Vec<Instant>>>>,
+    stall: Arc<Mutex<StallState>>,
+    /// Whether the current lack of peer diversity was already reported
+    eclipse_raised: Arc<Mutex<bool>>,
+}
+
+impl AlertMonitor {
+    pub fn new(config: AlertConfig, log: AlertLog) -> Self {
+        AlertMonitor {
+            config,
+            log,
+            invalid_blocks: Arc::new(Mutex::new(HashMap::new())),
+            stall: Arc::new(Mutex::new(StallState { last_block: Instant::now(), raised: false })),
+            eclipse_raised: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    pub fn log(&self) -> &AlertLog {
+        &self.log
+    }
+
+    /// Raise alerts for deep reorgs, and note connected blocks for stall detection
+    pub fn check_event(&self, event: &ChainEvent) {
+        match event {
+            ChainEvent::BlockConnected(_) => {
+                let mut stall = self.stall.lock().unwrap();
+                stall.last_block = Instant::now();
+                stall.raised = false;
+            },
+            ChainEvent::Reorg { old_tip, new_tip, depth } if *depth >= self.config.reorg_depth => {
+                self.log.raise(AlertKind::DeepReorg, format!(
+                    "Reorg of {} blocks from {} to {}", depth, old_tip, new_tip
+                ));
+            },
+            ChainEvent::BlockDisconnected(_) | ChainEvent::Reorg { .. } => {},
+        }
+    }
+
+    /// Raise an alert for a double spend seen in the mempool
+    pub fn check_mempool_event(&self, event: &MempoolEvent) {
+        if let MempoolEvent::DoubleSpendDetected { sender, txid, conflicts_with } = event {
+            self.log.raise(AlertKind::DoubleSpend, format!(
+                "Transaction {} from {} conflicts with pending {}", txid, sender, conflicts_with.join(", ")
+            ));
+        }
+    }
+
+    /// Raise an alert for a peer's fork refused because it would replace final
+    /// or checkpointed blocks
+    pub fn record_refused_reorg(&self, peer: &str, reason: &str) {
+        self.log.raise(AlertKind::DeepReorg, format!("Refused fork from {}: {}", peer, reason));
+    }
+
+    /// Raise an alert if a block is timestamped too far ahead of our clock
+    pub fn check_block_time(&self, block: &Block, from: &str) {
+        let now = now_secs();
+        if block.header.timestamp > now + self.config.max_future_secs {
+            self.log.raise(AlertKind::FutureBlock, format!(
+                "Block {} from {} is timestamped {}s in the future",
+                block.header.hash, from, block.header.timestamp - now
+            ));
+        }
+    }
+
+    /// Count an invalid block from a peer, raising an alert once the peer
+    /// reaches the limit within the window
+    pub fn record_invalid_block(&self, peer: &str) {
+        let now = Instant::now();
+        let mut invalid_blocks = self.invalid_blocks.lock().unwrap();
+        let times = invalid_blocks.entry(peer.to_string()).or_default();
+        times.retain(|at| now.duration_since(*at) < self.config.invalid_block_window);
+        times.push(now);
+        if times.len() >= self.config.invalid_block_limit {
+            times.clear();
+            self.log.raise(AlertKind::InvalidBlockSpam, format!(
+                "Peer {} sent {} invalid blocks within {}s",
+                peer, self.config.invalid_block_limit, self.config.invalid_block_window.as_secs()
+            ));
+        }
+    }
+
+    /// Raise an alert, once per stall, if no block was connected for the stall timeout
+    pub fn check_stall(&self, now: Instant) {
+        let mut stall = self.stall.lock().unwrap();
+        let idle = now.saturating_duration_since(stall.last_block);
+        if !stall.raised && idle >= self.config.stall_timeout {
+            stall.raised = true;
+            self.log.raise(AlertKind::BlockStall, format!("No new block for {}s", idle.as_secs()));
+        }
+    }
+
+    /// Whether the chain has gone the stall timeout without a new block
+    pub fn is_stalled(&self) -> bool {
+        self.stall.lock().unwrap().raised
+    }
+
+    /// Check the addresses of the connected peers. Returns whether they all sit
+    /// in one subnet while there are enough of them to tell, raising an alert
+    /// once each time that starts.
+    pub fn check_peer_diversity(&self, peers: &[IpAddr]) -> bool {
+        let subnets: HashSet<String> = peers.iter().map(|ip| peer_subnet(*ip)).collect();
+        let eclipsed = peers.len() >= self.config.eclipse_min_peers && subnets.len() == 1;
+        let mut raised = self.eclipse_raised.lock().unwrap();
+        if eclipsed && !*raised {
+            let subnet = subnets.iter().next().cloned().unwrap_or_default();
+            self.log.raise(AlertKind::PeerEclipse, format!(
+                "All {} connected peers are in subnet {}", peers.len(), subnet
+            ));
+        }
+        *raised = eclipsed;
+        eclipsed
+    }
+
+    /// Check chain events on a background thread until every publisher is gone
+    pub fn watch(&self, events: Receiver<ChainEvent>) -> thread::JoinHandle<()> {
+        let monitor = self.clone();
+        thread::spawn(move || loop {
+            match events.recv_timeout(STALL_CHECK_INTERVAL) {
+                Ok(event) => monitor.check_event(&event),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            monitor.check_stall(Instant::now());
+        })
+    }
+
+    /// Check mempool events on a background thread until the mempool is gone
+    pub fn watch_mempool(&self, events: Receiver<MempoolEvent>) -> thread::JoinHandle<()> {
+        let monitor = self.clone();
+        thread::spawn(move || {
+            for event in events {
+                monitor.check_mempool_event(&event);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_thresholds() {
+        let config = AlertConfig { invalid_block_limit: 2, ..AlertConfig::default() };
+        let monitor = AlertMonitor::new(config.clone(), AlertLog::new());
+        let kinds = || monitor.log().alerts().iter().map(|alert| alert.kind).collect::<Vec<_>>();
+
+        let reorg = |depth| ChainEvent::Reorg { old_tip: "a".to_string(), new_tip: "b".to_string(), depth };
+        monitor.check_event(&reorg(2));
+        monitor.check_event(&reorg(3));
+        assert_eq!(kinds(), vec![AlertKind::DeepReorg]);
+
+        let future = Block::new("0".to_string(), vec![], 0, now_secs() + config.max_future_secs + 60, 1);
+        monitor.check_block_time(&future, "peer");
+        monitor.record_invalid_block("peer");
+        monitor.record_invalid_block("peer");
+        monitor.check_stall(Instant::now());
+        monitor.check_stall(Instant::now() + config.stall_timeout);
+        monitor.check_stall(Instant::now() + config.stall_timeout * 2);
+        assert_eq!(kinds(), vec![
+            AlertKind::DeepReorg,
+            AlertKind::FutureBlock,
+            AlertKind::InvalidBlockSpam,
+            AlertKind::BlockStall,
+        ]);
+        assert!(monitor.is_stalled());
+
+        monitor.check_mempool_event(&MempoolEvent::DoubleSpendDetected {
+            sender: "alice".to_string(),
+            txid: "b".to_string(),
+            conflicts_with: vec!["a".to_string()],
+        });
+        let alerts = monitor.log().alerts();
+        assert_eq!(alerts.last().unwrap().kind, AlertKind::DoubleSpend);
+        assert!(alerts.last().unwrap().message.contains("conflicts with pending a"));
+    }
+
+    #[test]
+    fn test_peer_diversity() {
+        let monitor = AlertMonitor::new(AlertConfig::default(), AlertLog::new());
+        let ips = |addresses: &[&str]| addresses.iter().map(|ip| ip.parse().unwrap()).collect::<Vec<IpAddr>>();
+
+        assert_eq!(peer_subnet("10.1.2.3".parse().unwrap()), "10.1.0.0/16");
+        assert_eq!(peer_subnet("::ffff:10.1.2.3".parse().unwrap()), "10.1.0.0/16");
+        assert_eq!(peer_subnet("2001:db8:1::1".parse().unwrap()), "2001:db8::/32");
+
+        // Too few peers to tell, then a diverse set
+        assert!(!monitor.check_peer_diversity(&ips(&["10.1.0.1", "10.1.0.2"])));
+        assert!(!monitor.check_peer_diversity(&ips(&["10.1.0.1", "10.1.0.2", "192.168.0.1"])));
+        assert!(monitor.log().alerts().is_empty());
+
+        // One alert while the peers stay in one subnet, another once it happens again
+        let eclipsed = ips(&["10.1.0.1", "10.1.0.2", "10.1.9.9"]);
+        assert!(monitor.check_peer_diversity(&eclipsed));
+        assert!(monitor.check_peer_diversity(&eclipsed));
+        assert_eq!(monitor.log().alerts().len(), 1);
+        assert!(!monitor.check_peer_diversity(&ips(&["10.1.0.1", "10.1.0.2", "10.2.0.1"])));
+        assert!(monitor.check_peer_diversity(&eclipsed));
+        let kinds: Vec<AlertKind> = monitor.log().alerts().iter().map(|alert| alert.kind).collect();
+        assert_eq!(kinds, vec![AlertKind::PeerEclipse, AlertKind::PeerEclipse]);
+    }
+
+    #[test]
+    fn test_alerts_are_persisted() {
+        let dir = std::env::temp_dir().join(format!("rust_chain_alerts_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let log = AlertLog::open(&dir).unwrap();
+        log.raise(AlertKind::BlockStall, "No new block for 3600s".to_string());
+
+        let reopened = AlertLog::open(&dir).unwrap().alerts();
+        assert_eq!(reopened.len(), 1);
+        assert_eq!(reopened[0].kind, AlertKind::BlockStall);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! Amount denominations
+//!
+//! Amounts are stored, validated and sent over the wire as whole base units in a
+//! `u64`. For people they are also written in coins of `UNITS_PER_COIN` units,
+//! always with all eight decimals so columns line up and no precision is hidden:
+//! 150000000 units is `1.50000000`. Amounts typed by a user are always read as
+//! coins, with or without a decimal point: `1`, `1.0` and `1.00000000` are the
+//! same 100000000 units.
+//!
+//! No amount or balance consensus accepts may exceed `MAX_MONEY`. Sums of amounts
+//! go through `checked_money_add`, so an overflowing block is refused instead of
+//! wrapping, whatever the width of the machine it is checked on.
+
+/// Decimal places of a coin
+pub const COIN_DECIMALS: u32 = 8;
+
+/// Base units in one coin
+pub const UNITS_PER_COIN: u64 = 10u64.pow(COIN_DECIMALS);
+
+/// Most base units that can ever exist, a bound on every amount and balance: the
+/// 21,000,000 coins of the genesis allocation and as many again from mining
+pub const MAX_MONEY: u64 = 42_000_000 * UNITS_PER_COIN;
+
+/// Whether `units` is a valid amount, from zero to `MAX_MONEY`
+pub fn money_range(units: u64) -> bool {
+    units <= MAX_MONEY
+}
+
+/// Refuse amounts above `MAX_MONEY`
+pub fn check_money_range(units: u64) -> Result<(), String> {
+    if !money_range(units) {
+        return Err(format!("amount {} exceeds the maximum of {} units", units, MAX_MONEY));
+    }
+    Ok(())
+}
+
+/// `a + b`, refusing sums that overflow or exceed `MAX_MONEY`
+pub fn checked_money_add(a: u64, b: u64) -> Result<u64, String> {
+    a.checked_add(b)
+        .filter(|sum| money_range(*sum))
+        .ok_or_else(|| format!("{} + {} exceeds the maximum of {} units", a, b, MAX_MONEY))
+}
+
+/// Base units as a coin string, e.g. `1.50000000`
+pub fn format_coins(units: u64) -> String {
+    format!("{}.{:0width$}", units / UNITS_PER_COIN, units % UNITS_PER_COIN, width = COIN_DECIMALS as usize)
+}
+
+/// Base units in coins with the raw count alongside, for CLI output
+pub fn display_amount(units: u64) -> String {
+    format!("{} coins ({} units)", format_coins(units), units)
+}
+
+/// Parse a coin string such as `1.5`, `0.00000001` or `21`, refusing more than
+/// eight decimals and amounts that do not fit in a `u64`
+pub fn parse_coins(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid amount '{}'", value);
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !digits(whole) || !digits(fraction) {
+        return Err(invalid());
+    }
+    if fraction.len() > COIN_DECIMALS as usize {
+        return Err(format!("Amount '{}' has more than {} decimals", value, COIN_DECIMALS));
+    }
+
+    let whole = match whole {
+        "" => 0,
+        whole => whole.parse::<u64>().map_err(|_| invalid())?,
+    };
+    let fraction = match fraction {
+        "" => 0,
+        fraction => fraction.parse::<u64>().map_err(|_| invalid())? * 10u64.pow(COIN_DECIMALS - fraction.len() as u32),
+    };
+    whole.checked_mul(UNITS_PER_COIN)
+        .and_then(|units| units.checked_add(fraction))
+        .ok_or_else(|| format!("Amount '{}' is too large", value))
+}
+
+/// Parse an amount typed by a user, in coins whether or not it has a decimal point
+pub fn parse_amount(value: &str) -> Result<u64, String> {
+    parse_coins(value.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_and_parse_coins() {
+        assert_eq!(format_coins(150_000_000), "1.50000000");
+        assert_eq!(format_coins(1), "0.00000001");
+        assert_eq!(format_coins(0), "0.00000000");
+        assert_eq!(format_coins(u64::MAX), "184467440737.09551615");
+        assert_eq!(display_amount(50), "0.00000050 coins (50 units)");
+
+        assert_eq!(parse_coins("1.5"), Ok(150_000_000));
+        assert_eq!(parse_coins("0.00000001"), Ok(1));
+        assert_eq!(parse_coins(".25"), Ok(25_000_000));
+        assert_eq!(parse_coins("21"), Ok(21 * UNITS_PER_COIN));
+        assert_eq!(parse_coins("184467440737.09551615"), Ok(u64::MAX));
+        assert!(parse_coins("184467440737.09551616").is_err());
+        assert!(parse_coins("0.000000001").is_err());
+        assert!(parse_coins("-1.5").is_err());
+        assert!(parse_coins("1.5.0").is_err());
+        assert!(parse_coins(".").is_err());
+        assert!(parse_coins("").is_err());
+
+        for units in [0, 1, 99_999_999, 150_000_000, u64::MAX] {
+            assert_eq!(parse_coins(&format_coins(units)), Ok(units));
+        }
+    }
+
+    #[test]
+    fn test_parse_amount_reads_coins() {
+        assert_eq!(parse_amount("1.5"), Ok(150_000_000));
+        assert_eq!(parse_amount("1.0"), Ok(UNITS_PER_COIN));
+        // Whole numbers are coins too
+        assert_eq!(parse_amount("1"), Ok(UNITS_PER_COIN));
+        assert_eq!(parse_amount(" 150 "), Ok(150 * UNITS_PER_COIN));
+        assert_eq!(parse_amount("0.00000001"), Ok(1));
+        assert!(parse_amount("1e8").is_err());
+        assert!(parse_amount("-5").is_err());
+    }
+
+    #[test]
+    fn test_money_range_boundaries() {
+        assert!(money_range(0));
+        assert!(money_range(MAX_MONEY));
+        assert!(!money_range(MAX_MONEY + 1));
+        assert!(check_money_range(u64::MAX).is_err());
+        // Any amount in range fits a signed 64-bit delta
+        assert!(MAX_MONEY <= i64::MAX as u64);
+
+        assert_eq!(checked_money_add(MAX_MONEY - 1, 1), Ok(MAX_MONEY));
+        assert!(checked_money_add(MAX_MONEY, 1).is_err());
+        assert!(checked_money_add(u64::MAX, 1).is_err());
+        assert!(checked_money_add(1, u64::MAX).is_err());
+    }
+}
@@ -0,0 +1,590 @@
+//! Time series of chain activity
+//!
+//! `export-analytics` walks the chain once, oldest block first, folding blocks
+//! into one row per block or per UTC day. A row is written as soon as its bucket
+//! closes, so only the bucket being filled is held in memory and long chains
+//! stream through the block store without loading it.
+//!
+//! The `/charts` endpoints of the RPC server are drawn from an `AnalyticsSeries`
+//! instead: one point per connected block and a mempool sample a minute, kept
+//! in bounded windows and saved to `analytics_series.json`. The server adds the
+//! blocks connected since its last sample, so a chart never scans the chain.
+
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::block::Block;
+use crate::blockchain::chain::Chain;
+use crate::blockchain::genesis::is_coinbase_transaction;
+use crate::consensus::target::Target;
+use crate::consensus::timestamps;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// File in the data directory holding the series the charts are drawn from
+pub const ANALYTICS_SERIES_FILE: &str = "analytics_series.json";
+
+/// Most recent blocks kept in the series
+pub const SERIES_BLOCK_WINDOW: usize = 20_000;
+
+/// Mempool samples kept in the series, a week at one a minute
+pub const SERIES_MEMPOOL_WINDOW: usize = 7 * 24 * 60;
+
+/// Most points a chart is downsampled to
+pub const MAX_CHART_POINTS: u64 = 200;
+
+/// Seconds a chart covers when the request gives no window
+pub const DEFAULT_CHART_WINDOW: u64 = SECONDS_PER_DAY;
+
+/// First line of an exported series
+pub const ANALYTICS_CSV_HEADER: &str =
+    "start_height,end_height,start_time,blocks,transactions,fees,size_bytes,difficulty,active_addresses,block_interval";
+
+/// Span of chain covered by one row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyticsInterval {
+    Blocks,
+    Daily,
+}
+
+impl FromStr for AnalyticsInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blocks" => Ok(AnalyticsInterval::Blocks),
+            "daily" => Ok(AnalyticsInterval::Daily),
+            other => Err(format!("Unknown interval '{}', expected blocks or daily", other)),
+        }
+    }
+}
+
+impl AnalyticsInterval {
+    /// Key shared by the blocks of one row
+    fn bucket(&self, block: &Block) -> u64 {
+        match self {
+            AnalyticsInterval::Blocks => block.header.height,
+            AnalyticsInterval::Daily => block.header.timestamp / SECONDS_PER_DAY,
+        }
+    }
+}
+
+/// Activity within one block or one day
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyticsRow {
+    pub start_height: u64,
+    pub end_height: u64,
+    /// Timestamp of the block, or the start of the day for daily rows
+    pub start_time: u64,
+    pub blocks: u64,
+    pub transactions: u64,
+    pub fees: u64,
+    pub size_bytes: u64,
+    /// Average difficulty of the blocks, see `BlockHeader::difficulty`
+    pub difficulty: f64,
+    /// Distinct senders and recipients, not counting coinbase senders
+    pub active_addresses: usize,
+    /// Average seconds since the previous block, by effective time; None for the
+    /// genesis block alone
+    pub block_interval: Option<f64>,
+}
+
+impl AnalyticsRow {
+    pub fn to_csv(&self) -> String {
+        format!("{},{},{},{},{},{},{},{:.2},{},{}",
+            self.start_height, self.end_height, self.start_time, self.blocks, self.transactions,
+            self.fees, self.size_bytes, self.difficulty, self.active_addresses,
+            self.block_interval.map_or_else(String::new, |interval| format!("{:.2}", interval)))
+    }
+}
+
+/// Row being filled, with the totals its averages come from
+struct Bucket {
+    key: u64,
+    row: AnalyticsRow,
+    difficulty_total: f64,
+    interval_total: u64,
+    intervals: u64,
+    addresses: HashSet<String>,
+}
+
+impl Bucket {
+    fn into_row(self) -> AnalyticsRow {
+        AnalyticsRow {
+            difficulty: self.difficulty_total / self.row.blocks as f64,
+            active_addresses: self.addresses.len(),
+            block_interval: timestamps::average_interval(self.interval_total, self.intervals),
+            ..self.row
+        }
+    }
+}
+
+/// Folds blocks, given in height order, into rows written to `out` as CSV
+pub struct AnalyticsWriter<W: Write> {
+    interval: AnalyticsInterval,
+    /// Limit of the chain the blocks come from, which difficulties are relative to
+    pow_limit: Target,
+    out: W,
+    current: Option<Bucket>,
+    /// Effective time of the previous block, see `consensus::timestamps`
+    previous_time: Option<u64>,
+    rows: usize,
+}
+
+impl<W: Write> AnalyticsWriter<W> {
+    /// Start a series of blocks from a chain with the given `pow_limit`, writing
+    /// the header line
+    pub fn new(mut out: W, interval: AnalyticsInterval, pow_limit: Target) -> Result<Self, String> {
+        writeln!(out, "{}", ANALYTICS_CSV_HEADER)
+            .map_err(|e| format!("Failed to write analytics: {}", e))?;
+        Ok(AnalyticsWriter { interval, pow_limit, out, current: None, previous_time: None, rows: 0 })
+    }
+
+    /// Add the next block, which paid `fees`
+    pub fn add_block(&mut self, block: &Block, fees: u64) -> Result<(), String> {
+        let key = self.interval.bucket(block);
+        if self.current.as_ref().is_some_and(|bucket| bucket.key != key) {
+            self.flush()?;
+        }
+        let start_time = match self.interval {
+            AnalyticsInterval::Blocks => block.header.timestamp,
+            AnalyticsInterval::Daily => key * SECONDS_PER_DAY,
+        };
+        let bucket = self.current.get_or_insert_with(|| Bucket {
+            key,
+            row: AnalyticsRow {
+                start_height: block.header.height,
+                end_height: block.header.height,
+                start_time,
+                blocks: 0,
+                transactions: 0,
+                fees: 0,
+                size_bytes: 0,
+                difficulty: 0.0,
+                active_addresses: 0,
+                block_interval: None,
+            },
+            difficulty_total: 0.0,
+            interval_total: 0,
+            intervals: 0,
+            addresses: HashSet::new(),
+        });
+
+        bucket.row.end_height = block.header.height;
+        bucket.row.blocks += 1;
+        bucket.row.transactions += block.transactions.len() as u64;
+        bucket.row.fees += fees;
+        bucket.row.size_bytes += serde_json::to_string(block).unwrap_or_default().len() as u64;
+        bucket.difficulty_total += block.header.difficulty(&self.pow_limit);
+        if let Some(previous) = self.previous_time {
+            bucket.interval_total += timestamps::block_interval(previous, block.header.timestamp);
+            bucket.intervals += 1;
+        }
+        for tx in &block.transactions {
+            if !is_coinbase_transaction(tx) {
+                bucket.addresses.insert(tx.from.clone());
+            }
+            bucket.addresses.insert(tx.to.clone());
+        }
+        self.previous_time = Some(block.header.timestamp.max(self.previous_time.unwrap_or(0)));
+        Ok(())
+    }
+
+    /// Write the last row and flush the output, returning the number of rows written
+    pub fn finish(mut self) -> Result<usize, String> {
+        self.flush()?;
+        self.out.flush().map_err(|e| format!("Failed to write analytics: {}", e))?;
+        Ok(self.rows)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let Some(bucket) = self.current.take() else {
+            return Ok(());
+        };
+        writeln!(self.out, "{}", bucket.into_row().to_csv())
+            .map_err(|e| format!("Failed to write analytics: {}", e))?;
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+/// Quantity a chart plots over time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartMetric {
+    /// Difficulty of the blocks, as `getblock` and `export-analytics` report
+    Difficulty,
+    /// Seconds between blocks, by effective time
+    BlockInterval,
+    /// Confirmed transactions per second
+    TransactionRate,
+    /// Transactions waiting in the mempool
+    MempoolSize,
+}
+
+impl FromStr for ChartMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "difficulty" => Ok(ChartMetric::Difficulty),
+            "block-interval" => Ok(ChartMetric::BlockInterval),
+            "tx-rate" => Ok(ChartMetric::TransactionRate),
+            "mempool-size" => Ok(ChartMetric::MempoolSize),
+            other => Err(format!(
+                "Unknown chart '{}', expected difficulty, block-interval, tx-rate or mempool-size", other)),
+        }
+    }
+}
+
+impl ChartMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChartMetric::Difficulty => "difficulty",
+            ChartMetric::BlockInterval => "block-interval",
+            ChartMetric::TransactionRate => "tx-rate",
+            ChartMetric::MempoolSize => "mempool-size",
+        }
+    }
+}
+
+/// One block of the active chain in the stored series
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockPoint {
+    pub height: u64,
+    pub hash: String,
+    /// Effective time, see `consensus::timestamps`
+    pub time: u64,
+    /// See `BlockHeader::difficulty`
+    pub difficulty: f64,
+    pub transactions: u64,
+    /// Seconds since the previous block; None when that block is not in the series
+    pub interval: Option<u64>,
+}
+
+/// Mempool contents at one moment
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MempoolSample {
+    pub time: u64,
+    pub transactions: usize,
+    pub size_bytes: usize,
+}
+
+/// Value of a metric over the bucket starting at `time`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChartPoint {
+    pub time: u64,
+    pub value: f64,
+}
+
+/// A metric over a window ending now, downsampled to at most `MAX_CHART_POINTS`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Chart {
+    pub metric: String,
+    pub window: u64,
+    /// Seconds covered by each point
+    pub bucket_seconds: u64,
+    /// Oldest first. Buckets without data are left out, except that the
+    /// transaction rate is zero in buckets without blocks once the series starts.
+    pub points: Vec<ChartPoint>,
+}
+
+/// Stored time series of the active chain and the mempool, oldest first
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AnalyticsSeries {
+    blocks: VecDeque<BlockPoint>,
+    mempool: VecDeque<MempoolSample>,
+}
+
+impl AnalyticsSeries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn path_in(data_dir: &str) -> PathBuf {
+        Path::new(data_dir).join(ANALYTICS_SERIES_FILE)
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &BlockPoint> {
+        self.blocks.iter()
+    }
+
+    pub fn mempool_samples(&self) -> impl Iterator<Item = &MempoolSample> {
+        self.mempool.iter()
+    }
+
+    /// Add the block connected on top of the series, dropping the oldest point
+    /// once the window is full. `pow_limit` is the chain's.
+    pub fn add_block(&mut self, block: &Block, pow_limit: &Target) {
+        let previous = self.blocks.back().filter(|point| point.height + 1 == block.header.height);
+        let point = BlockPoint {
+            height: block.header.height,
+            hash: block.header.hash.clone(),
+            time: previous.map_or(block.header.timestamp, |point| point.time.max(block.header.timestamp)),
+            difficulty: block.header.difficulty(pow_limit),
+            transactions: block.transactions.len() as u64,
+            interval: previous.map(|point| timestamps::block_interval(point.time, block.header.timestamp)),
+        };
+        if self.blocks.len() >= SERIES_BLOCK_WINDOW {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(point);
+    }
+
+    /// Bring the block points in line with `chain`: drop those of blocks a reorg
+    /// disconnected, then add the blocks connected since the last one stored.
+    /// An empty series starts `SERIES_BLOCK_WINDOW` blocks below the tip.
+    /// Returns the number of blocks added.
+    pub fn sync(&mut self, chain: &Chain) -> Result<usize, String> {
+        while let Some(last) = self.blocks.back() {
+            if chain.block_hash_at(last.height)?.as_deref() == Some(last.hash.as_str()) {
+                break;
+            }
+            self.blocks.pop_back();
+        }
+
+        let tip = chain.height();
+        let from = match self.blocks.back() {
+            Some(last) => last.height + 1,
+            None => tip.saturating_sub(SERIES_BLOCK_WINDOW as u64 - 1),
+        };
+        if from > tip {
+            return Ok(0);
+        }
+        let pow_limit = chain.params().pow_limit();
+        let mut added = 0;
+        chain.scan_blocks(from, tip, |block| {
+            self.add_block(block, &pow_limit);
+            added += 1;
+        })?;
+        Ok(added)
+    }
+
+    /// Add a mempool sample, dropping the oldest once the window is full
+    pub fn record_mempool(&mut self, sample: MempoolSample) {
+        if self.mempool.len() >= SERIES_MEMPOOL_WINDOW {
+            self.mempool.pop_front();
+        }
+        self.mempool.push_back(sample);
+    }
+
+    /// `metric` over the `window` seconds up to `now`
+    pub fn chart(&self, metric: ChartMetric, window: u64, now: u64) -> Chart {
+        let window = window.max(1);
+        let start = now.saturating_sub(window);
+        let bucket_seconds = window.div_ceil(MAX_CHART_POINTS);
+        let buckets = window.div_ceil(bucket_seconds) as usize;
+        // Total and number of values in each bucket
+        let mut sums: Vec<Option<(f64, u64)>> = vec![None; buckets];
+        let mut add = |time: u64, value: f64| {
+            if (start..=now).contains(&time) {
+                let bucket = (((time - start) / bucket_seconds) as usize).min(buckets - 1);
+                let (total, count) = sums[bucket].get_or_insert((0.0, 0));
+                *total += value;
+                *count += 1;
+            }
+        };
+
+        match metric {
+            ChartMetric::Difficulty => self.blocks.iter().for_each(|point| add(point.time, point.difficulty)),
+            ChartMetric::BlockInterval => self.blocks.iter()
+                .filter_map(|point| point.interval.map(|interval| (point.time, interval)))
+                .for_each(|(time, interval)| add(time, interval as f64)),
+            ChartMetric::TransactionRate => self.blocks.iter().for_each(|point| add(point.time, point.transactions as f64)),
+            ChartMetric::MempoolSize => self.mempool.iter().for_each(|sample| add(sample.time, sample.transactions as f64)),
+        }
+
+        let first = sums.iter().position(Option::is_some).unwrap_or(buckets);
+        let points = sums.into_iter().enumerate().skip(first)
+            .filter_map(|(bucket, sum)| {
+                let value = match (metric, sum) {
+                    (ChartMetric::TransactionRate, sum) => {
+                        let (transactions, _) = sum.unwrap_or_default();
+                        transactions / bucket_seconds as f64
+                    },
+                    (_, Some((total, count))) => total / count as f64,
+                    (_, None) => return None,
+                };
+                Some(ChartPoint { time: start + bucket as u64 * bucket_seconds, value })
+            })
+            .collect();
+
+        Chart { metric: metric.name().to_string(), window, bucket_seconds, points }
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let data = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize analytics series: {}", e))?;
+        fs::write(path, data)
+            .map_err(|e| format!("Failed to write analytics series: {}", e))
+    }
+
+    /// Load a saved series, or None if none was written yet
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Option<Self>, String> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read analytics series: {}", e))?;
+        serde_json::from_str(&data)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse analytics series: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::block::Transaction;
+
+    fn payment(from: &str, to: &str) -> Transaction {
+        Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        }
+    }
+
+    fn export(blocks: &[Block], interval: AnalyticsInterval) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut writer = AnalyticsWriter::new(&mut out, interval, Target::from_leading_zeros(2)).unwrap();
+        for block in blocks {
+            writer.add_block(block, 2).unwrap();
+        }
+        let rows = writer.finish().unwrap();
+        let lines: Vec<String> = String::from_utf8(out).unwrap().lines().map(str::to_string).collect();
+        assert_eq!(lines.len(), rows + 1);
+        lines
+    }
+
+    #[test]
+    fn test_analytics_series() {
+        let day = SECONDS_PER_DAY;
+        let blocks = vec![
+            Block::new("0".to_string(), vec![payment("alice", "bob")], 0, day + 10, 0),
+            Block::new("a".to_string(), vec![payment("bob", "carol"), payment("alice", "carol")], 0, day + 70, 1),
+            Block::new("b".to_string(), vec![], 0, 2 * day + 5, 2),
+        ];
+        assert!("weekly".parse::<AnalyticsInterval>().is_err());
+
+        let lines = export(&blocks, AnalyticsInterval::Blocks);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], ANALYTICS_CSV_HEADER);
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(&fields[..4], &["0", "0", (day + 10).to_string().as_str(), "1"]);
+        assert_eq!(fields[8], "2");
+        // The first block has no previous one
+        assert_eq!(fields[9], "");
+        assert!(lines[2].ends_with(",3,60.00"));
+
+        let lines = export(&blocks, AnalyticsInterval::Daily);
+        assert_eq!(lines.len(), 3);
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(&fields[..6], &["0", "1", day.to_string().as_str(), "2", "3", "4"]);
+        assert_eq!(fields[8], "3");
+        assert_eq!(fields[9], "60.00");
+        let fields: Vec<&str> = lines[2].split(',').collect();
+        assert_eq!(&fields[..5], &["2", "2", (2 * day).to_string().as_str(), "1", "0"]);
+        assert_eq!(fields[9], format!("{}.00", day - 65));
+    }
+
+    #[test]
+    fn test_difficulty_comes_from_block_work() {
+        let pow_limit = Target::from_leading_zeros(2);
+        let blocks = vec![
+            Block::new_with_bits("0".to_string(), vec![], 0, 1_000, 0, 0x1f00ffff),
+            Block::new_with_bits("a".to_string(), vec![], 0, 1_060, 1, pow_limit.to_compact()),
+        ];
+        let difficulty = blocks[0].header.difficulty(&pow_limit);
+        assert_eq!(difficulty, blocks[0].work() as f64 / 256.0);
+
+        // The export and the stored series agree with each other and with getblock
+        let lines = export(&blocks, AnalyticsInterval::Blocks);
+        assert_eq!(lines[1].split(',').nth(7).unwrap(), format!("{:.2}", difficulty));
+        assert_eq!(lines[2].split(',').nth(7).unwrap(), "1.00");
+        let mut series = AnalyticsSeries::new();
+        series.add_block(&blocks[0], &pow_limit);
+        assert_eq!(series.blocks().next().unwrap().difficulty, difficulty);
+        let chart = series.chart(ChartMetric::Difficulty, 100, 1_050);
+        assert_eq!(chart.points, vec![ChartPoint { time: 1_000, value: difficulty }]);
+    }
+
+    #[test]
+    fn test_analytics_same_second_and_backwards_blocks() {
+        let blocks = vec![
+            Block::new("0".to_string(), vec![], 0, 1_000, 0),
+            Block::new("a".to_string(), vec![], 0, 1_000, 1),
+            Block::new("b".to_string(), vec![], 0, 1_000, 2),
+            // Stamped before its parent, so no time passes
+            Block::new("c".to_string(), vec![], 0, 940, 3),
+            Block::new("d".to_string(), vec![], 0, 1_060, 4),
+        ];
+        let lines = export(&blocks, AnalyticsInterval::Blocks);
+        assert!(lines[2].ends_with(",0.00"));
+        assert!(lines[4].ends_with(",0.00"));
+        // Measured from the latest earlier block rather than the one stamped 940
+        assert!(lines[5].ends_with(",60.00"));
+
+        let lines = export(&blocks, AnalyticsInterval::Daily);
+        assert!(lines[1].ends_with(",15.00"));
+    }
+
+    #[test]
+    fn test_series_follows_chain_through_reorg() {
+        let block = |previous: &str, time: u64, height: u64| Block::new(previous.to_string(), vec![payment("alice", "bob")], 0, time, height);
+        let genesis = block("0", 1_000, 0);
+        let a1 = block(&genesis.header.hash, 1_060, 1);
+        let a2 = block(&a1.header.hash, 1_120, 2);
+        let b1 = block(&genesis.header.hash, 1_090, 1);
+
+        let mut series = AnalyticsSeries::new();
+        let chain = Chain::from_blocks(vec![genesis.clone(), a1, a2]);
+        assert_eq!(series.sync(&chain).unwrap(), 3);
+        assert_eq!(series.sync(&chain).unwrap(), 0);
+
+        // The branch replaced both blocks above genesis
+        assert_eq!(series.sync(&Chain::from_blocks(vec![genesis, b1.clone()])).unwrap(), 1);
+        let points: Vec<&BlockPoint> = series.blocks().collect();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].hash, b1.header.hash);
+        assert_eq!(points[1].interval, Some(90));
+    }
+
+    #[test]
+    fn test_series_charts() {
+        let mut series = AnalyticsSeries::new();
+        for (height, time, transactions) in [(0, 1_000, 1), (1, 1_060, 3), (2, 1_180, 0)] {
+            series.add_block(&Block::new(height.to_string(), vec![payment("alice", "bob"); transactions], 0, time, height), &Target::MAX);
+        }
+        series.record_mempool(MempoolSample { time: 1_100, transactions: 4, size_bytes: 400 });
+        series.record_mempool(MempoolSample { time: 1_150, transactions: 6, size_bytes: 600 });
+        assert!("hashrate".parse::<ChartMetric>().is_err());
+
+        let chart = series.chart("block-interval".parse().unwrap(), 200, 1_200);
+        assert_eq!(chart.bucket_seconds, 1);
+        assert_eq!(chart.points, vec![
+            ChartPoint { time: 1_060, value: 60.0 },
+            ChartPoint { time: 1_180, value: 120.0 },
+        ]);
+
+        // Ten-second buckets from the first block on, empty ones at zero
+        let chart = series.chart(ChartMetric::TransactionRate, 2_000, 1_200);
+        assert_eq!(chart.bucket_seconds, 10);
+        assert_eq!(chart.points.len(), 100);
+        assert_eq!(chart.points[0], ChartPoint { time: 1_000, value: 0.1 });
+        assert_eq!(chart.points[1].value, 0.0);
+        assert_eq!(chart.points[6], ChartPoint { time: 1_060, value: 0.3 });
+
+        // Both samples fall into one bucket and are averaged
+        let chart = series.chart(ChartMetric::MempoolSize, 20_000, 1_200);
+        assert_eq!(chart.points, vec![ChartPoint { time: 1_100, value: 5.0 }]);
+        assert!(series.chart(ChartMetric::Difficulty, 100, 50_000).points.is_empty());
+    }
+}
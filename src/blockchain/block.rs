@@ -1,15 +1,42 @@
 use serde::{Serialize, Deserialize};
+use crate::consensus::target::Target;
 use crate::crypto::hash::sha256_hash;
+use std::fmt;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Transaction {
 	pub from: String,
 	pub to: String,
 	pub amount: u64,
 	pub signature: Vec<u8>,
+	/// First block height the transaction may be included at
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub lock_height: Option<u64>,
+	/// Last block height the transaction may be included at
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub expiry_height: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// Transaction and block hashes are computed from the Debug output, so the
+// height bounds are only printed when set to keep existing hashes unchanged.
+impl fmt::Debug for Transaction {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut debug = f.debug_struct("Transaction");
+		debug.field("from", &self.from)
+			.field("to", &self.to)
+			.field("amount", &self.amount)
+			.field("signature", &self.signature);
+		if let Some(lock_height) = &self.lock_height {
+			debug.field("lock_height", lock_height);
+		}
+		if let Some(expiry_height) = &self.expiry_height {
+			debug.field("expiry_height", expiry_height);
+		}
+		debug.finish()
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct BlockHeader {
 	pub previous_hash: String,
 	pub timestamp: u64,
@@ -17,6 +44,48 @@ pub struct BlockHeader {
 	pub merkle_root: String,
 	pub hash: String,
 	pub height: u64,
+	/// Compact proof of work target. Blocks stored before headers carried one
+	/// were mined to `DEFAULT_DIFFICULTY` leading zeros.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub bits: Option<u32>,
+}
+
+// Part of the block hash preimage, so bits are only printed when set to keep
+// the hashes of stored blocks unchanged
+impl fmt::Debug for BlockHeader {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut debug = f.debug_struct("BlockHeader");
+		debug.field("previous_hash", &self.previous_hash)
+			.field("timestamp", &self.timestamp)
+			.field("nonce", &self.nonce)
+			.field("merkle_root", &self.merkle_root)
+			.field("hash", &self.hash)
+			.field("height", &self.height);
+		if let Some(bits) = &self.bits {
+			debug.field("bits", bits);
+		}
+		debug.finish()
+	}
+}
+
+impl BlockHeader {
+	/// Target whose work the header proves: the one it was mined to, or for a
+	/// header without bits the easiest target, see `Block::work`
+	pub fn target(&self) -> Target {
+		self.bits.and_then(|bits| Target::from_compact(bits).ok())
+			.unwrap_or(Target::MAX)
+	}
+
+	/// Difficulty of the header's target against the chain's `pow_limit`, see
+	/// `Target::difficulty`
+	pub fn difficulty(&self, pow_limit: &Target) -> f64 {
+		self.target().difficulty(pow_limit)
+	}
+
+	/// Header serialized as hex (the JSON payload of its storage record)
+	pub fn to_hex(&self) -> String {
+		hex::encode(serde_json::to_vec(self).expect("Header serialization cannot fail"))
+	}
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -25,7 +94,54 @@ pub struct Block {
 	pub transactions: Vec<Transaction>,
 }
 
-fn calculate_merkle_root(transactions: &Vec<Transaction>) -> String {
+impl Transaction {
+	/// Transaction id as used by the chain's transaction index
+	pub fn hash(&self) -> String {
+		sha256_hash(&format!("{:?}", self))
+	}
+
+	/// Message covered by the transaction signature
+	pub fn signing_message(&self) -> String {
+		let mut message = format!("{}:{}:{}", self.from, self.to, self.amount);
+		if self.lock_height.is_some() || self.expiry_height.is_some() {
+			let bound = |h: Option<u64>| h.map(|h| h.to_string()).unwrap_or_default();
+			message.push_str(&format!(":{}:{}", bound(self.lock_height), bound(self.expiry_height)));
+		}
+		message
+	}
+
+	/// Check that the transaction may be included in a block at `height`
+	pub fn check_height_bounds(&self, height: u64) -> Result<(), String> {
+		if let Some(lock_height) = self.lock_height.filter(|&lock_height| height < lock_height) {
+			return Err(format!("transaction is locked until height {} (block height {})", lock_height, height));
+		}
+		if let Some(expiry_height) = self.expiry_height.filter(|&expiry_height| height > expiry_height) {
+			return Err(format!("transaction expired at height {} (block height {})", expiry_height, height));
+		}
+		Ok(())
+	}
+
+	/// Canonical byte serialization (the same JSON encoding used by storage)
+	pub fn serialize(&self) -> Vec<u8> {
+		serde_json::to_vec(self).expect("Transaction serialization cannot fail")
+	}
+
+	/// Canonical serialization as a hex string
+	pub fn to_hex(&self) -> String {
+		hex::encode(self.serialize())
+	}
+
+	/// Decode a transaction from its canonical hex serialization
+	pub fn from_hex(data: &str) -> Result<Self, String> {
+		let bytes = hex::decode(data.trim())
+			.map_err(|e| format!("Invalid hex encoding: {}", e))?;
+		serde_json::from_slice(&bytes)
+			.map_err(|e| format!("Failed to decode transaction: {}", e))
+	}
+}
+
+/// Merkle root over the transaction ids, duplicating the last hash of odd levels
+pub(crate) fn calculate_merkle_root(transactions: &Vec<Transaction>) -> String {
 	if transactions.is_empty() {
 		return sha256_hash("");
 	}
@@ -46,6 +162,15 @@ fn calculate_merkle_root(transactions: &Vec<Transaction>) -> String {
 
 impl Block {
 	pub fn new(previous_hash: String, transactions: Vec<Transaction>, nonce: u64, timestamp: u64, height: u64) -> Self {
+		Self::build(previous_hash, transactions, nonce, timestamp, height, None)
+	}
+
+	/// A block whose header names the compact target `bits` it was mined to
+	pub fn new_with_bits(previous_hash: String, transactions: Vec<Transaction>, nonce: u64, timestamp: u64, height: u64, bits: u32) -> Self {
+		Self::build(previous_hash, transactions, nonce, timestamp, height, Some(bits))
+	}
+
+	fn build(previous_hash: String, transactions: Vec<Transaction>, nonce: u64, timestamp: u64, height: u64, bits: Option<u32>) -> Self {
 		let merkle_root = calculate_merkle_root(&transactions);
 		let mut header = BlockHeader {
 			previous_hash,
@@ -54,6 +179,7 @@ impl Block {
 			merkle_root,
 			hash: String::new(), // Will be calculated below
 			height,
+			bits,
 		};
 		header.hash = sha256_hash(&format!("{:?}{:?}", &header, &transactions));
 		Block { header, transactions }
@@ -69,7 +195,32 @@ impl Block {
 			merkle_root: self.header.merkle_root.clone(),
 			hash: String::new(), // Empty hash for calculation
 			height: self.header.height,
+			bits: self.header.bits,
 		};
 		sha256_hash(&format!("{:?}{:?}", &temp_header, &self.transactions))
 	}
+
+	/// Work the block proves: the expected number of hashes to meet its target.
+	/// Blocks without bits count as meeting the easiest target, one hash, however
+	/// lucky their hash: no block with bits proves less, whatever the chain's
+	/// limit, and the work stored with a chain does not depend on that limit.
+	pub fn work(&self) -> u128 {
+		self.header.target().work()
+	}
+
+	/// Check that the stored hash and merkle root match the block contents
+	pub fn verify_integrity(&self) -> bool {
+		self.header.merkle_root == calculate_merkle_root(&self.transactions)
+			&& self.header.hash == self.calculate_hash()
+	}
+
+	/// Canonical byte serialization (the JSON encoding of the block)
+	pub fn serialize(&self) -> Vec<u8> {
+		serde_json::to_vec(self).expect("Block serialization cannot fail")
+	}
+
+	/// Canonical serialization as a hex string
+	pub fn to_hex(&self) -> String {
+		hex::encode(self.serialize())
+	}
 }
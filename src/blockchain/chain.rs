@@ -1,26 +1,128 @@
-use crate::blockchain::block::{Block, Transaction};
-use crate::blockchain::genesis::genesis_block;
-use crate::storage::block_store::BlockStore;
-use crate::storage::db::Database;
+use crate::blockchain::amount::{check_money_range, checked_money_add};
+use crate::blockchain::block::{Block, BlockHeader, Transaction};
+use crate::blockchain::genesis::{COINBASE_ADDRESS, genesis_block, is_coinbase_transaction};
+use crate::blockchain::merkle::MerkleProof;
+use crate::blockchain::multisig::{check_cosignatures, is_multisig_address};
+use crate::blockchain::state::{COINBASE_MATURITY, UTXOState, is_coinbase_mature};
+use crate::blockchain::vault::{is_unvault_transaction, is_vault_address};
+use crate::clock::{self, SharedClock};
+use crate::consensus::params::ChainParams;
+use crate::consensus::pow::ProofOfWork;
+use crate::consensus::target::Target;
+use crate::consensus::timestamps;
+use crate::perf::{self, BlockCost};
+use crate::progress::Progress;
+use crate::status;
+use crate::storage::block_store::{BlockStore, ChainMetadata};
+use crate::storage::cache::CacheStats;
+use crate::storage::db::{Database, DatabaseStats};
+use crate::storage::schema::{self, RecordKind, SchemaMigration, SCHEMA_VERSION};
+use crate::storage::undo::{apply_change, balance_changes};
 use serde::{Serialize, Deserialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Blocks a persistent chain keeps in memory by default; older ones are read from
+/// the block store on demand. Covers the coinbase maturity window.
+pub const RECENT_BLOCKS_IN_MEMORY: usize = 1_000;
+
+/// Furthest ahead of the clock a block may be timestamped, in seconds
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+/// Highest `verify_chain` check level
+pub const MAX_CHECK_LEVEL: u8 = 4;
+
+/// `verify_chain` check level and block count used when none are given
+pub const DEFAULT_CHECK_LEVEL: u8 = 3;
+pub const DEFAULT_CHECK_BLOCKS: u64 = 6;
+
+/// Addresses listed by `richest_addresses` when no count is given
+pub const DEFAULT_RICHEST_ADDRESSES: usize = 10;
+
+/// Most blocks `record_rejected_block` remembers; past this, invalid blocks are
+/// still refused but validated again each time
+pub const MAX_REJECTED_BLOCKS: usize = 10_000;
 
 /// Persistent blockchain structure with RocksDB storage
 pub struct Chain {
-	pub blocks: Vec<Block>,
+	/// Blocks held in memory, ending at the tip. In-memory chains hold every block;
+	/// persistent chains hold the most recent `memory_window`.
+	blocks: Vec<Block>,
+	memory_window: usize,
+	/// Sum of `Block::work` from genesis to the tip
+	chain_work: u128,
+	/// Blocks refused by `validate_block` after `invalidate_block`, mapped to the
+	/// invalidated block they descend from
+	invalid_blocks: HashMap<String, String>,
+	/// Blocks that broke a rule independent of the chain, mapped to the rule
+	rejected_blocks: HashMap<String, String>,
+	/// Statuses of transactions whose block was disconnected. Persistent chains
+	/// keep them in the transaction store instead.
+	transaction_statuses: HashMap<String, TransactionStatusRecord>,
 	block_store: Option<Arc<Mutex<BlockStore>>>,
 	transaction_store: Option<Arc<Mutex<Database>>>,
 	persistent: bool,
+	index_config: IndexConfig,
+	/// Time blocks are checked against for the future-timestamp rule
+	clock: SharedClock,
+	/// Subsidy schedule, proof of work limit and retarget schedule blocks are held to
+	params: ChainParams,
+}
+
+/// Which optional transaction indexes the chain maintains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexConfig {
+	/// Index transactions by hash (`tx:` and `tx_index:` keys)
+	pub txindex: bool,
+	/// Index transactions by sender and recipient address (`addr_from:` and `addr_to:`
+	/// keys), with running totals per address (`addr_balance:` keys)
+	pub addressindex: bool,
+}
+
+impl Default for IndexConfig {
+	fn default() -> Self {
+		IndexConfig {
+			txindex: true,
+			addressindex: true,
+		}
+	}
+}
+
+impl IndexConfig {
+	/// The address index stores tx hashes only, so it needs the tx index to resolve them
+	pub fn validate(&self) -> Result<(), String> {
+		if self.addressindex && !self.txindex {
+			return Err("addressindex requires txindex to be enabled".to_string());
+		}
+		Ok(())
+	}
 }
 
+/// Keys recording whether each index is complete for the stored blocks
+const TXINDEX_STATE_KEY: &str = "meta:txindex";
+const ADDRESSINDEX_STATE_KEY: &str = "meta:addressindex";
+/// Set once the per-address totals (`addr_balance:` keys) are complete. Stores
+/// written before the totals existed lack it and have them rebuilt on open.
+const ADDRESS_BALANCES_STATE_KEY: &str = "meta:addressbalances";
+
 // Manual Clone implementation that doesn't clone the stores
 impl Clone for Chain {
 	fn clone(&self) -> Self {
 		Chain {
 			blocks: self.blocks.clone(),
+			memory_window: self.memory_window,
+			chain_work: self.chain_work,
+			invalid_blocks: self.invalid_blocks.clone(),
+			rejected_blocks: self.rejected_blocks.clone(),
+			transaction_statuses: self.transaction_statuses.clone(),
 			block_store: self.block_store.clone(),
 			transaction_store: self.transaction_store.clone(),
 			persistent: self.persistent,
+			index_config: self.index_config,
+			clock: Arc::clone(&self.clock),
+			params: self.params.clone(),
 		}
 	}
 }
@@ -37,299 +139,1873 @@ pub struct TransactionIndex {
 	pub timestamp: u64,
 }
 
+/// Running totals for one address in the address index, updated as blocks are
+/// connected and disconnected
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressSummary {
+	/// Received from other addresses
+	pub total_received: u64,
+	/// Sent to other addresses
+	pub total_sent: u64,
+	pub transaction_count: u64,
+	/// Last block counted in the totals. Journal recovery writes a block's indexes
+	/// again, and this keeps it from being counted twice.
+	pub applied_block: String,
+}
+
+impl AddressSummary {
+	/// Received minus sent, never below zero. Self-transfers count for neither.
+	pub fn balance(&self) -> u64 {
+		self.total_received.saturating_sub(self.total_sent)
+	}
+
+	fn add(&mut self, other: &AddressSummary) {
+		// Lifetime totals count coins each time they move, so they can pass `MAX_MONEY`
+		self.total_received = self.total_received.saturating_add(other.total_received);
+		self.total_sent = self.total_sent.saturating_add(other.total_sent);
+		self.transaction_count = self.transaction_count.saturating_add(other.transaction_count);
+	}
+
+	fn subtract(&mut self, other: &AddressSummary) {
+		self.total_received = self.total_received.saturating_sub(other.total_received);
+		self.total_sent = self.total_sent.saturating_sub(other.total_sent);
+		self.transaction_count = self.transaction_count.saturating_sub(other.transaction_count);
+	}
+}
+
+/// Part of a payment to an address that the address has not spent yet. The chain
+/// keeps balances rather than outputs, so these are synthetic: every transaction
+/// is read as paying one output, and an address's spends use up its oldest outputs
+/// first. Nothing on chain records which outputs a spend used.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UnspentOutput {
+	pub txid: String,
+	/// Always 0, transactions have a single recipient
+	pub vout: u32,
+	pub address: String,
+	/// What is left of the payment
+	pub amount: u64,
+	pub block_height: u64,
+	pub confirmations: u64,
+	pub coinbase: bool,
+	/// False for coinbase outputs that have not matured yet
+	pub spendable: bool,
+}
+
+/// Where a transaction stands. A transaction starts out confirmed; when its
+/// block is disconnected it is reorged until it is returned to the mempool or
+/// given up on. Coinbase transactions cannot return to the mempool and are
+/// abandoned straight away.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatus {
+	Confirmed,
+	Reorged,
+	Mempool,
+	Abandoned,
+}
+
+impl TransactionStatus {
+	pub fn name(&self) -> &'static str {
+		match self {
+			TransactionStatus::Confirmed => "confirmed",
+			TransactionStatus::Reorged => "reorged",
+			TransactionStatus::Mempool => "mempool",
+			TransactionStatus::Abandoned => "abandoned",
+		}
+	}
+}
+
+/// Status of a transaction with the block it was last confirmed in
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionStatusRecord {
+	pub status: TransactionStatus,
+	pub transaction: Transaction,
+	pub block_hash: String,
+	pub block_height: u64,
+	/// Unix time of the last status change
+	pub updated_at: u64,
+}
+
 impl Chain {
-	/// Create a new in-memory chain (for testing)
+	/// Create a new in-memory chain (for testing). It accepts the regtest proof of
+	/// work limit, so blocks built without mining are accepted.
 	pub fn new() -> Self {
+		let genesis = genesis_block();
 		Chain { 
-			blocks: vec![genesis_block()],
+			chain_work: genesis.work(),
+			blocks: vec![genesis],
 			block_store: None,
 			transaction_store: None,
 			persistent: false,
+			memory_window: RECENT_BLOCKS_IN_MEMORY,
+			invalid_blocks: HashMap::new(),
+			rejected_blocks: HashMap::new(),
+			transaction_statuses: HashMap::new(),
+			index_config: IndexConfig::default(),
+			clock: clock::system(),
+			params: ChainParams::regtest(),
 		}
 	}
 
 	/// Create a new persistent chain with storage
 	pub fn new_persistent() -> Result<Self, String> {
 		let block_store = BlockStore::new()?;
-		let transaction_store = Database::new_with_path("./blockchain_data/transactions")
-			.map_err(|e| format!("Failed to create transaction database: {}", e))?;
+		let transaction_store = Self::open_transaction_store("./blockchain_data/transactions")?;
 		
 		let mut chain = Chain {
 			blocks: Vec::new(),
+			chain_work: 0,
 			block_store: Some(Arc::new(Mutex::new(block_store))),
 			transaction_store: Some(Arc::new(Mutex::new(transaction_store))),
 			persistent: true,
+			memory_window: RECENT_BLOCKS_IN_MEMORY,
+			invalid_blocks: HashMap::new(),
+			rejected_blocks: HashMap::new(),
+			transaction_statuses: HashMap::new(),
+			index_config: IndexConfig::default(),
+			clock: clock::system(),
+			params: ChainParams::mainnet(),
 		};
 
 		// Load existing blockchain or create genesis
 		chain.load_from_storage()?;
+		chain.recover_index_journal()?;
+		chain.sync_index_state()?;
 		Ok(chain)
 	}
 
 	/// Create a persistent chain with custom path
 	pub fn new_persistent_with_path(path: &str) -> Result<Self, String> {
+		Self::new_persistent_with_config(path, IndexConfig::default())
+	}
+
+	/// Create a persistent chain with custom path and index configuration
+	pub fn new_persistent_with_config(path: &str, index_config: IndexConfig) -> Result<Self, String> {
+		index_config.validate()?;
+
 		let block_store = BlockStore::new_with_path(path)?;
 		let tx_path = format!("{}/transactions", path);
-		let transaction_store = Database::new_with_path(tx_path)
-			.map_err(|e| format!("Failed to create transaction database: {}", e))?;
+		let transaction_store = Self::open_transaction_store(&tx_path)?;
 		
 		let mut chain = Chain {
 			blocks: Vec::new(),
+			chain_work: 0,
 			block_store: Some(Arc::new(Mutex::new(block_store))),
 			transaction_store: Some(Arc::new(Mutex::new(transaction_store))),
 			persistent: true,
+			memory_window: RECENT_BLOCKS_IN_MEMORY,
+			invalid_blocks: HashMap::new(),
+			rejected_blocks: HashMap::new(),
+			transaction_statuses: HashMap::new(),
+			index_config,
+			clock: clock::system(),
+			params: ChainParams::mainnet(),
 		};
 
 		chain.load_from_storage()?;
+		chain.recover_index_journal()?;
+		chain.sync_index_state()?;
 		Ok(chain)
 	}
 
-	/// Load blockchain from persistent storage
-	fn load_from_storage(&mut self) -> Result<(), String> {
+	/// Open the transaction database, marking a new one with the current schema version
+	fn open_transaction_store(path: &str) -> Result<Database, String> {
+		let transaction_store = Database::new_with_path(path)
+			.map_err(|e| format!("Failed to create transaction database: {}", e))?;
+		let empty = transaction_store.is_empty()
+			.map_err(|e| format!("Database error: {}", e))?;
+		schema::store_version(&transaction_store, empty)?;
+		Ok(transaction_store)
+	}
+
+	/// Reconcile the configured indexes with what is stored on disk.
+	/// An index that was disabled on a previous run is rebuilt when it is enabled again.
+	fn sync_index_state(&self) -> Result<(), String> {
 		if !self.persistent {
 			return Ok(());
 		}
 
-		let block_store = self.block_store.as_ref().unwrap();
-		let block_store_guard = block_store.lock()
-			.map_err(|e| format!("Failed to lock block store: {}", e))?;
-		
-		// Check if we have any blocks stored
-		match block_store_guard.get_latest_height()? {
-			Some(latest_height) => {
-				// Load all blocks from storage
-				self.blocks.clear();
-				for height in 0..=latest_height {
-					if let Some(block) = block_store_guard.get_block_by_height(height)? {
-						self.blocks.push(block);
-					} else {
-						return Err(format!("Missing block at height {}", height));
-					}
-				}
-				println!("Loaded {} blocks from storage", self.blocks.len());
-			},
-			None => {
-				// No blocks in storage, create and store genesis
-				let genesis = genesis_block();
-				self.blocks = vec![genesis.clone()];
-				drop(block_store_guard); // Release lock before calling persist_block
-				self.persist_block(&genesis)?;
-				println!("Created new blockchain with genesis block");
-			}
+		if self.index_config.txindex && !self.index_complete(TXINDEX_STATE_KEY)? {
+			status!("Transaction index was disabled previously, rebuilding...");
+			let count = self.rebuild_transaction_index()?;
+			status!("Rebuilt transaction index for {} transactions", count);
 		}
 
-		Ok(())
+		if self.index_config.addressindex && !self.index_complete(ADDRESSINDEX_STATE_KEY)? {
+			status!("Address index was disabled previously, rebuilding...");
+			let count = self.rebuild_address_index()?;
+			status!("Rebuilt address index for {} transactions", count);
+		} else if self.index_config.addressindex && self.index_state(ADDRESS_BALANCES_STATE_KEY)? != Some(true) {
+			status!("Address balances are missing from this store, rebuilding the address index...");
+			let count = self.rebuild_address_index()?;
+			status!("Rebuilt address index for {} transactions", count);
+		}
+
+		self.set_index_state(TXINDEX_STATE_KEY, self.index_config.txindex)?;
+		self.set_index_state(ADDRESSINDEX_STATE_KEY, self.index_config.addressindex)?;
+		self.set_index_state(ADDRESS_BALANCES_STATE_KEY, self.index_config.addressindex)
 	}
 
-	/// Add a block to the chain with persistence
-	pub fn add_block(&mut self, block: Block) -> bool {
-		if self.validate_block(&block) {
-			// Persist the block if storage is enabled
-			if self.persistent {
-				if let Err(e) = self.persist_block(&block) {
-					eprintln!("Failed to persist block: {}", e);
-					return false;
-				}
-			}
+	/// Whether an index is complete for the stored blocks (missing state means a
+	/// store created before indexes were optional, which always indexed everything)
+	fn index_complete(&self, state_key: &str) -> Result<bool, String> {
+		Ok(self.index_state(state_key)?.unwrap_or(true))
+	}
 
-			self.blocks.push(block);
-			true
-		} else {
-			false
+	/// Recorded state of an index, None if the store has never recorded one
+	fn index_state(&self, state_key: &str) -> Result<Option<bool>, String> {
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+
+		match tx_store_guard.get(state_key) {
+			Ok(state) => Ok(state.map(|state| state == vec![1])),
+			Err(e) => Err(format!("Database error: {}", e)),
 		}
 	}
 
-	/// Persist a block and its transactions to storage
-	fn persist_block(&self, block: &Block) -> Result<(), String> {
+	fn set_index_state(&self, state_key: &str, complete: bool) -> Result<(), String> {
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+
+		tx_store_guard.put(state_key.to_string(), vec![complete as u8])
+			.map_err(|e| format!("Failed to store index state: {}", e))
+	}
+
+	/// Get the index configuration
+	pub fn index_config(&self) -> IndexConfig {
+		self.index_config
+	}
+
+	/// Whether transactions can be looked up by hash
+	pub fn txindex_enabled(&self) -> bool {
+		self.index_config.txindex
+	}
+
+	/// Whether transactions can be looked up by address
+	pub fn address_index_enabled(&self) -> bool {
+		self.index_config.addressindex
+	}
+
+	/// Rebuild the hash index from stored blocks without touching the address index
+	pub fn rebuild_transaction_index(&self) -> Result<usize, String> {
+		if !self.index_config.txindex {
+			return Err("Transaction index is disabled (start with --txindex=1)".to_string());
+		}
 		if !self.persistent {
-			return Ok(());
+			return Ok(0);
 		}
 
-		let block_store = self.block_store.as_ref().unwrap();
 		let tx_store = self.transaction_store.as_ref().unwrap();
+		let mut count = 0;
+		let mut progress = Progress::new("Rebuilding transaction index", self.height() + 1).with_unit("blocks");
+		self.try_scan_blocks(0, self.height(), |block| {
+			progress.inc(1);
+			let mut operations = Vec::new();
+			for (tx_index, transaction) in block.transactions.iter().enumerate() {
+				operations.extend(Self::transaction_index_entries(block, tx_index, transaction)?);
+				count += 1;
+			}
+			tx_store.lock()
+				.map_err(|e| format!("Failed to lock transaction store: {}", e))?
+				.batch_put(operations)
+				.map_err(|e| format!("Failed to store transaction index: {}", e))
+		})?;
+		progress.finish();
 
-		// Store the block
-		{
-			let block_store_guard = block_store.lock()
-				.map_err(|e| format!("Failed to lock block store: {}", e))?;
-			block_store_guard.store_block(block)?;
+		Ok(count)
+	}
+
+	/// Rebuild the address index from stored blocks without touching the hash index
+	pub fn rebuild_address_index(&self) -> Result<usize, String> {
+		if !self.index_config.addressindex {
+			return Err("Address index is disabled (start with --addressindex=1)".to_string());
+		}
+		if !self.persistent {
+			return Ok(0);
 		}
 
-		// Index all transactions in the block
-		{
-			let tx_store_guard = tx_store.lock()
-				.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let mut count = 0;
+		let mut summaries: HashMap<String, AddressSummary> = HashMap::new();
+		let mut progress = Progress::new("Rebuilding address index", self.height() + 1).with_unit("blocks");
+		self.try_scan_blocks(0, self.height(), |block| {
+			progress.inc(1);
+			let mut operations = Vec::new();
+			for transaction in &block.transactions {
+				operations.extend(Self::address_index_entries(transaction));
+				count += 1;
+			}
+			for (address, delta) in Self::address_deltas(block) {
+				let summary = summaries.entry(address).or_default();
+				summary.add(&delta);
+				summary.applied_block = block.header.hash.clone();
+			}
+			tx_store.lock()
+				.map_err(|e| format!("Failed to lock transaction store: {}", e))?
+				.batch_put(operations)
+				.map_err(|e| format!("Failed to store address index: {}", e))
+		})?;
+		progress.finish();
 
-			for (tx_index, transaction) in block.transactions.iter().enumerate() {
-				let tx_hash = crate::crypto::hash::sha256_hash(&format!("{:?}", transaction));
-				
-				// Create transaction index
-				let tx_index_entry = TransactionIndex {
-					block_hash: block.header.hash.clone(),
-					block_height: block.header.height,
-					transaction_index: tx_index,
-					from: transaction.from.clone(),
-					to: transaction.to.clone(),
-					amount: transaction.amount,
-					timestamp: block.header.timestamp,
-				};
+		// The totals replace whatever was stored, including addresses no longer on the chain
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+		let stale = tx_store_guard.keys_with_prefix(RecordKind::AddressBalance.key_prefix())
+			.map_err(|e| format!("Database error: {}", e))?;
+		let mut puts = Vec::new();
+		for (address, summary) in &summaries {
+			let data = schema::encode(summary)
+				.map_err(|e| format!("Failed to serialize address balance: {}", e))?;
+			puts.push((Self::address_balance_key(address), data));
+		}
+		let deletes = stale.into_iter()
+			.filter(|key| !summaries.contains_key(&key[RecordKind::AddressBalance.key_prefix().len()..]))
+			.collect();
+		tx_store_guard.batch_write(puts, deletes)
+			.map_err(|e| format!("Failed to store address balances: {}", e))?;
+
+		Ok(count)
+	}
 
-				// Store transaction by hash
-				let tx_key = format!("tx:{}", tx_hash);
-				let tx_data = serde_json::to_vec(&transaction)
-					.map_err(|e| format!("Failed to serialize transaction: {}", e))?;
-				tx_store_guard.put(tx_key, tx_data)
-					.map_err(|e| format!("Failed to store transaction: {}", e))?;
+	/// Key-value pairs for the hash index of one transaction
+	fn transaction_index_entries(block: &Block, tx_index: usize, transaction: &Transaction) -> Result<Vec<(String, Vec<u8>)>, String> {
+		let tx_hash = transaction.hash();
 
-				// Store transaction index
-				let index_key = format!("tx_index:{}", tx_hash);
-				let index_data = serde_json::to_vec(&tx_index_entry)
-					.map_err(|e| format!("Failed to serialize transaction index: {}", e))?;
-				tx_store_guard.put(index_key, index_data)
-					.map_err(|e| format!("Failed to store transaction index: {}", e))?;
+		let tx_index_entry = TransactionIndex {
+			block_hash: block.header.hash.clone(),
+			block_height: block.header.height,
+			transaction_index: tx_index,
+			from: transaction.from.clone(),
+			to: transaction.to.clone(),
+			amount: transaction.amount,
+			timestamp: block.header.timestamp,
+		};
 
-				// Index by sender address
-				let from_key = format!("addr_from:{}:{}", transaction.from, tx_hash);
-				tx_store_guard.put(from_key, vec![1])
-					.map_err(|e| format!("Failed to index sender: {}", e))?;
+		let tx_data = schema::encode(&transaction)
+			.map_err(|e| format!("Failed to serialize transaction: {}", e))?;
+		let index_data = schema::encode(&tx_index_entry)
+			.map_err(|e| format!("Failed to serialize transaction index: {}", e))?;
 
-				// Index by recipient address
-				let to_key = format!("addr_to:{}:{}", transaction.to, tx_hash);
-				tx_store_guard.put(to_key, vec![1])
-					.map_err(|e| format!("Failed to index recipient: {}", e))?;
-			}
-		}
+		Ok(vec![
+			(format!("tx:{}", tx_hash), tx_data),
+			(format!("tx_index:{}", tx_hash), index_data),
+		])
+	}
 
-		Ok(())
+	/// Key-value pairs for the address index of one transaction
+	fn address_index_entries(transaction: &Transaction) -> Vec<(String, Vec<u8>)> {
+		let tx_hash = transaction.hash();
+		vec![
+			(format!("addr_from:{}:{}", transaction.from, tx_hash), vec![1]),
+			(format!("addr_to:{}:{}", transaction.to, tx_hash), vec![1]),
+		]
 	}
 
-	/// Get a transaction by hash
-	pub fn get_transaction(&self, tx_hash: &str) -> Result<Option<Transaction>, String> {
-		if !self.persistent {
-			// Search in-memory blocks
-			for block in &self.blocks {
-				for transaction in &block.transactions {
-					let hash = crate::crypto::hash::sha256_hash(&format!("{:?}", transaction));
-					if hash == tx_hash {
-						return Ok(Some(transaction.clone()));
-					}
-				}
+	fn address_balance_key(address: &str) -> String {
+		format!("{}{}", RecordKind::AddressBalance.key_prefix(), address)
+	}
+
+	/// Changes a block makes to the totals of each address it touches
+	fn address_deltas(block: &Block) -> HashMap<String, AddressSummary> {
+		let mut deltas: HashMap<String, AddressSummary> = HashMap::new();
+		for transaction in &block.transactions {
+			let sender = deltas.entry(transaction.from.clone()).or_default();
+			sender.transaction_count += 1;
+			if transaction.to == transaction.from {
+				continue;
 			}
-			return Ok(None);
+			sender.total_sent = sender.total_sent.saturating_add(transaction.amount);
+			let recipient = deltas.entry(transaction.to.clone()).or_default();
+			recipient.total_received = recipient.total_received.saturating_add(transaction.amount);
+			recipient.transaction_count += 1;
 		}
+		deltas
+	}
 
-		let tx_store = self.transaction_store.as_ref().unwrap();
-		let tx_store_guard = tx_store.lock()
-			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
-		
-		let tx_key = format!("tx:{}", tx_hash);
-		
-		match tx_store_guard.get(&tx_key) {
-			Ok(Some(tx_data)) => {
-				let transaction: Transaction = serde_json::from_slice(&tx_data)
-					.map_err(|e| format!("Failed to deserialize transaction: {}", e))?;
-				Ok(Some(transaction))
-			},
+	fn read_address_summary(db: &Database, address: &str) -> Result<Option<AddressSummary>, String> {
+		match db.get(&Self::address_balance_key(address)) {
+			Ok(Some(data)) => schema::decode(RecordKind::AddressBalance, &data)
+				.map(Some)
+				.map_err(|e| format!("Failed to deserialize address balance: {}", e)),
 			Ok(None) => Ok(None),
 			Err(e) => Err(format!("Database error: {}", e)),
 		}
 	}
 
-	/// Get transaction index information
-	pub fn get_transaction_index(&self, tx_hash: &str) -> Result<Option<TransactionIndex>, String> {
+	/// Updated totals of the addresses a block touches, as puts and deletes.
+	/// Addresses left without transactions are deleted.
+	fn address_balance_updates(db: &Database, block: &Block, connect: bool) -> Result<(Vec<(String, Vec<u8>)>, Vec<String>), String> {
+		let mut puts = Vec::new();
+		let mut deletes = Vec::new();
+		for (address, delta) in Self::address_deltas(block) {
+			let mut summary = Self::read_address_summary(db, &address)?.unwrap_or_default();
+			if connect {
+				if summary.applied_block == block.header.hash {
+					continue;
+				}
+				summary.add(&delta);
+				summary.applied_block = block.header.hash.clone();
+			} else {
+				summary.subtract(&delta);
+				summary.applied_block = block.header.previous_hash.clone();
+			}
+
+			if summary.transaction_count == 0 {
+				deletes.push(Self::address_balance_key(&address));
+			} else {
+				let data = schema::encode(&summary)
+					.map_err(|e| format!("Failed to serialize address balance: {}", e))?;
+				puts.push((Self::address_balance_key(&address), data));
+			}
+		}
+		Ok((puts, deletes))
+	}
+
+	fn transaction_status_key(txid: &str) -> String {
+		format!("{}{}", RecordKind::TransactionStatus.key_prefix(), txid)
+	}
+
+	/// Status records for the transactions of a block being disconnected
+	fn disconnected_statuses(block: &Block, updated_at: u64) -> Vec<(String, TransactionStatusRecord)> {
+		block.transactions.iter().map(|transaction| {
+			let status = if is_coinbase_transaction(transaction) {
+				TransactionStatus::Abandoned
+			} else {
+				TransactionStatus::Reorged
+			};
+			(transaction.hash(), TransactionStatusRecord {
+				status,
+				transaction: transaction.clone(),
+				block_hash: block.header.hash.clone(),
+				block_height: block.header.height,
+				updated_at,
+			})
+		}).collect()
+	}
+
+	fn recorded_transaction_status(&self, txid: &str) -> Result<Option<TransactionStatusRecord>, String> {
 		if !self.persistent {
-			return Ok(None);
+			return Ok(self.transaction_statuses.get(txid).cloned());
 		}
 
 		let tx_store = self.transaction_store.as_ref().unwrap();
 		let tx_store_guard = tx_store.lock()
 			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
-		
-		let index_key = format!("tx_index:{}", tx_hash);
-		
-		match tx_store_guard.get(&index_key) {
-			Ok(Some(index_data)) => {
-				let index: TransactionIndex = serde_json::from_slice(&index_data)
-					.map_err(|e| format!("Failed to deserialize transaction index: {}", e))?;
-				Ok(Some(index))
-			},
+		match tx_store_guard.get(&Self::transaction_status_key(txid)) {
+			Ok(Some(data)) => schema::decode(RecordKind::TransactionStatus, &data)
+				.map(Some)
+				.map_err(|e| format!("Failed to deserialize transaction status: {}", e)),
 			Ok(None) => Ok(None),
 			Err(e) => Err(format!("Database error: {}", e)),
 		}
 	}
 
-	/// Get all transactions for an address (both sent and received)
-	pub fn get_transactions_for_address(&self, address: &str) -> Result<Vec<(String, Transaction)>, String> {
-		let mut results = Vec::new();
+	/// Status of a transaction: confirmed while it is indexed on the active chain,
+	/// otherwise whatever was recorded since its block was disconnected. `None`
+	/// for transactions the chain knows nothing about.
+	pub fn transaction_status(&self, txid: &str) -> Result<Option<TransactionStatusRecord>, String> {
+		if let Some(index) = self.get_transaction_index(txid)?
+			&& let Some(transaction) = self.get_transaction(txid)? {
+			return Ok(Some(TransactionStatusRecord {
+				status: TransactionStatus::Confirmed,
+				transaction,
+				block_hash: index.block_hash,
+				block_height: index.block_height,
+				updated_at: index.timestamp,
+			}));
+		}
+		self.recorded_transaction_status(txid)
+	}
+
+	/// Record what became of a transaction whose block was disconnected, such as
+	/// its return to the mempool
+	pub fn set_transaction_status(&mut self, txid: &str, status: TransactionStatus) -> Result<(), String> {
+		let mut record = self.recorded_transaction_status(txid)?
+			.ok_or_else(|| format!("No disconnected transaction {}", txid))?;
+		record.status = status;
+		record.updated_at = self.clock.now();
 
 		if !self.persistent {
-			// Search in-memory blocks
+			self.transaction_statuses.insert(txid.to_string(), record);
+			return Ok(());
+		}
+		let data = schema::encode(&record)
+			.map_err(|e| format!("Failed to serialize transaction status: {}", e))?;
+		self.transaction_store.as_ref().unwrap().lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?
+			.put(Self::transaction_status_key(txid), data)
+			.map_err(|e| format!("Failed to store transaction status: {}", e))
+	}
+
+	/// Running totals for an address, read from the index in one lookup
+	pub fn get_address_summary(&self, address: &str) -> Result<AddressSummary, String> {
+		if !self.index_config.addressindex {
+			return Err("Address index is disabled (start with --addressindex=1)".to_string());
+		}
+
+		if !self.persistent {
+			let mut summary = AddressSummary::default();
 			for block in &self.blocks {
-				for transaction in &block.transactions {
-					if transaction.from == address || transaction.to == address {
-						let hash = crate::crypto::hash::sha256_hash(&format!("{:?}", transaction));
-						results.push((hash, transaction.clone()));
-					}
+				if let Some(delta) = Self::address_deltas(block).get(address) {
+					summary.add(delta);
+					summary.applied_block = block.header.hash.clone();
 				}
 			}
-			return Ok(results);
+			return Ok(summary);
 		}
 
 		let tx_store = self.transaction_store.as_ref().unwrap();
 		let tx_store_guard = tx_store.lock()
 			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
-		
-		// Get transactions where this address is the sender
-		let from_keys = tx_store_guard.keys_with_prefix(&format!("addr_from:{}", address))
-			.map_err(|e| format!("Database error: {}", e))?;
-		
-		// Get transactions where this address is the recipient
-		let to_keys = tx_store_guard.keys_with_prefix(&format!("addr_to:{}", address))
-			.map_err(|e| format!("Database error: {}", e))?;
+		Ok(Self::read_address_summary(&tx_store_guard, address)?.unwrap_or_default())
+	}
 
-		// Combine and extract transaction hashes
-		let mut tx_hashes = std::collections::HashSet::new();
-		
-		for key in from_keys {
-			if let Some(tx_hash) = key.split(':').last() {
-				tx_hashes.insert(tx_hash.to_string());
-			}
+	/// Totals for an address as they stood once the block at `height` was
+	/// connected, replayed from the address index. At the tip this is the stored
+	/// summary; below it every indexed transaction of the address is looked up.
+	pub fn get_address_summary_at(&self, address: &str, height: u64) -> Result<AddressSummary, String> {
+		let tip_height = self.height();
+		if height > tip_height {
+			return Err(format!("Height {} is beyond the tip at {}", height, tip_height));
 		}
-		
-		for key in to_keys {
-			if let Some(tx_hash) = key.split(':').last() {
-				tx_hashes.insert(tx_hash.to_string());
+		if height == tip_height {
+			return self.get_address_summary(address);
+		}
+
+		let mut summary = AddressSummary::default();
+		for (txid, transaction) in self.get_transactions_for_address(address)? {
+			let Some(index) = self.get_transaction_index(&txid)? else {
+				continue;
+			};
+			if index.block_height > height {
+				continue;
+			}
+			// Counted as in `address_deltas`: once per transaction, self-transfers moving nothing
+			summary.transaction_count += 1;
+			if transaction.from == transaction.to {
+				continue;
 			}
+			if transaction.from == address {
+				summary.total_sent = summary.total_sent.saturating_add(transaction.amount);
+			} else if transaction.to == address {
+				summary.total_received = summary.total_received.saturating_add(transaction.amount);
+			}
+		}
+		if let Some(block) = self.block_at(height)? {
+			summary.applied_block = block.header.hash;
 		}
+		Ok(summary)
+	}
 
-		// Release the guard before calling get_transaction
-		drop(tx_store_guard);
+	/// The `count` addresses with the highest indexed balance, richest first
+	pub fn richest_addresses(&self, count: usize) -> Result<Vec<(String, AddressSummary)>, String> {
+		if !self.index_config.addressindex {
+			return Err("Address index is disabled (start with --addressindex=1)".to_string());
+		}
 
-		// Retrieve each transaction
-		for tx_hash in tx_hashes {
-			if let Some(transaction) = self.get_transaction(&tx_hash)? {
-				results.push((tx_hash, transaction));
+		let mut summaries: Vec<(String, AddressSummary)> = if self.persistent {
+			let tx_store = self.transaction_store.as_ref().unwrap();
+			let tx_store_guard = tx_store.lock()
+				.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+			let prefix = RecordKind::AddressBalance.key_prefix();
+			let entries = tx_store_guard.entries_with_prefix(prefix)
+				.map_err(|e| format!("Database error: {}", e))?;
+			let mut summaries = Vec::new();
+			for (key, data) in entries {
+				let summary = schema::decode(RecordKind::AddressBalance, &data)
+					.map_err(|e| format!("Failed to deserialize address balance: {}", e))?;
+				summaries.push((key[prefix.len()..].to_string(), summary));
 			}
-		}
+			summaries
+		} else {
+			let mut totals: HashMap<String, AddressSummary> = HashMap::new();
+			for block in &self.blocks {
+				for (address, delta) in Self::address_deltas(block) {
+					totals.entry(address).or_default().add(&delta);
+				}
+			}
+			totals.into_iter().collect()
+		};
 
-		Ok(results)
+		summaries.retain(|(_, summary)| summary.balance() > 0);
+		summaries.sort_by(|a, b| b.1.balance().cmp(&a.1.balance()).then_with(|| a.0.cmp(&b.0)));
+		summaries.truncate(count);
+		Ok(summaries)
 	}
 
-	pub fn validate_block(&self, block: &Block) -> bool {
-		let last_hash = self.blocks.last().map(|b| b.header.hash.clone()).unwrap_or_default();
-		let expected_height = self.blocks.len() as u64;
-		block.header.previous_hash == last_hash && block.header.height == expected_height
-	}
+	/// Load blockchain from persistent storage
+	fn load_from_storage(&mut self) -> Result<(), String> {
+		if !self.persistent {
+			return Ok(());
+		}
 
-	/// Get chain statistics
-	pub fn get_stats(&self) -> ChainStats {
-		let total_transactions: usize = self.blocks.iter().map(|b| b.transactions.len()).sum();
+		let block_store = self.block_store.as_ref().unwrap();
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
 		
-		ChainStats {
-			total_blocks: self.blocks.len(),
-			total_transactions,
+		// Stores written before chain metadata existed get it computed once
+		let metadata = match block_store_guard.get_chain_metadata()? {
+			Some(metadata) => Some(metadata),
+			None => block_store_guard.rebuild_chain_metadata()?,
+		};
+		// Likewise for the balances and undo records used to disconnect blocks
+		if metadata.is_some() && !block_store_guard.has_chainstate()? {
+			status!("Building undo data for stored blocks...");
+			let count = block_store_guard.rebuild_chainstate()?;
+			status!("Built undo data for {} blocks", count);
+		}
+		self.invalid_blocks = block_store_guard.invalid_blocks()?.into_iter().collect();
+		self.rejected_blocks = block_store_guard.rejected_blocks()?.into_iter().collect();
+
+		match metadata {
+			Some(metadata) => {
+				// Only the recent blocks are loaded; older ones are read on demand
+				let from = (metadata.height + 1).saturating_sub(self.memory_window as u64);
+				self.blocks = block_store_guard.iter_blocks(from, metadata.height)
+					.collect::<Result<Vec<Block>, String>>()?;
+				if self.blocks.last().map(|b| &b.header.hash) != Some(&metadata.tip_hash) {
+					return Err(format!("Stored tip does not match chain metadata (expected {})", metadata.tip_hash));
+				}
+				self.chain_work = metadata.cumulative_work;
+				status!("Loaded chain tip at height {} ({} recent blocks in memory)", metadata.height, self.blocks.len());
+			},
+			None => {
+				// No blocks in storage, create and store genesis
+				let genesis = genesis_block();
+				self.chain_work = genesis.work();
+				self.blocks = vec![genesis.clone()];
+				drop(block_store_guard); // Release lock before calling persist_block
+				self.persist_block(&genesis)?;
+				status!("Created new blockchain with genesis block");
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Add a block to the chain with persistence. Persistent chains record what
+	/// validating and storing the block cost in `perf::blocks`.
+	pub fn add_block(&mut self, block: Block) -> bool {
+		let started = Instant::now();
+		if self.validate_block(&block) {
+			// Persist the block if storage is enabled
+			if self.persistent {
+				let validated = Instant::now();
+				if let Err(e) = self.persist_block(&block) {
+					eprintln!("Failed to persist block: {}", e);
+					return false;
+				}
+				perf::blocks::record_block(BlockCost::new(&block, validated - started, validated.elapsed()));
+			}
+
+			if !self.persistent {
+				for transaction in &block.transactions {
+					self.transaction_statuses.remove(&transaction.hash());
+				}
+			}
+
+			self.chain_work = self.chain_work.saturating_add(block.work());
+			self.blocks.push(block);
+			self.trim_to_window();
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Drop the oldest in-memory blocks beyond the window. Only persistent chains
+	/// can read them back, so other chains keep everything.
+	fn trim_to_window(&mut self) {
+		if self.persistent && self.blocks.len() > self.memory_window {
+			let excess = self.blocks.len() - self.memory_window;
+			self.blocks.drain(..excess);
+		}
+	}
+
+	/// Number of recent blocks a persistent chain keeps in memory
+	pub fn memory_window(&self) -> usize {
+		self.memory_window
+	}
+
+	/// Check block timestamps against `clock` instead of the system clock
+	pub fn set_clock(&mut self, clock: SharedClock) {
+		self.clock = clock;
+	}
+
+	/// Hold blocks to `params`' subsidy schedule, proof of work limit and retarget schedule
+	pub fn set_params(&mut self, params: ChainParams) {
+		self.params = params;
+	}
+
+	/// Subsidy schedule, proof of work limit and retarget schedule blocks are held to
+	pub fn params(&self) -> &ChainParams {
+		&self.params
+	}
+
+	/// Change how many recent blocks a persistent chain keeps in memory (at least one)
+	pub fn set_memory_window(&mut self, blocks: usize) {
+		self.memory_window = blocks.max(1);
+		self.trim_to_window();
+	}
+
+	/// A chain ending at the block `hash`, for building a fork on top of it.
+	/// Only blocks held in memory can be branched from. The branch shares this
+	/// chain's block store for the common history below them, but never writes to it.
+	pub fn branch_at(&self, hash: &str) -> Option<Chain> {
+		let position = self.blocks.iter().position(|b| b.header.hash == hash)?;
+		let blocks = self.blocks[..=position].to_vec();
+		let dropped_work = self.blocks[position + 1..].iter()
+			.fold(0u128, |work, b| work.saturating_add(b.work()));
+		Some(Chain {
+			blocks,
+			memory_window: self.memory_window,
+			chain_work: self.chain_work.saturating_sub(dropped_work),
+			invalid_blocks: self.invalid_blocks.clone(),
+			rejected_blocks: self.rejected_blocks.clone(),
+			transaction_statuses: self.transaction_statuses.clone(),
+			block_store: self.block_store.clone(),
+			transaction_store: None,
+			persistent: false,
+			index_config: self.index_config,
+			clock: Arc::clone(&self.clock),
+			params: self.params.clone(),
+		})
+	}
+
+	/// Disconnect the tip block. Persistent chains restore the balances it
+	/// overwrote from its undo record and drop its transactions from the indexes.
+	/// Its transactions are recorded as reorged (see `transaction_status`).
+	/// Returns the disconnected block.
+	pub fn disconnect_tip(&mut self) -> Result<Block, String> {
+		let tip = self.tip().cloned().ok_or_else(|| "Chain is empty".to_string())?;
+		if tip.header.height == 0 {
+			return Err("The genesis block cannot be disconnected".to_string());
+		}
+
+		if self.persistent {
+			self.block_store.as_ref().unwrap().lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?
+				.disconnect_block(&tip)?;
+			self.remove_block_indexes(&tip)?;
+		} else {
+			self.transaction_statuses.extend(Self::disconnected_statuses(&tip, self.clock.now()));
+		}
+		self.blocks.pop();
+		self.chain_work = self.chain_work.saturating_sub(tip.work());
+
+		// Keep the new tip in memory once the window runs empty
+		if self.blocks.is_empty() && let Some(block_store) = &self.block_store {
+			let parent = block_store.lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?
+				.get_block_by_height(tip.header.height - 1)?
+				.ok_or_else(|| format!("Missing block at height {}", tip.header.height - 1))?;
+			self.blocks.push(parent);
+		}
+		Ok(tip)
+	}
+
+	/// Switch to another branch: disconnect back to the block `fork_hash`, then
+	/// connect `new_blocks` on top of it. If a new block is rejected, the original
+	/// branch is reconnected. Returns the disconnected blocks, tip first.
+	pub fn reorganize(&mut self, fork_hash: &str, new_blocks: Vec<Block>) -> Result<Vec<Block>, String> {
+		let fork = self.find_header(fork_hash)?
+			.ok_or_else(|| format!("Fork point {} is not on this chain", fork_hash))?;
+
+		let mut disconnected = Vec::new();
+		while self.height() > fork.height {
+			disconnected.push(self.disconnect_tip()?);
+		}
+
+		for (connected, block) in new_blocks.iter().enumerate() {
+			if self.add_block(block.clone()) {
+				continue;
+			}
+			for _ in 0..connected {
+				self.disconnect_tip()?;
+			}
+			for old in disconnected.iter().rev() {
+				if !self.add_block(old.clone()) {
+					return Err(format!("Failed to reconnect block {} after a rejected reorganization", old.header.hash));
+				}
+			}
+			return Err(format!("Block {} of the new branch is invalid; kept the current chain", block.header.hash));
+		}
+		Ok(disconnected)
+	}
+
+	/// Mark a block invalid so it is never connected again, disconnecting it and
+	/// every block above it if it is on this chain. The disconnected descendants
+	/// are marked invalid too. Returns the disconnected blocks, tip first.
+	pub fn invalidate_block(&mut self, hash: &str) -> Result<Vec<Block>, String> {
+		let mut disconnected = Vec::new();
+		if let Some(header) = self.find_header(hash)? {
+			if header.height == 0 {
+				return Err("The genesis block cannot be invalidated".to_string());
+			}
+			while self.height() >= header.height {
+				disconnected.push(self.disconnect_tip()?);
+			}
+		}
+
+		let mut invalid: Vec<String> = disconnected.iter().map(|b| b.header.hash.clone()).collect();
+		if !invalid.iter().any(|invalid_hash| invalid_hash == hash) {
+			invalid.push(hash.to_string());
+		}
+		for invalid_hash in invalid {
+			if self.persistent {
+				self.block_store.as_ref().unwrap().lock()
+					.map_err(|e| format!("Failed to lock block store: {}", e))?
+					.mark_invalid(&invalid_hash, hash)?;
+			}
+			self.invalid_blocks.insert(invalid_hash, hash.to_string());
+		}
+		Ok(disconnected)
+	}
+
+	/// Clear the invalid mark from a block and the descendants invalidated with it,
+	/// then switch to their branch if it has more work than the current chain.
+	/// Returns the blocks that were connected and the ones disconnected to make
+	/// room for them, tip first.
+	pub fn reconsider_block(&mut self, hash: &str) -> Result<(Vec<Block>, Vec<Block>), String> {
+		let root = self.invalid_blocks.get(hash).cloned()
+			.ok_or_else(|| format!("Block {} is not marked invalid", hash))?;
+		let cleared: Vec<String> = self.invalid_blocks.iter()
+			.filter(|(_, invalid_root)| **invalid_root == root)
+			.map(|(invalid_hash, _)| invalid_hash.clone())
+			.collect();
+		for invalid_hash in &cleared {
+			if self.persistent {
+				self.block_store.as_ref().unwrap().lock()
+					.map_err(|e| format!("Failed to lock block store: {}", e))?
+					.clear_invalid(invalid_hash)?;
+			}
+			self.invalid_blocks.remove(invalid_hash);
+		}
+
+		let branch = self.stored_branch(&cleared)?;
+		let Some(first) = branch.first() else {
+			return Ok((Vec::new(), Vec::new()));
+		};
+		let Some(fork) = self.find_header(&first.header.previous_hash)? else {
+			return Ok((Vec::new(), Vec::new()));
+		};
+
+		// Compare the work above the fork point on both branches
+		let branch_work = branch.iter().fold(0u128, |work, b| work.saturating_add(b.work()));
+		let mut current_work = 0u128;
+		self.scan_blocks(fork.height + 1, self.height(), |b| current_work = current_work.saturating_add(b.work()))?;
+		if branch_work <= current_work {
+			return Ok((Vec::new(), Vec::new()));
+		}
+
+		let disconnected = self.reorganize(&fork.hash, branch.clone())?;
+		Ok((branch, disconnected))
+	}
+
+	/// The stored blocks among `hashes` that form one branch, in height order
+	fn stored_branch(&self, hashes: &[String]) -> Result<Vec<Block>, String> {
+		let Some(block_store) = &self.block_store else {
+			return Ok(Vec::new());
+		};
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+
+		let mut blocks = Vec::new();
+		for hash in hashes {
+			if let Some(block) = block_store_guard.get_block(hash)? {
+				blocks.push(block);
+			}
+		}
+		blocks.sort_by_key(|b| b.header.height);
+
+		// Stop at the first gap, keeping only blocks that build on each other
+		let mut branch: Vec<Block> = Vec::new();
+		for block in blocks {
+			if let Some(last) = branch.last()
+				&& block.header.previous_hash != last.header.hash {
+				break;
+			}
+			branch.push(block);
+		}
+		Ok(branch)
+	}
+
+	/// Whether a block was marked invalid with `invalidate_block`
+	pub fn is_invalid(&self, hash: &str) -> bool {
+		self.invalid_blocks.contains_key(hash)
+	}
+
+	/// Remember that a block broke a rule that does not depend on the chain, so
+	/// it is refused without being validated again when it is offered again.
+	/// The block's hash must have been checked against its contents.
+	pub fn record_rejected_block(&mut self, hash: &str, reason: &str) -> Result<(), String> {
+		if self.rejected_blocks.contains_key(hash) || self.rejected_blocks.len() >= MAX_REJECTED_BLOCKS {
+			return Ok(());
+		}
+		if self.persistent {
+			self.block_store.as_ref().unwrap().lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?
+				.mark_rejected(hash, reason)?;
+		}
+		self.rejected_blocks.insert(hash.to_string(), reason.to_string());
+		Ok(())
+	}
+
+	/// The rule a block recorded with `record_rejected_block` broke
+	pub fn rejected_reason(&self, hash: &str) -> Option<&str> {
+		self.rejected_blocks.get(hash).map(String::as_str)
+	}
+
+	/// Every block refused for good with why, sorted by hash: blocks recorded
+	/// with `record_rejected_block` and blocks marked with `invalidate_block`
+	pub fn invalid_block_list(&self) -> Vec<(String, String)> {
+		let invalidated = self.invalid_blocks.iter().map(|(hash, root)| {
+			let reason = if hash == root {
+				"invalidated".to_string()
+			} else {
+				format!("descends from invalidated block {}", root)
+			};
+			(hash.clone(), reason)
+		});
+		let mut blocks: Vec<(String, String)> = self.rejected_blocks.iter()
+			.map(|(hash, reason)| (hash.clone(), reason.clone()))
+			.chain(invalidated)
+			.collect();
+		blocks.sort();
+		blocks
+	}
+
+	/// Height of the tip
+	pub fn height(&self) -> u64 {
+		self.blocks.last().map(|b| b.header.height).unwrap_or(0)
+	}
+
+	/// Number of blocks from genesis to the tip, including ones not held in memory
+	pub fn block_count(&self) -> u64 {
+		self.blocks.last().map(|b| b.header.height + 1).unwrap_or(0)
+	}
+
+	/// The tip block
+	pub fn tip(&self) -> Option<&Block> {
+		self.blocks.last()
+	}
+
+	/// Height of the oldest block held in memory
+	pub fn first_loaded_height(&self) -> u64 {
+		self.blocks.first().map(|b| b.header.height).unwrap_or(0)
+	}
+
+	/// Sum of the work of every block up to the tip
+	pub fn chain_work(&self) -> u128 {
+		self.chain_work
+	}
+
+	/// Tip, height and cumulative work of the chain
+	pub fn metadata(&self) -> ChainMetadata {
+		ChainMetadata {
+			tip_hash: self.tip().map(|b| b.header.hash.clone()).unwrap_or_default(),
+			height: self.height(),
+			cumulative_work: self.chain_work,
+			pruned_height: None,
+		}
+	}
+
+	/// Block at `height` on this chain, read from the block store when it is older
+	/// than the blocks held in memory
+	pub fn block_at(&self, height: u64) -> Result<Option<Block>, String> {
+		if height > self.height() || self.blocks.is_empty() {
+			return Ok(None);
+		}
+		let first = self.first_loaded_height();
+		if height >= first {
+			return Ok(self.blocks.get((height - first) as usize).cloned());
+		}
+
+		match &self.block_store {
+			Some(block_store) => block_store.lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?
+				.get_block_by_height(height),
+			None => Ok(None),
+		}
+	}
+
+	/// Block on this chain with the given hash, searching the block store for
+	/// blocks older than the ones held in memory
+	pub fn find_block(&self, hash: &str) -> Result<Option<Block>, String> {
+		if let Some(block) = self.blocks.iter().find(|b| b.header.hash == hash) {
+			return Ok(Some(block.clone()));
+		}
+
+		let block_store = match &self.block_store {
+			Some(block_store) => block_store,
+			None => return Ok(None),
+		};
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+		match block_store_guard.get_block(hash)? {
+			// The store can hold blocks of abandoned branches; only accept ones on this chain
+			Some(block) if block.header.height < self.first_loaded_height()
+				&& block_store_guard.get_hash_by_height(block.header.height)?.as_deref() == Some(hash) => Ok(Some(block)),
+			_ => Ok(None),
+		}
+	}
+
+	/// Header of the block at `height` on this chain, read from the header index
+	/// when it is older than the blocks held in memory
+	pub fn header_at(&self, height: u64) -> Result<Option<BlockHeader>, String> {
+		let first = self.first_loaded_height();
+		if height >= first || self.block_store.is_none() {
+			return Ok(height.checked_sub(first)
+				.and_then(|offset| self.blocks.get(offset as usize))
+				.map(|block| block.header.clone()));
+		}
+		self.block_store.as_ref().unwrap().lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?
+			.get_header_by_height(height)
+	}
+
+	/// Header of the block on this chain with the given hash, without loading
+	/// its transactions
+	pub fn find_header(&self, hash: &str) -> Result<Option<BlockHeader>, String> {
+		if let Some(block) = self.blocks.iter().find(|b| b.header.hash == hash) {
+			return Ok(Some(block.header.clone()));
+		}
+
+		let block_store = match &self.block_store {
+			Some(block_store) => block_store,
+			None => return Ok(None),
+		};
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+		match block_store_guard.get_header(hash)? {
+			Some(header) if header.height < self.first_loaded_height()
+				&& block_store_guard.get_hash_by_height(header.height)?.as_deref() == Some(hash) => Ok(Some(header)),
+			_ => Ok(None),
+		}
+	}
+
+	/// Stored blocks that are not on this chain: those of branches a reorg left
+	/// behind. Chains without a block store have none.
+	pub fn stored_side_blocks(&self) -> Result<Vec<Block>, String> {
+		let Some(block_store) = &self.block_store else {
+			return Ok(Vec::new());
+		};
+		let stored = {
+			let block_store = block_store.lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?;
+			let mut blocks = Vec::new();
+			for hash in block_store.get_all_block_hashes()? {
+				if let Some(block) = block_store.get_block(&hash)? {
+					blocks.push(block);
+				}
+			}
+			blocks
+		};
+
+		let mut side = Vec::new();
+		for block in stored {
+			if self.block_hash_at(block.header.height)?.as_deref() != Some(block.header.hash.as_str()) {
+				side.push(block);
+			}
+		}
+		Ok(side)
+	}
+
+	/// Header of any stored block, whether on this chain or on a branch it left
+	pub fn stored_header(&self, hash: &str) -> Result<Option<BlockHeader>, String> {
+		if let Some(block) = self.blocks.iter().find(|b| b.header.hash == hash) {
+			return Ok(Some(block.header.clone()));
+		}
+		match &self.block_store {
+			Some(block_store) => block_store.lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?
+				.get_header(hash),
+			None => Ok(None),
+		}
+	}
+
+	/// Blocks at heights `from..=to` (clamped to the tip), borrowed when they are
+	/// all held in memory
+	pub fn blocks_in_range(&self, from: u64, to: u64) -> Result<Cow<'_, [Block]>, String> {
+		let to = to.min(self.height());
+		if from > to || self.blocks.is_empty() {
+			return Ok(Cow::Borrowed(&[]));
+		}
+		let first = self.first_loaded_height();
+		if from >= first {
+			return Ok(Cow::Borrowed(&self.blocks[(from - first) as usize..=(to - first) as usize]));
+		}
+
+		let mut blocks = Vec::new();
+		self.scan_blocks(from, to, |block| blocks.push(block.clone()))?;
+		Ok(Cow::Owned(blocks))
+	}
+
+	/// Balances after replaying every block on the chain
+	pub fn utxo_state(&self) -> Result<UTXOState, String> {
+		let mut state = UTXOState::new();
+		state.set_next_height(self.block_count());
+		self.try_scan_blocks(0, self.height(), |block| state.apply_block(block))?;
+		Ok(state)
+	}
+
+	/// Persist a block and its transactions to storage
+	fn persist_block(&self, block: &Block) -> Result<(), String> {
+		if !self.persistent {
+			return Ok(());
+		}
+
+		let block_store = self.block_store.as_ref().unwrap();
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+
+		// The indexes live in a second database, so the block is written together
+		// with a journal entry and the journal is cleared once the indexes are in.
+		// A crash in between is repaired by `recover_index_journal` on startup.
+		block_store_guard.store_block_journaled(block)?;
+		self.write_block_indexes(block)?;
+		block_store_guard.clear_index_journal()
+	}
+
+	/// Write the enabled indexes for every transaction in a block as one batch,
+	/// clearing the statuses left by an earlier disconnect of its transactions
+	fn write_block_indexes(&self, block: &Block) -> Result<(), String> {
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+
+		let mut operations = Vec::new();
+		let mut deletes = Vec::new();
+		for (tx_index, transaction) in block.transactions.iter().enumerate() {
+			if self.index_config.txindex {
+				operations.extend(Self::transaction_index_entries(block, tx_index, transaction)?);
+			}
+			if self.index_config.addressindex {
+				operations.extend(Self::address_index_entries(transaction));
+			}
+			deletes.push(Self::transaction_status_key(&transaction.hash()));
+		}
+		if self.index_config.addressindex {
+			let (puts, removed) = Self::address_balance_updates(&tx_store_guard, block, true)?;
+			operations.extend(puts);
+			deletes.extend(removed);
+		}
+
+		tx_store_guard.batch_write(operations, deletes)
+			.map_err(|e| format!("Failed to store transaction indexes: {}", e))
+	}
+
+	/// Remove the index entries of a disconnected block's transactions and
+	/// record them as reorged
+	fn remove_block_indexes(&self, block: &Block) -> Result<(), String> {
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+
+		let mut puts = Vec::new();
+		let mut deletes = Vec::new();
+		for (tx_index, transaction) in block.transactions.iter().enumerate() {
+			deletes.extend(Self::transaction_index_entries(block, tx_index, transaction)?.into_iter().map(|(key, _)| key));
+			deletes.extend(Self::address_index_entries(transaction).into_iter().map(|(key, _)| key));
+		}
+		for (txid, record) in Self::disconnected_statuses(block, self.clock.now()) {
+			let data = schema::encode(&record)
+				.map_err(|e| format!("Failed to serialize transaction status: {}", e))?;
+			puts.push((Self::transaction_status_key(&txid), data));
+		}
+		if self.index_config.addressindex {
+			let (updated, removed) = Self::address_balance_updates(&tx_store_guard, block, false)?;
+			puts.extend(updated);
+			deletes.extend(removed);
+		}
+
+		tx_store_guard.batch_write(puts, deletes)
+			.map_err(|e| format!("Failed to remove transaction indexes: {}", e))
+	}
+
+	/// Finish an index write interrupted by a crash. Index entries are plain puts,
+	/// so writing them again for the journaled block is safe.
+	/// Returns the hash of the recovered block, if any.
+	fn recover_index_journal(&self) -> Result<Option<String>, String> {
+		if !self.persistent {
+			return Ok(None);
+		}
+
+		let block_store = self.block_store.as_ref().unwrap();
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+
+		let hash = match block_store_guard.pending_index_journal()? {
+			Some(hash) => hash,
+			None => return Ok(None),
+		};
+
+		match self.blocks.iter().find(|b| b.header.hash == hash) {
+			Some(block) => {
+				self.write_block_indexes(block)?;
+				status!("Recovered interrupted index write for block {}", hash);
+			},
+			None => eprintln!("Warning: journaled block {} is not on the chain, dropping journal entry", hash),
+		}
+
+		block_store_guard.clear_index_journal()?;
+		Ok(Some(hash))
+	}
+
+	/// Get a transaction by hash
+	pub fn get_transaction(&self, tx_hash: &str) -> Result<Option<Transaction>, String> {
+		if !self.persistent {
+			// Search in-memory blocks
+			for block in &self.blocks {
+				for transaction in &block.transactions {
+					let hash = crate::crypto::hash::sha256_hash(&format!("{:?}", transaction));
+					if hash == tx_hash {
+						return Ok(Some(transaction.clone()));
+					}
+				}
+			}
+			return Ok(None);
+		}
+
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+		
+		let tx_key = format!("tx:{}", tx_hash);
+		
+		match tx_store_guard.get(&tx_key) {
+			Ok(Some(tx_data)) => {
+				let transaction: Transaction = schema::decode(RecordKind::Transaction, &tx_data)
+					.map_err(|e| format!("Failed to deserialize transaction: {}", e))?;
+				Ok(Some(transaction))
+			},
+			Ok(None) => Ok(None),
+			Err(e) => Err(format!("Database error: {}", e)),
+		}
+	}
+
+	/// Get transaction index information
+	pub fn get_transaction_index(&self, tx_hash: &str) -> Result<Option<TransactionIndex>, String> {
+		if !self.persistent {
+			// Search in-memory blocks
+			for block in &self.blocks {
+				for (tx_index, transaction) in block.transactions.iter().enumerate() {
+					if transaction.hash() == tx_hash {
+						return Ok(Some(TransactionIndex {
+							block_hash: block.header.hash.clone(),
+							block_height: block.header.height,
+							transaction_index: tx_index,
+							from: transaction.from.clone(),
+							to: transaction.to.clone(),
+							amount: transaction.amount,
+							timestamp: block.header.timestamp,
+						}));
+					}
+				}
+			}
+			return Ok(None);
+		}
+
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+		
+		let index_key = format!("tx_index:{}", tx_hash);
+		
+		match tx_store_guard.get(&index_key) {
+			Ok(Some(index_data)) => {
+				let index: TransactionIndex = schema::decode(RecordKind::TransactionIndex, &index_data)
+					.map_err(|e| format!("Failed to deserialize transaction index: {}", e))?;
+				Ok(Some(index))
+			},
+			Ok(None) => Ok(None),
+			Err(e) => Err(format!("Database error: {}", e)),
+		}
+	}
+
+	/// Which of `txids` the chain already holds. They are looked up in the
+	/// transaction index; without one, the blocks from `from_height` up are scanned.
+	pub fn confirmed_txids(&self, txids: &[String], from_height: u64) -> Result<HashSet<String>, String> {
+		let mut confirmed = HashSet::new();
+		if self.persistent && !self.index_config.txindex {
+			let wanted: HashSet<&String> = txids.iter().collect();
+			self.scan_blocks(from_height, self.height(), |block| {
+				for transaction in &block.transactions {
+					let txid = transaction.hash();
+					if wanted.contains(&txid) {
+						confirmed.insert(txid);
+					}
+				}
+			})?;
+			return Ok(confirmed);
+		}
+		for txid in txids {
+			if self.get_transaction_index(txid)?.is_some() {
+				confirmed.insert(txid.clone());
+			}
+		}
+		Ok(confirmed)
+	}
+
+	/// Proof that the confirmed transaction `tx_hash` is in its block, found
+	/// through the transaction index
+	pub fn transaction_proof(&self, tx_hash: &str) -> Result<Option<MerkleProof>, String> {
+		let Some(index) = self.get_transaction_index(tx_hash)? else {
+			return Ok(None);
+		};
+		let block = self.find_block(&index.block_hash)?
+			.ok_or_else(|| format!("Block {} of transaction {} is not on this chain", index.block_hash, tx_hash))?;
+		Ok(MerkleProof::build(&block, tx_hash))
+	}
+
+	/// Check a transaction proof against the headers of this chain, returning the
+	/// confirmations of the transaction
+	pub fn verify_transaction_proof(&self, proof: &MerkleProof) -> Result<u64, String> {
+		let header = self.find_header(&proof.block_hash)?
+			.ok_or_else(|| format!("Block {} is not on the local chain", proof.block_hash))?;
+		proof.verify(&header)?;
+		Ok(self.height() - header.height + 1)
+	}
+
+	/// Get all transactions for an address (both sent and received)
+	pub fn get_transactions_for_address(&self, address: &str) -> Result<Vec<(String, Transaction)>, String> {
+		if !self.index_config.addressindex {
+			return Err("Address index is disabled (start with --addressindex=1)".to_string());
+		}
+
+		let mut results = Vec::new();
+
+		if !self.persistent {
+			// Search in-memory blocks
+			for block in &self.blocks {
+				for transaction in &block.transactions {
+					if transaction.from == address || transaction.to == address {
+						let hash = crate::crypto::hash::sha256_hash(&format!("{:?}", transaction));
+						results.push((hash, transaction.clone()));
+					}
+				}
+			}
+			return Ok(results);
+		}
+
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+		
+		// Get transactions where this address is the sender. The trailing ':' keeps
+		// longer addresses that start with this one out of the scan.
+		let from_keys = tx_store_guard.keys_with_prefix(&format!("addr_from:{}:", address))
+			.map_err(|e| format!("Database error: {}", e))?;
+		
+		// Get transactions where this address is the recipient
+		let to_keys = tx_store_guard.keys_with_prefix(&format!("addr_to:{}:", address))
+			.map_err(|e| format!("Database error: {}", e))?;
+
+		// Combine and extract transaction hashes
+		let mut tx_hashes = std::collections::HashSet::new();
+		
+		for key in from_keys {
+			if let Some(tx_hash) = key.split(':').last() {
+				tx_hashes.insert(tx_hash.to_string());
+			}
+		}
+		
+		for key in to_keys {
+			if let Some(tx_hash) = key.split(':').last() {
+				tx_hashes.insert(tx_hash.to_string());
+			}
+		}
+
+		// Release the guard before calling get_transaction
+		drop(tx_store_guard);
+
+		// Retrieve each transaction
+		for tx_hash in tx_hashes {
+			if let Some(transaction) = self.get_transaction(&tx_hash)? {
+				results.push((tx_hash, transaction));
+			}
+		}
+
+		Ok(results)
+	}
+
+	/// Unspent outputs of an address in the order they were received, from the
+	/// transaction and address indexes
+	pub fn address_unspent_outputs(&self, address: &str) -> Result<Vec<UnspentOutput>, String> {
+		let mut history = Vec::new();
+		for (txid, transaction) in self.get_transactions_for_address(address)? {
+			if let Some(index) = self.get_transaction_index(&txid)? {
+				history.push((index.block_height, index.transaction_index, txid, transaction));
+			}
+		}
+		history.sort_by_key(|(height, position, _, _)| (*height, *position));
+
+		let tip_height = self.height();
+		let mut unspent: VecDeque<UnspentOutput> = VecDeque::new();
+		for (block_height, _, txid, transaction) in history {
+			// Self-transfers move no funds
+			if transaction.from == transaction.to {
+				continue;
+			}
+			if transaction.to == address {
+				let coinbase = is_coinbase_transaction(&transaction);
+				unspent.push_back(UnspentOutput {
+					txid,
+					vout: 0,
+					address: address.to_string(),
+					amount: transaction.amount,
+					block_height,
+					confirmations: tip_height - block_height + 1,
+					coinbase,
+					spendable: !coinbase || is_coinbase_mature(block_height, tip_height + 1),
+				});
+				continue;
+			}
+			let mut owed = transaction.amount;
+			while owed > 0 && let Some(oldest) = unspent.front_mut() {
+				let used = owed.min(oldest.amount);
+				oldest.amount -= used;
+				owed -= used;
+				if oldest.amount == 0 {
+					unspent.pop_front();
+				}
+			}
+		}
+		Ok(unspent.into())
+	}
+
+	pub fn validate_block(&self, block: &Block) -> bool {
+		let _timer = perf::timer(perf::BLOCK_VALIDATION);
+		let last_hash = self.blocks.last().map(|b| b.header.hash.clone()).unwrap_or_default();
+		let expected_height = self.block_count();
+		if block.header.previous_hash != last_hash || block.header.height != expected_height {
+			return false;
+		}
+		if self.invalid_blocks.contains_key(&block.header.hash) {
+			eprintln!("Rejecting block {}: marked invalid", block.header.height);
+			return false;
+		}
+		if let Some(reason) = self.rejected_blocks.get(&block.header.hash) {
+			eprintln!("Rejecting block {}: known invalid ({})", block.header.height, reason);
+			return false;
+		}
+
+		let checks = self.check_timestamp(block)
+			.and_then(|_| self.check_block_rules(block))
+			.and_then(|_| self.check_proof_of_work(block))
+			.and_then(|_| self.check_bits(block))
+			.and_then(|_| self.check_duplicate_transactions(block))
+			.and_then(|_| self.check_coinbase_maturity(block))
+			.and_then(|_| self.check_vault_spends(block));
+		match checks {
+			Ok(()) => true,
+			Err(e) => {
+				eprintln!("Rejecting block {}: {}", block.header.height, e);
+				false
+			}
+		}
+	}
+
+	/// The rules a block has to follow whatever chain it is on: amounts within
+	/// `MAX_MONEY`, coinbases within the subsidy and transaction lock and expiry
+	/// heights that allow the block. A block breaking one is invalid for good.
+	pub fn check_block_rules(&self, block: &Block) -> Result<(), String> {
+		self.check_amounts(block)?;
+		Self::check_transaction_heights(block)
+	}
+
+	/// Make sure the block's hash meets the target its bits name and that target is
+	/// no easier than the limit. Blocks without bits are held to the leading zeros
+	/// of the limit. Only depends on the block, so a block failing it is invalid
+	/// for good.
+	pub fn check_proof_of_work(&self, block: &Block) -> Result<(), String> {
+		if !ProofOfWork::with_bits(self.params.pow_limit_bits).validate_block(block) {
+			return Err(match block.header.bits {
+				Some(bits) => format!("hash does not meet the target of bits {:08x} within the limit {:08x}", bits, self.params.pow_limit_bits),
+				None => "hash does not meet the proof of work limit".to_string(),
+			});
+		}
+		Ok(())
+	}
+
+	/// Compact target the next block must carry: the tip's, rescaled every
+	/// `retarget_interval` blocks by how long the last interval took against
+	/// `target_block_time` per block. A tip without bits is followed by the limit.
+	pub fn next_bits(&self) -> Result<u32, String> {
+		let Some(tip) = self.blocks.last() else {
+			return Ok(self.params.pow_limit_bits);
+		};
+		let bits = tip.header.bits.unwrap_or(self.params.pow_limit_bits);
+		let height = tip.header.height + 1;
+		let retarget_interval = self.params.retarget_interval;
+		if !height.is_multiple_of(retarget_interval) {
+			return Ok(bits);
+		}
+
+		let mut interval = Vec::new();
+		self.scan_blocks(height - retarget_interval, tip.header.height, |block| interval.push(block.clone()))?;
+		let previous = self.timestamp_before(height - retarget_interval)?;
+		let mut pow = ProofOfWork::with_bits(bits).with_pow_limit(Target::from_compact(self.params.pow_limit_bits)?);
+		Ok(pow.retarget(&interval, previous, self.params.target_block_time))
+	}
+
+	/// Hold blocks to the retarget schedule. Blocks may only leave out bits below
+	/// the params' `bits_required_height` and while the tip has none, as blocks
+	/// stored before headers had bits did.
+	fn check_bits(&self, block: &Block) -> Result<(), String> {
+		let expected = self.next_bits()?;
+		match block.header.bits {
+			Some(bits) if bits != expected => Err(format!("bits {:08x} do not match the required {:08x}", bits, expected)),
+			None if block.header.height >= self.params.bits_required_height
+				|| self.tip().is_some_and(|tip| tip.header.bits.is_some()) => {
+				Err(format!("missing bits, {:08x} required", expected))
+			},
+			_ => Ok(()),
+		}
+	}
+
+	/// Refuse blocks timestamped more than `MAX_FUTURE_BLOCK_TIME` ahead of the
+	/// clock or earlier than the median time past at their height
+	fn check_timestamp(&self, block: &Block) -> Result<(), String> {
+		let now = self.clock.now();
+		if block.header.timestamp > now.saturating_add(MAX_FUTURE_BLOCK_TIME) {
+			return Err(format!("timestamp {} is more than {}s ahead of the clock ({})",
+				block.header.timestamp, MAX_FUTURE_BLOCK_TIME, now));
+		}
+		let earliest = self.median_time_past_at(block.header.height)?;
+		if block.header.timestamp < earliest {
+			return Err(format!("timestamp {} is earlier than the median time past {}",
+				block.header.timestamp, earliest));
+		}
+		Ok(())
+	}
+
+	/// Earliest timestamp the next block may carry: see `median_time_past_at`
+	pub fn median_time_past(&self) -> Result<u64, String> {
+		self.median_time_past_at(self.block_count())
+	}
+
+	/// Median timestamp of the `MEDIAN_TIME_SPAN` blocks below `height`, zero when
+	/// there are none. Genesis is left out: its timestamp is a fixed launch date
+	/// that chains built on it need not follow.
+	fn median_time_past_at(&self, height: u64) -> Result<u64, String> {
+		let start = height.saturating_sub(timestamps::MEDIAN_TIME_SPAN as u64).max(1);
+		let mut times = Vec::new();
+		for h in start..height {
+			if let Some(header) = self.header_at(h)? {
+				times.push(header.timestamp);
+			}
+		}
+		Ok(timestamps::median_time_past(&times))
+	}
+
+	/// Timestamp of the block before `height`, zero when that is genesis or
+	/// there is none, as genesis is left out of the median time past
+	fn timestamp_before(&self, height: u64) -> Result<u64, String> {
+		match height.checked_sub(1) {
+			Some(previous) if previous > 0 => Ok(self.header_at(previous)?.map_or(0, |header| header.timestamp)),
+			_ => Ok(0),
+		}
+	}
+
+	/// Keep every amount within `MAX_MONEY` and the rewards the block pays out
+	/// within the subsidy at its height. Fees are never paid to the miner, so the
+	/// subsidy is all a coinbase may claim.
+	fn check_amounts(&self, block: &Block) -> Result<(), String> {
+		let mut rewards = 0u64;
+		for tx in &block.transactions {
+			check_money_range(tx.amount)
+				.map_err(|e| format!("{} -> {}: {}", tx.from, tx.to, e))?;
+			if is_coinbase_transaction(tx) {
+				rewards = checked_money_add(rewards, tx.amount)
+					.map_err(|e| format!("coinbase total: {}", e))?;
+			}
+		}
+		let subsidy = self.params.subsidy_at(block.header.height);
+		if rewards > subsidy {
+			return Err(format!("coinbase pays {} but the subsidy at height {} is {}",
+				rewards, block.header.height, subsidy));
+		}
+		Ok(())
+	}
+
+	/// Make sure every transaction's lock and expiry heights allow this block
+	fn check_transaction_heights(block: &Block) -> Result<(), String> {
+		for tx in &block.transactions {
+			tx.check_height_bounds(block.header.height)
+				.map_err(|e| format!("{} -> {}: {}", tx.from, tx.to, e))?;
+		}
+		Ok(())
+	}
+
+	/// Reject blocks that repeat a transaction or include one that is already confirmed
+	fn check_duplicate_transactions(&self, block: &Block) -> Result<(), String> {
+		let mut txids = HashSet::new();
+		for tx in &block.transactions {
+			let tx_hash = tx.hash();
+			if !txids.insert(tx_hash.clone()) {
+				return Err(format!("transaction {} appears more than once", tx_hash));
+			}
+		}
+		match self.first_confirmed(&txids)? {
+			Some(tx_hash) => Err(format!("transaction {} is already confirmed", tx_hash)),
+			None => Ok(()),
+		}
+	}
+
+	/// One of `txids` that is already in the chain, if any. Uses the tx index when
+	/// available, matching its hits against this chain's blocks, since the index
+	/// may also describe blocks another view of the same storage has not adopted.
+	/// Otherwise the chain is scanned once for all of them.
+	fn first_confirmed(&self, txids: &HashSet<String>) -> Result<Option<String>, String> {
+		if self.persistent && self.index_config.txindex {
+			for tx_hash in txids {
+				if let Some(index) = self.get_transaction_index(tx_hash)?
+					&& self.block_hash_at(index.block_height)?.as_deref() == Some(index.block_hash.as_str()) {
+					return Ok(Some(tx_hash.clone()));
+				}
+			}
+			return Ok(None);
+		}
+
+		let mut confirmed = None;
+		self.scan_blocks(0, self.height(), |block| {
+			if confirmed.is_none() {
+				confirmed = block.transactions.iter().map(|tx| tx.hash()).find(|tx_hash| txids.contains(tx_hash));
+			}
+		})?;
+		Ok(confirmed)
+	}
+
+	/// Make sure no transaction in the block spends an immature coinbase reward
+	fn check_coinbase_maturity(&self, block: &Block) -> Result<(), String> {
+		// Only senders holding fresh coinbase rewards can break the rule
+		let immature = self.immature_coinbase_rewards()?;
+		if immature.is_empty() {
+			return Ok(());
+		}
+
+		// What each address gained and spent earlier in this block
+		let mut received: HashMap<&str, u64> = HashMap::new();
+		let mut sent: HashMap<&str, u64> = HashMap::new();
+		for tx in &block.transactions {
+			if is_coinbase_transaction(tx) || is_unvault_transaction(tx) {
+				continue;
+			}
+			if let Some(&locked) = immature.get(&tx.from) {
+				let spendable = self.confirmed_balance(&tx.from)?
+					.saturating_sub(locked)
+					.saturating_add(received.get(tx.from.as_str()).copied().unwrap_or(0))
+					.saturating_sub(sent.get(tx.from.as_str()).copied().unwrap_or(0));
+				if tx.amount > spendable {
+					return Err(format!(
+						"{} spends {} but only {} is mature ({} immature coinbase, maturity {} blocks)",
+						tx.from, tx.amount, spendable, locked, COINBASE_MATURITY
+					));
+				}
+			}
+			let spent = sent.entry(tx.from.as_str()).or_insert(0);
+			*spent = spent.saturating_add(tx.amount);
+			let gained = received.entry(tx.to.as_str()).or_insert(0);
+			*gained = gained.saturating_add(tx.amount);
+		}
+
+		Ok(())
+	}
+
+	/// Coinbase rewards not yet spendable in the next block, by recipient. They can
+	/// only be in the last `COINBASE_MATURITY` blocks, so only those are read.
+	fn immature_coinbase_rewards(&self) -> Result<HashMap<String, u64>, String> {
+		let next_height = self.block_count();
+		let mut immature: HashMap<String, u64> = HashMap::new();
+		self.scan_blocks(next_height.saturating_sub(COINBASE_MATURITY), self.height(), |block| {
+			for tx in block.transactions.iter().filter(|tx| is_coinbase_transaction(tx)) {
+				if !is_coinbase_mature(block.header.height, next_height) {
+					let reward = immature.entry(tx.to.clone()).or_insert(0);
+					*reward = reward.saturating_add(tx.amount);
+				}
+			}
+		})?;
+		Ok(immature)
+	}
+
+	/// Balance of an address at the tip, immature coinbase rewards included.
+	/// Persistent chains read it from the stored chainstate; in-memory chains
+	/// replay the address's balance changes.
+	fn confirmed_balance(&self, address: &str) -> Result<u64, String> {
+		if let Some(block_store) = &self.block_store {
+			return block_store.lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?
+				.get_balance(address);
+		}
+
+		let mut balance = 0;
+		for block in &self.blocks {
+			for (changed, change) in balance_changes(block) {
+				if changed == address {
+					balance = apply_change(balance, change)?;
+				}
+			}
+		}
+		Ok(balance)
+	}
+
+	/// Make sure every spend from a vault in the block waited out its unlock delay
+	fn check_vault_spends(&self, block: &Block) -> Result<(), String> {
+		let vaults: HashSet<&str> = block.transactions.iter()
+			.map(|tx| tx.from.as_str())
+			.filter(|from| is_vault_address(from))
+			.collect();
+		if vaults.is_empty() {
+			return Ok(());
+		}
+		let mut state = self.vault_unlocks(&vaults)?;
+
+		for tx in &block.transactions {
+			state.check_vault_spend(tx, block.header.height)?;
+			state.apply_vault_transaction(tx, block.header.height);
+		}
+
+		Ok(())
+	}
+
+	/// State holding the unlocks of `vaults` at the tip, replayed from what the
+	/// vaults sent. Their transactions come from the address index when it is
+	/// enabled, matched against this chain's blocks as in `first_confirmed`;
+	/// otherwise the chain is scanned once.
+	fn vault_unlocks(&self, vaults: &HashSet<&str>) -> Result<UTXOState, String> {
+		let mut sent: Vec<(u64, usize, Transaction)> = Vec::new();
+		if self.persistent && self.index_config.addressindex {
+			for vault in vaults {
+				for (txid, tx) in self.get_transactions_for_address(vault)? {
+					if tx.from != *vault {
+						continue;
+					}
+					if let Some(index) = self.get_transaction_index(&txid)?
+						&& self.block_hash_at(index.block_height)?.as_deref() == Some(index.block_hash.as_str()) {
+						sent.push((index.block_height, index.transaction_index, tx));
+					}
+				}
+			}
+		} else {
+			self.scan_blocks(0, self.height(), |block| {
+				for (position, tx) in block.transactions.iter().enumerate() {
+					if vaults.contains(tx.from.as_str()) {
+						sent.push((block.header.height, position, tx.clone()));
+					}
+				}
+			})?;
+		}
+		sent.sort_by_key(|(height, position, _)| (*height, *position));
+
+		let mut state = UTXOState::new();
+		for (height, _, tx) in &sent {
+			state.apply_vault_transaction(tx, *height);
+		}
+		Ok(state)
+	}
+
+	/// Visit the blocks at heights `from..=to` (clamped to the tip) in order.
+	/// Blocks older than the in-memory window are read from the block store one at a time.
+	pub fn scan_blocks<F: FnMut(&Block)>(&self, from: u64, to: u64, mut visit: F) -> Result<(), String> {
+		self.try_scan_blocks(from, to, |block| {
+			visit(block);
+			Ok(())
+		})
+	}
+
+	/// Like `scan_blocks`, stopping at the first error the visitor returns
+	pub fn try_scan_blocks<F: FnMut(&Block) -> Result<(), String>>(&self, from: u64, to: u64, mut visit: F) -> Result<(), String> {
+		if self.blocks.is_empty() {
+			return Ok(());
+		}
+		let to = to.min(self.height());
+		if from > to {
+			return Ok(());
+		}
+
+		// Blocks older than the in-memory window come from the block store
+		let first = self.first_loaded_height();
+		if from < first && let Some(block_store) = &self.block_store {
+			let block_store_guard = block_store.lock()
+				.map_err(|e| format!("Failed to lock block store: {}", e))?;
+			for block in block_store_guard.iter_blocks(from, (first - 1).min(to)) {
+				visit(&block?)?;
+			}
+		}
+		if to < first {
+			return Ok(());
+		}
+
+		let start = from.saturating_sub(first) as usize;
+		let end = (to - first) as usize;
+		self.blocks[start..=end].iter().try_for_each(visit)
+	}
+
+	/// Hash of the block at `height` on this chain
+	pub fn block_hash_at(&self, height: u64) -> Result<Option<String>, String> {
+		let first = self.first_loaded_height();
+		if height >= first || self.block_store.is_none() {
+			return Ok(height.checked_sub(first)
+				.and_then(|offset| self.blocks.get(offset as usize))
+				.map(|block| block.header.hash.clone()));
+		}
+		self.block_store.as_ref().unwrap().lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?
+			.get_hash_by_height(height)
+	}
+
+	/// Hashes of blocks on this chain from the tip back to genesis: the last ten
+	/// one by one, then at doubling distances. A peer looks for the first one it
+	/// has to find where its chain and ours split.
+	pub fn block_locator(&self) -> Result<Vec<String>, String> {
+		let mut locator = Vec::new();
+		if self.blocks.is_empty() {
+			return Ok(locator);
+		}
+		let mut height = self.height();
+		let mut step = 1;
+		loop {
+			if let Some(hash) = self.block_hash_at(height)? {
+				locator.push(hash);
+			}
+			if height == 0 {
+				break;
+			}
+			if locator.len() >= 10 {
+				step *= 2;
+			}
+			height = height.saturating_sub(step);
+		}
+		Ok(locator)
+	}
+
+	/// Height of the first locator hash that is on this chain, the last block
+	/// shared with whoever built the locator
+	pub fn locate_fork_point(&self, locator: &[String]) -> Result<Option<u64>, String> {
+		for hash in locator {
+			if let Some(header) = self.find_header(hash)? {
+				return Ok(Some(header.height));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Get chain statistics
+	pub fn get_stats(&self) -> ChainStats {
+		let mut total_transactions = 0;
+		if let Err(e) = self.scan_blocks(0, self.height(), |b| total_transactions += b.transactions.len()) {
+			eprintln!("Failed to count transactions: {}", e);
+		}
+		
+		ChainStats {
+			total_blocks: self.block_count() as usize,
+			total_transactions,
 			latest_block_hash: self.blocks.last().map(|b| b.header.hash.clone()).unwrap_or_default(),
-			chain_height: self.blocks.len().saturating_sub(1),
+			chain_height: self.height() as usize,
 			persistent: self.persistent,
 		}
 	}
@@ -337,17 +2013,621 @@ impl Chain {
 	/// Create a chain from a vector of blocks (for fork choice)
 	pub fn from_blocks(blocks: Vec<Block>) -> Self {
 		Chain {
+			chain_work: blocks.iter().fold(0u128, |work, b| work.saturating_add(b.work())),
 			blocks,
 			block_store: None,
 			transaction_store: None,
 			persistent: false,
+			memory_window: RECENT_BLOCKS_IN_MEMORY,
+			invalid_blocks: HashMap::new(),
+			rejected_blocks: HashMap::new(),
+			transaction_statuses: HashMap::new(),
+			index_config: IndexConfig::default(),
+			clock: clock::system(),
+			params: ChainParams::mainnet(),
 		}
 	}
 
-	/// Get the blocks (read-only access)
+	/// Get the blocks held in memory (read-only access)
 	pub fn get_blocks(&self) -> &[Block] {
 		&self.blocks
 	}
+
+	/// Resize the block store's block and header caches
+	pub fn set_block_cache_capacity(&self, block_bytes: usize, header_bytes: usize) -> Result<(), String> {
+		let block_store = self.block_store.as_ref()
+			.ok_or_else(|| "Block cache requires a persistent chain".to_string())?;
+		block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?
+			.set_cache_capacity(block_bytes, header_bytes);
+		Ok(())
+	}
+
+	/// Database size estimates plus block/header cache counters
+	pub fn storage_stats(&self) -> Result<StorageStats, String> {
+		let block_store = self.block_store.as_ref()
+			.ok_or_else(|| "Storage stats require a persistent chain".to_string())?;
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+		let (block_cache, header_cache) = block_store_guard.cache_stats();
+		Ok(StorageStats {
+			database: block_store_guard.get_stats()?,
+			block_cache,
+			header_cache,
+		})
+	}
+
+	/// Oldest schema version of the records in the block and transaction stores
+	pub fn schema_version(&self) -> Result<u8, String> {
+		let (Some(block_store), Some(tx_store)) = (&self.block_store, &self.transaction_store) else {
+			return Ok(SCHEMA_VERSION);
+		};
+		let block_version = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?
+			.schema_version()?;
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+		let empty = tx_store_guard.is_empty()
+			.map_err(|e| format!("Database error: {}", e))?;
+		Ok(block_version.min(schema::store_version(&tx_store_guard, empty)?))
+	}
+
+	/// Upgrade stored records to the current schema version. Both stores are first
+	/// copied to `backup_path`, with the transaction store in its `transactions`
+	/// subdirectory as in the data directory; nothing is copied when the stores are
+	/// already current.
+	pub fn migrate_schema(&self, backup_path: &str) -> Result<SchemaMigration, String> {
+		let (Some(block_store), Some(tx_store)) = (&self.block_store, &self.transaction_store) else {
+			return Err("Schema migration requires a persistent chain".to_string());
+		};
+		let from_version = self.schema_version()?;
+		if from_version == SCHEMA_VERSION {
+			return Ok(SchemaMigration {
+				from_version,
+				to_version: SCHEMA_VERSION,
+				records_migrated: 0,
+				backup_path: None,
+			});
+		}
+
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+
+		Self::write_checkpoint(&block_store_guard, &tx_store_guard, backup_path)?;
+
+		let records_migrated = block_store_guard.migrate_schema()?
+			+ schema::migrate_database(&tx_store_guard, RecordKind::TRANSACTION_STORE)?;
+		Ok(SchemaMigration {
+			from_version,
+			to_version: SCHEMA_VERSION,
+			records_migrated,
+			backup_path: Some(backup_path.to_string()),
+		})
+	}
+
+	/// Write a consistent snapshot of both stores to `path`, laid out like the
+	/// data directory with the transaction store in `transactions`
+	pub fn checkpoint_stores(&self, path: &str) -> Result<(), String> {
+		let (Some(block_store), Some(tx_store)) = (&self.block_store, &self.transaction_store) else {
+			return Err("Checkpoints require a persistent chain".to_string());
+		};
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+		Self::write_checkpoint(&block_store_guard, &tx_store_guard, path)
+	}
+
+	fn write_checkpoint(block_store: &BlockStore, tx_store: &Database, path: &str) -> Result<(), String> {
+		block_store.checkpoint(path)?;
+		let tx_path = format!("{}/transactions", path);
+		tx_store.checkpoint(&tx_path)
+			.map_err(|e| format!("Failed to back up transaction store to {}: {}", tx_path, e))
+	}
+
+	/// Find a block among the recent blocks held in memory; `find_block` also
+	/// searches older ones
+	pub fn get_block_by_hash(&self, hash: &str) -> Option<&Block> {
+		self.blocks.iter().find(|b| b.header.hash == hash)
+	}
+
+	/// Per-block statistics for the block at `height`
+	pub fn block_stats(&self, height: u64) -> Result<BlockStats, String> {
+		let block = self.block_at(height)?
+			.ok_or_else(|| format!("Block at height {} not found", height))?;
+
+		let subsidy = block.transactions.iter()
+			.filter(|tx| is_coinbase_transaction(tx))
+			.map(|tx| tx.amount)
+			.sum();
+		let time_delta = match height.checked_sub(1) {
+			Some(prev_height) => self.block_at(prev_height)?
+				.map(|prev| block.header.timestamp as i64 - prev.header.timestamp as i64),
+			None => None,
+		};
+
+		Ok(BlockStats {
+			height,
+			hash: block.header.hash.clone(),
+			timestamp: block.header.timestamp,
+			transaction_count: block.transactions.len(),
+			size_bytes: serde_json::to_string(&block).unwrap_or_default().len(),
+			nonce: block.header.nonce,
+			previous_hash: block.header.previous_hash.clone(),
+			// Transactions carry no fee field, so nothing is paid beyond the outputs
+			total_fees: 0,
+			subsidy,
+			time_delta,
+		})
+	}
+
+	/// Transaction rate over the last `nblocks` blocks ending at the tip
+	pub fn chain_tx_stats(&self, nblocks: Option<u64>) -> Result<ChainTxStats, String> {
+		let tip = self.blocks.last().ok_or_else(|| "No blocks in chain".to_string())?;
+		let tip_height = self.height();
+		let window = nblocks.unwrap_or(DEFAULT_TX_STATS_WINDOW.min(tip_height));
+		if window > tip_height {
+			return Err(format!("Window of {} blocks exceeds chain height {}", window, tip_height));
+		}
+
+		let blocks = self.blocks_in_range(tip_height - window, tip_height)?;
+		let window_tx_count = blocks[1..].iter()
+			.map(|b| b.transactions.len())
+			.sum::<usize>();
+		let mut total_tx_count = 0;
+		self.scan_blocks(0, tip_height, |b| total_tx_count += b.transactions.len())?;
+		let window_interval = timestamps::timespan(self.timestamp_before(tip_height - window)?, &blocks);
+		let tx_rate = timestamps::per_second(window_tx_count as u64, window_interval);
+
+		Ok(ChainTxStats {
+			tip_height,
+			tip_hash: tip.header.hash.clone(),
+			total_tx_count,
+			window_block_count: window,
+			window_tx_count,
+			window_interval,
+			tx_rate,
+		})
+	}
+
+	/// Cross-check the on-disk block store and indexes.
+	/// Levels: 0 = height mappings and block keys, 1 = block hashes and merkle roots,
+	/// 2 = transaction and address indexes, 3 = balance replay against the stored chainstate.
+	/// With `repair`, broken indexes are cleared and rebuilt from the stored blocks.
+	pub fn verify_storage(&self, level: u8, repair: bool) -> Result<StorageReport, String> {
+		if !self.persistent {
+			return Err("Storage verification requires a persistent chain".to_string());
+		}
+
+		let mut report = StorageReport {
+			level,
+			blocks_checked: 0,
+			index_entries_checked: 0,
+			issues: Vec::new(),
+			index_issues: 0,
+			repaired: false,
+		};
+
+		let stored_blocks = self.verify_block_store(level, &mut report)?;
+
+		if level >= 2 {
+			self.verify_indexes(&stored_blocks, &mut report)?;
+		}
+
+		if level >= 3 {
+			let mut balances = HashMap::new();
+			for block in &stored_blocks {
+				Self::replay_balance_changes(block, &mut balances, &mut report.issues);
+			}
+			self.compare_chainstate(&balances, &mut report.issues)?;
+		}
+
+		if repair && report.index_issues > 0 {
+			self.repair_indexes()?;
+			report.repaired = true;
+		}
+
+		Ok(report)
+	}
+
+	/// Check every height has a block stored under its own hash and that the blocks link up
+	fn verify_block_store(&self, level: u8, report: &mut StorageReport) -> Result<Vec<Block>, String> {
+		let block_store = self.block_store.as_ref().unwrap();
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+
+		let latest_height = match block_store_guard.get_latest_height()? {
+			Some(height) => height,
+			None => {
+				report.issues.push("No latest height recorded".to_string());
+				return Ok(Vec::new());
+			}
+		};
+
+		let mut blocks: Vec<Block> = Vec::new();
+		for height in 0..=latest_height {
+			report.blocks_checked += 1;
+
+			let hash = match block_store_guard.get_hash_by_height(height)? {
+				Some(hash) => hash,
+				None => {
+					report.issues.push(format!("Missing height mapping for height {}", height));
+					continue;
+				}
+			};
+
+			// Read from disk so a cached copy cannot hide corruption
+			let block = match block_store_guard.get_block_uncached(&hash) {
+				Ok(Some(block)) => block,
+				Ok(None) => {
+					report.issues.push(format!("Missing block {} at height {}", hash, height));
+					continue;
+				},
+				Err(e) => {
+					report.issues.push(format!("Unreadable block {} at height {}: {}", hash, height, e));
+					continue;
+				}
+			};
+
+			if block.header.hash != hash {
+				report.issues.push(format!("Block stored under {} has hash {}", hash, block.header.hash));
+			}
+			if block.header.height != height {
+				report.issues.push(format!("Block {} stored at height {} claims height {}", hash, height, block.header.height));
+			}
+			if blocks.last().is_some_and(|prev| block.header.previous_hash != prev.header.hash) {
+				report.issues.push(format!("Block at height {} does not link to height {}", height, height - 1));
+			}
+			if level >= 1 && !block.verify_integrity() {
+				report.issues.push(format!("Block {} at height {} fails hash/merkle check", hash, height));
+			}
+
+			blocks.push(block);
+		}
+
+		if blocks.len() as u64 != self.block_count() {
+			report.issues.push(format!("Store has {} blocks but the chain has {}", blocks.len(), self.block_count()));
+		}
+		match block_store_guard.get_chain_metadata()? {
+			Some(metadata) => {
+				let work = blocks.iter().fold(0u128, |work, b| work.saturating_add(b.work()));
+				if blocks.last().map(|b| &b.header.hash) != Some(&metadata.tip_hash) || metadata.height != latest_height {
+					report.issues.push(format!("Chain metadata points at {} (height {}), not the stored tip", metadata.tip_hash, metadata.height));
+				}
+				if metadata.cumulative_work != work {
+					report.issues.push(format!("Chain metadata records work {} but the stored blocks add up to {}", metadata.cumulative_work, work));
+				}
+			},
+			None => report.issues.push("No chain metadata recorded".to_string()),
+		}
+
+		Ok(blocks)
+	}
+
+	/// Check index entries exist for every stored transaction and point at real blocks
+	fn verify_indexes(&self, blocks: &[Block], report: &mut StorageReport) -> Result<(), String> {
+		let tx_store = self.transaction_store.as_ref().unwrap();
+		let tx_store_guard = tx_store.lock()
+			.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+
+		let mut issues = Vec::new();
+
+		if self.index_config.txindex {
+			for block in blocks {
+				for transaction in &block.transactions {
+					let tx_hash = transaction.hash();
+					report.index_entries_checked += 1;
+					let has_tx = tx_store_guard.get(&format!("tx:{}", tx_hash))
+						.map_err(|e| format!("Database error: {}", e))?
+						.is_some();
+					if !has_tx {
+						issues.push(format!("Missing tx entry for {}", tx_hash));
+					}
+				}
+			}
+
+			let index_keys = tx_store_guard.keys_with_prefix("tx_index:")
+				.map_err(|e| format!("Database error: {}", e))?;
+			for key in index_keys {
+				report.index_entries_checked += 1;
+				let tx_hash = key.trim_start_matches("tx_index:");
+				let entry = tx_store_guard.get(&key)
+					.map_err(|e| format!("Database error: {}", e))?
+					.and_then(|data| schema::decode::<TransactionIndex>(RecordKind::TransactionIndex, &data).ok());
+
+				let points_at_block = entry.as_ref()
+					.and_then(|entry| blocks.get(entry.block_height as usize)
+						.filter(|block| block.header.hash == entry.block_hash)
+						.and_then(|block| block.transactions.get(entry.transaction_index)))
+					.map(|tx| tx.hash() == tx_hash)
+					.unwrap_or(false);
+
+				if !points_at_block {
+					issues.push(format!("Index entry for {} does not point at a stored block", tx_hash));
+				}
+			}
+		}
+
+		if self.index_config.addressindex {
+			for block in blocks {
+				for transaction in &block.transactions {
+					for (key, _) in Self::address_index_entries(transaction) {
+						report.index_entries_checked += 1;
+						let exists = tx_store_guard.get(&key)
+							.map_err(|e| format!("Database error: {}", e))?
+							.is_some();
+						if !exists {
+							issues.push(format!("Missing address index entry {}", key));
+						}
+					}
+				}
+			}
+		}
+
+		report.index_issues = issues.len();
+		report.issues.extend(issues);
+		Ok(())
+	}
+
+	/// Drop all index entries and rebuild the enabled indexes from stored blocks
+	fn repair_indexes(&self) -> Result<(), String> {
+		{
+			let tx_store = self.transaction_store.as_ref().unwrap();
+			let tx_store_guard = tx_store.lock()
+				.map_err(|e| format!("Failed to lock transaction store: {}", e))?;
+
+			for prefix in ["tx:", "tx_index:", "addr_from:", "addr_to:"] {
+				let keys = tx_store_guard.keys_with_prefix(prefix)
+					.map_err(|e| format!("Database error: {}", e))?;
+				for key in keys {
+					tx_store_guard.delete(&key)
+						.map_err(|e| format!("Failed to delete index entry: {}", e))?;
+				}
+			}
+		}
+
+		if self.index_config.txindex {
+			self.rebuild_transaction_index()?;
+		}
+		if self.index_config.addressindex {
+			self.rebuild_address_index()?;
+		}
+		Ok(())
+	}
+
+	/// Re-validate the last `nblocks` blocks, or every block for 0. Each level adds to the
+	/// ones below: 0 = heights and hash linkage, 1 = block hashes and merkle roots,
+	/// 2 = proof of work, 3 = transaction signatures and height bounds, 4 = balance replay
+	/// from genesis, checking every spend in the window and the stored chainstate.
+	/// `progress` is called after each block with the number checked and the total.
+	pub fn verify_chain<F: FnMut(u64, u64)>(&self, level: u8, nblocks: u64, mut progress: F) -> Result<ChainVerification, String> {
+		if level > MAX_CHECK_LEVEL {
+			return Err(format!("Check level must be between 0 and {}", MAX_CHECK_LEVEL));
+		}
+
+		let to_height = self.height();
+		let from_height = match nblocks {
+			0 => 0,
+			n => (to_height + 1).saturating_sub(n),
+		};
+		let mut report = ChainVerification {
+			level,
+			from_height,
+			to_height,
+			blocks_checked: 0,
+			issues: Vec::new(),
+		};
+		if self.blocks.is_empty() {
+			return Ok(report);
+		}
+
+		let total = to_height - from_height + 1;
+		let mut previous_hash = match from_height {
+			0 => None,
+			height => self.block_hash_at(height - 1)?,
+		};
+		self.scan_blocks(from_height, to_height, |block| {
+			let height = block.header.height;
+			if let Some(previous_hash) = &previous_hash && block.header.previous_hash != *previous_hash {
+				report.issues.push(format!("Block {} at height {} does not link to the block below it", block.header.hash, height));
+			}
+			previous_hash = Some(block.header.hash.clone());
+
+			if level >= 1 && !block.verify_integrity() {
+				report.issues.push(format!("Block {} at height {} has a bad hash or merkle root", block.header.hash, height));
+			}
+			// The genesis block is not mined
+			if level >= 2 && height > 0 && self.check_proof_of_work(block).is_err() {
+				report.issues.push(format!("Block {} at height {} does not meet the proof of work target", block.header.hash, height));
+			}
+			if level >= 3 {
+				report.issues.extend(Self::verify_block_transactions(block).into_iter()
+					.map(|issue| format!("Block {} at height {}: {}", block.header.hash, height, issue)));
+			}
+
+			report.blocks_checked += 1;
+			progress(report.blocks_checked, total);
+		})?;
+
+		// Blocks are numbered by position, so a missing one shows up as a short count
+		if report.blocks_checked != total {
+			report.issues.push(format!("Expected {} blocks between heights {} and {}, found {}", total, from_height, to_height, report.blocks_checked));
+		}
+
+		if level >= 4 {
+			self.verify_balance_replay(from_height, &mut report)?;
+		}
+
+		Ok(report)
+	}
+
+	/// Signature format, height bounds and duplicates of a block's transactions.
+	/// Plain senders' signatures must be whole 64-byte signatures; multisig
+	/// senders' cosignatures must pass `check_cosignatures`, as in the mempool.
+	fn verify_block_transactions(block: &Block) -> Vec<String> {
+		let mut issues = Vec::new();
+		if let Err(e) = Self::check_transaction_heights(block) {
+			issues.push(e);
+		}
+
+		let mut seen = HashSet::new();
+		for tx in &block.transactions {
+			let tx_hash = tx.hash();
+			if !seen.insert(tx_hash.clone()) {
+				issues.push(format!("transaction {} appears more than once", tx_hash));
+			}
+			// Coinbase transactions carry no signature; the genesis message rides in the field
+			if is_multisig_address(&tx.from) {
+				if let Err(e) = check_cosignatures(tx) {
+					issues.push(format!("transaction {} has invalid cosignatures: {}", tx_hash, e));
+				}
+			} else if tx.from != COINBASE_ADDRESS && !tx.signature.len().is_multiple_of(64) {
+				issues.push(format!("transaction {} has a malformed signature", tx_hash));
+			}
+		}
+		issues
+	}
+
+	/// Replay balances from genesis, flagging spends in the checked window that exceed
+	/// the sender's balance, then compare the result with the stored chainstate.
+	/// Coinbase maturity is ignored, as in the chainstate.
+	fn verify_balance_replay(&self, from_height: u64, report: &mut ChainVerification) -> Result<(), String> {
+		let mut balances: HashMap<String, u64> = HashMap::new();
+		self.scan_blocks(0, self.height(), |block| {
+			for tx in &block.transactions {
+				let balance = balances.get(&tx.from).copied().unwrap_or(0);
+				if block.header.height >= from_height && !is_coinbase_transaction(tx) && balance < tx.amount {
+					report.issues.push(format!(
+						"Block {} at height {}: {} spends {} with a balance of {}",
+						block.header.hash, block.header.height, tx.from, tx.amount, balance
+					));
+				}
+			}
+			Self::replay_balance_changes(block, &mut balances, &mut report.issues);
+		})?;
+
+		self.compare_chainstate(&balances, &mut report.issues)
+	}
+
+	/// Apply a block's balance changes to `balances` the way the chainstate does,
+	/// reporting credits past `MAX_MONEY` in `issues`
+	fn replay_balance_changes(block: &Block, balances: &mut HashMap<String, u64>, issues: &mut Vec<String>) {
+		for (address, change) in balance_changes(block) {
+			let balance = balances.entry(address).or_insert(0);
+			match apply_change(*balance, change) {
+				Ok(updated) => *balance = updated,
+				Err(e) => issues.push(format!(
+					"Block {} at height {}: {}", block.header.hash, block.header.height, e
+				)),
+			}
+		}
+	}
+
+	/// Compare balances replayed from the stored blocks with the persisted
+	/// chainstate, reporting every address on which they disagree in `issues`,
+	/// including addresses only one of them has. Chains without a store pass.
+	fn compare_chainstate(&self, replayed: &HashMap<String, u64>, issues: &mut Vec<String>) -> Result<(), String> {
+		let Some(block_store) = &self.block_store else {
+			return Ok(());
+		};
+		let block_store_guard = block_store.lock()
+			.map_err(|e| format!("Failed to lock block store: {}", e))?;
+		if !block_store_guard.has_chainstate()? {
+			issues.push("Stored chainstate is missing".to_string());
+			return Ok(());
+		}
+
+		let stored = block_store_guard.get_all_balances()?;
+		let mut addresses: Vec<&String> = replayed.keys().chain(stored.keys()).collect();
+		addresses.sort();
+		addresses.dedup();
+		for address in addresses {
+			let replay = replayed.get(address).copied().unwrap_or(0);
+			let store = stored.get(address).copied().unwrap_or(0);
+			if replay != store {
+				issues.push(format!("Chainstate mismatch for {}: replay gives {}, store has {}", address, replay, store));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Statistics for a single block
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockStats {
+	pub height: u64,
+	pub hash: String,
+	pub timestamp: u64,
+	pub transaction_count: usize,
+	pub size_bytes: usize,
+	pub nonce: u64,
+	pub previous_hash: String,
+	pub total_fees: u64,
+	/// Coins created by the block's coinbase transactions
+	pub subsidy: u64,
+	/// Seconds since the previous block (None for genesis)
+	pub time_delta: Option<i64>,
+}
+
+/// Default number of blocks `chain_tx_stats` looks back over
+pub const DEFAULT_TX_STATS_WINDOW: u64 = 30;
+
+/// Transaction throughput over a window of recent blocks
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainTxStats {
+	pub tip_height: u64,
+	pub tip_hash: String,
+	pub total_tx_count: usize,
+	pub window_block_count: u64,
+	pub window_tx_count: usize,
+	/// Seconds between the block before the window and the tip
+	pub window_interval: u64,
+	/// Transactions per second over the window (None if no time elapsed)
+	pub tx_rate: Option<f64>,
+}
+
+/// Block database size estimates and cache counters
+#[derive(Debug)]
+pub struct StorageStats {
+	pub database: DatabaseStats,
+	pub block_cache: CacheStats,
+	pub header_cache: CacheStats,
+}
+
+/// Result of a storage self-check
+#[derive(Debug)]
+pub struct StorageReport {
+	pub level: u8,
+	pub blocks_checked: usize,
+	pub index_entries_checked: usize,
+	pub issues: Vec<String>,
+	/// Number of issues caused by broken indexes (repairable)
+	pub index_issues: usize,
+	pub repaired: bool,
+}
+
+impl StorageReport {
+	pub fn is_ok(&self) -> bool {
+		self.issues.is_empty()
+	}
+}
+
+/// Result of re-validating the most recent blocks
+#[derive(Debug, Serialize)]
+pub struct ChainVerification {
+	pub level: u8,
+	pub from_height: u64,
+	pub to_height: u64,
+	pub blocks_checked: u64,
+	pub issues: Vec<String>,
+}
+
+impl ChainVerification {
+	pub fn is_ok(&self) -> bool {
+		self.issues.is_empty()
+	}
 }
 
 /// Chain statistics structure
@@ -0,0 +1,50 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::blockchain::block::Block;
+
+/// Change to the active chain, sent to event bus subscribers
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+	/// A block was connected to the tip of the active chain
+	BlockConnected(Block),
+	/// The tip block was disconnected, returning its transactions to the
+	/// mempool or abandoning them
+	BlockDisconnected(Block),
+	/// The active chain switched to another branch
+	Reorg {
+		old_tip: String,
+		new_tip: String,
+		/// Blocks of the old branch that were disconnected
+		depth: u64,
+	},
+}
+
+/// Fans chain events out to subscribers. Clones share the subscriber list, so a
+/// bus can be handed to every component that connects blocks.
+#[derive(Clone, Default)]
+pub struct EventBus {
+	subscribers: Arc<Mutex<Vec<Sender<ChainEvent>>>>,
+}
+
+impl EventBus {
+	pub fn new() -> Self {
+		EventBus::default()
+	}
+
+	/// Subscribe to chain events
+	pub fn subscribe(&self) -> Receiver<ChainEvent> {
+		let (sender, receiver) = channel();
+		self.subscribers.lock().unwrap().push(sender);
+		receiver
+	}
+
+	/// Send an event to all subscribers, dropping ones that hung up
+	pub fn publish(&self, event: ChainEvent) {
+		self.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(event.clone()).is_ok());
+	}
+
+	pub fn subscriber_count(&self) -> usize {
+		self.subscribers.lock().unwrap().len()
+	}
+}
@@ -1,4 +1,6 @@
+use crate::blockchain::amount::UNITS_PER_COIN;
 use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::vault::parse_vault_address;
 
 /// Genesis block configuration
 pub struct GenesisConfig {
@@ -11,12 +13,12 @@ pub struct GenesisConfig {
 impl Default for GenesisConfig {
     fn default() -> Self {
         GenesisConfig {
-            total_supply: 21_000_000, // Similar to Bitcoin's max supply
+            total_supply: 21_000_000 * UNITS_PER_COIN, // Similar to Bitcoin's max supply
             initial_allocations: vec![
-                ("1RustChainFoundation".to_string(), 2_100_000), // 10% to foundation
-                ("1EarlyInvestors".to_string(), 1_050_000),      // 5% to early investors
-                ("1Development".to_string(), 2_100_000),         // 10% to development
-                ("1Community".to_string(), 15_750_000),          // 75% to community/mining
+                ("1RustChainFoundation".to_string(), 2_100_000 * UNITS_PER_COIN), // 10% to foundation
+                ("1EarlyInvestors".to_string(), 1_050_000 * UNITS_PER_COIN),      // 5% to early investors
+                ("1Development".to_string(), 2_100_000 * UNITS_PER_COIN),         // 10% to development
+                ("1Community".to_string(), 15_750_000 * UNITS_PER_COIN),          // 75% to community/mining
             ],
             genesis_message: "RustChain Genesis Block - A decentralized blockchain built in Rust".to_string(),
             timestamp: 1723804800, // August 16, 2024 00:00:00 UTC (example launch date)
@@ -24,6 +26,9 @@ impl Default for GenesisConfig {
     }
 }
 
+/// Null address used as the sender of coinbase transactions
+pub const COINBASE_ADDRESS: &str = "0000000000000000000000000000000000000000";
+
 /// Create a coinbase transaction (creates new coins from nothing)
 fn create_coinbase_transaction(to: &str, amount: u64, message: Option<String>) -> Transaction {
     Transaction {
@@ -31,9 +36,41 @@ fn create_coinbase_transaction(to: &str, amount: u64, message: Option<String>) -
         to: to.to_string(),
         amount,
         signature: message.unwrap_or_default().into_bytes(), // Use signature field for genesis message
+        lock_height: None,
+        expiry_height: None,
+    }
+}
+
+/// Coinbase paying a mined block's reward to `to`. It is locked to the block's
+/// height, so equal rewards in different blocks are different transactions.
+pub fn coinbase_transaction(to: &str, amount: u64, height: u64) -> Transaction {
+    Transaction {
+        lock_height: Some(height),
+        ..create_coinbase_transaction(to, amount, None)
     }
 }
 
+/// Check that block rewards can be paid to `address`: letters and digits only,
+/// or a vault address, and not the null coinbase address
+pub fn check_payout_address(address: &str) -> Result<(), String> {
+    let owner = match parse_vault_address(address) {
+        Some((_, owner)) => owner,
+        None => address,
+    };
+    if owner.len() < 3 || owner.len() > 64 || !owner.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("Invalid payout address '{}'", address));
+    }
+    if address == COINBASE_ADDRESS {
+        return Err("Payout address cannot be the null coinbase address".to_string());
+    }
+    Ok(())
+}
+
+/// Check if a transaction creates new coins
+pub fn is_coinbase_transaction(tx: &Transaction) -> bool {
+    tx.from == COINBASE_ADDRESS && tx.amount > 0
+}
+
 /// Check if a transaction is a genesis message transaction
 pub fn is_genesis_message_transaction(tx: &Transaction) -> bool {
     tx.from == "0000000000000000000000000000000000000000" && 
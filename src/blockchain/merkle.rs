@@ -0,0 +1,156 @@
+//! Transaction inclusion proofs
+//!
+//! A `MerkleProof` shows that a transaction is part of a block without the rest
+//! of the block: the transaction, its position and the sibling hashes on the
+//! path up the merkle tree. Whoever holds the block header can hash the path back
+//! up to the merkle root and compare, so a wallet that knows the header chain can
+//! check a payment it was sent without trusting the node that served the proof.
+//! Trees are built as in `calculate_merkle_root`: the last hash of a level with an
+//! odd number of hashes is paired with itself.
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::block::{Block, BlockHeader, Transaction};
+use crate::crypto::hash::sha256_hash;
+
+/// Path from one transaction of a block up to the block's merkle root
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MerkleProof {
+    pub txid: String,
+    pub transaction: Transaction,
+    pub block_hash: String,
+    pub block_height: u64,
+    /// Position of the transaction in the block
+    pub index: usize,
+    /// Sibling hashes from the transaction's level up to just below the root
+    pub branch: Vec<String>,
+}
+
+impl MerkleProof {
+    /// Proof that the transaction `txid` is in `block`, None when it is not
+    pub fn build(block: &Block, txid: &str) -> Option<Self> {
+        let mut hashes: Vec<String> = block.transactions.iter().map(Transaction::hash).collect();
+        let index = hashes.iter().position(|hash| hash == txid)?;
+
+        let mut branch = Vec::new();
+        let mut position = index;
+        while hashes.len() > 1 {
+            let sibling = (position ^ 1).min(hashes.len() - 1);
+            branch.push(hashes[sibling].clone());
+            hashes = hashes.chunks(2)
+                .map(|pair| combine(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+                .collect();
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            txid: txid.to_string(),
+            transaction: block.transactions[index].clone(),
+            block_hash: block.header.hash.clone(),
+            block_height: block.header.height,
+            index,
+            branch,
+        })
+    }
+
+    /// Merkle root the branch leads to from the transaction id
+    pub fn merkle_root(&self) -> String {
+        let mut hash = self.txid.clone();
+        let mut position = self.index;
+        for sibling in &self.branch {
+            hash = if position.is_multiple_of(2) { combine(&hash, sibling) } else { combine(sibling, &hash) };
+            position /= 2;
+        }
+        hash
+    }
+
+    /// Check the proof against `header`, the block it names as known locally
+    pub fn verify(&self, header: &BlockHeader) -> Result<(), String> {
+        if self.transaction.hash() != self.txid {
+            return Err(format!("Proof carries a transaction that is not {}", self.txid));
+        }
+        if header.hash != self.block_hash || header.height != self.block_height {
+            return Err(format!("Proof is for block {} at height {}, not block {} at height {}",
+                self.block_hash, self.block_height, header.hash, header.height));
+        }
+        if self.merkle_root() != header.merkle_root {
+            return Err(format!("Proof does not lead to the merkle root of block {}", header.hash));
+        }
+        Ok(())
+    }
+
+    /// The proof serialized as hex, for passing around as one string
+    pub fn to_hex(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("Proof serialization cannot fail"))
+    }
+
+    pub fn from_hex(data: &str) -> Result<Self, String> {
+        let bytes = hex::decode(data.trim())
+            .map_err(|e| format!("Invalid hex encoding: {}", e))?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| format!("Failed to decode proof: {}", e))
+    }
+}
+
+fn combine(left: &str, right: &str) -> String {
+    sha256_hash(&format!("{}{}", left, right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(to: &str, amount: u64) -> Transaction {
+        Transaction {
+            from: "alice".to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        }
+    }
+
+    #[test]
+    fn test_proofs_lead_to_merkle_root() {
+        // Odd and even levels, and a block of one transaction with an empty branch
+        for count in [1, 2, 3, 5, 8] {
+            let transactions: Vec<Transaction> = (0..count).map(|i| payment("bob", i as u64 + 1)).collect();
+            let block = Block::new("0".to_string(), transactions.clone(), 0, 0, 3);
+            for (index, tx) in transactions.iter().enumerate() {
+                let proof = MerkleProof::build(&block, &tx.hash()).unwrap();
+                assert_eq!(proof.index, index);
+                assert_eq!(proof.merkle_root(), block.header.merkle_root);
+                assert_eq!(proof.verify(&block.header), Ok(()));
+                assert_eq!(MerkleProof::from_hex(&proof.to_hex()).unwrap().to_hex(), proof.to_hex());
+            }
+        }
+
+        let block = Block::new("0".to_string(), vec![payment("bob", 1)], 0, 0, 3);
+        assert!(MerkleProof::build(&block, &payment("carol", 1).hash()).is_none());
+        assert!(MerkleProof::from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_tampered_proofs_are_refused() {
+        let transactions = vec![payment("bob", 1), payment("carol", 2), payment("dave", 3)];
+        let block = Block::new("0".to_string(), transactions, 0, 0, 3);
+        let proof = MerkleProof::build(&block, &block.transactions[1].hash()).unwrap();
+
+        let mut inflated = proof.clone();
+        inflated.transaction.amount = 2_000;
+        assert!(inflated.verify(&block.header).is_err());
+
+        let mut forged = proof.clone();
+        forged.transaction = payment("mallory", 2);
+        forged.txid = forged.transaction.hash();
+        assert!(forged.verify(&block.header).is_err());
+
+        let mut moved = proof.clone();
+        moved.index = 0;
+        assert!(moved.verify(&block.header).is_err());
+
+        let other = Block::new(block.header.hash.clone(), vec![payment("carol", 2)], 0, 0, 4);
+        assert!(proof.verify(&other.header).is_err());
+    }
+}
@@ -1,4 +1,10 @@
+pub mod amount;
+pub mod analytics;
 pub mod block;
 pub mod chain;
+pub mod events;
 pub mod genesis;
+pub mod merkle;
+pub mod multisig;
 pub mod state;
+pub mod vault;
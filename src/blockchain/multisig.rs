@@ -0,0 +1,162 @@
+//! Multisig addresses shared by several cosigners
+//!
+//! A multisig address names how many signatures a spend needs and the public
+//! keys allowed to give them: `multisig:<required>:<key>,<key>,...`, keys in hex
+//! and sorted, so one set of cosigners always has one address. A transaction
+//! from it carries each signature after the public key that made it, in public
+//! key order, and is valid once `required` distinct cosigners of the address
+//! have signed it.
+
+use ed25519_dalek::VerifyingKey;
+
+use crate::blockchain::block::Transaction;
+use crate::crypto::signature::verify_signature;
+
+/// Prefix of every multisig address
+pub const MULTISIG_PREFIX: &str = "multisig:";
+
+/// Most cosigners one address may have
+pub const MAX_COSIGNERS: usize = 15;
+
+/// Bytes of one cosignature: the signer's public key, then its signature
+pub const COSIGNATURE_LEN: usize = 32 + 64;
+
+/// Address spendable by `required` of `cosigners`, given as hex public keys
+pub fn multisig_address(required: usize, cosigners: &[String]) -> Result<String, String> {
+    let mut keys: Vec<String> = cosigners.iter().map(|key| key.to_lowercase()).collect();
+    keys.sort();
+    keys.dedup();
+    if keys.len() != cosigners.len() {
+        return Err("Cosigner keys must be distinct".to_string());
+    }
+    if let Some(key) = keys.iter().find(|key| parse_public_key(key).is_none()) {
+        return Err(format!("Invalid cosigner public key '{}'", key));
+    }
+    if keys.is_empty() || keys.len() > MAX_COSIGNERS {
+        return Err(format!("A multisig address needs between 1 and {} cosigners", MAX_COSIGNERS));
+    }
+    if required == 0 || required > keys.len() {
+        return Err(format!("Required signatures must be between 1 and {}", keys.len()));
+    }
+    Ok(format!("{}{}:{}", MULTISIG_PREFIX, required, keys.join(",")))
+}
+
+/// Required signatures and cosigner keys of a multisig address, or None for
+/// other addresses
+pub fn parse_multisig_address(address: &str) -> Option<(usize, Vec<&str>)> {
+    let (required, keys) = address.strip_prefix(MULTISIG_PREFIX)?.split_once(':')?;
+    let required = required.parse::<usize>().ok()?;
+    let keys: Vec<&str> = keys.split(',').collect();
+    let canonical = keys.windows(2).all(|pair| pair[0] < pair[1])
+        && keys.iter().all(|key| parse_public_key(key).is_some() && *key == key.to_lowercase());
+    (canonical && (1..=keys.len()).contains(&required) && keys.len() <= MAX_COSIGNERS).then_some((required, keys))
+}
+
+pub fn is_multisig_address(address: &str) -> bool {
+    parse_multisig_address(address).is_some()
+}
+
+/// Check the cosignatures of a transaction from a multisig address: every one
+/// is by a distinct cosigner of the address and signs the transaction, and
+/// there are at least as many as the address requires
+pub fn check_cosignatures(transaction: &Transaction) -> Result<(), String> {
+    let (required, keys) = parse_multisig_address(&transaction.from)
+        .ok_or_else(|| format!("{} is not a multisig address", transaction.from))?;
+    if transaction.signature.is_empty() || !transaction.signature.len().is_multiple_of(COSIGNATURE_LEN) {
+        return Err("Malformed cosignatures".to_string());
+    }
+
+    let message = transaction.signing_message();
+    let mut signers: Vec<String> = Vec::new();
+    for cosignature in transaction.signature.chunks(COSIGNATURE_LEN) {
+        let (key, signature) = cosignature.split_at(32);
+        let key_hex = hex::encode(key);
+        if !keys.contains(&key_hex.as_str()) {
+            return Err(format!("{} is not a cosigner of {}", key_hex, transaction.from));
+        }
+        if signers.contains(&key_hex) {
+            return Err(format!("Cosigner {} signed twice", key_hex));
+        }
+        let verifying_key = parse_public_key(&key_hex).ok_or_else(|| format!("Invalid public key {}", key_hex))?;
+        if !verify_signature(&verifying_key, message.as_bytes(), signature) {
+            return Err(format!("Signature from {} does not match the transaction", key_hex));
+        }
+        signers.push(key_hex);
+    }
+    if signers.len() < required {
+        return Err(format!("Transaction has {} of {} required signatures", signers.len(), required));
+    }
+    Ok(())
+}
+
+/// Public key from its hex encoding, as wallet addresses are
+pub fn parse_public_key(key: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(key).ok()?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn key(byte: u8) -> SigningKey {
+        SigningKey::from_bytes(&[byte; 32])
+    }
+
+    fn public(key: &SigningKey) -> String {
+        hex::encode(key.verifying_key().as_bytes())
+    }
+
+    fn cosign(transaction: &mut Transaction, signer: &SigningKey) {
+        transaction.signature.extend(signer.verifying_key().as_bytes());
+        transaction.signature.extend(signer.sign(transaction.signing_message().as_bytes()).to_bytes());
+    }
+
+    #[test]
+    fn test_multisig_address_round_trip() {
+        let (a, b) = (public(&key(1)), public(&key(2)));
+        let address = multisig_address(2, &[b.clone(), a.clone()]).unwrap();
+        assert_eq!(address, multisig_address(2, &[a.clone(), b.clone()]).unwrap());
+        assert_eq!(parse_multisig_address(&address).unwrap().0, 2);
+
+        assert!(multisig_address(3, &[a.clone(), b.clone()]).is_err());
+        assert!(multisig_address(1, &[a.clone(), a.clone()]).is_err());
+        assert!(multisig_address(1, &["alice".to_string()]).is_err());
+        assert!(!is_multisig_address("multisig:1:alice"));
+        assert!(!is_multisig_address(&format!("multisig:1:{},{}", b, a)));
+    }
+
+    #[test]
+    fn test_cosignatures_must_come_from_cosigners() {
+        let (a, b, outsider) = (key(1), key(2), key(3));
+        let address = multisig_address(2, &[public(&a), public(&b)]).unwrap();
+        let mut transaction = Transaction {
+            from: address,
+            to: "bob".to_string(),
+            amount: 25,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+
+        let mut one = transaction.clone();
+        cosign(&mut one, &a);
+        assert!(check_cosignatures(&one).unwrap_err().contains("1 of 2"));
+
+        // A key outside the set, or the same cosigner twice, does not count
+        let mut foreign = one.clone();
+        cosign(&mut foreign, &outsider);
+        assert!(check_cosignatures(&foreign).unwrap_err().contains("not a cosigner"));
+        let mut twice = one.clone();
+        cosign(&mut twice, &a);
+        assert!(check_cosignatures(&twice).unwrap_err().contains("twice"));
+
+        cosign(&mut transaction, &a);
+        cosign(&mut transaction, &b);
+        assert_eq!(check_cosignatures(&transaction), Ok(()));
+        // Signatures cover the payment
+        transaction.amount = 26;
+        assert!(check_cosignatures(&transaction).is_err());
+    }
+}
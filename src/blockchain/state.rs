@@ -1,4 +1,17 @@
 use std::collections::HashMap;
+use crate::blockchain::amount::checked_money_add;
+use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::genesis::is_coinbase_transaction;
+use crate::blockchain::vault::{is_clawback_transaction, is_unvault_transaction, parse_vault_address, VaultUnlock};
+
+/// Blocks a coinbase reward must wait before it can be spent
+pub const COINBASE_MATURITY: u64 = 100;
+
+/// Whether a coinbase created at `coinbase_height` can be spent in a block at `spend_height`.
+/// Genesis allocations are spendable immediately.
+pub fn is_coinbase_mature(coinbase_height: u64, spend_height: u64) -> bool {
+    coinbase_height == 0 || spend_height.saturating_sub(coinbase_height) >= COINBASE_MATURITY
+}
 
 #[derive(Debug, Clone)]
 pub struct UTXO {
@@ -25,7 +38,7 @@ impl State {
 	}
 
 	pub fn get_balance(&self, owner: &str) -> u64 {
-		self.utxos.values().filter(|u| u.owner == owner).map(|u| u.amount).sum()
+		self.utxos.values().filter(|u| u.owner == owner).fold(0u64, |total, u| total.saturating_add(u.amount))
 	}
 }
 
@@ -33,13 +46,94 @@ impl State {
 #[derive(Debug, Default, Clone)]
 pub struct UTXOState {
     balances: HashMap<String, u64>,
+    /// Coinbase rewards that have not reached maturity yet
+    immature_balances: HashMap<String, u64>,
+    /// Height of the next block, the one transactions validated against this state would go into
+    next_height: u64,
+    /// Latest confirmed unlock declaration of each vault
+    vault_unlocks: HashMap<String, VaultUnlock>,
 }
 
 impl UTXOState {
     pub fn new() -> Self {
         UTXOState {
             balances: HashMap::new(),
+            immature_balances: HashMap::new(),
+            next_height: 0,
+            vault_unlocks: HashMap::new(),
+        }
+    }
+
+    /// Build the state by replaying every transaction in the given blocks.
+    /// Coinbase rewards that are not yet spendable in the next block are kept
+    /// as immature balance.
+    pub fn from_blocks(blocks: &[Block]) -> Result<Self, String> {
+        let mut state = UTXOState::new();
+        state.next_height = blocks.last().map(|b| b.header.height + 1).unwrap_or(0);
+        
+        for block in blocks {
+            state.apply_block(block)?;
+        }
+        
+        Ok(state)
+    }
+
+    /// Replay one block's transactions. Set the next height first, since it decides
+    /// which coinbase rewards are still immature.
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), String> {
+        for tx in &block.transactions {
+            if is_coinbase_transaction(tx) && !is_coinbase_mature(block.header.height, self.next_height) {
+                let immature = self.immature_balances.entry(tx.to.clone()).or_insert(0);
+                *immature = checked_money_add(*immature, tx.amount)
+                    .map_err(|e| format!("immature balance of {}: {}", tx.to, e))?;
+                continue;
+            }
+            self.apply_transaction(tx, block.header.height)?;
         }
+        Ok(())
+    }
+
+    /// Apply one transaction confirmed at `height`: move its funds and track
+    /// vault unlocks (see `apply_vault_transaction`).
+    /// Fails if the sender cannot cover the amount or the receiver's balance
+    /// would exceed `MAX_MONEY`.
+    pub fn apply_transaction(&mut self, tx: &Transaction, height: u64) -> Result<(), String> {
+        if self.apply_vault_transaction(tx, height) {
+            return Ok(());
+        }
+        
+        // Subtract from sender (if not genesis or a coinbase minting new coins)
+        if !tx.from.is_empty() && tx.from != "genesis" && !is_coinbase_transaction(tx) {
+            self.debit(&tx.from, tx.amount)?;
+        }
+        
+        // Add to receiver
+        self.credit(&tx.to, tx.amount)
+    }
+
+    /// Track the vault unlocks of a transaction confirmed at `height`, leaving
+    /// balances alone. A new unlock declaration replaces the vault's previous one;
+    /// declaring zero or clawing funds back cancels it, and other spends use it up.
+    /// Returns whether the transaction was a declaration, which moves no funds.
+    pub fn apply_vault_transaction(&mut self, tx: &Transaction, height: u64) -> bool {
+        if is_unvault_transaction(tx) {
+            if tx.amount == 0 {
+                self.vault_unlocks.remove(&tx.from);
+                return true;
+            }
+            let (delay, _) = parse_vault_address(&tx.from).expect("unvault transactions come from vaults");
+            self.vault_unlocks.insert(tx.from.clone(), VaultUnlock { amount: tx.amount, declared_height: height, delay });
+            return true;
+        }
+        if is_clawback_transaction(tx) {
+            self.vault_unlocks.remove(&tx.from);
+        } else if let Some(unlock) = self.vault_unlocks.get_mut(&tx.from) {
+            unlock.amount = unlock.amount.saturating_sub(tx.amount);
+            if unlock.amount == 0 {
+                self.vault_unlocks.remove(&tx.from);
+            }
+        }
+        false
     }
 
     /// Get balance for an address
@@ -47,16 +141,76 @@ impl UTXOState {
         self.balances.get(address).copied().unwrap_or(0)
     }
 
-    /// Update balance by a delta (can be negative)
-    pub fn update_balance(&mut self, address: &str, delta: i64) {
-        let current_balance = self.get_balance(address) as i64;
-        let new_balance = (current_balance + delta).max(0) as u64;
+    /// Get coinbase rewards for an address that are not spendable yet
+    pub fn get_immature_balance(&self, address: &str) -> u64 {
+        self.immature_balances.get(address).copied().unwrap_or(0)
+    }
+
+    /// Check that a transaction is not paying with immature coinbase rewards
+    pub fn check_coinbase_maturity(&self, tx: &Transaction) -> Result<(), String> {
+        let spendable = self.get_balance(&tx.from);
+        let immature = self.get_immature_balance(&tx.from);
         
-        if new_balance == 0 {
-            self.balances.remove(address);
-        } else {
-            self.balances.insert(address.to_string(), new_balance);
+        if tx.amount > spendable && immature > 0 {
+            return Err(format!(
+                "{} spends {} but only {} is mature ({} immature coinbase, maturity {} blocks)",
+                tx.from, tx.amount, spendable, immature, COINBASE_MATURITY
+            ));
+        }
+        
+        Ok(())
+    }
+
+    /// Unlock declared for a vault that has not been spent in full yet
+    pub fn get_vault_unlock(&self, vault: &str) -> Option<&VaultUnlock> {
+        self.vault_unlocks.get(vault)
+    }
+
+    /// Check that a spend from a vault at `height` stays within funds whose unlock
+    /// delay has passed. Unlock declarations and clawbacks are always allowed.
+    pub fn check_vault_spend(&self, tx: &Transaction, height: u64) -> Result<(), String> {
+        if parse_vault_address(&tx.from).is_none() || is_unvault_transaction(tx) || is_clawback_transaction(tx) {
+            return Ok(());
+        }
+        let unlock = self.get_vault_unlock(&tx.from)
+            .ok_or_else(|| format!("vault {} has no unlock declared", tx.from))?;
+        if height < unlock.spendable_height() {
+            return Err(format!(
+                "vault {} unlocks at height {} (block height {})", tx.from, unlock.spendable_height(), height
+            ));
+        }
+        if tx.amount > unlock.amount {
+            return Err(format!("vault {} spends {} but only {} is unlocked", tx.from, tx.amount, unlock.amount));
         }
+        Ok(())
+    }
+
+    /// Height of the block that would extend the replayed chain
+    pub fn next_height(&self) -> u64 {
+        self.next_height
+    }
+
+    /// Set the height transactions are validated against
+    pub fn set_next_height(&mut self, height: u64) {
+        self.next_height = height;
+    }
+
+    /// Add to a balance, refusing results above `MAX_MONEY`
+    pub fn credit(&mut self, address: &str, amount: u64) -> Result<(), String> {
+        let balance = checked_money_add(self.get_balance(address), amount)
+            .map_err(|e| format!("balance of {}: {}", address, e))?;
+        self.set_balance(address, balance);
+        Ok(())
+    }
+
+    /// Take from a balance, refusing to take more than it holds
+    pub fn debit(&mut self, address: &str, amount: u64) -> Result<(), String> {
+        let balance = self.get_balance(address);
+        if amount > balance {
+            return Err(format!("balance of {} is {}, cannot take {}", address, balance, amount));
+        }
+        self.set_balance(address, balance - amount);
+        Ok(())
     }
 
     /// Set balance directly
@@ -73,8 +227,15 @@ impl UTXOState {
         &self.balances
     }
 
+    /// Get all addresses with immature coinbase rewards
+    pub fn get_all_immature_balances(&self) -> &HashMap<String, u64> {
+        &self.immature_balances
+    }
+
     /// Clear all balances
     pub fn clear(&mut self) {
         self.balances.clear();
+        self.immature_balances.clear();
+        self.vault_unlocks.clear();
     }
 }
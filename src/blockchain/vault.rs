@@ -0,0 +1,154 @@
+//! Vault addresses for time-locked savings
+//!
+//! A vault address names its owner and a delay in blocks: `vault:<delay>:<owner>`,
+//! optionally followed by a recovery address: `vault:<delay>:<owner>:<recovery>`.
+//! Anyone can pay into it, but the owner cannot spend from it right away. First a
+//! transaction from the vault to itself declares how much is to be unlocked; once
+//! it has been confirmed for `delay` blocks, up to that amount may be spent. A
+//! thief holding the owner's key has to announce themselves the same way, and the
+//! delay gives the owner time to notice.
+//!
+//! Declaring zero cancels a pending unlock. A payment from the vault to its
+//! recovery address is a clawback: it is valid at any time and cancels any
+//! pending unlock, so the owner can move the funds out of a thief's reach before
+//! the delay runs out. Wallets sign clawbacks with the recovery key (see
+//! `vault_signer`), but validation does not check which key signed; a clawback
+//! is safe to allow because its funds can only go to the recovery address.
+
+use crate::blockchain::block::Transaction;
+
+/// Prefix of every vault address
+pub const VAULT_PREFIX: &str = "vault:";
+
+/// Delay used by `create-vault` when none is given, about a day of blocks
+pub const DEFAULT_VAULT_DELAY: u64 = 144;
+
+/// Longest delay a vault may have, about a year of blocks
+pub const MAX_VAULT_DELAY: u64 = 52_560;
+
+/// Address of the vault owned by `owner` whose funds unlock `delay` blocks after
+/// an unvault is confirmed, and can be clawed back to `recovery` at any time
+pub fn vault_address(owner: &str, delay: u64, recovery: Option<&str>) -> Result<String, String> {
+    if owner.is_empty() || owner.starts_with(VAULT_PREFIX) || owner.contains(':') {
+        return Err(format!("Invalid vault owner '{}'", owner));
+    }
+    if delay == 0 || delay > MAX_VAULT_DELAY {
+        return Err(format!("Vault delay must be between 1 and {} blocks", MAX_VAULT_DELAY));
+    }
+    match recovery {
+        Some(recovery) if recovery.is_empty() || recovery.contains(':') || recovery == owner => {
+            Err(format!("Invalid vault recovery address '{}'", recovery))
+        }
+        Some(recovery) => Ok(format!("{}{}:{}:{}", VAULT_PREFIX, delay, owner, recovery)),
+        None => Ok(format!("{}{}:{}", VAULT_PREFIX, delay, owner)),
+    }
+}
+
+/// Delay and owner of a vault address, or None for ordinary addresses
+pub fn parse_vault_address(address: &str) -> Option<(u64, &str)> {
+    let (delay, owner, _) = split_vault_address(address)?;
+    Some((delay, owner))
+}
+
+/// Recovery address of a vault, if it has one
+pub fn vault_recovery_address(address: &str) -> Option<&str> {
+    split_vault_address(address)?.2
+}
+
+fn split_vault_address(address: &str) -> Option<(u64, &str, Option<&str>)> {
+    let (delay, keys) = address.strip_prefix(VAULT_PREFIX)?.split_once(':')?;
+    let delay = delay.parse::<u64>().ok().filter(|delay| (1..=MAX_VAULT_DELAY).contains(delay))?;
+    let (owner, recovery) = match keys.split_once(':') {
+        Some((owner, recovery)) if recovery.is_empty() || recovery.contains(':') || recovery == owner => return None,
+        Some((owner, recovery)) => (owner, Some(recovery)),
+        None => (keys, None),
+    };
+    (!owner.is_empty()).then_some((delay, owner, recovery))
+}
+
+pub fn is_vault_address(address: &str) -> bool {
+    parse_vault_address(address).is_some()
+}
+
+/// Whether the transaction declares an unlock: a vault paying itself. It moves no
+/// funds, and declaring zero cancels the pending unlock.
+pub fn is_unvault_transaction(tx: &Transaction) -> bool {
+    tx.from == tx.to && is_vault_address(&tx.from)
+}
+
+/// Whether the transaction claws vault funds back: a vault paying its recovery
+/// address, which needs no unlock
+pub fn is_clawback_transaction(tx: &Transaction) -> bool {
+    vault_recovery_address(&tx.from) == Some(tx.to.as_str())
+}
+
+/// Key that signs a transaction from a vault: the recovery key for clawbacks and
+/// the owner's for everything else. None if the sender is not a vault.
+pub fn vault_signer(tx: &Transaction) -> Option<&str> {
+    let (_, owner, recovery) = split_vault_address(&tx.from)?;
+    Some(recovery.filter(|recovery| *recovery == tx.to).unwrap_or(owner))
+}
+
+/// Funds of a vault declared for unlocking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VaultUnlock {
+    /// Amount still spendable under this declaration
+    pub amount: u64,
+    /// Height of the block that confirmed the declaration
+    pub declared_height: u64,
+    pub delay: u64,
+}
+
+impl VaultUnlock {
+    /// First block height the unlocked funds may be spent at
+    pub fn spendable_height(&self) -> u64 {
+        self.declared_height.saturating_add(self.delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_address_round_trip() {
+        let address = vault_address("alice", 10, None).unwrap();
+        assert_eq!(address, "vault:10:alice");
+        assert_eq!(parse_vault_address(&address), Some((10, "alice")));
+        assert_eq!(vault_recovery_address(&address), None);
+
+        let address = vault_address("alice", 10, Some("carol")).unwrap();
+        assert_eq!(address, "vault:10:alice:carol");
+        assert_eq!(parse_vault_address(&address), Some((10, "alice")));
+        assert_eq!(vault_recovery_address(&address), Some("carol"));
+
+        assert!(vault_address("alice", 0, None).is_err());
+        assert!(vault_address("alice", MAX_VAULT_DELAY + 1, None).is_err());
+        assert!(vault_address(&address, 10, None).is_err());
+        assert!(vault_address("alice", 10, Some("alice")).is_err());
+        assert!(vault_address("alice", 10, Some("")).is_err());
+        for address in ["alice", "vault:", "vault:10:", "vault:x:alice", "vault:0:alice", "vault:10:alice:", "vault:10:alice:alice", "vault:10:a:b:c"] {
+            assert!(!is_vault_address(address), "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_clawback_pays_recovery_address() {
+        let tx = |from: &str, to: &str| Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let vault = vault_address("alice", 10, Some("carol")).unwrap();
+        assert!(is_clawback_transaction(&tx(&vault, "carol")));
+        assert!(!is_clawback_transaction(&tx(&vault, "alice")));
+        assert!(!is_clawback_transaction(&tx(&vault, &vault)));
+        assert!(!is_clawback_transaction(&tx("vault:10:alice", "carol")));
+        assert_eq!(vault_signer(&tx(&vault, "carol")), Some("carol"));
+        assert_eq!(vault_signer(&tx(&vault, "bob")), Some("alice"));
+        assert_eq!(vault_signer(&tx("alice", "carol")), None);
+    }
+}
@@ -1,6 +1,37 @@
-use crate::cli::CLI;
-use crate::wallet::keychain::WalletStats;
+use crate::cli::{CLI, MempoolCommands};
+use crate::wallet::history::{wallet_history, HistoryEntry, HISTORY_CSV_HEADER};
+use crate::wallet::keychain::{AddressState, UsageScan, WalletStats};
+use crate::wallet::registry::WalletRegistry;
+use crate::wallet::offline::UnsignedTransaction;
+use crate::wallet::invoice::{Invoice, InvoiceStatus};
+use crate::wallet::policy::{LimitBreach, SpendPolicy};
+use crate::wallet::consolidate::{plan_consolidation, ConsolidationPlan};
+use crate::blockchain::amount::display_amount;
+use crate::blockchain::analytics::{AnalyticsInterval, AnalyticsWriter};
 use crate::blockchain::block::Transaction;
+use crate::blockchain::merkle::MerkleProof;
+use crate::blockchain::multisig::parse_multisig_address;
+use crate::crypto::keys::encode_wif;
+use crate::blockchain::chain::{AddressSummary, BlockStats, ChainTxStats, ChainVerification, StorageReport, StorageStats, TransactionStatus, UnspentOutput};
+use crate::storage::schema::SchemaMigration;
+use crate::storage::backup::{self, BackupManifest};
+use crate::alerts::{AlertKind, AlertLog, ALERTS_FILE};
+use crate::mempool::MIN_FEE_BUMP;
+use crate::mempool::estimator::{FEE_ESTIMATES_FILE, MAX_CONFIRM_TARGET};
+use crate::network::bootstrap::BOOTSTRAP_FILE;
+use crate::network::identity::NODE_KEY_FILE;
+use crate::network::peer_stats::PEER_STATS_DIR;
+use crate::progress::Progress;
+use crate::wallet::registry::WALLETS_DIR;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use crate::consensus::fork_choice::is_final;
+use crate::consensus::params::EmissionEra;
+use crate::consensus::timestamps;
+use crate::blockchain::genesis::is_coinbase_transaction;
+use crate::blockchain::vault::{parse_vault_address, vault_address, vault_recovery_address, vault_signer};
 
 /// Transaction lookup and persistence commands
 pub trait TransactionCommands {
@@ -8,6 +39,9 @@ pub trait TransactionCommands {
     fn get_transaction_info(&self, tx_hash: &str) -> Result<Option<TransactionInfo>, String>;
     fn get_address_transactions(&self, address: &str) -> Result<Vec<AddressTransaction>, String>;
     fn get_address_balance(&self, address: &str) -> Result<AddressBalance, String>;
+    fn get_address_balance_at(&self, address: &str, height: u64) -> Result<AddressBalance, String>;
+    fn get_richest_addresses(&self, count: usize) -> Result<Vec<AddressBalance>, String>;
+    fn rebuild_address_index(&self) -> Result<usize, String>;
 }
 
 impl TransactionCommands for CLI {
@@ -16,10 +50,14 @@ impl TransactionCommands for CLI {
         self.chain.get_transaction(tx_hash)
     }
     
-    /// Get detailed transaction information including block context
+    /// Get detailed transaction information including block context and status
     fn get_transaction_info(&self, tx_hash: &str) -> Result<Option<TransactionInfo>, String> {
         if let Some(transaction) = self.chain.get_transaction(tx_hash)? {
             let index = self.chain.get_transaction_index(tx_hash)?;
+            let tip_height = self.chain.height();
+            let is_final = index.as_ref()
+                .map(|i| is_final(i.block_height, tip_height, self.finality_depth))
+                .unwrap_or(false);
             
             Ok(Some(TransactionInfo {
                 hash: tx_hash.to_string(),
@@ -28,9 +66,43 @@ impl TransactionCommands for CLI {
                 block_height: index.as_ref().map(|i| i.block_height),
                 transaction_index: index.as_ref().map(|i| i.transaction_index),
                 timestamp: index.as_ref().map(|i| i.timestamp),
+                is_final,
+                status: TransactionStatus::Confirmed,
+                previous_block_hash: None,
+            }))
+        } else if let Some(record) = self.chain.transaction_status(tx_hash)? {
+            // A transaction returned to the mempool that has since left it without
+            // being confirmed again is abandoned
+            let status = match record.status {
+                TransactionStatus::Mempool if !self.mempool.contains_transaction(&record.transaction) => TransactionStatus::Abandoned,
+                status => status,
+            };
+            Ok(Some(TransactionInfo {
+                hash: tx_hash.to_string(),
+                transaction: record.transaction,
+                block_hash: None,
+                block_height: None,
+                transaction_index: None,
+                timestamp: None,
+                is_final: false,
+                status,
+                previous_block_hash: Some(record.block_hash),
             }))
         } else {
-            Ok(None)
+            let pending = self.mempool.get_pending_transactions()
+                .into_iter()
+                .find(|tx| tx.hash() == tx_hash);
+            Ok(pending.map(|transaction| TransactionInfo {
+                hash: tx_hash.to_string(),
+                transaction,
+                block_hash: None,
+                block_height: None,
+                transaction_index: None,
+                timestamp: None,
+                is_final: false,
+                status: TransactionStatus::Mempool,
+                previous_block_hash: None,
+            }))
         }
     }
     
@@ -64,35 +136,27 @@ impl TransactionCommands for CLI {
         Ok(result)
     }
     
-    /// Get address balance and transaction summary
+    /// Get address balance and transaction summary from the address balance index
     fn get_address_balance(&self, address: &str) -> Result<AddressBalance, String> {
-        let transactions = self.get_address_transactions(address)?;
-        
-        let mut balance: i64 = 0;
-        let mut sent = 0u64;
-        let mut received = 0u64;
-        let tx_count = transactions.len();
-        
-        for tx in &transactions {
-            if tx.is_sender && !tx.is_recipient {
-                // Only sent
-                sent += tx.amount;
-                balance -= tx.amount as i64;
-            } else if tx.is_recipient && !tx.is_sender {
-                // Only received
-                received += tx.amount;
-                balance += tx.amount as i64;
-            }
-            // If both sender and recipient (self-transfer), balance doesn't change
-        }
-        
-        Ok(AddressBalance {
-            address: address.to_string(),
-            balance: balance.max(0) as u64,
-            total_sent: sent,
-            total_received: received,
-            transaction_count: tx_count,
-        })
+        let summary = self.chain.get_address_summary(address)?;
+        Ok(AddressBalance::from_summary(address, &summary))
+    }
+    
+    /// Address balance and transaction summary as of the block at `height`
+    fn get_address_balance_at(&self, address: &str, height: u64) -> Result<AddressBalance, String> {
+        let summary = self.chain.get_address_summary_at(address, height)?;
+        Ok(AddressBalance::from_summary(address, &summary))
+    }
+    
+    /// Addresses with the highest balances, richest first
+    fn get_richest_addresses(&self, count: usize) -> Result<Vec<AddressBalance>, String> {
+        let richest = self.chain.richest_addresses(count)?;
+        Ok(richest.iter().map(|(address, summary)| AddressBalance::from_summary(address, summary)).collect())
+    }
+    
+    /// Rebuild the address index from stored blocks
+    fn rebuild_address_index(&self) -> Result<usize, String> {
+        self.chain.rebuild_address_index()
     }
 }
 
@@ -105,6 +169,11 @@ pub struct TransactionInfo {
     pub block_height: Option<u64>,
     pub transaction_index: Option<usize>,
     pub timestamp: Option<u64>,
+    /// Whether the containing block is deeper than the finality depth
+    pub is_final: bool,
+    pub status: TransactionStatus,
+    /// Block the transaction was confirmed in before it was disconnected
+    pub previous_block_hash: Option<String>,
 }
 
 /// Address transaction with context
@@ -128,18 +197,120 @@ pub struct AddressBalance {
     pub balance: u64,
     pub total_sent: u64,
     pub total_received: u64,
-    pub transaction_count: usize,
+    pub transaction_count: u64,
+}
+
+impl AddressBalance {
+    fn from_summary(address: &str, summary: &AddressSummary) -> Self {
+        AddressBalance {
+            address: address.to_string(),
+            balance: summary.balance(),
+            total_sent: summary.total_sent,
+            total_received: summary.total_received,
+            transaction_count: summary.transaction_count,
+        }
+    }
+}
+
+/// Wallet funds split by coinbase maturity
+#[derive(Debug)]
+pub struct WalletBalance {
+    pub spendable: u64,
+    pub immature: u64,
+    pub addresses: usize,
+}
+
+/// A pending wallet transaction replaced at a higher fee rate; `transaction` is
+/// the replacement
+#[derive(Debug)]
+pub struct FeeBump {
+    pub transaction: Transaction,
+    pub previous_fee_per_byte: f64,
+    pub fee_per_byte: f64,
+}
+
+/// Sweeps added to the mempool by `consolidate-utxos`
+#[derive(Debug)]
+pub struct Consolidation {
+    pub plan: ConsolidationPlan,
+    pub fee_per_byte: f64,
+    /// One per sweep, in the plan's order
+    pub txids: Vec<String>,
+}
+
+/// Funds and addresses of one wallet account
+#[derive(Debug)]
+pub struct AccountSummary {
+    pub name: String,
+    pub index: u32,
+    pub addresses: Vec<String>,
+    pub spendable: u64,
+    pub immature: u64,
+}
+
+/// An invoice with the payments found for it on chain
+#[derive(Debug)]
+pub struct InvoiceSummary {
+    pub invoice: Invoice,
+    /// Confirmed amount received at the invoice address before it expired
+    pub received: u64,
+    /// Confirmed amount received after it expired, which does not pay it
+    pub late: u64,
+    pub status: InvoiceStatus,
+}
+
+/// A payment checked against the local header chain with its merkle proof
+#[derive(Debug)]
+pub struct PaymentVerification {
+    pub transaction: Transaction,
+    pub block_hash: String,
+    pub block_height: u64,
+    pub confirmations: u64,
+    /// Whether the payment goes to an address of this wallet
+    pub to_wallet: bool,
 }
 
 /// Wallet management commands for Phase 8
 pub trait WalletCommands {
     fn generate_new_address(&mut self) -> Result<String, String>;
+    fn generate_account_address(&mut self, account: &str) -> Result<String, String>;
+    fn create_account(&mut self, name: &str) -> Result<u32, String>;
+    fn list_accounts(&self) -> Vec<AccountSummary>;
+    fn select_funding_address(&self, account: &str, amount: u64) -> Result<String, String>;
+    fn sign_unsigned_transaction(&self, unsigned: &mut UnsignedTransaction, key: Option<&str>) -> Result<String, String>;
+    fn create_vault<F: FnOnce(&str) -> bool>(&mut self, owner: &str, amount: u64, delay: u64, recovery: Option<&str>, override_limits: bool, confirm: F) -> Result<String, String>;
+    fn unvault(&mut self, vault: &str, amount: u64) -> Result<u64, String>;
+    fn cancel_unvault(&mut self, vault: &str) -> Result<(), String>;
+    fn clawback(&mut self, vault: &str, amount: Option<u64>) -> Result<u64, String>;
+    fn set_spend_policy(&mut self, policy: SpendPolicy) -> Result<(), String>;
+    fn spent_today(&self) -> u64;
+    fn authorize_spend<F: FnOnce(&str) -> bool>(&self, amount: u64, override_limits: bool, confirm: F) -> Result<Vec<LimitBreach>, String>;
+    fn record_spend(&mut self, tx: &Transaction, overridden: &[LimitBreach]) -> Result<(), String>;
+    fn send_transaction<F: FnOnce(&str) -> bool>(&mut self, transaction: Transaction, fee_per_byte: Option<f64>, override_limits: bool, confirm: F) -> Result<(), String>;
+    fn bump_fee(&mut self, txid: &str, fee_per_byte: Option<f64>) -> Result<FeeBump, String>;
+    fn create_invoice(&mut self, amount: u64, label: Option<&str>, expiry: u64) -> Result<Invoice, String>;
+    fn list_invoices(&self) -> Result<Vec<InvoiceSummary>, String>;
+    fn verify_payment(&self, txid: &str, proof: &str) -> Result<PaymentVerification, String>;
     fn list_addresses(&self) -> Vec<String>;
+    fn list_address_states(&self) -> Vec<(String, AddressState)>;
+    fn refresh_address_usage(&mut self) -> Result<usize, String>;
+    fn get_receive_address(&mut self, change: bool) -> Result<String, String>;
     fn show_seed_phrase(&self) -> String;
+    fn reveal_seed_phrase(&self) -> Result<String, String>;
+    fn export_seed_qr(&self, path: &str) -> Result<(), String>;
     fn restore_from_seed(&mut self, seed_phrase: &str) -> Result<(), String>;
     fn get_wallet_stats(&self) -> WalletStats;
+    fn get_wallet_balance(&self) -> WalletBalance;
+    fn get_wallet_history(&self) -> Result<Vec<HistoryEntry>, String>;
+    fn export_wallet_history(&self, path: &str) -> Result<usize, String>;
+    fn list_unspent(&self, minconf: u64) -> Result<Vec<UnspentOutput>, String>;
+    fn consolidate_utxos<F: FnOnce(&str) -> bool>(&mut self, max_fee_rate: f64, override_limits: bool, confirm: F) -> Result<Consolidation, String>;
     fn backup_wallet(&self, path: &str) -> Result<(), String>;
     fn import_private_key(&mut self, private_key: &str) -> Result<String, String>;
+    fn dump_private_key(&self, address: &str) -> Result<String, String>;
+    fn create_wallet(&self, name: &str) -> Result<String, String>;
+    fn load_wallet(&mut self, name: &str) -> Result<(), String>;
+    fn list_wallets(&self) -> Result<Vec<String>, String>;
 }
 
 impl WalletCommands for CLI {
@@ -148,24 +319,439 @@ impl WalletCommands for CLI {
         let address = self.wallet.generate_address()?;
         
         // Save wallet after modification
-        let wallet_path = "wallet.json";
-        if let Err(e) = self.wallet.save_to_file(wallet_path) {
+        if let Err(e) = self.save_wallet() {
+            eprintln!("Warning: Failed to save wallet: {}", e);
+        }
+        
+        Ok(address)
+    }
+
+    /// Generate a new address in a named account
+    fn generate_account_address(&mut self, account: &str) -> Result<String, String> {
+        let address = self.wallet.generate_account_address(account)?;
+        
+        if let Err(e) = self.save_wallet() {
             eprintln!("Warning: Failed to save wallet: {}", e);
         }
         
         Ok(address)
     }
 
+    /// Create a named account, returning its account index
+    fn create_account(&mut self, name: &str) -> Result<u32, String> {
+        let index = self.wallet.create_account(name)?;
+        
+        if let Err(e) = self.save_wallet() {
+            eprintln!("Warning: Failed to save wallet: {}", e);
+        }
+        
+        Ok(index)
+    }
+
+    /// List accounts with their addresses and balances
+    fn list_accounts(&self) -> Vec<AccountSummary> {
+        let state = self.get_current_utxo_state();
+        
+        self.wallet.list_accounts().into_iter().map(|account| {
+            let addresses = account.addresses();
+            AccountSummary {
+                spendable: addresses.iter().map(|addr| state.get_balance(addr)).sum(),
+                immature: addresses.iter().map(|addr| state.get_immature_balance(addr)).sum(),
+                name: account.name,
+                index: account.index,
+                addresses,
+            }
+        }).collect()
+    }
+
+    /// Pick the first address of an account that can pay `amount` on its own
+    fn select_funding_address(&self, account: &str, amount: u64) -> Result<String, String> {
+        let state = self.get_current_utxo_state();
+        let addresses = self.wallet.get_account_addresses(account)?;
+        
+        addresses.iter()
+            .find(|addr| state.get_balance(addr) >= amount)
+            .cloned()
+            .ok_or_else(|| {
+                let total: u64 = addresses.iter().map(|addr| state.get_balance(addr)).sum();
+                format!("No address in account '{}' can pay {} (account total {})", account, amount, total)
+            })
+    }
+
+    /// Add this wallet's signature to an offline transaction, using the sender's
+    /// key (the owner's for vaults or the recovery key for clawbacks, the first
+    /// cosigner held here that has not signed yet for multisig) unless a cosigner
+    /// address is given
+    fn sign_unsigned_transaction(&self, unsigned: &mut UnsignedTransaction, key: Option<&str>) -> Result<String, String> {
+        let addresses = self.wallet.get_all_addresses();
+        let sender = &unsigned.transaction.from;
+        let sender = match parse_multisig_address(sender) {
+            Some((_, cosigners)) => cosigners.into_iter()
+                .find(|cosigner| addresses.iter().any(|address| address == cosigner) && !unsigned.signatures.contains_key(*cosigner))
+                .unwrap_or(sender.as_str()),
+            None => vault_signer(&unsigned.transaction).unwrap_or(sender.as_str()),
+        };
+        let address = key.unwrap_or(sender).to_string();
+        
+        if !addresses.contains(&address) {
+            return Err(match key {
+                Some(_) => format!("Address {} is not in this wallet", address),
+                None => format!("Wallet does not hold the sender address {}; pass --key=<address> to cosign", address),
+            });
+        }
+        
+        unsigned.sign(&self.wallet, &address)
+    }
+
+    /// Move `amount` from a wallet address into the vault it owns with the given
+    /// delay and optional recovery address, returning the vault address. It is a
+    /// send like any other, held to the spend limits.
+    fn create_vault<F: FnOnce(&str) -> bool>(&mut self, owner: &str, amount: u64, delay: u64, recovery: Option<&str>, override_limits: bool, confirm: F) -> Result<String, String> {
+        if !self.wallet.get_all_addresses().iter().any(|address| address == owner) {
+            return Err(format!("Address {} is not in this wallet", owner));
+        }
+        let vault = vault_address(owner, delay, recovery)?;
+        
+        self.send_transaction(Transaction {
+            from: owner.to_string(),
+            to: vault.clone(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        }, None, override_limits, confirm)?;
+        Ok(vault)
+    }
+
+    /// Declare `amount` of a vault for unlocking, returning the earliest height
+    /// it can be spent at if the declaration confirms in the next block
+    fn unvault(&mut self, vault: &str, amount: u64) -> Result<u64, String> {
+        let (delay, owner) = parse_vault_address(vault)
+            .ok_or_else(|| format!("{} is not a vault address", vault))?;
+        if !self.wallet.get_all_addresses().iter().any(|address| address == owner) {
+            return Err(format!("Wallet does not hold the vault owner {}", owner));
+        }
+        let next_height = self.get_current_utxo_state().next_height();
+        
+        self.add_transaction_to_mempool(Transaction {
+            from: vault.to_string(),
+            to: vault.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        })?;
+        Ok(next_height + delay)
+    }
+
+    /// Cancel a vault's pending unlock by declaring zero
+    fn cancel_unvault(&mut self, vault: &str) -> Result<(), String> {
+        let (_, owner) = parse_vault_address(vault)
+            .ok_or_else(|| format!("{} is not a vault address", vault))?;
+        if !self.wallet.get_all_addresses().iter().any(|address| address == owner) {
+            return Err(format!("Wallet does not hold the vault owner {}", owner));
+        }
+        if self.get_current_utxo_state().get_vault_unlock(vault).is_none() {
+            return Err(format!("Vault {} has no pending unlock", vault));
+        }
+        
+        self.add_transaction_to_mempool(Transaction {
+            from: vault.to_string(),
+            to: vault.to_string(),
+            amount: 0,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        })
+    }
+
+    /// Move `amount` (the whole balance if None) of a vault to its recovery
+    /// address right away, cancelling any pending unlock. Returns the amount.
+    fn clawback(&mut self, vault: &str, amount: Option<u64>) -> Result<u64, String> {
+        let recovery = vault_recovery_address(vault)
+            .ok_or_else(|| format!("{} is not a vault with a recovery address", vault))?;
+        if !self.wallet.get_all_addresses().iter().any(|address| address == recovery) {
+            return Err(format!("Wallet does not hold the vault recovery address {}", recovery));
+        }
+        let amount = amount.unwrap_or_else(|| self.get_current_utxo_state().get_balance(vault));
+        
+        self.add_transaction_to_mempool(Transaction {
+            from: vault.to_string(),
+            to: recovery.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        })?;
+        Ok(amount)
+    }
+
+    /// Replace the selected wallet's spend limits
+    fn set_spend_policy(&mut self, policy: SpendPolicy) -> Result<(), String> {
+        self.wallet.set_spend_policy(policy);
+        self.save_wallet()
+    }
+
+    /// Amount the wallet sent in the last 24 hours
+    fn spent_today(&self) -> u64 {
+        self.wallet.spent_since(now_secs())
+    }
+
+    /// Check sending `amount` against the wallet's spend policy. A broken cap
+    /// refuses the send unless `override_limits` is set; overrides and amounts
+    /// above the confirmation threshold go ahead only if `confirm` accepts the
+    /// prompt. Returns the caps being overridden.
+    fn authorize_spend<F: FnOnce(&str) -> bool>(&self, amount: u64, override_limits: bool, confirm: F) -> Result<Vec<LimitBreach>, String> {
+        let check = self.wallet.spend_policy().check(amount, self.spent_today());
+        let breaches = describe_breaches(&check.breaches);
+        if !check.breaches.is_empty() && !override_limits {
+            return Err(format!("Spend limit exceeded: {}; pass --override-limits to send anyway", breaches));
+        }
+        if check.needs_confirmation {
+            let prompt = if check.breaches.is_empty() {
+                format!("Send {}, above the confirmation threshold?", display_amount(amount))
+            } else {
+                format!("Override spend limits ({}) and send {}?", breaches, display_amount(amount))
+            };
+            if !confirm(&prompt) {
+                return Err("Send cancelled".to_string());
+            }
+        }
+        Ok(check.breaches)
+    }
+
+    /// Send a payment: check it against the spend limits as `authorize_spend`
+    /// does, add it to the mempool at `fee_per_byte` (the default target's
+    /// estimate when None) and count it towards the daily limit. Every send the
+    /// wallet makes goes through here.
+    fn send_transaction<F: FnOnce(&str) -> bool>(&mut self, transaction: Transaction, fee_per_byte: Option<f64>, override_limits: bool, confirm: F) -> Result<(), String> {
+        let overridden = self.authorize_spend(transaction.amount, override_limits, confirm)?;
+        match fee_per_byte {
+            Some(fee_per_byte) => self.add_transaction_to_mempool_with_fee(transaction.clone(), fee_per_byte)?,
+            None => self.add_transaction_to_mempool(transaction.clone())?,
+        }
+        if let Err(e) = self.record_spend(&transaction, &overridden) {
+            eprintln!("Warning: Failed to record spend: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Count a sent transaction towards the daily limit, recording in the
+    /// alert log any caps it overrode
+    fn record_spend(&mut self, tx: &Transaction, overridden: &[LimitBreach]) -> Result<(), String> {
+        let txid = tx.hash();
+        self.wallet.record_spend(&txid, tx.amount, now_secs());
+        self.save_wallet()?;
+        
+        if !overridden.is_empty() {
+            let wallet = self.wallet_name.as_deref().unwrap_or(&self.wallet_path);
+            AlertLog::open(&self.data_dir)?.raise(AlertKind::SpendLimitOverride, format!(
+                "Wallet '{}' sent {} from {} to {} in {} overriding: {}",
+                wallet, tx.amount, tx.from, tx.to, txid, describe_breaches(overridden)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Pay a higher fee on an unconfirmed transaction sent from one of the wallet's
+    /// addresses. Without a rate it pays the estimate for the next block, or the
+    /// smallest bump allowed if that is higher. The payment is rebuilt valid from
+    /// the next block and signed again, so the replacement carries a new txid.
+    fn bump_fee(&mut self, txid: &str, fee_per_byte: Option<f64>) -> Result<FeeBump, String> {
+        let transaction = self.mempool.get_pending_transactions()
+            .into_iter()
+            .find(|tx| tx.hash() == txid)
+            .ok_or_else(|| match self.chain.get_transaction_index(txid) {
+                Ok(Some(index)) => format!("Transaction {} is already confirmed in block {}", txid, index.block_height),
+                _ => format!("Transaction {} is not in the mempool", txid),
+            })?;
+        if !self.wallet.get_all_addresses().contains(&transaction.from) {
+            return Err(format!("Transaction {} was not sent from this wallet", txid));
+        }
+        
+        let current = self.mempool.get_entry(txid).map_or(0.0, |entry| entry.fee_per_byte);
+        let fee_per_byte = fee_per_byte.unwrap_or_else(|| self.select_fee_rate(1).max(current + MIN_FEE_BUMP));
+        
+        // The fee is not signed, so the payment itself must change for the txid to
+        // change; a lock at the next height does not delay it
+        let utxo_state = self.get_current_utxo_state();
+        let next_height = utxo_state.next_height();
+        let mut unsigned = UnsignedTransaction::new(Transaction {
+            lock_height: Some(transaction.lock_height.map_or(next_height, |height| height.max(next_height))),
+            ..transaction.clone()
+        });
+        unsigned.sign(&self.wallet, &transaction.from)?;
+        let replacement = unsigned.finalize()?;
+        
+        let previous_fee_per_byte = self.mempool.bump_fee(txid, replacement.clone(), fee_per_byte, &utxo_state)?;
+        if let Err(e) = self.mempool.save_to_file("./mempool.json") {
+            eprintln!("Warning: Failed to save mempool: {}", e);
+        }
+        
+        self.wallet.record_replacement(txid, &replacement.hash(), now_secs());
+        if let Err(e) = self.save_wallet() {
+            eprintln!("Warning: Failed to save wallet: {}", e);
+        }
+        Ok(FeeBump { transaction: replacement, previous_fee_per_byte, fee_per_byte })
+    }
+
+    /// Issue an invoice for `amount` on a fresh address, open for `expiry` seconds
+    fn create_invoice(&mut self, amount: u64, label: Option<&str>, expiry: u64) -> Result<Invoice, String> {
+        let now = self.now();
+        let invoice = self.wallet.create_invoice(amount, label, now, expiry)?;
+        
+        if let Err(e) = self.save_wallet() {
+            eprintln!("Warning: Failed to save wallet: {}", e);
+        }
+        
+        Ok(invoice)
+    }
+
+    /// List invoices with their payment status, found through the address index.
+    /// Only payments in blocks timestamped before the invoice expired pay it.
+    fn list_invoices(&self) -> Result<Vec<InvoiceSummary>, String> {
+        let now = self.now();
+        
+        self.wallet.invoices().iter().map(|invoice| {
+            let (mut received, mut late) = (0u64, 0u64);
+            for tx in self.get_address_transactions(&invoice.address)? {
+                if !tx.is_recipient || tx.is_sender {
+                    continue;
+                }
+                if tx.timestamp.is_some_and(|timestamp| timestamp <= invoice.expires_at) {
+                    received = received.saturating_add(tx.amount);
+                } else {
+                    late = late.saturating_add(tx.amount);
+                }
+            }
+            Ok(InvoiceSummary {
+                invoice: invoice.clone(),
+                received,
+                late,
+                status: invoice.status(received, now),
+            })
+        }).collect()
+    }
+
+    /// Check a proof from `GET /tx/<txid>/proof` that `txid` is confirmed, using
+    /// only the headers known locally
+    fn verify_payment(&self, txid: &str, proof: &str) -> Result<PaymentVerification, String> {
+        let proof = MerkleProof::from_hex(proof)?;
+        if proof.txid != txid {
+            return Err(format!("Proof is for transaction {}, not {}", proof.txid, txid));
+        }
+        let confirmations = self.chain.verify_transaction_proof(&proof)?;
+
+        Ok(PaymentVerification {
+            to_wallet: self.wallet.is_mine(&proof.transaction.to),
+            transaction: proof.transaction,
+            block_hash: proof.block_hash,
+            block_height: proof.block_height,
+            confirmations,
+        })
+    }
+
     /// List all addresses in the wallet
     fn list_addresses(&self) -> Vec<String> {
         self.wallet.get_all_addresses()
     }
 
+    /// List all addresses in the wallet with their usage state
+    fn list_address_states(&self) -> Vec<(String, AddressState)> {
+        self.wallet.get_all_addresses().into_iter()
+            .map(|address| {
+                let state = self.wallet.address_state(&address);
+                (address, state)
+            })
+            .collect()
+    }
+
+    /// Mark wallet addresses that appear in a confirmed transaction as used,
+    /// returning how many were newly marked. Only blocks above the wallet's last
+    /// scan are read, unless a reorg replaced the block that scan stopped at.
+    fn refresh_address_usage(&mut self) -> Result<usize, String> {
+        let from = match self.wallet.usage_scan() {
+            Some(scan) if self.chain.block_hash_at(scan.height)?.as_deref() == Some(scan.block_hash.as_str()) => scan.height + 1,
+            _ => 0,
+        };
+        let tip = self.chain.height();
+        let wallet_addresses: HashSet<String> = self.wallet.get_all_addresses().into_iter().collect();
+        let mut seen = HashSet::new();
+        if from <= tip {
+            self.chain.scan_blocks(from, tip, |block| {
+                for tx in &block.transactions {
+                    for address in [&tx.from, &tx.to] {
+                        if wallet_addresses.contains(address) {
+                            seen.insert(address.clone());
+                        }
+                    }
+                }
+            })?;
+        }
+
+        let marked = seen.iter().filter(|address| self.wallet.mark_address_used(address)).count();
+        let mut changed = marked > 0;
+        if let Some(block) = self.chain.tip() {
+            let scan = UsageScan { height: tip, block_hash: block.header.hash.clone() };
+            changed |= self.wallet.usage_scan() != Some(&scan);
+            self.wallet.set_usage_scan(scan);
+        }
+        if changed && let Err(e) = self.save_wallet() {
+            eprintln!("Warning: Failed to save wallet: {}", e);
+        }
+        Ok(marked)
+    }
+
+    /// Address to receive the next payment at, or a new change address. The
+    /// receive address rotates once a payment to it is confirmed.
+    fn get_receive_address(&mut self, change: bool) -> Result<String, String> {
+        self.refresh_address_usage()?;
+        let address = if change {
+            self.wallet.change_address()?
+        } else {
+            self.wallet.receive_address()?
+        };
+
+        if let Err(e) = self.save_wallet() {
+            eprintln!("Warning: Failed to save wallet: {}", e);
+        }
+        Ok(address)
+    }
+
     /// Show the wallet's seed phrase for backup
     fn show_seed_phrase(&self) -> String {
         self.wallet.get_seed_phrase().to_string()
     }
 
+    /// Seed phrase for display to the user, recorded in the alert log
+    fn reveal_seed_phrase(&self) -> Result<String, String> {
+        self.record_seed_access("displayed")?;
+        Ok(self.wallet.get_seed_phrase().to_string())
+    }
+
+    /// Write the seed phrase as a Standard SeedQR payload, recorded in the alert log
+    fn export_seed_qr(&self, path: &str) -> Result<(), String> {
+        use std::io::Write;
+        #[cfg(unix)]
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        
+        let payload = self.wallet.seed_qr_payload()?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // The payload is the seed itself, so only the wallet's user may read it
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path)
+            .map_err(|e| format!("Failed to write seed QR payload: {}", e))?;
+        // The mode only applies to new files; an existing one may have been readable
+        #[cfg(unix)]
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to restrict seed QR payload permissions: {}", e))?;
+        file.write_all(payload.as_bytes())
+            .map_err(|e| format!("Failed to write seed QR payload: {}", e))?;
+        self.record_seed_access(&format!("exported as a SeedQR payload to {}", path))
+    }
+
     /// Restore wallet from seed phrase
     fn restore_from_seed(&mut self, seed_phrase: &str) -> Result<(), String> {
         use crate::wallet::keychain::Wallet;
@@ -174,8 +760,7 @@ impl WalletCommands for CLI {
         self.wallet = new_wallet;
         
         // Save the restored wallet
-        let wallet_path = "wallet.json";
-        if let Err(e) = self.wallet.save_to_file(wallet_path) {
+        if let Err(e) = self.save_wallet() {
             eprintln!("Warning: Failed to save wallet: {}", e);
         }
         
@@ -187,6 +772,99 @@ impl WalletCommands for CLI {
         self.wallet.get_stats()
     }
 
+    /// Sum spendable and immature funds across all wallet addresses
+    fn get_wallet_balance(&self) -> WalletBalance {
+        let state = self.get_current_utxo_state();
+        let addresses = self.wallet.get_all_addresses();
+        
+        WalletBalance {
+            spendable: addresses.iter().map(|addr| state.get_balance(addr)).sum(),
+            immature: addresses.iter().map(|addr| state.get_immature_balance(addr)).sum(),
+            addresses: addresses.len(),
+        }
+    }
+
+    /// Confirmed transactions of the wallet's addresses, oldest first
+    fn get_wallet_history(&self) -> Result<Vec<HistoryEntry>, String> {
+        let addresses: HashSet<String> = self.wallet.get_all_addresses().into_iter().collect();
+        let mut history = wallet_history(&self.chain, &addresses)?;
+        for entry in &mut history {
+            entry.replaces = self.wallet.replaced_txid(&entry.txid).map(str::to_string);
+        }
+        Ok(history)
+    }
+
+    /// Write the wallet history to `path` as CSV, returning the number of entries
+    fn export_wallet_history(&self, path: &str) -> Result<usize, String> {
+        let history = self.get_wallet_history()?;
+        let mut csv = String::from(HISTORY_CSV_HEADER);
+        for entry in &history {
+            csv.push('\n');
+            csv.push_str(&entry.to_csv());
+        }
+        csv.push('\n');
+        std::fs::write(path, csv).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        Ok(history.len())
+    }
+
+    /// Spendable outputs of the wallet's addresses with at least `minconf` confirmations
+    fn list_unspent(&self, minconf: u64) -> Result<Vec<UnspentOutput>, String> {
+        let mut outputs = Vec::new();
+        for address in self.wallet.get_all_addresses() {
+            outputs.extend(self.chain.address_unspent_outputs(&address)?.into_iter()
+                .filter(|output| output.spendable && output.confirmations >= minconf));
+        }
+        Ok(outputs)
+    }
+
+    /// Sweep the wallet's other addresses into the one holding the most, at the
+    /// fee rate for the slowest confirmation target; refused while that rate is
+    /// above `max_fee_rate`. The swept total is held to the spend limits as
+    /// `authorize_spend` does, and each sweep is signed by its address.
+    fn consolidate_utxos<F: FnOnce(&str) -> bool>(&mut self, max_fee_rate: f64, override_limits: bool, confirm: F) -> Result<Consolidation, String> {
+        let fee_per_byte = self.select_fee_rate(MAX_CONFIRM_TARGET);
+        if fee_per_byte > max_fee_rate {
+            return Err(format!(
+                "Fee rate {:.4} per byte is above the maximum of {:.4}; try again when the mempool is less congested",
+                fee_per_byte, max_fee_rate
+            ));
+        }
+        
+        let pending: HashSet<String> = self.mempool.get_pending_transactions().into_iter()
+            .map(|tx| tx.from)
+            .collect();
+        let plan = plan_consolidation(&self.list_unspent(1)?, &pending, self.mempool.policy().dust_threshold);
+        let overridden = if plan.sweeps.is_empty() {
+            Vec::new()
+        } else {
+            self.authorize_spend(plan.amount(), override_limits, confirm)?
+        };
+        let utxo_state = self.get_current_utxo_state();
+        let mut txids = Vec::new();
+        for sweep in &plan.sweeps {
+            let mut unsigned = UnsignedTransaction::new(Transaction {
+                from: sweep.address.clone(),
+                to: plan.destination.clone(),
+                amount: sweep.amount,
+                signature: vec![],
+                lock_height: None,
+                expiry_height: None,
+            });
+            unsigned.sign(&self.wallet, &sweep.address)?;
+            let tx = unsigned.finalize()?;
+            let txid = tx.hash();
+            self.mempool.add_transaction_with_fee(tx.clone(), fee_per_byte, &utxo_state)
+                .map_err(|e| format!("Sweep of {} rejected after {} sent: {}", sweep.address, txids.len(), e))?;
+            self.record_spend(&tx, &overridden)?;
+            txids.push(txid);
+        }
+        
+        if !txids.is_empty() && let Err(e) = self.mempool.save_to_file("./mempool.json") {
+            eprintln!("Warning: Failed to save mempool: {}", e);
+        }
+        Ok(Consolidation { plan, fee_per_byte, txids })
+    }
+
     /// Backup wallet to file
     fn backup_wallet(&self, path: &str) -> Result<(), String> {
         use std::fs;
@@ -194,6 +872,13 @@ impl WalletCommands for CLI {
         let backup_data = serde_json::json!({
             "seed_phrase": self.wallet.get_seed_phrase(),
             "addresses": self.wallet.get_all_addresses(),
+            // Not derived from the seed, so kept in full
+            "imported_keys": self.wallet.imported_addresses().iter()
+                .map(|address| self.wallet.get_private_key(address).map(|key| serde_json::json!({
+                    "address": address,
+                    "key": encode_wif(&key),
+                })))
+                .collect::<Result<Vec<_>, String>>()?,
             "stats": self.wallet.get_stats(),
             "backup_time": std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -207,45 +892,106 @@ impl WalletCommands for CLI {
         Ok(())
     }
 
-    /// Import a private key (simplified implementation)
-    fn import_private_key(&mut self, _private_key: &str) -> Result<String, String> {
-        // For simplicity, just generate a new address
-        // In a real implementation, this would derive the address from the private key
-        self.generate_new_address()
+    /// Create a named wallet in the data directory, returning its first address
+    fn create_wallet(&self, name: &str) -> Result<String, String> {
+        let wallet = WalletRegistry::in_data_dir(&self.data_dir).create(name)?;
+        wallet.get_current_address().ok_or_else(|| format!("Wallet '{}' has no address", name))
+    }
+
+    /// Switch to a named wallet and make it the default for later commands
+    fn load_wallet(&mut self, name: &str) -> Result<(), String> {
+        let registry = WalletRegistry::in_data_dir(&self.data_dir);
+        self.wallet = registry.load(name)?;
+        registry.set_default(name)?;
+        self.wallet_path = registry.path(name).to_string_lossy().into_owned();
+        self.wallet_name = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Names of the wallets in the data directory
+    fn list_wallets(&self) -> Result<Vec<String>, String> {
+        WalletRegistry::in_data_dir(&self.data_dir).list()
+    }
+
+    /// Import a private key given as hex or WIF, returning its address
+    fn import_private_key(&mut self, private_key: &str) -> Result<String, String> {
+        let address = self.wallet.import_private_key(private_key)?;
+        
+        if let Err(e) = self.save_wallet() {
+            eprintln!("Warning: Failed to save wallet: {}", e);
+        }
+        
+        Ok(address)
+    }
+
+    /// Private key of a wallet address as WIF, recorded in the alert log
+    fn dump_private_key(&self, address: &str) -> Result<String, String> {
+        let key = self.wallet.get_private_key(address)?;
+        let wallet = self.wallet_name.as_deref().unwrap_or(&self.wallet_path);
+        AlertLog::open(&self.data_dir)?
+            .raise(AlertKind::KeyRevealed, format!("Private key of address {} in wallet '{}' displayed", address, wallet));
+        Ok(encode_wif(&key))
     }
 }
 
 /// Advanced blockchain analysis commands
 pub trait AnalyticsCommands {
     fn analyze_chain(&self) -> ChainAnalytics;
+    fn export_analytics(&self, path: &str, interval: AnalyticsInterval) -> Result<usize, String>;
+    fn get_monetary_stats(&self) -> MonetaryStats;
+    fn get_emission_schedule(&self) -> Vec<EmissionEra>;
     fn get_block_stats(&self, height: Option<u64>) -> Result<BlockStats, String>;
+    fn get_chain_tx_stats(&self, nblocks: Option<u64>) -> Result<ChainTxStats, String>;
     fn get_transaction_stats(&self) -> TransactionStats;
     fn validate_chain_integrity(&self) -> ChainIntegrityReport;
+    fn verify_storage(&self, level: u8, repair: bool) -> Result<StorageReport, String>;
+    fn verify_chain<F: FnMut(u64, u64)>(&self, level: u8, nblocks: u64, progress: F) -> Result<ChainVerification, String>;
+    fn get_storage_stats(&self) -> Result<StorageStats, String>;
+    fn migrate_db(&self) -> Result<SchemaMigration, String>;
+    fn backup_node(&self, path: &str) -> Result<BackupManifest, String>;
 }
 
 impl AnalyticsCommands for CLI {
     /// Analyze the blockchain for various metrics
     fn analyze_chain(&self) -> ChainAnalytics {
-        let blocks = self.chain.get_blocks();
-        let total_blocks = blocks.len();
+        let total_blocks = self.chain.block_count() as usize;
         
         let mut total_transactions = 0;
         let mut total_size = 0;
+        let mut first_time = None;
         let mut min_time = u64::MAX;
         let mut max_time = 0;
+        let mut payments_received: HashMap<String, usize> = HashMap::new();
         
-        for block in blocks {
+        let scanned = self.chain.scan_blocks(0, self.chain.height(), |block| {
             total_transactions += block.transactions.len();
             total_size += serde_json::to_string(block).unwrap_or_default().len();
+            first_time.get_or_insert(block.header.timestamp);
             min_time = min_time.min(block.header.timestamp);
             max_time = max_time.max(block.header.timestamp);
+            for tx in block.transactions.iter().filter(|tx| tx.from != tx.to) {
+                *payments_received.entry(tx.to.clone()).or_default() += 1;
+            }
+        });
+        if let Err(e) = scanned {
+            eprintln!("Warning: Failed to read blocks: {}", e);
         }
         
-        let average_block_time = if total_blocks > 1 {
-            (max_time - min_time) / (total_blocks as u64 - 1)
-        } else {
-            0
-        };
+        // Same-second and out of order blocks add no time, see `consensus::timestamps`
+        let span = timestamps::block_interval(first_time.unwrap_or(0), max_time);
+        let average_block_time = timestamps::average_interval(span, total_blocks.saturating_sub(1) as u64)
+            .map_or(0, |interval| interval as u64);
+        
+        // Paying one address more than once links those payments together
+        let reused: Vec<&String> = payments_received.iter()
+            .filter(|(_, payments)| **payments > 1)
+            .map(|(address, _)| address)
+            .collect();
+        let mut wallet_reused_addresses: Vec<String> = reused.iter()
+            .filter(|address| self.wallet.is_mine(address))
+            .map(|address| address.to_string())
+            .collect();
+        wallet_reused_addresses.sort();
         
         ChainAnalytics {
             total_blocks,
@@ -254,45 +1000,101 @@ impl AnalyticsCommands for CLI {
             average_block_time_seconds: average_block_time,
             chain_start_time: min_time,
             chain_latest_time: max_time,
+            reused_addresses: reused.len(),
+            wallet_reused_addresses,
+            monetary: self.get_monetary_stats(),
         }
     }
 
+    /// Write a time series of chain activity to a CSV file, one block at a time.
+    /// Returns the number of rows written.
+    fn export_analytics(&self, path: &str, interval: AnalyticsInterval) -> Result<usize, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path, e))?;
+        let mut writer = AnalyticsWriter::new(BufWriter::new(file), interval, self.params.pow_limit())?;
+        let mut progress = Progress::new("Exporting analytics", self.chain.height() + 1).with_unit("blocks");
+        self.chain.try_scan_blocks(0, self.chain.height(), |block| {
+            progress.inc(1);
+            // Fees are what the miner claimed beyond the subsidy; the genesis
+            // allocation is not a fee
+            let claimed: u64 = block.transactions.iter()
+                .filter(|tx| is_coinbase_transaction(tx))
+                .map(|tx| tx.amount)
+                .sum();
+            let fees = match block.header.height {
+                0 => 0,
+                height => claimed.saturating_sub(self.params.subsidy_at(height)),
+            };
+            writer.add_block(block, fees)
+        })?;
+        progress.finish();
+        writer.finish()
+    }
+
+    /// Compare coins issued on chain with the emission schedule at the current height
+    fn get_monetary_stats(&self) -> MonetaryStats {
+        let height = self.chain.height();
+        let mut issued: u64 = 0;
+        let scanned = self.chain.scan_blocks(0, height, |block| {
+            issued = block.transactions.iter()
+                .filter(|tx| is_coinbase_transaction(tx))
+                .fold(issued, |issued, tx| issued.saturating_add(tx.amount));
+        });
+        if let Err(e) = scanned {
+            eprintln!("Warning: Failed to read blocks: {}", e);
+        }
+        let next_halving_height = self.params.next_halving_height(height);
+        
+        MonetaryStats {
+            height,
+            current_subsidy: self.params.subsidy_at(height + 1),
+            issued,
+            expected_issued: self.params.expected_supply_at(height),
+            max_supply: self.params.max_supply(),
+            next_halving_height,
+            blocks_until_halving: next_halving_height.map(|next| next - height),
+        }
+    }
+
+    /// Halving eras of the chain's emission schedule
+    fn get_emission_schedule(&self) -> Vec<EmissionEra> {
+        self.params.schedule()
+    }
+
     /// Get statistics for a specific block
     fn get_block_stats(&self, height: Option<u64>) -> Result<BlockStats, String> {
-        let block = if let Some(h) = height {
-            self.chain.get_blocks().get(h as usize)
-                .ok_or_else(|| format!("Block at height {} not found", h))?
-        } else {
-            self.chain.get_blocks().last()
-                .ok_or_else(|| "No blocks in chain".to_string())?
+        let height = match height {
+            Some(h) => h,
+            None => self.chain.tip()
+                .map(|block| block.header.height)
+                .ok_or_else(|| "No blocks in chain".to_string())?,
         };
         
-        let block_size = serde_json::to_string(block).unwrap_or_default().len();
-        
-        Ok(BlockStats {
-            height: height.unwrap_or(self.chain.blocks.len() as u64 - 1),
-            hash: block.header.hash.clone(),
-            timestamp: block.header.timestamp,
-            transaction_count: block.transactions.len(),
-            size_bytes: block_size,
-            nonce: block.header.nonce,
-            previous_hash: block.header.previous_hash.clone(),
-        })
+        self.chain.block_stats(height)
+    }
+
+    /// Get the transaction rate over the last `nblocks` blocks
+    fn get_chain_tx_stats(&self, nblocks: Option<u64>) -> Result<ChainTxStats, String> {
+        self.chain.chain_tx_stats(nblocks)
     }
 
     /// Get transaction statistics across the chain
     fn get_transaction_stats(&self) -> TransactionStats {
         let mut total_transactions = 0;
-        let mut total_value = 0;
+        let mut total_value: u64 = 0;
         let mut unique_addresses = std::collections::HashSet::new();
         
-        for block in &self.chain.blocks {
+        let scanned = self.chain.scan_blocks(0, self.chain.height(), |block| {
             for tx in &block.transactions {
                 total_transactions += 1;
-                total_value += tx.amount;
+                // Coins are counted each time they move, so the total can pass `MAX_MONEY`
+                total_value = total_value.saturating_add(tx.amount);
                 unique_addresses.insert(tx.from.clone());
                 unique_addresses.insert(tx.to.clone());
             }
+        });
+        if let Err(e) = scanned {
+            eprintln!("Warning: Failed to read blocks: {}", e);
         }
         
         TransactionStats {
@@ -312,44 +1114,101 @@ impl AnalyticsCommands for CLI {
         let mut issues = Vec::new();
         let mut valid_blocks = 0;
         
-        for (i, block) in self.chain.blocks.iter().enumerate() {
+        let mut previous: Option<(String, u64)> = None;
+        
+        let scanned = self.chain.scan_blocks(0, self.chain.height(), |block| {
+            let i = block.header.height;
+            let prev = previous.replace((block.header.hash.clone(), block.header.timestamp));
+            
             // Check block hash
             let calculated_hash = block.calculate_hash();
             if calculated_hash != block.header.hash {
                 issues.push(format!("Block {} has invalid hash: expected {}, got {}", 
                                     i, block.header.hash, calculated_hash));
-                continue;
+                return;
             }
             
-            // Check previous hash linkage
-            if i > 0 {
-                let prev_block = &self.chain.blocks[i - 1];
-                if block.header.previous_hash != prev_block.header.hash {
+            if let Some((prev_hash, prev_timestamp)) = prev {
+                // Check previous hash linkage
+                if block.header.previous_hash != prev_hash {
                     issues.push(format!("Block {} has invalid previous hash", i));
-                    continue;
+                    return;
                 }
-            }
-            
-            // Check timestamp ordering
-            if i > 0 {
-                let prev_block = &self.chain.blocks[i - 1];
-                if block.header.timestamp < prev_block.header.timestamp {
+                
+                // Check timestamp ordering
+                if block.header.timestamp < prev_timestamp {
                     issues.push(format!("Block {} has timestamp before previous block", i));
                 }
             }
             
             valid_blocks += 1;
+        });
+        if let Err(e) = scanned {
+            issues.push(format!("Failed to read blocks: {}", e));
         }
         
         ChainIntegrityReport {
-            total_blocks: self.chain.blocks.len(),
+            total_blocks: self.chain.block_count() as usize,
             valid_blocks,
             is_valid: issues.is_empty(),
             issues,
         }
     }
+    
+    /// Cross-check the on-disk block store and indexes
+    fn verify_storage(&self, level: u8, repair: bool) -> Result<StorageReport, String> {
+        if level > MAX_VERIFY_LEVEL {
+            return Err(format!("Verification level must be between 0 and {}", MAX_VERIFY_LEVEL));
+        }
+        self.chain.verify_storage(level, repair)
+    }
+
+    /// Re-validate the most recent blocks at the given check level
+    fn verify_chain<F: FnMut(u64, u64)>(&self, level: u8, nblocks: u64, progress: F) -> Result<ChainVerification, String> {
+        self.chain.verify_chain(level, nblocks, progress)
+    }
+
+    /// Database size and block/header cache hit rates
+    fn get_storage_stats(&self) -> Result<StorageStats, String> {
+        self.chain.storage_stats()
+    }
+
+    /// Upgrade stored records to the current schema version, backing up the
+    /// data directory next to it first
+    fn migrate_db(&self) -> Result<SchemaMigration, String> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("System clock error: {}", e))?
+            .as_secs();
+        let backup_path = format!("{}_backup_{}", self.data_dir.trim_end_matches('/'), timestamp);
+        self.chain.migrate_schema(&backup_path)
+    }
+
+    /// Snapshot the databases, wallets and node state files to `path`, with a
+    /// manifest of checksums for `restore-node`
+    fn backup_node(&self, path: &str) -> Result<BackupManifest, String> {
+        if Path::new(path).exists() {
+            return Err(format!("Backup path {} already exists", path));
+        }
+        self.save_wallet()?;
+        self.chain.checkpoint_stores(&format!("{}/{}", path, backup::DATA_DIR))?;
+
+        let data_dir = Path::new(&self.data_dir);
+        let files: Vec<PathBuf> = [ALERTS_FILE, BOOTSTRAP_FILE, NODE_KEY_FILE, PEER_STATS_DIR, FEE_ESTIMATES_FILE, WALLETS_DIR]
+            .iter()
+            .map(|name| data_dir.join(name))
+            .chain(std::iter::once(PathBuf::from(&self.wallet_path)))
+            .collect();
+        let external_files = backup::copy_files(path, data_dir, &files)?;
+
+        let tip_hash = self.chain.tip().map(|block| block.header.hash.clone()).unwrap_or_default();
+        BackupManifest::create(path, self.chain.height(), &tip_hash, external_files)
+    }
 }
 
+/// Highest `verify-storage` level (balance replay against the stored chainstate)
+pub const MAX_VERIFY_LEVEL: u8 = 3;
+
 /// Chain analytics data structures
 #[derive(Debug)]
 pub struct ChainAnalytics {
@@ -359,17 +1218,27 @@ pub struct ChainAnalytics {
     pub average_block_time_seconds: u64,
     pub chain_start_time: u64,
     pub chain_latest_time: u64,
+    /// Addresses that received more than one payment
+    pub reused_addresses: usize,
+    /// The reused addresses that belong to this wallet
+    pub wallet_reused_addresses: Vec<String>,
+    pub monetary: MonetaryStats,
 }
 
+/// Coin issuance compared with the emission schedule
 #[derive(Debug)]
-pub struct BlockStats {
+pub struct MonetaryStats {
     pub height: u64,
-    pub hash: String,
-    pub timestamp: u64,
-    pub transaction_count: usize,
-    pub size_bytes: usize,
-    pub nonce: u64,
-    pub previous_hash: String,
+    /// Subsidy of the next block
+    pub current_subsidy: u64,
+    /// Coins created by coinbase transactions on the chain
+    pub issued: u64,
+    /// Coins the schedule has issued by this height
+    pub expected_issued: u64,
+    pub max_supply: u64,
+    pub next_halving_height: Option<u64>,
+    /// Blocks left to mine up to and including the halving block
+    pub blocks_until_halving: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -387,3 +1256,14 @@ pub struct ChainIntegrityReport {
     pub issues: Vec<String>,
     pub is_valid: bool,
 }
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn describe_breaches(breaches: &[LimitBreach]) -> String {
+    breaches.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
@@ -1,15 +1,89 @@
+use crate::blockchain::amount::{display_amount, format_coins, parse_amount};
 use crate::blockchain::block::{Block, Transaction};
-use crate::blockchain::genesis::get_genesis_message;
+use crate::blockchain::events::ChainEvent;
+use crate::blockchain::genesis::{get_genesis_message, is_coinbase_transaction};
 use crate::cli::CLI;
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::consensus::pow::difficulty_info;
+
+/// Height range, transaction filters and output mode for `show-blocks`
+#[derive(Debug, Clone, Default)]
+pub struct BlockQuery {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    /// Only the most recent N blocks
+    pub last: Option<u64>,
+    /// Only transactions sent from or to this address
+    pub address: Option<String>,
+    /// Only transactions of at least this amount
+    pub min_amount: Option<u64>,
+    /// One line per block instead of full details
+    pub summary: bool,
+}
+
+impl BlockQuery {
+    /// Parse `--from <h> --to <h> --last <n> --address <addr> --min-amount <n> --summary`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut query = BlockQuery::default();
+        let mut args = args.iter();
+        
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} requires a value", flag));
+            let number = |flag: &str, value: String| value.parse::<u64>()
+                .map_err(|_| format!("Invalid value '{}' for {}", value, flag));
+            
+            match arg.as_str() {
+                "--from" => query.from = Some(number(arg, value(arg)?)?),
+                "--to" => query.to = Some(number(arg, value(arg)?)?),
+                "--last" => query.last = Some(number(arg, value(arg)?)?),
+                "--address" => query.address = Some(value(arg)?),
+                "--min-amount" => query.min_amount = Some(parse_amount(&value(arg)?).map_err(|e| format!("{} for {}", e, arg))?),
+                "--summary" => query.summary = true,
+                other => return Err(format!("Unknown show-blocks option '{}'", other)),
+            }
+        }
+        
+        if query.last.is_some() && (query.from.is_some() || query.to.is_some()) {
+            return Err("--last cannot be combined with --from/--to".to_string());
+        }
+        
+        Ok(query)
+    }
+    
+    /// Inclusive height range to show for a chain whose tip is at `tip_height`
+    pub fn height_range(&self, tip_height: u64) -> (u64, u64) {
+        match self.last {
+            Some(last) => ((tip_height + 1).saturating_sub(last), tip_height),
+            None => (self.from.unwrap_or(0), self.to.unwrap_or(tip_height).min(tip_height)),
+        }
+    }
+    
+    /// Whether any transaction filter is set
+    pub fn filters_transactions(&self) -> bool {
+        self.address.is_some() || self.min_amount.is_some()
+    }
+    
+    /// Whether a transaction passes the address and amount filters
+    pub fn matches(&self, tx: &Transaction) -> bool {
+        let address_ok = self.address.as_ref()
+            .is_none_or(|address| tx.from == *address || tx.to == *address);
+        let amount_ok = self.min_amount.is_none_or(|min| tx.amount >= min);
+        address_ok && amount_ok
+    }
+}
 
 /// Trait for blockchain-related commands
 pub trait BlockchainCommands {
     fn init_chain(&mut self) -> Result<(), String>;
-    fn show_blocks(&self);
+    fn show_blocks(&self, query: &BlockQuery) -> Result<(), String>;
     fn add_block(&mut self, transactions: Vec<Transaction>) -> Result<(), String>;
     fn show_stats(&self);
     fn get_block(&self, hash: &str) -> Result<(), String>;
+    fn get_header(&self, id: &str) -> Result<(), String>;
+    fn invalidate_block(&mut self, hash: &str) -> Result<(), String>;
+    fn reconsider_block(&mut self, hash: &str) -> Result<(), String>;
+    fn list_invalid_blocks(&self);
     fn show_genesis(&self);
     fn show_genesis_info(&self);
 }
@@ -20,104 +94,81 @@ impl BlockchainCommands for CLI {
         println!("Initializing new blockchain...");
         
         // Store genesis block
-        let genesis = &self.chain.blocks[0];
-        self.block_store.store_block(genesis)?;
+        let genesis = self.chain.block_at(0)?
+            .ok_or_else(|| "Chain has no genesis block".to_string())?;
+        self.block_store.store_block(&genesis)?;
         
         println!("Genesis block created with hash: {}", genesis.header.hash);
         println!("Blockchain initialized successfully!");
         Ok(())
     }
     
-    /// Show all blocks in the chain
-    fn show_blocks(&self) {
-        println!("Blockchain contains {} blocks:", self.chain.blocks.len());
-        println!("{:-<80}", "");
+    /// Show blocks in the requested range, optionally filtered by transaction
+    fn show_blocks(&self, query: &BlockQuery) -> Result<(), String> {
+        let tip_height = self.chain.height();
+        let (from, to) = query.height_range(tip_height);
         
-        for (i, block) in self.chain.blocks.iter().enumerate() {
-            println!("Block #{}: {}", i, block.header.hash);
+        println!("Blockchain contains {} blocks, showing heights {}..={}:", self.chain.block_count(), from, to);
+        if !query.summary {
+            println!("{:-<80}", "");
+        }
+        
+        let mut shown = 0;
+        self.chain.scan_blocks(from, to, |block| {
+            let transactions: Vec<(usize, &Transaction)> = block.transactions.iter()
+                .enumerate()
+                .filter(|(_, tx)| query.matches(tx))
+                .collect();
+            if query.filters_transactions() && transactions.is_empty() {
+                return;
+            }
+            shown += 1;
+            
+            if query.summary {
+                let total: u64 = transactions.iter().map(|(_, tx)| tx.amount).sum();
+                println!("#{:<6} {}  time {}  {} txs  {} coins",
+                    block.header.height, block.header.hash, block.header.timestamp, transactions.len(), format_coins(total));
+                return;
+            }
+            
+            println!("Block #{}: {}", block.header.height, block.header.hash);
             println!("  Height: {}", block.header.height);
             println!("  Previous Hash: {}", block.header.previous_hash);
             println!("  Timestamp: {}", block.header.timestamp);
             println!("  Merkle Root: {}", block.header.merkle_root);
             println!("  Nonce: {}", block.header.nonce);
+            if let Some(bits) = block.header.bits {
+                println!("  Bits: {:08x}", bits);
+            }
             println!("  Transactions: {}", block.transactions.len());
             
-            for (j, tx) in block.transactions.iter().enumerate() {
-                // Check if this is a genesis message transaction
-                if let Some(message) = get_genesis_message(tx) {
-                    println!("    Tx #{}: Genesis Message: \"{}\"", j, message);
-                } else if tx.from == "0000000000000000000000000000000000000000" {
-                    println!("    Tx #{}: Coinbase -> {} ({} coins)", j, tx.to, tx.amount);
-                } else {
-                    println!("    Tx #{}: {} -> {} ({})", j, tx.from, tx.to, tx.amount);
-                }
+            for (j, tx) in transactions {
+                print_transaction(j, tx);
             }
             println!("{:-<80}", "");
+        })?;
+        
+        if query.filters_transactions() {
+            println!("{} blocks with matching transactions", shown);
         }
+        Ok(())
     }
     
     /// Add a new block with given transactions
     fn add_block(&mut self, transactions: Vec<Transaction>) -> Result<(), String> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
-        let previous_hash = self.chain.blocks.last()
-            .map(|b| b.header.hash.clone())
+        let (previous_hash, parent_timestamp) = self.chain.tip()
+            .map(|b| (b.header.hash.clone(), b.header.timestamp))
             .unwrap_or_default();
+        let height = self.chain.block_count();
         
-        let height = self.chain.blocks.len() as u64;
-        
-        // Use proper mining for nonce calculation
-        let difficulty = 4; // Standard difficulty
-        let mut nonce = 0u64;
-        
-        // Calculate merkle root first
-        let merkle_root = if transactions.is_empty() {
-            crate::crypto::hash::sha256_hash("")
-        } else {
-            let mut hashes: Vec<String> = transactions.iter()
-                .map(|tx| crate::crypto::hash::sha256_hash(&format!("{:?}", tx)))
-                .collect();
-            while hashes.len() > 1 {
-                let mut next_level = Vec::new();
-                for i in (0..hashes.len()).step_by(2) {
-                    let left = &hashes[i];
-                    let right = if i + 1 < hashes.len() { &hashes[i + 1] } else { left };
-                    next_level.push(crate::crypto::hash::sha256_hash(&(left.clone() + right)));
-                }
-                hashes = next_level;
-            }
-            hashes[0].clone()
-        };
-        
-        // Mine for correct nonce
-        loop {
-            let block_header_data = format!("{}{}{}{}{}",
-                previous_hash, merkle_root, timestamp, height, nonce);
-            let hash = crate::crypto::hash::sha256_hash(&block_header_data);
-            
-            if hash.starts_with(&"0".repeat(difficulty)) {
-                break;
-            }
-            nonce += 1;
-            
-            if nonce % 10000 == 0 {
-                println!("Mining... attempts: {}", nonce);
-            }
-        }
-        
-        let new_block = Block::new(
-            previous_hash,
-            transactions,
-            nonce,
-            timestamp,
-            height,
-        );
+        // Mine to the target the chain requires of the next block
+        self.mining_pool.set_bits(self.chain.next_bits()?);
+        self.mining_pool.set_parent_timestamp(parent_timestamp.max(self.chain.median_time_past()?));
+        let new_block = self.mining_pool.mine_block(previous_hash, transactions, height).block;
         
         if self.chain.add_block(new_block.clone()) {
             self.block_store.store_block(&new_block)?;
+            self.events.publish(ChainEvent::BlockConnected(new_block.clone()));
             println!("Block added successfully with hash: {}", new_block.header.hash);
             Ok(())
         } else {
@@ -128,20 +179,37 @@ impl BlockchainCommands for CLI {
     /// Show chain statistics
     fn show_stats(&self) {
         println!("=== Blockchain Statistics ===");
-        println!("Total Blocks: {}", self.chain.blocks.len());
-        println!("Chain Height: {}", self.chain.blocks.len() - 1);
-        
-        let total_transactions: usize = self.chain.blocks
-            .iter()
-            .map(|b| b.transactions.len())
-            .sum();
-        
-        println!("Total Transactions: {}", total_transactions);
+        let stats = self.chain.get_stats();
+        println!("Total Blocks: {}", stats.total_blocks);
+        println!("Chain Height: {}", stats.chain_height);
+        println!("Chain Work: {:x}", self.chain.chain_work());
+        println!("Total Transactions: {}", stats.total_transactions);
         
-        if let Some(latest_block) = self.chain.blocks.last() {
+        if let Some(latest_block) = self.chain.tip() {
             println!("Latest Block Hash: {}", latest_block.header.hash);
             println!("Latest Block Timestamp: {}", latest_block.header.timestamp);
         }
+        
+        match difficulty_info(&self.chain) {
+            Ok(difficulty) => {
+                println!("Difficulty: {}", difficulty.difficulty);
+                println!("Bits: {}", difficulty.bits);
+                println!("Target: {}", difficulty.target);
+                println!("Next Retarget: height {} ({} blocks, ~{}s)",
+                    difficulty.next_retarget_height, difficulty.blocks_until_retarget, difficulty.estimated_seconds_to_retarget);
+            },
+            Err(e) => eprintln!("Could not read the chain's difficulty: {}", e),
+        }
+        
+        let status = self.checkpoints.status(&self.chain);
+        println!("Checkpoints: {}/{} verified", status.verified_checkpoints, status.total_checkpoints);
+        if let Some(height) = status.last_checkpoint_height {
+            println!("Last Checkpoint Height: {}", height);
+            println!("Past Last Checkpoint: {}", if status.past_last_checkpoint { "Yes" } else { "No" });
+        }
+        if !status.mismatches.is_empty() {
+            println!("WARNING: Chain conflicts with checkpoints at heights {:?}", status.mismatches);
+        }
     }
     
     /// Get block by hash
@@ -154,6 +222,9 @@ impl BlockchainCommands for CLI {
                 println!("  Previous Hash: {}", block.header.previous_hash);
                 println!("  Timestamp: {}", block.header.timestamp);
                 println!("  Transactions: {}", block.transactions.len());
+                for (i, tx) in block.transactions.iter().enumerate() {
+                    print_transaction(i, tx);
+                }
                 Ok(())
             },
             None => {
@@ -163,14 +234,98 @@ impl BlockchainCommands for CLI {
         }
     }
     
+    /// Show a block header by hash or height without loading its transactions
+    fn get_header(&self, id: &str) -> Result<(), String> {
+        let header = match id.parse::<u64>() {
+            Ok(height) => self.chain.header_at(height)?,
+            Err(_) => self.chain.find_header(id)?,
+        };
+        let Some(header) = header else {
+            println!("Block '{}' not found", id);
+            return Ok(());
+        };
+        
+        println!("Block header:");
+        println!("  Hash: {}", header.hash);
+        println!("  Height: {}", header.height);
+        println!("  Confirmations: {}", self.chain.height() - header.height + 1);
+        println!("  Previous Hash: {}", header.previous_hash);
+        if let Some(next_hash) = self.chain.block_hash_at(header.height + 1)? {
+            println!("  Next Hash: {}", next_hash);
+        }
+        println!("  Merkle Root: {}", header.merkle_root);
+        println!("  Timestamp: {}", header.timestamp);
+        println!("  Nonce: {}", header.nonce);
+        if let Some(bits) = header.bits {
+            println!("  Bits: {:08x}", bits);
+        }
+        Ok(())
+    }
+    
+    /// Mark a block invalid, disconnecting it and everything built on it. The
+    /// disconnected transactions go back to the mempool where they still validate.
+    fn invalidate_block(&mut self, hash: &str) -> Result<(), String> {
+        let disconnected = self.chain.invalidate_block(hash)?;
+        if disconnected.is_empty() {
+            println!("Block {} marked invalid (it was not on the active chain)", hash);
+        } else {
+            println!("Block {} marked invalid, disconnected {} block(s)", hash, disconnected.len());
+        }
+        for block in &disconnected {
+            self.events.publish(ChainEvent::BlockDisconnected(block.clone()));
+        }
+
+        self.return_disconnected(&disconnected)?;
+        println!("New tip: {} (height {})", 
+            self.chain.tip().map(|b| b.header.hash.as_str()).unwrap_or_default(), self.chain.height());
+        Ok(())
+    }
+    
+    /// Clear the invalid mark from a block and switch back to it if its branch has
+    /// more work. Transactions of the blocks the switch disconnects go back to the
+    /// mempool where they still validate.
+    fn reconsider_block(&mut self, hash: &str) -> Result<(), String> {
+        let (connected, disconnected) = self.chain.reconsider_block(hash)?;
+        if connected.is_empty() {
+            println!("Block {} is no longer marked invalid; the active chain has at least as much work", hash);
+        } else {
+            println!("Block {} reconsidered, connected {} block(s)", hash, connected.len());
+        }
+        for block in &disconnected {
+            self.events.publish(ChainEvent::BlockDisconnected(block.clone()));
+        }
+        for block in &connected {
+            self.mempool.remove_transactions(&block.transactions);
+            self.events.publish(ChainEvent::BlockConnected(block.clone()));
+        }
+        self.return_disconnected(&disconnected)?;
+        println!("Tip: {} (height {})", 
+            self.chain.tip().map(|b| b.header.hash.as_str()).unwrap_or_default(), self.chain.height());
+        Ok(())
+    }
+    
+    /// List the blocks refused for good: ones peers sent that broke a rule, and
+    /// ones marked with `invalidate-block`
+    fn list_invalid_blocks(&self) {
+        let blocks = self.chain.invalid_block_list();
+        if blocks.is_empty() {
+            println!("No invalid blocks recorded.");
+            return;
+        }
+        
+        println!("=== Invalid Blocks ({}) ===", blocks.len());
+        for (hash, reason) in &blocks {
+            println!("{}  {}", hash, reason);
+        }
+    }
+    
     /// Show basic genesis block details
     fn show_genesis(&self) {
-        if self.chain.blocks.is_empty() {
+        let Ok(Some(genesis)) = self.chain.block_at(0) else {
             println!("No genesis block found!");
             return;
-        }
+        };
         
-        let genesis = &self.chain.blocks[0];
         println!("Genesis Block:");
         println!("  Hash: {}", genesis.header.hash);
         println!("  Timestamp: {}", genesis.header.timestamp);
@@ -190,12 +345,11 @@ impl BlockchainCommands for CLI {
     
     /// Show detailed genesis block information
     fn show_genesis_info(&self) {
-        if self.chain.blocks.is_empty() {
+        let Ok(Some(genesis)) = self.chain.block_at(0) else {
             println!("No genesis block found!");
             return;
-        }
+        };
         
-        let genesis = &self.chain.blocks[0];
         println!("=== Genesis Block Information ===");
         println!("Hash: {}", genesis.header.hash);
         println!("Timestamp: {} ({})", genesis.header.timestamp, 
@@ -218,7 +372,7 @@ impl BlockchainCommands for CLI {
                 println!("Genesis Message: \"{}\"", message);
                 message_found = true;
             } else if tx.from == "0000000000000000000000000000000000000000" && tx.amount > 0 {
-                println!("• {}: {} coins", tx.to, tx.amount);
+                println!("• {}: {}", tx.to, display_amount(tx.amount));
                 total_supply += tx.amount;
                 coinbase_count += 1;
             }
@@ -226,10 +380,44 @@ impl BlockchainCommands for CLI {
         
         println!();
         println!("=== Genesis Summary ===");
-        println!("Total Initial Supply: {} coins", total_supply);
+        println!("Total Initial Supply: {}", display_amount(total_supply));
         println!("Coinbase Transactions: {}", coinbase_count);
         println!("Genesis Message: {}", if message_found { "Yes" } else { "No" });
         println!("Block Height: {}", genesis.header.height);
         println!("Network Launch: {}", if genesis.header.timestamp > 0 { "Production" } else { "Development" });
     }
 }
+
+impl CLI {
+    /// Return the transactions of disconnected blocks to the mempool, recording
+    /// on the chain which were returned and which abandoned
+    fn return_disconnected(&mut self, disconnected: &[Block]) -> Result<(), String> {
+        let (accepted, refused) = self.mempool.return_disconnected(&mut self.chain, disconnected)?;
+        if !accepted.is_empty() || !refused.is_empty() {
+            if let Err(e) = self.mempool.save_to_file("./mempool.json") {
+                eprintln!("Warning: Failed to save mempool: {}", e);
+            }
+            println!("Returned {} transaction(s) to the mempool, abandoned {}", accepted.len(), refused.len());
+        }
+        Ok(())
+    }
+}
+
+/// Print a transaction of a block decoded: id, size and fee, then the input
+/// with the address and amount it spends and the output it pays
+fn print_transaction(index: usize, tx: &Transaction) {
+    println!("    Tx #{}: {}", index, tx.hash());
+    if let Some(message) = get_genesis_message(tx) {
+        println!("      Genesis Message: \"{}\"", message);
+        return;
+    }
+    if is_coinbase_transaction(tx) {
+        println!("      Size: {} bytes", tx.serialize().len());
+        println!("      In:  coinbase");
+    } else {
+        // Transactions carry no fee field, so nothing is paid beyond the output
+        println!("      Size: {} bytes, fee 0", tx.serialize().len());
+        println!("      In:  {} ({} coins)", tx.from, format_coins(tx.amount));
+    }
+    println!("      Out: {} ({} coins)", tx.to, format_coins(tx.amount));
+}
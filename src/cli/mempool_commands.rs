@@ -1,23 +1,45 @@
-use crate::blockchain::block::Transaction;
+use crate::alerts::{AlertConfig, AlertLog, AlertMonitor};
+use crate::blockchain::amount::{display_amount, format_coins};
+use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::events::{ChainEvent, EventBus};
 use crate::cli::{CLI, BlockchainCommands};
-use crate::mempool::ValidationError;
+use crate::cli::mining_commands::{print_coinbase, with_coinbase};
+use crate::consensus::pow::{TemplateRefreshPolicy, TipWatcher};
+use crate::mempool::{BlockTemplate, FeeEstimator, MempoolEvent, MempoolSnapshot, SnapshotEntry, ValidationError, DEFAULT_CONFIRM_TARGET, MAX_BLOCK_TRANSACTIONS};
+use crate::network::{NetworkServer, NodeIdentity, PeerAddress, TipFollower, DEFAULT_TIP_FOLLOW_INTERVAL};
+use crate::{detail, status};
+use std::sync::mpsc;
 
 /// Trait for mempool-related commands
 pub trait MempoolCommands {
     fn add_transaction_to_mempool(&mut self, transaction: Transaction) -> Result<(), String>;
+    fn add_transaction_to_mempool_with_fee(&mut self, transaction: Transaction, fee_per_byte: f64) -> Result<(), String>;
     fn show_mempool_stats(&self);
     fn show_pending_transactions(&self);
-    fn mine_block_from_mempool(&mut self) -> Result<(), String>;
+    fn show_mempool_entry(&self, txid: &str) -> Result<(), String>;
+    fn show_fee_histogram(&self);
+    fn preview_block(&self) -> Result<(), String>;
+    fn dump_mempool(&self, path: &str) -> Result<(), String>;
+    fn diff_mempool(&self, path: &str) -> Result<(), String>;
+    fn diff_mempool_with_peer(&self, peer_address: &PeerAddress) -> Result<(), String>;
+    fn mine_block_from_mempool(&mut self, peer: Option<&PeerAddress>) -> Result<(), String>;
     fn clear_mempool(&mut self);
     fn demo_mempool(&mut self) -> Result<(), String>;
 }
 
 impl MempoolCommands for CLI {
-    /// Add a transaction to the mempool
+    /// Add a transaction to the mempool, paying the fee rate estimated for the default confirmation target
     fn add_transaction_to_mempool(&mut self, transaction: Transaction) -> Result<(), String> {
+        let fee_per_byte = self.select_fee_rate(DEFAULT_CONFIRM_TARGET);
+        self.add_transaction_to_mempool_with_fee(transaction, fee_per_byte)
+    }
+    
+    /// Add a transaction paying the given fee per byte to the mempool
+    fn add_transaction_to_mempool_with_fee(&mut self, transaction: Transaction, fee_per_byte: f64) -> Result<(), String> {
         let utxo_state = self.get_current_utxo_state();
+        let events = self.mempool.subscribe();
         
-        match self.mempool.add_transaction(transaction.clone(), &utxo_state) {
+        match self.mempool.add_transaction_with_fee(transaction.clone(), fee_per_byte, &utxo_state) {
             Ok(()) => {
                 // Auto-save mempool after adding transaction
                 if let Err(e) = self.mempool.save_to_file("./mempool.json") {
@@ -27,31 +49,36 @@ impl MempoolCommands for CLI {
                 println!("Transaction added to mempool successfully!");
                 println!("  From: {}", transaction.from);
                 println!("  To: {}", transaction.to);
-                println!("  Amount: {}", transaction.amount);
+                println!("  Amount: {}", display_amount(transaction.amount));
+                println!("  Fee rate: {:.4} per byte", fee_per_byte);
+                if let Some(lock_height) = transaction.lock_height {
+                    println!("  Valid from height: {}", lock_height);
+                }
+                if let Some(expiry_height) = transaction.expiry_height {
+                    println!("  Expires after height: {}", expiry_height);
+                }
                 println!("  Current mempool size: {}", self.mempool.size());
+                
+                // Both spends stay pending; warn anyone relying on either before it confirms
+                for event in events.try_iter() {
+                    if let MempoolEvent::DoubleSpendDetected { conflicts_with, .. } = &event {
+                        println!("  Warning: Double spend of pending transaction(s) {}", conflicts_with.join(", "));
+                        AlertMonitor::new(AlertConfig::default(), AlertLog::open(&self.data_dir)?)
+                            .check_mempool_event(&event);
+                    }
+                }
                 Ok(())
             },
-            Err(ValidationError::InsufficientFunds) => {
-                Err("Transaction rejected: Insufficient funds".to_string())
-            },
-            Err(ValidationError::DuplicateTransaction) => {
-                Err("Transaction rejected: Duplicate transaction".to_string())
-            },
-            Err(ValidationError::SelfTransfer) => {
-                Err("Transaction rejected: Cannot send to yourself".to_string())
-            },
-            Err(ValidationError::InvalidAddress) => {
-                Err("Transaction rejected: Invalid address".to_string())
-            },
-            Err(ValidationError::InvalidSignature) => {
-                Err("Transaction rejected: Invalid signature".to_string())
+            Err(ValidationError::NotYetValid) => {
+                Err(format!("Transaction rejected: Locked until height {}", transaction.lock_height.unwrap_or_default()))
             },
-            Err(ValidationError::NegativeAmount) => {
-                Err("Transaction rejected: Negative amount".to_string())
+            Err(ValidationError::Expired) => {
+                Err(format!("Transaction rejected: Expired at height {}", transaction.expiry_height.unwrap_or_default()))
             },
-            Err(ValidationError::EmptyTransaction) => {
-                Err("Transaction rejected: Empty transaction".to_string())
+            Err(ValidationError::Policy(violation)) => {
+                Err(format!("Transaction rejected by relay policy: {}", violation))
             },
+            Err(e) => Err(format!("Transaction rejected: {}", e)),
         }
     }
     
@@ -64,12 +91,14 @@ impl MempoolCommands for CLI {
         println!("Total size: {} bytes", stats.total_size_bytes);
         println!("Oldest transaction age: {} seconds", stats.oldest_transaction_age_seconds);
         println!("Average fee per byte: {:.6}", stats.average_fee_per_byte);
+        println!("Size limit: {} transactions", stats.max_size);
+        println!("Expiry: {} seconds", stats.max_age_seconds);
         
         if stats.total_transactions > 0 {
             println!("\nSample pending transactions:");
             let pending = self.mempool.get_pending_transactions();
             for (i, tx) in pending.iter().take(5).enumerate() {
-                println!("  {}. {} -> {} ({})", i + 1, tx.from, tx.to, tx.amount);
+                println!("  {}. {} -> {} ({} coins)", i + 1, tx.from, tx.to, format_coins(tx.amount));
             }
             if pending.len() > 5 {
                 println!("  ... and {} more", pending.len() - 5);
@@ -77,6 +106,58 @@ impl MempoolCommands for CLI {
         }
     }
     
+    /// Show pending transactions grouped by fee band, with fee estimates
+    fn show_fee_histogram(&self) {
+        println!("=== Mempool Fee Histogram ===");
+        println!("{:<16} {:>12} {:>14}", "Fee/byte", "Transactions", "Size (bytes)");
+        
+        for bucket in self.mempool.fee_histogram().iter().rev() {
+            let band = match bucket.max_fee_per_byte {
+                Some(max) => format!("{}-{}", bucket.min_fee_per_byte, max),
+                None => format!("{}+", bucket.min_fee_per_byte),
+            };
+            println!("{:<16} {:>12} {:>14}", band, bucket.transaction_count, bucket.total_size_bytes);
+        }
+        
+        println!();
+        println!("Blocks to clear backlog: {}", self.mempool.blocks_to_clear());
+        for target in [1, 3, 6] {
+            println!("Estimated fee for {} block(s): {} per byte", target, self.mempool.estimate_fee(target));
+        }
+        
+        let estimator = self.load_fee_estimator();
+        for target in [1, 3, 6] {
+            match estimator.estimate_smart_fee(target).fee_per_byte {
+                Some(fee) => println!("Smart fee estimate for {} block(s): {:.4} per byte", target, fee),
+                None => println!("Smart fee estimate for {} block(s): insufficient data", target),
+            }
+        }
+    }
+    
+    /// Show the block that would be mined next on the current tip, without mining it
+    fn preview_block(&self) -> Result<(), String> {
+        let tip = self.chain.tip().ok_or("Chain has no blocks")?;
+        let template = BlockTemplate::build(tip, self.chain.median_time_past()?, &self.mempool, &self.get_current_utxo_state(), &self.params);
+        
+        println!("=== Block Preview ===");
+        println!("Height: {}", template.height);
+        println!("Previous block: {}", template.previous_hash);
+        println!("Timestamp: {}", template.timestamp);
+        println!("Transactions: {}", template.transactions.len());
+        for (i, tx) in template.transactions.iter().enumerate() {
+            println!("  {}. {} {} -> {} ({} coins, fee {:.4} at {:.4} per byte)",
+                i + 1, tx.txid, tx.from, tx.to, format_coins(tx.amount), tx.fee, tx.fee_per_byte);
+            for parent in &tx.depends {
+                println!("       Depends on: {}", parent);
+            }
+        }
+        println!("Estimated size: {} bytes", template.size_bytes);
+        println!("Total fees: {:.4}", template.total_fees);
+        println!("Subsidy: {}", display_amount(template.subsidy));
+        println!("Coinbase value: {}", display_amount(template.coinbase_value));
+        Ok(())
+    }
+    
     /// Show all pending transactions in mempool
     fn show_pending_transactions(&self) {
         let pending = self.mempool.get_pending_transactions();
@@ -92,20 +173,75 @@ impl MempoolCommands for CLI {
         
         for (i, tx) in pending.iter().enumerate() {
             println!("Transaction #{}: ", i + 1);
+            println!("  Txid: {}", tx.hash());
             println!("  From: {}", tx.from);
             println!("  To: {}", tx.to);
-            println!("  Amount: {}", tx.amount);
+            println!("  Amount: {}", display_amount(tx.amount));
             println!("  Signature: {} bytes", tx.signature.len());
             println!("{:-<80}", "");
         }
     }
     
-    /// Mine a block using transactions from mempool
-    fn mine_block_from_mempool(&mut self) -> Result<(), String> {
-        let utxo_state = self.get_current_utxo_state();
+    /// Show fee, size, time in pool and in-pool relatives of a pending transaction
+    fn show_mempool_entry(&self, txid: &str) -> Result<(), String> {
+        let entry = self.mempool.get_entry(txid)
+            .ok_or_else(|| format!("Transaction {} is not in the mempool", txid))?;
+        
+        println!("=== Mempool Entry ===");
+        println!("Txid: {}", entry.txid);
+        println!("Fee: {:.4} ({:.4} per byte)", entry.fee, entry.fee_per_byte);
+        println!("Size: {} bytes", entry.size_bytes);
+        println!("Time in pool: {}s (entered at height {})", entry.seconds_in_pool, entry.entry_height);
+        println!("Ancestors: {} ({} bytes, {:.4} fees)", entry.ancestor_count, entry.ancestor_size, entry.ancestor_fees);
+        println!("Descendants: {} ({} bytes, {:.4} fees)", entry.descendant_count, entry.descendant_size, entry.descendant_fees);
+        for parent in &entry.depends {
+            println!("  Depends on: {}", parent);
+        }
+        for child in &entry.spent_by {
+            println!("  Spent by: {}", child);
+        }
+        Ok(())
+    }
+    
+    /// Write a canonical snapshot of the mempool for comparison with another node
+    fn dump_mempool(&self, path: &str) -> Result<(), String> {
+        let snapshot = MempoolSnapshot::from_mempool(&self.mempool, self.chain.height());
+        snapshot.save_to_file(path)?;
+        println!("Wrote {} pending transaction(s) at height {} to {}", snapshot.entries.len(), snapshot.height, path);
+        Ok(())
+    }
+    
+    /// Compare the mempool with a snapshot dumped on another node
+    fn diff_mempool(&self, path: &str) -> Result<(), String> {
+        let remote = MempoolSnapshot::load_from_file(path)?;
+        let local = MempoolSnapshot::from_mempool(&self.mempool, self.chain.height());
+        print_mempool_diff(&local, &remote, path);
+        Ok(())
+    }
+    
+    /// Compare the mempool with the one a peer reports over the network
+    fn diff_mempool_with_peer(&self, peer_address: &PeerAddress) -> Result<(), String> {
+        println!("Fetching mempool from {}...", peer_address);
+        let identity = NodeIdentity::load_or_create(&self.data_dir)?;
+        let server = NetworkServer::new(self.chain.clone(), "127.0.0.1".to_string(), 8333)
+            .with_checkpoints(self.checkpoints.clone())
+            .with_finality_depth(self.finality_depth)
+            .with_identity(identity);
+        let remote = server.fetch_mempool(peer_address)
+            .map_err(|e| format!("Failed to fetch mempool from {}: {}", peer_address, e))?;
+        let local = MempoolSnapshot::from_mempool(&self.mempool, self.chain.height());
+        print_mempool_diff(&local, &remote, &peer_address.to_string());
+        Ok(())
+    }
+    
+    /// Mine a block using transactions from mempool. With a peer, usually the
+    /// local node, mining restarts on every new tip the peer reports and the
+    /// candidate block takes in the transactions that enter the peer's mempool.
+    fn mine_block_from_mempool(&mut self, peer: Option<&PeerAddress>) -> Result<(), String> {
+        let mut utxo_state = self.get_current_utxo_state();
         
         // Get transactions from mempool for the block
-        let transactions = self.mempool.get_transactions_for_block(10, &utxo_state);
+        let mut transactions = self.mempool.get_transactions_for_block(MAX_BLOCK_TRANSACTIONS, &utxo_state);
         
         if transactions.is_empty() {
             return Err("No valid transactions in mempool to mine".to_string());
@@ -113,17 +249,83 @@ impl MempoolCommands for CLI {
         
         println!("Mining block with {} transactions from mempool...", transactions.len());
         
-        // Mine the block
-        let previous_hash = self.chain.blocks.last()
-            .map(|b| b.header.hash.clone())
-            .unwrap_or_default();
-        let height = self.chain.blocks.len() as u64;
-        
-        let result = self.mining_pool.mine_block(
-            previous_hash,
-            transactions.clone(),
-            height,
-        );
+        // Rebuild the candidate block if enough new fees arrive at the peer while
+        // mining, and start over on the new parent whenever it reports a new best
+        // tip. Nothing else changes the mempool of this process while it mines.
+        let (peer_transactions, events) = mpsc::channel();
+        let peer_tips = EventBus::new();
+        let tip_watcher = TipWatcher::start(peer_tips.subscribe());
+        let tip_follower = match peer {
+            Some(peer) => {
+                let identity = NodeIdentity::load_or_create(&self.data_dir)?;
+                let server = NetworkServer::new(self.chain.clone(), "127.0.0.1".to_string(), 8333)
+                    .with_checkpoints(self.checkpoints.clone())
+                    .with_finality_depth(self.finality_depth)
+                    .with_identity(identity);
+                let known = self.mempool.txids().into_iter().collect();
+                println!("Following the tip and mempool of {}", peer);
+                Some(TipFollower::start(server, peer.clone(), peer_tips, peer_transactions, known, DEFAULT_TIP_FOLLOW_INTERVAL))
+            },
+            None => None,
+        };
+        let policy = TemplateRefreshPolicy::default();
+        let result = loop {
+            let (previous_hash, parent_timestamp) = self.chain.tip()
+                .map(|b| (b.header.hash.clone(), b.header.timestamp))
+                .unwrap_or_default();
+            let height = self.chain.block_count();
+            self.mining_pool.set_bits(self.chain.next_bits()?);
+            self.mining_pool.set_parent_timestamp(parent_timestamp.max(self.chain.median_time_past()?));
+            
+            let mempool = &mut self.mempool;
+            let state = &utxo_state;
+            let payout_address = self.payout_address.as_deref();
+            let subsidy = self.params.subsidy_at(height);
+            let result = self.mining_pool.mine_block_with_refresh(
+                previous_hash,
+                with_coinbase(payout_address, subsidy, height, transactions),
+                height,
+                &events,
+                &policy,
+                tip_watcher.token(),
+                |added| {
+                    for event in added {
+                        if let MempoolEvent::TransactionAdded { transaction, .. } = event
+                            && let Err(e) = mempool.add_network_transaction(transaction.clone(), state) {
+                            eprintln!("Warning: Skipping peer transaction {}: {}", transaction.hash(), e);
+                        }
+                    }
+                    with_coinbase(payout_address, subsidy, height,
+                        mempool.get_transactions_for_block(MAX_BLOCK_TRANSACTIONS, state))
+                },
+            );
+            if let Some(result) = result {
+                break result;
+            }
+            
+            // Catch up with the blocks that arrived and drop what they confirmed
+            for block in tip_watcher.take_new_blocks() {
+                if self.chain.add_block(block.clone()) {
+                    self.record_confirmed_fees(&block);
+                    self.mempool.remove_transactions(&block.transactions);
+                } else if self.chain.find_block(&block.header.hash)?.is_none() {
+                    eprintln!("Warning: New tip {} does not extend our chain", block.header.hash);
+                }
+            }
+            
+            utxo_state = self.get_current_utxo_state();
+            transactions = self.mempool.get_transactions_for_block(MAX_BLOCK_TRANSACTIONS, &utxo_state);
+            if transactions.is_empty() {
+                return Err("Mining stopped: the new tip confirmed every mempool transaction".to_string());
+            }
+            println!("Restarting mining on new tip at height {} with {} transactions...",
+                self.chain.height(), transactions.len());
+        };
+        // Stop watching before our own block is published
+        drop(tip_follower);
+        drop(tip_watcher);
+        drop(events);
+        let transactions = result.block.transactions.clone();
         
         println!("Block mined! Nonce: {}, Attempts: {}, Time: {}ms", 
                  result.nonce, result.attempts, result.elapsed_ms);
@@ -134,8 +336,11 @@ impl MempoolCommands for CLI {
             if let Err(e) = self.block_store.store_block(&result.block) {
                 eprintln!("Warning: Failed to store block: {}", e);
             }
+            self.record_mined_block(&result.block);
+            self.events.publish(ChainEvent::BlockConnected(result.block.clone()));
             
             // Remove mined transactions from mempool
+            self.record_confirmed_fees(&result.block);
             self.mempool.remove_transactions(&transactions);
             
             // Auto-save mempool after mining
@@ -149,6 +354,10 @@ impl MempoolCommands for CLI {
             println!("  Attempts: {}", result.attempts);
             println!("  Time: {}ms", result.elapsed_ms);
             println!("  Transactions included: {}", transactions.len());
+            print_coinbase(&result.block);
+            if result.template_refreshes > 0 {
+                println!("  Template refreshes: {}", result.template_refreshes);
+            }
             println!("  Remaining in mempool: {}", self.mempool.size());
             Ok(())
         } else {
@@ -185,6 +394,8 @@ impl MempoolCommands for CLI {
             to: "charlie".to_string(),
             amount: 100,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         let tx2 = Transaction {
@@ -192,6 +403,8 @@ impl MempoolCommands for CLI {
             to: "david".to_string(),
             amount: 150,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         let tx3 = Transaction {
@@ -199,6 +412,8 @@ impl MempoolCommands for CLI {
             to: "alice".to_string(),
             amount: 75,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         // Add transactions
@@ -226,7 +441,7 @@ impl MempoolCommands for CLI {
         
         // Mine a block from mempool
         println!("\n5. Mining block from mempool:");
-        match self.mine_block_from_mempool() {
+        match self.mine_block_from_mempool(None) {
             Ok(()) => println!("✓ Block mined successfully from mempool"),
             Err(e) => println!("✗ Mining failed: {}", e),
         }
@@ -243,25 +458,81 @@ impl MempoolCommands for CLI {
 }
 
 impl CLI {
+    /// Fee estimator saved in the data directory, empty if it cannot be read
+    pub(crate) fn load_fee_estimator(&self) -> FeeEstimator {
+        FeeEstimator::load_from_file(FeeEstimator::path_in(&self.data_dir)).unwrap_or_else(|e| {
+            eprintln!("Warning: Failed to load fee estimates: {}", e);
+            FeeEstimator::new()
+        })
+    }
+    
+    /// Fee rate for a transaction to confirm within `target_blocks`: the smart
+    /// estimate from confirmed transactions, or the mempool backlog estimate while
+    /// there is not enough history
+    pub fn select_fee_rate(&self, target_blocks: u64) -> f64 {
+        self.load_fee_estimator().estimate_smart_fee(target_blocks).fee_per_byte
+            .unwrap_or_else(|| self.mempool.estimate_fee(target_blocks as usize))
+    }
+    
+    /// Feed the mempool transactions a block confirms to the fee estimator
+    pub(crate) fn record_confirmed_fees(&self, block: &Block) {
+        let path = FeeEstimator::path_in(&self.data_dir);
+        let result = FeeEstimator::load_from_file(&path).and_then(|mut estimator| {
+            estimator.process_block(block.header.height, &self.mempool.confirmation_stats(block));
+            estimator.save_to_file(&path)
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: Failed to record fee estimates: {}", e);
+        }
+    }
+    
+    /// Drop loaded mempool transactions the chain already confirmed or that no
+    /// longer validate against its state, and log what was dropped
+    pub(crate) fn reconcile_mempool(&mut self, utxo_state: &crate::blockchain::state::UTXOState) -> Result<(), String> {
+        let Some(reconciliation) = self.mempool.reconcile_with_chain(&self.chain, utxo_state)? else {
+            return Ok(());
+        };
+        status!("Mempool reconciled with chain at height {}: {}", self.chain.height(), reconciliation);
+        for txid in reconciliation.confirmed.iter().chain(&reconciliation.invalid) {
+            detail!("Dropped mempool transaction {}", txid);
+        }
+        Ok(())
+    }
+    
     /// Get current UTXO state from the blockchain
     pub fn get_current_utxo_state(&self) -> crate::blockchain::state::UTXOState {
-        use crate::blockchain::state::UTXOState;
-        
-        let mut state = UTXOState::new();
-        
-        // Process all transactions in all blocks to build current state
-        for block in &self.chain.blocks {
-            for tx in &block.transactions {
-                // Subtract from sender (if not genesis)
-                if !tx.from.is_empty() && tx.from != "genesis" {
-                    state.update_balance(&tx.from, -(tx.amount as i64));
-                }
-                
-                // Add to receiver
-                state.update_balance(&tx.to, tx.amount as i64);
-            }
-        }
-        
-        state
+        self.chain.utxo_state().unwrap_or_else(|e| {
+            eprintln!("Warning: Failed to rebuild UTXO state: {}", e);
+            crate::blockchain::state::UTXOState::new()
+        })
+    }
+}
+
+/// Print where the local pool and the one from `source` disagree
+fn print_mempool_diff(local: &MempoolSnapshot, remote: &MempoolSnapshot, source: &str) {
+    let diff = local.diff(remote);
+    
+    println!("=== Mempool Diff ===");
+    println!("Local: {} transaction(s) at height {}", local.entries.len(), local.height);
+    println!("Remote ({}): {} transaction(s) at height {}", source, remote.entries.len(), remote.height);
+    println!("Common: {}", diff.common);
+    if diff.is_empty() {
+        println!("Mempools match.");
+        return;
+    }
+    
+    let describe = |entry: &SnapshotEntry| format!("{} ({:.4} per byte, {} bytes, since {})", entry.txid, entry.fee_per_byte, entry.size, entry.time);
+    println!("Only local: {}", diff.only_local.len());
+    for entry in &diff.only_local {
+        println!("  - {}", describe(entry));
+    }
+    println!("Only remote: {}", diff.only_remote.len());
+    for entry in &diff.only_remote {
+        println!("  + {}", describe(entry));
+    }
+    println!("Fee mismatches: {}", diff.fee_mismatches.len());
+    for (local_entry, remote_entry) in &diff.fee_mismatches {
+        println!("  ~ {} local {:.4} (since {}), remote {:.4} (since {})",
+            local_entry.txid, local_entry.fee_per_byte, local_entry.time, remote_entry.fee_per_byte, remote_entry.time);
     }
 }
@@ -1,23 +1,35 @@
-use crate::blockchain::block::Transaction;
+use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::amount::display_amount;
+use crate::blockchain::events::ChainEvent;
+use crate::blockchain::genesis::{coinbase_transaction, is_coinbase_transaction};
 use crate::cli::CLI;
+use crate::consensus::fork_choice::ForkTree;
+use crate::consensus::pow::{difficulty_info, estimate_network_hash_rate, expected_hashes_per_block, DEFAULT_HASHPS_WINDOW};
+use crate::network::peer_stats::PeerStatsDb;
+use crate::network::propagation::now_millis;
 
 /// Trait for mining-related commands
 pub trait MiningCommands {
     fn mine_block(&mut self, transactions: Vec<Transaction>) -> Result<(), String>;
     fn show_mining_stats(&self);
     fn show_fork_stats(&self);
+    fn show_forks(&self) -> Result<(), String>;
+    fn export_forks(&self, format: &str) -> Result<String, String>;
 }
 
 impl MiningCommands for CLI {
     /// Mine a new block
     fn mine_block(&mut self, transactions: Vec<Transaction>) -> Result<(), String> {
-        let previous_hash = self.chain.blocks.last()
-            .map(|b| b.header.hash.clone())
+        let (previous_hash, parent_timestamp) = self.chain.tip()
+            .map(|b| (b.header.hash.clone(), b.header.timestamp))
             .unwrap_or_default();
         
-        let height = self.chain.blocks.len() as u64;
+        let height = self.chain.block_count();
+        let transactions = with_coinbase(self.payout_address.as_deref(), self.params.subsidy_at(height), height, transactions);
         
         println!("Starting to mine block at height {}...", height);
+        self.mining_pool.set_bits(self.chain.next_bits()?);
+        self.mining_pool.set_parent_timestamp(parent_timestamp.max(self.chain.median_time_past()?));
         
         let result = self.mining_pool.mine_block(
             previous_hash,
@@ -28,6 +40,8 @@ impl MiningCommands for CLI {
         // Add the mined block to the chain
         if self.chain.add_block(result.block.clone()) {
             self.block_store.store_block(&result.block)?;
+            self.record_mined_block(&result.block);
+            self.events.publish(ChainEvent::BlockConnected(result.block.clone()));
             
             // Update fork choice
             match self.fork_choice.add_block(result.block.clone()) {
@@ -37,6 +51,7 @@ impl MiningCommands for CLI {
                     println!("  Nonce: {}", result.nonce);
                     println!("  Attempts: {}", result.attempts);
                     println!("  Time: {}ms", result.elapsed_ms);
+                    print_coinbase(&result.block);
                     Ok(())
                 },
                 Err(e) => Err(format!("Failed to update fork choice: {}", e))
@@ -56,7 +71,35 @@ impl MiningCommands for CLI {
         println!("Average attempts per block: {:.2}", stats.average_attempts_per_block);
         println!("Average time per block: {:.2}ms", stats.average_time_per_block_ms);
         println!("Current hash rate: {:.2} H/s", stats.current_hash_rate);
-        println!("Current difficulty: {}", self.mining_pool.get_difficulty());
+        
+        match difficulty_info(&self.chain) {
+            Ok(info) => {
+                println!("Current difficulty: {}", info.difficulty);
+                println!("Bits: {}", info.bits);
+                println!("Target: {}", info.target);
+                println!("Next retarget: height {} ({} blocks, ~{}s)",
+                    info.next_retarget_height, info.blocks_until_retarget, info.estimated_seconds_to_retarget);
+            },
+            Err(e) => eprintln!("Could not read the chain's difficulty: {}", e),
+        }
+        let bits = self.chain.next_bits().unwrap_or_else(|_| self.mining_pool.get_bits());
+        match estimate_network_hash_rate(&self.chain, DEFAULT_HASHPS_WINDOW, None) {
+            Ok(network) => {
+                println!();
+                println!("=== Network ===");
+                println!("Expected hashes per block: {:.0}", expected_hashes_per_block(bits));
+                println!("Estimated network hash rate: {:.2} H/s (last {} blocks)", network.hashes_per_second, network.window_blocks);
+                match network.average_block_time {
+                    Some(seconds) => println!("Average block time: {:.1}s", seconds),
+                    None => println!("Average block time: n/a"),
+                }
+                if network.hashes_per_second > 0.0 && stats.current_hash_rate > 0.0 {
+                    println!("Local share of network: {:.2}%", stats.current_hash_rate / network.hashes_per_second * 100.0);
+                    println!("Expected time to find a block: {:.1}s", expected_hashes_per_block(bits) / stats.current_hash_rate);
+                }
+            },
+            Err(e) => eprintln!("Could not estimate network hash rate: {}", e),
+        }
     }
     
     /// Show fork choice statistics
@@ -71,7 +114,86 @@ impl MiningCommands for CLI {
         
         if let Some(best_chain) = self.fork_choice.get_best_chain() {
             println!("Best chain tip: {}", 
-                best_chain.blocks.last().unwrap().header.hash);
+                best_chain.tip().unwrap().header.hash);
+        }
+    }
+    
+    /// Show the tree of known tips and where each branch leaves the best chain,
+    /// from the branches the block store kept
+    fn show_forks(&self) -> Result<(), String> {
+        let tree = ForkTree::from_stored_branches(&self.chain)?;
+        let Some(best) = tree.best() else {
+            println!("No chains known");
+            return Ok(());
+        };
+        println!("=== Known Tips ===");
+        println!("Best chain: {} at height {}", best.tip_hash, best.tip_height);
+        println!("  Work: {:x}", best.chain_work);
+        if tree.branches.len() == 1 {
+            println!("No forks");
+            return Ok(());
+        }
+        for branch in &tree.branches[1..] {
+            println!();
+            println!("Fork at height {} ({})", branch.fork_height, branch.fork_hash);
+            println!("  └─ {} at height {}", branch.tip_hash, branch.tip_height);
+            println!("     Branch length: {} block(s), {} behind the best tip",
+                branch.length, best.tip_height.saturating_sub(branch.tip_height));
+            let shared_work = branch.chain_work.saturating_sub(branch.branch_work);
+            println!("     Branch work: {:x} (best chain above the fork: {:x})",
+                branch.branch_work, best.chain_work.saturating_sub(shared_work));
+            println!("     Chain work: {:x}", branch.chain_work);
+        }
+        Ok(())
+    }
+    
+    /// Fork tree as Graphviz `dot` or JSON
+    fn export_forks(&self, format: &str) -> Result<String, String> {
+        let tree = ForkTree::from_stored_branches(&self.chain)?;
+        match format {
+            "dot" => Ok(tree.to_dot()),
+            "json" => serde_json::to_string_pretty(&tree.to_json())
+                .map_err(|e| format!("Failed to serialize forks: {}", e)),
+            other => Err(format!("Unknown format '{}', expected dot or json", other)),
+        }
+    }
+}
+
+/// `transactions` behind a coinbase paying `payout_address` the block subsidy, or
+/// unchanged without a payout address. Fees are not collected: the mempool's fee
+/// figures are claimed by whoever submitted a transaction, and no sender is
+/// debited for them on chain.
+pub(crate) fn with_coinbase(
+    payout_address: Option<&str>,
+    subsidy: u64,
+    height: u64,
+    transactions: Vec<Transaction>,
+) -> Vec<Transaction> {
+    let Some(payout_address) = payout_address else {
+        return transactions;
+    };
+    let mut block_transactions = vec![coinbase_transaction(payout_address, subsidy, height)];
+    block_transactions.extend(transactions);
+    block_transactions
+}
+
+/// Report the reward a mined block pays, if it carries one
+pub(crate) fn print_coinbase(block: &Block) {
+    if let Some(coinbase) = block.transactions.iter().find(|tx| is_coinbase_transaction(tx)) {
+        println!("  Reward: {} to {}", display_amount(coinbase.amount), coinbase.to);
+    }
+}
+
+impl CLI {
+    /// Start tracking propagation of a block this node mined
+    pub(crate) fn record_mined_block(&self, block: &Block) {
+        let result = PeerStatsDb::open(&self.data_dir).and_then(|peer_stats| {
+            let mut tracker = peer_stats.propagation()?;
+            tracker.record_mined(&block.header.hash, block.header.height, now_millis());
+            peer_stats.save_propagation(&tracker)
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: Failed to record block propagation: {}", e);
         }
     }
 }
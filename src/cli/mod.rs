@@ -1,9 +1,22 @@
-use crate::blockchain::chain::Chain;
+use crate::alerts::{AlertKind, AlertLog};
+use crate::blockchain::chain::{Chain, IndexConfig};
+use crate::clock::{Clock, MockClock, SystemClock};
 use crate::storage::block_store::BlockStore;
-use crate::consensus::pow::MiningPool;
-use crate::consensus::fork_choice::ForkChoice;
-use crate::mempool::Mempool;
+use crate::storage::backup::{self, BackupManifest};
+use crate::storage::lock::DataDirLock;
+use crate::storage::cache::{DEFAULT_BLOCK_CACHE_BYTES, DEFAULT_HEADER_CACHE_BYTES};
+use crate::consensus::pow::{MiningPool, DEFAULT_DIFFICULTY};
+use crate::consensus::fork_choice::{ForkChoice, DEFAULT_FINALITY_DEPTH};
+use crate::consensus::checkpoints::Checkpoints;
+use crate::consensus::params::ChainParams;
+use crate::mempool::{Mempool, RelayPolicy};
+use crate::config::NodeConfig;
+use crate::network::{BootstrapBundle, BOOTSTRAP_PUBLISHER_KEYS};
+use crate::blockchain::events::EventBus;
+use crate::notify::{Notifier, NotifyConfig};
 use crate::wallet::keychain::Wallet;
+use crate::wallet::registry::WalletRegistry;
+use std::sync::Arc;
 
 pub mod blockchain_commands;
 pub mod mempool_commands;
@@ -12,11 +25,14 @@ pub mod network_commands;
 pub mod advanced_commands;
 pub mod utils;
 
-pub use blockchain_commands::BlockchainCommands;
+pub use blockchain_commands::{BlockchainCommands, BlockQuery};
 pub use mempool_commands::MempoolCommands;
 pub use mining_commands::MiningCommands;
 pub use network_commands::NetworkCommands;
-pub use advanced_commands::{WalletCommands, AnalyticsCommands, TransactionCommands};
+pub use advanced_commands::{WalletCommands, InvoiceSummary, AnalyticsCommands, TransactionCommands};
+
+/// Data directory used unless a path is given
+pub const DEFAULT_DATA_DIR: &str = "./blockchain_data";
 
 /// Main CLI struct that holds all the blockchain components
 pub struct CLI {
@@ -26,34 +42,181 @@ pub struct CLI {
     pub fork_choice: ForkChoice,
     pub mempool: Mempool,
     pub wallet: Wallet,
+    /// Name of the selected wallet, `None` for the unnamed `wallet.json`
+    pub wallet_name: Option<String>,
+    /// File the selected wallet is saved to
+    pub wallet_path: String,
+    pub checkpoints: Checkpoints,
+    pub finality_depth: u64,
+    /// Monetary parameters used for emission reporting
+    pub params: ChainParams,
+    /// Directory holding chain data and the node identity key
+    pub data_dir: String,
+    /// Config file watched for reloadable settings while a node or RPC server runs
+    pub config_path: Option<String>,
+    /// Chain events published by everything that connects blocks
+    pub events: EventBus,
+    /// Delivers chain events to external hooks, when any are configured
+    pub notifier: Option<Notifier>,
+    /// Where `update-bootstrap` fetches signed bootstrap bundles from
+    pub bootstrap_urls: Vec<String>,
+    /// Held while the CLI exists so no other instance opens the data directory
+    pub data_dir_lock: DataDirLock,
+    /// Clock of a `--regtest` node, shared by the chain, mempool and miner and
+    /// set with `setmocktime`
+    pub mock_clock: Option<MockClock>,
+    /// Address the coinbase of mined blocks pays; without one blocks carry no reward
+    pub payout_address: Option<String>,
+}
+
+/// Node options supplied on the command line
+#[derive(Debug, Clone)]
+pub struct CliOptions {
+    pub index_config: IndexConfig,
+    pub checkpoints: Checkpoints,
+    pub finality_depth: u64,
+    pub block_cache_bytes: usize,
+    pub header_cache_bytes: usize,
+    pub relay_policy: RelayPolicy,
+    /// Config file given with `--conf`, with its contents
+    pub config: Option<(String, NodeConfig)>,
+    /// Hooks run on new blocks, reorgs and wallet transactions
+    pub notify: NotifyConfig,
+    /// Named wallet given with `--wallet`
+    pub wallet: Option<String>,
+    /// Bootstrap bundle URLs given with `--bootstrap-url` or the config file
+    pub bootstrap_urls: Vec<String>,
+    /// Remove a stale data directory lock before taking it (`--force-unlock`)
+    pub force_unlock: bool,
+    /// Run on a mock clock (`--regtest`)
+    pub regtest: bool,
+    /// Time the mock clock starts stopped at, 0 to follow the system clock (`--mocktime`)
+    pub mock_time: u64,
+    /// Address mined blocks pay their reward to (`--payout-address` or `mining.payout_address`)
+    pub payout_address: Option<String>,
+}
+
+impl Default for CliOptions {
+    fn default() -> Self {
+        CliOptions {
+            index_config: IndexConfig::default(),
+            checkpoints: Checkpoints::mainnet(),
+            finality_depth: DEFAULT_FINALITY_DEPTH,
+            block_cache_bytes: DEFAULT_BLOCK_CACHE_BYTES,
+            header_cache_bytes: DEFAULT_HEADER_CACHE_BYTES,
+            relay_policy: RelayPolicy::default(),
+            config: None,
+            notify: NotifyConfig::default(),
+            wallet: None,
+            bootstrap_urls: Vec::new(),
+            force_unlock: false,
+            regtest: false,
+            mock_time: 0,
+            payout_address: None,
+        }
+    }
+}
+
+impl CliOptions {
+    /// Load a config file and apply its startup settings. Command-line flags
+    /// parsed afterwards take precedence.
+    pub fn load_config(&mut self, path: &str) -> Result<(), String> {
+        let config = NodeConfig::load_from_file(path)?;
+        
+        self.index_config.txindex = config.txindex.unwrap_or(self.index_config.txindex);
+        self.index_config.addressindex = config.addressindex.unwrap_or(self.index_config.addressindex);
+        self.block_cache_bytes = config.block_cache_bytes.unwrap_or(self.block_cache_bytes);
+        self.header_cache_bytes = config.header_cache_bytes.unwrap_or(self.header_cache_bytes);
+        self.finality_depth = config.finality_depth.unwrap_or(self.finality_depth);
+        self.relay_policy = config.relay_policy(&self.relay_policy);
+        self.relay_policy.blocks_only = config.blocks_only.unwrap_or(self.relay_policy.blocks_only);
+        self.bootstrap_urls = config.bootstrap_urls.clone().unwrap_or_default();
+        self.payout_address = config.payout_address().map(str::to_string);
+        
+        self.config = Some((path.to_string(), config));
+        Ok(())
+    }
 }
 
 impl CLI {
     pub fn new() -> Result<Self, String> {
+        Self::new_with_options(CliOptions::default())
+    }
+    
+    /// Create the CLI with custom index configuration and checkpoints
+    pub fn new_with_options(options: CliOptions) -> Result<Self, String> {
+        let data_dir = DEFAULT_DATA_DIR;
+        let data_dir_lock = lock_data_dir(data_dir, options.force_unlock)?;
+        
         // Use persistent chain
-        let chain = Chain::new_persistent()?;
-        let fork_choice = ForkChoice::with_genesis_chain(chain.clone());
+        let mut chain = Chain::new_persistent_with_config(data_dir, options.index_config)?;
+        chain.set_block_cache_capacity(options.block_cache_bytes, options.header_cache_bytes)?;
+        let mock_clock = options.regtest.then(|| MockClock::new(options.mock_time));
+        if let Some(clock) = &mock_clock {
+            chain.set_clock(Arc::new(clock.clone()));
+        }
+        let params = if options.regtest { ChainParams::regtest() } else { ChainParams::mainnet() };
+        chain.set_params(params.clone());
+        let mut checkpoints = options.checkpoints;
+        add_bootstrap_checkpoints(&mut checkpoints, data_dir);
+        let finality_depth = options.finality_depth;
+        let events = EventBus::new();
+        let fork_choice = ForkChoice::with_genesis_chain(chain.clone())
+            .with_checkpoints(checkpoints.clone())
+            .with_finality_depth(finality_depth)
+            .with_events(events.clone());
         
-        // Load existing wallet or create new one
-        let wallet_path = "wallet.json";
-        let wallet = if Wallet::wallet_exists(wallet_path) {
-            Wallet::load_from_file(wallet_path).unwrap_or_else(|e| {
-                eprintln!("Warning: Failed to load wallet: {}. Creating new wallet.", e);
-                Wallet::new()
-            })
-        } else {
-            Wallet::new()
+        // Load the named wallet, or the unnamed one which is created if missing
+        let registry = WalletRegistry::in_data_dir(data_dir);
+        let wallet_name = match options.wallet {
+            Some(name) => Some(name),
+            None => registry.default_wallet().filter(|name| {
+                let exists = registry.exists(name);
+                if !exists {
+                    eprintln!("Warning: Default wallet '{}' not found, using wallet.json", name);
+                }
+                exists
+            }),
+        };
+        let (wallet_path, wallet) = match &wallet_name {
+            Some(name) => (registry.path(name).to_string_lossy().into_owned(), registry.load(name)?),
+            None => {
+                let wallet_path = "wallet.json".to_string();
+                let wallet = if Wallet::wallet_exists(&wallet_path) {
+                    Wallet::load_from_file(&wallet_path).unwrap_or_else(|e| {
+                        eprintln!("Warning: Failed to load wallet: {}. Creating new wallet.", e);
+                        Wallet::new()
+                    })
+                } else {
+                    Wallet::new()
+                };
+                (wallet_path, wallet)
+            },
         };
         
         // Use a unique CLI block store path to avoid conflicts with network nodes
         let cli_block_store_path = format!("./cli_block_store_{}", std::process::id());
-        let block_store = BlockStore::new_with_path(&cli_block_store_path)?;
+        let block_store = BlockStore::new_with_path(&cli_block_store_path)?
+            .with_cache_capacity(options.block_cache_bytes, options.header_cache_bytes);
         
         // Create persistent mempool
-        let mempool = Mempool::new_persistent("./mempool.json".to_string());
+        let mut mempool = Mempool::new_persistent("./mempool.json".to_string());
+        if let Some((_, config)) = &options.config {
+            mempool.apply_config(config);
+        }
+        mempool.set_policy(options.relay_policy);
         
         // Create a minimal structure to get UTXO state without duplicate BlockStore
-        let mining_pool = MiningPool::new(4);
+        let mut mining_pool = MiningPool::with_bits(params.pow_limit_bits);
+        if let Some(clock) = &mock_clock {
+            mempool.set_clock(Arc::new(clock.clone()));
+            mining_pool.set_clock(Arc::new(clock.clone()));
+        }
+        
+        let notifier = (!options.notify.is_empty()).then(|| {
+            let wallet_addresses = wallet.get_all_addresses().into_iter().collect();
+            Notifier::new(options.notify, events.subscribe(), wallet_addresses)
+        });
         
         let mut cli = CLI {
             chain: chain.clone(),
@@ -62,6 +225,19 @@ impl CLI {
             fork_choice,
             mempool,
             wallet,
+            wallet_name,
+            wallet_path,
+            checkpoints,
+            finality_depth,
+            params,
+            data_dir: data_dir.to_string(),
+            config_path: options.config.map(|(path, _)| path),
+            events,
+            notifier,
+            bootstrap_urls: options.bootstrap_urls,
+            data_dir_lock,
+            mock_clock,
+            payout_address: options.payout_address,
         };
         
         // Load mempool from persistence using the CLI we just created
@@ -69,9 +245,10 @@ impl CLI {
         if let Err(e) = cli.mempool.load_from_file("./mempool.json", &utxo_state) {
             eprintln!("Warning: Failed to load mempool: {}", e);
         }
+        cli.reconcile_mempool(&utxo_state)?;
         
         // Save wallet to persist any changes
-        if let Err(e) = cli.wallet.save_to_file(wallet_path) {
+        if let Err(e) = cli.save_wallet() {
             eprintln!("Warning: Failed to save wallet: {}", e);
         }
         
@@ -79,9 +256,15 @@ impl CLI {
     }
     
     pub fn new_with_path(db_path: &str) -> Result<Self, String> {
+        let data_dir_lock = DataDirLock::acquire(db_path)?;
+        
         // Use persistent chain with custom path
         let chain = Chain::new_persistent_with_path(db_path)?;
-        let fork_choice = ForkChoice::with_genesis_chain(chain.clone());
+        let checkpoints = Checkpoints::mainnet();
+        let events = EventBus::new();
+        let fork_choice = ForkChoice::with_genesis_chain(chain.clone())
+            .with_checkpoints(checkpoints.clone())
+            .with_events(events.clone());
         
         // Load existing wallet or create new one (using custom path)
         let wallet_path = format!("{}/wallet.json", db_path);
@@ -100,10 +283,23 @@ impl CLI {
         let mut cli = CLI {
             chain,
             block_store: BlockStore::new_with_path(&cli_block_store_path)?,
-            mining_pool: MiningPool::new(4), // Default difficulty of 4
+            mining_pool: MiningPool::new(DEFAULT_DIFFICULTY),
             fork_choice,
             mempool: Mempool::new_persistent(format!("{}/mempool.json", db_path)),
             wallet,
+            wallet_name: None,
+            wallet_path,
+            checkpoints,
+            finality_depth: DEFAULT_FINALITY_DEPTH,
+            params: ChainParams::mainnet(),
+            data_dir: db_path.to_string(),
+            config_path: None,
+            events,
+            notifier: None,
+            bootstrap_urls: Vec::new(),
+            data_dir_lock,
+            mock_clock: None,
+            payout_address: None,
         };
         
         // Load mempool from persistence
@@ -112,10 +308,11 @@ impl CLI {
         if let Err(e) = cli.mempool.load_from_file(&mempool_path, &utxo_state) {
             eprintln!("Warning: Failed to load mempool: {}", e);
         }
+        cli.reconcile_mempool(&utxo_state)?;
         
         // Ensure directory exists and save wallet
         std::fs::create_dir_all(db_path).map_err(|e| format!("Failed to create directory: {}", e))?;
-        if let Err(e) = cli.wallet.save_to_file(&wallet_path) {
+        if let Err(e) = cli.save_wallet() {
             eprintln!("Warning: Failed to save wallet: {}", e);
         }
         
@@ -123,8 +320,81 @@ impl CLI {
     }
 }
 
+impl CLI {
+    /// Replace the default data directory and wallet with a verified backup,
+    /// returning its manifest and where the replaced files were moved. Runs
+    /// without a CLI, which would hold the databases open.
+    pub fn restore_node(backup_path: &str, force_unlock: bool) -> Result<(BackupManifest, String), String> {
+        let _lock = lock_data_dir(DEFAULT_DATA_DIR, force_unlock)?;
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("System clock error: {}", e))?
+            .as_secs();
+        let previous_path = format!("{}_pre_restore_{}", DEFAULT_DATA_DIR, timestamp);
+        let manifest = backup::restore(backup_path, DEFAULT_DATA_DIR, &previous_path)?;
+        Ok((manifest, previous_path))
+    }
+    
+    /// The current time, from the mock clock when one is set (`--regtest`)
+    pub fn now(&self) -> u64 {
+        match &self.mock_clock {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+    
+    /// Save the selected wallet to its file
+    pub fn save_wallet(&self) -> Result<(), String> {
+        self.wallet.save_to_file(&self.wallet_path)
+    }
+    
+    /// Record in the alert log that the selected wallet's seed left the wallet
+    pub(crate) fn record_seed_access(&self, action: &str) -> Result<(), String> {
+        let wallet = self.wallet_name.as_deref().unwrap_or(&self.wallet_path);
+        AlertLog::open(&self.data_dir)?
+            .raise(AlertKind::SeedRevealed, format!("Seed phrase of wallet '{}' {}", wallet, action));
+        Ok(())
+    }
+    
+    /// Deliver notifications for chain events published so far. One-shot commands
+    /// call this before exiting; long-running nodes deliver them as they happen.
+    pub fn flush_notifications(&self) {
+        if let Some(notifier) = &self.notifier {
+            notifier.process_pending();
+        }
+    }
+}
+
 impl Default for CLI {
     fn default() -> Self {
         Self::new().expect("Failed to create default CLI")
     }
+}
+
+/// Take the data directory lock, first removing a stale one if asked to
+fn lock_data_dir(data_dir: &str, force_unlock: bool) -> Result<DataDirLock, String> {
+    if force_unlock {
+        if let Some(owner) = DataDirLock::force_unlock(data_dir)? {
+            eprintln!("Warning: Removed data directory lock held by process {}", owner.pid);
+        }
+    }
+    DataDirLock::acquire(data_dir)
+}
+
+/// Add the checkpoints of the saved bootstrap bundle. Checkpoints given on the
+/// command line win over the bundle's at the same height.
+fn add_bootstrap_checkpoints(checkpoints: &mut Checkpoints, data_dir: &str) {
+    let bundle = match BootstrapBundle::load_from_file(BootstrapBundle::path_in(data_dir), BOOTSTRAP_PUBLISHER_KEYS) {
+        Ok(Some(bundle)) => bundle,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Warning: Ignoring bootstrap bundle: {}", e);
+            return;
+        }
+    };
+    for (height, hash) in bundle.checkpoints().unwrap_or_default() {
+        if checkpoints.get(height).is_none() {
+            checkpoints.add(height, hash);
+        }
+    }
 }
\ No newline at end of file
@@ -1,50 +1,245 @@
+use crate::alerts::{AlertConfig, AlertLog, AlertMonitor};
 use crate::cli::CLI;
-use crate::network::{NetworkServer, PeerDiscovery};
-use std::net::SocketAddr;
+use crate::network::{NetworkConfig, NetworkServer, NodeIdentity, PeerAddress, PeerDiscovery, PropagationStats, PropagationTracker};
+use crate::network::bootstrap::{self, BootstrapBundle, BOOTSTRAP_PUBLISHER_KEYS};
+use crate::network::dialer::{OutboundDialer, DEFAULT_DIAL_INTERVAL};
+use crate::network::height_poll::{HeightPoller, DEFAULT_HEIGHT_POLL_INTERVAL};
+use crate::network::keepalive::{self, KeepaliveScheduler, DEFAULT_STALE_PEER_SWEEP_INTERVAL};
+use crate::network::peer_stats::{self, PeerStatsDb, DEFAULT_PEER_STATS_INTERVAL};
+use crate::network::traffic::{self, NodeStats, DEFAULT_NODE_STATS_INTERVAL};
+use crate::network::watchdog::{ChainWatchdog, DEFAULT_REDISCOVERY_INTERVAL, DEFAULT_WATCHDOG_INTERVAL};
+use crate::perf::{self, BlockCostLog, PerfReport, DEFAULT_PERF_SNAPSHOT_INTERVAL};
+use crate::status;
+use crate::rpc::server::{RpcConfig, RpcServer};
+use crate::config::{ConfigWatcher, CONFIG_POLL_INTERVAL, NODE_RELOADABLE_SETTINGS, RPC_RELOADABLE_SETTINGS};
+use crate::mempool::FeeEstimator;
+use crate::daemon;
+use crate::wallet::registry::WalletRegistry;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
+/// Slowest blocks listed by `perf-report`
+const SLOWEST_BLOCKS_SHOWN: usize = 5;
+
+/// RPC server over the CLI's chain, mempool and wallets
+fn configure_rpc_server(cli: &CLI, config: RpcConfig) -> Result<RpcServer, String> {
+    let wallets = WalletRegistry::in_data_dir(&cli.data_dir).load_all()?;
+    let wallet_names: Vec<&String> = wallets.keys().collect();
+    if !wallet_names.is_empty() {
+        status!("  ✓ Named wallets at /wallet/<name>: {:?}", wallet_names);
+    }
+    
+    // Use existing CLI components instead of creating new ones
+    // This avoids the database lock conflict
+    let server = RpcServer::new(
+        config,
+        cli.chain.clone(),
+        cli.mempool.clone(),
+        cli.wallet.clone(),
+    )
+    .with_data_dir(&cli.data_dir)
+    .with_fork_choice(cli.fork_choice.clone())
+    .with_wallets(wallets);
+    let server = match &cli.mock_clock {
+        Some(clock) => {
+            status!("  ✓ Regtest: setmocktime sets the clock of this process");
+            server.with_mock_clock(clock.clone())
+        },
+        None => server,
+    };
+    let server = match &cli.config_path {
+        Some(path) => {
+            status!("  ✓ Watching {} for setting changes", path);
+            server.with_config_watcher(ConfigWatcher::new(path, RPC_RELOADABLE_SETTINGS)?)
+        },
+        None => server,
+    };
+    Ok(server)
+}
+
 /// Trait for network-related commands
 pub trait NetworkCommands {
-    fn start_node(&self, listen_address: String, listen_port: u16) -> Result<(), String>;
+    fn start_node(&self, listen_address: String, listen_port: u16, rediscover: bool, rpc: Option<RpcConfig>) -> Result<(), String>;
     fn connect_peer(&self, address: String, port: u16) -> Result<(), String>;
-    fn start_rpc_server(&self, rpc_port: u16) -> Result<(), String>;
+    fn start_rpc_server(&self, config: RpcConfig) -> Result<(), String>;
     fn discover_peers(&self, seed_nodes: Vec<String>) -> Result<(), String>;
+    fn default_seed_nodes(&self) -> Vec<String>;
+    fn update_bootstrap(&self, urls: Vec<String>) -> Result<(), String>;
     fn show_peers(&self) -> Result<(), String>;
     fn show_network_stats(&self) -> Result<(), String>;
+    fn get_propagation_stats(&self) -> Result<PropagationStats, String>;
+    fn show_propagation_stats(&self, recent: usize) -> Result<(), String>;
+    fn list_alerts(&self, recent: usize) -> Result<(), String>;
+    fn show_peer_history(&self, address: Option<String>) -> Result<(), String>;
+    fn show_perf_report(&self) -> Result<(), String>;
 }
 
 impl NetworkCommands for CLI {
-    /// Start network node
-    fn start_node(&self, listen_address: String, listen_port: u16) -> Result<(), String> {
-        println!("Starting network node on {}:{}...", listen_address, listen_port);
+    /// Start network node, reconnecting to the seeds while stalled or eclipsed if `rediscover` is set,
+    /// and serving JSON-RPC with peer management when `rpc` is given
+    fn start_node(&self, listen_address: String, listen_port: u16, rediscover: bool, rpc: Option<RpcConfig>) -> Result<(), String> {
+        status!("Starting network node on {}:{}...", listen_address, listen_port);
+        
+        let identity = NodeIdentity::load_or_create(&self.data_dir)?;
+        status!("Node id: {}", identity.node_id());
+        
+        let peer_stats = PeerStatsDb::open(&self.data_dir)?;
+        let propagation = peer_stats.propagation()?;
+        let alerts = AlertMonitor::new(AlertConfig::default(), AlertLog::open(&self.data_dir)?);
+        alerts.watch(self.events.subscribe());
+        let seeds = self.default_seed_nodes().iter()
+            .filter_map(|seed| match seed.parse::<PeerAddress>() {
+                Ok(seed) => Some(seed),
+                Err(e) => {
+                    eprintln!("Warning: Ignoring seed node '{}': {}", seed, e);
+                    None
+                },
+            })
+            .collect::<Vec<_>>();
+        let dialer = OutboundDialer::new(seeds.clone());
+        // Keepalive traffic keeps peer discovery's last-seen times current
+        let local_address = SocketAddr::new(listen_address.parse().unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), listen_port);
+        let mut discovery = PeerDiscovery::new(local_address, "rust-chain-v1.0".to_string());
+        if let Some(clock) = &self.mock_clock {
+            discovery.set_clock(Arc::new(clock.clone()));
+        }
+        discovery.add_seed_nodes(seeds.clone());
+        discovery.update_chain_height(self.chain.block_count());
+        let discovery = Arc::new(Mutex::new(discovery));
+        let mut watchdog = ChainWatchdog::new(alerts.clone());
+        if rediscover {
+            status!("Rediscovering peers from {} seed node(s) while stalled or eclipsed", seeds.len());
+            watchdog = watchdog.with_rediscovery(seeds, DEFAULT_REDISCOVERY_INTERVAL);
+        }
+        // Blocks the node connects feed the fee estimator and leave the mempool
+        let mempool = Arc::new(Mutex::new(self.mempool.clone()));
+        self.load_fee_estimator().watch(FeeEstimator::path_in(&self.data_dir), Arc::clone(&mempool), self.events.subscribe());
+        let server = NetworkServer::new(self.chain.clone(), listen_address, listen_port)
+            .with_checkpoints(self.checkpoints.clone())
+            .with_finality_depth(self.finality_depth)
+            .with_identity(identity)
+            .with_propagation(propagation)
+            .with_relay_policy(self.mempool.policy().clone())
+            .with_mempool(mempool)
+            .with_keepalive(KeepaliveScheduler::default().with_discovery(Arc::clone(&discovery)))
+            .with_events(self.events.clone())
+            .with_alerts(alerts)
+            .with_peer_stats(peer_stats);
+        let server = match &self.mock_clock {
+            Some(clock) => server.with_clock(Arc::new(clock.clone())),
+            None => server,
+        };
         
-        let server = NetworkServer::new(self.chain.clone(), listen_address, listen_port);
+        let mut watcher = match &self.config_path {
+            Some(path) => Some(ConfigWatcher::new(path, NODE_RELOADABLE_SETTINGS)?),
+            None => None,
+        };
+        if let Some(watcher) = &watcher {
+            server.apply_config(watcher.current());
+        }
         
         // Start server in a separate thread
-        let server_handle = thread::spawn(move || {
-            if let Err(e) = server.start() {
-                eprintln!("Server error: {}", e);
+        let server = Arc::new(server);
+        let server_handle = {
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                if let Err(e) = server.start() {
+                    eprintln!("Server error: {}", e);
+                }
+            })
+        };
+        let mut rpc_shutdown = None;
+        if let Some(config) = rpc {
+            let rpc_port = config.bind_address.port();
+            let rpc_server = configure_rpc_server(self, config)?.with_network(Arc::clone(&server));
+            rpc_shutdown = Some(rpc_server.shutdown_handle());
+            status!("JSON-RPC on http://127.0.0.1:{}/rpc, with getconnectioncount, addnode, disconnectnode and stop", rpc_port);
+            let node = Arc::clone(&server);
+            thread::spawn(move || {
+                let rt = match tokio::runtime::Runtime::new() {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        eprintln!("RPC server error: Failed to create async runtime: {}", e);
+                        return;
+                    },
+                };
+                match rt.block_on(rpc_server.start()) {
+                    // The `stop` method stops the whole node
+                    Ok(()) => node.stop(),
+                    Err(e) => eprintln!("RPC server error: {}", e),
+                }
+            });
+        }
+        // Stop cleanly on SIGTERM from `stop` or Ctrl+C, so the databases are closed
+        // and the data directory lock released on the way out
+        {
+            let server = Arc::clone(&server);
+            daemon::on_shutdown_signal(move || {
+                if let Some(rpc_shutdown) = rpc_shutdown {
+                    rpc_shutdown.stop();
+                }
+                server.stop();
+            });
+        }
+        watchdog.watch(Arc::clone(&server), DEFAULT_WATCHDOG_INTERVAL);
+        HeightPoller::new(DEFAULT_HEIGHT_POLL_INTERVAL).watch(Arc::clone(&server));
+        status!("Keeping {} outbound connection(s) open", server.outbound_target());
+        dialer.watch(Arc::clone(&server), DEFAULT_DIAL_INTERVAL);
+        keepalive::sweep_stale_peers(discovery, Arc::clone(&server), DEFAULT_STALE_PEER_SWEEP_INTERVAL);
+        perf::write_reports(&self.data_dir, DEFAULT_PERF_SNAPSHOT_INTERVAL);
+        traffic::write_node_stats(Arc::clone(&server), NodeStats::path_in(&self.data_dir), DEFAULT_NODE_STATS_INTERVAL);
+        peer_stats::write_peer_traffic(Arc::clone(&server), DEFAULT_PEER_STATS_INTERVAL);
+        
+        status!("Network node started. Press Ctrl+C to stop.");
+        
+        // Deliver chain notifications and apply config file changes until the server stops
+        if let Some(watcher) = &watcher {
+            status!("Watching {} for setting changes", watcher.path().display());
+        }
+        if watcher.is_some() || self.notifier.is_some() {
+            while !server_handle.is_finished() {
+                match &self.notifier {
+                    Some(notifier) => {
+                        notifier.process_for(CONFIG_POLL_INTERVAL);
+                    },
+                    None => thread::sleep(CONFIG_POLL_INTERVAL),
+                }
+                
+                let Some(watcher) = &mut watcher else { continue };
+                match watcher.poll() {
+                    Some(Ok(reload)) => {
+                        server.apply_config(&reload.config);
+                        println!("Config reloaded from {}: {}", watcher.path().display(), reload.changes);
+                    },
+                    Some(Err(e)) => eprintln!("Config reload failed, keeping previous settings: {}", e),
+                    None => {},
+                }
             }
-        });
-        
-        println!("Network node started. Press Ctrl+C to stop.");
+        }
         
         // Wait for the server thread (this will block until the server stops)
         if let Err(e) = server_handle.join() {
             eprintln!("Server thread error: {:?}", e);
         }
+        server.save_peer_traffic();
         
         Ok(())
     }
     
     /// Connect to a peer
     fn connect_peer(&self, address: String, port: u16) -> Result<(), String> {
-        println!("Connecting to peer at {}:{}...", address, port);
+        let peer_address = PeerAddress::new(&address, port)?;
+        status!("Connecting to peer at {}...", peer_address);
         
         // Create a network server with proper configuration
-        let server = NetworkServer::new(self.chain.clone(), "127.0.0.1".to_string(), 8333);
+        let identity = NodeIdentity::load_or_create(&self.data_dir)?;
+        let server = NetworkServer::new(self.chain.clone(), "127.0.0.1".to_string(), 8333)
+            .with_checkpoints(self.checkpoints.clone())
+            .with_identity(identity)
+            .with_peer_stats(PeerStatsDb::open(&self.data_dir)?);
         
-        server.connect_to_peer(&address, port)
+        server.connect_to_peer(&peer_address)
             .map_err(|e| format!("Failed to connect to peer: {}", e))?;
         
         // Give the connection a moment to establish properly
@@ -60,11 +255,11 @@ impl NetworkCommands for CLI {
         
         // After showing initial stats, attempt to sync blockchain
         if stats.connected_peers > 0 {
-            println!("Connected! Attempting blockchain synchronization...");
+            status!("Connected! Attempting blockchain synchronization...");
             if let Err(e) = server.sync_blockchain() {
                 eprintln!("Warning: Blockchain sync failed: {}", e);
             } else {
-                println!("Blockchain synchronization completed successfully");
+                status!("Blockchain synchronization completed successfully");
             }
         } else {
             println!("Warning: No peers connected after handshake");
@@ -74,51 +269,63 @@ impl NetworkCommands for CLI {
     }
 
     /// Start JSON-RPC server
-    fn start_rpc_server(&self, rpc_port: u16) -> Result<(), String> {
-        println!("Starting production JSON-RPC server on port {}...", rpc_port);
-        
-        // Create RPC config
-        let config = crate::rpc::server::RpcConfig {
-            bind_address: format!("127.0.0.1:{}", rpc_port).parse()
-                .map_err(|e| format!("Invalid address: {}", e))?,
-            max_request_size: 1_048_576, // 1MB
-            enable_cors: true,
-            allowed_origins: vec!["*".to_string()],
-        };
-        
-        // Use existing CLI components instead of creating new ones
-        // This avoids the database lock conflict
-        let server = crate::rpc::server::RpcServer::new(
-            config,
-            self.chain.clone(),
-            self.mempool.clone(),
-            self.wallet.clone(),
+    fn start_rpc_server(&self, config: RpcConfig) -> Result<(), String> {
+        let rpc_port = config.bind_address.port();
+        status!("Starting production JSON-RPC server on port {}...", rpc_port);
+        
+        let limits = format!(
+            "  ✓ Request timeout {} ms, at most {} requests in flight, slow log from {} ms",
+            config.request_timeout.as_millis(),
+            config.max_concurrent_requests,
+            config.slow_request_threshold.as_millis()
         );
         
-        println!("✓ RPC server configured successfully!");
-        println!("Server Details:");
-        println!("  Endpoint: http://127.0.0.1:{}/rpc", rpc_port);
-        println!("  Health check: http://127.0.0.1:{}/health", rpc_port);
-        println!("  Metrics: http://127.0.0.1:{}/metrics", rpc_port);
-        println!("  Using existing CLI components (shared state)");
-        
-        println!("Available JSON-RPC methods:");
-        println!("  getblockcount - Get current block height");
-        println!("  getblockhash <height> - Get block hash by height");
-        println!("  getblock <hash> - Get block details");
-        println!("  getmempoolinfo - Get mempool statistics");
-        println!("  sendrawtransaction <hex> - Submit transaction");
-        println!("  getnewaddress - Generate new wallet address");
-        
-        println!("Note: Server runs with:");
-        println!("  ✓ Shared blockchain state with CLI");
-        println!("  ✓ Shared mempool state with CLI");
-        println!("  ✓ Shared wallet state with CLI");
-        println!("  ✓ CORS enabled");
-        println!("  ✓ Request size limits (1MB)");
+        let server = configure_rpc_server(self, config)?;
+        // Ctrl+C and SIGTERM let requests in flight finish, like the `stop` method
+        let shutdown = server.shutdown_handle();
+        daemon::on_shutdown_signal(move || shutdown.stop());
+        
+        status!("✓ RPC server configured successfully!");
+        status!("Server Details:");
+        status!("  Endpoint: http://127.0.0.1:{}/rpc", rpc_port);
+        status!("  Health check: http://127.0.0.1:{}/health", rpc_port);
+        status!("  Metrics: http://127.0.0.1:{}/metrics", rpc_port);
+        status!("  Using existing CLI components (shared state)");
+        
+        status!("Available JSON-RPC methods:");
+        status!("  getblockcount - Get current block height");
+        status!("  getblockhash <height> - Get block hash by height");
+        status!("  getblock <hash> - Get block details");
+        status!("  getblockstats <height|hash> - Get fees, subsidy, size and timing of a block");
+        status!("  getchaintxstats [nblocks] - Get transaction rate over recent blocks");
+        status!("  getdifficulty - Get the difficulty required of the next block");
+        status!("  getmininginfo - Get difficulty, network hash rate and pending transactions");
+        status!("  getnetworkhashps [nblocks] [height] - Estimate network hashes per second");
+        status!("  getpropagationstats [count] - Propagation times and orphan rate of mined blocks");
+        status!("  getalerts [count] - Recent consensus anomaly alerts");
+        status!("  getmempoolinfo - Get mempool statistics");
+        status!("  getrawmempool [verbose] - Get pending txids, or their entries when verbose");
+        status!("  getmempoolentry <txid> - Get fee, size, time in pool and relatives of a pending transaction");
+        status!("  getrawtransaction <txid> [verbose] - Get transaction hex or decoded JSON");
+        status!("  decoderawtransaction <hex> - Decode a raw transaction");
+        status!("  getaddresstxids <address> - List transaction ids for an address");
+        status!("  getmempoolfeehistogram - Pending transactions grouped by fee band");
+        status!("  estimatefee [nblocks] - Estimate fee per byte to confirm within nblocks");
+        status!("  sendrawtransaction <hex> - Submit transaction");
+        status!("  getnewaddress - Generate new wallet address");
+        status!("  getconnectioncount, addnode, disconnectnode - Peer management, when served by start-node --rpc");
+        status!("  stop - Stop the server once requests in flight are answered (localhost only)");
+        
+        status!("Note: Server runs with:");
+        status!("  ✓ Shared blockchain state with CLI");
+        status!("  ✓ Shared mempool state with CLI");
+        status!("  ✓ Shared wallet state with CLI");
+        status!("  ✓ CORS enabled");
+        status!("  ✓ Request size limits (1MB)");
+        status!("{}", limits);
         
         // Start the server in an async runtime
-        println!("\nStarting server...");
+        status!("\nStarting server...");
         let rt = tokio::runtime::Runtime::new()
             .map_err(|e| format!("Failed to create async runtime: {}", e))?;
         
@@ -138,11 +345,14 @@ impl NetworkCommands for CLI {
             .map_err(|e| format!("Invalid local address: {}", e))?;
         
         let mut discovery = PeerDiscovery::new(local_addr, "rust-chain-v1.0".to_string());
+        if let Some(clock) = &self.mock_clock {
+            discovery.set_clock(Arc::new(clock.clone()));
+        }
         
         // Parse and add seed nodes
         let mut seed_addrs = Vec::new();
         for seed in seed_nodes {
-            let addr: SocketAddr = seed.parse()
+            let addr: PeerAddress = seed.parse()
                 .map_err(|e| format!("Invalid seed node address '{}': {}", seed, e))?;
             seed_addrs.push(addr);
         }
@@ -154,7 +364,7 @@ impl NetworkCommands for CLI {
         discovery.add_seed_nodes(seed_addrs);
         
         // Update discovery with current chain height
-        let chain_height = self.chain.blocks.len() as u64;
+        let chain_height = self.chain.block_count();
         discovery.update_chain_height(chain_height);
         
         println!("Added {} seed nodes for discovery", discovery.get_seed_nodes().len());
@@ -167,6 +377,44 @@ impl NetworkCommands for CLI {
         Ok(())
     }
 
+    /// Seeds from the saved bootstrap bundle, or the built-in seeds without one
+    fn default_seed_nodes(&self) -> Vec<String> {
+        match BootstrapBundle::load_from_file(BootstrapBundle::path_in(&self.data_dir), BOOTSTRAP_PUBLISHER_KEYS) {
+            Ok(Some(bundle)) => return bundle.payload.seeds,
+            Ok(None) => {},
+            Err(e) => eprintln!("Warning: Ignoring bootstrap bundle: {}", e),
+        }
+        NetworkConfig::default().seed_nodes
+    }
+    
+    /// Fetch a signed bootstrap bundle and save it once verified
+    fn update_bootstrap(&self, urls: Vec<String>) -> Result<(), String> {
+        let urls = if urls.is_empty() { self.bootstrap_urls.clone() } else { urls };
+        let path = BootstrapBundle::path_in(&self.data_dir);
+        let current = BootstrapBundle::load_from_file(&path, BOOTSTRAP_PUBLISHER_KEYS).unwrap_or_else(|e| {
+            eprintln!("Warning: Replacing invalid bootstrap bundle: {}", e);
+            None
+        });
+        
+        println!("Fetching bootstrap bundle from {} URL(s)...", urls.len());
+        let bundle = bootstrap::update_bundle(&urls, BOOTSTRAP_PUBLISHER_KEYS, current.as_ref())?;
+        bundle.save_to_file(&path)?;
+        
+        println!("✅ Bootstrap bundle version {} verified", bundle.payload.version);
+        println!("   Publisher: {}", bundle.public_key);
+        println!("   Seeds: {}", bundle.payload.seeds.len());
+        for seed in &bundle.payload.seeds {
+            println!("     {}", seed);
+        }
+        println!("   Checkpoints: {}", bundle.payload.checkpoints.len());
+        for checkpoint in &bundle.payload.checkpoints {
+            println!("     {}", checkpoint);
+        }
+        println!("Checkpoints take effect the next time the node starts");
+        
+        Ok(())
+    }
+    
     /// Show connected peers
     fn show_peers(&self) -> Result<(), String> {
         println!("\n=== Connected Peers ===");
@@ -182,8 +430,8 @@ impl NetworkCommands for CLI {
         } else {
             println!("Active peers: {}", active_peers.len());
             for (i, peer) in active_peers.iter().enumerate() {
-                println!("  {}. {} (height: {}, version: {})", 
-                    i + 1, peer.address, peer.chain_height, peer.version);
+                println!("  {}. {} (height: {}, version: {}, services: {:?})", 
+                    i + 1, peer.address, peer.chain_height, peer.version, peer.services.names());
             }
         }
         
@@ -204,8 +452,8 @@ impl NetworkCommands for CLI {
         
         // Get blockchain stats
         println!("Blockchain:");
-        println!("  Block count: {}", self.chain.blocks.len());
-        println!("  Chain height: {}", self.chain.blocks.len().saturating_sub(1));
+        println!("  Block count: {}", self.chain.block_count());
+        println!("  Chain height: {}", self.chain.height());
         
         // Get mempool stats
         let mempool_stats = self.mempool.get_stats();
@@ -221,6 +469,193 @@ impl NetworkCommands for CLI {
         println!("  Network ID: rust-chain-mainnet");
         println!("  Default ports: P2P=8333, RPC=8545");
         
+        // Traffic and resource use saved by the running node
+        let stats = match NodeStats::load_from_file(NodeStats::path_in(&self.data_dir))? {
+            Some(stats) => stats,
+            None => {
+                println!("\nNode: no stats recorded yet (start a node with start-node)");
+                return Ok(());
+            }
+        };
+        println!("\nNode (as of {}):", stats.taken_at);
+        println!("  Version: {} (protocol {})", stats.version, stats.protocol_version);
+        println!("  Uptime: {}s", stats.uptime);
+        println!("  Connections: {}", stats.connections);
+        println!("  Memory usage: {:.1} MiB", stats.memory_usage as f64 / (1024.0 * 1024.0));
+        println!("  CPU usage: {:.1}%", stats.cpu_usage);
+        println!("  Bandwidth: {} bytes in, {} bytes out", stats.totals.bytes_received, stats.totals.bytes_sent);
+        println!("  Messages: {} in, {} out", stats.totals.messages_received, stats.totals.messages_sent);
+        let compression = stats.compression;
+        match compression.ratio() {
+            Some(ratio) => println!("  Compression: {} in, {} out compressed, {:.1}% of original size, {} bytes saved",
+                compression.messages_received, compression.messages_sent, ratio * 100.0, compression.saved_bytes()),
+            None => println!("  Compression: no compressed messages yet"),
+        }
+        if !stats.peers.is_empty() {
+            println!("\n{:<24} {:>12} {:>12} {:>8} {:>8}", "Peer", "Bytes in", "Bytes out", "Msgs in", "Msgs out");
+            for (peer, counts) in &stats.peers {
+                println!("{:<24} {:>12} {:>12} {:>8} {:>8}",
+                    peer, counts.bytes_received, counts.bytes_sent, counts.messages_received, counts.messages_sent);
+            }
+        }
+        if !stats.messages.is_empty() {
+            println!("\n{:<24} {:>12} {:>12} {:>8} {:>8}", "Message", "Bytes in", "Bytes out", "Msgs in", "Msgs out");
+            for (name, counts) in &stats.messages {
+                println!("{:<24} {:>12} {:>12} {:>8} {:>8}",
+                    name, counts.bytes_received, counts.bytes_sent, counts.messages_received, counts.messages_sent);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Propagation and orphan statistics for blocks this node mined
+    fn get_propagation_stats(&self) -> Result<PropagationStats, String> {
+        let tracker = PeerStatsDb::open(&self.data_dir)?.propagation()?;
+        Ok(tracker.stats(&self.chain))
+    }
+    
+    /// Show propagation statistics and the most recent mined blocks
+    fn show_propagation_stats(&self, recent: usize) -> Result<(), String> {
+        let tracker = PeerStatsDb::open(&self.data_dir)?.propagation()?;
+        let stats = tracker.stats(&self.chain);
+        
+        println!("\n=== Block Propagation ===");
+        println!("Blocks mined: {}", stats.blocks_mined);
+        println!("Blocks relayed: {}", stats.blocks_relayed);
+        println!("Blocks acknowledged: {}", stats.blocks_acknowledged);
+        match (stats.avg_first_ack_ms, stats.max_first_ack_ms) {
+            (Some(avg), Some(max)) => println!("Time to first ack: {:.0}ms average, {}ms max", avg, max),
+            _ => println!("Time to first ack: n/a"),
+        }
+        println!("Average peers relayed to: {:.1}", stats.avg_peers_relayed);
+        println!("Orphaned: {} ({:.1}%)", stats.orphaned, stats.orphan_rate * 100.0);
+        
+        let blocks = tracker.blocks();
+        if !blocks.is_empty() && recent > 0 {
+            println!("\nRecent blocks:");
+            for block in blocks.iter().rev().take(recent) {
+                let first_ack = block.first_ack_ms()
+                    .map(|ms| format!("{}ms", ms))
+                    .unwrap_or_else(|| "-".to_string());
+                let status = if PropagationTracker::is_orphaned(block, &self.chain) { " [orphaned]" } else { "" };
+                println!("  #{} {}... relayed to {} peers ({} acked), first ack {}{}",
+                    block.height, &block.hash[..16.min(block.hash.len())], block.peers_relayed, block.peers_acked, first_ack, status);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Show the most recent alerts raised by the node
+    fn list_alerts(&self, recent: usize) -> Result<(), String> {
+        let alerts = AlertLog::open(&self.data_dir)?.alerts();
+        
+        println!("\n=== Alerts ===");
+        if alerts.is_empty() {
+            println!("No alerts raised");
+            return Ok(());
+        }
+        println!("{} alert(s) raised, showing the last {}", alerts.len(), recent.min(alerts.len()));
+        for alert in alerts.iter().rev().take(recent) {
+            println!("  [{}] {}: {}", alert.raised_at, alert.kind.name(), alert.message);
+        }
+        
+        Ok(())
+    }
+    
+    /// Show what we remember about one peer host, or a summary of every peer
+    fn show_peer_history(&self, address: Option<String>) -> Result<(), String> {
+        let peer_stats = PeerStatsDb::open(&self.data_dir)?;
+        let now = peer_stats::unix_now();
+        
+        let Some(address) = address else {
+            let records = peer_stats.all(now)?;
+            println!("\n=== Peer History ===");
+            if records.is_empty() {
+                println!("No peers recorded yet");
+                return Ok(());
+            }
+            println!("{:<40} {:>11} {:>7} {:>7} {:>8} {:>11}", "Host", "Reliability", "Conns", "Failed", "Blocks", "Misbehaved");
+            for record in &records {
+                println!("{:<40} {:>11.2} {:>7} {:>7} {:>8} {:>11}",
+                    record.host, record.reliability(now), record.connections, record.failed_connections,
+                    record.blocks_provided, record.misbehavior_total);
+            }
+            return Ok(());
+        };
+        
+        // Records are per host, so a port is accepted and ignored
+        let host = match address.parse::<PeerAddress>() {
+            Ok(peer_address) => peer_address.host(),
+            Err(_) => address.clone(),
+        };
+        let record = peer_stats.get(&host)?
+            .ok_or_else(|| format!("No history for peer {}", host))?;
+        
+        println!("\n=== Peer History: {} ===", record.host);
+        println!("First seen: {}", record.first_seen);
+        println!("Last seen: {}", record.last_seen);
+        println!("Connections: {} ({} failed)", record.connections, record.failed_connections);
+        println!("Bandwidth: {} bytes in, {} bytes out", record.bytes_received, record.bytes_sent);
+        println!("Blocks provided: {}", record.blocks_provided);
+        println!("Transactions provided: {}", record.transactions_provided);
+        println!("Reliability: {:.2}{}", record.reliability(now),
+            if record.is_avoided(now) { " (avoided for recent misbehavior)" } else { "" });
+        println!("Misbehavior: {} total", record.misbehavior_total);
+        for event in record.misbehavior.iter().rev() {
+            println!("  [{}] {}", event.at, event.reason);
+        }
+        
+        Ok(())
+    }
+    
+    /// Show the timings last written by a running node, or this process's own
+    fn show_perf_report(&self) -> Result<(), String> {
+        let report = match PerfReport::load_from_file(PerfReport::path_in(&self.data_dir))? {
+            Some(report) => {
+                println!("\n=== Performance (node report taken at {}) ===", report.taken_at);
+                report
+            },
+            None => {
+                println!("\n=== Performance (this process, no node report found) ===");
+                perf::report()
+            },
+        };
+        println!("Recording since: {}", report.since);
+        
+        if report.areas.is_empty() {
+            println!("Nothing timed yet");
+        } else {
+            println!("{:<18} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}", "area", "count", "mean", "p50", "p90", "p99", "max");
+            for stats in &report.areas {
+                println!("{:<18} {:>10} {:>8}us {:>8}us {:>8}us {:>8}us {:>8}us",
+                    stats.area, stats.count, stats.mean_micros, stats.p50_micros, stats.p90_micros, stats.p99_micros, stats.max_micros);
+            }
+        }
+        
+        let costs = BlockCostLog::load_from_file(BlockCostLog::path_in(&self.data_dir))?
+            .unwrap_or_else(perf::blocks::block_costs);
+        if costs.is_empty() {
+            return Ok(());
+        }
+        let summary = costs.summary();
+        println!("\n=== Block Costs (last {} connected blocks) ===", summary.blocks);
+        println!("{:<18} {:>10} {:>10} {:>10} {:>10}", "measure", "p50", "p95", "p99", "max");
+        for (measure, percentiles, unit) in [
+            ("validation", &summary.validation_micros, "us"),
+            ("persistence", &summary.persistence_micros, "us"),
+            ("transactions", &summary.transactions, ""),
+        ] {
+            println!("{:<18} {:>8}{:<2} {:>8}{:<2} {:>8}{:<2} {:>8}{:<2}", measure,
+                percentiles.p50, unit, percentiles.p95, unit, percentiles.p99, unit, percentiles.max, unit);
+        }
+        println!("Slowest blocks:");
+        for cost in costs.slowest(SLOWEST_BLOCKS_SHOWN) {
+            println!("  #{} {} {} txs, validation {}us, persistence {}us",
+                cost.height, cost.hash, cost.transactions, cost.validation_micros, cost.persistence_micros);
+        }
+        
         Ok(())
     }
 }
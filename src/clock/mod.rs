@@ -0,0 +1,95 @@
+//! Source of the current time
+//!
+//! Block timestamps, the future-timestamp rule, mempool expiry and peer
+//! staleness read the time from a `Clock` rather than from the system, so that
+//! tests and regtest nodes can control it. `SystemClock` is the default.
+//! `MockClock` follows the system clock until it is set, then stays at the set
+//! time until it is set or advanced again. A node started with `--regtest` runs
+//! on a mock clock, which the `setmocktime` RPC method sets.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Seconds since the Unix epoch
+    fn now(&self) -> u64;
+}
+
+/// Clock shared by the components of one node
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+}
+
+/// Clock set by hand. Clones share the time, so a clock handed to the chain
+/// and the mempool can be moved by whoever kept a clone.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    /// Mock time, 0 while the clock follows the system clock
+    time: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// A clock stopped at `time`, or following the system clock if `time` is 0
+    pub fn new(time: u64) -> Self {
+        MockClock { time: Arc::new(AtomicU64::new(time)) }
+    }
+
+    /// Stop the clock at `time`; 0 makes it follow the system clock again
+    pub fn set(&self, time: u64) {
+        self.time.store(time, Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by `secs`, stopping it at the current time first
+    /// if it follows the system clock
+    pub fn advance(&self, secs: u64) {
+        let now = self.now();
+        self.set(now.saturating_add(secs));
+    }
+
+    /// The time the clock is stopped at, or None while it follows the system clock
+    pub fn mock_time(&self) -> Option<u64> {
+        Some(self.time.load(Ordering::SeqCst)).filter(|&time| time != 0)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u64 {
+        self.mock_time().unwrap_or_else(|| SystemClock.now())
+    }
+}
+
+/// The system clock, shared
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::default();
+        assert_eq!(clock.mock_time(), None);
+        assert!(clock.now() >= SystemClock.now() - 1);
+
+        let shared = clock.clone();
+        clock.set(1_000);
+        assert_eq!(shared.now(), 1_000);
+        shared.advance(60);
+        assert_eq!(clock.now(), 1_060);
+
+        clock.set(0);
+        assert_eq!(clock.mock_time(), None);
+    }
+}
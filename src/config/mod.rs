@@ -0,0 +1,230 @@
+pub mod watcher;
+
+pub use watcher::{ConfigWatcher, ConfigReload, CONFIG_POLL_INTERVAL};
+
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::blockchain::genesis::check_payout_address;
+use crate::mempool::policy::RelayPolicy;
+use crate::mempool::pool::check_limits;
+
+/// Settings a running `start-rpc` server picks up when the config file changes
+pub const RPC_RELOADABLE_SETTINGS: &[&str] = &[
+    "access_log",
+    "slow_request_ms",
+    "rpc_timeout_secs",
+    "rpc_max_inflight",
+    "mempool_max_size",
+    "mempool_max_age_secs",
+    "min_relay_fee",
+    "max_tx_size",
+    "dust_threshold",
+];
+
+/// Settings a running `start-node` node picks up when the config file changes
+pub const NODE_RELOADABLE_SETTINGS: &[&str] = &[
+    "max_peers",
+    "mempool_max_size",
+    "mempool_max_age_secs",
+    "min_relay_fee",
+    "max_tx_size",
+    "dust_threshold",
+    "sync_threshold",
+    "outbound_target",
+];
+
+/// Node settings read from a JSON config file (`--conf=<path>`).
+///
+/// Every setting is optional; a missing one keeps the command-line or built-in
+/// value. Settings not listed in `RPC_RELOADABLE_SETTINGS` or
+/// `NODE_RELOADABLE_SETTINGS` only take effect on restart.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Log one line per RPC request
+    pub access_log: Option<bool>,
+    /// RPC requests at least this slow are logged
+    pub slow_request_ms: Option<u64>,
+    pub rpc_timeout_secs: Option<u64>,
+    /// RPC requests allowed in flight at once
+    pub rpc_max_inflight: Option<usize>,
+    pub max_peers: Option<usize>,
+    pub mempool_max_size: Option<usize>,
+    pub mempool_max_age_secs: Option<u64>,
+    pub min_relay_fee: Option<f64>,
+    pub max_tx_size: Option<usize>,
+    pub dust_threshold: Option<u64>,
+    /// Blocks a peer may be ahead of us before the node syncs on its own
+    pub sync_threshold: Option<u64>,
+    /// Outbound connections the node keeps open on its own
+    pub outbound_target: Option<usize>,
+    pub blocks_only: Option<bool>,
+    pub txindex: Option<bool>,
+    pub addressindex: Option<bool>,
+    pub block_cache_bytes: Option<usize>,
+    pub header_cache_bytes: Option<usize>,
+    pub finality_depth: Option<u64>,
+    /// Where `update-bootstrap` fetches signed bootstrap bundles from
+    pub bootstrap_urls: Option<Vec<String>>,
+    pub mining: Option<MiningConfig>,
+}
+
+/// The `mining` section of a config file
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningConfig {
+    /// Address the coinbase of mined blocks pays
+    pub payout_address: Option<String>,
+}
+
+/// Settings that differ between two versions of the config file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigChanges {
+    /// Changed settings that were applied to the running node
+    pub applied: Vec<String>,
+    /// Changed settings that need a restart to take effect, including reloadable
+    /// ones that were removed from the file
+    pub restart_required: Vec<String>,
+    /// Changed settings the running service does not use
+    pub ignored: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.restart_required.is_empty() && self.ignored.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no settings changed");
+        }
+        let parts = [
+            ("applied", &self.applied),
+            ("restart required for", &self.restart_required),
+            ("not used by this service:", &self.ignored),
+        ];
+        let parts: Vec<String> = parts.iter()
+            .filter(|(_, settings)| !settings.is_empty())
+            .map(|(label, settings)| format!("{} {}", label, settings.join(", ")))
+            .collect();
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+impl NodeConfig {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let data = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.as_ref().display(), e))?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let config: NodeConfig = serde_json::from_str(data).map_err(|e| format!("Invalid config: {}", e))?;
+        check_limits(config.mempool_max_size, config.mempool_max_age_secs)
+            .map_err(|e| format!("Invalid config: {}", e))?;
+        if let Some(address) = config.payout_address() {
+            check_payout_address(address).map_err(|e| format!("Invalid config: {}", e))?;
+        }
+        Ok(config)
+    }
+
+    /// `mining.payout_address`, when set
+    pub fn payout_address(&self) -> Option<&str> {
+        self.mining.as_ref()?.payout_address.as_deref()
+    }
+
+    /// Settings that changed from `self` to `new`, split by whether a service that
+    /// reloads `reloadable` applies them live. A reloadable setting removed from
+    /// the file keeps its current value, so it needs a restart too.
+    pub fn changes(&self, new: &NodeConfig, reloadable: &[&str]) -> ConfigChanges {
+        let old = serde_json::to_value(self).unwrap_or_default();
+        let new = serde_json::to_value(new).unwrap_or_default();
+
+        let mut changes = ConfigChanges::default();
+        if let (Some(old), Some(new)) = (old.as_object(), new.as_object()) {
+            for (key, value) in new {
+                if old.get(key) == Some(value) {
+                    continue;
+                }
+                let key_str = key.as_str();
+                if reloadable.contains(&key_str) && !value.is_null() {
+                    changes.applied.push(key.clone());
+                } else if reloadable.contains(&key_str)
+                    || !(RPC_RELOADABLE_SETTINGS.contains(&key_str) || NODE_RELOADABLE_SETTINGS.contains(&key_str))
+                {
+                    changes.restart_required.push(key.clone());
+                } else {
+                    changes.ignored.push(key.clone());
+                }
+            }
+        }
+        changes
+    }
+
+    /// The relay policy with this config's fee, size and dust settings applied.
+    /// Blocks-only mode changes the advertised services, so it is left alone.
+    pub fn relay_policy(&self, base: &RelayPolicy) -> RelayPolicy {
+        RelayPolicy {
+            min_relay_fee: self.min_relay_fee.unwrap_or(base.min_relay_fee),
+            max_tx_size: self.max_tx_size.unwrap_or(base.max_tx_size),
+            dust_threshold: self.dust_threshold.unwrap_or(base.dust_threshold),
+            blocks_only: base.blocks_only,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_split_by_reloadability() {
+        let old = NodeConfig::parse(r#"{ "max_peers": 10, "txindex": true }"#).unwrap();
+        let new = NodeConfig::parse(r#"{ "max_peers": 20, "txindex": false, "min_relay_fee": 1.5 }"#).unwrap();
+
+        let changes = old.changes(&new, NODE_RELOADABLE_SETTINGS);
+        assert_eq!(changes.applied, vec!["max_peers", "min_relay_fee"]);
+        assert_eq!(changes.restart_required, vec!["txindex"]);
+        assert_eq!(changes.to_string(), "applied max_peers, min_relay_fee; restart required for txindex");
+        assert!(new.changes(&new, NODE_RELOADABLE_SETTINGS).is_empty());
+
+        // The RPC server has no use for peer limits
+        let changes = old.changes(&new, RPC_RELOADABLE_SETTINGS);
+        assert_eq!(changes.applied, vec!["min_relay_fee"]);
+        assert_eq!(changes.ignored, vec!["max_peers"]);
+        assert_eq!(
+            changes.to_string(),
+            "applied min_relay_fee; restart required for txindex; not used by this service: max_peers"
+        );
+
+        // Removing a setting leaves the running value alone
+        let cleared = NodeConfig::parse(r#"{ "txindex": true }"#).unwrap();
+        let changes = old.changes(&cleared, NODE_RELOADABLE_SETTINGS);
+        assert!(changes.applied.is_empty());
+        assert_eq!(changes.restart_required, vec!["max_peers"]);
+
+        assert!(NodeConfig::parse(r#"{ "max_peer": 10 }"#).is_err());
+        assert!(NodeConfig::parse(r#"{ "mempool_max_size": 0 }"#).is_err());
+
+        let mining = NodeConfig::parse(r#"{ "mining": { "payout_address": "1MinerPayout" } }"#).unwrap();
+        assert_eq!(mining.payout_address(), Some("1MinerPayout"));
+        assert_eq!(NodeConfig::default().changes(&mining, NODE_RELOADABLE_SETTINGS).restart_required, vec!["mining"]);
+        assert!(NodeConfig::parse(r#"{ "mining": { "payout_address": "not an address" } }"#).is_err());
+        assert!(NodeConfig::parse(r#"{ "mining": { "payout_address": "0000000000000000000000000000000000000000" } }"#).is_err());
+        assert!(NodeConfig::parse(r#"{ "mining": { "payout": "1MinerPayout" } }"#).is_err());
+    }
+
+    #[test]
+    fn test_relay_policy_overrides() {
+        let config = NodeConfig::parse(r#"{ "dust_threshold": 50, "blocks_only": true }"#).unwrap();
+        let policy = config.relay_policy(&RelayPolicy::default());
+        assert_eq!(policy.dust_threshold, 50);
+        assert_eq!(policy.max_tx_size, RelayPolicy::default().max_tx_size);
+        assert!(!policy.blocks_only);
+    }
+}
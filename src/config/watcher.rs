@@ -0,0 +1,100 @@
+//! Config file watching for hot reload
+//!
+//! The watcher re-reads the config file whenever its contents change and
+//! reports which settings moved. Applying them is up to the caller, since each
+//! service (RPC server, network node) owns different parts of the config.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{ConfigChanges, NodeConfig};
+
+/// How often running services check the config file for changes
+pub const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A newly loaded config and what changed compared to the previous one
+#[derive(Debug, Clone)]
+pub struct ConfigReload {
+    pub config: NodeConfig,
+    pub changes: ConfigChanges,
+}
+
+/// Watches a config file for changes
+pub struct ConfigWatcher {
+    path: PathBuf,
+    contents: String,
+    current: NodeConfig,
+    reloadable: &'static [&'static str],
+}
+
+impl ConfigWatcher {
+    /// Load the config file and start watching it for a service that applies the
+    /// `reloadable` settings while it runs
+    pub fn new<P: AsRef<Path>>(path: P, reloadable: &'static [&'static str]) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let current = NodeConfig::parse(&contents)?;
+
+        Ok(ConfigWatcher { path, contents, current, reloadable })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The config currently in effect
+    pub fn current(&self) -> &NodeConfig {
+        &self.current
+    }
+
+    /// Re-read the file. Returns `None` while it is unchanged. An unreadable or
+    /// invalid file is reported once and the previous config stays in effect.
+    pub fn poll(&mut self) -> Option<Result<ConfigReload, String>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) => return Some(Err(format!("Failed to read config {}: {}", self.path.display(), e))),
+        };
+        if contents == self.contents {
+            return None;
+        }
+        self.contents = contents;
+
+        Some(NodeConfig::parse(&self.contents).map(|config| {
+            let changes = self.current.changes(&config, self.reloadable);
+            self.current = config.clone();
+            ConfigReload { config, changes }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NODE_RELOADABLE_SETTINGS;
+
+    #[test]
+    fn test_poll_reports_changes_once() {
+        let path = std::env::temp_dir().join(format!("rust_chain_config_{}.json", std::process::id()));
+        fs::write(&path, r#"{ "max_peers": 8 }"#).unwrap();
+
+        let mut watcher = ConfigWatcher::new(&path, NODE_RELOADABLE_SETTINGS).unwrap();
+        assert_eq!(watcher.current().max_peers, Some(8));
+        assert!(watcher.poll().is_none());
+
+        fs::write(&path, r#"{ "max_peers": 12, "txindex": false }"#).unwrap();
+        let reload = watcher.poll().unwrap().unwrap();
+        assert_eq!(reload.changes.applied, vec!["max_peers"]);
+        assert_eq!(reload.changes.restart_required, vec!["txindex"]);
+        assert!(watcher.poll().is_none());
+
+        // A broken file keeps the previous config
+        fs::write(&path, "{ not json").unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+        assert!(watcher.poll().is_none());
+        assert_eq!(watcher.current().max_peers, Some(12));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
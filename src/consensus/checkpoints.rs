@@ -0,0 +1,176 @@
+use crate::blockchain::block::Block;
+use crate::blockchain::chain::Chain;
+use std::collections::BTreeMap;
+
+/// Hardcoded checkpoints: (height, block hash) pairs known to be on the main chain
+pub const MAINNET_CHECKPOINTS: &[(u64, &str)] = &[
+    (0, "4715390542899cd064d5008100fa39ab7939d13499784f806f158ab70ac5fb87"),
+];
+
+/// Known block hashes at specific heights that are treated as final
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoints {
+    checkpoints: BTreeMap<u64, String>,
+}
+
+impl Checkpoints {
+    /// Create an empty checkpoint set
+    pub fn new() -> Self {
+        Checkpoints {
+            checkpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Create the hardcoded mainnet checkpoint set
+    pub fn mainnet() -> Self {
+        let mut checkpoints = Self::new();
+        for (height, hash) in MAINNET_CHECKPOINTS {
+            checkpoints.add(*height, hash.to_string());
+        }
+        checkpoints
+    }
+
+    /// Add (or replace) a checkpoint
+    pub fn add(&mut self, height: u64, hash: String) {
+        self.checkpoints.insert(height, hash);
+    }
+
+    /// Parse a config-supplied checkpoint in `<height>:<hash>` form
+    pub fn parse_checkpoint(value: &str) -> Result<(u64, String), String> {
+        let (height, hash) = value.split_once(':')
+            .ok_or_else(|| format!("Invalid checkpoint '{}', expected <height>:<hash>", value))?;
+
+        let height = height.parse::<u64>()
+            .map_err(|_| format!("Invalid checkpoint height '{}'", height))?;
+
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid checkpoint hash '{}'", hash));
+        }
+
+        Ok((height, hash.to_lowercase()))
+    }
+
+    /// Get the checkpoint hash at a height, if any
+    pub fn get(&self, height: u64) -> Option<&String> {
+        self.checkpoints.get(&height)
+    }
+
+    /// Check that a block does not contradict a checkpoint
+    pub fn check_block(&self, block: &Block) -> bool {
+        match self.checkpoints.get(&block.header.height) {
+            Some(hash) => *hash == block.header.hash,
+            None => true,
+        }
+    }
+
+    /// Get the highest checkpoint
+    pub fn last_checkpoint(&self) -> Option<(u64, &String)> {
+        self.checkpoints.iter().next_back().map(|(height, hash)| (*height, hash))
+    }
+
+    /// Get the height of the highest checkpoint
+    pub fn last_checkpoint_height(&self) -> Option<u64> {
+        self.last_checkpoint().map(|(height, _)| height)
+    }
+
+    /// A fork starting at `fork_height` is only allowed above the last checkpoint the chain has passed
+    pub fn allows_fork_at(&self, fork_height: u64, chain_height: u64) -> bool {
+        match self.checkpoints.range(..=chain_height).next_back() {
+            Some((checkpoint_height, _)) => fork_height > *checkpoint_height,
+            None => true,
+        }
+    }
+
+    /// Number of checkpoints
+    pub fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Check if there are no checkpoints
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    /// Report how a chain relates to the checkpoints
+    pub fn status(&self, chain: &Chain) -> CheckpointStatus {
+        let chain_height = chain.height();
+        let mut verified = 0;
+        let mut mismatches = Vec::new();
+
+        for (height, hash) in &self.checkpoints {
+            // Checkpoints are usually older than the blocks held in memory
+            if let Ok(Some(block_hash)) = chain.block_hash_at(*height) {
+                if block_hash == *hash {
+                    verified += 1;
+                } else {
+                    mismatches.push(*height);
+                }
+            }
+        }
+
+        CheckpointStatus {
+            total_checkpoints: self.checkpoints.len(),
+            verified_checkpoints: verified,
+            last_checkpoint_height: self.last_checkpoint_height(),
+            chain_height,
+            past_last_checkpoint: self.last_checkpoint_height()
+                .map(|height| chain_height >= height)
+                .unwrap_or(true),
+            mismatches,
+        }
+    }
+}
+
+/// Checkpoint verification status for a chain
+#[derive(Debug, Clone)]
+pub struct CheckpointStatus {
+    pub total_checkpoints: usize,
+    pub verified_checkpoints: usize,
+    pub last_checkpoint_height: Option<u64>,
+    pub chain_height: u64,
+    pub past_last_checkpoint: bool,
+    pub mismatches: Vec<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::genesis::genesis_block;
+
+    #[test]
+    fn test_mainnet_genesis_checkpoint() {
+        let checkpoints = Checkpoints::mainnet();
+        assert!(checkpoints.check_block(&genesis_block()));
+        assert_eq!(checkpoints.status(&Chain::new()).verified_checkpoints, 1);
+    }
+
+    #[test]
+    fn test_checkpoint_rejects_conflicting_block() {
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.add(1, "ab".repeat(32));
+
+        let block = Block::new("prev".to_string(), vec![], 0, 0, 1);
+        assert!(!checkpoints.check_block(&block));
+    }
+
+    #[test]
+    fn test_fork_below_checkpoint_not_allowed() {
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.add(10, "ab".repeat(32));
+
+        // Chain has not reached the checkpoint yet, forks are fine
+        assert!(checkpoints.allows_fork_at(5, 8));
+        // Chain passed the checkpoint, forks at or below it are not
+        assert!(!checkpoints.allows_fork_at(10, 12));
+        assert!(checkpoints.allows_fork_at(11, 12));
+    }
+
+    #[test]
+    fn test_parse_checkpoint() {
+        let (height, hash) = Checkpoints::parse_checkpoint(&format!("42:{}", "AB".repeat(32))).unwrap();
+        assert_eq!(height, 42);
+        assert_eq!(hash, "ab".repeat(32));
+        assert!(Checkpoints::parse_checkpoint("42").is_err());
+        assert!(Checkpoints::parse_checkpoint("x:abcd").is_err());
+    }
+}
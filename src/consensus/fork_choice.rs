@@ -1,13 +1,49 @@
 use crate::blockchain::block::Block;
 use crate::blockchain::chain::Chain;
-use std::collections::HashMap;
+use crate::blockchain::events::{ChainEvent, EventBus};
+use crate::consensus::checkpoints::Checkpoints;
+use crate::status;
+use std::collections::{HashMap, HashSet};
+
+/// Default number of blocks after which a block is considered final
+pub const DEFAULT_FINALITY_DEPTH: u64 = 6;
+
+/// A block is final once at least `finality_depth` blocks have been built on top of it
+pub fn is_final(block_height: u64, tip_height: u64, finality_depth: u64) -> bool {
+    block_height <= tip_height && tip_height - block_height >= finality_depth
+}
+
+/// Refuse a reorganization whose lowest replaced block, at `fork_height`, is at
+/// or below the last checkpoint a chain at `tip_height` passed, or is final
+pub fn check_reorg_allowed(checkpoints: &Checkpoints, finality_depth: u64, fork_height: u64, tip_height: u64) -> Result<(), String> {
+    if !checkpoints.allows_fork_at(fork_height, tip_height) {
+        return Err(format!("Fork at height {} is below the last checkpoint", fork_height));
+    }
+    
+    if is_final(fork_height, tip_height, finality_depth) {
+        eprintln!(
+            "ALERT: refusing reorganization at height {} past final blocks (tip height {}, finality depth {})",
+            fork_height, tip_height, finality_depth
+        );
+        return Err(format!("Fork at height {} would reorganize final blocks", fork_height));
+    }
+    
+    Ok(())
+}
 
 /// Fork choice implementation using longest chain rule
+#[derive(Clone)]
 pub struct ForkChoice {
     /// All known chains by their tip hash
     chains: HashMap<String, Chain>,
     /// Current best chain hash
     best_chain_hash: Option<String>,
+    /// Checkpoints treated as final when choosing between forks
+    checkpoints: Checkpoints,
+    /// Blocks this deep in the best chain are never reorganized away
+    finality_depth: u64,
+    /// Bus that switches of the best chain are published on
+    events: Option<EventBus>,
 }
 
 impl ForkChoice {
@@ -16,13 +52,44 @@ impl ForkChoice {
         ForkChoice {
             chains: HashMap::new(),
             best_chain_hash: None,
+            checkpoints: Checkpoints::new(),
+            finality_depth: DEFAULT_FINALITY_DEPTH,
+            events: None,
         }
     }
     
+    /// Use the given checkpoints for fork choice
+    pub fn with_checkpoints(mut self, checkpoints: Checkpoints) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+    
+    /// Get the checkpoints used for fork choice
+    pub fn checkpoints(&self) -> &Checkpoints {
+        &self.checkpoints
+    }
+    
+    /// Set how deep a block must be before it can no longer be reorganized away
+    pub fn with_finality_depth(mut self, finality_depth: u64) -> Self {
+        self.finality_depth = finality_depth;
+        self
+    }
+    
+    /// Get the finality depth
+    pub fn finality_depth(&self) -> u64 {
+        self.finality_depth
+    }
+    
+    /// Publish a `ChainEvent::Reorg` whenever the best chain moves to another branch
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+    
     /// Initialize with genesis chain
     pub fn with_genesis_chain(chain: Chain) -> Self {
         let mut fork_choice = Self::new();
-        if let Some(tip) = chain.blocks.last() {
+        if let Some(tip) = chain.tip() {
             let tip_hash = tip.header.hash.clone();
             fork_choice.chains.insert(tip_hash.clone(), chain);
             fork_choice.best_chain_hash = Some(tip_hash);
@@ -35,6 +102,10 @@ impl ForkChoice {
         let block_hash = block.header.hash.clone();
         let parent_hash = block.header.previous_hash.clone();
         
+        if !self.checkpoints.check_block(&block) {
+            return Err(format!("Block {} conflicts with checkpoint at height {}", block_hash, block.header.height));
+        }
+        
         // Special handling for genesis blocks (parent hash is "0")
         if parent_hash == "0" {
             let chain = Chain::from_blocks(vec![block]);
@@ -54,6 +125,8 @@ impl ForkChoice {
         
         match parent_chain {
             Some(mut chain) => {
+                self.check_fork_allowed(&chain, &block)?;
+                
                 // Validate the block against the parent chain
                 if !chain.validate_block(&block) {
                     return Err(format!("Invalid block: {}", block_hash));
@@ -73,8 +146,10 @@ impl ForkChoice {
                     self.chains.remove(&parent_hash);
                 }
                 
-                if is_new_best {
-                    self.best_chain_hash = Some(block_hash);
+                if is_new_best
+                    && let Some(old_tip) = self.best_chain_hash.replace(block_hash.clone())
+                    && old_tip != parent_hash {
+                    self.publish_reorg(&old_tip, &block_hash);
                 }
                 
                 Ok(is_new_best)
@@ -85,6 +160,25 @@ impl ForkChoice {
         }
     }
     
+    /// Announce a switch of the best chain from `old_tip` to `new_tip`
+    fn publish_reorg(&self, old_tip: &str, new_tip: &str) {
+        let (Some(events), Some(old_chain), Some(new_chain)) =
+            (&self.events, self.chains.get(old_tip), self.chains.get(new_tip)) else {
+            return;
+        };
+        
+        // Blocks of the old branch above the last block both chains share
+        let new_hashes: HashSet<&str> = new_chain.get_blocks().iter().map(|b| b.header.hash.as_str()).collect();
+        let depth = old_chain.get_blocks().iter().rev()
+            .take_while(|b| !new_hashes.contains(b.header.hash.as_str()))
+            .count();
+        events.publish(ChainEvent::Reorg {
+            old_tip: old_tip.to_string(),
+            new_tip: new_tip.to_string(),
+            depth: depth as u64,
+        });
+    }
+    
     /// Get the current best chain
     pub fn get_best_chain(&self) -> Option<&Chain> {
         match &self.best_chain_hash {
@@ -117,7 +211,7 @@ impl ForkChoice {
     /// Check if we have a specific block
     pub fn has_block(&self, block_hash: &str) -> bool {
         for chain in self.chains.values() {
-            if chain.blocks.iter().any(|b| b.header.hash == block_hash) {
+            if let Ok(Some(_)) = chain.find_block(block_hash) {
                 return true;
             }
         }
@@ -125,13 +219,9 @@ impl ForkChoice {
     }
     
     /// Get a specific block by hash
-    pub fn get_block(&self, block_hash: &str) -> Option<&Block> {
-        for chain in self.chains.values() {
-            if let Some(block) = chain.blocks.iter().find(|b| b.header.hash == block_hash) {
-                return Some(block);
-            }
-        }
-        None
+    pub fn get_block(&self, block_hash: &str) -> Option<Block> {
+        self.chains.values()
+            .find_map(|chain| chain.find_block(block_hash).ok().flatten())
     }
     
     /// Handle chain reorganization
@@ -143,7 +233,7 @@ impl ForkChoice {
                 Ok(is_new_best) => {
                     if is_new_best {
                         reorg_occurred = true;
-                        println!("Chain reorganization occurred!");
+                        status!("Chain reorganization occurred!");
                     }
                 },
                 Err(e) => {
@@ -159,18 +249,18 @@ impl ForkChoice {
     pub fn get_chain_stats(&self) -> ForkChoiceStats {
         let total_chains = self.chains.len();
         let best_height = self.get_best_chain()
-            .map(|c| c.blocks.len() as u64 - 1)
+            .map(|c| c.height())
             .unwrap_or(0);
         
         let mut max_height = 0;
         let mut total_blocks = 0;
         
         for chain in self.chains.values() {
-            let height = chain.blocks.len() as u64 - 1;
+            let height = chain.height();
             if height > max_height {
                 max_height = height;
             }
-            total_blocks += chain.blocks.len();
+            total_blocks += chain.block_count() as usize;
         }
         
         ForkChoiceStats {
@@ -182,6 +272,87 @@ impl ForkChoice {
         }
     }
     
+    /// Every known tip and where its branch leaves the best chain, best chain first,
+    /// then the other branches from the highest fork point down
+    pub fn fork_tree(&self) -> ForkTree {
+        let Some(best) = self.get_best_chain() else {
+            return ForkTree { branches: Vec::new() };
+        };
+        let genesis_hash = best.block_at(0).ok().flatten()
+            .map(|b| b.header.hash)
+            .unwrap_or_default();
+        let mut branches = vec![ForkBranch {
+            tip_hash: best.tip().map(|b| b.header.hash.clone()).unwrap_or_default(),
+            tip_height: best.height(),
+            is_best: true,
+            fork_height: 0,
+            fork_hash: genesis_hash,
+            length: best.height(),
+            branch_work: best.get_blocks().iter().skip(1).fold(0u128, |work, b| work.saturating_add(b.work())),
+            chain_work: best.chain_work(),
+        }];
+        
+        let mut others: Vec<ForkBranch> = self.chains.iter()
+            .filter(|(tip_hash, _)| Some(*tip_hash) != self.best_chain_hash.as_ref())
+            .map(|(tip_hash, chain)| {
+                let (fork_height, fork_hash) = Self::common_ancestor(best, chain);
+                let above_fork: Vec<&Block> = chain.get_blocks().iter()
+                    .filter(|b| b.header.height > fork_height)
+                    .collect();
+                ForkBranch {
+                    tip_hash: tip_hash.clone(),
+                    tip_height: chain.height(),
+                    is_best: false,
+                    fork_height,
+                    fork_hash,
+                    length: chain.height().saturating_sub(fork_height),
+                    branch_work: above_fork.iter().fold(0u128, |work, b| work.saturating_add(b.work())),
+                    chain_work: chain.chain_work(),
+                }
+            })
+            .collect();
+        others.sort_by(|a, b| b.fork_height.cmp(&a.fork_height).then_with(|| a.tip_hash.cmp(&b.tip_hash)));
+        branches.extend(others);
+        ForkTree { branches }
+    }
+    
+    /// Height and hash of the highest block two chains share
+    fn common_ancestor(a: &Chain, b: &Chain) -> (u64, String) {
+        let mut height = a.height().min(b.height());
+        loop {
+            let hash_a = a.block_at(height).ok().flatten().map(|block| block.header.hash);
+            let hash_b = b.block_at(height).ok().flatten().map(|block| block.header.hash);
+            if let (Some(hash_a), Some(hash_b)) = (&hash_a, &hash_b)
+                && hash_a == hash_b {
+                return (height, hash_a.clone());
+            }
+            if height == 0 {
+                return (0, String::new());
+            }
+            height -= 1;
+        }
+    }
+    
+    /// Refuse blocks whose branch forks off the best chain below a passed checkpoint
+    /// or a final block. `parent_chain` is the branch the block extends.
+    fn check_fork_allowed(&self, parent_chain: &Chain, block: &Block) -> Result<(), String> {
+        let best = match self.get_best_chain() {
+            Some(best) => best,
+            None => return Ok(()),
+        };
+        
+        if best.tip().map(|b| &b.header.hash) == Some(&block.header.previous_hash) {
+            return Ok(());
+        }
+        
+        // The lowest block of the best chain the branch would replace
+        let fork_height = match Self::common_ancestor(parent_chain, best) {
+            (_, hash) if hash.is_empty() => 0,
+            (height, _) => height + 1,
+        };
+        check_reorg_allowed(&self.checkpoints, self.finality_depth, fork_height, best.height())
+    }
+    
     /// Find chain that ends with the given block hash
     fn find_chain_with_tip(&self, tip_hash: &str) -> Option<Chain> {
         // First check if we have a chain ending with this hash
@@ -189,22 +360,8 @@ impl ForkChoice {
             return Some(chain.clone());
         }
         
-        // If not, look for a chain that contains this block
-        for chain in self.chains.values() {
-            if chain.blocks.iter().any(|b| b.header.hash == tip_hash) {
-                // Create a new chain up to this block
-                let mut new_chain_blocks = Vec::new();
-                for block in &chain.blocks {
-                    new_chain_blocks.push(block.clone());
-                    if block.header.hash == tip_hash {
-                        break;
-                    }
-                }
-                return Some(Chain::from_blocks(new_chain_blocks));
-            }
-        }
-        
-        None
+        // If not, branch off a chain that contains this block
+        self.chains.values().find_map(|chain| chain.branch_at(tip_hash))
     }
     
     /// Determine if a chain is better than the current best chain
@@ -212,8 +369,8 @@ impl ForkChoice {
         match self.get_best_chain() {
             Some(current_best) => {
                 // Longest chain rule: more blocks wins
-                let new_height = chain.blocks.len();
-                let current_height = current_best.blocks.len();
+                let new_height = chain.height();
+                let current_height = current_best.height();
                 
                 if new_height > current_height {
                     return true;
@@ -222,7 +379,7 @@ impl ForkChoice {
                 // If same length, use most work (sum of difficulty)
                 if new_height == current_height {
                     // For now, just use the newer timestamp as tiebreaker
-                    if let (Some(new_tip), Some(current_tip)) = (chain.blocks.last(), current_best.blocks.last()) {
+                    if let (Some(new_tip), Some(current_tip)) = (chain.tip(), current_best.tip()) {
                         return new_tip.header.timestamp > current_tip.header.timestamp;
                     }
                 }
@@ -250,6 +407,160 @@ pub struct ForkChoiceStats {
     pub has_forks: bool,
 }
 
+/// A known tip and how its branch splits from the best chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForkBranch {
+    pub tip_hash: String,
+    pub tip_height: u64,
+    pub is_best: bool,
+    /// Last block the branch shares with the best chain; genesis for the best chain itself
+    pub fork_height: u64,
+    pub fork_hash: String,
+    /// Blocks above the fork point
+    pub length: u64,
+    /// Work of the blocks above the fork point
+    pub branch_work: u128,
+    /// Work of the whole chain up to the tip
+    pub chain_work: u128,
+}
+
+/// Known tips of the fork choice, for display and export
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForkTree {
+    /// The best chain first
+    pub branches: Vec<ForkBranch>,
+}
+
+impl ForkTree {
+    /// Tree of `chain` and the branches its block store kept, as the node that
+    /// wrote the store saw them: each stored block off the chain that no other
+    /// one builds on is the tip of a branch, followed down to the chain
+    pub fn from_stored_branches(chain: &Chain) -> Result<ForkTree, String> {
+        let Some(tip) = chain.tip() else {
+            return Ok(ForkTree { branches: Vec::new() });
+        };
+        let genesis = chain.block_at(0)?;
+        let genesis_work = genesis.as_ref().map(|b| b.work()).unwrap_or_default();
+        let mut branches = vec![ForkBranch {
+            tip_hash: tip.header.hash.clone(),
+            tip_height: chain.height(),
+            is_best: true,
+            fork_height: 0,
+            fork_hash: genesis.map(|b| b.header.hash).unwrap_or_default(),
+            length: chain.height(),
+            branch_work: chain.chain_work().saturating_sub(genesis_work),
+            chain_work: chain.chain_work(),
+        }];
+        
+        let side: HashMap<String, Block> = chain.stored_side_blocks()?.into_iter()
+            .map(|block| (block.header.hash.clone(), block))
+            .collect();
+        let parents: HashSet<&str> = side.values().map(|b| b.header.previous_hash.as_str()).collect();
+        let mut others = Vec::new();
+        for branch_tip in side.values().filter(|b| !parents.contains(b.header.hash.as_str())) {
+            let mut lowest = branch_tip;
+            let mut branch_work = 0u128;
+            loop {
+                branch_work = branch_work.saturating_add(lowest.work());
+                match side.get(&lowest.header.previous_hash) {
+                    Some(parent) => lowest = parent,
+                    None => break,
+                }
+            }
+            let fork_height = lowest.header.height.saturating_sub(1);
+            // Work of the chain up to the fork point
+            let mut above_fork = 0u128;
+            chain.scan_blocks(fork_height + 1, chain.height(), |b| above_fork = above_fork.saturating_add(b.work()))?;
+            others.push(ForkBranch {
+                tip_hash: branch_tip.header.hash.clone(),
+                tip_height: branch_tip.header.height,
+                is_best: false,
+                fork_height,
+                fork_hash: lowest.header.previous_hash.clone(),
+                length: branch_tip.header.height - fork_height,
+                branch_work,
+                chain_work: chain.chain_work().saturating_sub(above_fork).saturating_add(branch_work),
+            });
+        }
+        others.sort_by(|a, b| b.fork_height.cmp(&a.fork_height).then_with(|| a.tip_hash.cmp(&b.tip_hash)));
+        branches.extend(others);
+        Ok(ForkTree { branches })
+    }
+    
+    pub fn best(&self) -> Option<&ForkBranch> {
+        self.branches.first()
+    }
+    
+    /// JSON form, with work as hex like the `chainwork` RPC field
+    pub fn to_json(&self) -> serde_json::Value {
+        let branches: Vec<serde_json::Value> = self.branches.iter().map(|branch| serde_json::json!({
+            "tip": branch.tip_hash,
+            "height": branch.tip_height,
+            "best": branch.is_best,
+            "forkheight": branch.fork_height,
+            "forkhash": branch.fork_hash,
+            "length": branch.length,
+            "branchwork": format!("{:032x}", branch.branch_work),
+            "chainwork": format!("{:032x}", branch.chain_work),
+        })).collect();
+        serde_json::json!({ "branches": branches })
+    }
+    
+    /// Graphviz form: the best chain runs through every fork point to its tip,
+    /// and each other branch hangs off its fork point. Edges are labelled with
+    /// the number of blocks they stand for.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph forks {\n    rankdir=LR;\n    node [shape=box, fontname=monospace];\n");
+        let Some(best) = self.best() else {
+            dot.push_str("}\n");
+            return dot;
+        };
+        let node = |hash: &str| format!("\"{}\"", hash);
+        let short = |hash: &str| hash.chars().take(12).collect::<String>();
+        
+        // Fork points along the best chain, lowest first
+        let mut points: Vec<(u64, &str)> = vec![(best.fork_height, best.fork_hash.as_str())];
+        for branch in &self.branches[1..] {
+            if !points.iter().any(|(_, hash)| *hash == branch.fork_hash) {
+                points.push((branch.fork_height, branch.fork_hash.as_str()));
+            }
+        }
+        points.sort();
+        for (height, hash) in &points {
+            dot.push_str(&format!("    {} [label=\"{}\\nheight {}\"];\n", node(hash), short(hash), height));
+        }
+        dot.push_str(&format!(
+            "    {} [label=\"{}\\nheight {}\\nbest tip\", style=bold];\n",
+            node(&best.tip_hash), short(&best.tip_hash), best.tip_height
+        ));
+        
+        let mut trunk = points.clone();
+        if trunk.last().map(|(_, hash)| *hash) != Some(best.tip_hash.as_str()) {
+            trunk.push((best.tip_height, best.tip_hash.as_str()));
+        }
+        for pair in trunk.windows(2) {
+            let ((from_height, from), (to_height, to)) = (pair[0], pair[1]);
+            dot.push_str(&format!(
+                "    {} -> {} [label=\"{} blocks\", style=bold];\n",
+                node(from), node(to), to_height - from_height
+            ));
+        }
+        
+        for branch in &self.branches[1..] {
+            dot.push_str(&format!(
+                "    {} [label=\"{}\\nheight {}\"];\n",
+                node(&branch.tip_hash), short(&branch.tip_hash), branch.tip_height
+            ));
+            dot.push_str(&format!(
+                "    {} -> {} [label=\"{} blocks, work {:x}\", style=dashed];\n",
+                node(&branch.fork_hash), node(&branch.tip_hash), branch.length, branch.branch_work
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
 /// Chain reorganization event
 #[derive(Debug, Clone)]
 pub struct ReorgEvent {
@@ -278,7 +589,7 @@ impl ForkChoiceWithReorg {
     
     pub fn add_block(&mut self, block: Block) -> Result<Option<ReorgEvent>, String> {
         let old_best = self.fork_choice.get_best_chain()
-            .and_then(|c| c.blocks.last())
+            .and_then(|c| c.tip())
             .map(|b| b.header.hash.clone());
         
         let is_new_best = self.fork_choice.add_block(block.clone())?;
@@ -1,2 +1,7 @@
 pub mod pow;
-pub mod fork_choice;
\ No newline at end of file
+pub mod target;
+pub mod fork_choice;
+pub mod checkpoints;
+pub mod params;
+pub mod timestamps;
+pub mod test_vectors;
@@ -0,0 +1,217 @@
+use serde::Serialize;
+
+use crate::blockchain::amount::UNITS_PER_COIN;
+use crate::blockchain::genesis::GenesisConfig;
+use crate::consensus::pow::DEFAULT_BITS;
+use crate::consensus::target::Target;
+
+/// Block subsidy paid when the chain starts, in base units
+pub const DEFAULT_INITIAL_SUBSIDY: u64 = 50 * UNITS_PER_COIN;
+
+/// Blocks between subsidy halvings
+pub const DEFAULT_HALVING_INTERVAL: u64 = 210_000;
+
+/// Blocks between difficulty retargets
+pub const DEFAULT_RETARGET_INTERVAL: u64 = 2016;
+
+/// Seconds between blocks the difficulty aims for
+pub const DEFAULT_TARGET_BLOCK_TIME: u64 = 60;
+
+/// Easiest compact target on regtest, met by about every other hash
+pub const REGTEST_POW_LIMIT_BITS: u32 = 0x207fffff;
+
+/// Monetary and proof of work parameters of a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    /// Subsidy of the first block after genesis
+    pub initial_subsidy: u64,
+    /// Blocks between halvings
+    pub halving_interval: u64,
+    /// Coins allocated in the genesis block
+    pub genesis_allocation: u64,
+    /// Easiest compact target a block may claim, which new chains start at
+    pub pow_limit_bits: u32,
+    /// Blocks between difficulty retargets
+    pub retarget_interval: u64,
+    /// Seconds between blocks the difficulty aims for
+    pub target_block_time: u64,
+    /// First height every block must carry bits at. Blocks below it may leave
+    /// them out, as blocks stored before headers had bits did, and are held to
+    /// the limit.
+    pub bits_required_height: u64,
+}
+
+/// One halving era of the emission schedule
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmissionEra {
+    pub era: u32,
+    pub start_height: u64,
+    pub end_height: u64,
+    pub subsidy: u64,
+    /// Coins in existence once the era is over, genesis allocation included
+    pub supply_at_end: u64,
+}
+
+impl ChainParams {
+    /// Parameters of the main chain
+    pub fn mainnet() -> Self {
+        let genesis_allocation = GenesisConfig::default().initial_allocations.iter()
+            .map(|(_, amount)| amount)
+            .sum();
+        ChainParams {
+            initial_subsidy: DEFAULT_INITIAL_SUBSIDY,
+            halving_interval: DEFAULT_HALVING_INTERVAL,
+            genesis_allocation,
+            pow_limit_bits: DEFAULT_BITS,
+            retarget_interval: DEFAULT_RETARGET_INTERVAL,
+            target_block_time: DEFAULT_TARGET_BLOCK_TIME,
+            bits_required_height: 1,
+        }
+    }
+
+    /// Parameters of a local regtest chain: mainnet's schedule with a target
+    /// so easy blocks are mined instantly. Blocks may leave out bits at any
+    /// height, so test chains can be built by hand.
+    pub fn regtest() -> Self {
+        ChainParams {
+            pow_limit_bits: REGTEST_POW_LIMIT_BITS,
+            bits_required_height: u64::MAX,
+            ..ChainParams::mainnet()
+        }
+    }
+
+    /// Easiest target a block may claim; an invalid limit allows any target
+    pub fn pow_limit(&self) -> Target {
+        Target::from_compact(self.pow_limit_bits).unwrap_or(Target::MAX)
+    }
+
+    /// Halvings that have happened by `height`
+    pub fn halvings_at(&self, height: u64) -> u64 {
+        height.saturating_sub(1) / self.halving_interval
+    }
+
+    /// Subsidy of the block at `height`. Genesis carries the allocation instead.
+    pub fn subsidy_at(&self, height: u64) -> u64 {
+        if height == 0 {
+            return 0;
+        }
+        match self.halvings_at(height) {
+            halvings if halvings >= 64 => 0,
+            halvings => self.initial_subsidy >> halvings,
+        }
+    }
+
+    /// Coins the schedule has issued once the block at `height` is mined
+    pub fn expected_supply_at(&self, height: u64) -> u64 {
+        let mut supply = self.genesis_allocation;
+        let mut start = 1;
+        while start <= height {
+            let subsidy = self.subsidy_at(start);
+            if subsidy == 0 {
+                break;
+            }
+            let end = start.saturating_add(self.halving_interval - 1).min(height);
+            supply = supply.saturating_add(subsidy.saturating_mul(end - start + 1));
+            if end == u64::MAX {
+                break;
+            }
+            start = end + 1;
+        }
+        supply
+    }
+
+    /// First height paying a reduced subsidy after `height`, if any subsidy is left to halve
+    pub fn next_halving_height(&self, height: u64) -> Option<u64> {
+        let next = (self.halvings_at(height) + 1) * self.halving_interval + 1;
+        (self.subsidy_at(height.max(1)) > 0).then_some(next)
+    }
+
+    /// Coins the schedule will ever issue
+    pub fn max_supply(&self) -> u64 {
+        self.schedule().last()
+            .map(|era| era.supply_at_end)
+            .unwrap_or(self.genesis_allocation)
+    }
+
+    /// Every era with a non-zero subsidy
+    pub fn schedule(&self) -> Vec<EmissionEra> {
+        let mut eras = Vec::new();
+        let mut supply = self.genesis_allocation;
+        let mut start_height = 1;
+        loop {
+            let subsidy = self.subsidy_at(start_height);
+            if subsidy == 0 {
+                break;
+            }
+            let end_height = start_height.saturating_add(self.halving_interval - 1);
+            supply = supply.saturating_add(subsidy.saturating_mul(self.halving_interval));
+            eras.push(EmissionEra {
+                era: eras.len() as u32,
+                start_height,
+                end_height,
+                subsidy,
+                supply_at_end: supply,
+            });
+            if end_height == u64::MAX {
+                break;
+            }
+            start_height = end_height + 1;
+        }
+        eras
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::amount::MAX_MONEY;
+
+    fn params() -> ChainParams {
+        ChainParams { initial_subsidy: 50, halving_interval: 10, genesis_allocation: 1_000, ..ChainParams::mainnet() }
+    }
+
+    #[test]
+    fn test_subsidy_halves() {
+        let params = params();
+        assert_eq!(params.subsidy_at(0), 0);
+        assert_eq!(params.subsidy_at(1), 50);
+        assert_eq!(params.subsidy_at(10), 50);
+        assert_eq!(params.subsidy_at(11), 25);
+        assert_eq!(params.subsidy_at(21), 12);
+        assert_eq!(params.next_halving_height(0), Some(11));
+        assert_eq!(params.next_halving_height(10), Some(11));
+        assert_eq!(params.next_halving_height(11), Some(21));
+    }
+
+    #[test]
+    fn test_expected_supply_matches_schedule() {
+        let params = params();
+        assert_eq!(params.expected_supply_at(0), 1_000);
+        assert_eq!(params.expected_supply_at(12), 1_000 + 500 + 50);
+
+        let schedule = params.schedule();
+        assert_eq!(schedule.len(), 6); // 50, 25, 12, 6, 3, 1
+        for era in &schedule {
+            assert_eq!(params.expected_supply_at(era.end_height), era.supply_at_end);
+        }
+        assert_eq!(params.max_supply(), 1_000 + 10 * (50 + 25 + 12 + 6 + 3 + 1));
+        assert_eq!(params.next_halving_height(schedule[5].end_height + 1), None);
+    }
+
+    #[test]
+    fn test_supply_within_max_money() {
+        assert!(ChainParams::mainnet().max_supply() <= MAX_MONEY);
+
+        // Parameters that would overflow saturate instead of wrapping
+        let params = ChainParams { initial_subsidy: u64::MAX, halving_interval: u64::MAX, genesis_allocation: 1, ..ChainParams::mainnet() };
+        assert_eq!(params.expected_supply_at(u64::MAX), u64::MAX);
+        assert_eq!(params.max_supply(), u64::MAX);
+        assert_eq!(params.schedule().len(), 1);
+    }
+}
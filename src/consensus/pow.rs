@@ -1,10 +1,35 @@
 use crate::blockchain::block::{Block, Transaction};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::blockchain::chain::Chain;
+use crate::blockchain::events::ChainEvent;
+use crate::clock::{self, SharedClock};
+use crate::consensus::target::Target;
+use crate::consensus::timestamps;
+use crate::mempool::MempoolEvent;
+use crate::perf;
+use crate::{detail, status};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
 
 /// Proof of Work difficulty target
 pub const DEFAULT_DIFFICULTY: u32 = 4; // Number of leading zeros required
 pub const MAX_NONCE: u64 = u64::MAX;
 
+/// Compact target of `DEFAULT_DIFFICULTY`, the easiest target mainnet accepts
+pub const DEFAULT_BITS: u32 = 0x1f00ffff;
+
+/// How many nonces to try between checks for mempool changes
+pub const TEMPLATE_CHECK_INTERVAL: u64 = 10_000;
+
+/// Number of recent blocks used for network hash rate estimates
+pub const DEFAULT_HASHPS_WINDOW: u64 = 120;
+
+/// How often the tip watcher checks whether it should stop while no events arrive
+const TIP_WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Proof of Work mining result
 #[derive(Debug, Clone)]
 pub struct MiningResult {
@@ -13,24 +38,165 @@ pub struct MiningResult {
     pub hash: String,
     pub attempts: u64,
     pub elapsed_ms: u128,
+    /// Number of times the block template was rebuilt while mining
+    pub template_refreshes: u32,
+}
+
+/// When to rebuild the candidate block after new transactions arrive
+#[derive(Debug, Clone)]
+pub struct TemplateRefreshPolicy {
+    /// Rebuild once newly added transactions carry at least this much total fee
+    pub min_added_fees: f64,
+    /// Rebuild pending changes once the template is this old, whatever their
+    /// fees. Age is read from the miner's clock.
+    pub max_template_age: Duration,
+}
+
+impl Default for TemplateRefreshPolicy {
+    fn default() -> Self {
+        TemplateRefreshPolicy {
+            min_added_fees: 1000.0,
+            max_template_age: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Flag that stops an in-flight mining run. Clones share the flag, so another
+/// thread can cancel the run the miner is checking.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+    
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+    
+    /// Clear the flag before starting the next run
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Cancels in-flight mining whenever the chain event bus reports a new best tip.
+/// Connected blocks are kept so the miner can catch up and restart on the new
+/// parent. The watcher thread stops when the watcher is dropped.
+pub struct TipWatcher {
+    token: CancellationToken,
+    new_blocks: Arc<Mutex<Vec<Block>>>,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TipWatcher {
+    /// Watch a chain event subscription on a background thread
+    pub fn start(events: Receiver<ChainEvent>) -> Self {
+        let token = CancellationToken::new();
+        let new_blocks = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        
+        let handle = {
+            let (token, new_blocks, stopped) = (token.clone(), Arc::clone(&new_blocks), Arc::clone(&stopped));
+            thread::spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    match events.recv_timeout(TIP_WATCH_INTERVAL) {
+                        Ok(ChainEvent::BlockConnected(block)) => {
+                            new_blocks.lock().unwrap().push(block);
+                            token.cancel();
+                        },
+                        Ok(ChainEvent::BlockDisconnected(_) | ChainEvent::Reorg { .. }) => token.cancel(),
+                        Err(RecvTimeoutError::Timeout) => {},
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            })
+        };
+        
+        TipWatcher { token, new_blocks, stopped, handle: Some(handle) }
+    }
+    
+    /// Token cancelled by every new best tip
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+    
+    /// Blocks connected since the last call, oldest first. Resets the token, so a
+    /// tip that arrives after this call cancels the next run.
+    pub fn take_new_blocks(&self) -> Vec<Block> {
+        let mut new_blocks = self.new_blocks.lock().unwrap();
+        self.token.reset();
+        std::mem::take(&mut *new_blocks)
+    }
+}
+
+impl Drop for TipWatcher {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
 }
 
 /// Proof of Work implementation
 pub struct ProofOfWork {
-    difficulty: u32,
+    /// Compact target blocks are mined to
+    bits: u32,
+    /// Easiest target a block may claim
+    pow_limit: Target,
+    /// Source of block timestamps
+    clock: SharedClock,
+    /// Timestamp of the parent, which mined blocks are never stamped before
+    parent_timestamp: u64,
 }
 
 impl ProofOfWork {
     /// Create new PoW instance with default difficulty
     pub fn new() -> Self {
-        ProofOfWork {
-            difficulty: DEFAULT_DIFFICULTY,
-        }
+        Self::with_bits(DEFAULT_BITS)
     }
     
-    /// Create new PoW instance with custom difficulty
+    /// Create new PoW instance with custom difficulty, in leading zero hex digits
     pub fn with_difficulty(difficulty: u32) -> Self {
-        ProofOfWork { difficulty }
+        Self::with_bits(Target::from_leading_zeros(difficulty).to_compact())
+    }
+    
+    /// Create new PoW instance mining to the compact target `bits`, which is also
+    /// the easiest target it accepts
+    pub fn with_bits(bits: u32) -> Self {
+        let pow_limit = Target::from_compact(bits).unwrap_or(Target::MAX);
+        ProofOfWork { bits, pow_limit, clock: clock::system(), parent_timestamp: 0 }
+    }
+    
+    /// Accept targets up to `limit` rather than the one blocks are mined to
+    pub fn with_pow_limit(mut self, limit: Target) -> Self {
+        self.pow_limit = limit;
+        self
+    }
+    
+    /// Timestamp mined blocks with `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+    
+    /// Stamp the next blocks no earlier than `timestamp`, the parent's, even when
+    /// the clock is behind it
+    pub fn set_parent_timestamp(&mut self, timestamp: u64) {
+        self.parent_timestamp = timestamp;
+    }
+    
+    /// Timestamp for a block mined now
+    fn block_timestamp(&self) -> u64 {
+        timestamps::next_timestamp(self.clock.now(), self.parent_timestamp)
     }
     
     /// Mine a block using Proof of Work
@@ -40,32 +206,31 @@ impl ProofOfWork {
         transactions: Vec<Transaction>,
         height: u64,
     ) -> MiningResult {
+        let _timer = perf::timer(perf::MINING);
         let start_time = SystemTime::now();
-        let timestamp = start_time
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let timestamp = self.block_timestamp();
         
-        let target = self.calculate_target();
+        let target = self.target();
         let mut attempts = 0u64;
         
-        println!("Mining block with difficulty {}...", self.difficulty);
+        status!("Mining block to bits {:08x} (about {} hashes)...", self.bits, self.target().work());
         
         for nonce in 0..MAX_NONCE {
             attempts += 1;
             
-            let block = Block::new(
+            let block = Block::new_with_bits(
                 previous_hash.clone(),
                 transactions.clone(),
                 nonce,
                 timestamp,
                 height,
+                self.bits,
             );
             
-            if self.validate_proof(&block.header.hash, &target) {
+            if target.is_met_by(&block.header.hash) {
                 let elapsed = start_time.elapsed().unwrap().as_millis();
                 let hash = block.header.hash.clone();
-                println!("Block mined! Nonce: {}, Attempts: {}, Time: {}ms", nonce, attempts, elapsed);
+                status!("Block mined! Nonce: {}, Attempts: {}, Time: {}ms", nonce, attempts, elapsed);
                 
                 return MiningResult {
                     block,
@@ -73,12 +238,13 @@ impl ProofOfWork {
                     hash,
                     attempts,
                     elapsed_ms: elapsed,
+                    template_refreshes: 0,
                 };
             }
             
             // Progress indicator for long mining sessions
             if attempts % 100000 == 0 {
-                println!("Mining... attempts: {}", attempts);
+                detail!("Mining... attempts: {}", attempts);
             }
         }
         
@@ -86,73 +252,169 @@ impl ProofOfWork {
         panic!("Failed to mine block: exhausted all nonces");
     }
     
-    /// Validate a block's proof of work
+    /// Mine a block, rebuilding the template when the mempool changes enough.
+    /// `rebuild` receives the mempool events seen since the last template and
+    /// returns the new transaction list. The nonce search and statistics carry on
+    /// across rebuilds rather than restarting. Returns `None` once `cancel` is
+    /// cancelled, since the parent is no longer the best tip.
+    #[allow(clippy::too_many_arguments)]
+    pub fn mine_block_with_refresh<F>(
+        &self,
+        previous_hash: String,
+        transactions: Vec<Transaction>,
+        height: u64,
+        events: &Receiver<MempoolEvent>,
+        policy: &TemplateRefreshPolicy,
+        cancel: &CancellationToken,
+        mut rebuild: F,
+    ) -> Option<MiningResult>
+    where
+        F: FnMut(&[MempoolEvent]) -> Vec<Transaction>,
+    {
+        let _timer = perf::timer(perf::MINING);
+        let start_time = SystemTime::now();
+        let mut timestamp = self.block_timestamp();
+        
+        let target = self.target();
+        let mut transactions = transactions;
+        let mut template_built = self.clock.now();
+        let mut pending_events: Vec<MempoolEvent> = Vec::new();
+        let mut template_refreshes = 0;
+        let mut attempts = 0u64;
+        
+        status!("Mining block to bits {:08x} (about {} hashes)...", self.bits, self.target().work());
+        
+        for nonce in 0..MAX_NONCE {
+            if cancel.is_cancelled() {
+                status!("Mining cancelled after {} attempts: new best tip", attempts);
+                return None;
+            }
+            attempts += 1;
+            
+            let block = Block::new_with_bits(
+                previous_hash.clone(),
+                transactions.clone(),
+                nonce,
+                timestamp,
+                height,
+                self.bits,
+            );
+            
+            if target.is_met_by(&block.header.hash) {
+                let elapsed = start_time.elapsed().unwrap().as_millis();
+                let hash = block.header.hash.clone();
+                status!("Block mined! Nonce: {}, Attempts: {}, Time: {}ms, Template refreshes: {}",
+                    nonce, attempts, elapsed, template_refreshes);
+                
+                return Some(MiningResult {
+                    block,
+                    nonce,
+                    hash,
+                    attempts,
+                    elapsed_ms: elapsed,
+                    template_refreshes,
+                });
+            }
+            
+            if attempts.is_multiple_of(TEMPLATE_CHECK_INTERVAL) {
+                pending_events.extend(events.try_iter());
+                
+                let added_fees: f64 = pending_events.iter().map(|event| event.total_fee()).sum();
+                let template_age = Duration::from_secs(self.clock.now().saturating_sub(template_built));
+                
+                if !pending_events.is_empty()
+                    && (added_fees >= policy.min_added_fees || template_age >= policy.max_template_age) {
+                    transactions = rebuild(&pending_events);
+                    pending_events.clear();
+                    template_built = self.clock.now();
+                    timestamp = self.block_timestamp();
+                    template_refreshes += 1;
+                    detail!("Block template refreshed ({} transactions, {:.2} added fees)",
+                        transactions.len(), added_fees);
+                }
+            }
+            
+            // Progress indicator for long mining sessions
+            if attempts.is_multiple_of(100000) {
+                detail!("Mining... attempts: {}", attempts);
+            }
+        }
+        
+        panic!("Failed to mine block: exhausted all nonces");
+    }
+    
+    /// Validate a block's proof of work. Blocks carrying bits must meet the
+    /// target they name, which may be no easier than the limit; blocks stored
+    /// before headers had bits are held to the leading zeros of this instance's
+    /// difficulty instead.
     pub fn validate_block(&self, block: &Block) -> bool {
-        let target = self.calculate_target();
-        self.validate_proof(&block.header.hash, &target)
+        self.validate_hash(&block.header.hash, block.header.bits)
     }
     
-    /// Check if a hash meets the difficulty target
-    fn validate_proof(&self, hash: &str, target: &str) -> bool {
-        hash < target
+    /// Validate the proof of work of a block hash carrying `bits`, as
+    /// `validate_block` does, for when only the header is at hand
+    pub fn validate_hash(&self, hash: &str, bits: Option<u32>) -> bool {
+        match bits {
+            Some(bits) => match Target::from_compact(bits) {
+                Ok(target) => target <= self.pow_limit && target.is_met_by(hash),
+                Err(_) => false,
+            },
+            None => Target::from_leading_zeros(self.get_difficulty()).is_met_by(hash),
+        }
     }
     
-    /// Calculate the target hash for current difficulty
-    fn calculate_target(&self) -> String {
-        let mut target = String::from("0".repeat(self.difficulty as usize));
-        target.push_str(&"f".repeat(64 - self.difficulty as usize));
-        target
+    /// Target for the current bits; a block hash must not exceed it
+    pub fn target(&self) -> Target {
+        Target::from_compact(self.bits).unwrap_or(self.pow_limit)
     }
     
-    /// Get current difficulty
+    /// Get current difficulty, in leading zero hex digits of the target
     pub fn get_difficulty(&self) -> u32 {
-        self.difficulty
+        self.target().leading_zero_digits()
     }
     
-    /// Set new difficulty
+    /// Set new difficulty, in leading zero hex digits
     pub fn set_difficulty(&mut self, difficulty: u32) {
-        self.difficulty = difficulty;
+        self.bits = Target::from_leading_zeros(difficulty).to_compact();
     }
     
-    /// Calculate difficulty adjustment based on block times
-    /// This is a simple implementation - in a real blockchain you'd have more sophisticated logic
-    pub fn adjust_difficulty(
+    /// Compact target blocks are mined to
+    pub fn get_bits(&self) -> u32 {
+        self.bits
+    }
+    
+    pub fn set_bits(&mut self, bits: u32) {
+        self.bits = bits;
+    }
+    
+    /// Retarget from the time `last_blocks` took after the block stamped
+    /// `previous_timestamp` against `target_block_time_seconds` per block, scaling the target by actual over expected time within
+    /// `MAX_RETARGET_FACTOR` and never past the limit. Returns the new compact
+    /// target (bits), not a difficulty; `get_difficulty` gives that.
+    pub fn retarget(
         &mut self,
         last_blocks: &[Block],
+        previous_timestamp: u64,
         target_block_time_seconds: u64,
     ) -> u32 {
         if last_blocks.len() < 2 {
-            return self.difficulty;
-        }
-        
-        // Calculate average time between blocks
-        let mut total_time = 0u64;
-        for i in 1..last_blocks.len() {
-            let time_diff = last_blocks[i].header.timestamp - last_blocks[i-1].header.timestamp;
-            total_time += time_diff;
+            return self.bits;
         }
         
-        let avg_block_time = total_time / (last_blocks.len() - 1) as u64;
+        // Equal or backwards timestamps count as no time, which `retarget` clamps
+        let actual_timespan = timestamps::timespan(previous_timestamp, last_blocks);
+        let expected_timespan = target_block_time_seconds * (last_blocks.len() - 1) as u64;
+        let target = self.target().retarget(actual_timespan, expected_timespan, &self.pow_limit);
+        self.bits = target.to_compact();
         
-        // Adjust difficulty based on whether blocks are coming too fast or too slow
-        if avg_block_time < target_block_time_seconds / 2 {
-            // Blocks too fast, increase difficulty
-            self.difficulty += 1;
-        } else if avg_block_time > target_block_time_seconds * 2 {
-            // Blocks too slow, decrease difficulty (but never below 1)
-            if self.difficulty > 1 {
-                self.difficulty -= 1;
-            }
-        }
-        
-        println!("Difficulty adjusted to {} (avg block time: {}s)", self.difficulty, avg_block_time);
-        self.difficulty
+        status!("Difficulty adjusted to {:.2} (bits {:08x}, {}s for {} blocks, expected {}s)",
+            target.difficulty(&self.pow_limit), self.bits, actual_timespan, last_blocks.len() - 1, expected_timespan);
+        self.bits
     }
     
     /// Estimate mining time for current difficulty
     pub fn estimate_mining_time(&self, hash_rate_per_second: u64) -> f64 {
-        let target_calculations = 16u64.pow(self.difficulty);
-        target_calculations as f64 / hash_rate_per_second as f64
+        self.target().work() as f64 / hash_rate_per_second as f64
     }
 }
 
@@ -162,6 +424,114 @@ impl Default for ProofOfWork {
     }
 }
 
+/// Expected number of hashes needed to find a block mined to the compact target
+/// `bits`: the work the block proves
+pub fn expected_hashes_per_block(bits: u32) -> f64 {
+    ProofOfWork::with_bits(bits).target().work() as f64
+}
+
+/// Network hash rate over a window of recent blocks
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkHashRate {
+    /// Height of the last block in the window
+    pub height: u64,
+    /// Number of block intervals the estimate covers
+    pub window_blocks: u64,
+    /// Seconds between the first and last block of the window
+    pub window_seconds: u64,
+    /// Estimated hashes per second, 0 when the window has no elapsed time
+    pub hashes_per_second: f64,
+    /// Average seconds between blocks in the window
+    pub average_block_time: Option<f64>,
+}
+
+/// Estimate the network hash rate from the work and timestamps of up to `nblocks`
+/// blocks ending at `height` (the tip if `None`). The first block of the window
+/// only marks its start, so its work is not counted.
+pub fn estimate_network_hash_rate(
+    chain: &Chain,
+    nblocks: u64,
+    height: Option<u64>,
+) -> Result<NetworkHashRate, String> {
+    if chain.tip().is_none() {
+        return Err("Chain has no blocks".to_string());
+    }
+    let tip_height = chain.height();
+    let height = height.unwrap_or(tip_height);
+    if height > tip_height {
+        return Err(format!("Height {} is beyond the tip at {}", height, tip_height));
+    }
+
+    let start = height.saturating_sub(nblocks.max(1));
+    let window = chain.blocks_in_range(start, height)?;
+    let first = window.iter().map(|b| b.header.timestamp).min().unwrap_or(0);
+    let last = window.iter().map(|b| b.header.timestamp).max().unwrap_or(0);
+    let window_blocks = height - start;
+    let window_seconds = last - first;
+    let window_work: f64 = window.iter()
+        .filter(|block| block.header.height > start)
+        .map(|block| block.work() as f64)
+        .sum();
+
+    let hashes_per_second = if window_seconds == 0 {
+        0.0
+    } else {
+        window_work / window_seconds as f64
+    };
+    let average_block_time = (window_blocks > 0).then(|| window_seconds as f64 / window_blocks as f64);
+
+    Ok(NetworkHashRate {
+        height,
+        window_blocks,
+        window_seconds,
+        hashes_per_second,
+        average_block_time,
+    })
+}
+
+/// Difficulty of the next block, its target and the distance to the next retarget
+#[derive(Debug, Clone, Serialize)]
+pub struct DifficultyInfo {
+    /// Difficulty of the target against the chain's limit, see `Target::difficulty`
+    pub difficulty: f64,
+    /// Compact target, as eight hex digits
+    pub bits: String,
+    pub target: String,
+    /// Height of the tip the figures are relative to
+    pub height: u64,
+    pub next_retarget_height: u64,
+    pub blocks_until_retarget: u64,
+    /// Seconds until the next retarget at the recent average block time, or at
+    /// the chain's target block time when it has no recent intervals
+    pub estimated_seconds_to_retarget: u64,
+}
+
+/// Difficulty info for the next block on `chain`: the bits the chain requires of
+/// it and the retarget schedule of the chain's parameters
+pub fn difficulty_info(chain: &Chain) -> Result<DifficultyInfo, String> {
+    let params = chain.params();
+    let bits = chain.next_bits()?;
+    let pow = ProofOfWork::with_bits(bits);
+    let difficulty = pow.target().difficulty(&params.pow_limit());
+    let height = chain.height();
+    let next_retarget_height = (height / params.retarget_interval + 1) * params.retarget_interval;
+    let blocks_until_retarget = next_retarget_height - height;
+    let block_time = estimate_network_hash_rate(chain, DEFAULT_HASHPS_WINDOW, None).ok()
+        .and_then(|estimate| estimate.average_block_time)
+        .filter(|seconds| *seconds > 0.0)
+        .unwrap_or(params.target_block_time as f64);
+
+    Ok(DifficultyInfo {
+        difficulty,
+        bits: format!("{:08x}", bits),
+        target: pow.target().to_string(),
+        height,
+        next_retarget_height,
+        blocks_until_retarget,
+        estimated_seconds_to_retarget: (blocks_until_retarget as f64 * block_time).round() as u64,
+    })
+}
+
 /// Mining statistics
 #[derive(Debug, Clone)]
 pub struct MiningStats {
@@ -181,6 +551,15 @@ pub struct MiningPool {
 
 impl MiningPool {
     pub fn new(difficulty: u32) -> Self {
+        Self::with_pow(ProofOfWork::with_difficulty(difficulty))
+    }
+    
+    /// Pool mining to the compact target `bits`
+    pub fn with_bits(bits: u32) -> Self {
+        Self::with_pow(ProofOfWork::with_bits(bits))
+    }
+    
+    fn with_pow(pow: ProofOfWork) -> Self {
         MiningPool {
             stats: MiningStats {
                 total_blocks_mined: 0,
@@ -190,10 +569,20 @@ impl MiningPool {
                 average_time_per_block_ms: 0.0,
                 current_hash_rate: 0.0,
             },
-            pow: ProofOfWork::with_difficulty(difficulty),
+            pow,
         }
     }
     
+    /// Timestamp mined blocks with `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.pow.set_clock(clock);
+    }
+    
+    /// Stamp the next blocks no earlier than their parent's `timestamp`
+    pub fn set_parent_timestamp(&mut self, timestamp: u64) {
+        self.pow.set_parent_timestamp(timestamp);
+    }
+    
     pub fn mine_block(
         &mut self,
         previous_hash: String,
@@ -201,7 +590,32 @@ impl MiningPool {
         height: u64,
     ) -> MiningResult {
         let result = self.pow.mine_block(previous_hash, transactions, height);
-        
+        self.record_result(&result);
+        result
+    }
+    
+    /// Mine a block, refreshing the template as mempool events arrive. Cancelled
+    /// runs are not counted in the statistics.
+    #[allow(clippy::too_many_arguments)]
+    pub fn mine_block_with_refresh<F>(
+        &mut self,
+        previous_hash: String,
+        transactions: Vec<Transaction>,
+        height: u64,
+        events: &Receiver<MempoolEvent>,
+        policy: &TemplateRefreshPolicy,
+        cancel: &CancellationToken,
+        rebuild: F,
+    ) -> Option<MiningResult>
+    where
+        F: FnMut(&[MempoolEvent]) -> Vec<Transaction>,
+    {
+        let result = self.pow.mine_block_with_refresh(previous_hash, transactions, height, events, policy, cancel, rebuild)?;
+        self.record_result(&result);
+        Some(result)
+    }
+    
+    fn record_result(&mut self, result: &MiningResult) {
         // Update statistics
         self.stats.total_blocks_mined += 1;
         self.stats.total_attempts += result.attempts;
@@ -220,8 +634,6 @@ impl MiningPool {
             // Very fast mining, estimate based on attempts
             self.stats.current_hash_rate = result.attempts as f64 * 1000.0; // Assume 1ms
         }
-        
-        result
     }
     
     pub fn get_stats(&self) -> &MiningStats {
@@ -232,7 +644,18 @@ impl MiningPool {
         self.pow.get_difficulty()
     }
     
-    pub fn adjust_difficulty(&mut self, last_blocks: &[Block], target_time: u64) {
-        self.pow.adjust_difficulty(last_blocks, target_time);
+    pub fn get_bits(&self) -> u32 {
+        self.pow.get_bits()
+    }
+    
+    /// Retarget from the time `last_blocks` took after a block stamped
+    /// `previous_timestamp`, returning the new bits
+    pub fn retarget(&mut self, last_blocks: &[Block], previous_timestamp: u64, target_time: u64) -> u32 {
+        self.pow.retarget(last_blocks, previous_timestamp, target_time)
+    }
+    
+    /// Mine the next blocks to the compact target `bits`, as the chain requires
+    pub fn set_bits(&mut self, bits: u32) {
+        self.pow.set_bits(bits);
     }
 }
\ No newline at end of file
@@ -0,0 +1,295 @@
+//! 256-bit proof of work targets and their compact "bits" encoding
+//!
+//! A block hash, read as a big-endian 256-bit number, must not exceed the target
+//! named by the block header's `bits`. As in Bitcoin, the high byte of `bits` is
+//! the length of the target in bytes and the low three bytes are its leading
+//! digits, so `0x1f00ffff` is `0x00ffff` followed by 28 zero bytes. Blocks stored
+//! before headers carried bits were mined to a number of leading zero hex digits;
+//! `Target::from_leading_zeros` gives the target such a difficulty stands for.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Most a retarget moves the target either way, as a factor
+pub const MAX_RETARGET_FACTOR: u64 = 4;
+
+/// An unsigned 256-bit target, least significant 64-bit limb first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target([u64; 4]);
+
+impl Target {
+    pub const ZERO: Target = Target([0; 4]);
+    pub const MAX: Target = Target([u64::MAX; 4]);
+
+    fn from_u64(value: u64) -> Self {
+        Target([value, 0, 0, 0])
+    }
+
+    /// Decode compact bits, refusing negative targets and ones over 256 bits
+    pub fn from_compact(bits: u32) -> Result<Self, String> {
+        let size = bits >> 24;
+        let mantissa = bits & 0x007f_ffff;
+        if bits & 0x0080_0000 != 0 && mantissa != 0 {
+            return Err(format!("Compact target {:08x} is negative", bits));
+        }
+        if size <= 3 {
+            return Ok(Target::from_u64((mantissa >> (8 * (3 - size))) as u64));
+        }
+        let shift = 8 * (size - 3);
+        if mantissa != 0 && shift + (32 - mantissa.leading_zeros()) > 256 {
+            return Err(format!("Compact target {:08x} overflows 256 bits", bits));
+        }
+        Ok(Target::from_u64(mantissa as u64).shl(shift))
+    }
+
+    /// Encode as compact bits, keeping the three most significant bytes
+    pub fn to_compact(&self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            (self.0[0] << (8 * (3 - size))) as u32
+        } else {
+            self.shr(8 * (size - 3)).0[0] as u32
+        };
+        // The top mantissa bit is a sign bit, so a set one moves into a new byte
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        mantissa | (size << 24)
+    }
+
+    /// Target of hashes starting with at least `zeros` zero hex digits, the
+    /// difficulty blocks without bits were mined to
+    pub fn from_leading_zeros(zeros: u32) -> Self {
+        if zeros >= 64 {
+            return Target::ZERO;
+        }
+        Target::MAX.shr(4 * zeros)
+    }
+
+    /// A 64 digit hex hash as a number, or None if it is not one
+    pub fn from_hash(hash: &str) -> Option<Self> {
+        if hash.len() != 64 || !hash.is_ascii() {
+            return None;
+        }
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 64 - 16 * (i + 1);
+            *limb = u64::from_str_radix(&hash[start..start + 16], 16).ok()?;
+        }
+        Some(Target(limbs))
+    }
+
+    /// Whether `hash` meets this target
+    pub fn is_met_by(&self, hash: &str) -> bool {
+        Target::from_hash(hash).is_some_and(|hash| hash <= *self)
+    }
+
+    /// Zero hex digits every hash meeting the target starts with
+    pub fn leading_zero_digits(&self) -> u32 {
+        self.leading_zeros() / 4
+    }
+
+    /// Expected number of hashes to meet the target, 2^256 / (target + 1),
+    /// saturating at `u128::MAX`
+    pub fn work(&self) -> u128 {
+        if *self == Target::MAX {
+            return 1;
+        }
+        let work = self.not().div(self.add_one()).add_one();
+        if work.0[2] != 0 || work.0[3] != 0 {
+            return u128::MAX;
+        }
+        ((work.0[1] as u128) << 64) | work.0[0] as u128
+    }
+
+    /// Target for the next period when the last one took `actual_timespan`
+    /// seconds instead of `expected_timespan`. The change is held within
+    /// `MAX_RETARGET_FACTOR` and the result never exceeds `limit`.
+    pub fn retarget(&self, actual_timespan: u64, expected_timespan: u64, limit: &Target) -> Target {
+        let expected_timespan = expected_timespan.max(1);
+        let actual_timespan = actual_timespan.clamp(
+            (expected_timespan / MAX_RETARGET_FACTOR).max(1),
+            expected_timespan.saturating_mul(MAX_RETARGET_FACTOR),
+        );
+        match self.mul_u64(actual_timespan) {
+            Some(scaled) => scaled.div_u64(expected_timespan).min(*limit),
+            None => *limit,
+        }
+    }
+
+    /// Difficulty against `limit`: how many times the work of a block at `limit`
+    /// a block at this target proves. 1 at the limit; every figure reported as
+    /// a difficulty comes from here.
+    pub fn difficulty(&self, limit: &Target) -> f64 {
+        self.work() as f64 / limit.work() as f64
+    }
+
+    fn leading_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for limb in self.0.iter().rev() {
+            zeros += limb.leading_zeros();
+            if *limb != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+
+    /// Number of significant bits
+    fn bits(&self) -> u32 {
+        256 - self.leading_zeros()
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        self.0[(index / 64) as usize] >> (index % 64) & 1 == 1
+    }
+
+    fn shl(&self, shift: u32) -> Target {
+        let mut result = [0u64; 4];
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for (i, limb) in result.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *limb |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        Target(result)
+    }
+
+    fn shr(&self, shift: u32) -> Target {
+        let mut result = [0u64; 4];
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for (i, limb) in result.iter_mut().enumerate().take(4usize.saturating_sub(limbs)) {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        Target(result)
+    }
+
+    fn not(&self) -> Target {
+        Target(self.0.map(|limb| !limb))
+    }
+
+    fn add_one(&self) -> Target {
+        let mut result = self.0;
+        for limb in result.iter_mut() {
+            let (sum, carry) = limb.overflowing_add(1);
+            *limb = sum;
+            if !carry {
+                break;
+            }
+        }
+        Target(result)
+    }
+
+    fn wrapping_sub(&self, other: &Target) -> Target {
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (difference, borrow_a) = self.0[i].overflowing_sub(other.0[i]);
+            let (difference, borrow_b) = difference.overflowing_sub(borrow as u64);
+            *limb = difference;
+            borrow = borrow_a || borrow_b;
+        }
+        Target(result)
+    }
+
+    fn mul_u64(&self, factor: u64) -> Option<Target> {
+        let mut result = [0u64; 4];
+        let mut carry = 0u128;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let product = self.0[i] as u128 * factor as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        (carry == 0).then_some(Target(result))
+    }
+
+    fn div_u64(&self, divisor: u64) -> Target {
+        let mut result = [0u64; 4];
+        let mut remainder = 0u128;
+        for i in (0..4).rev() {
+            let current = (remainder << 64) | self.0[i] as u128;
+            result[i] = (current / divisor as u128) as u64;
+            remainder = current % divisor as u128;
+        }
+        Target(result)
+    }
+
+    /// Long division, one bit at a time
+    fn div(&self, divisor: Target) -> Target {
+        let mut quotient = [0u64; 4];
+        let mut remainder = Target::ZERO;
+        for i in (0..256).rev() {
+            let overflow = remainder.bit(255);
+            remainder = remainder.shl(1);
+            remainder.0[0] |= self.bit(i) as u64;
+            if overflow || remainder >= divisor {
+                remainder = remainder.wrapping_sub(&divisor);
+                quotient[(i / 64) as usize] |= 1 << (i % 64);
+            }
+        }
+        Target(quotient)
+    }
+}
+
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}{:016x}{:016x}{:016x}", self.0[3], self.0[2], self.0[1], self.0[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_encoding() {
+        for bits in [0x1f00ffff, 0x207fffff, 0x1d00ffff, 0x1b0404cb] {
+            assert_eq!(Target::from_compact(bits).unwrap().to_compact(), bits);
+        }
+        let target = Target::from_compact(0x1d00ffff).unwrap();
+        assert_eq!(target.to_string(), format!("00000000ffff{}", "0".repeat(52)));
+        assert!(Target::from_compact(0x04923456).is_err());
+        assert!(Target::from_compact(0x22123456).is_err());
+
+        // Leading zero difficulties lose the digits past the third byte
+        assert_eq!(Target::from_leading_zeros(4).to_compact(), 0x1f00ffff);
+        assert_eq!(Target::from_leading_zeros(4).leading_zero_digits(), 4);
+        let target = Target::from_compact(0x1f00ffff).unwrap();
+        assert!(target.is_met_by(&format!("0000fffe{}", "f".repeat(56))));
+        assert!(!target.is_met_by(&format!("0001{}", "0".repeat(60))));
+        assert!(!target.is_met_by("not a hash"));
+    }
+
+    #[test]
+    fn test_work_and_retarget() {
+        assert_eq!(Target::from_compact(0x207fffff).unwrap().work(), 2);
+        assert_eq!(Target::from_leading_zeros(4).work(), 1 << 16);
+        assert_eq!(Target::from_compact(0x1d00ffff).unwrap().work(), 0x1_0001_0001);
+
+        let limit = Target::from_compact(0x1f00ffff).unwrap();
+        let target = Target::from_compact(0x1e00ffff).unwrap();
+        assert_eq!(target.retarget(600, 1200, &limit).to_compact(), 0x1d7fff80);
+        // Held to a factor of four, and never easier than the limit
+        assert_eq!(target.retarget(1, 1200, &limit), target.retarget(300, 1200, &limit));
+        let half_limit = Target::from_compact(0x1f007fff).unwrap();
+        assert_eq!(half_limit.retarget(u64::MAX, 1200, &limit), limit);
+        assert_eq!(target.difficulty(&limit), 256.0);
+    }
+}
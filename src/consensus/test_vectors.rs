@@ -0,0 +1,263 @@
+//! Deterministic test vectors for consensus hashing
+//!
+//! Txids, merkle roots and block hashes are computed from the `Debug` output of
+//! transactions and headers, so renaming a field or changing how it is printed
+//! changes every hash on the chain. The vectors below hash fixed inputs; the
+//! golden files in `tests/vectors` record the expected results and are checked
+//! by tests. Regenerate them with `dump-test-vectors` only when a change to the
+//! hash preimage or serialization is intended.
+
+use crate::blockchain::block::{calculate_merkle_root, Block, Transaction};
+use crate::blockchain::genesis::{genesis_block, COINBASE_ADDRESS};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Directory of the golden files, relative to the crate root
+pub const GOLDEN_DIR: &str = "tests/vectors";
+
+/// Golden file names inside the vector directory
+pub const TRANSACTIONS_FILE: &str = "transactions.json";
+pub const MERKLE_ROOTS_FILE: &str = "merkle_roots.json";
+pub const BLOCKS_FILE: &str = "blocks.json";
+
+/// A transaction with its id, signed message and canonical serialization
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionVector {
+    pub name: String,
+    pub transaction: Transaction,
+    pub txid: String,
+    pub signing_message: String,
+    pub hex: String,
+}
+
+/// The merkle root of a list of transactions
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MerkleVector {
+    pub name: String,
+    pub transactions: Vec<Transaction>,
+    pub merkle_root: String,
+}
+
+/// The inputs of `Block::new` with the resulting merkle root and hash
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockVector {
+    pub name: String,
+    pub previous_hash: String,
+    pub transactions: Vec<Transaction>,
+    pub nonce: u64,
+    pub timestamp: u64,
+    pub height: u64,
+    pub merkle_root: String,
+    pub hash: String,
+}
+
+/// All consensus test vectors
+#[derive(Debug, Clone)]
+pub struct TestVectors {
+    pub transactions: Vec<TransactionVector>,
+    pub merkle_roots: Vec<MerkleVector>,
+    pub blocks: Vec<BlockVector>,
+}
+
+fn transaction(from: &str, to: &str, amount: u64, signature: &[u8]) -> Transaction {
+    Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: signature.to_vec(),
+        lock_height: None,
+        expiry_height: None,
+    }
+}
+
+/// Fixed transactions covering each field of the hash preimage
+fn sample_transactions() -> Vec<(&'static str, Transaction)> {
+    let locked = Transaction {
+        lock_height: Some(100),
+        ..transaction("alice", "carol", 25, &[])
+    };
+    let bounded = Transaction {
+        lock_height: Some(100),
+        expiry_height: Some(200),
+        ..transaction("bob", "carol", 7, &[0x30, 0x45, 0x02, 0x21, 0x00, 0xff])
+    };
+    let expiring = Transaction {
+        expiry_height: Some(50),
+        ..transaction("carol", "alice", 1, &[])
+    };
+    vec![
+        ("unsigned", transaction("alice", "bob", 10, &[])),
+        ("signed", transaction("alice", "bob", 10, &[1, 2, 3, 4, 254, 255])),
+        ("coinbase", transaction(COINBASE_ADDRESS, "miner", 50, &[])),
+        ("lock_height", locked),
+        ("expiry_height", expiring),
+        ("height_bounds", bounded),
+    ]
+}
+
+fn transaction_vector(name: &str, transaction: Transaction) -> TransactionVector {
+    TransactionVector {
+        name: name.to_string(),
+        txid: transaction.hash(),
+        signing_message: transaction.signing_message(),
+        hex: transaction.to_hex(),
+        transaction,
+    }
+}
+
+fn merkle_vector(name: &str, transactions: Vec<Transaction>) -> MerkleVector {
+    MerkleVector {
+        name: name.to_string(),
+        merkle_root: calculate_merkle_root(&transactions),
+        transactions,
+    }
+}
+
+fn block_vector(name: &str, block: Block) -> BlockVector {
+    BlockVector {
+        name: name.to_string(),
+        previous_hash: block.header.previous_hash,
+        transactions: block.transactions,
+        nonce: block.header.nonce,
+        timestamp: block.header.timestamp,
+        height: block.header.height,
+        merkle_root: block.header.merkle_root,
+        hash: block.header.hash,
+    }
+}
+
+impl TestVectors {
+    /// Hash the fixed inputs with the current code
+    pub fn generate() -> Self {
+        let samples = sample_transactions();
+        let txs: Vec<Transaction> = samples.iter().map(|(_, tx)| tx.clone()).collect();
+
+        let transactions = samples.into_iter()
+            .map(|(name, tx)| transaction_vector(name, tx))
+            .collect();
+
+        // Odd counts exercise the duplicated last hash on each level
+        let merkle_roots = vec![
+            merkle_vector("empty", vec![]),
+            merkle_vector("one", txs[..1].to_vec()),
+            merkle_vector("two", txs[..2].to_vec()),
+            merkle_vector("three", txs[..3].to_vec()),
+            merkle_vector("five", txs[..5].to_vec()),
+        ];
+
+        let genesis = genesis_block();
+        let child = Block::new(genesis.header.hash.clone(), vec![], 7, genesis.header.timestamp + 60, 1);
+        let with_transactions = Block::new(child.header.hash.clone(), txs[2..].to_vec(), 42, child.header.timestamp + 60, 2);
+        let blocks = vec![
+            block_vector("genesis", genesis),
+            block_vector("empty", child),
+            block_vector("with_transactions", with_transactions),
+        ];
+
+        TestVectors { transactions, merkle_roots, blocks }
+    }
+
+    /// Load the golden files from `dir`
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        Ok(TestVectors {
+            transactions: read_file(&dir.join(TRANSACTIONS_FILE))?,
+            merkle_roots: read_file(&dir.join(MERKLE_ROOTS_FILE))?,
+            blocks: read_file(&dir.join(BLOCKS_FILE))?,
+        })
+    }
+
+    /// Write the golden files to `dir`, creating it if needed
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), String> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+        write_file(&dir.join(TRANSACTIONS_FILE), &self.transactions)?;
+        write_file(&dir.join(MERKLE_ROOTS_FILE), &self.merkle_roots)?;
+        write_file(&dir.join(BLOCKS_FILE), &self.blocks)
+    }
+
+    /// Recompute every vector from its inputs, returning one message per
+    /// value the current code no longer reproduces
+    pub fn check(&self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        let mut compare = |file: &str, name: &str, field: &str, expected: &str, actual: String| {
+            if expected != actual {
+                mismatches.push(format!("{} '{}': {} is {}, expected {}", file, name, field, actual, expected));
+            }
+        };
+
+        for vector in &self.transactions {
+            let tx = &vector.transaction;
+            compare(TRANSACTIONS_FILE, &vector.name, "txid", &vector.txid, tx.hash());
+            compare(TRANSACTIONS_FILE, &vector.name, "signing_message", &vector.signing_message, tx.signing_message());
+            compare(TRANSACTIONS_FILE, &vector.name, "hex", &vector.hex, tx.to_hex());
+        }
+        for vector in &self.merkle_roots {
+            compare(MERKLE_ROOTS_FILE, &vector.name, "merkle_root", &vector.merkle_root, calculate_merkle_root(&vector.transactions));
+        }
+        for vector in &self.blocks {
+            let block = Block::new(vector.previous_hash.clone(), vector.transactions.clone(), vector.nonce, vector.timestamp, vector.height);
+            compare(BLOCKS_FILE, &vector.name, "merkle_root", &vector.merkle_root, block.header.merkle_root);
+            compare(BLOCKS_FILE, &vector.name, "hash", &vector.hash, block.header.hash);
+        }
+        mismatches
+    }
+
+    /// Names of the vectors, in file order
+    pub fn names(&self) -> Vec<String> {
+        self.transactions.iter().map(|v| format!("{}:{}", TRANSACTIONS_FILE, v.name))
+            .chain(self.merkle_roots.iter().map(|v| format!("{}:{}", MERKLE_ROOTS_FILE, v.name)))
+            .chain(self.blocks.iter().map(|v| format!("{}:{}", BLOCKS_FILE, v.name)))
+            .collect()
+    }
+}
+
+pub(crate) fn read_file<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&data)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+pub(crate) fn write_file<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Failed to serialize test vectors: {}", e))?;
+    fs::write(path, data + "\n")
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn golden() -> TestVectors {
+        TestVectors::load(Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN_DIR))
+            .expect("golden test vectors should load")
+    }
+
+    #[test]
+    fn test_golden_vectors_match() {
+        let mismatches = golden().check();
+        assert!(mismatches.is_empty(), "consensus hashes changed:\n{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn test_golden_files_cover_generated_vectors() {
+        // A vector added to `generate` must also be added to the golden files
+        assert_eq!(golden().names(), TestVectors::generate().names());
+        assert!(TestVectors::generate().check().is_empty());
+    }
+
+    #[test]
+    fn test_changed_hash_is_reported() {
+        let mut vectors = golden();
+        vectors.blocks[0].hash = "00".repeat(32);
+        vectors.transactions[1].transaction.signature.push(0);
+
+        let mismatches = vectors.check();
+        assert_eq!(mismatches.len(), 3); // txid and hex of the transaction, block hash
+        assert!(mismatches.iter().any(|m| m.starts_with("blocks.json 'genesis': hash")));
+    }
+}
@@ -0,0 +1,111 @@
+//! Ordering of block timestamps
+//!
+//! Block timestamps are whole seconds, so several blocks mined in the same second
+//! carry equal timestamps, and a block may be stamped before its parent as long
+//! as it is not earlier than the median time past: the median timestamp of the
+//! `MEDIAN_TIME_SPAN` blocks before it. Consensus does not require timestamps to
+//! increase. Anything that measures time across blocks uses their effective
+//! times instead: the largest timestamp seen so far along the chain. Blocks with
+//! the same effective time are ordered by height, an interval is never
+//! negative, and a span of blocks all mined in one second takes no time: their
+//! average interval is zero and a rate over them is unknown rather than a
+//! division by zero.
+//!
+//! New blocks are never stamped earlier than their parent: miners take the later
+//! of the clock and the parent's timestamp.
+
+use crate::blockchain::block::Block;
+
+/// Number of blocks before a new block whose median timestamp bounds it below
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Median of `timestamps`, the earliest time a block following them may carry;
+/// zero when there are none
+pub fn median_time_past(timestamps: &[u64]) -> u64 {
+    let mut sorted = timestamps.to_vec();
+    sorted.sort_unstable();
+    sorted.get(sorted.len() / 2).copied().unwrap_or(0)
+}
+
+/// Timestamp for a block mined at `now` on a parent stamped `parent_timestamp`
+pub fn next_timestamp(now: u64, parent_timestamp: u64) -> u64 {
+    now.max(parent_timestamp)
+}
+
+/// Seconds from the effective time `previous` of the block before to a block
+/// stamped `timestamp`; zero when the block is not later
+pub fn block_interval(previous: u64, timestamp: u64) -> u64 {
+    timestamp.saturating_sub(previous)
+}
+
+/// Seconds between the effective times of the first and last of `blocks`, given
+/// in height order after a block stamped `previous` (zero when they start the
+/// chain). A first block stamped before `previous` takes its time, so a
+/// backdated start cannot stretch the span.
+pub fn timespan(previous: u64, blocks: &[Block]) -> u64 {
+    let Some(first) = blocks.first() else {
+        return 0;
+    };
+    let latest = blocks.iter().map(|b| b.header.timestamp).max().unwrap_or(0);
+    block_interval(first.header.timestamp.max(previous), latest)
+}
+
+/// Average seconds per block interval, None when there are no intervals
+pub fn average_interval(span: u64, intervals: u64) -> Option<f64> {
+    (intervals > 0).then(|| span as f64 / intervals as f64)
+}
+
+/// `count` per second over `span` seconds, None when no time passed
+pub fn per_second(count: u64, span: u64) -> Option<f64> {
+    (span > 0).then(|| count as f64 / span as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(timestamps: &[u64]) -> Vec<Block> {
+        timestamps.iter().enumerate()
+            .map(|(height, timestamp)| Block::new("0".to_string(), vec![], 0, *timestamp, height as u64))
+            .collect()
+    }
+
+    #[test]
+    fn test_same_second_blocks() {
+        assert_eq!(timespan(0, &blocks(&[100, 100, 100])), 0);
+        assert_eq!(average_interval(0, 2), Some(0.0));
+        assert_eq!(average_interval(0, 0), None);
+        assert_eq!(average_interval(120, 2), Some(60.0));
+        assert_eq!(per_second(3, 0), None);
+        assert_eq!(per_second(3, 2), Some(1.5));
+        assert_eq!(timespan(0, &blocks(&[])), 0);
+        assert_eq!(timespan(0, &blocks(&[100])), 0);
+    }
+
+    #[test]
+    fn test_non_monotonic_timestamps() {
+        // A block stamped before its parent takes the parent's time
+        assert_eq!(block_interval(160, 130), 0);
+        assert_eq!(block_interval(160, 200), 40);
+        assert_eq!(timespan(0, &blocks(&[100, 160, 130])), 60);
+        // A span whose last block steps backwards still covers the latest time
+        assert_eq!(timespan(0, &blocks(&[100, 300, 50])), 200);
+        assert_eq!(timespan(0, &blocks(&[100, 50])), 0);
+
+        // A backdated first block takes the time of the block before the span
+        assert_eq!(timespan(150, &blocks(&[100, 160, 200])), 50);
+        assert_eq!(timespan(90, &blocks(&[100, 160, 200])), 100);
+
+        assert_eq!(next_timestamp(90, 100), 100);
+        assert_eq!(next_timestamp(120, 100), 120);
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        assert_eq!(median_time_past(&[]), 0);
+        assert_eq!(median_time_past(&[100]), 100);
+        assert_eq!(median_time_past(&[300, 100, 200]), 200);
+        // One stray timestamp cannot drag the median along
+        assert_eq!(median_time_past(&[100, 110, 120, 130, 9_999]), 120);
+    }
+}
@@ -1,8 +1,29 @@
 use ed25519_dalek::SigningKey;
 use rand::rngs::OsRng;
 
+/// Version byte of WIF-style private key strings
+pub const WIF_VERSION: u8 = 0x80;
+
 pub fn generate_keypair() -> SigningKey {
 	let mut csprng = OsRng;
 	SigningKey::generate(&mut csprng)
 }
 
+/// A private key as a WIF-style string: Base58Check of the version byte and the key
+pub fn encode_wif(key: &[u8; 32]) -> String {
+	bs58::encode(key).with_check_version(WIF_VERSION).into_string()
+}
+
+/// Parse a private key given as 64 hex characters or as a WIF-style string
+pub fn parse_private_key(data: &str) -> Result<[u8; 32], String> {
+	let data = data.trim();
+	if data.len() == 64 && data.chars().all(|c| c.is_ascii_hexdigit()) {
+		let bytes = hex::decode(data).map_err(|e| format!("Invalid private key: {}", e))?;
+		return bytes.try_into().map_err(|_| "Invalid private key: expected 32 bytes".to_string());
+	}
+	let bytes = bs58::decode(data).with_check(Some(WIF_VERSION)).into_vec()
+		.map_err(|_| "Invalid private key: expected 64 hex characters or a WIF key with a valid checksum".to_string())?;
+	// The version byte comes first
+	bytes.get(1..).and_then(|key| key.try_into().ok())
+		.ok_or_else(|| "Invalid private key: a WIF key holds 32 bytes".to_string())
+}
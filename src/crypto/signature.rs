@@ -1,6 +1,9 @@
 use ed25519_dalek::{VerifyingKey, Signature, Verifier};
 
+use crate::perf;
+
 pub fn verify_signature(public_key: &VerifyingKey, message: &[u8], signature: &[u8]) -> bool {
+    let _timer = perf::timer(perf::SIGNATURE_CHECK);
     if signature.len() != 64 {
         return false;
     }
@@ -0,0 +1,298 @@
+//! Running a node in the background
+//!
+//! `start-node --daemon` starts the node again as a child process in its own
+//! process group, with stdin closed and its output appended to a log file, and
+//! returns once the child has written its PID file and is still running after a
+//! short grace period. The node writes its PID and start time to a file for as
+//! long as it runs; `stop` reads that file, checks that the process with that
+//! PID started at the recorded time, so a PID reused after a crash is left
+//! alone, and sends SIGTERM, on which the node shuts its server down and exits
+//! through the normal path, so databases are closed and the data directory lock
+//! is released. Supervisors can skip `--daemon` and run
+//! `start-node --pid-file=<path>` in the foreground.
+
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::storage::lock::process_running;
+use crate::status;
+
+/// PID file in the data directory used when no `--pid-file` is given
+pub const PID_FILE: &str = "rust_chain.pid";
+
+/// Log file in the data directory a daemonized node writes to by default
+pub const LOG_FILE: &str = "node.log";
+
+/// How long `stop` waits for the node to exit by default
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `--daemon` waits for the background node to write its PID file
+pub const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the background node must keep running after writing its PID file
+/// for `--daemon` to report it started
+pub const STARTUP_GRACE: Duration = Duration::from_secs(1);
+
+/// PID of the running process, written to a file that is removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    pid: u32,
+}
+
+/// A process named in a PID file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PidRecord {
+    pub pid: u32,
+    /// When the process started, as `process_start_time` gave it
+    pub started: Option<String>,
+}
+
+impl PidRecord {
+    /// Whether the process that wrote the file is still running: some process
+    /// has its PID and started at the recorded time. None when the file has no
+    /// start time to check.
+    pub fn is_running(&self) -> Option<bool> {
+        if process_running(self.pid) == Some(false) {
+            return Some(false);
+        }
+        let started = self.started.as_ref()?;
+        Some(process_start_time(self.pid).as_ref() == Some(started))
+    }
+}
+
+impl PidFile {
+    pub fn path_in(data_dir: &str) -> PathBuf {
+        Path::new(data_dir).join(PID_FILE)
+    }
+
+    /// Write this process's PID and start time to `path`, refusing if the file
+    /// names another process that is still running
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let pid = std::process::id();
+        if let Some(other) = Self::read(&path)?
+            && other.pid != pid
+            && other.is_running() != Some(false)
+        {
+            return Err(format!("PID file {} names process {}, which may still be running", path.display(), other.pid));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let started = process_start_time(pid).map(|started| format!("{}\n", started)).unwrap_or_default();
+        fs::write(&path, format!("{}\n{}", pid, started))
+            .map_err(|e| format!("Failed to write PID file: {}", e))?;
+        Ok(PidFile { path, pid })
+    }
+
+    /// Process named in the file at `path`, or None if there is no such file.
+    /// Files from before start times were recorded hold only the PID.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Option<PidRecord>, String> {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(data) => {
+                let mut lines = data.lines();
+                let pid = lines.next().and_then(|line| line.trim().parse::<u32>().ok())
+                    .ok_or_else(|| format!("Invalid PID file {}", path.as_ref().display()))?;
+                let started = lines.next().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string);
+                Ok(Some(PidRecord { pid, started }))
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read PID file: {}", e)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Leave a file another process has written since alone
+        if matches!(Self::read(&self.path), Ok(Some(record)) if record.pid == self.pid) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Run this executable again with `args` in the background, appending its output
+/// to `log_path`, and wait for it to write `pid_path` and keep running for
+/// `STARTUP_GRACE`. Returns the child's PID.
+pub fn spawn_background<P: AsRef<Path>, Q: AsRef<Path>>(args: &[String], log_path: P, pid_path: Q) -> Result<u32, String> {
+    let (log_path, pid_path) = (log_path.as_ref(), pid_path.as_ref());
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+    }
+    let log = OpenOptions::new().create(true).append(true).open(log_path)
+        .map_err(|e| format!("Failed to open log file {}: {}", log_path.display(), e))?;
+    let log_err = log.try_clone()
+        .map_err(|e| format!("Failed to open log file {}: {}", log_path.display(), e))?;
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("Failed to find the executable: {}", e))?;
+
+    let mut command = Command::new(exe);
+    command.args(args)
+        .stdin(Stdio::null())
+        .stdout(log)
+        .stderr(log_err);
+    // Out of the terminal's process group, so closing it or Ctrl+C leaves the node running
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command.spawn()
+        .map_err(|e| format!("Failed to start background node: {}", e))?;
+    let pid = child.id();
+
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    let mut written_at = None;
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| format!("Failed to check background node: {}", e))? {
+            return Err(format!("Background node exited during startup ({}); see {}", status, log_path.display()));
+        }
+        match written_at {
+            Some(written_at) if Instant::now() >= written_at + STARTUP_GRACE => return Ok(pid),
+            Some(_) => {},
+            None if matches!(PidFile::read(pid_path), Ok(Some(record)) if record.pid == pid) => written_at = Some(Instant::now()),
+            None if Instant::now() >= deadline => {
+                return Err(format!(
+                    "Background node (PID {}) did not write {} within {}s; see {}",
+                    pid, pid_path.display(), STARTUP_TIMEOUT.as_secs(), log_path.display()
+                ));
+            },
+            None => {},
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// When the process `pid` started, as `ps` reports it, or None where that
+/// cannot be told. Together with the PID it names one process, since a PID is
+/// only reused by a process started later.
+pub fn process_start_time(pid: u32) -> Option<String> {
+    if !cfg!(unix) {
+        return None;
+    }
+    let output = Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let started = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !started.is_empty()).then_some(started)
+}
+
+/// Ask the process `pid` to shut down
+pub fn signal_stop(pid: u32) -> Result<(), String> {
+    if cfg!(unix) {
+        let status = Command::new("kill")
+            .args(["-TERM", &pid.to_string()])
+            .status()
+            .map_err(|e| format!("Failed to run kill: {}", e))?;
+        if !status.success() {
+            return Err(format!("Failed to signal process {}", pid));
+        }
+        Ok(())
+    } else {
+        Err("Stopping a background node is only supported on Unix".to_string())
+    }
+}
+
+/// Wait up to `timeout` for the process in `record` to exit. Returns whether it
+/// did, or None where that cannot be checked.
+pub fn wait_for_exit(record: &PidRecord, timeout: Duration) -> Option<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match record.is_running() {
+            Some(true) if Instant::now() < deadline => thread::sleep(Duration::from_millis(100)),
+            Some(running) => return Some(!running),
+            None => return None,
+        }
+    }
+}
+
+/// Call `shutdown` on a background thread when the process receives SIGTERM or Ctrl+C
+pub fn on_shutdown_signal<F: FnOnce() + Send + 'static>(shutdown: F) {
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Warning: Failed to watch for shutdown signals: {}", e);
+                return;
+            },
+        };
+        let received = runtime.block_on(wait_for_signal());
+        match received {
+            Ok(name) => status!("Received {}, shutting down", name),
+            Err(e) => {
+                eprintln!("Warning: Failed to watch for shutdown signals: {}", e);
+                return;
+            },
+        }
+        shutdown();
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => Ok("SIGTERM"),
+        result = tokio::signal::ctrl_c() => result.map(|_| "Ctrl+C"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    tokio::signal::ctrl_c().await.map(|_| "Ctrl+C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_lifecycle() {
+        let dir = std::env::temp_dir().join(format!("rust_chain_daemon_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join(PID_FILE);
+        assert_eq!(PidFile::read(&path).unwrap(), None);
+
+        let pid_file = PidFile::create(&path).unwrap();
+        let record = PidFile::read(pid_file.path()).unwrap().unwrap();
+        assert_eq!(record.pid, std::process::id());
+        assert_eq!(record.started, process_start_time(std::process::id()));
+        if record.started.is_some() {
+            assert_eq!(record.is_running(), Some(true));
+        }
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // A file left by a process that is gone is taken over
+        fs::write(&path, format!("{}\n", u32::MAX)).unwrap();
+        if process_running(u32::MAX) == Some(false) {
+            let pid_file = PidFile::create(&path).unwrap();
+            assert_eq!(PidFile::read(&path).unwrap().unwrap().pid, std::process::id());
+            drop(pid_file);
+        }
+
+        // The PID of a running process that started at another time was reused
+        let reused = PidRecord { pid: std::process::id(), started: Some("Thu Jan  1 00:00:00 1970".to_string()) };
+        if process_start_time(reused.pid).is_some() {
+            assert_eq!(reused.is_running(), Some(false));
+        }
+        assert_eq!(PidRecord { pid: std::process::id(), started: None }.is_running(), None);
+
+        fs::write(&path, "not a pid").unwrap();
+        assert!(PidFile::read(&path).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -7,5 +7,13 @@ pub mod network;
 pub mod consensus;
 pub mod mempool;
 pub mod rpc;
+pub mod config;
+pub mod notify;
+pub mod alerts;
+pub mod perf;
+pub mod clock;
+pub mod daemon;
+pub mod progress;
+pub mod scenario;
 
 pub use crypto::signature::verify_signature;
@@ -1,16 +1,102 @@
-use rust_chain::cli::{CLI, BlockchainCommands, MempoolCommands, MiningCommands, NetworkCommands, WalletCommands, AnalyticsCommands, TransactionCommands};
+use rust_chain::cli::{CLI, CliOptions, DEFAULT_DATA_DIR, BlockQuery, BlockchainCommands, MempoolCommands, MiningCommands, NetworkCommands, WalletCommands, AnalyticsCommands, TransactionCommands};
+use rust_chain::blockchain::amount::{display_amount, format_coins, parse_amount};
+use rust_chain::blockchain::analytics::AnalyticsInterval;
 use rust_chain::blockchain::block::Transaction;
+use rust_chain::blockchain::genesis::check_payout_address;
+use rust_chain::blockchain::multisig::multisig_address;
+use rust_chain::blockchain::vault::DEFAULT_VAULT_DELAY;
+use rust_chain::wallet::consolidate::DEFAULT_CONSOLIDATION_MAX_FEE_RATE;
+use rust_chain::blockchain::chain::{DEFAULT_CHECK_BLOCKS, DEFAULT_CHECK_LEVEL, DEFAULT_RICHEST_ADDRESSES, MAX_CHECK_LEVEL};
+use rust_chain::consensus::checkpoints::Checkpoints;
+use rust_chain::consensus::test_vectors::{TestVectors, GOLDEN_DIR};
+use rust_chain::network::conformance::ConformanceVectors;
+use rust_chain::daemon::{self, PidFile, DEFAULT_STOP_TIMEOUT, LOG_FILE};
+use rust_chain::cli::advanced_commands::MAX_VERIFY_LEVEL;
+use rust_chain::mempool::{DEFAULT_CONFIRM_TARGET, MAX_BLOCK_TRANSACTIONS};
+use rust_chain::network::PeerAddress;
+use rust_chain::network::server::NetworkServer;
+use rust_chain::notify::NotifyKind;
+use rust_chain::progress::{self, Progress, Verbosity};
+use rust_chain::scenario::{Scenario, ScenarioRunner, DEFAULT_SCENARIO_WAIT};
+use rust_chain::rpc::{parse_cli_params, split_cli_args, RpcClient, RpcConfig, DEFAULT_RPC_URL};
+use rust_chain::wallet::history::{HistoryCategory, MiningIncome};
+use rust_chain::wallet::offline::{UnsignedTransaction, DEFAULT_UNSIGNED_TX_FILE};
+use rust_chain::wallet::invoice::DEFAULT_INVOICE_EXPIRY;
+use rust_chain::wallet::registry::WalletRegistry;
+use serde_json::Value;
 use std::env;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Word the user types to confirm `show-seed` and `dump-privkey`
+const SEED_REVEAL_CONFIRMATION: &str = "REVEAL";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // Passed on unchanged to a node started in the background
+    let original_args: Vec<String> = args[1..].to_vec();
+    
+    let options = match parse_global_flags(&mut args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
     
     if args.len() < 2 {
         print_help();
         return;
     }
     
-    let mut cli = match CLI::new() {
+    // The CLI keeps the databases open, so a restore runs before one is created
+    if args[1] == "restore-node" {
+        if args.len() < 3 {
+            eprintln!("Usage: {} restore-node <path>", args[0]);
+            return;
+        }
+        match CLI::restore_node(&args[2], options.force_unlock) {
+            Ok((manifest, previous_path)) => {
+                println!("Restored node backup of height {} ({})", manifest.height, manifest.tip_hash);
+                println!("  Files verified: {}", manifest.files.len());
+                println!("  Previous data moved to: {}", previous_path);
+            },
+            Err(e) => eprintln!("Error restoring node: {}", e),
+        }
+        return;
+    }
+    
+    // Talks to a running node over JSON-RPC, so it opens none of the databases
+    // the node holds locked
+    if args[1] == "rpc" {
+        run_rpc_command(&args, options.wallet.as_deref());
+        return;
+    }
+    if matches!(args[1].as_str(), "get-connection-count" | "add-node" | "disconnect-node") {
+        run_peer_command(&args);
+        return;
+    }
+    
+    // The background node takes the data directory lock itself, and `stop`
+    // signals the node holding it, so neither creates a CLI here
+    if args[1] == "start-node" && args[2..].iter().any(|arg| arg == "--daemon") {
+        start_daemon(&original_args);
+        return;
+    }
+    if args[1] == "stop" {
+        stop_daemon(&args);
+        return;
+    }
+    
+    // Scenarios run on nodes of their own and never touch the data directory
+    if args[1] == "scenario" {
+        run_scenario(&args);
+        return;
+    }
+    
+    let mut cli = match CLI::new_with_options(options) {
         Ok(cli) => cli,
         Err(e) => {
             eprintln!("Error creating CLI: {}", e);
@@ -31,18 +117,26 @@ fn main() {
             cli.show_genesis_info();
         },
         "show-blocks" => {
-            cli.show_blocks();
+            let result = BlockQuery::parse(&args[2..])
+                .and_then(|query| cli.show_blocks(&query));
+            if let Err(e) = result {
+                eprintln!("Error showing blocks: {}", e);
+            }
         },
         "stats" | "chain-info" => {
             cli.show_stats();
         },
         "mine-block" => {
-            // Mine a block with a sample transaction
+            // Mine a block with a sample transaction, pinned to the next height so
+            // repeated runs don't produce a duplicate of an already confirmed one
+            let height = cli.chain.block_count();
             let tx = Transaction {
                 from: "alice".to_string(),
                 to: "bob".to_string(),
                 amount: 10,
                 signature: vec![],
+                lock_height: Some(height),
+                expiry_height: None,
             };
             
             if let Err(e) = cli.mine_block(vec![tx]) {
@@ -55,10 +149,27 @@ fn main() {
         "fork-stats" => {
             cli.show_fork_stats();
         },
+        "show-forks" => {
+            if let Err(e) = cli.show_forks() {
+                eprintln!("Error showing forks: {}", e);
+            }
+        },
+        "export-forks" => {
+            let format = match args.iter().position(|arg| arg == "--format") {
+                Some(i) => args.get(i + 1).cloned().unwrap_or_default(),
+                None => args.iter()
+                    .find_map(|arg| arg.strip_prefix("--format=").map(|format| format.to_string()))
+                    .unwrap_or_else(|| "dot".to_string()),
+            };
+            match cli.export_forks(&format) {
+                Ok(output) => println!("{}", output.trim_end()),
+                Err(e) => eprintln!("Error exporting forks: {}", e),
+            }
+        },
         "add-block" => {
             // Get transactions from mempool for the block
             let utxo_state = cli.get_current_utxo_state();
-            let transactions = cli.mempool.get_transactions_for_block(10, &utxo_state);
+            let transactions = cli.mempool.get_transactions_for_block(MAX_BLOCK_TRANSACTIONS, &utxo_state);
             
             if transactions.is_empty() {
                 eprintln!("No valid transactions in mempool to add to block. Use 'add-transaction' first.");
@@ -79,13 +190,81 @@ fn main() {
                 eprintln!("Error getting block: {}", e);
             }
         },
+        "get-header" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} get-header <hash|height>", args[0]);
+                return;
+            }
+            
+            if let Err(e) = cli.get_header(&args[2]) {
+                eprintln!("Error getting header: {}", e);
+            }
+        },
+        "invalidate-block" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} invalidate-block <hash>", args[0]);
+                return;
+            }
+            
+            if let Err(e) = cli.invalidate_block(&args[2]) {
+                eprintln!("Error invalidating block: {}", e);
+            }
+        },
+        "reconsider-block" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} reconsider-block <hash>", args[0]);
+                return;
+            }
+            
+            if let Err(e) = cli.reconsider_block(&args[2]) {
+                eprintln!("Error reconsidering block: {}", e);
+            }
+        },
+        "list-invalid-blocks" => {
+            cli.list_invalid_blocks();
+        },
         "start-node" => {
-            let address = args.get(2).unwrap_or(&"127.0.0.1".to_string()).clone();
-            let port = args.get(3)
+            let (flags, positional): (Vec<String>, Vec<String>) = args[2..].iter()
+                .cloned()
+                .partition(|arg| arg.starts_with("--"));
+            let rediscover = flags.iter().any(|arg| arg == "--rediscover");
+            let address = positional.first().cloned().unwrap_or("127.0.0.1".to_string());
+            let port = positional.get(1)
                 .and_then(|s| s.parse::<u16>().ok())
                 .unwrap_or(8333);
+            // `--rpc` serves JSON-RPC from the node itself, with peer management
+            let rpc_port = flags.iter().find_map(|arg| match arg.as_str() {
+                "--rpc" => Some("8545"),
+                arg => arg.strip_prefix("--rpc="),
+            });
+            let rpc = match rpc_port {
+                Some(port) => {
+                    let config = port.parse::<u16>()
+                        .map_err(|_| format!("Invalid RPC port: {}", port))
+                        .and_then(|port| parse_rpc_config(port, &flags));
+                    match config {
+                        Ok(config) => Some(config),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            return;
+                        }
+                    }
+                },
+                None => None,
+            };
+            // Removed again when the node exits
+            let _pid_file = match flags.iter().find_map(|arg| arg.strip_prefix("--pid-file=")) {
+                Some(path) => match PidFile::create(path) {
+                    Ok(pid_file) => Some(pid_file),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return;
+                    }
+                },
+                None => None,
+            };
             
-            if let Err(e) = cli.start_node(address, port) {
+            if let Err(e) = cli.start_node(address, port, rediscover, rpc) {
                 eprintln!("Error starting node: {}", e);
             }
         },
@@ -113,7 +292,15 @@ fn main() {
                 .and_then(|s| s.parse::<u16>().ok())
                 .unwrap_or(8545);
             
-            if let Err(e) = cli.start_rpc_server(port) {
+            let config = match parse_rpc_config(port, &args[2..]) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            
+            if let Err(e) = cli.start_rpc_server(config) {
                 eprintln!("Error starting RPC server: {}", e);
             }
         },
@@ -121,13 +308,18 @@ fn main() {
             let seed_nodes = if args.len() > 2 {
                 args[2..].to_vec()
             } else {
-                vec!["127.0.0.1:8334".to_string(), "127.0.0.1:8335".to_string()]
+                cli.default_seed_nodes()
             };
             
             if let Err(e) = cli.discover_peers(seed_nodes) {
                 eprintln!("Error discovering peers: {}", e);
             }
         },
+        "update-bootstrap" => {
+            if let Err(e) = cli.update_bootstrap(args[2..].to_vec()) {
+                eprintln!("Error updating bootstrap bundle: {}", e);
+            }
+        },
         "show-peers" => {
             if let Err(e) = cli.show_peers() {
                 eprintln!("Error showing peers: {}", e);
@@ -138,28 +330,118 @@ fn main() {
                 eprintln!("Error showing network stats: {}", e);
             }
         },
+        "propagation-stats" => {
+            let recent = match args.get(2).map(|arg| arg.parse::<usize>()) {
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    eprintln!("Invalid block count: {}", args[2]);
+                    return;
+                },
+                None => 10,
+            };
+            
+            if let Err(e) = cli.show_propagation_stats(recent) {
+                eprintln!("Error showing propagation stats: {}", e);
+            }
+        },
+        "list-alerts" => {
+            let recent = match args.get(2).map(|arg| arg.parse::<usize>()) {
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    eprintln!("Invalid alert count: {}", args[2]);
+                    return;
+                },
+                None => 20,
+            };
+            
+            if let Err(e) = cli.list_alerts(recent) {
+                eprintln!("Error listing alerts: {}", e);
+            }
+        },
+        "peer-history" => {
+            if let Err(e) = cli.show_peer_history(args.get(2).cloned()) {
+                eprintln!("Error showing peer history: {}", e);
+            }
+        },
+        "perf-report" => {
+            if let Err(e) = cli.show_perf_report() {
+                eprintln!("Error showing perf report: {}", e);
+            }
+        },
         "add-transaction" => {
-            if args.len() < 5 {
-                eprintln!("Usage: {} add-transaction <from> <to> <amount>", args[0]);
+            let (flags, positional): (Vec<String>, Vec<String>) = args[2..].iter()
+                .cloned()
+                .partition(|arg| arg.starts_with("--"));
+            let account = flags.iter().find_map(|arg| arg.strip_prefix("--account=")).map(str::to_string);
+            
+            // With --account the sender is picked from that account's addresses
+            let expected = if account.is_some() { 2 } else { 3 };
+            if positional.len() < expected {
+                eprintln!("Usage: {} add-transaction <from> <to> <amount> [--lock-height=<h>] [--expiry-height=<h>] [--fee-rate=<r>|--conf-target=<n>] [--yes] [--override-limits]", args[0]);
+                eprintln!("       {} add-transaction --account=<name> <to> <amount> [--lock-height=<h>] [--expiry-height=<h>] [--fee-rate=<r>|--conf-target=<n>] [--yes] [--override-limits]", args[0]);
                 return;
             }
+            let (to, amount_arg) = (&positional[expected - 2], &positional[expected - 1]);
             
-            let amount = match args[4].parse::<u64>() {
+            let amount = match parse_amount(amount_arg) {
                 Ok(a) => a,
-                Err(_) => {
-                    eprintln!("Invalid amount: {}", args[4]);
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            
+            let (lock_height, expiry_height) = match (
+                parse_height_flag(&flags, "--lock-height="),
+                parse_height_flag(&flags, "--expiry-height="),
+            ) {
+                (Ok(lock_height), Ok(expiry_height)) => (lock_height, expiry_height),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("{}", e);
                     return;
                 }
             };
             
+            let from = match &account {
+                Some(account) => match cli.select_funding_address(account, amount) {
+                    Ok(address) => address,
+                    Err(e) => {
+                        eprintln!("Error choosing funding address: {}", e);
+                        return;
+                    }
+                },
+                None => positional[0].clone(),
+            };
+            
+            // Without an explicit rate the fee is picked for the confirmation target
+            let fee_rate = flags.iter().find_map(|arg| arg.strip_prefix("--fee-rate=")).map(|value| {
+                value.parse::<f64>().ok().filter(|rate| *rate >= 0.0)
+                    .ok_or_else(|| format!("Invalid fee rate '{}'", value))
+            });
+            let conf_target = flags.iter().find_map(|arg| arg.strip_prefix("--conf-target=")).map(|value| {
+                value.parse::<u64>().ok().filter(|target| *target > 0)
+                    .ok_or_else(|| format!("Invalid confirmation target '{}'", value))
+            });
+            let fee_per_byte = match (fee_rate, conf_target.transpose()) {
+                (Some(Err(e)), _) | (_, Err(e)) => {
+                    eprintln!("{}", e);
+                    return;
+                },
+                (Some(Ok(rate)), _) => rate,
+                (None, Ok(target)) => cli.select_fee_rate(target.unwrap_or(DEFAULT_CONFIRM_TARGET)),
+            };
+            
             let tx = Transaction {
-                from: args[2].clone(),
-                to: args[3].clone(),
+                from,
+                to: to.clone(),
                 amount,
                 signature: vec![],
+                lock_height,
+                expiry_height,
             };
             
-            if let Err(e) = cli.add_transaction_to_mempool(tx) {
+            let override_limits = flags.iter().any(|arg| arg == "--override-limits");
+            if let Err(e) = cli.send_transaction(tx, Some(fee_per_byte), override_limits, confirm_prompt(&flags)) {
                 eprintln!("Error adding transaction: {}", e);
             }
         },
@@ -169,8 +451,63 @@ fn main() {
         "pending-transactions" => {
             cli.show_pending_transactions();
         },
+        "mempool-entry" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} mempool-entry <txid>", args[0]);
+                return;
+            }
+            
+            if let Err(e) = cli.show_mempool_entry(&args[2]) {
+                eprintln!("Error: {}", e);
+            }
+        },
+        "mempool-fees" => {
+            cli.show_fee_histogram();
+        },
+        "preview-block" => {
+            if let Err(e) = cli.preview_block() {
+                eprintln!("Error previewing block: {}", e);
+            }
+        },
+        "dump-mempool" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} dump-mempool <file>", args[0]);
+                return;
+            }
+            
+            if let Err(e) = cli.dump_mempool(&args[2]) {
+                eprintln!("Error dumping mempool: {}", e);
+            }
+        },
+        "diff-mempool" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} diff-mempool <file> | --peer=<address:port>", args[0]);
+                return;
+            }
+            
+            let result = match args[2].strip_prefix("--peer=") {
+                Some(peer) => match peer.parse::<PeerAddress>() {
+                    Ok(peer_address) => cli.diff_mempool_with_peer(&peer_address),
+                    Err(e) => Err(format!("Invalid peer address '{}': {}", peer, e)),
+                },
+                None => cli.diff_mempool(&args[2]),
+            };
+            if let Err(e) = result {
+                eprintln!("Error diffing mempool: {}", e);
+            }
+        },
         "mine-mempool" => {
-            if let Err(e) = cli.mine_block_from_mempool() {
+            let peer = match args.get(2).map(|arg| arg.strip_prefix("--peer=").unwrap_or(arg)) {
+                Some(peer) => match peer.parse::<PeerAddress>() {
+                    Ok(peer_address) => Some(peer_address),
+                    Err(e) => {
+                        eprintln!("Invalid peer address '{}': {}", peer, e);
+                        return;
+                    },
+                },
+                None => None,
+            };
+            if let Err(e) = cli.mine_block_from_mempool(peer.as_ref()) {
                 eprintln!("Error mining from mempool: {}", e);
             }
         },
@@ -182,27 +519,532 @@ fn main() {
                 eprintln!("Error in mempool demo: {}", e);
             }
         },
-        // **Phase 8 - Advanced Wallet Commands**
-        "generate-address" => {
-            match cli.generate_new_address() {
-                Ok(address) => println!("New address generated: {}", address),
-                Err(e) => eprintln!("Error generating address: {}", e),
+        "create-multisig" => {
+            if args.len() < 4 {
+                eprintln!("Usage: {} create-multisig <required> <public key>...", args[0]);
+                return;
+            }
+            
+            let result = args[2].parse::<usize>()
+                .map_err(|_| format!("Invalid signature count: {}", args[2]))
+                .and_then(|required| multisig_address(required, &args[3..]));
+            match result {
+                Ok(address) => println!("{}", address),
+                Err(e) => eprintln!("Error creating multisig address: {}", e),
+            }
+        },
+        "create-unsigned-tx" => {
+            let (flags, positional): (Vec<String>, Vec<String>) = args[2..].iter()
+                .cloned()
+                .partition(|arg| arg.starts_with("--"));
+            if positional.len() < 3 {
+                eprintln!("Usage: {} create-unsigned-tx <from> <to> <amount> [--lock-height=<h>] [--expiry-height=<h>] [--out=<file>]", args[0]);
+                return;
+            }
+            
+            let amount = match parse_amount(&positional[2]) {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            
+            let (lock_height, expiry_height) = match (
+                parse_height_flag(&flags, "--lock-height="),
+                parse_height_flag(&flags, "--expiry-height="),
+            ) {
+                (Ok(lock_height), Ok(expiry_height)) => (lock_height, expiry_height),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            
+            // Held to the spend limits when broadcast, which is when it is sent
+            let unsigned = UnsignedTransaction::new(Transaction {
+                from: positional[0].clone(),
+                to: positional[1].clone(),
+                amount,
+                signature: vec![],
+                lock_height,
+                expiry_height,
+            });
+            
+            let out = flags.iter().find_map(|arg| arg.strip_prefix("--out=")).unwrap_or(DEFAULT_UNSIGNED_TX_FILE);
+            match unsigned.save_to_file(out).and_then(|_| unsigned.to_hex()) {
+                Ok(hex) => {
+                    println!("Unsigned transaction written to {} ({} signature(s) required)", out, unsigned.required_signatures);
+                    println!("Hex: {}", hex);
+                },
+                Err(e) => eprintln!("Error creating unsigned transaction: {}", e),
+            }
+        },
+        "sign-tx" => {
+            let (flags, positional): (Vec<String>, Vec<String>) = args[2..].iter()
+                .cloned()
+                .partition(|arg| arg.starts_with("--"));
+            if positional.is_empty() {
+                eprintln!("Usage: {} sign-tx <file> [--key=<address>] [--out=<file>]", args[0]);
+                return;
+            }
+            
+            let key = flags.iter().find_map(|arg| arg.strip_prefix("--key="));
+            let out = flags.iter().find_map(|arg| arg.strip_prefix("--out=")).unwrap_or(&positional[0]);
+            
+            let result = UnsignedTransaction::load_from_file(&positional[0]).and_then(|mut unsigned| {
+                let public_key = cli.sign_unsigned_transaction(&mut unsigned, key)?;
+                unsigned.save_to_file(out)?;
+                Ok((public_key, unsigned))
+            });
+            match result {
+                Ok((public_key, unsigned)) => {
+                    println!("Signed with key {}", public_key);
+                    println!("Signatures: {} of {} written to {}", unsigned.signatures.len(), unsigned.required_signatures, out);
+                },
+                Err(e) => eprintln!("Error signing transaction: {}", e),
+            }
+        },
+        "broadcast-tx" => {
+            let (flags, files): (Vec<String>, Vec<String>) = args[2..].iter()
+                .cloned()
+                .partition(|arg| arg.starts_with("--"));
+            if files.is_empty() {
+                eprintln!("Usage: {} broadcast-tx <file> [more partially signed files...] [--peer=<addr:port>] [--yes] [--override-limits]", args[0]);
+                return;
+            }
+            
+            // Partial signatures from every file are merged before finalizing
+            let mut unsigned = match UnsignedTransaction::load_from_file(&files[0]) {
+                Ok(unsigned) => unsigned,
+                Err(e) => {
+                    eprintln!("Error loading transaction: {}", e);
+                    return;
+                }
+            };
+            for file in &files[1..] {
+                if let Err(e) = UnsignedTransaction::load_from_file(file).and_then(|other| unsigned.merge(&other)) {
+                    eprintln!("Error merging {}: {}", file, e);
+                    return;
+                }
+            }
+            
+            let tx = match unsigned.finalize() {
+                Ok(tx) => tx,
+                Err(e) => {
+                    eprintln!("Transaction is not ready to broadcast: {}", e);
+                    return;
+                }
+            };
+            
+            let override_limits = flags.iter().any(|arg| arg == "--override-limits");
+            if let Err(e) = cli.send_transaction(tx.clone(), None, override_limits, confirm_prompt(&flags)) {
+                eprintln!("Error adding transaction: {}", e);
+                return;
+            }
+            println!("  Transaction id: {}", tx.hash());
+            
+            if let Some(peer) = flags.iter().find_map(|arg| arg.strip_prefix("--peer=")) {
+                let result = peer.parse::<PeerAddress>()
+                    .and_then(|peer| NetworkServer::relay_transaction(&peer, &tx).map_err(|e| e.to_string()));
+                if let Err(e) = result {
+                    eprintln!("Error relaying transaction to {}: {}", peer, e);
+                }
+            }
+        },
+        "bump-fee" => {
+            let rate_at = args.iter().position(|arg| arg == "--rate");
+            let (flags, positional): (Vec<String>, Vec<String>) = args.iter().enumerate().skip(2)
+                .filter(|(i, _)| rate_at.is_none_or(|at| *i != at + 1))
+                .map(|(_, arg)| arg.clone())
+                .partition(|arg| arg.starts_with("--"));
+            let Some(txid) = positional.first() else {
+                eprintln!("Usage: {} bump-fee <txid> [--rate <fee per byte>] [--peer=<addr:port>]", args[0]);
+                return;
+            };
+            let rate = match rate_at.map(|at| args.get(at + 1)) {
+                Some(None) => {
+                    eprintln!("Usage: {} bump-fee <txid> [--rate <fee per byte>] [--peer=<addr:port>]", args[0]);
+                    return;
+                },
+                Some(Some(value)) => match value.parse::<f64>().ok().filter(|rate| *rate >= 0.0) {
+                    Some(rate) => Some(rate),
+                    None => {
+                        eprintln!("Invalid fee rate '{}'", value);
+                        return;
+                    }
+                },
+                None => None,
+            };
+            
+            let bump = match cli.bump_fee(txid, rate) {
+                Ok(bump) => bump,
+                Err(e) => {
+                    eprintln!("Error bumping fee: {}", e);
+                    return;
+                }
+            };
+            println!("Replaced transaction {} with {} at a higher fee", txid, bump.transaction.hash());
+            println!("  Fee rate: {:.4} -> {:.4} per byte", bump.previous_fee_per_byte, bump.fee_per_byte);
+            
+            if let Some(peer) = flags.iter().find_map(|arg| arg.strip_prefix("--peer=")) {
+                let result = peer.parse::<PeerAddress>().and_then(|peer| {
+                    NetworkServer::relay_transaction(&peer, &bump.transaction)
+                        .map_err(|e| e.to_string())
+                });
+                if let Err(e) = result {
+                    eprintln!("Error relaying transaction to {}: {}", peer, e);
+                }
+            }
+        },
+        // **Phase 8 - Advanced Wallet Commands**
+        "create-wallet" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} create-wallet <name>", args[0]);
+                return;
+            }
+            
+            match cli.create_wallet(&args[2]) {
+                Ok(address) => {
+                    println!("Wallet '{}' created, first address: {}", args[2], address);
+                    println!("Use it with --wallet={} or make it the default with load-wallet {}", args[2], args[2]);
+                },
+                Err(e) => eprintln!("Error creating wallet: {}", e),
+            }
+        },
+        "load-wallet" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} load-wallet <name>", args[0]);
+                return;
+            }
+            
+            match cli.load_wallet(&args[2]) {
+                Ok(()) => println!("Wallet '{}' loaded and set as the default ({} addresses)",
+                    args[2], cli.list_addresses().len()),
+                Err(e) => eprintln!("Error loading wallet: {}", e),
+            }
+        },
+        "list-wallets" => {
+            match cli.list_wallets() {
+                Ok(names) => {
+                    let selected = cli.wallet_name.as_deref();
+                    println!("Wallets:");
+                    println!("  wallet.json (unnamed){}", if selected.is_none() { " *" } else { "" });
+                    for name in &names {
+                        println!("  {}{}", name, if selected == Some(name.as_str()) { " *" } else { "" });
+                    }
+                },
+                Err(e) => eprintln!("Error listing wallets: {}", e),
+            }
+        },
+        "generate-address" => {
+            let result = match args.get(2) {
+                Some(account) => cli.generate_account_address(account),
+                None => cli.generate_new_address(),
+            };
+            match result {
+                Ok(address) => println!("New address generated: {}", address),
+                Err(e) => eprintln!("Error generating address: {}", e),
+            }
+        },
+        "get-receive-address" => {
+            let change = args[2..].iter().any(|arg| arg == "--change");
+            match cli.get_receive_address(change) {
+                Ok(address) if change => println!("Change address: {}", address),
+                Ok(address) => println!("Receive address: {}", address),
+                Err(e) => eprintln!("Error getting receive address: {}", e),
+            }
+        },
+        "create-account" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} create-account <name>", args[0]);
+                return;
+            }
+            
+            match cli.create_account(&args[2]) {
+                Ok(index) => println!("Account '{}' created (account index {})", args[2], index),
+                Err(e) => eprintln!("Error creating account: {}", e),
+            }
+        },
+        "list-accounts" => {
+            for account in cli.list_accounts() {
+                println!("{} (index {}): {} addresses, {} spendable, {} immature",
+                    account.name, account.index, account.addresses.len(), account.spendable, account.immature);
+                for address in &account.addresses {
+                    println!("  {}", address);
+                }
+            }
+        },
+        "create-invoice" => {
+            let (flags, positional): (Vec<String>, Vec<String>) = args[2..].iter()
+                .cloned()
+                .partition(|arg| arg.starts_with("--"));
+            if positional.is_empty() {
+                eprintln!("Usage: {} create-invoice <amount> [label] [--expiry=<seconds>]", args[0]);
+                return;
+            }
+            
+            let amount = match parse_amount(&positional[0]) {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let expiry = match flags.iter().find_map(|arg| arg.strip_prefix("--expiry=")) {
+                Some(value) => match value.parse::<u64>() {
+                    Ok(secs) => secs,
+                    Err(_) => {
+                        eprintln!("Invalid expiry: {}", value);
+                        return;
+                    }
+                },
+                None => DEFAULT_INVOICE_EXPIRY,
+            };
+            
+            match cli.create_invoice(amount, positional.get(1).map(String::as_str), expiry) {
+                Ok(invoice) => {
+                    println!("Invoice #{} created", invoice.id);
+                    println!("  Address: {}", invoice.address);
+                    println!("  Amount: {}", display_amount(invoice.amount));
+                    println!("  Payment URI: {}", invoice.to_uri());
+                },
+                Err(e) => eprintln!("Error creating invoice: {}", e),
+            }
+        },
+        "create-vault" => {
+            let (flags, positional): (Vec<String>, Vec<String>) = args[2..].iter()
+                .cloned()
+                .partition(|arg| arg.starts_with("--"));
+            if positional.len() < 2 {
+                eprintln!("Usage: {} create-vault <owner> <amount> [--delay=<blocks>] [--recovery=<address>] [--yes] [--override-limits]", args[0]);
+                return;
+            }
+            
+            let amount = match parse_amount(&positional[1]) {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let delay = match flags.iter().find_map(|arg| arg.strip_prefix("--delay=")) {
+                Some(value) => match value.parse::<u64>() {
+                    Ok(blocks) => blocks,
+                    Err(_) => {
+                        eprintln!("Invalid delay: {}", value);
+                        return;
+                    }
+                },
+                None => DEFAULT_VAULT_DELAY,
+            };
+            
+            let recovery = flags.iter().find_map(|arg| arg.strip_prefix("--recovery="));
+            
+            let override_limits = flags.iter().any(|arg| arg == "--override-limits");
+            match cli.create_vault(&positional[0], amount, delay, recovery, override_limits, confirm_prompt(&flags)) {
+                Ok(vault) => {
+                    println!("Vault address: {}", vault);
+                    println!("Spending from it takes an unvault and {} blocks of waiting", delay);
+                    if let Some(recovery) = recovery {
+                        println!("{} can claw the funds back at any time", recovery);
+                    }
+                },
+                Err(e) => eprintln!("Error creating vault: {}", e),
+            }
+        },
+        "unvault" => {
+            if args.len() < 4 {
+                eprintln!("Usage: {} unvault <vault-address> <amount>", args[0]);
+                return;
+            }
+            
+            let amount = match parse_amount(&args[3]) {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            
+            match cli.unvault(&args[2], amount) {
+                Ok(height) => println!("Unvault declared: {} can be spent from height {} at the earliest", display_amount(amount), height),
+                Err(e) => eprintln!("Error declaring unvault: {}", e),
+            }
+        },
+        "cancel-unvault" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} cancel-unvault <vault-address>", args[0]);
+                return;
+            }
+            
+            match cli.cancel_unvault(&args[2]) {
+                Ok(()) => println!("Unvault cancelled: the vault's funds are locked again"),
+                Err(e) => eprintln!("Error cancelling unvault: {}", e),
+            }
+        },
+        "clawback" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} clawback <vault-address> [amount]", args[0]);
+                return;
+            }
+            
+            let amount = match args.get(3).map(|amount| parse_amount(amount)).transpose() {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            
+            match cli.clawback(&args[2], amount) {
+                Ok(amount) => println!("Clawback sent: {} back to the vault's recovery address", display_amount(amount)),
+                Err(e) => eprintln!("Error clawing back vault funds: {}", e),
+            }
+        },
+        "spend-policy" => {
+            println!("Spend policy: {}", cli.wallet.spend_policy());
+            println!("Sent in the last 24 hours: {}", display_amount(cli.spent_today()));
+        },
+        "set-spend-policy" => {
+            let flags = &args[2..];
+            if flags.is_empty() {
+                eprintln!("Usage: {} set-spend-policy [--max-tx=<amount>|off] [--max-daily=<amount>|off] [--confirm-above=<amount>|off]", args[0]);
+                return;
+            }
+            
+            let mut policy = cli.wallet.spend_policy().clone();
+            let limits = [
+                ("--max-tx=", &mut policy.max_per_transaction),
+                ("--max-daily=", &mut policy.max_daily),
+                ("--confirm-above=", &mut policy.confirm_above),
+            ];
+            for (prefix, limit) in limits {
+                match parse_limit_flag(flags, prefix) {
+                    Ok(Some(value)) => *limit = value,
+                    Ok(None) => {},
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+            }
+            
+            match cli.set_spend_policy(policy.clone()) {
+                Ok(()) => println!("Spend policy set: {}", policy),
+                Err(e) => eprintln!("Error setting spend policy: {}", e),
+            }
+        },
+        "list-invoices" => {
+            match cli.list_invoices() {
+                Ok(invoices) if invoices.is_empty() => println!("No invoices found in wallet"),
+                Ok(invoices) => {
+                    for summary in invoices {
+                        let invoice = &summary.invoice;
+                        println!("#{} {} {}/{} {}{}{}",
+                            invoice.id, summary.status, format_coins(summary.received), format_coins(invoice.amount), invoice.address,
+                            invoice.label.as_ref().map(|label| format!(" ({})", label)).unwrap_or_default(),
+                            if summary.late > 0 { format!(", {} received after expiry", format_coins(summary.late)) } else { String::new() });
+                    }
+                },
+                Err(e) => eprintln!("Error listing invoices: {}", e),
+            }
+        },
+        "verify-payment" => {
+            if args.len() < 4 {
+                eprintln!("Usage: {} verify-payment <txid> <proof>", args[0]);
+                eprintln!("The proof is the hex from GET /tx/<txid>/proof on a node's RPC server");
+                return;
+            }
+            match cli.verify_payment(&args[2], &args[3]) {
+                Ok(payment) => {
+                    let tx = &payment.transaction;
+                    println!("Payment verified against the local header chain");
+                    println!("  From: {}", tx.from);
+                    println!("  To: {}{}", tx.to, if payment.to_wallet { " (this wallet)" } else { "" });
+                    println!("  Amount: {}", format_coins(tx.amount));
+                    println!("  Block: {} (height {})", payment.block_hash, payment.block_height);
+                    println!("  Confirmations: {}", payment.confirmations);
+                },
+                Err(e) => eprintln!("Payment not verified: {}", e),
             }
         },
         "list-addresses" => {
-            let addresses = cli.list_addresses();
+            if let Err(e) = cli.refresh_address_usage() {
+                eprintln!("Warning: Failed to check address usage: {}", e);
+            }
+            let addresses = cli.list_address_states();
             if addresses.is_empty() {
                 println!("No addresses found in wallet");
             } else {
                 println!("Wallet addresses:");
-                for (i, addr) in addresses.iter().enumerate() {
-                    println!("  {}: {}", i, addr);
+                for (i, (addr, state)) in addresses.iter().enumerate() {
+                    println!("  {}: {} ({})", i, addr, state.name());
                 }
             }
         },
         "show-seed" => {
-            println!("IMPORTANT: Keep this seed phrase safe and private!");
-            println!("Seed phrase: {}", cli.show_seed_phrase());
+            let flags = &args[2..];
+            if !flags.iter().any(|arg| arg == "--reveal") {
+                eprintln!("Usage: {} show-seed --reveal [--qr=<file>]", args[0]);
+                eprintln!("The seed phrase gives full control over the wallet's funds, so --reveal is required");
+                return;
+            }
+            
+            println!("WARNING: Anyone who sees the seed phrase can spend this wallet's funds.");
+            println!("Make sure nobody can see your screen or read the terminal history.");
+            print!("Type {} to continue: ", SEED_REVEAL_CONFIRMATION);
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer).is_err() || answer.trim() != SEED_REVEAL_CONFIRMATION {
+                eprintln!("Seed phrase not revealed");
+                return;
+            }
+            
+            match flags.iter().find_map(|arg| arg.strip_prefix("--qr=")) {
+                Some(path) => match cli.export_seed_qr(path) {
+                    Ok(()) => println!("Seed QR payload written to {} (delete it once the QR code is made)", path),
+                    Err(e) => eprintln!("Error exporting seed: {}", e),
+                },
+                None => match cli.reveal_seed_phrase() {
+                    Ok(seed_phrase) => println!("Seed phrase: {}", seed_phrase),
+                    Err(e) => eprintln!("Error revealing seed: {}", e),
+                },
+            }
+        },
+        "import-privkey" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} import-privkey <hex or WIF key>", args[0]);
+                return;
+            }
+            
+            match cli.import_private_key(&args[2]) {
+                Ok(address) => {
+                    println!("Imported key for address {}", address);
+                    println!("Imported keys are not restored from the seed phrase; keep a backup-wallet copy");
+                },
+                Err(e) => eprintln!("Error importing key: {}", e),
+            }
+        },
+        "dump-privkey" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} dump-privkey <address>", args[0]);
+                return;
+            }
+            
+            println!("WARNING: Anyone who sees this private key can spend the funds of {}.", args[2]);
+            print!("Type {} to continue: ", SEED_REVEAL_CONFIRMATION);
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer).is_err() || answer.trim() != SEED_REVEAL_CONFIRMATION {
+                eprintln!("Private key not revealed");
+                return;
+            }
+            
+            match cli.dump_private_key(&args[2]) {
+                Ok(key) => println!("Private key: {}", key),
+                Err(e) => eprintln!("Error revealing private key: {}", e),
+            }
         },
         "restore-wallet" => {
             if args.len() < 3 {
@@ -221,6 +1063,106 @@ fn main() {
             println!("  Total addresses: {}", stats.total_addresses);
             println!("  Next index: {}", stats.next_index);
             println!("  Master fingerprint: {}", stats.master_fingerprint);
+            println!("  Accounts: {}", stats.accounts);
+            println!("  Imported keys: {}", stats.imported_keys);
+            for account in cli.list_accounts() {
+                println!("    {}: {} addresses, {} spendable, {} immature",
+                    account.name, account.addresses.len(), format_coins(account.spendable), format_coins(account.immature));
+            }
+            match cli.get_wallet_history() {
+                Ok(history) => {
+                    let income = MiningIncome::from_history(&history);
+                    println!("  Mining income: {} from {} block(s), {} immature",
+                        format_coins(income.total), income.blocks, format_coins(income.immature));
+                },
+                Err(e) => eprintln!("Error reading wallet history: {}", e),
+            }
+        },
+        "wallet-history" => {
+            if let Some(path) = args.get(2).and_then(|arg| arg.strip_prefix("--csv=")) {
+                match cli.export_wallet_history(path) {
+                    Ok(count) => println!("Exported {} wallet history entries to {}", count, path),
+                    Err(e) => eprintln!("Error exporting wallet history: {}", e),
+                }
+            } else {
+                match cli.get_wallet_history() {
+                    Ok(history) if history.is_empty() => println!("No confirmed wallet transactions"),
+                    Ok(history) => {
+                        println!("Wallet history:");
+                        for entry in &history {
+                            let direction = if entry.category == HistoryCategory::Send { "to" } else { "from" };
+                            println!("  #{} {} {:<13} {} {} {}{}{}", entry.block_height, entry.txid, entry.category.as_str(),
+                                format_coins(entry.amount), direction, entry.counterparty,
+                                if entry.spendable { "" } else { " (immature)" },
+                                entry.replaces.as_deref().map(|txid| format!(" (replaced {})", txid)).unwrap_or_default());
+                        }
+                    },
+                    Err(e) => eprintln!("Error reading wallet history: {}", e),
+                }
+            }
+        },
+        "wallet-balance" => {
+            let balance = cli.get_wallet_balance();
+            println!("Wallet Balance ({} addresses):", balance.addresses);
+            println!("  Spendable: {}", display_amount(balance.spendable));
+            println!("  Immature: {}", display_amount(balance.immature));
+        },
+        "list-unspent" => {
+            let minconf = match args.get(2).map(|value| value.parse::<u64>()) {
+                Some(Ok(minconf)) => minconf,
+                Some(Err(_)) => {
+                    eprintln!("Usage: {} list-unspent [minconf]", args[0]);
+                    return;
+                },
+                None => 1,
+            };
+            
+            match cli.list_unspent(minconf) {
+                Ok(outputs) if outputs.is_empty() => println!("No unspent outputs with {} or more confirmations", minconf),
+                Ok(outputs) => {
+                    println!("Unspent outputs:");
+                    for output in &outputs {
+                        println!("  {}:{} {} to {} ({} confirmations{})", output.txid, output.vout, format_coins(output.amount),
+                            output.address, output.confirmations, if output.coinbase { ", coinbase" } else { "" });
+                    }
+                    println!("Total: {}", display_amount(outputs.iter().map(|output| output.amount).sum::<u64>()));
+                },
+                Err(e) => eprintln!("Error listing unspent outputs: {}", e),
+            }
+        },
+        "consolidate-utxos" => {
+            let flags = &args[2..];
+            let max_fee_rate = match flags.iter().find_map(|arg| arg.strip_prefix("--max-fee-rate=")) {
+                Some(value) => match value.parse::<f64>().ok().filter(|rate| *rate >= 0.0) {
+                    Some(rate) => rate,
+                    None => {
+                        eprintln!("Invalid fee rate '{}'", value);
+                        return;
+                    }
+                },
+                None => DEFAULT_CONSOLIDATION_MAX_FEE_RATE,
+            };
+            
+            let override_limits = flags.iter().any(|arg| arg == "--override-limits");
+            match cli.consolidate_utxos(max_fee_rate, override_limits, confirm_prompt(flags)) {
+                Ok(consolidation) => {
+                    let plan = &consolidation.plan;
+                    if plan.sweeps.is_empty() {
+                        println!("Nothing to consolidate");
+                    } else {
+                        println!("Consolidating {} outputs from {} addresses into {} at {:.4} per byte",
+                            plan.outputs(), plan.sweeps.len(), plan.destination, consolidation.fee_per_byte);
+                        for (sweep, txid) in plan.sweeps.iter().zip(&consolidation.txids) {
+                            println!("  {} from {} ({} outputs): {}", format_coins(sweep.amount), sweep.address, sweep.outputs, txid);
+                        }
+                        println!("Total: {}", display_amount(plan.amount()));
+                    }
+                    if !plan.dust.is_empty() {
+                        println!("Left as dust: {}", plan.dust.join(", "));
+                    }
+                },
+                Err(e) => eprintln!("Error consolidating outputs: {}", e),
+            }
         },
         "backup-wallet" => {
             let path = if args.len() > 2 {
@@ -244,6 +1186,56 @@ fn main() {
             println!("  Average block time: {} seconds", analytics.average_block_time_seconds);
             println!("  Chain start time: {}", analytics.chain_start_time);
             println!("  Latest block time: {}", analytics.chain_latest_time);
+            println!("  Reused addresses: {}", analytics.reused_addresses);
+            for address in &analytics.wallet_reused_addresses {
+                println!("  Warning: wallet address {} received more than one payment", address);
+            }
+            
+            let monetary = &analytics.monetary;
+            println!();
+            println!("Monetary Stats:");
+            println!("  Current subsidy: {}", monetary.current_subsidy);
+            println!("  Coins issued: {}", monetary.issued);
+            println!("  Expected at height {}: {}", monetary.height, monetary.expected_issued);
+            if monetary.issued != monetary.expected_issued {
+                println!("  Difference: {}", monetary.issued as i128 - monetary.expected_issued as i128);
+            }
+            println!("  Maximum supply: {}", monetary.max_supply);
+            match (monetary.next_halving_height, monetary.blocks_until_halving) {
+                (Some(next), Some(remaining)) => println!("  Next halving: height {} ({} blocks to go)", next, remaining),
+                _ => println!("  Next halving: none, emission has ended"),
+            }
+        },
+        "export-analytics" => {
+            let (path, interval) = match parse_export_analytics_args(&args[2..]) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    eprintln!("Usage: {} export-analytics <file> [--interval blocks|daily]", args[0]);
+                    drop(cli);
+                    std::process::exit(1);
+                }
+            };
+            
+            match cli.export_analytics(&path, interval) {
+                Ok(rows) => println!("Wrote {} rows of chain analytics to {}", rows, path),
+                Err(e) => {
+                    eprintln!("Error exporting analytics: {}", e);
+                    // Release the data directory before exiting
+                    drop(cli);
+                    std::process::exit(1);
+                }
+            }
+        },
+        "get-emission-schedule" => {
+            let height = cli.chain.height();
+            println!("Emission schedule (genesis allocation {}):", cli.params.genesis_allocation);
+            println!("  {:>4} {:>12} {:>12} {:>10} {:>16}", "era", "start", "end", "subsidy", "supply at end");
+            for era in cli.get_emission_schedule() {
+                let marker = if (era.start_height..=era.end_height).contains(&(height + 1)) { " <- current" } else { "" };
+                println!("  {:>4} {:>12} {:>12} {:>10} {:>16}{}",
+                    era.era, era.start_height, era.end_height, era.subsidy, era.supply_at_end, marker);
+            }
         },
         "block-stats" => {
             let height = if args.len() > 2 {
@@ -262,10 +1254,44 @@ fn main() {
                     println!("  Size: {} bytes", stats.size_bytes);
                     println!("  Nonce: {}", stats.nonce);
                     println!("  Previous hash: {}", stats.previous_hash);
+                    println!("  Subsidy: {}", stats.subsidy);
+                    println!("  Fees: {}", stats.total_fees);
+                    match stats.time_delta {
+                        Some(delta) => println!("  Time since previous block: {} seconds", delta),
+                        None => println!("  Time since previous block: n/a"),
+                    }
                 },
                 Err(e) => eprintln!("Error getting block stats: {}", e),
             }
         },
+        "chain-tx-stats" => {
+            let nblocks = if args.len() > 2 {
+                match args[2].parse::<u64>() {
+                    Ok(n) => Some(n),
+                    Err(_) => {
+                        eprintln!("Usage: {} chain-tx-stats [nblocks]", args[0]);
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+            
+            match cli.get_chain_tx_stats(nblocks) {
+                Ok(stats) => {
+                    println!("Chain Transaction Statistics:");
+                    println!("  Tip: {} ({})", stats.tip_height, stats.tip_hash);
+                    println!("  Total transactions: {}", stats.total_tx_count);
+                    println!("  Window: {} blocks, {} seconds", stats.window_block_count, stats.window_interval);
+                    println!("  Transactions in window: {}", stats.window_tx_count);
+                    match stats.tx_rate {
+                        Some(rate) => println!("  Transaction rate: {:.4} tx/s", rate),
+                        None => println!("  Transaction rate: n/a"),
+                    }
+                },
+                Err(e) => eprintln!("Error getting chain transaction stats: {}", e),
+            }
+        },
         "transaction-stats" => {
             let stats = cli.get_transaction_stats();
             println!("Transaction Statistics:");
@@ -288,6 +1314,143 @@ fn main() {
                 }
             }
         },
+        "verify-storage" => {
+            let repair = args.iter().any(|arg| arg == "--repair");
+            let level = match args.iter().skip(2).find(|arg| *arg != "--repair") {
+                Some(level) => match level.parse::<u8>() {
+                    Ok(level) => level,
+                    Err(_) => {
+                        eprintln!("Usage: {} verify-storage [level] [--repair]", args[0]);
+                        return;
+                    }
+                },
+                None => MAX_VERIFY_LEVEL,
+            };
+            
+            match cli.verify_storage(level, repair) {
+                Ok(report) => {
+                    println!("Storage Verification Report (level {}):", report.level);
+                    println!("  Blocks checked: {}", report.blocks_checked);
+                    println!("  Index entries checked: {}", report.index_entries_checked);
+                    println!("  Is valid: {}", report.is_ok());
+                    
+                    if !report.issues.is_empty() {
+                        println!("  Issues found:");
+                        for issue in &report.issues {
+                            println!("    - {}", issue);
+                        }
+                    }
+                    if report.repaired {
+                        println!("  Indexes rebuilt from stored blocks");
+                    } else if report.index_issues > 0 {
+                        println!("  Run with --repair to rebuild broken indexes");
+                    }
+                },
+                Err(e) => eprintln!("Error verifying storage: {}", e),
+            }
+        },
+        "verify-chain" => {
+            let usage = || eprintln!("Usage: {} verify-chain [checklevel 0-{}] [nblocks]", args[0], MAX_CHECK_LEVEL);
+            let level = match args.get(2).map(|arg| arg.parse::<u8>()) {
+                Some(Ok(level)) => level,
+                Some(Err(_)) => return usage(),
+                None => DEFAULT_CHECK_LEVEL,
+            };
+            let nblocks = match args.get(3).map(|arg| arg.parse::<u64>()) {
+                Some(Ok(nblocks)) => nblocks,
+                Some(Err(_)) => return usage(),
+                None => DEFAULT_CHECK_BLOCKS,
+            };
+            
+            let mut progress = Progress::new("Verifying chain", 0).with_unit("blocks");
+            let result = cli.verify_chain(level, nblocks, |checked, total| {
+                progress.set_total(total);
+                progress.set_position(checked);
+            });
+            progress.finish();
+            match result {
+                Ok(report) => {
+                    println!("Chain Verification Report (level {}):", report.level);
+                    println!("  Heights: {} to {}", report.from_height, report.to_height);
+                    println!("  Blocks checked: {}", report.blocks_checked);
+                    println!("  Result: {}", if report.is_ok() { "PASS" } else { "FAIL" });
+                    
+                    if !report.issues.is_empty() {
+                        println!("  Issues found:");
+                        for issue in &report.issues {
+                            println!("    - {}", issue);
+                        }
+                    }
+                },
+                Err(e) => eprintln!("Error verifying chain: {}", e),
+            }
+        },
+        "migrate-db" => {
+            match cli.migrate_db() {
+                Ok(migration) => match &migration.backup_path {
+                    Some(backup_path) => {
+                        println!("Migrated database from schema version {} to {}", migration.from_version, migration.to_version);
+                        println!("  Records rewritten: {}", migration.records_migrated);
+                        println!("  Backup: {}", backup_path);
+                    },
+                    None => println!("Database is already at schema version {}", migration.to_version),
+                },
+                Err(e) => eprintln!("Error migrating database: {}", e),
+            }
+        },
+        "backup-node" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} backup-node <path>", args[0]);
+                return;
+            }
+            
+            match cli.backup_node(&args[2]) {
+                Ok(manifest) => {
+                    println!("Backed up node at height {} to {}", manifest.height, args[2]);
+                    println!("  Files: {}", manifest.files.len());
+                    println!("  Size: {} bytes", manifest.files.iter().map(|file| file.size).sum::<u64>());
+                },
+                Err(e) => eprintln!("Error backing up node: {}", e),
+            }
+        },
+        "dump-test-vectors" => {
+            // Rewrites the golden files; only do this for an intended hashing change
+            let dir = args.get(2).map(String::as_str).unwrap_or(GOLDEN_DIR);
+            let vectors = TestVectors::generate();
+            match vectors.save(dir) {
+                Ok(()) => {
+                    println!("Wrote {} test vectors to {}", vectors.names().len(), dir);
+                    for mismatch in vectors.check() {
+                        eprintln!("Warning: {}", mismatch);
+                    }
+                },
+                Err(e) => eprintln!("Error writing test vectors: {}", e),
+            }
+            let conformance = ConformanceVectors::generate();
+            match conformance.save(dir) {
+                Ok(()) => {
+                    println!("Wrote {} peer protocol vectors to {}", conformance.names().len(), dir);
+                    for mismatch in conformance.check() {
+                        eprintln!("Warning: {}", mismatch);
+                    }
+                },
+                Err(e) => eprintln!("Error writing peer protocol vectors: {}", e),
+            }
+        },
+        "db-stats" => {
+            match cli.get_storage_stats() {
+                Ok(stats) => {
+                    println!("Database Statistics:");
+                    println!("  Estimated size: {} bytes", stats.database.estimated_size_bytes);
+                    println!("  Estimated keys: {}", stats.database.estimated_keys);
+                    for (name, cache) in [("Block cache", &stats.block_cache), ("Header cache", &stats.header_cache)] {
+                        println!("  {}: {} entries, {}/{} bytes", name, cache.entries, cache.used_bytes, cache.capacity_bytes);
+                        println!("    Hits: {}, Misses: {}, Hit rate: {:.1}%", cache.hits, cache.misses, cache.hit_rate() * 100.0);
+                    }
+                },
+                Err(e) => eprintln!("Error getting database stats: {}", e),
+            }
+        },
         // **Phase 8 - Transaction Persistence Commands**
         "get-transaction" => {
             if args.len() < 3 {
@@ -332,6 +1495,11 @@ fn main() {
                     if let Some(timestamp) = info.timestamp {
                         println!("  Timestamp: {}", timestamp);
                     }
+                    println!("  Final: {}", info.is_final);
+                    println!("  Status: {}", info.status.name());
+                    if let Some(previous_block_hash) = info.previous_block_hash {
+                        println!("  Previously In Block: {}", previous_block_hash);
+                    }
                 },
                 Ok(None) => println!("Transaction not found"),
                 Err(e) => eprintln!("Error getting transaction info: {}", e),
@@ -350,8 +1518,8 @@ fn main() {
                     } else {
                         println!("Transactions for address {}:", args[2]);
                         for (i, tx) in transactions.iter().enumerate() {
-                            println!("  {}. {} -> {} ({})", 
-                                i + 1, tx.from, tx.to, tx.amount);
+                            println!("  {}. {} -> {} ({} coins)", 
+                                i + 1, tx.from, tx.to, format_coins(tx.amount));
                             if let Some(height) = tx.block_height {
                                 println!("     Block: {}", height);
                             }
@@ -370,21 +1538,68 @@ fn main() {
         },
         "get-address-balance" => {
             if args.len() < 3 {
-                eprintln!("Usage: {} get-address-balance <address>", args[0]);
+                eprintln!("Usage: {} get-address-balance <address> [--at-height <n>]", args[0]);
                 return;
             }
+            let at_height = match args.iter().position(|arg| arg == "--at-height") {
+                Some(i) => args.get(i + 1).cloned(),
+                None => args.iter()
+                    .find_map(|arg| arg.strip_prefix("--at-height=").map(|height| height.to_string())),
+            };
+            let at_height = match at_height.map(|height| height.parse::<u64>()) {
+                Some(Ok(height)) => Some(height),
+                Some(Err(_)) => {
+                    eprintln!("Usage: {} get-address-balance <address> [--at-height <n>]", args[0]);
+                    return;
+                },
+                None => None,
+            };
             
-            match cli.get_address_balance(&args[2]) {
+            let balance = match at_height {
+                Some(height) => cli.get_address_balance_at(&args[2], height),
+                None => cli.get_address_balance(&args[2]),
+            };
+            match balance {
                 Ok(balance) => {
                     println!("Address Balance for {}:", balance.address);
-                    println!("  Current Balance: {}", balance.balance);
-                    println!("  Total Sent: {}", balance.total_sent);
-                    println!("  Total Received: {}", balance.total_received);
+                    match at_height {
+                        Some(height) => println!("  Balance at Height {}: {}", height, display_amount(balance.balance)),
+                        None => println!("  Current Balance: {}", display_amount(balance.balance)),
+                    }
+                    println!("  Total Sent: {}", display_amount(balance.total_sent));
+                    println!("  Total Received: {}", display_amount(balance.total_received));
                     println!("  Transaction Count: {}", balance.transaction_count);
                 },
                 Err(e) => eprintln!("Error getting address balance: {}", e),
             }
         },
+        "richest-addresses" => {
+            let count = match args.get(2).map(|s| s.parse::<usize>()) {
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    eprintln!("Usage: {} richest-addresses [count]", args[0]);
+                    return;
+                },
+                None => DEFAULT_RICHEST_ADDRESSES,
+            };
+            
+            match cli.get_richest_addresses(count) {
+                Ok(richest) if richest.is_empty() => println!("No addresses with a balance"),
+                Ok(richest) => {
+                    println!("Richest {} addresses:", richest.len());
+                    for (rank, balance) in richest.iter().enumerate() {
+                        println!("  {}. {} balance {} ({} transactions)", rank + 1, balance.address, balance.balance, balance.transaction_count);
+                    }
+                },
+                Err(e) => eprintln!("Error getting richest addresses: {}", e),
+            }
+        },
+        "reindex-addresses" => {
+            match cli.rebuild_address_index() {
+                Ok(count) => println!("Address index rebuilt for {} transactions", count),
+                Err(e) => eprintln!("Error rebuilding address index: {}", e),
+            }
+        },
         "help" | "--help" | "-h" => {
             print_help();
         },
@@ -393,6 +1608,412 @@ fn main() {
             print_help();
         }
     }
+    
+    cli.flush_notifications();
+}
+
+/// Strip global flags (`--txindex=0`, `--addressindex=1`, `--checkpoint=<height>:<hash>`,
+/// `--finality-depth=<n>`, cache sizes, relay policy, notification hooks, `--wallet=<name>`,
+/// `--bootstrap-url=<url>`, `--payout-address=<addr>`, `--force-unlock`, `--regtest`, `--mocktime=<secs>`
+/// and `--conf=<file>`)
+/// from the arguments
+fn parse_global_flags(args: &mut Vec<String>) -> Result<CliOptions, String> {
+    let mut options = CliOptions::default();
+    let mut remaining = Vec::new();
+    
+    // The config file goes first so flags can override it
+    if let Some(path) = args.iter().find_map(|arg| arg.strip_prefix("--conf=")) {
+        options.load_config(path)?;
+    }
+    
+    for arg in args.drain(..) {
+        if let Some(value) = arg.strip_prefix("--txindex=") {
+            options.index_config.txindex = parse_flag_value(value)?;
+        } else if let Some(value) = arg.strip_prefix("--addressindex=") {
+            options.index_config.addressindex = parse_flag_value(value)?;
+        } else if let Some(value) = arg.strip_prefix("--checkpoint=") {
+            let (height, hash) = Checkpoints::parse_checkpoint(value)?;
+            options.checkpoints.add(height, hash);
+        } else if let Some(value) = arg.strip_prefix("--finality-depth=") {
+            options.finality_depth = value.parse::<u64>()
+                .map_err(|_| format!("Invalid finality depth '{}'", value))?;
+        } else if let Some(value) = arg.strip_prefix("--blockcache=") {
+            options.block_cache_bytes = value.parse::<usize>()
+                .map_err(|_| format!("Invalid block cache size '{}'", value))?;
+        } else if let Some(value) = arg.strip_prefix("--headercache=") {
+            options.header_cache_bytes = value.parse::<usize>()
+                .map_err(|_| format!("Invalid header cache size '{}'", value))?;
+        } else if arg.starts_with("--conf=") {
+            continue;
+        } else if arg == "--quiet" || arg == "-q" {
+            progress::set_verbosity(Verbosity::Quiet);
+        } else if arg == "--verbose" || arg == "-v" {
+            progress::set_verbosity(Verbosity::Verbose);
+        } else if arg == "--force-unlock" {
+            options.force_unlock = true;
+        } else if arg == "--regtest" {
+            options.regtest = true;
+        } else if let Some(value) = arg.strip_prefix("--mocktime=") {
+            options.regtest = true;
+            options.mock_time = value.parse::<u64>()
+                .map_err(|_| format!("Invalid mock time '{}'", value))?;
+        } else if arg == "-blocksonly" || arg == "--blocksonly" {
+            options.relay_policy.blocks_only = true;
+        } else if let Some(value) = arg.strip_prefix("--minrelayfee=") {
+            options.relay_policy.min_relay_fee = value.parse::<f64>()
+                .map_err(|_| format!("Invalid minimum relay fee '{}'", value))?;
+        } else if let Some(value) = arg.strip_prefix("--maxtxsize=") {
+            options.relay_policy.max_tx_size = value.parse::<usize>()
+                .map_err(|_| format!("Invalid maximum transaction size '{}'", value))?;
+        } else if let Some(value) = arg.strip_prefix("--dustthreshold=") {
+            options.relay_policy.dust_threshold = parse_amount(value)
+                .map_err(|_| format!("Invalid dust threshold '{}'", value))?;
+        } else if let Some(command) = arg.strip_prefix("--blocknotify=") {
+            options.notify.add_command(NotifyKind::Block, command);
+        } else if let Some(command) = arg.strip_prefix("--reorgnotify=") {
+            options.notify.add_command(NotifyKind::Reorg, command);
+        } else if let Some(command) = arg.strip_prefix("--walletnotify=") {
+            options.notify.add_command(NotifyKind::WalletTransaction, command);
+        } else if let Some(url) = arg.strip_prefix("--notify-url=") {
+            options.notify.add_webhook(url);
+        } else if let Some(value) = arg.strip_prefix("--notify-timeout=") {
+            options.notify.timeout = Duration::from_secs(value.parse::<u64>()
+                .map_err(|_| format!("Invalid notification timeout '{}'", value))?);
+        } else if let Some(url) = arg.strip_prefix("--bootstrap-url=") {
+            options.bootstrap_urls.push(url.to_string());
+        } else if let Some(address) = arg.strip_prefix("--payout-address=") {
+            check_payout_address(address)?;
+            options.payout_address = Some(address.to_string());
+        } else if let Some(name) = arg.strip_prefix("--wallet=") {
+            WalletRegistry::validate_name(name)?;
+            options.wallet = Some(name.to_string());
+        } else if let Some(value) = arg.strip_prefix("--notify-retries=") {
+            options.notify.retries = value.parse::<u32>()
+                .map_err(|_| format!("Invalid notification retry count '{}'", value))?;
+        } else {
+            remaining.push(arg);
+        }
+    }
+    
+    *args = remaining;
+    options.index_config.validate()?;
+    Ok(options)
+}
+
+/// Build the RPC server config from `start-rpc` options
+fn parse_rpc_config(port: u16, args: &[String]) -> Result<RpcConfig, String> {
+    let mut config = RpcConfig {
+        bind_address: SocketAddr::from(([127, 0, 0, 1], port)),
+        ..Default::default()
+    };
+    
+    for arg in args {
+        let number = |value: &str| value.parse::<u64>()
+            .map_err(|_| format!("Invalid value in '{}'", arg));
+        
+        if let Some(value) = arg.strip_prefix("--timeout=") {
+            config.request_timeout = Duration::from_secs(number(value)?);
+        } else if let Some(value) = arg.strip_prefix("--max-inflight=") {
+            config.max_concurrent_requests = number(value)? as usize;
+        } else if let Some(value) = arg.strip_prefix("--slow-ms=") {
+            config.slow_request_threshold = Duration::from_millis(number(value)?);
+        } else if arg == "--no-access-log" {
+            config.access_log = false;
+        } else if let Some(path) = arg.strip_prefix("--socket=") {
+            config.unix_socket = Some(PathBuf::from(path));
+        }
+    }
+    
+    Ok(config)
+}
+
+/// Run `start-node` again in the background with the same arguments, writing
+/// a PID file and appending its output to `--log-file` (default `node.log` in
+/// the data directory)
+fn start_daemon(args: &[String]) {
+    let log_path = args.iter()
+        .find_map(|arg| arg.strip_prefix("--log-file="))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(DEFAULT_DATA_DIR).join(LOG_FILE));
+    let mut child_args: Vec<String> = args.iter()
+        .filter(|arg| *arg != "--daemon" && !arg.starts_with("--log-file="))
+        .cloned()
+        .collect();
+    let pid_path = match child_args.iter().find_map(|arg| arg.strip_prefix("--pid-file=")) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let path = PidFile::path_in(DEFAULT_DATA_DIR);
+            child_args.push(format!("--pid-file={}", path.display()));
+            path
+        }
+    };
+    
+    match daemon::spawn_background(&child_args, &log_path, &pid_path) {
+        Ok(pid) => {
+            println!("Node started in the background (PID {})", pid);
+            println!("  PID file: {}", pid_path.display());
+            println!("  Log: {}", log_path.display());
+        },
+        Err(e) => eprintln!("Error starting node: {}", e),
+    }
+}
+
+/// Signal the node named in the PID file to shut down and wait for it to exit:
+/// `stop [--pid-file=<path>] [--timeout=<s>]`
+fn stop_daemon(args: &[String]) {
+    let pid_path = args[2..].iter()
+        .find_map(|arg| arg.strip_prefix("--pid-file="))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PidFile::path_in(DEFAULT_DATA_DIR));
+    let timeout = match args[2..].iter().find_map(|arg| arg.strip_prefix("--timeout=")) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                eprintln!("Invalid timeout: {}", value);
+                return;
+            }
+        },
+        None => DEFAULT_STOP_TIMEOUT,
+    };
+    
+    let record = match PidFile::read(&pid_path) {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            eprintln!("No running node: {} not found", pid_path.display());
+            return;
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+    // A stale file may name a PID since taken by an unrelated process
+    match record.is_running() {
+        Some(true) => {},
+        Some(false) => {
+            eprintln!("No running node: process {} named in {} has exited", record.pid, pid_path.display());
+            return;
+        },
+        None => {
+            eprintln!("Cannot tell whether process {} is the node that wrote {}; stop it by hand", record.pid, pid_path.display());
+            return;
+        },
+    }
+    if let Err(e) = daemon::signal_stop(record.pid) {
+        eprintln!("Error stopping node: {}", e);
+        return;
+    }
+    println!("Sent shutdown signal to node (PID {})", record.pid);
+    match daemon::wait_for_exit(&record, timeout) {
+        Some(true) => println!("Node stopped"),
+        Some(false) => eprintln!("Node still running after {}s", timeout.as_secs()),
+        None => {},
+    }
+}
+
+/// Run the steps of a scenario file on fresh nodes, exiting with status 1 when
+/// one fails or the arguments are wrong so CI notices: `scenario <file> [--wait=<s>]`
+fn run_scenario(args: &[String]) {
+    let Some(path) = args.get(2).filter(|arg| !arg.starts_with("--")) else {
+        eprintln!("Usage: {} scenario <file> [--wait=<s>]", args[0]);
+        std::process::exit(1);
+    };
+    let wait = match args[3..].iter().find_map(|arg| arg.strip_prefix("--wait=")) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                eprintln!("Invalid wait: {}", value);
+                std::process::exit(1);
+            }
+        },
+        None => DEFAULT_SCENARIO_WAIT,
+    };
+    let scenario = match Scenario::load_from_file(path) {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    
+    let name = if scenario.name.is_empty() { path.as_str() } else { scenario.name.as_str() };
+    println!("=== Scenario: {} ({} steps) ===", name, scenario.steps.len());
+    let total = scenario.steps.len();
+    let mut runner = ScenarioRunner::new().with_wait(wait);
+    match runner.run(&scenario, |number, description| println!("[{}/{}] {}", number, total, description)) {
+        Ok(()) => println!("Scenario passed"),
+        Err(e) => {
+            eprintln!("Scenario failed: {}", e);
+            drop(runner);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// File and interval of `export-analytics <file> [--interval blocks|daily]`,
+/// also accepting `--interval=<interval>`. Any other argument is an error.
+fn parse_export_analytics_args(args: &[String]) -> Result<(String, AnalyticsInterval), String> {
+    let mut path = None;
+    let mut interval = "blocks";
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--interval" {
+            interval = rest.next().map(String::as_str).ok_or("--interval needs a value")?;
+        } else if let Some(value) = arg.strip_prefix("--interval=") {
+            interval = value;
+        } else if arg.starts_with("--") {
+            return Err(format!("Unknown option {}", arg));
+        } else if path.is_none() {
+            path = Some(arg.clone());
+        } else {
+            return Err(format!("Unexpected argument {}", arg));
+        }
+    }
+    let path = path.ok_or("Missing output file")?;
+    Ok((path, interval.parse()?))
+}
+
+/// Call a method on a running node's JSON-RPC server and print the result:
+/// `rpc <method> [params...] [--url=<url>]`, using the named wallet if `--wallet` was given.
+/// Exits with status 1 on a usage error or when the call fails.
+fn run_rpc_command(args: &[String], wallet: Option<&str>) {
+    let (url, positional) = match split_cli_args(&args[2..]) {
+        Ok(split) => split,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let Some(method) = positional.first() else {
+        eprintln!("Usage: {} rpc <method> [params...] [--url=<url>]", args[0]);
+        std::process::exit(1);
+    };
+    let mut client = RpcClient::new(url.unwrap_or_else(|| DEFAULT_RPC_URL.to_string()));
+    if let Some(wallet) = wallet {
+        client = client.with_wallet(wallet);
+    }
+    
+    match call_rpc(&client, method, parse_cli_params(&positional[1..])) {
+        // Strings print bare, so they can be used in scripts
+        Some(Value::String(result)) => println!("{}", result),
+        Some(Value::Null) => {},
+        Some(result) => println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default()),
+        None => std::process::exit(1),
+    }
+}
+
+/// Manage the peers of a node started with `start-node --rpc`, exiting with
+/// status 1 on a usage error or when the call fails
+fn run_peer_command(args: &[String]) {
+    let (url, positional) = match split_cli_args(&args[2..]) {
+        Ok(split) => split,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let (method, usage, param_count) = match args[1].as_str() {
+        "add-node" => ("addnode", "add-node <address> <add|remove|onetry>", 2),
+        "disconnect-node" => ("disconnectnode", "disconnect-node <address|node-id>", 1),
+        _ => ("getconnectioncount", "get-connection-count", 0),
+    };
+    if positional.len() != param_count {
+        eprintln!("Usage: {} {} [--url=<url>]", args[0], usage);
+        std::process::exit(1);
+    }
+    let client = RpcClient::new(url.unwrap_or_else(|| DEFAULT_RPC_URL.to_string()));
+    
+    // Addresses and node ids are sent as strings, even when they look like numbers
+    let params = (param_count > 0).then(|| Value::from(positional.clone()));
+    let Some(result) = call_rpc(&client, method, params) else {
+        std::process::exit(1);
+    };
+    match args[1].as_str() {
+        "add-node" => match positional[1].as_str() {
+            "add" => println!("Added node {}; the node keeps reconnecting to it", positional[0]),
+            "remove" => println!("Removed node {}", positional[0]),
+            _ => println!("Connected to {}", positional[0]),
+        },
+        "disconnect-node" => {
+            for peer in result.as_array().into_iter().flatten().filter_map(|peer| peer.as_str()) {
+                println!("Disconnected {}", peer);
+            }
+        },
+        _ => println!("{}", result),
+    }
+}
+
+/// Call `method` on a running node, printing any error. Returns the result,
+/// `Value::Null` when the method returns none.
+fn call_rpc(client: &RpcClient, method: &str, params: Option<Value>) -> Option<Value> {
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: Failed to create runtime: {}", e);
+            return None;
+        }
+    };
+    match runtime.block_on(client.call(method, params)) {
+        Ok(response) => match (response.result, response.error) {
+            (_, Some(error)) => {
+                eprintln!("Error {}: {}", error.code, error.message);
+                if let Some(data) = error.data {
+                    eprintln!("{}", data);
+                }
+                None
+            },
+            (result, None) => Some(result.unwrap_or(Value::Null)),
+        },
+        Err(e) => {
+            eprintln!("Error calling {} at {}: {}", method, client.base_url(), e);
+            None
+        },
+    }
+}
+
+/// Find an optional `<prefix><height>` argument
+fn parse_height_flag(args: &[String], prefix: &str) -> Result<Option<u64>, String> {
+    match args.iter().find_map(|arg| arg.strip_prefix(prefix)) {
+        Some(value) => value.parse::<u64>()
+            .map(Some)
+            .map_err(|_| format!("Invalid height '{}' for {}", value, prefix.trim_end_matches('='))),
+        None => Ok(None),
+    }
+}
+
+/// Ask a yes/no question on the terminal, answered up front by `--yes`, as
+/// sends above the confirmation threshold or overriding spend limits do
+fn confirm_prompt(flags: &[String]) -> impl FnOnce(&str) -> bool {
+    let yes = flags.iter().any(|arg| arg == "--yes");
+    move |prompt| {
+        if yes {
+            println!("{} Confirmed with --yes", prompt);
+            return true;
+        }
+        print!("{} [y/N]: ", prompt);
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+    }
+}
+
+/// Limit given as `--<name>=<amount>`, with `off` clearing it
+fn parse_limit_flag(args: &[String], prefix: &str) -> Result<Option<Option<u64>>, String> {
+    match args.iter().find_map(|arg| arg.strip_prefix(prefix)) {
+        Some("off") => Ok(Some(None)),
+        Some(value) => parse_amount(value)
+            .map(|limit| Some(Some(limit)))
+            .map_err(|e| format!("{} for {}", e, prefix.trim_end_matches('='))),
+        None => Ok(None),
+    }
+}
+
+fn parse_flag_value(value: &str) -> Result<bool, String> {
+    match value {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(format!("Invalid flag value '{}', expected 0 or 1", value)),
+    }
 }
 
 fn print_help() {
@@ -402,7 +2023,8 @@ fn print_help() {
     println!("  init-chain               Initialize a new blockchain");
     println!("  show-genesis             Show genesis block details");
     println!("  genesis-info             Show detailed genesis block information");
-    println!("  show-blocks              Show all blocks in the chain");
+    println!("  show-blocks [--from <h>] [--to <h>] [--last <n>] [--address <addr>] [--min-amount <n>] [--summary]");
+    println!("                           Show blocks in a height range, optionally filtered, one line each with --summary");
     println!("  stats                    Show blockchain statistics");
     println!("  chain-info               Show blockchain information (alias for stats)");
     println!("  help                     Show this help message");
@@ -411,42 +2033,149 @@ fn print_help() {
     println!("  mine-block               Mine a new block with sample transaction");
     println!("  mining-stats             Show mining statistics");
     println!("  fork-stats               Show fork choice statistics");
+    println!("  show-forks               Show known tips, fork heights, branch lengths and work");
+    println!("  export-forks [--format dot|json] Export the fork tree for Graphviz (default) or as JSON");
     println!("  add-block                Add a block using mempool transactions");
-    println!("  mine-mempool             Mine a block using mempool transactions");
+    println!("  mine-mempool [--peer=<address:port>]  Mine a block using mempool transactions, following the peer's mempool and tip");
+    println!("  preview-block            Show the transactions, fees, size and coinbase value of the next block, without mining");
     println!();
     println!("TRANSACTION & MEMPOOL:");
-    println!("  add-transaction <from> <to> <amount> Add transaction to mempool");
+    println!("  Amounts are in coins, e.g. 1 or 1.5 (1 coin = 10^8 base units)");
+    println!("  add-transaction <from> <to> <amount> [--lock-height=<h>] [--expiry-height=<h>]");
+    println!("                           Add transaction to mempool, optionally valid only from/until a block height");
+    println!("  add-transaction --account=<name> <to> <amount>  Same, paying from an address of the named account");
+    println!("                           --fee-rate=<r> pays r per byte; otherwise the fee is estimated to confirm");
+    println!("                           within --conf-target=<n> blocks (default 6)");
+    println!("  create-multisig <required> <public key>...  Print the address spendable by <required> of the given keys");
+    println!("  create-unsigned-tx <from> <to> <amount> [--out=<file>]");
+    println!("                           Write an unsigned transaction for offline signing (default: unsigned_tx.json)");
+    println!("  sign-tx <file> [--key=<address>] [--out=<file>]  Add this wallet's signature to an unsigned transaction");
+    println!("  broadcast-tx <file>... [--peer=<addr:port>]  Merge signatures, then submit to the mempool and optionally a peer");
+    println!("  bump-fee <txid> [--rate <r>] [--peer=<addr:port>]");
+    println!("                           Replace an unconfirmed wallet transaction at a higher fee rate");
     println!("  mempool-stats            Show mempool statistics");
     println!("  pending-transactions     Show all pending transactions");
+    println!("  mempool-entry <txid>     Show fee, size, time in pool and relatives of a pending transaction");
+    println!("  mempool-fees             Show mempool fee histogram and fee estimates");
+    println!("  dump-mempool <file>      Write a snapshot of the mempool (txids, fee rates, times)");
+    println!("  diff-mempool <file> | --peer=<address:port>");
+    println!("                           Compare the mempool with a snapshot or a peer's mempool");
     println!("  clear-mempool            Clear all transactions from mempool");
     println!("  demo-mempool             Demonstrate complete mempool workflow");
     println!();
     println!("NETWORKING COMMANDS:");
-    println!("  start-node [addr] [port] Start P2P network node (default: 127.0.0.1:8333)");
-    println!("  connect-peer <addr> <port> Connect to a peer");
-    println!("  start-rpc [port]         Start JSON-RPC server (default: 8545)");
+    println!("  start-node [addr] [port] [--rediscover] [--rpc[=<port>]] [--daemon] [--pid-file=<path>] [--log-file=<path>]");
+    println!("                           Start P2P network node (default: 127.0.0.1:8333); --daemon runs it in the background,");
+    println!("                           --rpc serves JSON-RPC with peer management (default port 8545)");
+    println!("  stop [--pid-file=<path>] [--timeout=<s>]");
+    println!("                           Shut down a background node and wait for it to exit");
+    println!("  connect-peer <addr> <port> Connect to a peer (IPv4, IPv6 or hostname)");
+    println!("  get-connection-count [--url=<url>]");
+    println!("                           Number of peers of a node started with --rpc");
+    println!("  add-node <addr> <add|remove|onetry> [--url=<url>]");
+    println!("                           Keep a running node connected to a peer, stop that, or connect once");
+    println!("  disconnect-node <addr|node-id> [--url=<url>]");
+    println!("                           Disconnect a running node from a peer");
+    println!("  start-rpc [port] [--timeout=<s>] [--max-inflight=<n>] [--slow-ms=<ms>] [--no-access-log] [--socket=<path>]");
+    println!("                           Start JSON-RPC server (default: 8545, 30s timeout, 16 in flight, 1000ms slow log)");
+    println!("  rpc <method> [params...] [--url=<url>]");
+    println!("                           Call a running node's JSON-RPC server (default: {}, unix:<path> for a socket)", DEFAULT_RPC_URL);
     println!("  discover-peers [seeds...] Discover peers using seed nodes");
+    println!("  update-bootstrap [urls...] Fetch and verify a signed seed/checkpoint bundle");
     println!("  show-peers               Show connected peers");
     println!("  network-stats            Show network statistics");
+    println!("  propagation-stats [n]    Propagation times and orphan rate of mined blocks (last n, default 10)");
+    println!("  list-alerts [n]          Reorgs, future blocks, invalid block spam and stalls (last n, default 20)");
+    println!("  peer-history [addr]      Connections, traffic, blocks and misbehavior of a peer across restarts,");
+    println!("                           or every peer by reliability");
+    println!("  perf-report              Timing percentiles of validation, DB writes, signatures and messages");
     println!();
     println!("WALLET COMMANDS:");
-    println!("  generate-address         Generate a new wallet address");
+    println!("  create-wallet <name>     Create a named wallet in the data directory");
+    println!("  load-wallet <name>       Make a named wallet the default for later commands");
+    println!("  list-wallets             List named wallets (* marks the selected one)");
+    println!("  generate-address [account] Generate a new wallet address (in the default or named account)");
+    println!("  get-receive-address [--change] Current receive address, rotated once it is paid (or a new change address)");
+    println!("  create-account <name>    Create a named account on the next BIP-44 account index");
+    println!("  list-accounts            List accounts with their addresses and balances");
+    println!("  create-invoice <amount> [label] [--expiry=<secs>] Request a payment to a fresh address");
+    println!("  list-invoices            List invoices with their paid/unpaid status");
+    println!("  verify-payment <txid> <proof> Check a transaction proof from GET /tx/<txid>/proof against local headers");
+    println!("  create-vault <owner> <amount> [--delay=<blocks>] [--recovery=<address>] Move funds into a time-locked vault (default delay 144)");
+    println!("  unvault <vault> <amount> Declare vault funds for spending once the vault delay has passed");
+    println!("  cancel-unvault <vault>   Cancel a vault's pending unvault");
+    println!("  clawback <vault> [amount] Move vault funds to its recovery address right away");
+    println!("  spend-policy             Show the wallet's spend limits and the amount sent in the last 24 hours");
+    println!("  set-spend-policy [--max-tx=<n>|off] [--max-daily=<n>|off] [--confirm-above=<n>|off]");
+    println!("                           Limit sends; breaking a limit needs --override-limits, which is logged");
     println!("  list-addresses           List all wallet addresses");
-    println!("  show-seed                Show wallet seed phrase (keep safe!)");
+    println!("  show-seed --reveal [--qr=<file>]  Show the seed phrase after typed confirmation, or write it as a SeedQR payload");
     println!("  restore-wallet \"<phrase>\" Restore wallet from seed phrase");
-    println!("  wallet-stats             Show wallet statistics");
+    println!("  import-privkey <key>     Add a private key given as hex or WIF, outside the seed");
+    println!("  dump-privkey <address>   Show an address's private key as WIF after typed confirmation");
+    println!("  wallet-stats             Show wallet statistics, including mining income");
+    println!("  wallet-history [--csv=<file>] Confirmed wallet transactions tagged receive, send or mining_income");
+    println!("  wallet-balance           Show spendable and immature (coinbase) wallet funds");
+    println!("  list-unspent [minconf]   List synthetic spendable outputs of the wallet addresses, read from balances (default 1 confirmation)");
+    println!("  consolidate-utxos [--max-fee-rate=<r>] [--override-limits] [--yes]  Sweep small wallet addresses into the largest at a low fee (default max {})", DEFAULT_CONSOLIDATION_MAX_FEE_RATE);
     println!("  backup-wallet [path]     Backup wallet to file (default: wallet_backup.json)");
     println!();
     println!("ANALYTICS COMMANDS:");
     println!("  analyze-chain            Comprehensive blockchain analysis");
+    println!("  export-analytics <file> [--interval blocks|daily]  Write a CSV time series of chain activity");
+    println!("  get-emission-schedule    Show block subsidy per halving era and total supply");
     println!("  block-stats [height]     Detailed statistics for a block");
+    println!("  chain-tx-stats [nblocks] Transaction rate over the last nblocks (default 30)");
     println!("  transaction-stats        Transaction statistics across the chain");
     println!("  validate-chain           Validate blockchain integrity");
+    println!("  verify-storage [level] [--repair]  Cross-check stored blocks and indexes (levels 0-3)");
+    println!("  verify-chain [checklevel] [nblocks]  Re-validate the last nblocks (default level 3, 6 blocks, 0 = all)");
     println!("  get-block <hash>         Get block by hash");
+    println!("  get-header <hash|height> Get a block header without its transactions");
+    println!("  invalidate-block <hash>  Disconnect a block and refuse it from now on");
+    println!("  reconsider-block <hash>  Clear an invalid mark and re-run fork choice");
+    println!("  list-invalid-blocks      List blocks refused for good, with the rule each broke");
+    println!("  db-stats                 Database size and block/header cache hit rates");
+    println!("  migrate-db               Upgrade stored blocks and transactions to the current format (backs up first)");
+    println!("  backup-node <path>       Snapshot databases, wallets and node state with a checksum manifest");
+    println!("  restore-node <path>      Verify a node backup and replace the data directory with it");
+    println!("  dump-test-vectors [dir]  Regenerate the consensus and peer protocol golden files (default: tests/vectors)");
+    println!("  scenario <file> [--wait=<s>]  Run a scripted scenario on fresh local nodes; exits 1 if a step fails");
+    println!("                           Assertions wait up to --wait seconds for nodes to catch up (default: 10)");
     println!();
     println!("TRANSACTION PERSISTENCE:");
     println!("  get-transaction <hash>   Get transaction by hash");
     println!("  get-transaction-info <hash> Get detailed transaction information");
     println!("  get-address-transactions <addr> Get all transactions for an address");
     println!("  get-address-balance <addr> Get address balance and transaction summary");
+    println!("                           --at-height <n> for the balance once block <n> was connected");
+    println!("  richest-addresses [count] Addresses with the highest balances (default 10)");
+    println!("  reindex-addresses        Rebuild the address index from stored blocks");
+    println!();
+    println!("GLOBAL OPTIONS:");
+    println!("  --txindex=<0|1>          Maintain the transaction hash index (default: 1)");
+    println!("  --addressindex=<0|1>     Maintain the address index, requires txindex (default: 1)");
+    println!("  --checkpoint=<h>:<hash>  Add a checkpoint on top of the built-in ones (repeatable)");
+    println!("  --finality-depth=<n>     Blocks this deep are final and never reorganized (default: 6)");
+    println!("  --blockcache=<bytes>     Memory for cached blocks (default: 16 MiB)");
+    println!("  --headercache=<bytes>    Memory for cached block headers (default: 2 MiB)");
+    println!("  --conf=<file>            JSON config file, re-read while start-node/start-rpc run");
+    println!("  -blocksonly              Do not accept or relay loose transactions from peers");
+    println!("  --minrelayfee=<rate>     Minimum fee per byte to relay a transaction (default: 0)");
+    println!("  --maxtxsize=<bytes>      Largest transaction accepted for relay (default: 100000)");
+    println!("  --dustthreshold=<n>      Refuse relayed payments below this many coins (default: 0.00000001)");
+    println!("  --blocknotify=<cmd>      Run <cmd> when a block is connected (%s = block hash)");
+    println!("  --reorgnotify=<cmd>      Run <cmd> when the chain reorganizes (%s = new tip hash)");
+    println!("  --walletnotify=<cmd>     Run <cmd> when a wallet transaction confirms (%s = txid)");
+    println!("  --notify-url=<url>       POST every notification as JSON to <url>");
+    println!("  --notify-timeout=<secs>  Time a notification hook may take (default: 10)");
+    println!("  --notify-retries=<n>     Retries after a failed notification hook (default: 2)");
+    println!("  --wallet=<name>          Use a named wallet for wallet and transaction commands");
+    println!("  --bootstrap-url=<url>    Fetch bootstrap bundles from <url> (repeatable)");
+    println!("  --payout-address=<addr>  Pay the reward of mined blocks to <addr> (config: mining.payout_address)");
+    println!("  --quiet, -q              Print only results and errors, without progress or status");
+    println!("  --verbose, -v            Print per-block detail and a summary of long operations");
+    println!("  --force-unlock           Remove a data directory lock left by a crashed instance");
+    println!("  --regtest                Run on a mock clock that the setmocktime RPC method sets");
+    println!("  --mocktime=<secs>        Start the regtest clock stopped at Unix time <secs> (implies --regtest)");
 }
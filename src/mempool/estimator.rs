@@ -0,0 +1,270 @@
+//! Fee estimation from confirmed transactions
+//!
+//! Every transaction confirmed out of the mempool is recorded with its fee rate
+//! and the number of blocks it waited. Records go into fee rate buckets and decay
+//! a little with every block, so the estimate follows recent conditions. An
+//! estimate for a target is the lowest fee rate whose buckets confirmed enough of
+//! their transactions within that many blocks. The buckets are saved in the data
+//! directory, so estimates survive restarts.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::events::ChainEvent;
+use crate::mempool::pool::Mempool;
+
+/// File in the data directory holding the fee estimator state
+pub const FEE_ESTIMATES_FILE: &str = "fee_estimates.json";
+
+/// Longest confirmation target that can be estimated, in blocks
+pub const MAX_CONFIRM_TARGET: u64 = 48;
+
+/// Confirmation target used for automatic fee selection
+pub const DEFAULT_CONFIRM_TARGET: u64 = 6;
+
+/// Weight kept by old records for every new block
+pub const DECAY: f64 = 0.998;
+
+/// Share of a bucket's transactions that must confirm within the target
+pub const SUCCESS_THRESHOLD: f64 = 0.85;
+
+/// Decayed transaction count a group of buckets needs before it is trusted
+pub const MIN_DATA_POINTS: f64 = 2.0;
+
+/// Lowest non-zero bucket bound and the ratio between neighbouring bounds
+const MIN_BUCKET_FEE: f64 = 0.1;
+const BUCKET_SPACING: f64 = 1.2;
+const MAX_BUCKET_FEE: f64 = 10_000.0;
+
+/// Confirmed transactions paying at least `min_fee_per_byte`, below the next bucket
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct FeeBucket {
+    min_fee_per_byte: f64,
+    /// Decayed number of transactions recorded
+    total: f64,
+    /// Decayed number confirmed within `i + 1` blocks, at index `i`
+    confirmed_within: Vec<f64>,
+}
+
+/// Answer to a fee estimate request
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FeeEstimate {
+    /// Fee per byte, `None` when there is not enough data
+    pub fee_per_byte: Option<f64>,
+    /// Target the estimate is for, capped at `MAX_CONFIRM_TARGET`
+    pub blocks: u64,
+}
+
+/// Decaying fee rate buckets built from confirmed transactions
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeeEstimator {
+    buckets: Vec<FeeBucket>,
+    /// Height of the last block recorded
+    best_height: Option<u64>,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        let mut bounds = vec![0.0];
+        let mut bound = MIN_BUCKET_FEE;
+        while bound <= MAX_BUCKET_FEE {
+            bounds.push(bound);
+            bound *= BUCKET_SPACING;
+        }
+
+        FeeEstimator {
+            buckets: bounds.into_iter()
+                .map(|min_fee_per_byte| FeeBucket {
+                    min_fee_per_byte,
+                    total: 0.0,
+                    confirmed_within: vec![0.0; MAX_CONFIRM_TARGET as usize],
+                })
+                .collect(),
+            best_height: None,
+        }
+    }
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        FeeEstimator::default()
+    }
+
+    /// Path of the fee estimates file inside a data directory
+    pub fn path_in<P: AsRef<Path>>(data_dir: P) -> PathBuf {
+        data_dir.as_ref().join(FEE_ESTIMATES_FILE)
+    }
+
+    /// Load the estimator, starting empty when the file does not exist yet
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        if !path.as_ref().exists() {
+            return Ok(FeeEstimator::new());
+        }
+        let data = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read fee estimates: {}", e))?;
+        serde_json::from_str(&data)
+            .map_err(|e| format!("Failed to parse fee estimates: {}", e))
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize fee estimates: {}", e))?;
+        fs::write(&path, data)
+            .map_err(|e| format!("Failed to write fee estimates: {}", e))
+    }
+
+    pub fn best_height(&self) -> Option<u64> {
+        self.best_height
+    }
+
+    /// Record the mempool transactions a block confirmed, as fee rate and blocks
+    /// waited. Blocks at or below the last recorded height are ignored, so a block
+    /// is never counted twice.
+    pub fn process_block(&mut self, height: u64, confirmed: &[(f64, u64)]) {
+        if self.best_height.is_some_and(|best| height <= best) {
+            return;
+        }
+        self.best_height = Some(height);
+
+        for bucket in &mut self.buckets {
+            bucket.total *= DECAY;
+            bucket.confirmed_within.iter_mut().for_each(|count| *count *= DECAY);
+        }
+
+        for &(fee_per_byte, blocks_waited) in confirmed {
+            let bucket = self.bucket_for(fee_per_byte);
+            bucket.total += 1.0;
+            let first = blocks_waited.clamp(1, MAX_CONFIRM_TARGET) as usize - 1;
+            if blocks_waited <= MAX_CONFIRM_TARGET {
+                bucket.confirmed_within[first..].iter_mut().for_each(|count| *count += 1.0);
+            }
+        }
+    }
+
+    fn bucket_for(&mut self, fee_per_byte: f64) -> &mut FeeBucket {
+        let index = self.buckets.iter()
+            .rposition(|bucket| bucket.min_fee_per_byte <= fee_per_byte)
+            .unwrap_or(0);
+        &mut self.buckets[index]
+    }
+
+    /// Lowest fee rate expected to confirm within `target_blocks`. Buckets are
+    /// walked from the highest fee rate down, grouping neighbours until they hold
+    /// enough data; the walk stops at the first group that confirms too slowly.
+    pub fn estimate_smart_fee(&self, target_blocks: u64) -> FeeEstimate {
+        let blocks = target_blocks.clamp(1, MAX_CONFIRM_TARGET);
+        let within = blocks as usize - 1;
+
+        let mut fee_per_byte = None;
+        let (mut total, mut confirmed) = (0.0, 0.0);
+        for bucket in self.buckets.iter().rev() {
+            total += bucket.total;
+            confirmed += bucket.confirmed_within[within];
+            if total < MIN_DATA_POINTS {
+                continue;
+            }
+            if confirmed / total < SUCCESS_THRESHOLD {
+                break;
+            }
+            fee_per_byte = Some(bucket.min_fee_per_byte);
+            (total, confirmed) = (0.0, 0.0);
+        }
+
+        FeeEstimate { fee_per_byte, blocks }
+    }
+
+    /// Record every block the chain connects on a background thread, until the
+    /// event bus is gone: the fees of the mempool transactions it confirms are
+    /// saved to `path`, and those transactions leave the mempool.
+    pub fn watch(mut self, path: PathBuf, mempool: Arc<Mutex<Mempool>>, events: Receiver<ChainEvent>) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for event in events {
+                let ChainEvent::BlockConnected(block) = event else { continue };
+                let mut mempool = mempool.lock().unwrap();
+                self.process_block(block.header.height, &mempool.confirmation_stats(&block));
+                mempool.remove_transactions(&block.transactions);
+                drop(mempool);
+                if let Err(e) = self.save_to_file(&path) {
+                    eprintln!("Warning: Failed to record fee estimates: {}", e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimates_follow_confirmations() {
+        let mut estimator = FeeEstimator::new();
+        assert_eq!(estimator.estimate_smart_fee(1).fee_per_byte, None);
+
+        // Rates of 10 confirm in the next block, rates of 1 take five blocks
+        for height in 1..=20 {
+            estimator.process_block(height, &[(10.0, 1), (10.0, 1), (1.0, 5), (1.0, 5)]);
+        }
+
+        let fast = estimator.estimate_smart_fee(1).fee_per_byte.unwrap();
+        assert!((9.0..=10.0).contains(&fast));
+        let slow = estimator.estimate_smart_fee(6).fee_per_byte.unwrap();
+        assert!(slow <= 1.0);
+        assert_eq!(estimator.estimate_smart_fee(500).blocks, MAX_CONFIRM_TARGET);
+    }
+
+    #[test]
+    fn test_estimator_is_persisted() {
+        let path = std::env::temp_dir().join(format!("rust_chain_fees_{}.json", std::process::id()));
+        let mut estimator = FeeEstimator::new();
+        estimator.process_block(7, &[(3.0, 1), (3.0, 2)]);
+        estimator.save_to_file(&path).unwrap();
+
+        let loaded = FeeEstimator::load_from_file(&path).unwrap();
+        assert_eq!(loaded.best_height(), Some(7));
+        assert_eq!(loaded.estimate_smart_fee(2), estimator.estimate_smart_fee(2));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_connected_blocks_are_recorded() {
+        use crate::blockchain::block::{Block, Transaction};
+        use crate::blockchain::state::UTXOState;
+        use std::sync::mpsc::channel;
+
+        let path = std::env::temp_dir().join(format!("rust_chain_fees_watch_{}.json", std::process::id()));
+        let tx = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        state.set_next_height(3);
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        mempool.lock().unwrap().add_transaction_with_fee(tx.clone(), 4.0, &state).unwrap();
+
+        let (events, receiver) = channel();
+        let watcher = FeeEstimator::new().watch(path.clone(), Arc::clone(&mempool), receiver);
+        events.send(ChainEvent::BlockConnected(Block::new("0".to_string(), vec![tx], 0, 0, 3))).unwrap();
+        drop(events);
+        watcher.join().unwrap();
+
+        let loaded = FeeEstimator::load_from_file(&path).unwrap();
+        assert_eq!(loaded.best_height(), Some(3));
+        assert_eq!(mempool.lock().unwrap().size(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,10 +4,22 @@
 //! - Transaction validation before inclusion in blocks
 //! - Mempool management with priority ordering
 //! - Transaction fee handling and prioritization
+//! - Package selection, so high-fee children pull in low-fee parents (CPFP)
+//! - Fee estimation from the waits of confirmed transactions
 //! - Duplicate transaction prevention
+//! - Snapshots for comparing the pools of two nodes
+//! - Templates of the next block, for inspection without mining
 
 pub mod validator;
 pub mod pool;
+pub mod policy;
+pub mod estimator;
+pub mod snapshot;
+pub mod template;
 
 pub use validator::{TransactionValidator, ValidationError};
-pub use pool::{Mempool, MempoolTransaction, MempoolStats};
+pub use policy::{paid_fee_per_byte, RelayPolicy, PolicyViolation};
+pub use estimator::{FeeEstimator, FeeEstimate, DEFAULT_CONFIRM_TARGET};
+pub use snapshot::{MempoolSnapshot, MempoolDiff, SnapshotEntry};
+pub use template::{BlockTemplate, TemplateTransaction};
+pub use pool::{Mempool, MempoolTransaction, MempoolEntry, MempoolStats, MempoolEvent, MempoolReconciliation, FeeHistogramBucket, MAX_ANCESTORS, MAX_BLOCK_TRANSACTIONS, MAX_DESCENDANTS, MIN_FEE_BUMP};
@@ -0,0 +1,157 @@
+use crate::blockchain::block::Transaction;
+use std::fmt;
+
+/// Default minimum fee per byte for relayed transactions
+pub const DEFAULT_MIN_RELAY_FEE: f64 = 0.0;
+
+/// Default largest serialized transaction accepted for relay, in bytes
+pub const DEFAULT_MAX_TX_SIZE: usize = 100_000;
+
+/// Default amount below which a payment is treated as dust
+pub const DEFAULT_DUST_THRESHOLD: u64 = 1;
+
+/// Node relay policy.
+///
+/// These are local settings, not consensus rules: a transaction rejected here is
+/// still valid in a block, this node just won't accept it from peers or pass it on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayPolicy {
+    /// Minimum fee per byte
+    pub min_relay_fee: f64,
+    /// Largest serialized transaction size in bytes
+    pub max_tx_size: usize,
+    /// Payments below this amount are refused
+    pub dust_threshold: u64,
+    /// Do not accept or relay loose transactions at all
+    pub blocks_only: bool,
+}
+
+impl Default for RelayPolicy {
+    fn default() -> Self {
+        RelayPolicy {
+            min_relay_fee: DEFAULT_MIN_RELAY_FEE,
+            max_tx_size: DEFAULT_MAX_TX_SIZE,
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            blocks_only: false,
+        }
+    }
+}
+
+/// Why a transaction was refused by the relay policy
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyViolation {
+    /// The node runs in blocks-only mode
+    RelayDisabled,
+    FeeTooLow { fee_per_byte: f64, minimum: f64 },
+    TooLarge { size: usize, maximum: usize },
+    Dust { amount: u64, threshold: u64 },
+}
+
+impl PolicyViolation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PolicyViolation::RelayDisabled => "relay_disabled",
+            PolicyViolation::FeeTooLow { .. } => "fee_too_low",
+            PolicyViolation::TooLarge { .. } => "too_large",
+            PolicyViolation::Dust { .. } => "dust",
+        }
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::RelayDisabled => write!(f, "transaction relay is disabled (blocks-only mode)"),
+            PolicyViolation::FeeTooLow { fee_per_byte, minimum } => {
+                write!(f, "fee rate {} per byte is below the minimum relay fee {}", fee_per_byte, minimum)
+            },
+            PolicyViolation::TooLarge { size, maximum } => {
+                write!(f, "transaction size {} bytes exceeds the maximum of {}", size, maximum)
+            },
+            PolicyViolation::Dust { amount, threshold } => {
+                write!(f, "amount {} is below the dust threshold {}", amount, threshold)
+            },
+        }
+    }
+}
+
+/// Fee per byte a transaction pays, worked out from the transaction itself so a
+/// rate claimed by whoever sent it never counts. Transactions carry no fee field
+/// and deliver their whole amount, so every transaction pays 0.
+pub fn paid_fee_per_byte(_transaction: &Transaction) -> f64 {
+    0.0
+}
+
+impl RelayPolicy {
+    /// Check a transaction offered by a peer, at the fee rate it really pays
+    pub fn check(&self, transaction: &Transaction) -> Result<(), PolicyViolation> {
+        if self.blocks_only {
+            return Err(PolicyViolation::RelayDisabled);
+        }
+
+        let size = transaction.serialize().len();
+        if size > self.max_tx_size {
+            return Err(PolicyViolation::TooLarge { size, maximum: self.max_tx_size });
+        }
+
+        self.check_dust(transaction)?;
+
+        let fee_per_byte = paid_fee_per_byte(transaction);
+        if fee_per_byte < self.min_relay_fee {
+            return Err(PolicyViolation::FeeTooLow { fee_per_byte, minimum: self.min_relay_fee });
+        }
+
+        Ok(())
+    }
+
+    /// Check that a transaction does not pay out dust. Unlike the rest of the
+    /// policy this also applies to transactions submitted locally.
+    pub fn check_dust(&self, transaction: &Transaction) -> Result<(), PolicyViolation> {
+        if transaction.amount < self.dust_threshold {
+            return Err(PolicyViolation::Dust { amount: transaction.amount, threshold: self.dust_threshold });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(amount: u64) -> Transaction {
+        Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        }
+    }
+
+    #[test]
+    fn test_relay_policy_checks() {
+        let policy = RelayPolicy {
+            min_relay_fee: 0.0,
+            max_tx_size: 200,
+            dust_threshold: 10,
+            blocks_only: false,
+        };
+
+        assert_eq!(policy.check(&transaction(50)), Ok(()));
+        assert_eq!(policy.check(&transaction(5)), Err(PolicyViolation::Dust { amount: 5, threshold: 10 }));
+
+        // The rate is the one the transaction pays, which is nothing
+        let paying = RelayPolicy { min_relay_fee: 2.0, ..policy.clone() };
+        assert_eq!(paid_fee_per_byte(&transaction(50)), 0.0);
+        assert_eq!(paying.check(&transaction(50)), Err(PolicyViolation::FeeTooLow { fee_per_byte: 0.0, minimum: 2.0 }));
+
+        let mut large = transaction(50);
+        large.signature = vec![7; 128];
+        assert!(matches!(policy.check(&large), Err(PolicyViolation::TooLarge { maximum: 200, .. })));
+
+        let blocks_only = RelayPolicy { blocks_only: true, ..RelayPolicy::default() };
+        assert_eq!(blocks_only.check(&transaction(50)), Err(PolicyViolation::RelayDisabled));
+        assert_eq!(RelayPolicy::default().check(&transaction(1)), Ok(()));
+    }
+}
@@ -1,21 +1,59 @@
-use crate::blockchain::block::Transaction;
+use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::chain::{Chain, TransactionStatus};
+use crate::blockchain::genesis::is_coinbase_transaction;
 use crate::blockchain::state::UTXOState;
+use crate::blockchain::vault::{is_clawback_transaction, is_unvault_transaction};
+use crate::clock::{self, SharedClock};
+use crate::config::NodeConfig;
+use crate::mempool::policy::{paid_fee_per_byte, RelayPolicy};
 use crate::mempool::validator::{TransactionValidator, ValidationError};
-use std::collections::{HashMap, VecDeque};
+use crate::status;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Most transactions the mempool holds unless configured otherwise
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 1000;
+
+/// Seconds a transaction stays in the mempool unless configured otherwise
+pub const DEFAULT_MEMPOOL_EXPIRY_SECS: u64 = 3600;
+
+/// Maximum number of transactions mined into a single block
+pub const MAX_BLOCK_TRANSACTIONS: usize = 10;
+
+/// Most in-pool ancestors a transaction may have, counting itself
+pub const MAX_ANCESTORS: usize = 25;
+
+/// Most in-pool descendants a transaction may have, counting itself
+pub const MAX_DESCENDANTS: usize = 25;
+
+/// Smallest raise in fee per byte that replaces a pending transaction's fee
+pub const MIN_FEE_BUMP: f64 = 1.0;
+
+/// Lower bounds (fee per byte) of the fee histogram bands
+pub const FEE_HISTOGRAM_BANDS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
 /// Transaction with metadata for mempool management
 #[derive(Debug, Clone)]
 pub struct MempoolTransaction {
     pub transaction: Transaction,
+    /// Canonical transaction id, `Transaction::hash`
+    pub txid: String,
     pub timestamp: u64,
     pub fee_per_byte: f64, // For future fee-based prioritization
     pub size_bytes: usize,
+    /// Txids of the pending transactions that fund this one's sender
+    pub parents: Vec<String>,
+    /// Height of the next block when the transaction entered the pool
+    pub entry_height: u64,
 }
 
 impl MempoolTransaction {
     pub fn new(transaction: Transaction) -> Self {
         let size_bytes = std::mem::size_of_val(&transaction);
+        let txid = transaction.hash();
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -23,9 +61,12 @@ impl MempoolTransaction {
         
         MempoolTransaction {
             transaction,
+            txid,
             timestamp,
             fee_per_byte: 0.0, // Default fee
             size_bytes,
+            parents: Vec::new(),
+            entry_height: 0,
         }
     }
 
@@ -33,6 +74,17 @@ impl MempoolTransaction {
         self.fee_per_byte = fee_per_byte;
         self
     }
+
+    /// Set the time the transaction entered the pool
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Total fee paid by the transaction
+    pub fn total_fee(&self) -> f64 {
+        self.fee_per_byte * self.size_bytes as f64
+    }
 }
 
 /// Mempool statistics
@@ -43,6 +95,128 @@ pub struct MempoolStats {
     pub oldest_transaction_age_seconds: u64,
     pub average_fee_per_byte: f64,
     pub pending_count: usize,
+    /// Configured limit on the number of transactions
+    pub max_size: usize,
+    /// Configured age after which transactions expire, in seconds
+    pub max_age_seconds: u64,
+}
+
+/// Notification sent to mempool subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum MempoolEvent {
+    TransactionAdded {
+        transaction: Transaction,
+        fee_per_byte: f64,
+        size_bytes: usize,
+    },
+    /// A transaction spends funds its sender already spends in pending
+    /// transactions. Both are kept; whichever is mined first wins.
+    DoubleSpendDetected {
+        sender: String,
+        txid: String,
+        /// Pending transactions from the same sender it conflicts with
+        conflicts_with: Vec<String>,
+    },
+}
+
+impl MempoolEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MempoolEvent::TransactionAdded { .. } => "transaction_added",
+            MempoolEvent::DoubleSpendDetected { .. } => "double_spend_detected",
+        }
+    }
+
+    /// Total fee carried by the event's transaction
+    pub fn total_fee(&self) -> f64 {
+        match self {
+            MempoolEvent::TransactionAdded { fee_per_byte, size_bytes, .. } => fee_per_byte * *size_bytes as f64,
+            MempoolEvent::DoubleSpendDetected { .. } => 0.0,
+        }
+    }
+}
+
+/// Details of one pending transaction. Ancestor and descendant figures count the
+/// transaction itself.
+#[derive(Debug, Clone, Serialize)]
+pub struct MempoolEntry {
+    pub txid: String,
+    pub fee: f64,
+    pub fee_per_byte: f64,
+    pub size_bytes: usize,
+    /// Unix time the transaction entered the pool
+    pub time: u64,
+    pub seconds_in_pool: u64,
+    pub entry_height: u64,
+    pub ancestor_count: usize,
+    pub ancestor_size: usize,
+    pub ancestor_fees: f64,
+    pub descendant_count: usize,
+    pub descendant_size: usize,
+    pub descendant_fees: f64,
+    /// Pending parents of the transaction
+    pub depends: Vec<String>,
+    /// Pending transactions spending from this one
+    pub spent_by: Vec<String>,
+}
+
+/// Pending transactions within one fee band
+#[derive(Debug, Clone)]
+pub struct FeeHistogramBucket {
+    pub min_fee_per_byte: f64,
+    /// Exclusive upper bound, `None` for the top band
+    pub max_fee_per_byte: Option<f64>,
+    pub transaction_count: usize,
+    pub total_size_bytes: usize,
+}
+
+/// What `Mempool::reconcile` dropped and kept
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MempoolReconciliation {
+    /// Transactions the chain already holds
+    pub confirmed: Vec<String>,
+    /// Transactions that no longer validate against the chain state
+    pub invalid: Vec<String>,
+    pub kept: usize,
+}
+
+impl MempoolReconciliation {
+    pub fn dropped(&self) -> usize {
+        self.confirmed.len() + self.invalid.len()
+    }
+}
+
+impl fmt::Display for MempoolReconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "kept {}, dropped {} ({} already confirmed, {} no longer valid)",
+            self.kept, self.dropped(), self.confirmed.len(), self.invalid.len())
+    }
+}
+
+/// Pending transaction as written to the mempool file
+#[derive(Serialize, Deserialize)]
+struct SavedTransaction {
+    transaction: Transaction,
+    fee_per_byte: f64,
+    entry_height: u64,
+}
+
+/// Mempool file contents. Older files hold bare transactions.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedMempool {
+    Entries(Vec<SavedTransaction>),
+    Transactions(Vec<Transaction>),
+}
+
+/// A pending transaction and the unselected ancestors mined along with it
+struct Package {
+    /// Txid of the transaction the package was built for
+    head: String,
+    fee_rate: f64,
+    /// Indexes into the pool, parents first
+    members: Vec<usize>,
 }
 
 /// Transaction mempool for pending transactions
@@ -62,6 +236,15 @@ pub struct Mempool {
     
     /// Maximum age of transactions in seconds
     max_age_seconds: u64,
+    
+    /// Listeners notified when transactions are added
+    subscribers: Vec<Sender<MempoolEvent>>,
+    
+    /// Local rules for transactions received from peers
+    policy: RelayPolicy,
+    
+    /// Source of entry times and of the time expiry is measured against
+    clock: SharedClock,
 }
 
 impl Mempool {
@@ -71,8 +254,11 @@ impl Mempool {
             transactions: VecDeque::new(),
             transaction_lookup: HashMap::new(),
             validator: TransactionValidator::new(),
-            max_size: 1000, // Default max 1000 transactions
-            max_age_seconds: 3600, // Default 1 hour expiry
+            max_size: DEFAULT_MAX_MEMPOOL_SIZE,
+            max_age_seconds: DEFAULT_MEMPOOL_EXPIRY_SECS,
+            subscribers: Vec::new(),
+            policy: RelayPolicy::default(),
+            clock: clock::system(),
         }
     }
 
@@ -84,6 +270,9 @@ impl Mempool {
             validator: TransactionValidator::new(),
             max_size,
             max_age_seconds,
+            subscribers: Vec::new(),
+            policy: RelayPolicy::default(),
+            clock: clock::system(),
         }
     }
 
@@ -93,20 +282,74 @@ impl Mempool {
         transaction: Transaction,
         utxo_state: &UTXOState,
     ) -> Result<(), ValidationError> {
-        // Validate the transaction
-        self.validator.validate_transaction(&transaction, utxo_state)?;
+        self.add_transaction_with_fee(transaction, 0.0, utxo_state)
+    }
+
+    /// Add a transaction paying the given fee per byte to the mempool
+    pub fn add_transaction_with_fee(
+        &mut self,
+        transaction: Transaction,
+        fee_per_byte: f64,
+        utxo_state: &UTXOState,
+    ) -> Result<(), ValidationError> {
+        // Validate the transaction, letting it spend funds from pending transactions
+        // when the confirmed balance falls short
+        let parents = match self.validator.validate_transaction(&transaction, utxo_state) {
+            Ok(()) => Vec::new(),
+            Err(ValidationError::InsufficientFunds) => self.validate_unconfirmed_spend(&transaction, utxo_state)?,
+            Err(e) => return Err(e),
+        };
+        
+        // Pending spends from one vault share its unlocked amount; clawbacks need no unlock
+        if let Some(unlock) = utxo_state.get_vault_unlock(&transaction.from)
+            && !is_unvault_transaction(&transaction)
+            && !is_clawback_transaction(&transaction)
+        {
+            let pending: u64 = self.transactions.iter()
+                .filter(|tx| tx.transaction.from == transaction.from && !is_unvault_transaction(&tx.transaction) && !is_clawback_transaction(&tx.transaction))
+                .map(|tx| tx.transaction.amount)
+                .sum();
+            if pending + transaction.amount > unlock.amount {
+                self.validator.forget_transaction(&transaction);
+                return Err(ValidationError::VaultLocked);
+            }
+        }
+        
+        // Peers would drop a dust payment, so it is not accepted locally either
+        if let Err(violation) = self.policy.check_dust(&transaction) {
+            self.validator.forget_transaction(&transaction);
+            return Err(ValidationError::Policy(violation));
+        }
+        
+        let conflicts = self.conflicting_spends(&transaction, utxo_state);
         
         // Create mempool transaction
-        let mempool_tx = MempoolTransaction::new(transaction);
-        let tx_hash = self.calculate_transaction_hash(&mempool_tx.transaction);
+        let mut mempool_tx = MempoolTransaction::new(transaction)
+            .with_fee(fee_per_byte)
+            .with_timestamp(self.clock.now());
+        mempool_tx.parents = parents;
+        mempool_tx.entry_height = utxo_state.next_height();
+        let tx_hash = mempool_tx.txid.clone();
         
         // Check if already in mempool
         if self.transaction_lookup.contains_key(&tx_hash) {
             return Err(ValidationError::DuplicateTransaction);
         }
         
+        let event = MempoolEvent::TransactionAdded {
+            transaction: mempool_tx.transaction.clone(),
+            fee_per_byte: mempool_tx.fee_per_byte,
+            size_bytes: mempool_tx.size_bytes,
+        };
+        
+        let sender = mempool_tx.transaction.from.clone();
+        
         // Add to mempool with priority ordering
-        self.insert_with_priority(mempool_tx, tx_hash);
+        self.insert_with_priority(mempool_tx, tx_hash.clone());
+        self.notify(event);
+        if !conflicts.is_empty() {
+            self.notify(MempoolEvent::DoubleSpendDetected { sender, txid: tx_hash, conflicts_with: conflicts });
+        }
         
         // Clean up old transactions and enforce size limits
         self.cleanup();
@@ -114,44 +357,335 @@ impl Mempool {
         Ok(())
     }
 
-    /// Get transactions for block creation (highest priority first)
+    /// Add a transaction received from the network. Unlike local submissions,
+    /// which are only held to its dust threshold, these must satisfy the whole
+    /// relay policy, and they are ranked by the fee they pay rather than by a
+    /// rate their sender could choose.
+    pub fn add_network_transaction(&mut self, transaction: Transaction, utxo_state: &UTXOState) -> Result<(), ValidationError> {
+        self.policy.check(&transaction).map_err(ValidationError::Policy)?;
+        let fee_per_byte = paid_fee_per_byte(&transaction);
+        self.add_transaction_with_fee(transaction, fee_per_byte, utxo_state)
+    }
+
+    /// Replace a pending transaction with `replacement` paying `fee_per_byte`, at
+    /// least `MIN_FEE_BUMP` more than it pays now. The replacement must make the
+    /// same payment, rebuilt and signed again so that it carries a new txid peers
+    /// have not seen; pending spends of the original's funds follow it.
+    /// Returns the previous fee rate.
+    pub fn bump_fee(
+        &mut self,
+        txid: &str,
+        replacement: Transaction,
+        fee_per_byte: f64,
+        utxo_state: &UTXOState,
+    ) -> Result<f64, String> {
+        let &index = self.transaction_lookup.get(txid)
+            .ok_or_else(|| format!("Transaction {} is not in the mempool", txid))?;
+        let original = &self.transactions[index];
+        let previous = original.fee_per_byte;
+        if fee_per_byte < previous + MIN_FEE_BUMP {
+            return Err(format!(
+                "Fee rate {} must be at least {} per byte above the current {}",
+                fee_per_byte, MIN_FEE_BUMP, previous
+            ));
+        }
+        let payment = |tx: &Transaction| (tx.from.clone(), tx.to.clone(), tx.amount);
+        if payment(&replacement) != payment(&original.transaction) {
+            return Err(format!("Replacement of {} does not make the same payment", txid));
+        }
+        let replacement_txid = replacement.hash();
+        if self.transaction_lookup.contains_key(&replacement_txid) {
+            return Err(format!("Replacement of {} does not carry a new txid", txid));
+        }
+        // Funded the same way as the original, which may be by pending parents
+        match self.validator.validate_transaction(&replacement, utxo_state) {
+            Ok(()) | Err(ValidationError::InsufficientFunds) => {},
+            Err(e) => return Err(format!("Replacement of {} rejected: {}", txid, e)),
+        }
+        
+        let original = self.transactions.remove(index).expect("lookup index is in the pool");
+        self.transaction_lookup.remove(txid);
+        self.validator.forget_transaction(&original.transaction);
+        let mut mempool_tx = MempoolTransaction::new(replacement)
+            .with_fee(fee_per_byte)
+            .with_timestamp(self.clock.now());
+        mempool_tx.parents = original.parents;
+        mempool_tx.entry_height = original.entry_height;
+        for parent in self.transactions.iter_mut().flat_map(|tx| tx.parents.iter_mut()) {
+            if parent == txid {
+                *parent = replacement_txid.clone();
+            }
+        }
+        self.insert_with_priority(mempool_tx, replacement_txid);
+        Ok(previous)
+    }
+
+    /// Set the relay policy applied to network transactions, whose dust threshold
+    /// also applies to local ones
+    pub fn set_policy(&mut self, policy: RelayPolicy) {
+        self.policy = policy;
+    }
+
+    /// Time entries and expiry with `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    /// Current time on the pool's clock
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    pub fn policy(&self) -> &RelayPolicy {
+        &self.policy
+    }
+
+    /// Apply reloadable limits and relay policy from a config file, evicting
+    /// transactions that no longer fit
+    pub fn apply_config(&mut self, config: &NodeConfig) {
+        self.policy = config.relay_policy(&self.policy);
+        if let Err(e) = self.set_limits(config.mempool_max_size, config.mempool_max_age_secs) {
+            eprintln!("Warning: Keeping mempool limits: {}", e);
+        }
+    }
+
+    /// Most transactions the pool holds
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Seconds after which pending transactions expire
+    pub fn max_age_seconds(&self) -> u64 {
+        self.max_age_seconds
+    }
+
+    /// Change the size limit, the expiry age or both, leaving a `None` limit as it
+    /// is. Transactions that no longer fit are evicted at once, lowest priority
+    /// first; returns how many were.
+    pub fn set_limits(&mut self, max_size: Option<usize>, max_age_seconds: Option<u64>) -> Result<usize, String> {
+        check_limits(max_size, max_age_seconds)?;
+        self.max_size = max_size.unwrap_or(self.max_size);
+        self.max_age_seconds = max_age_seconds.unwrap_or(self.max_age_seconds);
+        let before = self.transactions.len();
+        self.cleanup();
+        Ok(before - self.transactions.len())
+    }
+
+    /// Subscribe to mempool events
+    pub fn subscribe(&mut self) -> Receiver<MempoolEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Send an event to all subscribers, dropping ones that hung up
+    fn notify(&mut self, event: MempoolEvent) {
+        self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Pending transactions from the sender of `transaction` if, together with
+    /// them, it spends more than the sender's balance plus pending payments to it.
+    /// Any of them may then be invalid once another is mined.
+    fn conflicting_spends(&self, transaction: &Transaction, utxo_state: &UTXOState) -> Vec<String> {
+        if is_coinbase_transaction(transaction) || is_unvault_transaction(transaction) {
+            return Vec::new();
+        }
+        let mut incoming = 0u64;
+        let mut outgoing = transaction.amount;
+        let mut conflicts = Vec::new();
+        for tx in &self.transactions {
+            if tx.transaction.to == transaction.from {
+                incoming += tx.transaction.amount;
+            }
+            if tx.transaction.from == transaction.from && !is_unvault_transaction(&tx.transaction) {
+                outgoing += tx.transaction.amount;
+                conflicts.push(tx.txid.clone());
+            }
+        }
+        if outgoing <= utxo_state.get_balance(&transaction.from) + incoming {
+            return Vec::new();
+        }
+        conflicts
+    }
+
+    /// Check a transaction against the balances left once every pending transaction
+    /// is applied, after checking the ancestor and descendant limits. Returns the
+    /// pending transactions paying its sender, which become its parents.
+    fn validate_unconfirmed_spend(
+        &mut self,
+        transaction: &Transaction,
+        utxo_state: &UTXOState,
+    ) -> Result<Vec<String>, ValidationError> {
+        let parents: Vec<String> = self.transactions.iter()
+            .filter(|mempool_tx| mempool_tx.transaction.to == transaction.from)
+            .map(|mempool_tx| mempool_tx.txid.clone())
+            .collect();
+        if parents.is_empty() {
+            return Err(ValidationError::InsufficientFunds);
+        }
+        
+        let mut ancestors: HashSet<String> = HashSet::new();
+        for parent in &parents {
+            ancestors.insert(parent.clone());
+            ancestors.extend(self.ancestors(parent));
+        }
+        if ancestors.len() + 1 > MAX_ANCESTORS
+            || ancestors.iter().any(|ancestor| self.descendants(ancestor).len() + 2 > MAX_DESCENDANTS) {
+            return Err(ValidationError::TooLongMempoolChain);
+        }
+        
+        // Credit every pending payment before debiting any, since debits cannot
+        // overdraw and the pool is ordered by fee rather than ancestry
+        let mut pending_state = utxo_state.clone();
+        for mempool_tx in &self.transactions {
+            pending_state.credit(&mempool_tx.transaction.to, mempool_tx.transaction.amount)
+                .map_err(|_| ValidationError::AmountOutOfRange)?;
+        }
+        for mempool_tx in &self.transactions {
+            pending_state.debit(&mempool_tx.transaction.from, mempool_tx.transaction.amount)
+                .map_err(|_| ValidationError::InsufficientFunds)?;
+        }
+        self.validator.validate_transaction(transaction, &pending_state)?;
+        
+        Ok(parents)
+    }
+
+    /// Pending transactions a transaction depends on, directly or through its parents
+    fn ancestors(&self, tx_hash: &str) -> HashSet<String> {
+        let mut ancestors = HashSet::new();
+        let mut queue = vec![tx_hash.to_string()];
+        while let Some(hash) = queue.pop() {
+            let Some(&index) = self.transaction_lookup.get(&hash) else { continue };
+            for parent in &self.transactions[index].parents {
+                if ancestors.insert(parent.clone()) {
+                    queue.push(parent.clone());
+                }
+            }
+        }
+        ancestors
+    }
+
+    /// Pending transactions that depend on a transaction, directly or through its children
+    fn descendants(&self, tx_hash: &str) -> HashSet<String> {
+        let mut descendants = HashSet::new();
+        let mut queue = vec![tx_hash.to_string()];
+        while let Some(hash) = queue.pop() {
+            for mempool_tx in &self.transactions {
+                if mempool_tx.parents.contains(&hash) {
+                    let child = mempool_tx.txid.clone();
+                    if descendants.insert(child.clone()) {
+                        queue.push(child);
+                    }
+                }
+            }
+        }
+        descendants
+    }
+
+    /// Get transactions for block creation.
+    /// Each pending transaction is considered together with its unselected ancestors
+    /// as a package, ranked by the package fee rate, so a high-fee child pays for a
+    /// low-fee parent. Ties are broken by txid, so the same mempool contents always
+    /// produce the same template regardless of arrival order. Parents are placed
+    /// before their children.
     pub fn get_transactions_for_block(
         &self,
         max_transactions: usize,
         utxo_state: &UTXOState,
     ) -> Vec<Transaction> {
         let mut selected = Vec::new();
+        let mut selected_hashes: HashSet<String> = HashSet::new();
         let mut temp_state = utxo_state.clone();
         
-        for mempool_tx in &self.transactions {
-            if selected.len() >= max_transactions {
-                break;
-            }
+        // A transaction funded by another pending one may rank ahead of it, so
+        // packages that fail are retried once something new has been selected
+        let mut skipped: HashSet<String> = HashSet::new();
+        while selected.len() < max_transactions {
+            let best = self.transactions.iter()
+                .map(|mempool_tx| mempool_tx.txid.clone())
+                .filter(|hash| !selected_hashes.contains(hash) && !skipped.contains(hash))
+                .map(|hash| self.package(&hash, &selected_hashes))
+                .max_by(|a, b| a.fee_rate.total_cmp(&b.fee_rate).then_with(|| b.head.cmp(&a.head)));
+            let Some(package) = best else { break };
+            
+            let fits = selected.len() + package.members.len() <= max_transactions;
+            let mut package_state = temp_state.clone();
+            let valid = fits && package.members.iter().all(|&index| {
+                let transaction = &self.transactions[index].transaction;
+                let mut temp_validator = TransactionValidator::new();
+                temp_validator.validate_transaction(transaction, &package_state).is_ok()
+                    && self.apply_transaction_to_state(transaction, &mut package_state).is_ok()
+            });
             
-            // Check if transaction is still valid against current state
-            let mut temp_validator = TransactionValidator::new();
-            if temp_validator.validate_transaction(&mempool_tx.transaction, &temp_state).is_ok() {
-                // Apply transaction to temporary state
-                self.apply_transaction_to_state(&mempool_tx.transaction, &mut temp_state);
-                selected.push(mempool_tx.transaction.clone());
+            if !valid {
+                skipped.insert(package.head);
+                continue;
             }
+            temp_state = package_state;
+            for index in package.members {
+                let transaction = &self.transactions[index].transaction;
+                selected_hashes.insert(transaction.hash());
+                selected.push(transaction.clone());
+            }
+            skipped.clear();
         }
         
         selected
     }
 
+    /// A transaction with its ancestors not yet selected, parents first
+    fn package(&self, tx_hash: &str, selected: &HashSet<String>) -> Package {
+        let mut members: Vec<(usize, usize, String)> = self.ancestors(tx_hash).into_iter()
+            .chain(std::iter::once(tx_hash.to_string()))
+            .filter(|hash| !selected.contains(hash))
+            .filter_map(|hash| self.transaction_lookup.get(&hash).map(|&index| {
+                // A parent always has fewer ancestors than its child
+                (self.ancestors(&hash).len(), index, hash)
+            }))
+            .collect();
+        members.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.2.cmp(&b.2)));
+        
+        let total_fee: f64 = members.iter().map(|&(_, index, _)| self.transactions[index].total_fee()).sum();
+        let total_size: usize = members.iter().map(|&(_, index, _)| self.transactions[index].size_bytes).sum();
+        
+        Package {
+            head: tx_hash.to_string(),
+            fee_rate: if total_size > 0 { total_fee / total_size as f64 } else { 0.0 },
+            members: members.into_iter().map(|(_, index, _)| index).collect(),
+        }
+    }
+
+    /// Fee rate and number of blocks waited of each pending transaction a block
+    /// confirms, for fee estimation. A transaction confirmed in the block after it
+    /// arrived waited one block.
+    pub fn confirmation_stats(&self, block: &Block) -> Vec<(f64, u64)> {
+        block.transactions.iter()
+            .filter_map(|tx| self.transaction_lookup.get(&tx.hash()))
+            .map(|&index| {
+                let mempool_tx = &self.transactions[index];
+                let waited = (block.header.height + 1).saturating_sub(mempool_tx.entry_height).max(1);
+                (mempool_tx.fee_per_byte, waited)
+            })
+            .collect()
+    }
+
     /// Remove transactions that have been included in a block
     pub fn remove_transactions(&mut self, transactions: &[Transaction]) {
         for tx in transactions {
-            let tx_hash = self.calculate_transaction_hash(tx);
+            let tx_hash = tx.hash();
             if let Some(_index) = self.transaction_lookup.get(&tx_hash) {
                 // Find the actual index in the deque (may have changed due to removals)
                 if let Some(pos) = self.transactions.iter().position(|mtx| {
-                    self.calculate_transaction_hash(&mtx.transaction) == tx_hash
+                    mtx.txid == tx_hash
                 }) {
                     self.transactions.remove(pos);
                     self.transaction_lookup.remove(&tx_hash);
                     
+                    // Children of a confirmed transaction no longer depend on the pool
+                    for mempool_tx in self.transactions.iter_mut() {
+                        mempool_tx.parents.retain(|parent| *parent != tx_hash);
+                    }
+                    
                     // Update indices in lookup table
                     self.rebuild_lookup_table();
                 }
@@ -159,12 +693,50 @@ impl Mempool {
         }
     }
 
+    /// Return the transactions of disconnected blocks to the pool, oldest block
+    /// first so that spends within the batch find their parents. Coinbase
+    /// transactions are skipped. Returns the txids that were accepted and the
+    /// ones that no longer validate against `utxo_state`.
+    pub fn resubmit_disconnected(&mut self, blocks: &[Block], utxo_state: &UTXOState) -> (Vec<String>, Vec<String>) {
+        let mut accepted = Vec::new();
+        let mut refused = Vec::new();
+        for block in blocks.iter().rev() {
+            for transaction in &block.transactions {
+                if is_coinbase_transaction(transaction) {
+                    continue;
+                }
+                let txid = transaction.hash();
+                // It was seen when it first arrived; it is allowed in once more
+                self.validator.forget_transaction(transaction);
+                match self.add_transaction(transaction.clone(), utxo_state) {
+                    Ok(()) => accepted.push(txid),
+                    Err(_) => refused.push(txid),
+                }
+            }
+        }
+        (accepted, refused)
+    }
+
+    /// Return the transactions of blocks just disconnected from `chain` to the
+    /// pool and record on the chain whether each went back to the mempool or was
+    /// abandoned, so none is left reorged. Every path that disconnects blocks
+    /// (invalidation, reconsideration, switching to a peer's branch) goes through
+    /// here. Returns the txids that were accepted and the ones abandoned.
+    pub fn return_disconnected(&mut self, chain: &mut Chain, blocks: &[Block]) -> Result<(Vec<String>, Vec<String>), String> {
+        let utxo_state = chain.utxo_state()?;
+        let (accepted, refused) = self.resubmit_disconnected(blocks, &utxo_state);
+        for txid in &accepted {
+            chain.set_transaction_status(txid, TransactionStatus::Mempool)?;
+        }
+        for txid in &refused {
+            chain.set_transaction_status(txid, TransactionStatus::Abandoned)?;
+        }
+        Ok((accepted, refused))
+    }
+
     /// Get mempool statistics
     pub fn get_stats(&self) -> MempoolStats {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let current_time = self.clock.now();
         
         let oldest_age = if let Some(oldest) = self.transactions.front() {
             current_time.saturating_sub(oldest.timestamp)
@@ -190,7 +762,55 @@ impl Mempool {
             oldest_transaction_age_seconds: oldest_age,
             average_fee_per_byte: average_fee,
             pending_count: self.transactions.len(),
+            max_size: self.max_size,
+            max_age_seconds: self.max_age_seconds,
+        }
+    }
+
+    /// Group pending transactions into fee bands (lowest band first)
+    pub fn fee_histogram(&self) -> Vec<FeeHistogramBucket> {
+        let mut buckets: Vec<FeeHistogramBucket> = FEE_HISTOGRAM_BANDS.iter()
+            .enumerate()
+            .map(|(i, min_fee)| FeeHistogramBucket {
+                min_fee_per_byte: *min_fee,
+                max_fee_per_byte: FEE_HISTOGRAM_BANDS.get(i + 1).copied(),
+                transaction_count: 0,
+                total_size_bytes: 0,
+            })
+            .collect();
+        
+        for mempool_tx in &self.transactions {
+            let band = FEE_HISTOGRAM_BANDS.iter()
+                .rposition(|min_fee| mempool_tx.fee_per_byte >= *min_fee)
+                .unwrap_or(0);
+            buckets[band].transaction_count += 1;
+            buckets[band].total_size_bytes += mempool_tx.size_bytes;
+        }
+        
+        buckets
+    }
+
+    /// Number of blocks needed to clear the current backlog
+    pub fn blocks_to_clear(&self) -> usize {
+        self.transactions.len().div_ceil(MAX_BLOCK_TRANSACTIONS)
+    }
+
+    /// Estimate the fee per byte needed to be mined within `target_blocks` blocks.
+    /// Blocks are limited by transaction count, so the estimate walks the histogram
+    /// from the highest band down until the pending transactions fill the target.
+    pub fn estimate_fee(&self, target_blocks: usize) -> f64 {
+        let capacity = target_blocks.max(1) * MAX_BLOCK_TRANSACTIONS;
+        let mut ahead = 0;
+        
+        for bucket in self.fee_histogram().iter().rev() {
+            ahead += bucket.transaction_count;
+            if ahead >= capacity {
+                // Not everything in this band fits, so outbid it
+                return bucket.max_fee_per_byte.unwrap_or(bucket.min_fee_per_byte);
+            }
         }
+        
+        FEE_HISTOGRAM_BANDS[0]
     }
 
     /// Get all pending transactions
@@ -200,9 +820,68 @@ impl Mempool {
             .collect()
     }
 
+    /// Txids of all pending transactions, in priority order
+    pub fn txids(&self) -> Vec<String> {
+        self.transactions.iter()
+            .map(|mtx| mtx.txid.clone())
+            .collect()
+    }
+
+    /// Details of the pending transaction with the given txid
+    pub fn get_entry(&self, txid: &str) -> Option<MempoolEntry> {
+        let &index = self.transaction_lookup.get(txid)?;
+        Some(self.entry_at(index))
+    }
+
+    /// Details of every pending transaction, in priority order
+    pub fn entries(&self) -> Vec<MempoolEntry> {
+        (0..self.transactions.len())
+            .map(|index| self.entry_at(index))
+            .collect()
+    }
+
+    fn entry_at(&self, index: usize) -> MempoolEntry {
+        let current_time = self.clock.now();
+        let mempool_tx = &self.transactions[index];
+        
+        // Size and fees of a set of pool transactions plus this one
+        let totals = |hashes: &HashSet<String>| {
+            hashes.iter()
+                .filter_map(|hash| self.transaction_lookup.get(hash))
+                .map(|&i| &self.transactions[i])
+                .chain(std::iter::once(mempool_tx))
+                .fold((0usize, 0usize, 0.0), |(count, size, fees), tx| (count + 1, size + tx.size_bytes, fees + tx.total_fee()))
+        };
+        let (ancestor_count, ancestor_size, ancestor_fees) = totals(&self.ancestors(&mempool_tx.txid));
+        let (descendant_count, descendant_size, descendant_fees) = totals(&self.descendants(&mempool_tx.txid));
+        let mut spent_by: Vec<String> = self.transactions.iter()
+            .filter(|child| child.parents.contains(&mempool_tx.txid))
+            .map(|child| child.txid.clone())
+            .collect();
+        spent_by.sort();
+        
+        MempoolEntry {
+            txid: mempool_tx.txid.clone(),
+            fee: mempool_tx.total_fee(),
+            fee_per_byte: mempool_tx.fee_per_byte,
+            size_bytes: mempool_tx.size_bytes,
+            time: mempool_tx.timestamp,
+            seconds_in_pool: current_time.saturating_sub(mempool_tx.timestamp),
+            entry_height: mempool_tx.entry_height,
+            ancestor_count,
+            ancestor_size,
+            ancestor_fees,
+            descendant_count,
+            descendant_size,
+            descendant_fees,
+            depends: mempool_tx.parents.clone(),
+            spent_by,
+        }
+    }
+
     /// Check if mempool contains a specific transaction
     pub fn contains_transaction(&self, transaction: &Transaction) -> bool {
-        let tx_hash = self.calculate_transaction_hash(transaction);
+        let tx_hash = transaction.hash();
         self.transaction_lookup.contains_key(&tx_hash)
     }
 
@@ -251,34 +930,40 @@ impl Mempool {
     fn rebuild_lookup_table(&mut self) {
         self.transaction_lookup.clear();
         for (index, mempool_tx) in self.transactions.iter().enumerate() {
-            let tx_hash = self.calculate_transaction_hash(&mempool_tx.transaction);
-            self.transaction_lookup.insert(tx_hash, index);
+            self.transaction_lookup.insert(mempool_tx.txid.clone(), index);
         }
     }
 
     /// Clean up old transactions and enforce size limits
     fn cleanup(&mut self) {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let current_time = self.clock.now();
         
-        // Remove expired transactions
-        while let Some(oldest) = self.transactions.front() {
-            if current_time.saturating_sub(oldest.timestamp) > self.max_age_seconds {
-                let removed = self.transactions.pop_front().unwrap();
-                let tx_hash = self.calculate_transaction_hash(&removed.transaction);
-                self.transaction_lookup.remove(&tx_hash);
-            } else {
-                break;
+        // Remove expired transactions, wherever their fee placed them
+        let max_age_seconds = self.max_age_seconds;
+        let lookup = &mut self.transaction_lookup;
+        self.transactions.retain(|mempool_tx| {
+            let expired = current_time.saturating_sub(mempool_tx.timestamp) > max_age_seconds;
+            if expired {
+                lookup.remove(&mempool_tx.txid);
             }
-        }
+            !expired
+        });
         
         // Enforce size limit (remove lowest priority transactions)
         while self.transactions.len() > self.max_size {
             let removed = self.transactions.pop_back().unwrap();
-            let tx_hash = self.calculate_transaction_hash(&removed.transaction);
-            self.transaction_lookup.remove(&tx_hash);
+            self.transaction_lookup.remove(&removed.txid);
+        }
+        
+        // Transactions whose parents were evicted can no longer be funded
+        loop {
+            let before = self.transactions.len();
+            let lookup = &self.transaction_lookup;
+            self.transactions.retain(|mempool_tx| mempool_tx.parents.iter().all(|parent| lookup.contains_key(parent)));
+            if self.transactions.len() == before {
+                break;
+            }
+            self.rebuild_lookup_table();
         }
         
         // Rebuild lookup table after cleanup
@@ -288,25 +973,11 @@ impl Mempool {
     }
 
     /// Apply transaction to UTXO state
-    fn apply_transaction_to_state(&self, transaction: &Transaction, state: &mut UTXOState) {
-        state.update_balance(&transaction.from, -(transaction.amount as i64));
-        state.update_balance(&transaction.to, transaction.amount as i64);
+    fn apply_transaction_to_state(&self, transaction: &Transaction, state: &mut UTXOState) -> Result<(), String> {
+        state.debit(&transaction.from, transaction.amount)?;
+        state.credit(&transaction.to, transaction.amount)
     }
 
-    /// Calculate transaction hash
-    fn calculate_transaction_hash(&self, transaction: &Transaction) -> String {
-        use crate::crypto::hash::sha256_hash;
-        
-        let tx_string = format!(
-            "{}:{}:{}:{}",
-            transaction.from,
-            transaction.to,
-            transaction.amount,
-            hex::encode(&transaction.signature)
-        );
-        
-        sha256_hash(&tx_string)
-    }
 
     /// Save mempool state to disk for persistence
     pub fn save_to_file(&self, path: &str) -> Result<(), String> {
@@ -319,9 +990,13 @@ impl Mempool {
                 .map_err(|e| format!("Failed to create mempool directory: {}", e))?;
         }
 
-        // Serialize mempool transactions (excluding validator state)
+        // Serialize mempool transactions with their fee and entry height (excluding validator state)
         let serializable_data = self.transactions.iter()
-            .map(|mempool_tx| &mempool_tx.transaction)
+            .map(|mempool_tx| SavedTransaction {
+                transaction: mempool_tx.transaction.clone(),
+                fee_per_byte: mempool_tx.fee_per_byte,
+                entry_height: mempool_tx.entry_height,
+            })
             .collect::<Vec<_>>();
         
         let json_data = serde_json::to_string_pretty(&serializable_data)
@@ -345,26 +1020,87 @@ impl Mempool {
         let json_data = fs::read_to_string(path)
             .map_err(|e| format!("Failed to read mempool file: {}", e))?;
         
-        let transactions: Vec<Transaction> = serde_json::from_str(&json_data)
+        let saved: SavedMempool = serde_json::from_str(&json_data)
             .map_err(|e| format!("Failed to deserialize mempool: {}", e))?;
+        let entries = match saved {
+            SavedMempool::Entries(entries) => entries,
+            SavedMempool::Transactions(transactions) => transactions.into_iter()
+                .map(|transaction| SavedTransaction { transaction, fee_per_byte: 0.0, entry_height: utxo_state.next_height() })
+                .collect(),
+        };
         
         // Clear current state
         self.clear();
         
-        // Re-add transactions with validation
-        let mut loaded_count = 0;
-        for tx in transactions {
-            match self.add_transaction(tx, utxo_state) {
-                Ok(()) => loaded_count += 1,
-                Err(_) => {
-                    // Skip invalid transactions from saved state
-                    continue;
+        // Skip invalid transactions from saved state
+        let total = entries.len();
+        let rejected = self.readd(entries, utxo_state);
+        
+        status!("Loaded {} valid transactions from mempool persistence", total - rejected.len());
+        Ok(())
+    }
+
+    /// Check the pool against the chain, as after a crash the saved pool may be
+    /// out of step with it: transactions in `confirmed` are dropped and the rest
+    /// are validated again against `utxo_state`
+    pub fn reconcile(&mut self, confirmed: &HashSet<String>, utxo_state: &UTXOState) -> MempoolReconciliation {
+        let entries: Vec<SavedTransaction> = self.transactions.iter()
+            .map(|mempool_tx| SavedTransaction {
+                transaction: mempool_tx.transaction.clone(),
+                fee_per_byte: mempool_tx.fee_per_byte,
+                entry_height: mempool_tx.entry_height,
+            })
+            .collect();
+        self.clear();
+
+        let (mined, pending): (Vec<SavedTransaction>, Vec<SavedTransaction>) = entries.into_iter()
+            .partition(|entry| confirmed.contains(&entry.transaction.hash()));
+        let invalid = self.readd(pending, utxo_state);
+        MempoolReconciliation {
+            confirmed: mined.iter().map(|entry| entry.transaction.hash()).collect(),
+            invalid: invalid.iter().map(|entry| entry.transaction.hash()).collect(),
+            kept: self.transactions.len(),
+        }
+    }
+
+    /// `reconcile` against `chain`, looking for confirmations from the earliest
+    /// height a pending transaction entered the pool at. `None` when the pool is
+    /// empty; an error reading the chain leaves the pool untouched.
+    pub fn reconcile_with_chain(&mut self, chain: &Chain, utxo_state: &UTXOState) -> Result<Option<MempoolReconciliation>, String> {
+        let Some(from_height) = self.earliest_entry_height() else {
+            return Ok(None);
+        };
+        let confirmed = chain.confirmed_txids(&self.txids(), from_height)
+            .map_err(|e| format!("Failed to check mempool against the chain: {}", e))?;
+        Ok(Some(self.reconcile(&confirmed, utxo_state)))
+    }
+
+    /// Lowest height a pending transaction entered the pool at, the first one
+    /// that could have confirmed any of them
+    pub fn earliest_entry_height(&self) -> Option<u64> {
+        self.transactions.iter().map(|mempool_tx| mempool_tx.entry_height).min()
+    }
+
+    /// Add saved transactions with validation, returning the ones refused.
+    /// Children may be saved ahead of their parents, so the rest are retried
+    /// until no more are accepted.
+    fn readd(&mut self, mut pending: Vec<SavedTransaction>, utxo_state: &UTXOState) -> Vec<SavedTransaction> {
+        loop {
+            let before = pending.len();
+            pending.retain(|entry| {
+                if self.add_transaction_with_fee(entry.transaction.clone(), entry.fee_per_byte, utxo_state).is_err() {
+                    return true;
+                }
+                // Keep the original entry height so fee estimates count the full wait
+                if let Some(&index) = self.transaction_lookup.get(&entry.transaction.hash()) {
+                    self.transactions[index].entry_height = entry.entry_height;
                 }
+                false
+            });
+            if pending.len() == before {
+                return pending;
             }
         }
-        
-        println!("Loaded {} valid transactions from mempool persistence", loaded_count);
-        Ok(())
     }
 
     /// Create a persistent mempool that auto-saves and loads
@@ -376,6 +1112,17 @@ impl Mempool {
     }
 }
 
+/// Refuse limits that would leave no room in the pool: a size or expiry of zero
+pub fn check_limits(max_size: Option<usize>, max_age_seconds: Option<u64>) -> Result<(), String> {
+    if max_size == Some(0) {
+        return Err("mempool size limit must be at least 1 transaction".to_string());
+    }
+    if max_age_seconds == Some(0) {
+        return Err("mempool expiry must be at least 1 second".to_string());
+    }
+    Ok(())
+}
+
 impl Default for Mempool {
     fn default() -> Self {
         Self::new()
@@ -386,6 +1133,7 @@ impl Default for Mempool {
 mod tests {
     use super::*;
     use crate::blockchain::state::UTXOState;
+    use crate::mempool::policy::PolicyViolation;
 
     fn create_test_transaction(from: &str, to: &str, amount: u64) -> Transaction {
         Transaction {
@@ -393,6 +1141,8 @@ mod tests {
             to: to.to_string(),
             amount,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         }
     }
 
@@ -400,7 +1150,7 @@ mod tests {
     fn test_mempool_basic_operations() {
         let mut mempool = Mempool::new();
         let mut state = UTXOState::new();
-        state.update_balance("alice", 100);
+        state.credit("alice", 100).unwrap();
         
         let tx = create_test_transaction("alice", "bob", 50);
         
@@ -423,7 +1173,7 @@ mod tests {
     fn test_mempool_priority_ordering() {
         let mut mempool = Mempool::new();
         let mut state = UTXOState::new();
-        state.update_balance("alice", 1000);
+        state.credit("alice", 1000).unwrap();
         
         // Add transactions with different fees
         let tx1 = create_test_transaction("alice", "bob", 100);
@@ -434,11 +1184,43 @@ mod tests {
         
         let block_txs = mempool.get_transactions_for_block(10, &state);
         
-        // Should be ordered by fee (higher fee first), but since we have same fees,
-        // order should be by timestamp (first added first)
+        // Same fees, so the template is ordered by txid
         assert_eq!(block_txs.len(), 2);
-        assert_eq!(block_txs[0].amount, 100); // First added
-        assert_eq!(block_txs[1].amount, 200); // Second added
+        assert!(block_txs[0].hash() < block_txs[1].hash());
+        
+        // A higher fee rate goes first regardless of txid
+        let tx3 = create_test_transaction("alice", "dave", 300);
+        mempool.add_transaction_with_fee(tx3.clone(), 5.0, &state).unwrap();
+        let block_txs = mempool.get_transactions_for_block(10, &state);
+        assert_eq!(block_txs[0].hash(), tx3.hash());
+    }
+
+    #[test]
+    fn test_bump_fee() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 1000).unwrap();
+        
+        let tx1 = create_test_transaction("alice", "bob", 100);
+        let tx2 = create_test_transaction("alice", "charlie", 200);
+        mempool.add_transaction_with_fee(tx1.clone(), 2.0, &state).unwrap();
+        mempool.add_transaction_with_fee(tx2.clone(), 3.0, &state).unwrap();
+        
+        let mut replacement = tx1.clone();
+        replacement.signature = vec![7; 64];
+        
+        // Too small a raise, unknown transactions, a different payment and an
+        // unchanged txid are refused
+        assert!(mempool.bump_fee(&tx1.hash(), replacement.clone(), 2.5, &state).is_err());
+        assert!(mempool.bump_fee("missing", replacement.clone(), 10.0, &state).is_err());
+        assert!(mempool.bump_fee(&tx1.hash(), tx2.clone(), 5.0, &state).is_err());
+        assert!(mempool.bump_fee(&tx1.hash(), tx1.clone(), 5.0, &state).is_err());
+        
+        assert_eq!(mempool.bump_fee(&tx1.hash(), replacement.clone(), 5.0, &state), Ok(2.0));
+        assert_eq!(mempool.size(), 2);
+        assert!(mempool.get_entry(&tx1.hash()).is_none());
+        assert_eq!(mempool.get_entry(&replacement.hash()).unwrap().fee_per_byte, 5.0);
+        assert_eq!(mempool.get_transactions_for_block(10, &state)[0].hash(), replacement.hash());
     }
 
     #[test]
@@ -456,11 +1238,168 @@ mod tests {
         assert_eq!(mempool.size(), 0);
     }
 
+    #[test]
+    fn test_local_dust_rejected() {
+        let mut mempool = Mempool::new();
+        mempool.set_policy(RelayPolicy { dust_threshold: 10, ..RelayPolicy::default() });
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        
+        let dust = create_test_transaction("alice", "bob", 5);
+        assert_eq!(
+            mempool.add_transaction(dust.clone(), &state),
+            Err(ValidationError::Policy(PolicyViolation::Dust { amount: 5, threshold: 10 }))
+        );
+        assert_eq!(mempool.size(), 0);
+        
+        // The refused transaction is not remembered, so it is accepted once the threshold is lowered
+        mempool.set_policy(RelayPolicy::default());
+        assert!(mempool.add_transaction(dust, &state).is_ok());
+    }
+
+    #[test]
+    fn test_child_pays_for_parent() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        state.credit("carol", 100).unwrap();
+        
+        // Bob has nothing confirmed, so his payment spends the pending one to him
+        let parent = create_test_transaction("alice", "bob", 50);
+        let child = create_test_transaction("bob", "dave", 30);
+        let other = create_test_transaction("carol", "erin", 10);
+        mempool.add_transaction_with_fee(parent.clone(), 0.0, &state).unwrap();
+        mempool.add_transaction_with_fee(other.clone(), 2.0, &state).unwrap();
+        mempool.add_transaction_with_fee(child.clone(), 10.0, &state).unwrap();
+        assert_eq!(
+            mempool.add_transaction(create_test_transaction("bob", "frank", 30), &state),
+            Err(ValidationError::InsufficientFunds)
+        );
+        
+        // The package rate of 5.0 beats the unrelated 2.0, parent first
+        let block_txs = mempool.get_transactions_for_block(2, &state);
+        let hashes: Vec<String> = block_txs.iter().map(|tx| tx.hash()).collect();
+        assert_eq!(hashes, vec![parent.hash(), child.hash()]);
+        
+        // A package that does not fit whole is passed over
+        let block_txs = mempool.get_transactions_for_block(1, &state);
+        assert_eq!(block_txs[0].hash(), other.hash());
+        
+        // Once the parent confirms, the child stands on its own
+        mempool.remove_transactions(std::slice::from_ref(&parent));
+        state.debit("alice", 50).unwrap();
+        state.credit("bob", 50).unwrap();
+        let block_txs = mempool.get_transactions_for_block(1, &state);
+        assert_eq!(block_txs[0].hash(), child.hash());
+    }
+
+    #[test]
+    fn test_mempool_entry_by_txid() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        
+        let parent = create_test_transaction("alice", "bob", 50);
+        let child = create_test_transaction("bob", "dave", 30);
+        mempool.add_transaction_with_fee(parent.clone(), 1.0, &state).unwrap();
+        mempool.add_transaction_with_fee(child.clone(), 3.0, &state).unwrap();
+        assert_eq!(mempool.txids(), vec![child.hash(), parent.hash()]);
+        
+        let entry = mempool.get_entry(&parent.hash()).unwrap();
+        let size = entry.size_bytes;
+        assert_eq!(entry.txid, parent.hash());
+        assert_eq!(entry.fee, size as f64);
+        assert_eq!((entry.ancestor_count, entry.ancestor_size), (1, size));
+        assert_eq!((entry.descendant_count, entry.descendant_fees), (2, 4.0 * size as f64));
+        assert_eq!(entry.spent_by, vec![child.hash()]);
+        
+        let entry = mempool.get_entry(&child.hash()).unwrap();
+        assert_eq!((entry.ancestor_count, entry.descendant_count), (2, 1));
+        assert_eq!(entry.depends, vec![parent.hash()]);
+        assert_eq!(mempool.entries().len(), 2);
+        assert!(mempool.get_entry("unknown").is_none());
+    }
+
+    #[test]
+    fn test_mempool_chain_limits() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("addr0", 1000).unwrap();
+        
+        // Each payment spends the previous one, so payment i has i ancestors
+        for i in 0..MAX_ANCESTORS {
+            let tx = create_test_transaction(&format!("addr{}", i), &format!("addr{}", i + 1), 10);
+            mempool.add_transaction(tx, &state).unwrap();
+        }
+        let too_deep = create_test_transaction(&format!("addr{}", MAX_ANCESTORS), "last", 10);
+        assert_eq!(mempool.add_transaction(too_deep, &state), Err(ValidationError::TooLongMempoolChain));
+        assert_eq!(mempool.size(), MAX_ANCESTORS);
+        
+        let block_txs = mempool.get_transactions_for_block(MAX_ANCESTORS, &state);
+        assert_eq!(block_txs.len(), MAX_ANCESTORS);
+        assert_eq!(block_txs[0].from, "addr0");
+    }
+
+    #[test]
+    fn test_confirmation_stats_survive_reload() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        state.set_next_height(5);
+        
+        let tx = create_test_transaction("alice", "bob", 10);
+        mempool.add_transaction_with_fee(tx.clone(), 3.0, &state).unwrap();
+        
+        let path = std::env::temp_dir().join(format!("rust_chain_mempool_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        mempool.save_to_file(path).unwrap();
+        state.set_next_height(7);
+        let mut reloaded = Mempool::new();
+        reloaded.load_from_file(path, &state).unwrap();
+        std::fs::remove_file(path).unwrap();
+        
+        // Entered before block 5, confirmed in block 7
+        let block = Block::new("0".to_string(), vec![tx], 0, 0, 7);
+        assert_eq!(reloaded.confirmation_stats(&block), vec![(3.0, 3)]);
+    }
+
+    #[test]
+    fn test_reconcile_drops_confirmed_and_invalid() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        state.credit("dave", 10).unwrap();
+        state.set_next_height(5);
+
+        let mined = create_test_transaction("alice", "bob", 30);
+        let pending = create_test_transaction("alice", "carol", 40);
+        let overspent = create_test_transaction("dave", "erin", 10);
+        for tx in [&mined, &pending, &overspent] {
+            mempool.add_transaction(tx.clone(), &state).unwrap();
+        }
+        assert_eq!(mempool.earliest_entry_height(), Some(5));
+
+        // The chain confirmed the first payment and dave's funds went elsewhere
+        let mut state = UTXOState::new();
+        state.credit("alice", 70).unwrap();
+        state.set_next_height(8);
+        let confirmed: HashSet<String> = [mined.hash()].into();
+        let reconciliation = mempool.reconcile(&confirmed, &state);
+
+        assert_eq!(reconciliation.confirmed, vec![mined.hash()]);
+        assert_eq!(reconciliation.invalid, vec![overspent.hash()]);
+        assert_eq!(reconciliation.kept, 1);
+        assert_eq!(reconciliation.dropped(), 2);
+        assert_eq!(reconciliation.to_string(), "kept 1, dropped 2 (1 already confirmed, 1 no longer valid)");
+        assert_eq!(mempool.txids(), vec![pending.hash()]);
+        assert_eq!(mempool.get_entry(&pending.hash()).unwrap().entry_height, 5);
+    }
+
     #[test]
     fn test_mempool_stats() {
         let mut mempool = Mempool::new();
         let mut state = UTXOState::new();
-        state.update_balance("alice", 1000);
+        state.credit("alice", 1000).unwrap();
         
         assert_eq!(mempool.get_stats().total_transactions, 0);
         
@@ -473,11 +1412,60 @@ mod tests {
         assert!(stats.total_size_bytes > 0);
     }
 
+    #[test]
+    fn test_set_limits_evicts_at_once() {
+        let clock = crate::clock::MockClock::new(1_000);
+        let mut mempool = Mempool::with_limits(10, 600);
+        mempool.set_clock(std::sync::Arc::new(clock.clone()));
+        let mut state = UTXOState::new();
+        state.credit("alice", 1000).unwrap();
+
+        mempool.add_transaction(create_test_transaction("alice", "bob", 10), &state).unwrap();
+        clock.advance(100);
+        // Pays more, so it sits ahead of the older transaction
+        mempool.add_transaction_with_fee(create_test_transaction("alice", "carol", 10), 5.0, &state).unwrap();
+        mempool.add_transaction(create_test_transaction("alice", "dave", 10), &state).unwrap();
+        assert_eq!(mempool.size(), 3);
+
+        assert!(mempool.set_limits(Some(0), None).is_err());
+        assert!(mempool.set_limits(None, Some(0)).is_err());
+        assert_eq!(mempool.max_size(), 10);
+
+        // The oldest expires even though a newer one is ahead of it
+        assert_eq!(mempool.set_limits(None, Some(50)), Ok(1));
+        assert_eq!(mempool.size(), 2);
+        assert_eq!(mempool.set_limits(Some(1), None), Ok(1));
+        assert_eq!(mempool.get_pending_transactions()[0].to, "carol");
+
+        let stats = mempool.get_stats();
+        assert_eq!((stats.max_size, stats.max_age_seconds), (1, 50));
+    }
+
+    #[test]
+    fn test_expiry_follows_clock() {
+        let clock = crate::clock::MockClock::new(1_000);
+        let mut mempool = Mempool::with_limits(100, 60);
+        mempool.set_clock(std::sync::Arc::new(clock.clone()));
+        let mut state = UTXOState::new();
+        state.credit("alice", 1000).unwrap();
+
+        mempool.add_transaction(create_test_transaction("alice", "bob", 50), &state).unwrap();
+        clock.advance(30);
+        assert_eq!(mempool.entries()[0].time, 1_000);
+        assert_eq!(mempool.entries()[0].seconds_in_pool, 30);
+
+        // The first transaction expires when the next one is added
+        clock.advance(31);
+        mempool.add_transaction(create_test_transaction("alice", "carol", 50), &state).unwrap();
+        assert_eq!(mempool.size(), 1);
+        assert_eq!(mempool.get_stats().oldest_transaction_age_seconds, 0);
+    }
+
     #[test]
     fn test_mempool_duplicate_prevention() {
         let mut mempool = Mempool::new();
         let mut state = UTXOState::new();
-        state.update_balance("alice", 1000);
+        state.credit("alice", 1000).unwrap();
         
         let tx = create_test_transaction("alice", "bob", 50);
         
@@ -492,4 +1480,91 @@ mod tests {
         
         assert_eq!(mempool.size(), 1);
     }
+
+    #[test]
+    fn test_fee_histogram_and_estimate() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 10_000).unwrap();
+        
+        // 12 cheap transactions and 3 expensive ones
+        for i in 0..12 {
+            let tx = create_test_transaction("alice", &format!("cheap{}", i), 1);
+            mempool.add_transaction_with_fee(tx, 1.5, &state).unwrap();
+        }
+        for i in 0..3 {
+            let tx = create_test_transaction("alice", &format!("rich{}", i), 1);
+            mempool.add_transaction_with_fee(tx, 25.0, &state).unwrap();
+        }
+        
+        let histogram = mempool.fee_histogram();
+        assert_eq!(histogram.len(), FEE_HISTOGRAM_BANDS.len());
+        assert_eq!(histogram[1].transaction_count, 12); // 1.0..2.0
+        assert_eq!(histogram[5].transaction_count, 3); // 20.0..50.0
+        assert!(histogram[1].total_size_bytes > 0);
+        assert_eq!(mempool.blocks_to_clear(), 2);
+        
+        // One block only fits 10, so the cheap band gets outbid
+        assert_eq!(mempool.estimate_fee(1), 2.0);
+        // Two blocks clear everything
+        assert_eq!(mempool.estimate_fee(2), 0.0);
+    }
+
+    #[test]
+    fn test_subscribers_notified_on_add() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        
+        let events = mempool.subscribe();
+        let tx = create_test_transaction("alice", "bob", 10);
+        mempool.add_transaction_with_fee(tx.clone(), 2.0, &state).unwrap();
+        
+        // Rejected transactions produce no event
+        assert!(mempool.add_transaction_with_fee(tx.clone(), 2.0, &state).is_err());
+        
+        let received: Vec<MempoolEvent> = events.try_iter().collect();
+        assert_eq!(received.len(), 1);
+        let MempoolEvent::TransactionAdded { transaction, size_bytes, .. } = &received[0] else {
+            panic!("Unexpected event {:?}", received[0]);
+        };
+        assert_eq!(transaction.hash(), tx.hash());
+        assert_eq!(received[0].total_fee(), 2.0 * *size_bytes as f64);
+        
+        // Dropped receivers are pruned on the next add
+        drop(events);
+        mempool.add_transaction(create_test_transaction("alice", "carol", 10), &state).unwrap();
+        assert!(mempool.subscribers.is_empty());
+    }
+
+    #[test]
+    fn test_double_spend_detected() {
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        
+        let events = mempool.subscribe();
+        let first = create_test_transaction("alice", "bob", 60);
+        mempool.add_transaction(first.clone(), &state).unwrap();
+        mempool.add_transaction(create_test_transaction("alice", "carol", 40), &state).unwrap();
+        assert!(events.try_iter().all(|event| event.name() == "transaction_added"));
+        
+        // Spending the same funds again keeps both transactions
+        let second = create_test_transaction("alice", "dave", 50);
+        mempool.add_transaction(second.clone(), &state).unwrap();
+        assert_eq!(mempool.size(), 3);
+        
+        let conflicts: Vec<MempoolEvent> = events.try_iter()
+            .filter(|event| event.name() == "double_spend_detected")
+            .collect();
+        assert_eq!(conflicts.len(), 1);
+        let MempoolEvent::DoubleSpendDetected { sender, txid, conflicts_with } = &conflicts[0] else {
+            panic!("Unexpected event {:?}", conflicts[0]);
+        };
+        assert_eq!(sender, "alice");
+        assert_eq!(*txid, second.hash());
+        assert!(conflicts_with.contains(&first.hash()));
+        assert_eq!(conflicts_with.len(), 2);
+        assert_eq!(conflicts[0].total_fee(), 0.0);
+    }
 }
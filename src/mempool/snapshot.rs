@@ -0,0 +1,164 @@
+//! Mempool snapshots for comparing the pools of two nodes
+//!
+//! A snapshot lists every pending transaction by txid with its fee rate, size
+//! and the time it entered the pool, sorted by txid so that two snapshots of
+//! the same pool are identical apart from the time they were taken. Diffing
+//! snapshots from two nodes shows which transactions did not propagate and
+//! where the nodes disagree about fee rates.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::mempool::pool::Mempool;
+
+/// Layout version of snapshots written by this build
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// One pending transaction in a snapshot
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    pub txid: String,
+    pub fee_per_byte: f64,
+    pub size: usize,
+    /// Unix time the transaction entered the pool
+    pub time: u64,
+}
+
+/// Contents of a mempool at one point in time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MempoolSnapshot {
+    pub format_version: u32,
+    /// Unix time the snapshot was taken
+    pub taken_at: u64,
+    /// Chain height of the node when the snapshot was taken
+    pub height: u64,
+    /// Sorted by txid
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl MempoolSnapshot {
+    /// Snapshot of the given entries, sorted into canonical order
+    pub fn new(height: u64, mut entries: Vec<SnapshotEntry>) -> Self {
+        entries.sort_by(|a, b| a.txid.cmp(&b.txid));
+        MempoolSnapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            taken_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            height,
+            entries,
+        }
+    }
+
+    pub fn from_mempool(mempool: &Mempool, height: u64) -> Self {
+        let entries = mempool.entries().into_iter()
+            .map(|entry| SnapshotEntry {
+                txid: entry.txid,
+                fee_per_byte: entry.fee_per_byte,
+                size: entry.size_bytes,
+                time: entry.time,
+            })
+            .collect();
+        Self::new(height, entries)
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize mempool snapshot: {}", e))?;
+        fs::write(path, data)
+            .map_err(|e| format!("Failed to write mempool snapshot: {}", e))
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let data = fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("Failed to read mempool snapshot {}: {}", path.as_ref().display(), e))?;
+        let mut snapshot: MempoolSnapshot = serde_json::from_str(&data)
+            .map_err(|e| format!("Failed to parse mempool snapshot: {}", e))?;
+        if snapshot.format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported mempool snapshot version {} (expected {})",
+                snapshot.format_version, SNAPSHOT_FORMAT_VERSION
+            ));
+        }
+        // Files edited by hand may be out of order
+        snapshot.entries.sort_by(|a, b| a.txid.cmp(&b.txid));
+        Ok(snapshot)
+    }
+
+    /// Compare this snapshot, taken locally, with one from another node
+    pub fn diff(&self, remote: &MempoolSnapshot) -> MempoolDiff {
+        let remote_entries: HashMap<&str, &SnapshotEntry> = remote.entries.iter()
+            .map(|entry| (entry.txid.as_str(), entry))
+            .collect();
+        let mut diff = MempoolDiff::default();
+        for entry in &self.entries {
+            match remote_entries.get(entry.txid.as_str()) {
+                Some(remote_entry) if remote_entry.fee_per_byte != entry.fee_per_byte => {
+                    diff.fee_mismatches.push((entry.clone(), (*remote_entry).clone()));
+                },
+                Some(_) => diff.common += 1,
+                None => diff.only_local.push(entry.clone()),
+            }
+        }
+        diff.only_remote = remote.entries.iter()
+            .filter(|entry| self.entries.binary_search_by(|local| local.txid.cmp(&entry.txid)).is_err())
+            .cloned()
+            .collect();
+        diff
+    }
+}
+
+/// Differences between a local and a remote snapshot, each list sorted by txid
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MempoolDiff {
+    /// Pending here but not on the other node
+    pub only_local: Vec<SnapshotEntry>,
+    /// Pending on the other node but not here
+    pub only_remote: Vec<SnapshotEntry>,
+    /// Pending on both with different fee rates, local entry first
+    pub fee_mismatches: Vec<(SnapshotEntry, SnapshotEntry)>,
+    /// Pending on both with the same fee rate
+    pub common: usize,
+}
+
+impl MempoolDiff {
+    /// Whether both pools hold the same transactions at the same fee rates
+    pub fn is_empty(&self) -> bool {
+        self.only_local.is_empty() && self.only_remote.is_empty() && self.fee_mismatches.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(txid: &str, fee_per_byte: f64) -> SnapshotEntry {
+        SnapshotEntry { txid: txid.to_string(), fee_per_byte, size: 100, time: 1 }
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let local = MempoolSnapshot::new(5, vec![entry("c", 1.0), entry("a", 1.0), entry("b", 2.0)]);
+        assert_eq!(local.entries.iter().map(|e| e.txid.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        let remote = MempoolSnapshot::new(5, vec![entry("a", 1.0), entry("b", 3.0), entry("d", 1.0)]);
+
+        let diff = local.diff(&remote);
+        assert_eq!(diff.only_local, vec![entry("c", 1.0)]);
+        assert_eq!(diff.only_remote, vec![entry("d", 1.0)]);
+        assert_eq!(diff.fee_mismatches, vec![(entry("b", 2.0), entry("b", 3.0))]);
+        assert_eq!(diff.common, 1);
+        assert!(!diff.is_empty());
+        assert!(local.diff(&local).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("rust_chain_mempool_snapshot_{}.json", std::process::id()));
+        let snapshot = MempoolSnapshot::new(7, vec![entry("b", 1.5), entry("a", 0.5)]);
+        snapshot.save_to_file(&path).unwrap();
+        assert_eq!(MempoolSnapshot::load_from_file(&path).unwrap(), snapshot);
+        fs::remove_file(&path).unwrap();
+    }
+}
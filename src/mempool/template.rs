@@ -0,0 +1,139 @@
+//! Block templates
+//!
+//! A `BlockTemplate` is the block the node would mine next on the current tip:
+//! the transactions `Mempool::get_transactions_for_block` selects, in the order
+//! they would be included, with what they pay and what the coinbase would
+//! claim. Building one does no proof of work, so `preview-block` and
+//! `getblocktemplate` can show it at any time without touching the chain.
+
+use serde::Serialize;
+
+use crate::blockchain::block::Block;
+use crate::blockchain::state::UTXOState;
+use crate::consensus::params::ChainParams;
+use crate::consensus::timestamps;
+use crate::mempool::pool::{Mempool, MAX_BLOCK_TRANSACTIONS};
+
+/// A selected transaction with what it pays
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateTransaction {
+    pub txid: String,
+    pub from: String,
+    pub to: String,
+    pub amount: u64,
+    pub fee: f64,
+    pub fee_per_byte: f64,
+    pub size_bytes: usize,
+    /// Pending parents, which come earlier in the template
+    pub depends: Vec<String>,
+}
+
+/// The next block as it would be mined now
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockTemplate {
+    pub previous_hash: String,
+    pub height: u64,
+    /// Timestamp the block would carry, never before its parent's or the
+    /// median time past
+    pub timestamp: u64,
+    pub transactions: Vec<TemplateTransaction>,
+    pub total_fees: f64,
+    /// Size of the block as stored, before a nonce is found
+    pub size_bytes: usize,
+    pub subsidy: u64,
+    /// What the coinbase pays, in base units. Mined coinbases claim only the
+    /// subsidy, since no sender is debited on chain for the fees in `total_fees`.
+    pub coinbase_value: u64,
+}
+
+impl BlockTemplate {
+    /// Template for the block after `tip`, which the chain holds to
+    /// `median_time_past`, selecting from `mempool` against the UTXO state at the tip
+    pub fn build(tip: &Block, median_time_past: u64, mempool: &Mempool, utxo_state: &UTXOState, params: &ChainParams) -> Self {
+        let height = tip.header.height + 1;
+        let timestamp = timestamps::next_timestamp(mempool.now(), tip.header.timestamp.max(median_time_past));
+        let selected = mempool.get_transactions_for_block(MAX_BLOCK_TRANSACTIONS, utxo_state);
+
+        let transactions: Vec<TemplateTransaction> = selected.iter().map(|transaction| {
+            let txid = transaction.hash();
+            let entry = mempool.get_entry(&txid);
+            TemplateTransaction {
+                from: transaction.from.clone(),
+                to: transaction.to.clone(),
+                amount: transaction.amount,
+                fee: entry.as_ref().map_or(0.0, |entry| entry.fee),
+                fee_per_byte: entry.as_ref().map_or(0.0, |entry| entry.fee_per_byte),
+                size_bytes: entry.as_ref().map_or(0, |entry| entry.size_bytes),
+                depends: entry.map(|entry| entry.depends).unwrap_or_default(),
+                txid,
+            }
+        }).collect();
+
+        let total_fees: f64 = transactions.iter().map(|tx| tx.fee).sum();
+        let subsidy = params.subsidy_at(height);
+        let block = Block::new(tip.header.hash.clone(), selected, 0, timestamp, height);
+
+        BlockTemplate {
+            previous_hash: tip.header.hash.clone(),
+            height,
+            timestamp,
+            transactions,
+            total_fees,
+            size_bytes: serde_json::to_string(&block).unwrap_or_default().len(),
+            subsidy,
+            coinbase_value: subsidy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::block::Transaction;
+
+    fn payment(from: &str, to: &str, amount: u64) -> Transaction {
+        Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        }
+    }
+
+    #[test]
+    fn test_template_of_next_block() {
+        let tip = Block::new("0".to_string(), vec![], 0, u64::MAX / 2, 4);
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        let mut mempool = Mempool::new();
+        let params = ChainParams::mainnet();
+
+        let empty = BlockTemplate::build(&tip, 0, &mempool, &state, &params);
+        assert_eq!((empty.height, empty.transactions.len()), (5, 0));
+        assert_eq!(empty.coinbase_value, params.subsidy_at(5));
+        // A parent stamped in the future holds the template's timestamp back
+        assert_eq!(empty.timestamp, tip.header.timestamp);
+        // As does a median time past later than the parent
+        let later = BlockTemplate::build(&tip, u64::MAX / 2 + 60, &mempool, &state, &params);
+        assert_eq!(later.timestamp, u64::MAX / 2 + 60);
+
+        // The child pays for its parent, which comes first
+        let parent = payment("alice", "bob", 60);
+        let child = payment("bob", "carol", 10);
+        mempool.add_transaction_with_fee(parent.clone(), 1.0, &state).unwrap();
+        mempool.add_transaction_with_fee(child.clone(), 20.0, &state).unwrap();
+
+        let template = BlockTemplate::build(&tip, 0, &mempool, &state, &params);
+        let txids: Vec<&str> = template.transactions.iter().map(|tx| tx.txid.as_str()).collect();
+        assert_eq!(txids, vec![parent.hash().as_str(), child.hash().as_str()]);
+        assert_eq!(template.transactions[1].depends, vec![parent.hash()]);
+        let fees = template.transactions[0].fee + template.transactions[1].fee;
+        assert_eq!(template.total_fees, fees);
+        assert_eq!(template.coinbase_value, params.subsidy_at(5));
+        assert!(template.size_bytes > empty.size_bytes);
+        // Previewing leaves the pool alone
+        assert_eq!(mempool.size(), 2);
+    }
+}
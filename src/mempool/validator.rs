@@ -1,6 +1,13 @@
+use crate::blockchain::amount::{checked_money_add, money_range, MAX_MONEY};
 use crate::blockchain::block::Transaction;
-use crate::blockchain::state::UTXOState;
+use crate::blockchain::multisig::{check_cosignatures, is_multisig_address};
+use crate::blockchain::state::{UTXOState, COINBASE_MATURITY};
+use crate::blockchain::vault::is_unvault_transaction;
+use crate::mempool::policy::PolicyViolation;
+use crate::mempool::pool::{MAX_ANCESTORS, MAX_DESCENDANTS};
+use crate::perf;
 use std::collections::HashSet;
+use std::fmt;
 
 /// Transaction validation errors
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +19,66 @@ pub enum ValidationError {
     DuplicateTransaction,
     InvalidAddress,
     EmptyTransaction,
+    ImmatureCoinbase,
+    /// The transaction's lock height has not been reached yet
+    NotYetValid,
+    /// The transaction's expiry height has passed
+    Expired,
+    /// Accepting the transaction would exceed the in-pool ancestor or descendant limit
+    TooLongMempoolChain,
+    /// Spends vault funds that have not been declared for unlocking or are still in their delay
+    VaultLocked,
+    /// The amount, or a balance it would create, is above `MAX_MONEY`
+    AmountOutOfRange,
+    /// Valid, but refused by this node's relay policy
+    Policy(PolicyViolation),
+}
+
+impl ValidationError {
+    /// Short machine-readable reason, as reported over RPC
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ValidationError::InvalidSignature => "invalid_signature",
+            ValidationError::InsufficientFunds => "insufficient_funds",
+            ValidationError::NegativeAmount => "negative_amount",
+            ValidationError::SelfTransfer => "self_transfer",
+            ValidationError::DuplicateTransaction => "duplicate_transaction",
+            ValidationError::InvalidAddress => "invalid_address",
+            ValidationError::EmptyTransaction => "empty_transaction",
+            ValidationError::ImmatureCoinbase => "immature_coinbase",
+            ValidationError::NotYetValid => "not_yet_valid",
+            ValidationError::Expired => "expired",
+            ValidationError::TooLongMempoolChain => "too_long_mempool_chain",
+            ValidationError::VaultLocked => "vault_locked",
+            ValidationError::AmountOutOfRange => "amount_out_of_range",
+            ValidationError::Policy(violation) => violation.name(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::InvalidSignature => write!(f, "Invalid signature"),
+            ValidationError::InsufficientFunds => write!(f, "Insufficient funds"),
+            ValidationError::NegativeAmount => write!(f, "Negative amount"),
+            ValidationError::SelfTransfer => write!(f, "Cannot send to yourself"),
+            ValidationError::DuplicateTransaction => write!(f, "Duplicate transaction"),
+            ValidationError::InvalidAddress => write!(f, "Invalid address"),
+            ValidationError::EmptyTransaction => write!(f, "Empty transaction"),
+            ValidationError::ImmatureCoinbase => {
+                write!(f, "Coinbase rewards need {} confirmations before they can be spent", COINBASE_MATURITY)
+            },
+            ValidationError::NotYetValid => write!(f, "Lock height not reached yet"),
+            ValidationError::Expired => write!(f, "Expiry height has passed"),
+            ValidationError::TooLongMempoolChain => write!(
+                f, "Too many unconfirmed ancestors or descendants (limits {} and {})", MAX_ANCESTORS, MAX_DESCENDANTS
+            ),
+            ValidationError::VaultLocked => write!(f, "Vault funds must be unvaulted and wait out the vault delay first"),
+            ValidationError::AmountOutOfRange => write!(f, "Amount exceeds the maximum of {} units", MAX_MONEY),
+            ValidationError::Policy(violation) => write!(f, "Rejected by relay policy: {}", violation),
+        }
+    }
 }
 
 /// Transaction validator for the mempool
@@ -37,6 +104,12 @@ impl TransactionValidator {
         // Basic validation checks
         self.validate_basic_rules(transaction)?;
         
+        // Check lock and expiry heights against the next block
+        self.validate_height_bounds(transaction, utxo_state.next_height())?;
+        
+        // Vault spends need a confirmed unlock whose delay has passed
+        self.validate_vault_spend(transaction, utxo_state)?;
+        
         // Check for duplicate transactions
         self.validate_uniqueness(transaction)?;
         
@@ -67,7 +140,7 @@ impl TransactionValidator {
             self.validate_transaction(tx, &temp_state)?;
             
             // Apply the transaction to the temporary state
-            self.apply_transaction_to_state(tx, &mut temp_state);
+            self.apply_transaction_to_state(tx, &mut temp_state)?;
         }
         
         Ok(())
@@ -80,19 +153,42 @@ impl TransactionValidator {
             return Err(ValidationError::InvalidAddress);
         }
         
-        // Check for zero or negative amount
-        if transaction.amount == 0 {
+        // Check for zero or negative amount; a vault declaring zero cancels its unlock
+        if transaction.amount == 0 && !is_unvault_transaction(transaction) {
             return Err(ValidationError::EmptyTransaction);
         }
         
-        // Check for self-transfer
-        if transaction.from == transaction.to {
+        if !money_range(transaction.amount) {
+            return Err(ValidationError::AmountOutOfRange);
+        }
+        
+        // Check for self-transfer; a vault paying itself declares an unlock
+        if transaction.from == transaction.to && !is_unvault_transaction(transaction) {
             return Err(ValidationError::SelfTransfer);
         }
         
         Ok(())
     }
 
+    /// Check that the transaction can be included at the given block height
+    fn validate_height_bounds(&self, transaction: &Transaction, height: u64) -> Result<(), ValidationError> {
+        if transaction.lock_height.is_some_and(|lock_height| height < lock_height) {
+            return Err(ValidationError::NotYetValid);
+        }
+        
+        if transaction.expiry_height.is_some_and(|expiry_height| height > expiry_height) {
+            return Err(ValidationError::Expired);
+        }
+        
+        Ok(())
+    }
+
+    /// Check that a spend from a vault stays within its unlocked funds
+    fn validate_vault_spend(&self, transaction: &Transaction, utxo_state: &UTXOState) -> Result<(), ValidationError> {
+        utxo_state.check_vault_spend(transaction, utxo_state.next_height())
+            .map_err(|_| ValidationError::VaultLocked)
+    }
+
     /// Check if transaction is unique (not already seen)
     fn validate_uniqueness(&self, transaction: &Transaction) -> Result<(), ValidationError> {
         let tx_hash = self.calculate_transaction_hash(transaction);
@@ -106,8 +202,9 @@ impl TransactionValidator {
 
     /// Validate transaction signature
     fn validate_signature(&self, transaction: &Transaction) -> Result<(), ValidationError> {
+        let _timer = perf::timer(perf::SIGNATURE_CHECK);
         // Create message to verify
-        let message = format!("{}:{}:{}", transaction.from, transaction.to, transaction.amount);
+        let message = transaction.signing_message();
         
         // In production, we need proper cryptographic signature verification
         if transaction.signature.is_empty() {
@@ -143,6 +240,11 @@ impl TransactionValidator {
         use crate::crypto::hash::sha256_hash;
         let expected_sig_length = 64; // Typical ECDSA signature length
         
+        // Multisig transactions carry a signature from each required cosigner
+        // of the address, each checked against the address's cosigner set
+        if is_multisig_address(&transaction.from) {
+            return check_cosignatures(transaction).map_err(|_| ValidationError::InvalidSignature);
+        }
         if transaction.signature.len() != expected_sig_length {
             return Err(ValidationError::InvalidSignature);
         }
@@ -167,19 +269,24 @@ impl TransactionValidator {
         let sender_balance = utxo_state.get_balance(&transaction.from);
         
         if sender_balance < transaction.amount {
+            // Funds exist but are still locked behind coinbase maturity
+            if utxo_state.check_coinbase_maturity(transaction).is_err() {
+                return Err(ValidationError::ImmatureCoinbase);
+            }
             return Err(ValidationError::InsufficientFunds);
         }
         
+        if checked_money_add(utxo_state.get_balance(&transaction.to), transaction.amount).is_err() {
+            return Err(ValidationError::AmountOutOfRange);
+        }
+        
         Ok(())
     }
 
     /// Apply transaction to UTXO state (for validation purposes)
-    fn apply_transaction_to_state(&self, transaction: &Transaction, state: &mut UTXOState) {
-        // Subtract from sender
-        state.update_balance(&transaction.from, -(transaction.amount as i64));
-        
-        // Add to receiver
-        state.update_balance(&transaction.to, transaction.amount as i64);
+    fn apply_transaction_to_state(&self, transaction: &Transaction, state: &mut UTXOState) -> Result<(), ValidationError> {
+        let height = state.next_height();
+        state.apply_transaction(transaction, height).map_err(|_| ValidationError::AmountOutOfRange)
     }
 
     /// Calculate a simple hash for the transaction
@@ -187,10 +294,8 @@ impl TransactionValidator {
         use crate::crypto::hash::sha256_hash;
         
         let tx_string = format!(
-            "{}:{}:{}:{}",
-            transaction.from,
-            transaction.to,
-            transaction.amount,
+            "{}:{}",
+            transaction.signing_message(),
             hex::encode(&transaction.signature)
         );
         
@@ -202,6 +307,12 @@ impl TransactionValidator {
         self.seen_transactions.clear();
     }
 
+    /// Forget that a transaction was seen, so it can be accepted again
+    pub fn forget_transaction(&mut self, transaction: &Transaction) {
+        let tx_hash = self.calculate_transaction_hash(transaction);
+        self.seen_transactions.remove(&tx_hash);
+    }
+
     /// Get count of seen transactions
     pub fn seen_count(&self) -> usize {
         self.seen_transactions.len()
@@ -231,13 +342,15 @@ mod tests {
         let mut state = UTXOState::new();
         
         // Add some initial balance
-        state.update_balance("alice", 100);
+        state.credit("alice", 100).unwrap();
         
         let valid_tx = Transaction {
             from: "alice".to_string(),
             to: "bob".to_string(),
             amount: 50,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         assert!(validator.validate_transaction(&valid_tx, &state).is_ok());
@@ -253,6 +366,8 @@ mod tests {
             to: "bob".to_string(),
             amount: 50,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         assert_eq!(
@@ -261,6 +376,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_amounts_above_max_money_rejected() {
+        let tx = |from: &str, to: &str, amount| Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let mut state = UTXOState::new();
+        state.set_balance("alice", u64::MAX);
+        
+        let mut validator = TransactionValidator::new();
+        assert_eq!(validator.validate_transaction(&tx("alice", "bob", MAX_MONEY + 1), &state), Err(ValidationError::AmountOutOfRange));
+        assert!(validator.validate_transaction(&tx("alice", "bob", MAX_MONEY), &state).is_ok());
+        
+        // The receiver's balance may not pass the maximum either
+        state.set_balance("bob", MAX_MONEY);
+        let mut validator = TransactionValidator::new();
+        assert_eq!(validator.validate_transaction(&tx("alice", "bob", 1), &state), Err(ValidationError::AmountOutOfRange));
+        
+        // Nor through a sequence of transactions that are each fine alone
+        state.set_balance("bob", MAX_MONEY - 10);
+        let mut validator = TransactionValidator::new();
+        let transactions = [tx("alice", "bob", 10), tx("alice", "bob", 1)];
+        assert_eq!(validator.validate_transactions(&transactions, &state), Err(ValidationError::AmountOutOfRange));
+    }
+
     #[test]
     fn test_self_transfer() {
         let mut validator = TransactionValidator::new();
@@ -271,6 +415,8 @@ mod tests {
             to: "alice".to_string(),
             amount: 50,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         assert_eq!(
@@ -283,13 +429,15 @@ mod tests {
     fn test_duplicate_transaction() {
         let mut validator = TransactionValidator::new();
         let mut state = UTXOState::new();
-        state.update_balance("alice", 100);
+        state.credit("alice", 100).unwrap();
         
         let tx = Transaction {
             from: "alice".to_string(),
             to: "bob".to_string(),
             amount: 50,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         // First time should be OK
@@ -301,4 +449,147 @@ mod tests {
             Err(ValidationError::DuplicateTransaction)
         );
     }
+
+    #[test]
+    fn test_immature_coinbase_rejected() {
+        use crate::blockchain::block::Block;
+        use crate::blockchain::genesis::COINBASE_ADDRESS;
+        
+        let reward = Transaction {
+            from: COINBASE_ADDRESS.to_string(),
+            to: "miner".to_string(),
+            amount: 50,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let blocks = vec![Block::new("prev".to_string(), vec![reward], 0, 0, 1)];
+        let state = UTXOState::from_blocks(&blocks).unwrap();
+        
+        let mut validator = TransactionValidator::new();
+        let spend = Transaction {
+            from: "miner".to_string(),
+            to: "bob".to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        
+        assert_eq!(
+            validator.validate_transaction(&spend, &state),
+            Err(ValidationError::ImmatureCoinbase)
+        );
+    }
+
+    #[test]
+    fn test_lock_and_expiry_heights() {
+        let mut validator = TransactionValidator::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        state.set_next_height(10);
+        
+        let locked = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: Some(11),
+            expiry_height: None,
+        };
+        assert_eq!(
+            validator.validate_transaction(&locked, &state),
+            Err(ValidationError::NotYetValid)
+        );
+        
+        let expired = Transaction {
+            lock_height: None,
+            expiry_height: Some(9),
+            ..locked.clone()
+        };
+        assert_eq!(
+            validator.validate_transaction(&expired, &state),
+            Err(ValidationError::Expired)
+        );
+        
+        let in_window = Transaction {
+            lock_height: Some(10),
+            expiry_height: Some(10),
+            ..locked
+        };
+        assert!(validator.validate_transaction(&in_window, &state).is_ok());
+    }
+
+    #[test]
+    fn test_vault_spend_waits_for_unlock() {
+        use crate::blockchain::block::Block;
+        use crate::blockchain::vault::vault_address;
+        
+        let vault = vault_address("alice", 10, Some("carol")).unwrap();
+        let tx = |from: &str, to: &str, amount| Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let mut validator = TransactionValidator::new();
+        let mut state = UTXOState::new();
+        state.credit(&vault, 100).unwrap();
+        state.set_next_height(5);
+        
+        // Nothing declared yet
+        assert_eq!(validator.validate_transaction(&tx(&vault, "bob", 10), &state), Err(ValidationError::VaultLocked));
+        
+        // Declaring is allowed even though it is a self-transfer, and moves no funds
+        let unvault = tx(&vault, &vault, 40);
+        assert!(validator.validate_transaction(&unvault, &state).is_ok());
+        state.apply_block(&Block::new("prev".to_string(), vec![unvault], 0, 0, 5)).unwrap();
+        assert_eq!(state.get_balance(&vault), 100);
+        
+        state.set_next_height(14);
+        assert_eq!(validator.validate_transaction(&tx(&vault, "bob", 10), &state), Err(ValidationError::VaultLocked));
+        state.set_next_height(15);
+        assert_eq!(validator.validate_transaction(&tx(&vault, "bob", 50), &state), Err(ValidationError::VaultLocked));
+        assert!(validator.validate_transaction(&tx(&vault, "bob", 40), &state).is_ok());
+    }
+
+    #[test]
+    fn test_vault_unlock_cancel_and_clawback() {
+        use crate::blockchain::block::Block;
+        use crate::blockchain::vault::vault_address;
+        
+        let vault = vault_address("alice", 10, Some("carol")).unwrap();
+        let tx = |from: &str, to: &str, amount| Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let mut validator = TransactionValidator::new();
+        let mut state = UTXOState::new();
+        state.credit(&vault, 100).unwrap();
+        state.apply_block(&Block::new("prev".to_string(), vec![tx(&vault, &vault, 40)], 0, 0, 5)).unwrap();
+        
+        // Declaring zero cancels the unlock
+        let cancel = tx(&vault, &vault, 0);
+        assert!(validator.validate_transaction(&cancel, &state).is_ok());
+        state.apply_block(&Block::new("prev".to_string(), vec![cancel], 0, 0, 6)).unwrap();
+        assert_eq!(state.get_vault_unlock(&vault), None);
+        state.set_next_height(20);
+        assert_eq!(validator.validate_transaction(&tx(&vault, "bob", 10), &state), Err(ValidationError::VaultLocked));
+        
+        // A clawback to the recovery address needs no unlock and cancels a pending one
+        state.apply_block(&Block::new("prev".to_string(), vec![tx(&vault, &vault, 40)], 0, 0, 20)).unwrap();
+        state.set_next_height(21);
+        let clawback = tx(&vault, "carol", 100);
+        assert!(validator.validate_transaction(&clawback, &state).is_ok());
+        state.apply_block(&Block::new("prev".to_string(), vec![clawback], 0, 0, 21)).unwrap();
+        assert_eq!(state.get_balance(&vault), 0);
+        assert_eq!(state.get_balance("carol"), 100);
+        assert_eq!(state.get_vault_unlock(&vault), None);
+    }
 }
@@ -0,0 +1,198 @@
+//! Peer addresses
+//!
+//! A peer is reached by IP address (v4 or v6) or by DNS hostname. Hostnames are
+//! kept as given and resolved each time we connect, so a seed whose address
+//! changes is still reachable. IPv6 addresses are written in brackets when a
+//! port follows, as in `[2001:db8::1]:8333`.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::network::protocol::NetworkError;
+
+/// How long a single connection attempt may take before the next resolved address is tried
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where a peer can be reached
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerAddress {
+    Ip(SocketAddr),
+    Host { host: String, port: u16 },
+}
+
+impl PeerAddress {
+    /// Address from a host and port. The host may be an IPv4 or IPv6 address, with
+    /// or without brackets, or a hostname.
+    pub fn new(host: &str, port: u16) -> Result<Self, String> {
+        let host = host.trim();
+        let unbracketed = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+        if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+            return Ok(PeerAddress::Ip(SocketAddr::new(ip, port)));
+        }
+
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        };
+        if host.len() > 253 || !host.split('.').all(valid_label) {
+            return Err(format!("Invalid peer host '{}'", host));
+        }
+        Ok(PeerAddress::Host { host: host.to_ascii_lowercase(), port })
+    }
+
+    /// Host without the port; IPv6 addresses are not bracketed
+    pub fn host(&self) -> String {
+        match self {
+            PeerAddress::Ip(addr) => addr.ip().to_string(),
+            PeerAddress::Host { host, .. } => host.clone(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            PeerAddress::Ip(addr) => addr.port(),
+            PeerAddress::Host { port, .. } => *port,
+        }
+    }
+
+    /// Socket addresses to try, resolving hostnames through DNS
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>, NetworkError> {
+        match self {
+            PeerAddress::Ip(addr) => Ok(vec![*addr]),
+            PeerAddress::Host { host, port } => {
+                let addrs: Vec<SocketAddr> = (host.as_str(), *port).to_socket_addrs()
+                    .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to resolve {}: {}", host, e)))?
+                    .collect();
+                if addrs.is_empty() {
+                    return Err(NetworkError::ConnectionFailed(format!("{} did not resolve to any address", host)));
+                }
+                Ok(addrs)
+            },
+        }
+    }
+
+    /// Connect to the first resolved address that accepts
+    pub fn connect(&self) -> Result<TcpStream, NetworkError> {
+        let mut last_error = None;
+        for addr in self.resolve()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(NetworkError::ConnectionFailed(format!(
+            "Failed to connect to {}: {}",
+            self,
+            last_error.map(|e| e.to_string()).unwrap_or_default()
+        )))
+    }
+
+    /// Bind a listener. An unspecified address (`0.0.0.0` or `::`) binds the IPv6
+    /// wildcard first, which also accepts IPv4 where the system allows dual-stack
+    /// sockets, and falls back to IPv4 only.
+    pub fn bind(&self) -> Result<TcpListener, NetworkError> {
+        let candidates = match self {
+            PeerAddress::Ip(addr) if addr.ip().is_unspecified() => vec![
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), addr.port()),
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), addr.port()),
+            ],
+            _ => self.resolve()?,
+        };
+
+        let mut last_error = None;
+        for addr in candidates {
+            match TcpListener::bind(addr) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(NetworkError::ConnectionFailed(format!(
+            "Failed to bind to {}: {}",
+            self,
+            last_error.map(|e| e.to_string()).unwrap_or_default()
+        )))
+    }
+}
+
+impl From<SocketAddr> for PeerAddress {
+    fn from(addr: SocketAddr) -> Self {
+        PeerAddress::Ip(addr)
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddress::Ip(addr) => write!(f, "{}", addr),
+            PeerAddress::Host { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+/// Parses `host:port`, with IPv6 hosts in brackets
+impl FromStr for PeerAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, port) = s.rsplit_once(':')
+            .ok_or_else(|| format!("Missing port in peer address '{}'", s))?;
+        if host.contains(':') && !host.starts_with('[') {
+            return Err(format!("IPv6 peer address '{}' must be written as [address]:port", s));
+        }
+        let port = port.parse::<u16>()
+            .map_err(|_| format!("Invalid port in peer address '{}'", s))?;
+        PeerAddress::new(host, port)
+    }
+}
+
+impl Serialize for PeerAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PeerAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peer_addresses() {
+        let v4: PeerAddress = "192.168.1.100:8333".parse().unwrap();
+        assert_eq!(v4, PeerAddress::Ip("192.168.1.100:8333".parse().unwrap()));
+
+        let v6: PeerAddress = "[2001:db8::1]:8333".parse().unwrap();
+        assert_eq!(v6.host(), "2001:db8::1");
+        assert_eq!(v6.to_string(), "[2001:db8::1]:8333");
+        assert_eq!(PeerAddress::new("2001:db8::1", 8333).unwrap(), v6);
+        assert!("2001:db8::1:8333".parse::<PeerAddress>().is_err());
+
+        let host: PeerAddress = "Seed.Example.com:8333".parse().unwrap();
+        assert_eq!(host, PeerAddress::Host { host: "seed.example.com".to_string(), port: 8333 });
+        assert!("bad_host:8333".parse::<PeerAddress>().is_err());
+        assert!("seed.example.com".parse::<PeerAddress>().is_err());
+    }
+
+    #[test]
+    fn test_resolve_and_bind() {
+        let localhost = PeerAddress::new("localhost", 8333).unwrap();
+        assert!(localhost.resolve().unwrap().iter().all(|addr| addr.ip().is_loopback()));
+
+        let listener = PeerAddress::new("::", 0).unwrap().bind().unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(PeerAddress::new("127.0.0.1", port).unwrap().connect().is_ok());
+    }
+}
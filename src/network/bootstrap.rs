@@ -0,0 +1,317 @@
+//! Signed bootstrap bundles
+//!
+//! A bootstrap bundle lists seed peers and checkpoints for a new node. Bundles
+//! are fetched over HTTP(S) and only used once their signature checks out
+//! against one of the publisher keys built into the node. The last verified
+//! bundle is kept in the data directory and checked again whenever it is loaded.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use serde::{Serialize, Deserialize};
+
+use crate::consensus::checkpoints::Checkpoints;
+use crate::crypto::signature::verify_signature;
+use crate::network::PeerAddress;
+
+/// File in the data directory holding the last verified bundle
+pub const BOOTSTRAP_FILE: &str = "bootstrap.json";
+
+/// Hex ed25519 public keys allowed to sign bootstrap bundles, compiled into the
+/// node. This is the project's bundle signing key: its private half is held
+/// offline by the maintainers and only signs the bundles served from the
+/// project's seed URLs. There is no configuration override, and a bundle's own
+/// `public_key` only says which of these keys signed it, so adding or rotating
+/// a key takes a new release.
+pub const BOOTSTRAP_PUBLISHER_KEYS: &[&str] = &[
+    "30c30fa5aed8047775ba3b857f3ea2e91c6a658c428d22bef60d8482da2d35e0",
+];
+
+/// How long a single bundle download may take
+pub const BOOTSTRAP_FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Largest bundle accepted from a server, in bytes
+pub const MAX_BUNDLE_SIZE: usize = 1024 * 1024;
+
+/// Signed contents of a bundle
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BootstrapPayload {
+    /// Publisher's sequence number; a node never replaces a bundle with an older one
+    pub version: u64,
+    /// Seed peers as `host:port`
+    pub seeds: Vec<String>,
+    /// Checkpoints as `<height>:<hash>`
+    pub checkpoints: Vec<String>,
+}
+
+/// Bundle as served by a publisher
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BootstrapBundle {
+    pub payload: BootstrapPayload,
+    /// Hex public key of the publisher
+    pub public_key: String,
+    /// Hex signature over the JSON encoding of the payload
+    pub signature: String,
+}
+
+impl BootstrapPayload {
+    /// Bytes the publisher signs
+    pub fn signing_message(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("bootstrap payload serializes")
+    }
+}
+
+impl BootstrapBundle {
+    /// Sign a payload as a publisher
+    pub fn sign(payload: BootstrapPayload, signing_key: &SigningKey) -> Self {
+        let signature = signing_key.sign(&payload.signing_message());
+        BootstrapBundle {
+            public_key: hex::encode(signing_key.verifying_key().to_bytes()),
+            signature: hex::encode(signature.to_bytes()),
+            payload,
+        }
+    }
+
+    /// Check the bundle is signed by one of `trusted_keys` and that its seeds and
+    /// checkpoints are well formed
+    pub fn verify(&self, trusted_keys: &[&str]) -> Result<(), String> {
+        let public_key = self.public_key.to_lowercase();
+        if !trusted_keys.iter().any(|key| key.eq_ignore_ascii_case(&public_key)) {
+            return Err(format!("Bundle signed by untrusted key {}", self.public_key));
+        }
+
+        let key_bytes: [u8; 32] = hex::decode(&public_key).ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| format!("Invalid public key {}", self.public_key))?;
+        let verifying_key = VerifyingKey::from_bytes(&key_bytes)
+            .map_err(|e| format!("Invalid public key {}: {}", self.public_key, e))?;
+        let signature = hex::decode(&self.signature)
+            .map_err(|_| "Invalid bundle signature encoding".to_string())?;
+        if !verify_signature(&verifying_key, &self.payload.signing_message(), &signature) {
+            return Err("Bundle signature does not match its contents".to_string());
+        }
+
+        self.seeds()?;
+        self.checkpoints()?;
+        Ok(())
+    }
+
+    /// Seed peers listed in the bundle
+    pub fn seeds(&self) -> Result<Vec<PeerAddress>, String> {
+        self.payload.seeds.iter()
+            .map(|seed| seed.parse::<PeerAddress>()
+                .map_err(|e| format!("Invalid seed '{}' in bundle: {}", seed, e)))
+            .collect()
+    }
+
+    /// Checkpoints listed in the bundle
+    pub fn checkpoints(&self) -> Result<Vec<(u64, String)>, String> {
+        self.payload.checkpoints.iter()
+            .map(|checkpoint| Checkpoints::parse_checkpoint(checkpoint))
+            .collect()
+    }
+
+    /// Path of the bootstrap file inside a data directory
+    pub fn path_in<P: AsRef<Path>>(data_dir: P) -> PathBuf {
+        data_dir.as_ref().join(BOOTSTRAP_FILE)
+    }
+
+    /// Load the saved bundle, `None` when no bundle has been fetched yet. The
+    /// bundle is verified again, so a tampered file is rejected.
+    pub fn load_from_file<P: AsRef<Path>>(path: P, trusted_keys: &[&str]) -> Result<Option<Self>, String> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read bootstrap bundle: {}", e))?;
+        let bundle: BootstrapBundle = serde_json::from_str(&data)
+            .map_err(|e| format!("Failed to parse bootstrap bundle: {}", e))?;
+        bundle.verify(trusted_keys)?;
+        Ok(Some(bundle))
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize bootstrap bundle: {}", e))?;
+        fs::write(&path, data)
+            .map_err(|e| format!("Failed to write bootstrap bundle: {}", e))
+    }
+}
+
+/// Download a bundle, without verifying it
+pub fn fetch_bundle(url: &str, timeout: Duration) -> Result<BootstrapBundle, String> {
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(format!("Unsupported bootstrap URL '{}'", url));
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to create runtime: {}", e))?;
+
+    let body = runtime.block_on(async {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let mut response = client.get(url).send().await
+            .map_err(|e| format!("GET {} failed: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("GET {} returned {}", url, response.status()));
+        }
+        let too_large = || format!("Bundle from {} is larger than {} bytes", url, MAX_BUNDLE_SIZE);
+        if response.content_length().is_some_and(|length| length > MAX_BUNDLE_SIZE as u64) {
+            return Err(too_large());
+        }
+        // Read chunk by chunk so a server ignoring its Content-Length can't
+        // make us buffer more than the cap
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await
+            .map_err(|e| format!("GET {} failed: {}", url, e))?
+        {
+            if body.len() + chunk.len() > MAX_BUNDLE_SIZE {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    })?;
+
+    serde_json::from_slice(&body)
+        .map_err(|e| format!("Invalid bundle from {}: {}", url, e))
+}
+
+/// Fetch bundles from `urls` in order and return the first one that verifies
+/// and is not older than `current`. Every URL's failure is reported when none
+/// succeeds.
+pub fn update_bundle(
+    urls: &[String],
+    trusted_keys: &[&str],
+    current: Option<&BootstrapBundle>,
+) -> Result<BootstrapBundle, String> {
+    if urls.is_empty() {
+        return Err("No bootstrap URLs configured".to_string());
+    }
+
+    let mut errors = Vec::new();
+    for url in urls {
+        let result = fetch_bundle(url, BOOTSTRAP_FETCH_TIMEOUT)
+            .and_then(|bundle| accept_bundle(bundle, trusted_keys, current));
+        match result {
+            Ok(bundle) => return Ok(bundle),
+            Err(e) => errors.push(format!("{}: {}", url, e)),
+        }
+    }
+    Err(format!("No usable bootstrap bundle:\n  {}", errors.join("\n  ")))
+}
+
+/// Verify a fetched bundle and refuse to roll back to an older version
+pub fn accept_bundle(
+    bundle: BootstrapBundle,
+    trusted_keys: &[&str],
+    current: Option<&BootstrapBundle>,
+) -> Result<BootstrapBundle, String> {
+    bundle.verify(trusted_keys)?;
+    if let Some(current) = current
+        && bundle.payload.version < current.payload.version
+    {
+        return Err(format!(
+            "Bundle version {} is older than the saved version {}",
+            bundle.payload.version, current.payload.version
+        ));
+    }
+    Ok(bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys::generate_keypair;
+
+    fn test_bundle(version: u64, signing_key: &SigningKey) -> BootstrapBundle {
+        BootstrapBundle::sign(BootstrapPayload {
+            version,
+            seeds: vec!["seed.example.com:8333".to_string(), "[2001:db8::1]:8333".to_string()],
+            checkpoints: vec![format!("10:{}", "ab".repeat(32))],
+        }, signing_key)
+    }
+
+    #[test]
+    fn test_bundle_verification() {
+        let publisher = generate_keypair();
+        let key = hex::encode(publisher.verifying_key().to_bytes());
+        let bundle = test_bundle(1, &publisher);
+
+        assert!(bundle.verify(&[key.as_str()]).is_ok());
+        assert_eq!(bundle.seeds().unwrap().len(), 2);
+        assert_eq!(bundle.checkpoints().unwrap(), vec![(10, "ab".repeat(32))]);
+
+        // Keys that are not embedded are refused
+        assert!(bundle.verify(BOOTSTRAP_PUBLISHER_KEYS).is_err());
+
+        // So are bundles changed after signing
+        let mut tampered = bundle.clone();
+        tampered.payload.seeds.push("203.0.113.9:8333".to_string());
+        assert!(tampered.verify(&[key.as_str()]).is_err());
+
+        // Rollbacks to an older version are refused
+        let newer = test_bundle(2, &publisher);
+        assert!(accept_bundle(bundle.clone(), &[key.as_str()], Some(&newer)).is_err());
+        assert!(accept_bundle(newer.clone(), &[key.as_str()], Some(&bundle)).is_ok());
+    }
+
+    #[test]
+    fn test_saved_bundle_is_verified_on_load() {
+        let path = std::env::temp_dir().join(format!("rust_chain_bootstrap_{}.json", std::process::id()));
+        let publisher = generate_keypair();
+        let key = hex::encode(publisher.verifying_key().to_bytes());
+        assert_eq!(BootstrapBundle::load_from_file(&path, &[key.as_str()]).unwrap(), None);
+
+        let bundle = test_bundle(3, &publisher);
+        bundle.save_to_file(&path).unwrap();
+        assert_eq!(BootstrapBundle::load_from_file(&path, &[key.as_str()]).unwrap(), Some(bundle));
+        assert!(BootstrapBundle::load_from_file(&path, BOOTSTRAP_PUBLISHER_KEYS).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pinned_keys_are_valid() {
+        assert!(!BOOTSTRAP_PUBLISHER_KEYS.is_empty());
+        for key in BOOTSTRAP_PUBLISHER_KEYS {
+            let bytes: [u8; 32] = hex::decode(key).unwrap().try_into().unwrap();
+            assert!(VerifyingKey::from_bytes(&bytes).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_oversized_bundle_is_refused() {
+        use std::io::{Read, Write};
+
+        // A server that sends no Content-Length and more than the cap
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/bundle.json", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n");
+            let _ = stream.write_all(&vec![b' '; MAX_BUNDLE_SIZE + 1]);
+        });
+
+        let err = fetch_bundle(&url, BOOTSTRAP_FETCH_TIMEOUT).unwrap_err();
+        assert!(err.contains("larger than"), "{}", err);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_url_is_refused() {
+        assert!(fetch_bundle("ftp://example.com/bundle.json", BOOTSTRAP_FETCH_TIMEOUT).is_err());
+        assert!(update_bundle(&[], BOOTSTRAP_PUBLISHER_KEYS, None).is_err());
+    }
+}
@@ -0,0 +1,710 @@
+//! Peer protocol conformance vectors and a handshake and inventory fuzzer
+//!
+//! Alternative implementations of the peer protocol can check themselves
+//! against the golden files in `tests/vectors`. `network_messages.json` holds
+//! one fixed message of every `MessageType` together with its frame, the
+//! length-prefixed bytes that go on the wire. `network_exchanges.json` holds
+//! requests and the frames a fresh node on the genesis chain answers them with,
+//! plus whether it hangs up. Only requests whose answer depends on neither the
+//! clock nor randomness are included. Every timestamp is `VECTOR_TIMESTAMP`,
+//! and answers are compared with their timestamp set to it. `dump-test-vectors`
+//! rewrites these files together with the consensus vectors.
+//!
+//! `fuzz_handshake_and_inventory` mutates handshake, `Inv` and `GetData`
+//! messages, both field by field and byte by byte, and delivers them to a node
+//! the way a connection would. It reports panics, and it reports invariants the
+//! handler broke: only an acknowledgement signed over the node's challenge may
+//! register the peer, and no message may move the chain.
+
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::chain::Chain;
+use crate::blockchain::genesis::genesis_block;
+use crate::consensus::test_vectors::{read_file, write_file};
+use crate::mempool::snapshot::SnapshotEntry;
+use crate::network::framing::{encode_frame, FrameReader, FramingConfig, LENGTH_PREFIX_SIZE};
+use crate::network::identity::{HandshakeRole, NodeIdentity};
+use crate::network::inventory::MAX_INV_BATCH;
+use crate::network::protocol::{
+    BlockHeader, Features, InventoryItem, InventoryType, MessageResult, MessageType, NetworkMessage, PeerCapabilities, PeerInfo,
+    LOCAL_FEATURES, LOCAL_SERVICES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::network::server::{NetworkServer, OfflineConnection};
+
+/// Golden file names inside the vector directory
+pub const MESSAGES_FILE: &str = "network_messages.json";
+pub const EXCHANGES_FILE: &str = "network_exchanges.json";
+
+/// Timestamp of every message in the vectors
+pub const VECTOR_TIMESTAMP: u64 = 1_700_000_000;
+
+/// Secret key of the node answering the exchanges
+const NODE_KEY: [u8; 32] = [1; 32];
+
+/// Secret key of the peer whose handshake the vectors and fuzzer send
+const PEER_KEY: [u8; 32] = [2; 32];
+
+/// Challenge the peer's opening handshake issues
+const PEER_NONCE: &str = "000102030405060708090a0b0c0d0e0f";
+
+/// Challenge the sample acknowledgement is signed over
+const ACK_CHALLENGE: &str = "0f0e0d0c0b0a09080706050403020100";
+
+/// Address the vector and fuzzer connections come from
+const PEER_ADDR: &str = "203.0.113.5:8333";
+
+/// One message and its frame
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageVector {
+    pub name: String,
+    pub message: NetworkMessage,
+    /// Hex of the length prefix and the serialized message
+    pub frame: String,
+}
+
+/// A request to a fresh node and the frames it answers with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExchangeVector {
+    pub name: String,
+    pub request: String,
+    pub responses: Vec<String>,
+    /// Whether the node closes the connection after answering
+    pub disconnects: bool,
+}
+
+/// All peer protocol conformance vectors
+#[derive(Debug, Clone)]
+pub struct ConformanceVectors {
+    pub messages: Vec<MessageVector>,
+    pub exchanges: Vec<ExchangeVector>,
+}
+
+/// A message with the fixed vector timestamp
+fn message(message_type: MessageType) -> NetworkMessage {
+    NetworkMessage {
+        timestamp: VECTOR_TIMESTAMP,
+        ..NetworkMessage::new(message_type)
+    }
+}
+
+fn frame_hex(message: &NetworkMessage) -> String {
+    hex::encode(encode_frame(message).expect("vector messages serialize"))
+}
+
+/// Read one message from a frame the way a connection does
+fn decode_frame(frame: &[u8]) -> Result<NetworkMessage, String> {
+    FrameReader::new(FramingConfig::default())
+        .read_message(&mut Cursor::new(frame))
+        .map_err(|e| e.to_string())
+}
+
+fn peer_addr() -> SocketAddr {
+    PEER_ADDR.parse().unwrap()
+}
+
+/// A node on the genesis chain with a fixed identity
+fn conformance_node() -> NetworkServer {
+    NetworkServer::new(Chain::new(), "127.0.0.1".to_string(), 0)
+        .with_identity(NodeIdentity::from_secret_key(NODE_KEY))
+}
+
+/// The vector peer's opening handshake, issuing `PEER_NONCE`
+fn peer_handshake(chain_height: u64) -> MessageType {
+    let identity = NodeIdentity::from_secret_key(PEER_KEY);
+    MessageType::Handshake {
+        version: PROTOCOL_VERSION,
+        min_version: MIN_PROTOCOL_VERSION,
+        features: LOCAL_FEATURES,
+        services: LOCAL_SERVICES,
+        node_id: identity.node_id(),
+        chain_height,
+        public_key: identity.public_key_hex(),
+        nonce: PEER_NONCE.to_string(),
+        signature: String::new(),
+        listen_port: 8333,
+    }
+}
+
+/// The vector peer's acknowledgement of `challenge`
+fn peer_ack(challenge: &str) -> MessageType {
+    MessageType::HandshakeAck {
+        signature: NodeIdentity::from_secret_key(PEER_KEY).sign_challenge(HandshakeRole::Initiator, challenge),
+    }
+}
+
+fn sample_transaction() -> Transaction {
+    Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 10,
+        signature: vec![1, 2, 3, 4, 254, 255],
+        lock_height: None,
+        expiry_height: None,
+    }
+}
+
+fn sample_peer() -> PeerInfo {
+    PeerInfo {
+        address: "203.0.113.5".to_string(),
+        port: 8333,
+        node_id: NodeIdentity::from_secret_key(PEER_KEY).node_id(),
+        last_seen: VECTOR_TIMESTAMP,
+        chain_height: 42,
+        capabilities: PeerCapabilities { version: PROTOCOL_VERSION, features: LOCAL_FEATURES },
+        services: LOCAL_SERVICES,
+        listen_port: 8333,
+    }
+}
+
+/// One message of every type, in declaration order
+fn sample_messages() -> Vec<MessageType> {
+    let genesis = genesis_block();
+    let child = Block::new(genesis.header.hash.clone(), vec![], 7, genesis.header.timestamp + 60, 1);
+    let tx = sample_transaction();
+    let inventory = vec![InventoryItem::block(child.header.hash.clone()), InventoryItem::transaction(tx.hash())];
+
+    vec![
+        MessageType::GetPeers,
+        MessageType::Peers(vec![sample_peer()]),
+        MessageType::GetBlocks { start_hash: genesis.header.hash.clone(), count: 10 },
+        MessageType::Blocks(vec![genesis.clone()]),
+        MessageType::GetBlockRange { start_height: 1, count: 100 },
+        MessageType::GetBlocksByLocator { locator: vec![genesis.header.hash.clone()], count: 500 },
+        MessageType::LocatorBlocks { fork_height: Some(0), blocks: vec![child.clone()], remaining: 0 },
+        MessageType::NewBlock(child),
+        MessageType::GetChainInfo,
+        MessageType::ChainInfo { latest_hash: genesis.header.hash.clone(), height: 0 },
+        MessageType::Ping,
+        MessageType::Pong,
+        peer_handshake(42),
+        peer_ack(ACK_CHALLENGE),
+        MessageType::NewTransaction {
+            transaction_data: tx.to_hex(),
+            from_address: tx.from.clone(),
+            to_address: tx.to.clone(),
+            amount: tx.amount,
+            signature: hex::encode(&tx.signature),
+            fee_per_byte: 2.5,
+        },
+        MessageType::GetMempool,
+        MessageType::MempoolResponse {
+            transactions: vec![tx.to_hex()],
+            count: 1,
+            entries: vec![SnapshotEntry { txid: tx.hash(), fee_per_byte: 2.5, size: tx.to_hex().len() / 2, time: VECTOR_TIMESTAMP }],
+        },
+        MessageType::GetTransaction { tx_hash: tx.hash() },
+        MessageType::TransactionResponse { tx_hash: tx.hash(), transaction_data: Some(tx.to_hex()), confirmed: false, block_hash: None },
+        MessageType::NodeStatus { uptime: 3_600, peer_count: 8, block_height: 42, memory_usage: 64 << 20, cpu_usage: 12.5 },
+        MessageType::GetNodeStats,
+        MessageType::NodeStatsResponse {
+            version: "rust-chain-v1.0".to_string(),
+            uptime: 3_600,
+            connections: 8,
+            blocks: 43,
+            transactions_processed: 120,
+            bandwidth_in: 1 << 20,
+            bandwidth_out: 2 << 20,
+            memory_usage: 64 << 20,
+            cpu_usage: 12.5,
+            messages_in: 900,
+            messages_out: 1_100,
+        },
+        MessageType::PeerReport { peer_id: sample_peer().node_id, latency_ms: 85, reliability_score: 0.75, last_message_time: VECTOR_TIMESTAMP },
+        MessageType::SyncRequest { local_height: 0, local_best_hash: genesis.header.hash.clone() },
+        MessageType::SyncResponse { should_sync: true, start_height: 1, end_height: 42, blocks_available: 42 },
+        MessageType::BlockHeaders { headers: vec![BlockHeader::from(&genesis.header)], start_height: 0 },
+        MessageType::GetBlockHeaders { start_height: 0, count: 10 },
+        MessageType::VersionNegotiation {
+            supported_versions: (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).collect(),
+            preferred_version: PROTOCOL_VERSION,
+        },
+        MessageType::AddressBook { addresses: vec![sample_peer()], timestamp: VECTOR_TIMESTAMP },
+        MessageType::Inv(inventory.clone()),
+        MessageType::GetData(inventory),
+    ]
+}
+
+/// Requests whose answer from a fresh node is fixed, sent without a handshake
+/// unless they are one
+fn sample_requests() -> Vec<(&'static str, MessageType)> {
+    let genesis = genesis_block();
+    let unknown_block = InventoryItem::block("ab".repeat(32));
+    vec![
+        ("ping", MessageType::Ping),
+        ("pong", MessageType::Pong),
+        ("get_chain_info", MessageType::GetChainInfo),
+        ("get_peers", MessageType::GetPeers),
+        ("get_blocks_from_start", MessageType::GetBlocks { start_hash: "0".to_string(), count: 10 }),
+        ("get_blocks_after_tip", MessageType::GetBlocks { start_hash: genesis.header.hash.clone(), count: 10 }),
+        ("get_block_range", MessageType::GetBlockRange { start_height: 0, count: 1 }),
+        ("get_block_range_past_tip", MessageType::GetBlockRange { start_height: u64::MAX, count: 500 }),
+        ("get_block_headers", MessageType::GetBlockHeaders { start_height: 0, count: 10 }),
+        ("get_blocks_by_locator", MessageType::GetBlocksByLocator { locator: vec![genesis.header.hash.clone()], count: 10 }),
+        ("get_blocks_by_unknown_locator", MessageType::GetBlocksByLocator { locator: vec!["cd".repeat(32)], count: 10 }),
+        ("inv_unknown_block", MessageType::Inv(vec![unknown_block.clone()])),
+        ("inv_transaction_before_handshake", MessageType::Inv(vec![InventoryItem::transaction(sample_transaction().hash())])),
+        ("get_data_genesis", MessageType::GetData(vec![InventoryItem::block(genesis.header.hash.clone()), unknown_block])),
+        ("get_mempool_before_handshake", MessageType::GetMempool),
+        ("handshake_unsupported_version", MessageType::Handshake {
+            version: PROTOCOL_VERSION + 2,
+            min_version: PROTOCOL_VERSION + 1,
+            ..peer_handshake(0)
+        }),
+        ("handshake_mismatched_node_id", MessageType::Handshake {
+            node_id: "ab".repeat(32),
+            ..peer_handshake(0)
+        }),
+        ("handshake_ack_unsolicited", peer_ack(ACK_CHALLENGE)),
+    ]
+}
+
+/// Answer `request` on a new connection to a fresh node, returning the response
+/// frames and whether the node hangs up
+fn exchange(request: &NetworkMessage) -> (Vec<String>, bool) {
+    let node = conformance_node();
+    let mut connection = node.offline_connection(peer_addr());
+    let (responses, disconnects) = match connection.deliver(request.clone()) {
+        MessageResult::Success => (vec![], false),
+        MessageResult::Response(response) => {
+            // Like `run_connection`, a version reply ends the connection
+            let disconnects = matches!(response.message_type, MessageType::VersionNegotiation { .. });
+            (vec![response], disconnects)
+        },
+        MessageResult::MultipleResponses(responses) => (responses, false),
+        MessageResult::Error(_) => (vec![], true),
+    };
+    let responses = responses.into_iter()
+        .map(|response| frame_hex(&NetworkMessage { timestamp: VECTOR_TIMESTAMP, ..response }))
+        .collect();
+    (responses, disconnects)
+}
+
+impl ConformanceVectors {
+    /// Encode the fixed messages and answer the fixed requests with the current code
+    pub fn generate() -> Self {
+        let messages = sample_messages().into_iter()
+            .map(|message_type| {
+                let message = message(message_type);
+                MessageVector {
+                    name: message.message_type.name().to_string(),
+                    frame: frame_hex(&message),
+                    message,
+                }
+            })
+            .collect();
+
+        let exchanges = sample_requests().into_iter()
+            .map(|(name, request)| {
+                let request = message(request);
+                let (responses, disconnects) = exchange(&request);
+                ExchangeVector { name: name.to_string(), request: frame_hex(&request), responses, disconnects }
+            })
+            .collect();
+
+        ConformanceVectors { messages, exchanges }
+    }
+
+    /// Load the golden files from `dir`
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        Ok(ConformanceVectors {
+            messages: read_file(&dir.join(MESSAGES_FILE))?,
+            exchanges: read_file(&dir.join(EXCHANGES_FILE))?,
+        })
+    }
+
+    /// Write the golden files to `dir`, which must exist
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), String> {
+        let dir = dir.as_ref();
+        write_file(&dir.join(MESSAGES_FILE), &self.messages)?;
+        write_file(&dir.join(EXCHANGES_FILE), &self.exchanges)
+    }
+
+    /// Check every frame against the current encoder and every exchange against
+    /// a fresh node, returning one message per difference
+    pub fn check(&self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+
+        for vector in &self.messages {
+            let frame = frame_hex(&vector.message);
+            if frame != vector.frame {
+                mismatches.push(format!("{} '{}': frame is {}, expected {}", MESSAGES_FILE, vector.name, frame, vector.frame));
+            }
+            let decoded = hex::decode(&vector.frame).map_err(|e| e.to_string()).and_then(|frame| decode_frame(&frame));
+            match decoded {
+                Ok(decoded) if frame_hex(&decoded) == vector.frame => {},
+                Ok(_) => mismatches.push(format!("{} '{}': frame does not decode to its message", MESSAGES_FILE, vector.name)),
+                Err(e) => mismatches.push(format!("{} '{}': frame does not decode: {}", MESSAGES_FILE, vector.name, e)),
+            }
+        }
+
+        for vector in &self.exchanges {
+            let request = match hex::decode(&vector.request).map_err(|e| e.to_string()).and_then(|frame| decode_frame(&frame)) {
+                Ok(request) => request,
+                Err(e) => {
+                    mismatches.push(format!("{} '{}': request does not decode: {}", EXCHANGES_FILE, vector.name, e));
+                    continue;
+                },
+            };
+            let (responses, disconnects) = exchange(&request);
+            if responses != vector.responses {
+                mismatches.push(format!("{} '{}': responses are {:?}, expected {:?}", EXCHANGES_FILE, vector.name, responses, vector.responses));
+            }
+            if disconnects != vector.disconnects {
+                mismatches.push(format!("{} '{}': disconnects is {}, expected {}", EXCHANGES_FILE, vector.name, disconnects, vector.disconnects));
+            }
+        }
+        mismatches
+    }
+
+    /// Names of the vectors, in file order
+    pub fn names(&self) -> Vec<String> {
+        self.messages.iter().map(|v| format!("{}:{}", MESSAGES_FILE, v.name))
+            .chain(self.exchanges.iter().map(|v| format!("{}:{}", EXCHANGES_FILE, v.name)))
+            .collect()
+    }
+}
+
+/// Outcome of a fuzzing run
+#[derive(Debug, Clone, Default)]
+pub struct FuzzReport {
+    /// Mutated messages tried
+    pub cases: usize,
+    /// Cases the frame reader refused before they reached the handler
+    pub undecodable: usize,
+    /// Cases the handler answered, ignored or refused
+    pub handled: usize,
+    /// Panics and broken invariants, one line per case
+    pub failures: Vec<String>,
+}
+
+/// A handshake, acknowledgement, `Inv` or `GetData` message with one or more
+/// fields set to something a well-behaved peer would not send
+fn mutate_fields(rng: &mut StdRng) -> MessageType {
+    let extremes = |rng: &mut StdRng| match rng.gen_range(0..4) {
+        0 => 0,
+        1 => u32::MAX,
+        2 => PROTOCOL_VERSION + 1,
+        _ => rng.gen_range(0..=PROTOCOL_VERSION + 1),
+    };
+    let hex_string = |rng: &mut StdRng, bytes: usize| hex::encode((0..bytes).map(|_| rng.r#gen::<u8>()).collect::<Vec<u8>>());
+
+    match rng.gen_range(0..4) {
+        0 => {
+            let MessageType::Handshake { mut version, mut min_version, mut features, mut services, mut node_id, mut chain_height, mut public_key, mut nonce, mut signature, mut listen_port } = peer_handshake(0) else {
+                unreachable!("peer_handshake builds a handshake");
+            };
+            for _ in 0..rng.gen_range(1..=3) {
+                match rng.gen_range(0..8) {
+                    0 => version = extremes(rng),
+                    1 => min_version = extremes(rng),
+                    2 => features = Features(rng.r#gen()),
+                    3 => services.0 = rng.r#gen(),
+                    4 => {
+                        let bytes = rng.gen_range(0..40);
+                        node_id = hex_string(rng, bytes);
+                    },
+                    5 => if rng.gen_bool(0.5) {
+                        chain_height = rng.r#gen();
+                    } else {
+                        listen_port = rng.r#gen();
+                    },
+                    6 => public_key = if rng.gen_bool(0.5) { hex_string(rng, 32) } else { "not hex".to_string() },
+                    _ => if rng.gen_bool(0.5) {
+                        nonce = hex_string(rng, 16);
+                    } else {
+                        let bytes = rng.gen_range(0..80);
+                        signature = hex_string(rng, bytes);
+                    },
+                }
+            }
+            MessageType::Handshake { version, min_version, features, services, node_id, chain_height, public_key, nonce, signature, listen_port }
+        },
+        1 => match rng.gen_range(0..3) {
+            // Signed by the peer, but over a challenge the node did not issue
+            0 => peer_ack(&hex_string(rng, 16)),
+            1 => {
+                let bytes = rng.gen_range(0..80);
+                MessageType::HandshakeAck { signature: hex_string(rng, bytes) }
+            },
+            _ => MessageType::HandshakeAck { signature: "not hex".to_string() },
+        },
+        kind => {
+            let genesis_hash = genesis_block().header.hash;
+            let count = if rng.gen_bool(0.1) { MAX_INV_BATCH + 1 } else { rng.gen_range(0..20) };
+            let items = (0..count).map(|_| {
+                let hash = match rng.gen_range(0..5) {
+                    0 => String::new(),
+                    1 => genesis_hash.clone(),
+                    2 => "z".repeat(rng.gen_range(1..200)),
+                    _ => hex_string(rng, 32),
+                };
+                let inv_type = if rng.gen_bool(0.5) { InventoryType::Block } else { InventoryType::Transaction };
+                InventoryItem { inv_type, hash }
+            }).collect();
+            if kind == 2 { MessageType::Inv(items) } else { MessageType::GetData(items) }
+        },
+    }
+}
+
+/// Damage the serialized message inside `frame`, usually keeping the length
+/// prefix consistent so the damage reaches the parser
+fn mutate_bytes(rng: &mut StdRng, frame: &[u8]) -> Vec<u8> {
+    let mut body = frame[LENGTH_PREFIX_SIZE..].to_vec();
+    for _ in 0..rng.gen_range(1..=4) {
+        if body.is_empty() {
+            break;
+        }
+        let at = rng.gen_range(0..body.len());
+        match rng.gen_range(0..6) {
+            0 => body[at] ^= 1 << rng.gen_range(0..8),
+            1 => body[at] = rng.r#gen(),
+            2 => body.truncate(at),
+            3 => {
+                let end = rng.gen_range(at..=body.len());
+                body.drain(at..end);
+            },
+            4 => {
+                let end = rng.gen_range(at..=body.len().min(at + 64));
+                let chunk = body[at..end].to_vec();
+                insert_at(&mut body, at, &chunk);
+            },
+            _ => {
+                let number: &[u8] = match rng.gen_range(0..3) {
+                    0 => b"18446744073709551616",
+                    1 => b"-1",
+                    _ => b"1e400",
+                };
+                insert_at(&mut body, at, number);
+            },
+        }
+    }
+    let length = if rng.gen_bool(0.9) { body.len() as u32 } else { rng.r#gen() };
+    let mut mutated = length.to_be_bytes().to_vec();
+    mutated.extend_from_slice(&body);
+    mutated
+}
+
+fn insert_at(body: &mut Vec<u8>, at: usize, bytes: &[u8]) {
+    let tail = body.split_off(at);
+    body.extend_from_slice(bytes);
+    body.extend(tail);
+}
+
+/// Check what the node did with one fuzzed message, returning what went wrong
+fn check_invariants(
+    node: &NetworkServer,
+    genesis_hash: &str,
+    message: &MessageType,
+    result: &MessageResult,
+    before: Option<PeerCapabilities>,
+    after: Option<PeerCapabilities>,
+    peers_before: usize,
+) -> Vec<String> {
+    let mut broken = Vec::new();
+    let tip = node.chain().lock().unwrap().tip().map(|block| block.header.hash.clone());
+    if tip.as_deref() != Some(genesis_hash) {
+        broken.push(format!("chain tip moved to {:?}", tip));
+    }
+    let peers_after = node.get_connected_peers().len();
+
+    match message {
+        MessageType::Handshake { nonce, .. } => {
+            if let MessageResult::Response(NetworkMessage { message_type: MessageType::Handshake { public_key, signature, .. }, .. }) = result
+                && NodeIdentity::verify_challenge(public_key, HandshakeRole::Responder, nonce, signature).is_err()
+            {
+                broken.push("handshake reply did not answer the peer's challenge".to_string());
+            }
+        },
+        MessageType::HandshakeAck { .. } if !matches!(result, MessageResult::Error(_)) => {
+            broken.push(format!("answered a forged acknowledgement with {:?}", result));
+        },
+        MessageType::Inv(items) => {
+            let negotiated = after.unwrap_or_default();
+            match result {
+                MessageResult::Response(NetworkMessage { message_type: MessageType::GetData(wanted), .. }) => {
+                    if !negotiated.allows(message) {
+                        broken.push("answered inventory the peer did not negotiate".to_string());
+                    }
+                    if let Some(item) = wanted.iter().find(|item| !items.contains(item)) {
+                        broken.push(format!("requested {:?}, which was not announced", item));
+                    }
+                },
+                MessageResult::Success if !negotiated.allows(message) => {},
+                other => broken.push(format!("answered Inv with {:?}", other)),
+            }
+        },
+        MessageType::GetData(items) => {
+            let asked: HashSet<&str> = items.iter().map(|item| item.hash.as_str()).collect();
+            let responses = match result {
+                MessageResult::MultipleResponses(responses) => responses.as_slice(),
+                _ => &[],
+            };
+            for response in responses {
+                let served = match &response.message_type {
+                    MessageType::NewBlock(block) => block.header.hash.clone(),
+                    MessageType::NewTransaction { transaction_data, .. } => Transaction::from_hex(transaction_data)
+                        .map(|tx| tx.hash())
+                        .unwrap_or_default(),
+                    other => {
+                        broken.push(format!("answered GetData with {}", other.name()));
+                        continue;
+                    },
+                };
+                if !asked.contains(served.as_str()) {
+                    broken.push(format!("served {}, which was not asked for", served));
+                }
+            }
+        },
+        _ => {},
+    }
+    // Only a signed acknowledgement negotiates or registers, and no fuzzed one is
+    if after != before {
+        broken.push(format!("{} changed capabilities from {:?} to {:?}", message.name(), before, after));
+    }
+    if peers_after != peers_before {
+        broken.push(format!("{} changed the peer list", message.name()));
+    }
+    broken
+}
+
+/// Send the vector peer's opening handshake, then acknowledge the node's challenge
+/// if `complete`, returning what the node got wrong
+fn open_handshake(connection: &mut OfflineConnection, complete: bool) -> Vec<String> {
+    let challenge = match connection.deliver(message(peer_handshake(0))) {
+        MessageResult::Response(NetworkMessage { message_type: MessageType::Handshake { nonce, .. }, .. }) => nonce,
+        other => return vec![format!("answered the vector handshake with {:?}", other)],
+    };
+    if !complete {
+        return vec![];
+    }
+    match (connection.deliver(message(peer_ack(&challenge))), connection.capabilities()) {
+        (MessageResult::Success, Some(capabilities)) => {
+            let mut broken = Vec::new();
+            if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&capabilities.version) {
+                broken.push(format!("negotiated unsupported version {}", capabilities.version));
+            }
+            if !LOCAL_FEATURES.contains(capabilities.features) {
+                broken.push(format!("negotiated features {:?} we do not have", capabilities.features));
+            }
+            broken
+        },
+        (result, capabilities) => vec![format!("signed acknowledgement answered with {:?}, capabilities {:?}", result, capabilities)],
+    }
+}
+
+/// Deliver `cases` mutated handshake, acknowledgement, `Inv` and `GetData`
+/// messages, generated from `seed`, to a node on the genesis chain. Each case
+/// arrives on a new connection: a third of them before any handshake, a third
+/// while the node waits for the acknowledgement, and a third after a completed
+/// handshake. A panic is recorded and the node replaced, since it may have
+/// poisoned the node's locks.
+pub fn fuzz_handshake_and_inventory(seed: u64, cases: usize) -> FuzzReport {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut report = FuzzReport::default();
+    let genesis_hash = genesis_block().header.hash;
+    let mut node = conformance_node();
+
+    for case in 0..cases {
+        report.cases += 1;
+        let base = message(mutate_fields(&mut rng));
+        let frame = encode_frame(&base).expect("fuzzed messages serialize");
+        let frame = if rng.gen_bool(0.5) { mutate_bytes(&mut rng, &frame) } else { frame };
+        let prelude = rng.gen_range(0..3);
+
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let Ok(fuzzed) = decode_frame(&frame) else {
+                return None;
+            };
+            let mut connection = node.offline_connection(peer_addr());
+            let mut broken = match prelude {
+                0 => vec![],
+                opened => open_handshake(&mut connection, opened == 2),
+            };
+            let before = connection.capabilities();
+            let peers_before = node.get_connected_peers().len();
+            let message_type = fuzzed.message_type.clone();
+            let result = connection.deliver(fuzzed);
+            broken.extend(check_invariants(&node, &genesis_hash, &message_type, &result, before, connection.capabilities(), peers_before));
+            Some(broken)
+        }));
+
+        match outcome {
+            Ok(None) => report.undecodable += 1,
+            Ok(Some(broken)) => {
+                report.handled += 1;
+                report.failures.extend(broken.into_iter().map(|failure| format!("case {}: {}", case, failure)));
+            },
+            Err(cause) => {
+                let cause = cause.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| cause.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                report.failures.push(format!("case {}: panicked: {} (frame {})", case, cause, hex::encode(&frame)));
+                node = conformance_node();
+            },
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::test_vectors::GOLDEN_DIR;
+
+    fn golden() -> ConformanceVectors {
+        ConformanceVectors::load(Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN_DIR))
+            .expect("golden conformance vectors should load")
+    }
+
+    #[test]
+    fn test_golden_conformance_vectors_match() {
+        let mismatches = golden().check();
+        assert!(mismatches.is_empty(), "peer protocol changed:\n{}", mismatches.join("\n"));
+        assert_eq!(golden().names(), ConformanceVectors::generate().names());
+    }
+
+    #[test]
+    fn test_changed_frame_is_reported() {
+        let mut vectors = golden();
+        vectors.messages[0].frame.push_str("00");
+        vectors.exchanges[0].responses.clear();
+
+        let mismatches = vectors.check();
+        assert_eq!(mismatches.len(), 3); // frame mismatch and trailing byte, exchange responses
+    }
+
+    #[test]
+    fn test_block_that_does_not_extend_is_processed_again() {
+        let node = conformance_node();
+        let genesis = genesis_block();
+        let first = Block::new(genesis.header.hash.clone(), vec![], 1, genesis.header.timestamp + 60, 1);
+        let second = Block::new(first.header.hash.clone(), vec![], 2, genesis.header.timestamp + 120, 2);
+        let mut connection = node.offline_connection(peer_addr());
+
+        // Delivered before its parent, the block is not marked seen
+        connection.deliver(message(MessageType::NewBlock(second.clone())));
+        assert_eq!(node.chain().lock().unwrap().height(), 0);
+
+        connection.deliver(message(MessageType::NewBlock(first)));
+        connection.deliver(message(MessageType::NewBlock(second)));
+        assert_eq!(node.chain().lock().unwrap().height(), 2);
+    }
+
+    #[test]
+    fn test_fuzzed_handshakes_and_inventory() {
+        let report = fuzz_handshake_and_inventory(4937, 2_000);
+        assert!(report.failures.is_empty(), "fuzzing found:\n{}", report.failures.join("\n"));
+        assert_eq!(report.cases, 2_000);
+        assert!(report.handled > 500 && report.undecodable > 100);
+    }
+}
@@ -0,0 +1,263 @@
+//! Outbound connection dialer
+//!
+//! Without a dialer the node only has the peers that connect to it and the ones
+//! connected to by hand. The dialer keeps up to a target number of outbound
+//! connections: whenever some have dropped it picks new addresses from the seeds
+//! and an address book filled from the listen ports peers advertise, most
+//! reliable first. The count is the server's, of sockets it dialed that are still
+//! open. Banned peers, the ones that misbehaved too often lately, are never
+//! dialed, and no more than `MAX_OUTBOUND_PER_SUBNET` connections go to one
+//! subnet, so a single operator cannot fill every slot.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::alerts::peer_subnet;
+use crate::network::address::PeerAddress;
+use crate::network::protocol::PeerInfo;
+use crate::network::server::NetworkServer;
+use crate::{detail, status};
+
+/// Default number of outbound connections kept open
+pub const DEFAULT_OUTBOUND_TARGET: usize = 8;
+
+/// Default time between two checks of the outbound connections
+pub const DEFAULT_DIAL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Most connected peers in one subnet the dialer adds to
+pub const MAX_OUTBOUND_PER_SUBNET: usize = 2;
+
+/// Least time before an address that failed is dialed again
+pub const DIAL_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Most addresses kept in the address book
+pub const MAX_ADDRESS_BOOK: usize = 1_000;
+
+/// Keeps the node's outbound connections at a target count
+pub struct OutboundDialer {
+    seeds: Vec<PeerAddress>,
+    /// Addresses learned from peers, oldest first
+    address_book: Vec<PeerAddress>,
+    /// When each address that failed was last dialed
+    failed: HashMap<PeerAddress, Instant>,
+}
+
+impl OutboundDialer {
+    pub fn new(seeds: Vec<PeerAddress>) -> Self {
+        OutboundDialer {
+            seeds,
+            address_book: Vec::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    /// Add addresses to the address book, dropping the oldest past `MAX_ADDRESS_BOOK`
+    pub fn learn(&mut self, addresses: impl IntoIterator<Item = PeerAddress>) {
+        for address in addresses {
+            if !self.seeds.contains(&address) && !self.address_book.contains(&address) {
+                self.address_book.push(address);
+            }
+        }
+        let excess = self.address_book.len().saturating_sub(MAX_ADDRESS_BOOK);
+        self.address_book.drain(..excess);
+    }
+
+    /// Seeds and learned addresses, the ones to try next
+    pub fn candidates(&self) -> Vec<PeerAddress> {
+        self.seeds.iter().chain(&self.address_book).cloned().collect()
+    }
+
+    /// Pick addresses from `candidates`, in their order, to bring the open
+    /// `outbound` connections up to `target`. Connected addresses, ones that
+    /// failed within `DIAL_RETRY_DELAY` and ones in a subnet that already has
+    /// `MAX_OUTBOUND_PER_SUBNET` connected peers are skipped; a hostname counts
+    /// as its own subnet.
+    pub fn select(
+        &mut self,
+        candidates: &[PeerAddress],
+        outbound: &[PeerAddress],
+        peers: &[PeerInfo],
+        target: usize,
+        now: Instant,
+    ) -> Vec<PeerAddress> {
+        let needed = target.saturating_sub(outbound.len());
+        self.failed.retain(|_, at| now.saturating_duration_since(*at) < DIAL_RETRY_DELAY);
+
+        let mut per_subnet: HashMap<String, usize> = HashMap::new();
+        for peer in peers {
+            *per_subnet.entry(subnet(&peer.address)).or_default() += 1;
+        }
+
+        let mut selected = Vec::new();
+        for candidate in candidates {
+            if selected.len() >= needed {
+                break;
+            }
+            if outbound.contains(candidate)
+                || is_connected(candidate, peers)
+                || selected.contains(candidate)
+                || self.failed.contains_key(candidate)
+            {
+                continue;
+            }
+            let count = per_subnet.entry(subnet(&candidate.host())).or_default();
+            if *count >= MAX_OUTBOUND_PER_SUBNET {
+                continue;
+            }
+            *count += 1;
+            selected.push(candidate.clone());
+        }
+        selected
+    }
+
+    /// Record the outcome of dialing `address`; the server tracks the
+    /// connection itself once it is open
+    pub fn dialed(&mut self, address: PeerAddress, connected: bool, now: Instant) {
+        if connected {
+            self.failed.remove(&address);
+        } else {
+            self.failed.insert(address, now);
+        }
+    }
+
+    /// Check the server's outbound connections every `interval` on a background
+    /// thread until the server stops. While below the server's outbound target,
+    /// a connected peer is asked for its peers before dialing.
+    pub fn watch(mut self, server: Arc<NetworkServer>, interval: Duration) -> thread::JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if !server.is_running() {
+                return;
+            }
+            let target = server.outbound_target();
+            let outbound = server.outbound_connections();
+            if outbound.len() >= target {
+                continue;
+            }
+
+            let peers = server.get_connected_peers();
+            if let Some(peer) = peers.get(rand::random::<usize>() % peers.len().max(1)) {
+                match server.request_peers(peer) {
+                    Ok(learned) => self.learn(learned.iter().filter_map(|peer| peer.listen_address().ok())),
+                    Err(e) => detail!("Failed to get peers from {}:{}: {}", peer.address, peer.port, e),
+                }
+            }
+
+            let candidates: Vec<PeerAddress> = server.rank_peers(self.candidates())
+                .into_iter()
+                .filter(|address| !server.is_banned(&address.host()))
+                .collect();
+            for address in self.select(&candidates, &outbound, &peers, target, Instant::now()) {
+                match server.connect_to_peer(&address) {
+                    Ok(()) => {
+                        status!("Dialer: connected to {} ({}/{} outbound)", address, server.outbound_connections().len(), target);
+                        self.dialed(address, true, Instant::now());
+                    },
+                    Err(e) => {
+                        detail!("Dialer: failed to connect to {}: {}", address, e);
+                        self.dialed(address, false, Instant::now());
+                    },
+                }
+            }
+        })
+    }
+}
+
+/// Whether a connected peer is reached at `address`, directly or at the port
+/// it listens on
+fn is_connected(address: &PeerAddress, peers: &[PeerInfo]) -> bool {
+    peers.iter().any(|peer| {
+        peer.peer_address().is_ok_and(|peer| peer == *address) || peer.listen_address().is_ok_and(|peer| peer == *address)
+    })
+}
+
+/// Subnet of an IP address, or the host itself when it is a hostname
+fn subnet(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => peer_subnet(ip),
+        Err(_) => host.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(address: &str, port: u16) -> PeerInfo {
+        PeerInfo {
+            address: address.to_string(),
+            port,
+            node_id: String::new(),
+            last_seen: 0,
+            chain_height: 0,
+            capabilities: Default::default(),
+            services: Default::default(),
+            listen_port: 0,
+        }
+    }
+
+    fn address(host: &str) -> PeerAddress {
+        PeerAddress::new(host, 8333).unwrap()
+    }
+
+    #[test]
+    fn test_selection_fills_target_across_subnets() {
+        let now = Instant::now();
+        let mut dialer = OutboundDialer::new(vec![address("10.1.0.1")]);
+        dialer.learn(vec![address("10.1.0.2"), address("10.1.0.3"), address("172.16.0.1"), address("10.1.0.1")]);
+        assert_eq!(dialer.candidates().len(), 4);
+
+        // At most two per /16, and never more than the target
+        let selected = dialer.select(&dialer.candidates(), &[], &[], 8, now);
+        assert_eq!(selected, vec![address("10.1.0.1"), address("10.1.0.2"), address("172.16.0.1")]);
+        assert_eq!(dialer.select(&dialer.candidates(), &[], &[], 1, now), vec![address("10.1.0.1")]);
+
+        // Inbound peers count against their subnet too
+        let inbound = vec![peer("10.1.9.9", 50_000)];
+        let selected = dialer.select(&dialer.candidates(), &[], &inbound, 8, now);
+        assert_eq!(selected, vec![address("10.1.0.1"), address("172.16.0.1")]);
+    }
+
+    #[test]
+    fn test_outbound_connections_and_retries() {
+        let now = Instant::now();
+        let candidates = vec![address("10.1.0.1"), address("172.16.0.1"), address("192.168.0.1")];
+        let mut dialer = OutboundDialer::new(candidates.clone());
+
+        dialer.dialed(address("10.1.0.1"), true, now);
+        dialer.dialed(address("172.16.0.1"), false, now);
+        let outbound = vec![address("10.1.0.1")];
+        let peers = vec![peer("10.1.0.1", 8333)];
+
+        // Connected and recently failed addresses are skipped until the delay passes
+        assert_eq!(dialer.select(&candidates, &outbound, &peers, 3, now), vec![address("192.168.0.1")]);
+        let later = now + DIAL_RETRY_DELAY;
+        assert_eq!(
+            dialer.select(&candidates, &outbound, &peers, 3, later),
+            vec![address("172.16.0.1"), address("192.168.0.1")]
+        );
+        assert!(dialer.select(&candidates, &outbound, &peers, 1, later).is_empty());
+
+        // A dropped connection frees its slot
+        assert_eq!(dialer.select(&candidates, &[], &[], 1, later), vec![address("10.1.0.1")]);
+    }
+
+    #[test]
+    fn test_inbound_peers_match_their_listen_port() {
+        let now = Instant::now();
+        let candidates = vec![address("10.1.0.1"), address("172.16.0.1")];
+        let mut dialer = OutboundDialer::new(candidates.clone());
+
+        // A peer that connected to us from an ephemeral port is not dialed again
+        // at the port it listens on
+        let inbound = PeerInfo { listen_port: 8333, ..peer("10.1.0.1", 50_000) };
+        assert_eq!(inbound.listen_address().unwrap(), address("10.1.0.1"));
+        assert_eq!(dialer.select(&candidates, &[], &[inbound], 8, now), vec![address("172.16.0.1")]);
+
+        // One that does not listen cannot be dialed
+        assert!(peer("10.1.0.1", 50_000).listen_address().is_err());
+    }
+}
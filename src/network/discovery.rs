@@ -2,6 +2,10 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Serialize, Deserialize};
+use crate::clock::{self, SharedClock};
+use crate::network::DEFAULT_MAX_PEERS;
+use crate::network::address::PeerAddress;
+use crate::network::protocol::{Services, LOCAL_SERVICES};
 
 /// Information about a peer in the network
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -11,6 +15,9 @@ pub struct PeerInfo {
     pub version: String,
     pub chain_height: u64,
     pub is_active: bool,
+    /// Services the peer advertises
+    #[serde(default)]
+    pub services: Services,
 }
 
 impl PeerInfo {
@@ -26,23 +33,23 @@ impl PeerInfo {
             version,
             chain_height,
             is_active: true,
+            services: Services::NONE,
         }
     }
 
-    pub fn update_last_seen(&mut self) {
-        self.last_seen = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    /// Set the services the peer advertises
+    pub fn with_services(mut self, services: Services) -> Self {
+        self.services = services;
+        self
     }
 
-    pub fn is_stale(&self, max_age_seconds: u64) -> bool {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
-        current_time.saturating_sub(self.last_seen) > max_age_seconds
+    pub fn update_last_seen(&mut self, now: u64) {
+        self.last_seen = now;
+    }
+
+    /// Whether the peer was last seen more than `max_age_seconds` before `now`
+    pub fn is_stale(&self, max_age_seconds: u64, now: u64) -> bool {
+        now.saturating_sub(self.last_seen) > max_age_seconds
     }
 }
 
@@ -65,7 +72,8 @@ pub struct PeerDiscovery {
     peers: HashMap<SocketAddr, PeerInfo>,
     
     /// Seed nodes for bootstrapping
-    seed_nodes: Vec<SocketAddr>,
+    /// Seeds may be hostnames, resolved when we connect to them
+    seed_nodes: Vec<PeerAddress>,
     
     /// Maximum number of peers to maintain
     max_peers: usize,
@@ -78,6 +86,12 @@ pub struct PeerDiscovery {
     
     /// Current chain height
     chain_height: u64,
+    
+    /// Services we announce
+    services: Services,
+    
+    /// Source of last-seen times and of the time staleness is measured against
+    clock: SharedClock,
 }
 
 impl PeerDiscovery {
@@ -87,15 +101,27 @@ impl PeerDiscovery {
             local_address,
             peers: HashMap::new(),
             seed_nodes: Vec::new(),
-            max_peers: 50,
+            max_peers: DEFAULT_MAX_PEERS,
             max_peer_age: 3600, // 1 hour
             version,
             chain_height: 0,
+            services: LOCAL_SERVICES,
+            clock: clock::system(),
         }
     }
 
+    /// Set the services we announce to other peers
+    pub fn set_services(&mut self, services: Services) {
+        self.services = services;
+    }
+
+    /// Time peers with `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
     /// Add seed nodes for bootstrapping
-    pub fn add_seed_nodes(&mut self, seeds: Vec<SocketAddr>) {
+    pub fn add_seed_nodes(&mut self, seeds: Vec<PeerAddress>) {
         self.seed_nodes.extend(seeds);
     }
 
@@ -121,6 +147,11 @@ impl PeerDiscovery {
         true
     }
 
+    /// Whether the peer is known, active or not
+    pub fn has_peer(&self, address: &SocketAddr) -> bool {
+        self.peers.contains_key(address)
+    }
+
     /// Remove a peer
     pub fn remove_peer(&mut self, address: &SocketAddr) -> bool {
         self.peers.remove(address).is_some()
@@ -130,7 +161,15 @@ impl PeerDiscovery {
     pub fn update_peer(&mut self, address: &SocketAddr, chain_height: u64) {
         if let Some(peer) = self.peers.get_mut(address) {
             peer.chain_height = chain_height;
-            peer.update_last_seen();
+            peer.update_last_seen(self.clock.now());
+            peer.is_active = true;
+        }
+    }
+
+    /// Record that we just heard from a peer without changing its chain height
+    pub fn touch_peer(&mut self, address: &SocketAddr) {
+        if let Some(peer) = self.peers.get_mut(address) {
+            peer.update_last_seen(self.clock.now());
             peer.is_active = true;
         }
     }
@@ -145,7 +184,7 @@ impl PeerDiscovery {
     /// Get all active peers
     pub fn get_active_peers(&self) -> Vec<PeerInfo> {
         self.peers.values()
-            .filter(|p| p.is_active && !p.is_stale(self.max_peer_age))
+            .filter(|p| p.is_active && !p.is_stale(self.max_peer_age, self.clock.now()))
             .cloned()
             .collect()
     }
@@ -160,22 +199,25 @@ impl PeerDiscovery {
         active_peers.into_iter().take(count).collect()
     }
 
-    /// Get the best peers (highest chain height)
-    pub fn get_best_peers(&self, count: usize) -> Vec<PeerInfo> {
-        let mut active_peers = self.get_active_peers();
+    /// Get the best peers (highest chain height) offering all of the `required` services
+    pub fn get_best_peers(&self, count: usize, required: Services) -> Vec<PeerInfo> {
+        let mut active_peers: Vec<PeerInfo> = self.get_active_peers().into_iter()
+            .filter(|p| p.services.contains(required))
+            .collect();
         active_peers.sort_by(|a, b| b.chain_height.cmp(&a.chain_height));
         active_peers.into_iter().take(count).collect()
     }
 
     /// Get seed nodes for bootstrapping
-    pub fn get_seed_nodes(&self) -> &[SocketAddr] {
+    pub fn get_seed_nodes(&self) -> &[PeerAddress] {
         &self.seed_nodes
     }
 
     /// Clean up stale peers
     pub fn cleanup_stale_peers(&mut self) -> usize {
+        let now = self.clock.now();
         let stale_addresses: Vec<SocketAddr> = self.peers.iter()
-            .filter(|(_, peer)| peer.is_stale(self.max_peer_age))
+            .filter(|(_, peer)| peer.is_stale(self.max_peer_age, now))
             .map(|(addr, _)| *addr)
             .collect();
         
@@ -224,7 +266,9 @@ impl PeerDiscovery {
                 DiscoveryMessage::PeerResponse { peers }
             },
             DiscoveryMessageType::Announcement => {
-                let our_info = PeerInfo::new(self.local_address, self.version.clone(), self.chain_height);
+                let mut our_info = PeerInfo::new(self.local_address, self.version.clone(), self.chain_height)
+                    .with_services(self.services);
+                our_info.update_last_seen(self.clock.now());
                 DiscoveryMessage::PeerAnnouncement { peer: our_info }
             },
             DiscoveryMessageType::Ping => DiscoveryMessage::Ping,
@@ -253,12 +297,12 @@ impl PeerDiscovery {
             },
             DiscoveryMessage::Ping => {
                 // Update peer info and respond with pong
-                self.update_peer(&from, 0); // Height unknown from ping
+                self.touch_peer(&from);
                 Some(DiscoveryMessage::Pong)
             },
             DiscoveryMessage::Pong => {
                 // Update peer as active
-                self.update_peer(&from, 0); // Height unknown from pong
+                self.touch_peer(&from);
                 None
             },
         }
@@ -367,12 +411,32 @@ mod tests {
         discovery.add_peer(create_test_peer(8335, 200));
         discovery.add_peer(create_test_peer(8336, 150));
         
-        let best_peers = discovery.get_best_peers(2);
+        let best_peers = discovery.get_best_peers(2, Services::NONE);
         assert_eq!(best_peers.len(), 2);
         assert_eq!(best_peers[0].chain_height, 200);
         assert_eq!(best_peers[1].chain_height, 150);
     }
 
+    #[test]
+    fn test_get_best_peers_with_services() {
+        let mut discovery = PeerDiscovery::new(
+            "127.0.0.1:8333".parse().unwrap(),
+            "test-v1.0".to_string()
+        );
+        
+        discovery.add_peer(create_test_peer(8334, 300).with_services(Services::FULL_BLOCKS));
+        discovery.add_peer(create_test_peer(8335, 200).with_services(LOCAL_SERVICES));
+        discovery.add_peer(create_test_peer(8336, 100).with_services(Services::HEADERS.union(Services::ARCHIVE)));
+        
+        let archive_peers = discovery.get_best_peers(5, Services::ARCHIVE);
+        assert_eq!(archive_peers.len(), 2);
+        assert_eq!(archive_peers[0].chain_height, 200);
+        
+        let full_archive = discovery.get_best_peers(5, Services::ARCHIVE.union(Services::FULL_BLOCKS));
+        assert_eq!(full_archive.len(), 1);
+        assert_eq!(full_archive[0].address.port(), 8335);
+    }
+
     #[test]
     fn test_cleanup_stale_peers() {
         let mut discovery = PeerDiscovery::new(
@@ -391,6 +455,26 @@ mod tests {
         assert_eq!(discovery.peer_count(), 0);
     }
 
+    #[test]
+    fn test_staleness_follows_clock() {
+        let clock = crate::clock::MockClock::new(10_000);
+        let mut discovery = PeerDiscovery::default();
+        discovery.set_clock(std::sync::Arc::new(clock.clone()));
+        
+        let mut peer = create_test_peer(8334, 100);
+        peer.last_seen = 10_000;
+        let address = peer.address;
+        discovery.add_peer(peer);
+        
+        clock.advance(discovery.max_peer_age);
+        assert_eq!(discovery.active_peer_count(), 1);
+        clock.advance(1);
+        assert_eq!(discovery.active_peer_count(), 0);
+        
+        discovery.touch_peer(&address);
+        assert_eq!(discovery.get_active_peers()[0].last_seen, 13_601);
+    }
+
     #[test]
     fn test_discovery_messages() {
         let mut discovery = PeerDiscovery::new(
@@ -0,0 +1,338 @@
+//! Parallel block download while syncing
+//!
+//! The heights we are missing are split into windows of consecutive blocks.
+//! Every peer ahead of us asks for the lowest window nobody has claimed that
+//! lies within its chain, so different peers fetch different windows at once.
+//! A request that is not answered within the timeout, or is answered wrongly,
+//! puts its window back for another peer; a peer that fails too often is
+//! dropped from the download. Finished windows wait in a buffer until every
+//! window below them is in, and leave it in height order for validation.
+//! Peers are never given windows too far past the next height to validate,
+//! which bounds the buffer.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::blockchain::block::Block;
+
+/// Default number of blocks requested from a peer at a time
+pub const DEFAULT_WINDOW_SIZE: u32 = 100;
+
+/// Default time a peer has to answer a window request
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default number of windows past the next height to validate that may be requested
+pub const DEFAULT_MAX_WINDOWS_AHEAD: u64 = 16;
+
+/// Failed or timed out requests after which a peer is dropped from the download
+pub const MAX_PEER_FAILURES: u32 = 3;
+
+/// Window sizes and limits of a download
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadConfig {
+    pub window_size: u32,
+    pub request_timeout: Duration,
+    pub max_windows_ahead: u64,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig {
+            window_size: DEFAULT_WINDOW_SIZE,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_windows_ahead: DEFAULT_MAX_WINDOWS_AHEAD,
+        }
+    }
+}
+
+/// Blocks to ask one peer for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowRequest {
+    pub start_height: u64,
+    pub count: u32,
+}
+
+/// A window waiting for a peer's answer
+#[derive(Debug, Clone)]
+struct InFlight {
+    peer: String,
+    count: u32,
+    deadline: Instant,
+}
+
+/// A peer taking part in the download
+#[derive(Debug, Clone)]
+struct DownloadPeer {
+    best_height: u64,
+    failures: u32,
+}
+
+/// Splits a height range into windows and tracks which peer is fetching each
+#[derive(Debug)]
+pub struct DownloadScheduler {
+    config: DownloadConfig,
+    /// Next height to hand to validation
+    next_height: u64,
+    /// Last height to download
+    end_height: u64,
+    /// Windows no peer is fetching, by start height, with their length
+    pending: BTreeMap<u64, u32>,
+    in_flight: HashMap<u64, InFlight>,
+    /// Downloaded windows not yet handed to validation, by start height, with
+    /// the peer that sent each
+    completed: BTreeMap<u64, (String, Vec<Block>)>,
+    peers: HashMap<String, DownloadPeer>,
+}
+
+impl DownloadScheduler {
+    /// Download the blocks from `start_height` to `end_height`, inclusive
+    pub fn new(start_height: u64, end_height: u64, config: DownloadConfig) -> Self {
+        let window_size = config.window_size.max(1);
+        let mut pending = BTreeMap::new();
+        let mut start = start_height;
+        while start <= end_height {
+            let count = (end_height - start + 1).min(window_size as u64) as u32;
+            pending.insert(start, count);
+            start += count as u64;
+        }
+        DownloadScheduler {
+            config,
+            next_height: start_height,
+            end_height,
+            pending,
+            in_flight: HashMap::new(),
+            completed: BTreeMap::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Let `peer`, whose chain reaches `best_height`, fetch windows
+    pub fn add_peer(&mut self, peer: &str, best_height: u64) {
+        self.peers.insert(peer.to_string(), DownloadPeer { best_height, failures: 0 });
+    }
+
+    /// Whether `peer` is still taking part
+    pub fn has_peer(&self, peer: &str) -> bool {
+        self.peers.contains_key(peer)
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Claim the lowest unclaimed window `peer` has every block of, due by
+    /// `now` plus the request timeout. None when nothing is left for the peer
+    /// right now.
+    pub fn next_request(&mut self, peer: &str, now: Instant) -> Option<WindowRequest> {
+        let best_height = self.peers.get(peer)?.best_height;
+        let horizon = self.next_height
+            .saturating_add(self.config.max_windows_ahead.saturating_mul(self.config.window_size as u64));
+        let (&start_height, &count) = self.pending.iter()
+            .take_while(|(start, _)| **start < horizon)
+            .find(|(start, count)| **start + **count as u64 - 1 <= best_height)?;
+
+        self.pending.remove(&start_height);
+        self.in_flight.insert(start_height, InFlight {
+            peer: peer.to_string(),
+            count,
+            deadline: now + self.config.request_timeout,
+        });
+        Some(WindowRequest { start_height, count })
+    }
+
+    /// Record `peer`'s answer to its request for the window at `start_height`.
+    /// The blocks must run in height order from the start of the window. A
+    /// short answer leaves the rest of the window for another request; an empty
+    /// or malformed one counts against the peer. Answers to windows that timed
+    /// out and were handed to someone else are dropped.
+    pub fn complete(&mut self, peer: &str, start_height: u64, blocks: Vec<Block>) -> Result<(), String> {
+        let request = match self.in_flight.get(&start_height) {
+            Some(request) if request.peer == peer => self.in_flight.remove(&start_height).unwrap(),
+            _ => return Err(format!("No request for height {} is waiting on {}", start_height, peer)),
+        };
+
+        let in_order = blocks.iter().enumerate()
+            .all(|(i, block)| block.header.height == start_height + i as u64);
+        if blocks.is_empty() || blocks.len() > request.count as usize || !in_order {
+            self.pending.insert(start_height, request.count);
+            self.record_failure(peer);
+            return Err(format!("{} sent no usable blocks from height {}", peer, start_height));
+        }
+
+        let received = blocks.len() as u32;
+        if received < request.count {
+            self.pending.insert(start_height + received as u64, request.count - received);
+        }
+        self.completed.insert(start_height, (peer.to_string(), blocks));
+        Ok(())
+    }
+
+    /// Give the window at `start_height` back after `peer` could not be asked
+    /// or did not answer
+    pub fn fail(&mut self, peer: &str, start_height: u64) {
+        if self.in_flight.get(&start_height).is_some_and(|request| request.peer == peer) {
+            let request = self.in_flight.remove(&start_height).unwrap();
+            self.pending.insert(start_height, request.count);
+            self.record_failure(peer);
+        }
+    }
+
+    /// Give back every window whose deadline passed before `now`, returning
+    /// the peers that missed them
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let expired: Vec<(u64, String)> = self.in_flight.iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(start, request)| (*start, request.peer.clone()))
+            .collect();
+        for (start, peer) in &expired {
+            self.fail(peer, *start);
+        }
+        expired.into_iter().map(|(_, peer)| peer).collect()
+    }
+
+    /// Drop `peer` from the download, giving back the windows it was fetching
+    pub fn remove_peer(&mut self, peer: &str) {
+        self.peers.remove(peer);
+        let abandoned: Vec<u64> = self.in_flight.iter()
+            .filter(|(_, request)| request.peer == peer)
+            .map(|(start, _)| *start)
+            .collect();
+        for start in abandoned {
+            let request = self.in_flight.remove(&start).unwrap();
+            self.pending.insert(start, request.count);
+        }
+    }
+
+    fn record_failure(&mut self, peer: &str) {
+        let failures = match self.peers.get_mut(peer) {
+            Some(state) => {
+                state.failures += 1;
+                state.failures
+            },
+            None => return,
+        };
+        if failures >= MAX_PEER_FAILURES {
+            self.remove_peer(peer);
+        }
+    }
+
+    /// Downloaded windows that continue from the last ones taken, in height
+    /// order, with the peer that sent each
+    pub fn take_ready(&mut self) -> Vec<(String, Vec<Block>)> {
+        let mut ready = Vec::new();
+        while let Some((peer, blocks)) = self.completed.remove(&self.next_height) {
+            self.next_height += blocks.len() as u64;
+            ready.push((peer, blocks));
+        }
+        ready
+    }
+
+    /// Next height validation needs
+    pub fn next_height(&self) -> u64 {
+        self.next_height
+    }
+
+    /// Whether every window was handed to validation
+    pub fn is_complete(&self) -> bool {
+        self.next_height > self.end_height
+    }
+
+    /// Whether the download can go no further: complete, or with windows left
+    /// that no remaining peer has the blocks for
+    pub fn is_finished(&self) -> bool {
+        if self.is_complete() {
+            return true;
+        }
+        if !self.in_flight.is_empty() {
+            return false;
+        }
+        !self.pending.iter().any(|(start, count)| {
+            self.peers.values().any(|peer| start + *count as u64 - 1 <= peer.best_height)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(start_height: u64, count: u64) -> Vec<Block> {
+        (start_height..start_height + count)
+            .map(|height| Block::new(format!("parent_{}", height), vec![], 0, 1_700_000_000 + height, height))
+            .collect()
+    }
+
+    fn config(window_size: u32, max_windows_ahead: u64) -> DownloadConfig {
+        DownloadConfig { window_size, request_timeout: Duration::from_secs(5), max_windows_ahead }
+    }
+
+    #[test]
+    fn test_windows_are_spread_and_delivered_in_order() {
+        let now = Instant::now();
+        let mut scheduler = DownloadScheduler::new(10, 34, config(10, 4));
+        scheduler.add_peer("a", 34);
+        scheduler.add_peer("b", 29);
+
+        assert_eq!(scheduler.next_request("a", now), Some(WindowRequest { start_height: 10, count: 10 }));
+        assert_eq!(scheduler.next_request("b", now), Some(WindowRequest { start_height: 20, count: 10 }));
+        // The last window reaches past b's chain, so only a can fetch it
+        assert_eq!(scheduler.next_request("b", now), None);
+        assert_eq!(scheduler.next_request("a", now), Some(WindowRequest { start_height: 30, count: 5 }));
+
+        // Later windows wait for the ones below them
+        scheduler.complete("b", 20, blocks(20, 10)).unwrap();
+        assert!(scheduler.take_ready().is_empty());
+        scheduler.complete("a", 10, blocks(10, 10)).unwrap();
+        let ready = scheduler.take_ready();
+        let peers: Vec<&str> = ready.iter().map(|(peer, _)| peer.as_str()).collect();
+        assert_eq!(peers, ["a", "b"]);
+        let heights: Vec<u64> = ready.iter().flat_map(|(_, blocks)| blocks).map(|block| block.header.height).collect();
+        assert_eq!(heights, (10..30).collect::<Vec<u64>>());
+        assert!(!scheduler.is_complete());
+
+        // A short answer leaves the rest for another request
+        scheduler.complete("a", 30, blocks(30, 3)).unwrap();
+        assert_eq!(scheduler.next_request("a", now), Some(WindowRequest { start_height: 33, count: 2 }));
+        scheduler.complete("a", 33, blocks(33, 2)).unwrap();
+        assert_eq!(scheduler.take_ready().len(), 2);
+        assert!(scheduler.is_complete() && scheduler.is_finished());
+    }
+
+    #[test]
+    fn test_timeouts_reassign_windows() {
+        let now = Instant::now();
+        let mut scheduler = DownloadScheduler::new(0, 19, config(10, 1));
+        scheduler.add_peer("slow", 19);
+        scheduler.add_peer("fast", 19);
+
+        assert_eq!(scheduler.next_request("slow", now).unwrap().start_height, 0);
+        // Nothing past the first window is handed out until it is validated
+        assert_eq!(scheduler.next_request("fast", now), None);
+        assert!(scheduler.expire(now + Duration::from_secs(1)).is_empty());
+        assert_eq!(scheduler.expire(now + Duration::from_secs(5)), vec!["slow".to_string()]);
+
+        let retry = scheduler.next_request("fast", now).unwrap();
+        assert_eq!(retry.start_height, 0);
+        // The slow peer's late answer is no longer wanted
+        assert!(scheduler.complete("slow", 0, blocks(0, 10)).is_err());
+        scheduler.complete("fast", 0, blocks(0, 10)).unwrap();
+        assert_eq!(scheduler.take_ready()[0].1.len(), 10);
+        assert_eq!(scheduler.next_request("fast", now).unwrap().start_height, 10);
+    }
+
+    #[test]
+    fn test_failing_peers_are_dropped() {
+        let now = Instant::now();
+        let mut scheduler = DownloadScheduler::new(0, 9, config(10, 4));
+        scheduler.add_peer("bad", 9);
+        for _ in 0..MAX_PEER_FAILURES {
+            let request = scheduler.next_request("bad", now).unwrap();
+            // Blocks out of order are refused
+            assert!(scheduler.complete("bad", request.start_height, blocks(1, 10)).is_err());
+        }
+        assert!(!scheduler.has_peer("bad"));
+        assert_eq!(scheduler.next_request("bad", now), None);
+        // No one is left with the blocks
+        assert!(scheduler.is_finished() && !scheduler.is_complete());
+    }
+}
@@ -0,0 +1,345 @@
+//! Length-prefixed message framing with read deadlines
+//!
+//! Messages travel as a 4-byte big-endian length followed by the serialized
+//! message. A `FrameReader` keeps the bytes of an unfinished message between
+//! reads, so a socket read timeout in the middle of a message no longer loses
+//! data, and bounds what a peer can make us hold: a length above the buffer
+//! limit is refused before anything is allocated, the body is buffered as it
+//! arrives rather than up front, and a peer whose message is not complete
+//! within the deadline of its first byte is reported as slow.
+//!
+//! With peers that negotiated `Features::COMPRESSION`, messages over
+//! `COMPRESSION_THRESHOLD` are sent deflated: the body is a
+//! `COMPRESSED_FRAME_MARKER` byte, which never starts a serialized message,
+//! followed by the deflate stream. The reader inflates such frames whatever was
+//! negotiated, refusing any that inflate past `MAX_MESSAGE_SIZE`.
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use crate::network::protocol::{NetworkError, NetworkMessage, MAX_MESSAGE_SIZE};
+
+/// Size of the big-endian length in front of every message
+pub const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Default time a message may take to arrive once its first byte was read
+pub const DEFAULT_MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most bytes read from the socket at once
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Serialized messages larger than this are compressed for peers that support it
+pub const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+/// First body byte of a compressed frame
+pub const COMPRESSED_FRAME_MARKER: u8 = 0;
+
+/// Limits on how peers deliver messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramingConfig {
+    /// Time a message may take to arrive once its first byte was read
+    pub message_timeout: Duration,
+    /// Most bytes of one unfinished message held for a peer. Longer messages are
+    /// refused as soon as their length arrives.
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for FramingConfig {
+    fn default() -> Self {
+        FramingConfig {
+            message_timeout: DEFAULT_MESSAGE_TIMEOUT,
+            max_buffered_bytes: MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+/// Reads whole messages from one peer across partial reads and timeouts
+#[derive(Debug)]
+pub struct FrameReader {
+    config: FramingConfig,
+    /// Bytes of the message being read, length prefix included
+    buffer: Vec<u8>,
+    /// When the first byte of the message being read arrived
+    started: Option<Instant>,
+    /// Size of the last whole message read, length prefix included
+    last_frame_size: usize,
+    /// Size the last message read would have had uncompressed
+    last_raw_size: usize,
+}
+
+impl FrameReader {
+    pub fn new(config: FramingConfig) -> Self {
+        FrameReader {
+            config,
+            buffer: Vec::new(),
+            started: None,
+            last_frame_size: 0,
+            last_raw_size: 0,
+        }
+    }
+
+    pub fn config(&self) -> FramingConfig {
+        self.config
+    }
+
+    /// Bytes on the wire of the last whole message read, length prefix included
+    pub fn last_frame_size(&self) -> usize {
+        self.last_frame_size
+    }
+
+    /// Bytes the last whole message read would have taken uncompressed, length
+    /// prefix included; the same as `last_frame_size` unless it came compressed
+    pub fn last_raw_size(&self) -> usize {
+        self.last_raw_size
+    }
+
+    /// Bytes of an unfinished message held for the peer
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// How long the next read may block: `idle` between messages, otherwise no
+    /// longer than the time left before the message in progress is overdue.
+    /// Never zero, which sockets do not accept as a timeout.
+    pub fn read_timeout(&self, idle: Duration) -> Duration {
+        match self.started {
+            Some(started) => self.config.message_timeout
+                .saturating_sub(started.elapsed())
+                .min(idle)
+                .max(Duration::from_millis(1)),
+            None => idle,
+        }
+    }
+
+    /// Read until a whole message has arrived. A read timeout keeps the bytes
+    /// received so far and returns `NetworkError::Timeout`; the next call carries
+    /// on where this one stopped. Returns `NetworkError::SlowPeer` once the
+    /// message in progress is overdue.
+    pub fn read_message<R: Read>(&mut self, reader: &mut R) -> Result<NetworkMessage, NetworkError> {
+        loop {
+            let missing = self.missing_bytes()?;
+            if missing == 0 {
+                let body = decompress_body(&self.buffer[LENGTH_PREFIX_SIZE..]);
+                self.last_raw_size = LENGTH_PREFIX_SIZE + body.as_ref().map_or(0, |body| body.len());
+                let message = body.map_err(NetworkError::InvalidMessage)
+                    .and_then(|body| NetworkMessage::from_bytes(&body).map_err(NetworkError::InvalidMessage));
+                self.last_frame_size = self.buffer.len();
+                self.buffer = Vec::new();
+                self.started = None;
+                return message;
+            }
+            self.check_deadline()?;
+
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + missing.min(READ_CHUNK_SIZE), 0);
+            let read = reader.read(&mut self.buffer[filled..]);
+            self.buffer.truncate(filled + read.as_ref().map_or(0, |count| *count));
+            match read {
+                Ok(0) => return Err(NetworkError::PeerDisconnected),
+                Ok(_) => {
+                    self.started.get_or_insert_with(Instant::now);
+                },
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                // Unix reports read timeouts as WouldBlock
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    self.check_deadline()?;
+                    return Err(NetworkError::Timeout);
+                },
+                Err(e) => return Err(NetworkError::ConnectionFailed(format!("Failed to read message: {}", e))),
+            }
+        }
+    }
+
+    /// Bytes still needed to complete the length prefix or, once it is known,
+    /// the message
+    fn missing_bytes(&self) -> Result<usize, NetworkError> {
+        if self.buffer.len() < LENGTH_PREFIX_SIZE {
+            return Ok(LENGTH_PREFIX_SIZE - self.buffer.len());
+        }
+        let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+        prefix.copy_from_slice(&self.buffer[..LENGTH_PREFIX_SIZE]);
+        let length = u32::from_be_bytes(prefix) as usize;
+        if length > self.config.max_buffered_bytes {
+            return Err(NetworkError::InvalidMessage(format!(
+                "Message of {} bytes exceeds the limit of {}", length, self.config.max_buffered_bytes
+            )));
+        }
+        Ok(LENGTH_PREFIX_SIZE + length - self.buffer.len())
+    }
+
+    fn check_deadline(&self) -> Result<(), NetworkError> {
+        match self.started {
+            Some(started) if started.elapsed() > self.config.message_timeout => Err(NetworkError::SlowPeer(format!(
+                "{} bytes of a message received in {:?}", self.buffer.len(), self.config.message_timeout
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Serialize a message with its length prefix
+pub fn encode_frame(message: &NetworkMessage) -> Result<Vec<u8>, NetworkError> {
+    let data = message.to_bytes().map_err(NetworkError::ProtocolError)?;
+    let mut frame = Vec::with_capacity(LENGTH_PREFIX_SIZE + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&data);
+    Ok(frame)
+}
+
+/// Compress a frame from `encode_frame` when its message is over
+/// `COMPRESSION_THRESHOLD` and deflating makes it smaller; otherwise it is
+/// returned as it was
+pub fn compress_frame(frame: Vec<u8>) -> Result<Vec<u8>, NetworkError> {
+    let body = &frame[LENGTH_PREFIX_SIZE..];
+    if body.len() <= COMPRESSION_THRESHOLD {
+        return Ok(frame);
+    }
+    let mut encoder = DeflateEncoder::new(vec![COMPRESSED_FRAME_MARKER], Compression::fast());
+    let compressed = encoder.write_all(body)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| NetworkError::ProtocolError(format!("Failed to compress message: {}", e)))?;
+    if compressed.len() >= body.len() {
+        return Ok(frame);
+    }
+    let mut compressed_frame = Vec::with_capacity(LENGTH_PREFIX_SIZE + compressed.len());
+    compressed_frame.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+    compressed_frame.extend_from_slice(&compressed);
+    Ok(compressed_frame)
+}
+
+/// The serialized message in a frame body, inflated if it came compressed
+fn decompress_body(body: &[u8]) -> Result<std::borrow::Cow<'_, [u8]>, String> {
+    let Some((&COMPRESSED_FRAME_MARKER, compressed)) = body.split_first() else {
+        return Ok(body.into());
+    };
+    let mut inflated = Vec::new();
+    DeflateDecoder::new(compressed)
+        .take(MAX_MESSAGE_SIZE as u64 + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|e| format!("Failed to decompress message: {}", e))?;
+    if inflated.len() > MAX_MESSAGE_SIZE {
+        return Err(format!("Compressed message inflates past the limit of {} bytes", MAX_MESSAGE_SIZE));
+    }
+    Ok(inflated.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::MessageType;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::thread;
+
+    /// Hands out a few bytes per read and times out in between, as a trickling peer does
+    struct Trickle {
+        data: VecDeque<u8>,
+        chunk_size: usize,
+        stalled: bool,
+    }
+
+    impl Trickle {
+        fn new(data: &[u8], chunk_size: usize) -> Self {
+            Trickle { data: data.iter().copied().collect(), chunk_size, stalled: false }
+        }
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.stalled || self.data.is_empty() {
+                self.stalled = false;
+                return Err(io::Error::new(ErrorKind::WouldBlock, "no data yet"));
+            }
+            let count = self.chunk_size.min(buf.len()).min(self.data.len());
+            for (byte, value) in buf.iter_mut().zip(self.data.drain(..count)) {
+                *byte = value;
+            }
+            self.stalled = true;
+            Ok(count)
+        }
+    }
+
+    fn ping_frame() -> Vec<u8> {
+        encode_frame(&NetworkMessage::new(MessageType::Ping)).unwrap()
+    }
+
+    #[test]
+    fn test_message_survives_partial_reads() {
+        let frame = ping_frame();
+        let mut reader = FrameReader::new(FramingConfig::default());
+        let mut stream = io::Cursor::new(frame.clone());
+        assert!(matches!(reader.read_message(&mut stream).unwrap().message_type, MessageType::Ping));
+        assert_eq!(reader.last_frame_size(), frame.len());
+
+        // One byte per read with a timeout after each: nothing is lost in between
+        let mut stream = Trickle::new(&frame, 1);
+        let mut timeouts = 0;
+        let message = loop {
+            match reader.read_message(&mut stream) {
+                Ok(message) => break message,
+                Err(NetworkError::Timeout) => timeouts += 1,
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        };
+        assert!(matches!(message.message_type, MessageType::Ping));
+        assert!(timeouts > 0);
+        assert_eq!(reader.buffered(), 0);
+    }
+
+    #[test]
+    fn test_oversized_frame_is_refused_before_buffering() {
+        let config = FramingConfig { max_buffered_bytes: 16, ..FramingConfig::default() };
+        let mut reader = FrameReader::new(config);
+        let mut stream = io::Cursor::new(ping_frame());
+        assert!(matches!(reader.read_message(&mut stream), Err(NetworkError::InvalidMessage(_))));
+        assert_eq!(reader.buffered(), LENGTH_PREFIX_SIZE);
+
+        // A length prefix claiming more than the protocol allows is refused too
+        let mut reader = FrameReader::new(FramingConfig::default());
+        let mut stream = io::Cursor::new(u32::MAX.to_be_bytes().to_vec());
+        assert!(matches!(reader.read_message(&mut stream), Err(NetworkError::InvalidMessage(_))));
+    }
+
+    #[test]
+    fn test_trickling_peer_is_reported_slow() {
+        let config = FramingConfig { message_timeout: Duration::from_millis(50), ..FramingConfig::default() };
+        let mut reader = FrameReader::new(config);
+        let mut stream = Trickle::new(&ping_frame(), 1);
+
+        assert!(matches!(reader.read_message(&mut stream), Err(NetworkError::Timeout)));
+        assert!(reader.read_timeout(Duration::from_secs(30)) <= Duration::from_millis(50));
+        thread::sleep(Duration::from_millis(60));
+        assert!(matches!(reader.read_message(&mut stream), Err(NetworkError::SlowPeer(_))));
+    }
+
+    #[test]
+    fn test_large_messages_travel_compressed() {
+        let ping = ping_frame();
+        assert_eq!(compress_frame(ping.clone()).unwrap(), ping);
+
+        let transactions: Vec<String> = (0..2_000).map(|i| format!("{:064x}", i)).collect();
+        let message = NetworkMessage::new(MessageType::MempoolResponse { count: transactions.len(), transactions, entries: vec![] });
+        let frame = encode_frame(&message).unwrap();
+        let compressed = compress_frame(frame.clone()).unwrap();
+        assert!(compressed.len() < frame.len() / 2);
+        assert_eq!(compressed[LENGTH_PREFIX_SIZE], COMPRESSED_FRAME_MARKER);
+
+        let mut reader = FrameReader::new(FramingConfig::default());
+        let message = reader.read_message(&mut io::Cursor::new(compressed.clone())).unwrap();
+        assert!(matches!(message.message_type, MessageType::MempoolResponse { count: 2_000, .. }));
+        assert_eq!(reader.last_frame_size(), compressed.len());
+        assert_eq!(reader.last_raw_size(), frame.len());
+    }
+
+    #[test]
+    fn test_disconnect_mid_message() {
+        let frame = ping_frame();
+        let mut reader = FrameReader::new(FramingConfig::default());
+        let mut stream = io::Cursor::new(frame[..frame.len() / 2].to_vec());
+        assert!(matches!(reader.read_message(&mut stream), Err(NetworkError::PeerDisconnected)));
+    }
+}
@@ -0,0 +1,171 @@
+//! Peer chain height polling
+//!
+//! A peer's chain height is learned from its handshake and goes stale as soon as
+//! the peer mines or receives a block. The poller asks every connected peer for
+//! its `ChainInfo` once per interval, moved earlier or later by a random jitter
+//! so the requests to different peers do not line up, and records the height and
+//! the time of the answer in the peer list. When a poll finds a peer more than
+//! the sync threshold of blocks ahead of us, backed by the headers up to its
+//! tip, the node syncs without being told to. Heights peers merely claim, as in
+//! their handshake, never start a sync.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::network::protocol::PeerInfo;
+use crate::network::server::NetworkServer;
+use crate::{detail, status};
+
+/// Default time between two polls of the same peer
+pub const DEFAULT_HEIGHT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Largest fraction of the interval a poll is moved earlier or later by
+pub const HEIGHT_POLL_JITTER: f64 = 0.2;
+
+/// Default number of blocks a peer may be ahead of us before we sync
+pub const DEFAULT_SYNC_THRESHOLD: u64 = 1;
+
+/// How often the polling thread checks for peers that are due
+const HEIGHT_POLL_TICK: Duration = Duration::from_secs(1);
+
+/// Schedules `GetChainInfo` requests to the connected peers
+pub struct HeightPoller {
+    interval: Duration,
+    /// When each peer, by node id, is asked for its chain info next
+    next_poll: HashMap<String, Instant>,
+}
+
+impl HeightPoller {
+    pub fn new(interval: Duration) -> Self {
+        HeightPoller {
+            interval,
+            next_poll: HashMap::new(),
+        }
+    }
+
+    /// The peers due a poll at `now`, scheduling the next poll of each. A new
+    /// peer's handshake height is fresh, so it is first polled one jittered
+    /// interval after it shows up; peers no longer connected are forgotten.
+    pub fn due(&mut self, peers: &[PeerInfo], now: Instant) -> Vec<PeerInfo> {
+        self.next_poll.retain(|node_id, _| peers.iter().any(|peer| peer.node_id == *node_id));
+
+        let mut due = Vec::new();
+        for peer in peers {
+            let next = jittered(self.interval, rand::random::<f64>());
+            match self.next_poll.get_mut(&peer.node_id) {
+                Some(at) if *at <= now => {
+                    *at = now + next;
+                    due.push(peer.clone());
+                },
+                Some(_) => {},
+                None => {
+                    self.next_poll.insert(peer.node_id.clone(), now + next);
+                },
+            }
+        }
+        due
+    }
+
+    /// Poll the server's peers on a background thread until the server stops,
+    /// syncing whenever a poll finds a peer past the server's sync threshold
+    pub fn watch(mut self, server: Arc<NetworkServer>) -> thread::JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(HEIGHT_POLL_TICK);
+            if !server.is_running() {
+                return;
+            }
+            let due = self.due(&server.get_connected_peers(), Instant::now());
+            if due.is_empty() {
+                continue;
+            }
+            let mut polled = Vec::new();
+            for peer in &due {
+                match server.poll_chain_info(peer) {
+                    Ok(height) => {
+                        detail!("Peer {}:{} is at height {}", peer.address, peer.port, height);
+                        polled.push((peer, height));
+                    },
+                    Err(e) => detail!("Failed to poll chain info from {}:{}: {}", peer.address, peer.port, e),
+                }
+            }
+
+            let our_height = server.chain_height();
+            let threshold = server.sync_threshold();
+            let ahead = polled.into_iter()
+                .filter(|(_, height)| should_sync(our_height, *height, threshold))
+                .max_by_key(|(_, height)| *height);
+            if let Some((peer, height)) = ahead {
+                status!("Peer {}:{} is {} blocks ahead, syncing",
+                    peer.address, peer.port, height - our_height);
+                if let Err(e) = server.sync_blockchain() {
+                    eprintln!("Automatic sync failed: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// `interval` moved by up to `HEIGHT_POLL_JITTER` of itself either way;
+/// `unit`, in `[0, 1)`, picks where
+pub fn jittered(interval: Duration, unit: f64) -> Duration {
+    interval.mul_f64(1.0 - HEIGHT_POLL_JITTER + 2.0 * HEIGHT_POLL_JITTER * unit.clamp(0.0, 1.0))
+}
+
+/// Whether a peer at `peer_height` is more than `threshold` blocks ahead of us
+pub fn should_sync(our_height: u64, peer_height: u64, threshold: u64) -> bool {
+    peer_height.saturating_sub(our_height) > threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(node_id: &str) -> PeerInfo {
+        PeerInfo {
+            address: "127.0.0.1".to_string(),
+            port: 8333,
+            node_id: node_id.to_string(),
+            last_seen: 0,
+            chain_height: 0,
+            capabilities: Default::default(),
+            services: Default::default(),
+            listen_port: 0,
+        }
+    }
+
+    #[test]
+    fn test_peers_polled_once_per_interval() {
+        let interval = Duration::from_secs(60);
+        let mut poller = HeightPoller::new(interval);
+        let now = Instant::now();
+        let peers = vec![peer("a"), peer("b")];
+
+        // Fresh from the handshake: nothing due until an interval has passed
+        assert!(poller.due(&peers, now).is_empty());
+        assert!(poller.due(&peers, now + interval.mul_f64(1.0 - HEIGHT_POLL_JITTER) - Duration::from_secs(1)).is_empty());
+        let later = now + interval.mul_f64(1.0 + HEIGHT_POLL_JITTER);
+        assert_eq!(poller.due(&peers, later).len(), 2);
+        assert!(poller.due(&peers, later).is_empty());
+
+        // A disconnected peer is dropped, and starts over if it comes back
+        assert!(poller.due(&peers[..1], later).is_empty());
+        let due: Vec<String> = poller.due(&peers, later + interval * 2).into_iter().map(|peer| peer.node_id).collect();
+        assert_eq!(due, vec!["a"]);
+    }
+
+    #[test]
+    fn test_jitter_and_sync_threshold() {
+        let interval = Duration::from_secs(100);
+        assert_eq!(jittered(interval, 0.0), Duration::from_secs(80));
+        assert_eq!(jittered(interval, 0.5), Duration::from_secs(100));
+        assert!(jittered(interval, 0.999) < Duration::from_secs(120));
+
+        assert!(!should_sync(10, 10, 1));
+        assert!(!should_sync(10, 11, 1));
+        assert!(should_sync(10, 12, 1));
+        assert!(should_sync(10, 11, 0));
+        assert!(!should_sync(10, 5, 0));
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::keys::generate_keypair;
+use crate::crypto::signature::verify_signature;
+
+/// File name of the node identity key inside the data directory
+pub const NODE_KEY_FILE: &str = "node_key";
+
+/// Persistent ed25519 keypair identifying this node to its peers
+#[derive(Debug, Clone)]
+pub struct NodeIdentity {
+    signing_key: SigningKey,
+}
+
+impl NodeIdentity {
+    /// Generate a new random identity
+    pub fn generate() -> Self {
+        NodeIdentity {
+            signing_key: generate_keypair(),
+        }
+    }
+
+    /// The identity with the given secret key, as stored in the node key file
+    pub fn from_secret_key(key_bytes: [u8; 32]) -> Self {
+        NodeIdentity {
+            signing_key: SigningKey::from_bytes(&key_bytes),
+        }
+    }
+
+    /// Load the identity stored in `data_dir`, creating and saving a new one if missing
+    pub fn load_or_create<P: AsRef<Path>>(data_dir: P) -> Result<Self, String> {
+        let key_path = data_dir.as_ref().join(NODE_KEY_FILE);
+
+        if key_path.exists() {
+            let key_hex = fs::read_to_string(&key_path)
+                .map_err(|e| format!("Failed to read node key: {}", e))?;
+            let key_bytes: [u8; 32] = hex::decode(key_hex.trim())
+                .map_err(|e| format!("Invalid hex in node key: {}", e))?
+                .try_into()
+                .map_err(|_| "Node key must be exactly 32 bytes")?;
+            // Keys written by older versions were readable by everyone
+            #[cfg(unix)]
+            fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Failed to restrict node key permissions: {}", e))?;
+
+            return Ok(Self::from_secret_key(key_bytes));
+        }
+
+        let identity = Self::generate();
+        fs::create_dir_all(data_dir.as_ref())
+            .map_err(|e| format!("Failed to create data directory: {}", e))?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // Only the node's own user may read the key
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&key_path)
+            .and_then(|mut file| file.write_all(hex::encode(identity.signing_key.to_bytes()).as_bytes()))
+            .map_err(|e| format!("Failed to write node key: {}", e))?;
+
+        Ok(identity)
+    }
+
+    /// Hex-encoded public key
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    /// Canonical peer id: the hash of the public key
+    pub fn node_id(&self) -> String {
+        Self::node_id_for_key(&self.signing_key.verifying_key())
+    }
+
+    /// Sign a challenge the peer issued during the handshake, as the given side of
+    /// the connection, returning the hex-encoded signature
+    pub fn sign_challenge(&self, role: HandshakeRole, challenge: &str) -> String {
+        hex::encode(self.signing_key.sign(&role.signed_message(challenge)).to_bytes())
+    }
+
+    /// Verify a peer's signature over a challenge we issued and return its canonical node id
+    pub fn verify_challenge(public_key: &str, role: HandshakeRole, challenge: &str, signature: &str) -> Result<String, String> {
+        let verifying_key = Self::parse_public_key(public_key)?;
+        let signature = hex::decode(signature)
+            .map_err(|e| format!("Invalid signature hex: {}", e))?;
+
+        if !verify_signature(&verifying_key, &role.signed_message(challenge), &signature) {
+            return Err("Handshake signature verification failed".to_string());
+        }
+
+        Ok(Self::node_id_for_key(&verifying_key))
+    }
+
+    /// Canonical node id of a hex-encoded public key
+    pub fn node_id_for_public_key(public_key: &str) -> Result<String, String> {
+        Ok(Self::node_id_for_key(&Self::parse_public_key(public_key)?))
+    }
+
+    fn parse_public_key(public_key: &str) -> Result<VerifyingKey, String> {
+        let key_bytes: [u8; 32] = hex::decode(public_key)
+            .map_err(|e| format!("Invalid public key hex: {}", e))?
+            .try_into()
+            .map_err(|_| "Public key must be exactly 32 bytes")?;
+        VerifyingKey::from_bytes(&key_bytes)
+            .map_err(|e| format!("Invalid public key: {}", e))
+    }
+
+    fn node_id_for_key(key: &VerifyingKey) -> String {
+        hex::encode(Sha256::digest(key.to_bytes()))
+    }
+}
+
+/// Side of a connection signing a handshake challenge. The role is part of the
+/// signed message, so a node cannot be used to answer challenges as the other side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeRole {
+    /// The side that opened the connection
+    Initiator,
+    /// The side that accepted it
+    Responder,
+}
+
+impl HandshakeRole {
+    fn signed_message(self, challenge: &str) -> Vec<u8> {
+        let label = match self {
+            HandshakeRole::Initiator => "initiator",
+            HandshakeRole::Responder => "responder",
+        };
+        format!("{}:{}", label, challenge).into_bytes()
+    }
+}
+
+/// Generate a random handshake challenge
+pub fn generate_nonce() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_persists_across_loads() {
+        let dir = std::env::temp_dir().join(format!("node_identity_{}", rand::random::<u64>()));
+
+        let first = NodeIdentity::load_or_create(&dir).unwrap();
+        let second = NodeIdentity::load_or_create(&dir).unwrap();
+        assert_eq!(first.node_id(), second.node_id());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_node_key_is_private() {
+        let dir = std::env::temp_dir().join(format!("node_identity_{}", rand::random::<u64>()));
+        NodeIdentity::load_or_create(&dir).unwrap();
+
+        let mode = fs::metadata(dir.join(NODE_KEY_FILE)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_handshake_signature_verification() {
+        let identity = NodeIdentity::generate();
+        let challenge = generate_nonce();
+        let signature = identity.sign_challenge(HandshakeRole::Responder, &challenge);
+
+        let node_id = NodeIdentity::verify_challenge(&identity.public_key_hex(), HandshakeRole::Responder, &challenge, &signature).unwrap();
+        assert_eq!(node_id, identity.node_id());
+        assert_eq!(NodeIdentity::node_id_for_public_key(&identity.public_key_hex()).unwrap(), identity.node_id());
+
+        // Another challenge, or the same one answered as the other side, does not verify
+        let other_challenge = generate_nonce();
+        assert!(NodeIdentity::verify_challenge(&identity.public_key_hex(), HandshakeRole::Responder, &other_challenge, &signature).is_err());
+        assert!(NodeIdentity::verify_challenge(&identity.public_key_hex(), HandshakeRole::Initiator, &challenge, &signature).is_err());
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::blockchain::block::Transaction;
+use crate::network::protocol::{InventoryItem, InventoryType};
+
+/// Number of recently seen block hashes to remember
+pub const MAX_SEEN_BLOCKS: usize = 1_000;
+
+/// Number of recently seen transaction ids to remember
+pub const MAX_SEEN_TRANSACTIONS: usize = 10_000;
+
+/// How long announcements are collected before being sent as one batch
+pub const INV_BATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Most items sent in a single inventory announcement
+pub const MAX_INV_BATCH: usize = 1_000;
+
+/// Number of relayed transaction bodies kept for peers to fetch
+pub const MAX_RELAY_TRANSACTIONS: usize = 5_000;
+
+/// How long a block requested from a peer that announced it is left to that
+/// peer before it is requested from the next one
+pub const BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bounded LRU set of recently seen hashes
+#[derive(Debug, Clone)]
+pub struct InventoryCache {
+    capacity: usize,
+    order: VecDeque<String>,
+    entries: HashSet<String>,
+}
+
+impl InventoryCache {
+    /// Create a cache holding at most `capacity` hashes
+    pub fn new(capacity: usize) -> Self {
+        InventoryCache {
+            capacity,
+            order: VecDeque::new(),
+            entries: HashSet::new(),
+        }
+    }
+
+    /// Check if a hash has been seen recently
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains(hash)
+    }
+
+    /// Record a hash as seen, evicting the least recently seen one when full.
+    /// Returns true if the hash was not already known.
+    pub fn insert(&mut self, hash: &str) -> bool {
+        if self.entries.contains(hash) {
+            // Refresh position so active items are not evicted
+            if let Some(pos) = self.order.iter().position(|h| h == hash) {
+                self.order.remove(pos);
+            }
+            self.order.push_back(hash.to_string());
+            return false;
+        }
+
+        if self.capacity == 0 {
+            return true;
+        }
+
+        while self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(hash.to_string());
+        self.entries.insert(hash.to_string());
+        true
+    }
+
+    /// Number of hashes in the cache
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A block requested from a peer, or being processed for one
+#[derive(Debug, Clone)]
+struct BlockInFlight {
+    /// Connected peers that announced the block, in the order they announced it
+    announcers: Vec<String>,
+    /// How many of `announcers` were asked for it; the last of them is waited on
+    asked: usize,
+    /// When the last request went out, None once that peer is gone
+    requested_at: Option<Instant>,
+    processing: bool,
+}
+
+impl BlockInFlight {
+    fn new(peer: &str, now: Instant) -> Self {
+        BlockInFlight {
+            announcers: vec![peer.to_string()],
+            asked: 1,
+            requested_at: Some(now),
+            processing: false,
+        }
+    }
+
+    /// Whether the peer last asked had its chance to deliver
+    fn timed_out(&self, now: Instant) -> bool {
+        self.requested_at.is_none_or(|at| now.duration_since(at) >= BLOCK_REQUEST_TIMEOUT)
+    }
+
+    /// Whether `peer` is the next announcer to ask once the last request timed out
+    fn next_is(&self, peer: &str, now: Instant) -> bool {
+        !self.processing && self.timed_out(now) && self.announcers.get(self.asked).is_some_and(|next| next == peer)
+    }
+
+    /// Ask the next announcer
+    fn ask_next(&mut self, now: Instant) {
+        self.asked += 1;
+        self.requested_at = Some(now);
+    }
+
+    /// Every announcer was asked and the last one did not deliver
+    fn exhausted(&self, now: Instant) -> bool {
+        !self.processing && self.timed_out(now) && self.asked >= self.announcers.len()
+    }
+}
+
+/// Recently seen blocks and transactions, used to skip duplicate announcements.
+/// Blocks not yet seen are tracked while in flight, so that when several peers
+/// announce the same block only the first is asked for it and only one
+/// connection processes it. The other announcers are remembered and asked in
+/// turn if the block does not arrive in time.
+#[derive(Debug, Clone)]
+pub struct SeenInventory {
+    pub blocks: InventoryCache,
+    pub transactions: InventoryCache,
+    in_flight: HashMap<String, BlockInFlight>,
+}
+
+impl SeenInventory {
+    pub fn new() -> Self {
+        SeenInventory {
+            blocks: InventoryCache::new(MAX_SEEN_BLOCKS),
+            transactions: InventoryCache::new(MAX_SEEN_TRANSACTIONS),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    fn cache_mut(&mut self, inv_type: &InventoryType) -> &mut InventoryCache {
+        match inv_type {
+            InventoryType::Block => &mut self.blocks,
+            InventoryType::Transaction => &mut self.transactions,
+        }
+    }
+
+    /// Check if an inventory item has been seen
+    pub fn contains(&self, item: &InventoryItem) -> bool {
+        match item.inv_type {
+            InventoryType::Block => self.blocks.contains(&item.hash),
+            InventoryType::Transaction => self.transactions.contains(&item.hash),
+        }
+    }
+
+    /// Record an inventory item as seen; returns true if it is new
+    pub fn insert(&mut self, item: &InventoryItem) -> bool {
+        self.cache_mut(&item.inv_type).insert(&item.hash)
+    }
+
+    /// Filter announced items down to the ones we have not seen
+    pub fn unseen(&self, items: &[InventoryItem]) -> Vec<InventoryItem> {
+        items.iter()
+            .filter(|item| !self.contains(item))
+            .cloned()
+            .collect()
+    }
+
+    /// Items to request from `peer` in answer to its announcement: the unseen
+    /// ones, less blocks already requested from another peer or being processed.
+    /// `peer` is recorded as an announcer of every unseen block, and the blocks
+    /// returned as requested from it.
+    pub fn request(&mut self, items: &[InventoryItem], peer: &str, now: Instant) -> Vec<InventoryItem> {
+        self.in_flight.retain(|_, block| !block.exhausted(now));
+
+        let mut wanted = Vec::new();
+        for item in items.iter().filter(|item| !self.contains(item)) {
+            if item.inv_type == InventoryType::Block {
+                match self.in_flight.get_mut(&item.hash) {
+                    Some(block) => {
+                        if !block.announcers.iter().any(|announcer| announcer == peer) {
+                            block.announcers.push(peer.to_string());
+                        }
+                        let waiting_on = block.asked.checked_sub(1).and_then(|last| block.announcers.get(last));
+                        if !block.processing && waiting_on.is_some_and(|last| last == peer) {
+                            // Announced again by the peer we asked
+                            block.requested_at = Some(now);
+                        } else if block.next_is(peer, now) {
+                            block.ask_next(now);
+                        } else {
+                            continue;
+                        }
+                    },
+                    None => {
+                        self.in_flight.insert(item.hash.clone(), BlockInFlight::new(peer, now));
+                    },
+                }
+            }
+            wanted.push(item.clone());
+        }
+        wanted
+    }
+
+    /// Blocks to request from `peer` now because the announcer asked before it
+    /// did not deliver in time, recorded as requested from `peer`
+    pub fn rerequest(&mut self, peer: &str, now: Instant) -> Vec<InventoryItem> {
+        self.in_flight.retain(|_, block| !block.exhausted(now));
+
+        let mut due = Vec::new();
+        for (hash, block) in self.in_flight.iter_mut() {
+            if block.next_is(peer, now) && !self.blocks.contains(hash) {
+                block.ask_next(now);
+                due.push(InventoryItem::block(hash.clone()));
+            }
+        }
+        due
+    }
+
+    /// Forget a disconnected peer's announcements. Blocks that were waiting on it
+    /// are due from the next announcer straight away.
+    pub fn forget_peer(&mut self, peer: &str) {
+        for block in self.in_flight.values_mut() {
+            if let Some(index) = block.announcers.iter().position(|announcer| announcer == peer) {
+                block.announcers.remove(index);
+                if index < block.asked {
+                    if index + 1 == block.asked {
+                        block.requested_at = None;
+                    }
+                    block.asked -= 1;
+                }
+            }
+        }
+    }
+
+    /// Start processing a block delivered by `peer`. Returns false if the block
+    /// was already seen or another connection is processing it; the first
+    /// delivery wins, whichever peer it was requested from.
+    pub fn begin_block(&mut self, hash: &str, peer: &str, now: Instant) -> bool {
+        if self.blocks.contains(hash) || self.in_flight.get(hash).is_some_and(|block| block.processing) {
+            return false;
+        }
+        self.in_flight.entry(hash.to_string())
+            .or_insert_with(|| BlockInFlight::new(peer, now))
+            .processing = true;
+        true
+    }
+
+    /// Mark a block processed, so later deliveries and announcements of it are dropped
+    pub fn finish_block(&mut self, hash: &str) {
+        self.in_flight.remove(hash);
+        self.blocks.insert(hash);
+    }
+
+    /// Give up processing a block without marking it seen, so another delivery is
+    /// processed and the next announcer is asked for it
+    pub fn release_block(&mut self, hash: &str) {
+        let Some(block) = self.in_flight.get_mut(hash).filter(|block| block.processing) else {
+            return;
+        };
+        block.processing = false;
+        block.requested_at = None;
+        if block.asked >= block.announcers.len() {
+            self.in_flight.remove(hash);
+        }
+    }
+
+    /// Number of blocks requested from peers or being processed
+    pub fn blocks_in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+/// Processing lock on a block delivered by a peer. Dropping it without
+/// `finish` releases the block for another delivery.
+#[derive(Debug)]
+pub struct BlockClaim {
+    seen: Arc<Mutex<SeenInventory>>,
+    hash: String,
+}
+
+impl BlockClaim {
+    /// Claim a block delivered by `peer`, or None if it is a duplicate
+    pub fn acquire(seen: &Arc<Mutex<SeenInventory>>, hash: &str, peer: &str) -> Option<Self> {
+        seen.lock().unwrap().begin_block(hash, peer, Instant::now()).then(|| BlockClaim {
+            seen: Arc::clone(seen),
+            hash: hash.to_string(),
+        })
+    }
+
+    /// Mark the block processed and release the lock
+    pub fn finish(self) {
+        self.seen.lock().unwrap().finish_block(&self.hash);
+    }
+}
+
+impl Drop for BlockClaim {
+    fn drop(&mut self) {
+        if let Ok(mut seen) = self.seen.lock() {
+            seen.release_block(&self.hash);
+        }
+    }
+}
+
+impl Default for SeenInventory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Announcements waiting to be batched out to peers, along with the bodies of
+/// relayed transactions so peers can fetch the ones they are missing
+#[derive(Debug, Clone)]
+pub struct InventoryRelay {
+    interval: Duration,
+    pending: Vec<InventoryItem>,
+    last_flush: Instant,
+    transactions: HashMap<String, Transaction>,
+    transaction_order: VecDeque<String>,
+}
+
+impl InventoryRelay {
+    /// Create a relay that sends queued announcements every `interval`
+    pub fn new(interval: Duration) -> Self {
+        InventoryRelay {
+            interval,
+            pending: Vec::new(),
+            last_flush: Instant::now(),
+            transactions: HashMap::new(),
+            transaction_order: VecDeque::new(),
+        }
+    }
+
+    /// Queue an announcement unless it is already waiting
+    pub fn queue(&mut self, item: InventoryItem) {
+        if !self.pending.contains(&item) {
+            self.pending.push(item);
+        }
+    }
+
+    /// Keep a transaction's body for peers and queue its announcement
+    pub fn queue_transaction(&mut self, transaction: Transaction) {
+        let hash = transaction.hash();
+        if self.transactions.insert(hash.clone(), transaction).is_none() {
+            self.transaction_order.push_back(hash.clone());
+            while self.transaction_order.len() > MAX_RELAY_TRANSACTIONS {
+                if let Some(oldest) = self.transaction_order.pop_front() {
+                    self.transactions.remove(&oldest);
+                }
+            }
+        }
+        self.queue(InventoryItem::transaction(hash));
+    }
+
+    /// Body of a relayed transaction
+    pub fn transaction(&self, hash: &str) -> Option<&Transaction> {
+        self.transactions.get(hash)
+    }
+
+    /// Number of announcements waiting for the next batch
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Take the next batch once the interval has passed since the last one.
+    /// Items beyond `MAX_INV_BATCH` wait for the following batch.
+    pub fn take_batch(&mut self, now: Instant) -> Option<Vec<InventoryItem>> {
+        if self.pending.is_empty() || now.duration_since(self.last_flush) < self.interval {
+            return None;
+        }
+        self.last_flush = now;
+        let count = self.pending.len().min(MAX_INV_BATCH);
+        Some(self.pending.drain(..count).collect())
+    }
+}
+
+impl Default for InventoryRelay {
+    fn default() -> Self {
+        Self::new(INV_BATCH_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inventory_cache_evicts_least_recent() {
+        let mut cache = InventoryCache::new(2);
+        assert!(cache.insert("a"));
+        assert!(cache.insert("b"));
+        assert!(!cache.insert("a")); // refreshes "a"
+        assert!(cache.insert("c")); // evicts "b"
+
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert!(cache.contains("c"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_seen_inventory_filters_known_items() {
+        let mut seen = SeenInventory::new();
+        let block = InventoryItem::block("blockhash".to_string());
+        let tx = InventoryItem::transaction("txid".to_string());
+
+        assert!(seen.insert(&block));
+        assert!(!seen.insert(&block));
+
+        let unseen = seen.unseen(&[block, tx.clone()]);
+        assert_eq!(unseen.len(), 1);
+        assert_eq!(unseen[0].hash, tx.hash);
+    }
+
+    #[test]
+    fn test_first_announcement_of_block_wins() {
+        let start = Instant::now();
+        let mut seen = SeenInventory::new();
+        let block = InventoryItem::block("blockhash".to_string());
+        let tx = InventoryItem::transaction("txid".to_string());
+
+        assert_eq!(seen.request(&[block.clone(), tx.clone()], "peer-a", start), vec![block.clone(), tx.clone()]);
+        // Another peer is not asked for the block until the first request times out
+        assert_eq!(seen.request(&[block.clone(), tx.clone()], "peer-b", start), vec![tx.clone()]);
+        assert_eq!(seen.request(&[block.clone()], "peer-a", start), vec![block.clone()]);
+        let later = start + BLOCK_REQUEST_TIMEOUT;
+        assert_eq!(seen.request(&[block.clone()], "peer-b", later), vec![block.clone()]);
+
+        // Whoever delivers first processes it, and nobody is asked while it does
+        assert!(seen.begin_block("blockhash", "peer-a", later));
+        assert!(!seen.begin_block("blockhash", "peer-b", later));
+        assert!(seen.request(&[block.clone()], "peer-c", later + BLOCK_REQUEST_TIMEOUT).is_empty());
+        seen.finish_block("blockhash");
+        assert_eq!(seen.blocks_in_flight(), 0);
+        assert!(!seen.begin_block("blockhash", "peer-b", later));
+        assert!(seen.request(&[block], "peer-b", later).is_empty());
+    }
+
+    #[test]
+    fn test_block_rerequested_from_next_announcer() {
+        let start = Instant::now();
+        let mut seen = SeenInventory::new();
+        let block = InventoryItem::block("blockhash".to_string());
+
+        assert_eq!(seen.request(&[block.clone()], "peer-a", start), vec![block.clone()]);
+        assert!(seen.request(&[block.clone()], "peer-b", start).is_empty());
+        assert!(seen.request(&[block.clone()], "peer-c", start).is_empty());
+
+        // Peer b is next in line once peer a had its chance
+        assert!(seen.rerequest("peer-b", start).is_empty());
+        let later = start + BLOCK_REQUEST_TIMEOUT;
+        assert!(seen.rerequest("peer-c", later).is_empty());
+        assert_eq!(seen.rerequest("peer-b", later), vec![block.clone()]);
+        assert!(seen.rerequest("peer-b", later).is_empty());
+
+        // Peer b hangs up, so peer c is asked without waiting
+        seen.forget_peer("peer-b");
+        assert_eq!(seen.rerequest("peer-c", later), vec![block.clone()]);
+
+        // A delivery that could not be processed passes the block on too
+        seen.request(&[block.clone()], "peer-d", later);
+        assert!(seen.begin_block("blockhash", "peer-c", later));
+        seen.release_block("blockhash");
+        assert_eq!(seen.rerequest("peer-d", later), vec![block.clone()]);
+
+        // Nobody left to ask: the next announcement starts over
+        let much_later = later + BLOCK_REQUEST_TIMEOUT;
+        assert!(seen.rerequest("peer-a", much_later).is_empty());
+        assert_eq!(seen.blocks_in_flight(), 0);
+        assert_eq!(seen.request(&[block.clone()], "peer-a", much_later), vec![block]);
+    }
+
+    #[test]
+    fn test_dropped_block_claim_releases_block() {
+        let seen = Arc::new(Mutex::new(SeenInventory::new()));
+
+        let claim = BlockClaim::acquire(&seen, "blockhash", "peer-a").unwrap();
+        assert!(BlockClaim::acquire(&seen, "blockhash", "peer-b").is_none());
+        drop(claim);
+        assert!(!seen.lock().unwrap().contains(&InventoryItem::block("blockhash".to_string())));
+
+        BlockClaim::acquire(&seen, "blockhash", "peer-b").unwrap().finish();
+        assert!(BlockClaim::acquire(&seen, "blockhash", "peer-c").is_none());
+        assert_eq!(seen.lock().unwrap().blocks_in_flight(), 0);
+    }
+
+    #[test]
+    fn test_inventory_relay_batches_on_interval() {
+        let start = Instant::now();
+        let mut relay = InventoryRelay::new(Duration::from_millis(100));
+        let tx = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 5,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+
+        relay.queue(InventoryItem::block("blockhash".to_string()));
+        relay.queue(InventoryItem::block("blockhash".to_string()));
+        relay.queue_transaction(tx.clone());
+        assert_eq!(relay.pending_len(), 2);
+        assert!(relay.transaction(&tx.hash()).is_some());
+
+        assert!(relay.take_batch(start).is_none());
+        let batch = relay.take_batch(start + Duration::from_millis(150)).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[1], InventoryItem::transaction(tx.hash()));
+        assert!(relay.take_batch(start + Duration::from_millis(300)).is_none());
+
+        // The body stays available after the announcement went out
+        assert!(relay.transaction(&tx.hash()).is_some());
+    }
+}
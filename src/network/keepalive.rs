@@ -0,0 +1,234 @@
+//! Keepalive scheduling and dead-peer detection
+//!
+//! Every connection is pinged once it has been quiet for the keepalive interval.
+//! A ping that goes unanswered for another interval counts as a missed pong, and
+//! a peer that misses too many in a row is considered dead and disconnected.
+//! Activity is fed into `PeerDiscovery` so stale-peer cleanup sees real data.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::network::discovery::{PeerDiscovery, PeerInfo};
+use crate::network::server::NetworkServer;
+use crate::status;
+
+/// Default time a connection may stay quiet before it is pinged
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default number of consecutive unanswered pings before a peer is dropped
+pub const DEFAULT_MAX_MISSED_PONGS: u32 = 3;
+
+/// Default time between sweeps of stale peers out of peer discovery
+pub const DEFAULT_STALE_PEER_SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Keepalive timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    pub interval: Duration,
+    pub max_missed_pongs: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        KeepaliveConfig {
+            interval: DEFAULT_PING_INTERVAL,
+            max_missed_pongs: DEFAULT_MAX_MISSED_PONGS,
+        }
+    }
+}
+
+/// What a connection should do when its keepalive timer fires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepaliveAction {
+    /// Nothing is due yet
+    Wait,
+    /// Send a ping now
+    SendPing,
+    /// The peer missed too many pongs and should be disconnected
+    Disconnect,
+}
+
+/// Liveness of a single connection
+#[derive(Debug, Clone)]
+struct Liveness {
+    last_activity: Instant,
+    ping_sent: Option<Instant>,
+    missed_pongs: u32,
+}
+
+/// Tracks keepalive state for every open connection
+pub struct KeepaliveScheduler {
+    config: KeepaliveConfig,
+    connections: HashMap<SocketAddr, Liveness>,
+    discovery: Option<Arc<Mutex<PeerDiscovery>>>,
+}
+
+impl KeepaliveScheduler {
+    pub fn new(config: KeepaliveConfig) -> Self {
+        KeepaliveScheduler {
+            config,
+            connections: HashMap::new(),
+            discovery: None,
+        }
+    }
+
+    /// Report peer activity and dead peers to a discovery instance
+    pub fn with_discovery(mut self, discovery: Arc<Mutex<PeerDiscovery>>) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    pub fn config(&self) -> KeepaliveConfig {
+        self.config
+    }
+
+    /// Start tracking a new connection, adding the peer to discovery if it is new there
+    pub fn register(&mut self, addr: SocketAddr, now: Instant) {
+        self.connections.insert(addr, Liveness {
+            last_activity: now,
+            ping_sent: None,
+            missed_pongs: 0,
+        });
+
+        if let Some(discovery) = &self.discovery {
+            let mut discovery = discovery.lock().unwrap();
+            if !discovery.has_peer(&addr) {
+                discovery.add_peer(PeerInfo::new(addr, String::new(), 0));
+            }
+            discovery.touch_peer(&addr);
+        }
+    }
+
+    /// Stop tracking a closed connection
+    pub fn remove(&mut self, addr: &SocketAddr) {
+        self.connections.remove(addr);
+    }
+
+    /// Record any message from the peer; it answers an outstanding ping as well as a pong does
+    pub fn record_activity(&mut self, addr: SocketAddr, now: Instant) {
+        let liveness = self.connections.entry(addr).or_insert(Liveness {
+            last_activity: now,
+            ping_sent: None,
+            missed_pongs: 0,
+        });
+        liveness.last_activity = now;
+        liveness.ping_sent = None;
+        liveness.missed_pongs = 0;
+
+        if let Some(discovery) = &self.discovery {
+            discovery.lock().unwrap().touch_peer(&addr);
+        }
+    }
+
+    /// Decide what the connection should do at `now`
+    pub fn poll(&mut self, addr: SocketAddr, now: Instant) -> KeepaliveAction {
+        let interval = self.config.interval;
+        let Some(liveness) = self.connections.get_mut(&addr) else {
+            return KeepaliveAction::Wait;
+        };
+
+        match liveness.ping_sent {
+            Some(sent) if now.duration_since(sent) < interval => return KeepaliveAction::Wait,
+            Some(_) => liveness.missed_pongs += 1,
+            None if now.duration_since(liveness.last_activity) < interval => return KeepaliveAction::Wait,
+            None => {},
+        }
+
+        if liveness.missed_pongs >= self.config.max_missed_pongs {
+            if let Some(discovery) = &self.discovery {
+                discovery.lock().unwrap().mark_peer_inactive(&addr);
+            }
+            return KeepaliveAction::Disconnect;
+        }
+
+        liveness.ping_sent = Some(now);
+        KeepaliveAction::SendPing
+    }
+
+    /// Consecutive unanswered pings for a connection
+    pub fn missed_pongs(&self, addr: &SocketAddr) -> Option<u32> {
+        self.connections.get(addr).map(|liveness| liveness.missed_pongs)
+    }
+
+    /// Number of tracked connections
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+}
+
+impl Default for KeepaliveScheduler {
+    fn default() -> Self {
+        KeepaliveScheduler::new(KeepaliveConfig::default())
+    }
+}
+
+/// Remove the peers `discovery` has not heard from in too long every `interval`,
+/// on a background thread, until the server stops
+pub fn sweep_stale_peers(discovery: Arc<Mutex<PeerDiscovery>>, server: Arc<NetworkServer>, interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if !server.is_running() {
+            return;
+        }
+        let removed = discovery.lock().unwrap().cleanup_stale_peers();
+        if removed > 0 {
+            status!("Forgot {} stale peer(s)", removed);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> KeepaliveConfig {
+        KeepaliveConfig { interval: Duration::from_secs(10), max_missed_pongs: 2 }
+    }
+
+    #[test]
+    fn test_ping_after_quiet_interval() {
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let start = Instant::now();
+        let mut scheduler = KeepaliveScheduler::new(config());
+        scheduler.register(addr, start);
+
+        assert_eq!(scheduler.poll(addr, start + Duration::from_secs(5)), KeepaliveAction::Wait);
+        assert_eq!(scheduler.poll(addr, start + Duration::from_secs(10)), KeepaliveAction::SendPing);
+        // Waiting on the pong
+        assert_eq!(scheduler.poll(addr, start + Duration::from_secs(15)), KeepaliveAction::Wait);
+
+        // A pong resets the timer
+        scheduler.record_activity(addr, start + Duration::from_secs(16));
+        assert_eq!(scheduler.poll(addr, start + Duration::from_secs(20)), KeepaliveAction::Wait);
+        assert_eq!(scheduler.missed_pongs(&addr), Some(0));
+    }
+
+    #[test]
+    fn test_dead_peer_after_missed_pongs() {
+        let addr: SocketAddr = "127.0.0.1:9001".parse().unwrap();
+        let discovery = Arc::new(Mutex::new(PeerDiscovery::new(
+            "127.0.0.1:8333".parse().unwrap(),
+            "test-v1.0".to_string(),
+        )));
+
+        // Registering the connection makes the peer known to discovery
+        let start = Instant::now();
+        let mut scheduler = KeepaliveScheduler::new(config()).with_discovery(Arc::clone(&discovery));
+        scheduler.register(addr, start);
+        assert_eq!(discovery.lock().unwrap().active_peer_count(), 1);
+
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(scheduler.poll(addr, at(10)), KeepaliveAction::SendPing);
+        assert_eq!(scheduler.poll(addr, at(20)), KeepaliveAction::SendPing);
+        assert_eq!(scheduler.missed_pongs(&addr), Some(1));
+        assert_eq!(scheduler.poll(addr, at(30)), KeepaliveAction::Disconnect);
+        assert_eq!(discovery.lock().unwrap().active_peer_count(), 0);
+
+        // Hearing from the peer again revives it
+        scheduler.record_activity(addr, at(31));
+        assert_eq!(discovery.lock().unwrap().active_peer_count(), 1);
+    }
+}
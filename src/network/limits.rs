@@ -0,0 +1,212 @@
+//! Size limits checked before and while messages are deserialized
+//!
+//! `MAX_MESSAGE_SIZE` bounds every frame, but most message types never come
+//! near it. Before a message is deserialized, a pass that skips the payload
+//! reads its type, and the frame is held to that type's cap. A peer therefore
+//! cannot make us build a megabyte "transaction" or ping. Block lists are
+//! deserialized one block at a time, and the block and transaction counts are
+//! checked as they arrive. An oversized list is refused as soon as it passes a
+//! limit, without building the rest of it.
+
+use std::fmt;
+
+use serde::de::{self, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::blockchain::block::Block;
+use crate::mempool::policy::DEFAULT_MAX_TX_SIZE;
+use crate::network::protocol::{MAX_BLOCKS_PER_REQUEST, MAX_MESSAGE_SIZE};
+
+/// Largest request, reply or announcement that carries no blocks, transactions or lists
+pub const MAX_CONTROL_MESSAGE_SIZE: usize = 4 * 1024;
+
+/// Largest message carrying one transaction: a transaction at the default relay
+/// size limit, with room for the escaping it gets inside the message
+pub const MAX_TRANSACTION_MESSAGE_SIZE: usize = 2 * DEFAULT_MAX_TX_SIZE + MAX_CONTROL_MESSAGE_SIZE;
+
+/// Most blocks accepted in one `Blocks` or `LocatorBlocks` message
+pub const MAX_BLOCKS_PER_MESSAGE: usize = MAX_BLOCKS_PER_REQUEST as usize;
+
+/// Most transactions a block received from a peer may hold
+pub const MAX_TRANSACTIONS_PER_BLOCK: usize = 10_000;
+
+/// Largest frame accepted for a message type, by `MessageType::name`
+pub fn max_message_size(message_type: &str) -> usize {
+    match message_type {
+        "NewTransaction" | "TransactionResponse" => MAX_TRANSACTION_MESSAGE_SIZE,
+        "Blocks" | "LocatorBlocks" | "NewBlock" | "Peers" | "AddressBook" | "MempoolResponse"
+            | "BlockHeaders" | "GetBlocksByLocator" | "Inv" | "GetData" => MAX_MESSAGE_SIZE,
+        _ => MAX_CONTROL_MESSAGE_SIZE,
+    }
+}
+
+/// Refuse a serialized message that is larger than its type allows. Only the
+/// type name is built; the payload is skipped.
+pub fn check_message_size(data: &[u8]) -> Result<(), String> {
+    let message_type = peek_message_type(data)?;
+    let limit = max_message_size(&message_type);
+    if data.len() > limit {
+        return Err(format!("{} message of {} bytes exceeds its limit of {}", message_type, data.len(), limit));
+    }
+    Ok(())
+}
+
+/// Type name of a serialized `NetworkMessage`, read without building its payload
+pub fn peek_message_type(data: &[u8]) -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct Envelope {
+        message_type: TypeName,
+    }
+
+    serde_json::from_slice::<Envelope>(data)
+        .map(|envelope| envelope.message_type.0)
+        .map_err(|e| format!("Failed to read message type: {}", e))
+}
+
+/// Variant name of a serialized `MessageType`: a bare string for variants
+/// without data, otherwise the only key of an object
+struct TypeName(String);
+
+impl<'de> Deserialize<'de> for TypeName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TypeNameVisitor;
+
+        impl<'de> Visitor<'de> for TypeNameVisitor {
+            type Value = TypeName;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a message type")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TypeName, E> {
+                Ok(TypeName(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TypeName, A::Error> {
+                let name: String = map.next_key()?
+                    .ok_or_else(|| de::Error::custom("empty message type"))?;
+                map.next_value::<IgnoredAny>()?;
+                if map.next_key::<IgnoredAny>()?.is_some() {
+                    return Err(de::Error::custom("message type has more than one variant"));
+                }
+                Ok(TypeName(name))
+            }
+        }
+
+        deserializer.deserialize_any(TypeNameVisitor)
+    }
+}
+
+/// Refuse a block holding more than `MAX_TRANSACTIONS_PER_BLOCK` transactions
+pub fn check_block_transactions(block: &Block) -> Result<(), String> {
+    if block.transactions.len() > MAX_TRANSACTIONS_PER_BLOCK {
+        return Err(format!(
+            "block {} has {} transactions, more than the limit of {}",
+            block.header.hash, block.transactions.len(), MAX_TRANSACTIONS_PER_BLOCK
+        ));
+    }
+    Ok(())
+}
+
+/// Deserialize one block received from a peer, refusing too many transactions
+pub fn deserialize_block<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Block, D::Error> {
+    let block = Block::deserialize(deserializer)?;
+    check_block_transactions(&block).map_err(de::Error::custom)?;
+    Ok(block)
+}
+
+/// Deserialize a list of blocks one at a time, stopping at the first block past
+/// `MAX_BLOCKS_PER_MESSAGE` or with too many transactions
+pub fn deserialize_blocks<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Block>, D::Error> {
+    struct BlocksVisitor;
+
+    impl<'de> Visitor<'de> for BlocksVisitor {
+        type Value = Vec<Block>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a list of at most {} blocks", MAX_BLOCKS_PER_MESSAGE)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<Block>, A::Error> {
+            // The length a peer claims is not trusted for preallocation
+            let mut blocks = Vec::new();
+            while let Some(block) = seq.next_element::<Block>()? {
+                if blocks.len() == MAX_BLOCKS_PER_MESSAGE {
+                    return Err(de::Error::custom(format!(
+                        "more than {} blocks in one message", MAX_BLOCKS_PER_MESSAGE
+                    )));
+                }
+                check_block_transactions(&block).map_err(de::Error::custom)?;
+                blocks.push(block);
+            }
+            Ok(blocks)
+        }
+    }
+
+    deserializer.deserialize_seq(BlocksVisitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::block::Transaction;
+    use crate::network::protocol::{MessageType, NetworkMessage};
+
+    fn block_with(transactions: usize) -> Block {
+        let tx = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 1,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        Block::new("0".to_string(), vec![tx; transactions], 0, 0, 1)
+    }
+
+    fn encode(message_type: MessageType) -> Vec<u8> {
+        NetworkMessage::new(message_type).to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_message_type_caps() {
+        assert_eq!(peek_message_type(&encode(MessageType::Ping)).unwrap(), "Ping");
+        assert_eq!(peek_message_type(&encode(MessageType::Blocks(vec![]))).unwrap(), "Blocks");
+        assert!(peek_message_type(b"{\"message_type\":{}}").is_err());
+
+        // A control message padded past its cap is refused before it is built
+        let padded = encode(MessageType::GetTransaction { tx_hash: "a".repeat(MAX_CONTROL_MESSAGE_SIZE) });
+        assert!(check_message_size(&padded).is_err());
+        assert!(NetworkMessage::from_bytes(&padded).is_err());
+        let ping = encode(MessageType::Ping);
+        assert!(check_message_size(&ping).is_ok());
+
+        let transaction = MessageType::NewTransaction {
+            transaction_data: "a".repeat(MAX_TRANSACTION_MESSAGE_SIZE),
+            from_address: "alice".to_string(),
+            to_address: "bob".to_string(),
+            amount: 1,
+            signature: String::new(),
+            fee_per_byte: 0.0,
+        };
+        assert!(check_message_size(&encode(transaction)).is_err());
+    }
+
+    #[test]
+    fn test_block_lists_are_limited_while_reading() {
+        let blocks = vec![block_with(1); MAX_BLOCKS_PER_MESSAGE];
+        let message = NetworkMessage::from_bytes(&encode(MessageType::Blocks(blocks))).unwrap();
+        assert!(matches!(message.message_type, MessageType::Blocks(blocks) if blocks.len() == MAX_BLOCKS_PER_MESSAGE));
+
+        let blocks = vec![block_with(1); MAX_BLOCKS_PER_MESSAGE + 1];
+        let error = NetworkMessage::from_bytes(&encode(MessageType::Blocks(blocks))).unwrap_err();
+        assert!(error.contains("more than"), "{}", error);
+
+        let crowded = block_with(MAX_TRANSACTIONS_PER_BLOCK + 1);
+        assert!(serde_json::from_str::<MessageType>(&serde_json::to_string(&MessageType::NewBlock(crowded.clone())).unwrap()).is_err());
+        let locator = MessageType::LocatorBlocks { fork_height: None, blocks: vec![crowded], remaining: 0 };
+        assert!(serde_json::from_str::<MessageType>(&serde_json::to_string(&locator).unwrap()).is_err());
+        let fine = MessageType::NewBlock(block_with(MAX_TRANSACTIONS_PER_BLOCK));
+        assert!(serde_json::from_str::<MessageType>(&serde_json::to_string(&fine).unwrap()).is_ok());
+    }
+}
@@ -6,9 +6,25 @@
 //! - Peer discovery and management
 //! - Message routing and validation
 
+pub mod address;
 pub mod protocol;
 pub mod server;
 pub mod discovery;
+pub mod inventory;
+pub mod identity;
+pub mod keepalive;
+pub mod framing;
+pub mod watchdog;
+pub mod propagation;
+pub mod bootstrap;
+pub mod traffic;
+pub mod download;
+pub mod peer_stats;
+pub mod limits;
+pub mod height_poll;
+pub mod tip_follower;
+pub mod dialer;
+pub mod conformance;
 
 pub use discovery::{
     PeerDiscovery, 
@@ -21,13 +37,82 @@ pub use discovery::{
 pub use protocol::{
     NetworkMessage,
     MessageType,
-    NetworkError
+    NetworkError,
+    InventoryItem,
+    InventoryType,
+    Features,
+    PeerCapabilities,
+    Services
+};
+
+pub use inventory::{
+    BlockClaim,
+    InventoryCache,
+    InventoryRelay,
+    SeenInventory
+};
+
+pub use address::PeerAddress;
+
+pub use identity::NodeIdentity;
+
+pub use keepalive::{
+    KeepaliveConfig,
+    KeepaliveScheduler,
+    KeepaliveAction
+};
+
+pub use framing::{
+    FrameReader,
+    FramingConfig
+};
+
+pub use watchdog::ChainWatchdog;
+
+pub use height_poll::HeightPoller;
+
+pub use tip_follower::{TipFollower, DEFAULT_TIP_FOLLOW_INTERVAL};
+
+pub use dialer::OutboundDialer;
+
+pub use conformance::ConformanceVectors;
+
+pub use propagation::{
+    PropagationTracker,
+    PropagationStats,
+    BlockPropagation
+};
+
+pub use traffic::{
+    CompressionCounts,
+    NodeStats,
+    TrafficCounts,
+    TrafficMonitor
+};
+
+pub use download::{
+    DownloadConfig,
+    DownloadScheduler
+};
+
+pub use peer_stats::{
+    PeerRecord,
+    PeerStatsDb
+};
+
+pub use bootstrap::{
+    BootstrapBundle,
+    BootstrapPayload,
+    BOOTSTRAP_PUBLISHER_KEYS
 };
 
 pub use server::{
     NetworkServer
 };
 
+/// Default limit on connected peers
+pub const DEFAULT_MAX_PEERS: usize = 50;
+
 /// Network configuration
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
@@ -42,12 +127,12 @@ impl Default for NetworkConfig {
     fn default() -> Self {
         NetworkConfig {
             listen_port: 8333,
-            max_peers: 50,
+            max_peers: DEFAULT_MAX_PEERS,
             seed_nodes: vec![
                 "127.0.0.1:8334".to_string(),
                 "127.0.0.1:8335".to_string(),
             ],
-            protocol_version: 1,
+            protocol_version: protocol::PROTOCOL_VERSION,
             network_id: "rust-chain-mainnet".to_string(),
         }
     }
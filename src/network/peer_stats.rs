@@ -0,0 +1,387 @@
+//! Long-term peer statistics
+//!
+//! The traffic monitor and alert monitor forget a peer when the node stops. The
+//! peer stats database keeps one record per peer host in `peer_stats` in the data
+//! directory: when the peer was first and last seen, how often connecting to it
+//! worked, the bytes exchanged with it, the blocks and transactions it gave us and
+//! its misbehavior. Records are kept per host rather than per socket address,
+//! since inbound connections arrive from a new port every time. The node ranks
+//! candidate addresses by these records before connecting, so peers that served
+//! it well are tried first and recently misbehaving ones are left out. The same
+//! database keeps the propagation records of the blocks this node mined.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::network::address::PeerAddress;
+use crate::network::propagation::PropagationTracker;
+use crate::network::server::NetworkServer;
+use crate::network::traffic::TrafficCounts;
+use crate::storage::Database;
+
+/// Directory in the data directory holding the peer stats database
+pub const PEER_STATS_DIR: &str = "peer_stats";
+
+/// Default time between two writes of traffic counts to the database
+pub const DEFAULT_PEER_STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Misbehavior events kept per peer; older ones only remain in the total
+pub const MAX_MISBEHAVIOR_EVENTS: usize = 50;
+
+/// How far back misbehavior counts against a peer, in seconds
+pub const MISBEHAVIOR_WINDOW: u64 = 24 * 60 * 60;
+
+/// Misbehavior events within the window after which a peer is not connected to
+pub const MISBEHAVIOR_AVOID_THRESHOLD: usize = 3;
+
+const KEY_PREFIX: &str = "peer:";
+
+const PROPAGATION_KEY: &str = "propagation";
+
+/// One time a peer broke the rules
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MisbehaviorEvent {
+    /// Unix time
+    pub at: u64,
+    pub reason: String,
+}
+
+/// Everything remembered about one peer host
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    pub host: String,
+    /// Unix time of the first record
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Handshakes completed, in either direction
+    pub connections: u64,
+    /// Outbound connections or handshakes that failed
+    pub failed_connections: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Blocks from the peer that connected to our chain
+    pub blocks_provided: u64,
+    /// Transactions from the peer we had not seen before
+    pub transactions_provided: u64,
+    pub misbehavior_total: u64,
+    /// The most recent misbehavior, oldest first
+    pub misbehavior: Vec<MisbehaviorEvent>,
+}
+
+impl PeerRecord {
+    pub fn new(host: &str, now: u64) -> Self {
+        PeerRecord {
+            host: host.to_string(),
+            first_seen: now,
+            last_seen: now,
+            connections: 0,
+            failed_connections: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            blocks_provided: 0,
+            transactions_provided: 0,
+            misbehavior_total: 0,
+            misbehavior: Vec::new(),
+        }
+    }
+
+    /// Misbehavior events within `MISBEHAVIOR_WINDOW` of `now`
+    pub fn recent_misbehavior(&self, now: u64) -> usize {
+        self.misbehavior.iter()
+            .filter(|event| now.saturating_sub(event.at) < MISBEHAVIOR_WINDOW)
+            .count()
+    }
+
+    /// Whether the peer misbehaved too often lately to connect to
+    pub fn is_avoided(&self, now: u64) -> bool {
+        self.recent_misbehavior(now) >= MISBEHAVIOR_AVOID_THRESHOLD
+    }
+
+    /// Score between 0 and 1: the share of connection attempts that worked,
+    /// counting one success and one failure for peers we know little about,
+    /// divided down by recent misbehavior. A peer never seen scores 0.5.
+    pub fn reliability(&self, now: u64) -> f64 {
+        let attempts = self.connections + self.failed_connections;
+        let success = (self.connections as f64 + 1.0) / (attempts as f64 + 2.0);
+        success / (1.0 + self.recent_misbehavior(now) as f64)
+    }
+}
+
+/// Peer records in a RocksDB database
+pub struct PeerStatsDb {
+    db: Database,
+    /// Serializes read-modify-write updates from connection threads
+    update_lock: Mutex<()>,
+    /// Traffic counts per socket address already added to the records
+    recorded_traffic: Mutex<HashMap<String, TrafficCounts>>,
+}
+
+impl PeerStatsDb {
+    pub fn path_in(data_dir: &str) -> PathBuf {
+        Path::new(data_dir).join(PEER_STATS_DIR)
+    }
+
+    /// Open the database in `data_dir`, creating it if needed. Propagation
+    /// records still in the file they used to be kept in are moved into it.
+    pub fn open(data_dir: &str) -> Result<Self, String> {
+        let db = Self::open_path(Self::path_in(data_dir))?;
+        let legacy = PropagationTracker::path_in(data_dir);
+        if legacy.exists() {
+            if db.db.get(PROPAGATION_KEY).map_err(|e| format!("Database error: {}", e))?.is_none() {
+                db.save_propagation(&PropagationTracker::load_from_file(&legacy)?)?;
+            }
+            std::fs::remove_file(&legacy)
+                .map_err(|e| format!("Failed to remove {}: {}", legacy.display(), e))?;
+        }
+        Ok(db)
+    }
+
+    pub fn open_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let db = Database::new_with_path(path)
+            .map_err(|e| format!("Failed to open peer stats database: {}", e))?;
+        Ok(PeerStatsDb {
+            db,
+            update_lock: Mutex::new(()),
+            recorded_traffic: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Record for `host`, or None if we never met it
+    pub fn get(&self, host: &str) -> Result<Option<PeerRecord>, String> {
+        match self.db.get(&Self::key(host)).map_err(|e| format!("Database error: {}", e))? {
+            Some(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(|e| format!("Invalid peer record for {}: {}", host, e)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every record, most reliable first
+    pub fn all(&self, now: u64) -> Result<Vec<PeerRecord>, String> {
+        let mut records = Vec::new();
+        for (key, data) in self.db.entries_with_prefix(KEY_PREFIX).map_err(|e| format!("Database error: {}", e))? {
+            let record: PeerRecord = serde_json::from_slice(&data)
+                .map_err(|e| format!("Invalid peer record {}: {}", key, e))?;
+            records.push(record);
+        }
+        records.sort_by(|a, b| b.reliability(now).total_cmp(&a.reliability(now)).then_with(|| a.host.cmp(&b.host)));
+        Ok(records)
+    }
+
+    /// Propagation records of the blocks this node mined
+    pub fn propagation(&self) -> Result<PropagationTracker, String> {
+        match self.db.get(PROPAGATION_KEY).map_err(|e| format!("Database error: {}", e))? {
+            Some(data) => serde_json::from_slice(&data)
+                .map_err(|e| format!("Invalid propagation records: {}", e)),
+            None => Ok(PropagationTracker::new()),
+        }
+    }
+
+    pub fn save_propagation(&self, tracker: &PropagationTracker) -> Result<(), String> {
+        let data = serde_json::to_vec(tracker)
+            .map_err(|e| format!("Failed to serialize propagation records: {}", e))?;
+        self.db.put(PROPAGATION_KEY.to_string(), data).map_err(|e| format!("Database error: {}", e))
+    }
+
+    /// A completed handshake, or an outbound attempt that failed
+    pub fn record_connection(&self, host: &str, succeeded: bool, now: u64) -> Result<(), String> {
+        self.update(host, now, |record| match succeeded {
+            true => record.connections += 1,
+            false => record.failed_connections += 1,
+        })
+    }
+
+    pub fn record_blocks(&self, host: &str, count: u64, now: u64) -> Result<(), String> {
+        self.update(host, now, |record| record.blocks_provided += count)
+    }
+
+    pub fn record_transaction(&self, host: &str, now: u64) -> Result<(), String> {
+        self.update(host, now, |record| record.transactions_provided += 1)
+    }
+
+    pub fn record_misbehavior(&self, host: &str, reason: &str, now: u64) -> Result<(), String> {
+        self.update(host, now, |record| {
+            record.misbehavior_total += 1;
+            record.misbehavior.push(MisbehaviorEvent { at: now, reason: reason.to_string() });
+            if record.misbehavior.len() > MAX_MISBEHAVIOR_EVENTS {
+                let excess = record.misbehavior.len() - MAX_MISBEHAVIOR_EVENTS;
+                record.misbehavior.drain(..excess);
+            }
+        })
+    }
+
+    /// Add the traffic counted per socket address since the last call. Counts
+    /// only grow, so the difference is what is new.
+    pub fn record_traffic(&self, peers: &BTreeMap<String, TrafficCounts>, now: u64) -> Result<(), String> {
+        let mut recorded = self.recorded_traffic.lock().unwrap();
+        for (peer, counts) in peers {
+            let previous = recorded.get(peer).copied().unwrap_or_default();
+            let sent = counts.bytes_sent.saturating_sub(previous.bytes_sent);
+            let received = counts.bytes_received.saturating_sub(previous.bytes_received);
+            if sent == 0 && received == 0 {
+                continue;
+            }
+            let host = peer.parse::<SocketAddr>().map(|addr| addr.ip().to_string()).unwrap_or_else(|_| peer.clone());
+            self.update(&host, now, |record| {
+                record.bytes_sent += sent;
+                record.bytes_received += received;
+            })?;
+            recorded.insert(peer.clone(), *counts);
+        }
+        Ok(())
+    }
+
+    /// Order `candidates` most reliable first, leaving out peers that misbehaved
+    /// too often lately. Peers we never met rank as average.
+    pub fn rank(&self, candidates: Vec<PeerAddress>, now: u64) -> Result<Vec<PeerAddress>, String> {
+        let mut scored = Vec::new();
+        for candidate in candidates {
+            let score = match self.get(&candidate.host())? {
+                Some(record) if record.is_avoided(now) => continue,
+                Some(record) => record.reliability(now),
+                None => PeerRecord::new("", now).reliability(now),
+            };
+            scored.push((score, candidate));
+        }
+        // Stable, so equally reliable peers keep their order
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored.into_iter().map(|(_, candidate)| candidate).collect())
+    }
+
+    fn update<F: FnOnce(&mut PeerRecord)>(&self, host: &str, now: u64, change: F) -> Result<(), String> {
+        let _guard = self.update_lock.lock().unwrap();
+        let mut record = self.get(host)?.unwrap_or_else(|| PeerRecord::new(host, now));
+        record.last_seen = record.last_seen.max(now);
+        change(&mut record);
+        let data = serde_json::to_vec(&record)
+            .map_err(|e| format!("Failed to serialize peer record: {}", e))?;
+        self.db.put(Self::key(host), data).map_err(|e| format!("Database error: {}", e))
+    }
+
+    fn key(host: &str) -> String {
+        format!("{}{}", KEY_PREFIX, host)
+    }
+}
+
+/// Add the server's traffic to the peer records every `interval` until the server stops
+pub fn write_peer_traffic(server: Arc<NetworkServer>, interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if !server.is_running() {
+            return;
+        }
+        server.save_peer_traffic();
+    })
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> (PeerStatsDb, PathBuf) {
+        let path = std::env::temp_dir().join(format!("rust_chain_peer_stats_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        (PeerStatsDb::open_path(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn test_peer_records_persist() {
+        let (db, path) = temp_db("persist");
+        db.record_connection("10.0.0.1", true, 100).unwrap();
+        db.record_connection("10.0.0.1", false, 150).unwrap();
+        db.record_blocks("10.0.0.1", 5, 200).unwrap();
+        db.record_transaction("10.0.0.1", 200).unwrap();
+
+        let mut traffic = BTreeMap::new();
+        traffic.insert("10.0.0.1:50312".to_string(), TrafficCounts { bytes_sent: 100, bytes_received: 300, ..Default::default() });
+        db.record_traffic(&traffic, 210).unwrap();
+        // Only the growth since the last call is added
+        traffic.insert("10.0.0.1:50312".to_string(), TrafficCounts { bytes_sent: 150, bytes_received: 300, ..Default::default() });
+        db.record_traffic(&traffic, 220).unwrap();
+        drop(db);
+
+        let db = PeerStatsDb::open_path(&path).unwrap();
+        let record = db.get("10.0.0.1").unwrap().unwrap();
+        assert_eq!((record.first_seen, record.last_seen), (100, 220));
+        assert_eq!((record.connections, record.failed_connections), (1, 1));
+        assert_eq!((record.bytes_sent, record.bytes_received), (150, 300));
+        assert_eq!((record.blocks_provided, record.transactions_provided), (5, 1));
+        assert_eq!(db.get("10.0.0.2").unwrap(), None);
+        drop(db);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_propagation_records_persist() {
+        let dir = std::env::temp_dir().join(format!("rust_chain_peer_stats_propagation_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_dir = dir.to_string_lossy().into_owned();
+
+        // Records from the old file are moved into the database
+        let legacy = PropagationTracker::path_in(&dir);
+        std::fs::write(&legacy, r#"{"blocks":[{"hash":"aa","height":1,"mined_at":1000,"first_ack_at":null,"peers_relayed":0,"peers_acked":0}]}"#).unwrap();
+        let db = PeerStatsDb::open(&data_dir).unwrap();
+        assert!(!legacy.exists());
+        let mut tracker = db.propagation().unwrap();
+        assert_eq!(tracker.blocks().len(), 1);
+
+        tracker.record_relay("aa", true, 1_200);
+        db.save_propagation(&tracker).unwrap();
+        drop(db);
+
+        let db = PeerStatsDb::open(&data_dir).unwrap();
+        let reloaded = db.propagation().unwrap();
+        assert_eq!(reloaded.blocks(), tracker.blocks());
+        assert_eq!(reloaded.get("aa").unwrap().first_ack_ms(), Some(200));
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rank_prefers_reliable_peers() {
+        let (db, path) = temp_db("rank");
+        let now = 10 * MISBEHAVIOR_WINDOW;
+        for _ in 0..4 {
+            db.record_connection("10.0.0.1", true, now).unwrap();
+            db.record_connection("10.0.0.2", false, now).unwrap();
+        }
+        for _ in 0..MISBEHAVIOR_AVOID_THRESHOLD {
+            db.record_connection("10.0.0.3", true, now).unwrap();
+            db.record_misbehavior("10.0.0.3", "invalid block", now - 60).unwrap();
+        }
+
+        let candidates: Vec<PeerAddress> = ["10.0.0.2:8333", "10.0.0.3:8333", "10.0.0.9:8333", "10.0.0.1:8333"]
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect();
+        let ranked: Vec<String> = db.rank(candidates.clone(), now).unwrap().iter().map(|address| address.to_string()).collect();
+        assert_eq!(ranked, vec!["10.0.0.1:8333", "10.0.0.9:8333", "10.0.0.2:8333"]);
+
+        // Misbehavior stops counting once it is old
+        let later = now + MISBEHAVIOR_WINDOW;
+        assert_eq!(db.rank(candidates, later).unwrap().len(), 4);
+        let record = db.get("10.0.0.3").unwrap().unwrap();
+        assert_eq!(record.misbehavior_total, MISBEHAVIOR_AVOID_THRESHOLD as u64);
+        assert_eq!(db.all(now).unwrap()[0].host, "10.0.0.1");
+
+        for _ in 0..MAX_MISBEHAVIOR_EVENTS {
+            db.record_misbehavior("10.0.0.3", "invalid block", now).unwrap();
+        }
+        let record = db.get("10.0.0.3").unwrap().unwrap();
+        assert_eq!(record.misbehavior.len(), MAX_MISBEHAVIOR_EVENTS);
+        assert_eq!(record.misbehavior_total, (MAX_MISBEHAVIOR_EVENTS + MISBEHAVIOR_AVOID_THRESHOLD) as u64);
+        drop(db);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
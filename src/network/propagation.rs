@@ -0,0 +1,173 @@
+//! Propagation tracking for blocks mined by this node
+//!
+//! Records when each of our blocks was mined, how many peers it was announced
+//! to and when the first of them answered. The records are kept in the peer
+//! stats database. Orphan status is not stored; it is worked out against the
+//! current chain, so reorgs are reflected automatically.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::chain::Chain;
+
+/// File in the data directory that held propagation records before they moved
+/// to the peer stats database, which imports it when opened
+pub const PROPAGATION_FILE: &str = "propagation.json";
+
+/// Oldest records are dropped beyond this many blocks
+pub const MAX_TRACKED_BLOCKS: usize = 1000;
+
+/// Current time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+/// Propagation data for one mined block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockPropagation {
+    pub hash: String,
+    pub height: u64,
+    /// When the block was mined (ms since the epoch)
+    pub mined_at: u64,
+    /// When the first peer answered its announcement (ms since the epoch)
+    pub first_ack_at: Option<u64>,
+    /// Peers the block was announced to
+    pub peers_relayed: usize,
+    /// Peers that answered the announcement
+    pub peers_acked: usize,
+}
+
+impl BlockPropagation {
+    /// Milliseconds from mining to the first peer acknowledgment
+    pub fn first_ack_ms(&self) -> Option<u64> {
+        self.first_ack_at.map(|at| at.saturating_sub(self.mined_at))
+    }
+}
+
+/// Summary over all tracked blocks
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PropagationStats {
+    pub blocks_mined: usize,
+    /// Blocks announced to at least one peer
+    pub blocks_relayed: usize,
+    /// Blocks at least one peer answered for
+    pub blocks_acknowledged: usize,
+    pub avg_first_ack_ms: Option<f64>,
+    pub max_first_ack_ms: Option<u64>,
+    /// Average number of peers each relayed block was announced to
+    pub avg_peers_relayed: f64,
+    /// Mined blocks no longer on the best chain
+    pub orphaned: usize,
+    /// Orphaned blocks as a fraction of mined blocks
+    pub orphan_rate: f64,
+}
+
+/// Propagation records for blocks mined by this node
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PropagationTracker {
+    blocks: Vec<BlockPropagation>,
+}
+
+impl PropagationTracker {
+    pub fn new() -> Self {
+        PropagationTracker::default()
+    }
+
+    /// Path of the propagation file inside a data directory
+    pub fn path_in<P: AsRef<Path>>(data_dir: P) -> PathBuf {
+        data_dir.as_ref().join(PROPAGATION_FILE)
+    }
+
+    /// Load records, starting empty when the file does not exist yet
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        if !path.as_ref().exists() {
+            return Ok(PropagationTracker::new());
+        }
+        let data = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read propagation data: {}", e))?;
+        serde_json::from_str(&data)
+            .map_err(|e| format!("Failed to parse propagation data: {}", e))
+    }
+
+    /// Start tracking a block we just mined
+    pub fn record_mined(&mut self, hash: &str, height: u64, mined_at: u64) {
+        if self.get(hash).is_some() {
+            return;
+        }
+        self.blocks.push(BlockPropagation {
+            hash: hash.to_string(),
+            height,
+            mined_at,
+            first_ack_at: None,
+            peers_relayed: 0,
+            peers_acked: 0,
+        });
+        if self.blocks.len() > MAX_TRACKED_BLOCKS {
+            self.blocks.remove(0);
+        }
+    }
+
+    /// Record an announcement to one peer. Returns false for blocks we did not mine.
+    pub fn record_relay(&mut self, hash: &str, acknowledged: bool, at: u64) -> bool {
+        let Some(block) = self.blocks.iter_mut().find(|block| block.hash == hash) else {
+            return false;
+        };
+
+        block.peers_relayed += 1;
+        if acknowledged {
+            block.peers_acked += 1;
+            block.first_ack_at.get_or_insert(at);
+        }
+        true
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&BlockPropagation> {
+        self.blocks.iter().find(|block| block.hash == hash)
+    }
+
+    /// Tracked blocks, oldest first
+    pub fn blocks(&self) -> &[BlockPropagation] {
+        &self.blocks
+    }
+
+    /// Whether a mined block has been replaced on the chain by a reorg.
+    /// Blocks above the chain tip are not counted until the chain catches up.
+    pub fn is_orphaned(block: &BlockPropagation, chain: &Chain) -> bool {
+        match chain.block_hash_at(block.height) {
+            Ok(Some(on_chain)) => on_chain != block.hash,
+            _ => false,
+        }
+    }
+
+    pub fn stats(&self, chain: &Chain) -> PropagationStats {
+        let relayed: Vec<_> = self.blocks.iter().filter(|block| block.peers_relayed > 0).collect();
+        let ack_times: Vec<u64> = self.blocks.iter().filter_map(|block| block.first_ack_ms()).collect();
+        let orphaned = self.blocks.iter().filter(|block| Self::is_orphaned(block, chain)).count();
+
+        PropagationStats {
+            blocks_mined: self.blocks.len(),
+            blocks_relayed: relayed.len(),
+            blocks_acknowledged: ack_times.len(),
+            avg_first_ack_ms: if ack_times.is_empty() {
+                None
+            } else {
+                Some(ack_times.iter().sum::<u64>() as f64 / ack_times.len() as f64)
+            },
+            max_first_ack_ms: ack_times.iter().copied().max(),
+            avg_peers_relayed: if relayed.is_empty() {
+                0.0
+            } else {
+                relayed.iter().map(|block| block.peers_relayed).sum::<usize>() as f64 / relayed.len() as f64
+            },
+            orphaned,
+            orphan_rate: if self.blocks.is_empty() {
+                0.0
+            } else {
+                orphaned as f64 / self.blocks.len() as f64
+            },
+        }
+    }
+}
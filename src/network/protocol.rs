@@ -1,5 +1,8 @@
 use serde::{Serialize, Deserialize};
 use crate::blockchain::block::Block;
+use crate::mempool::snapshot::SnapshotEntry;
+use crate::network::address::PeerAddress;
+use crate::network::limits;
 
 /// Simplified block header for light clients
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -10,10 +13,180 @@ pub struct BlockHeader {
     pub timestamp: u64,
     pub nonce: u64,
     pub merkle_root: String,
+    /// Compact proof of work target, for headers of blocks that carry one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bits: Option<u32>,
 }
 
-/// Network protocol version
-pub const PROTOCOL_VERSION: u32 = 1;
+impl From<&crate::blockchain::block::BlockHeader> for BlockHeader {
+    fn from(header: &crate::blockchain::block::BlockHeader) -> Self {
+        BlockHeader {
+            height: header.height,
+            hash: header.hash.clone(),
+            previous_hash: header.previous_hash.clone(),
+            timestamp: header.timestamp,
+            nonce: header.nonce,
+            merkle_root: header.merkle_root.clone(),
+            bits: header.bits,
+        }
+    }
+}
+
+/// Network protocol version (highest we speak)
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version we still accept
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional protocol features a node can advertise in its handshake
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Features(pub u64);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    /// Compact block relay
+    pub const COMPACT_BLOCKS: Features = Features(1 << 0);
+    /// Binary (non-JSON) message encoding
+    pub const BINARY_ENCODING: Features = Features(1 << 1);
+    /// Transaction relay
+    pub const TX_RELAY: Features = Features(1 << 2);
+    /// Deflate-compressed large messages (see `network::framing`)
+    pub const COMPRESSION: Features = Features(1 << 3);
+
+    /// Check that every flag in `other` is set
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Flags set on both sides
+    pub fn intersection(self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+
+    pub fn union(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+
+    /// Human-readable flag names
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (Features::COMPACT_BLOCKS, "compact-blocks"),
+            (Features::BINARY_ENCODING, "binary-encoding"),
+            (Features::TX_RELAY, "tx-relay"),
+            (Features::COMPRESSION, "compression"),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
+/// Services a node offers to its peers, advertised in the handshake
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Services(pub u64);
+
+impl Services {
+    pub const NONE: Services = Services(0);
+    /// Serves full blocks near the tip
+    pub const FULL_BLOCKS: Services = Services(1 << 0);
+    /// Relays unconfirmed transactions
+    pub const TX_RELAY: Services = Services(1 << 1);
+    /// Keeps and serves the complete block history
+    pub const ARCHIVE: Services = Services(1 << 2);
+    /// Serves block headers to light clients
+    pub const HEADERS: Services = Services(1 << 3);
+
+    /// Check that every flag in `other` is set
+    pub fn contains(self, other: Services) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn union(self, other: Services) -> Services {
+        Services(self.0 | other.0)
+    }
+
+    /// Human-readable flag names
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (Services::FULL_BLOCKS, "full-blocks"),
+            (Services::TX_RELAY, "tx-relay"),
+            (Services::ARCHIVE, "archive"),
+            (Services::HEADERS, "headers"),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
+
+/// Services this node offers by default; it keeps every block, so it is an archive node
+pub const LOCAL_SERVICES: Services = Services(
+    Services::FULL_BLOCKS.0 | Services::TX_RELAY.0 | Services::ARCHIVE.0 | Services::HEADERS.0
+);
+
+/// How far behind a peer's tip we must be before sync prefers archive peers
+pub const DEEP_HISTORY_BLOCKS: u64 = 100;
+
+/// Features this node implements
+pub const LOCAL_FEATURES: Features = Features(Features::TX_RELAY.0 | Features::COMPRESSION.0);
+
+/// Version and features agreed with a peer during the handshake
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    pub version: u32,
+    pub features: Features,
+}
+
+impl PeerCapabilities {
+    /// Negotiate with a peer's advertised version range and features.
+    /// Fails when the version ranges do not overlap.
+    pub fn negotiate(peer_min_version: u32, peer_version: u32, peer_features: Features) -> Result<Self, String> {
+        let version = negotiate_version(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, peer_min_version, peer_version)
+            .ok_or_else(|| format!(
+                "No common protocol version (ours {}-{}, peer {}-{})",
+                MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, peer_min_version, peer_version
+            ))?;
+
+        Ok(PeerCapabilities {
+            version,
+            features: LOCAL_FEATURES.intersection(peer_features),
+        })
+    }
+
+    /// Whether a message may be exchanged with this peer
+    pub fn allows(&self, message: &MessageType) -> bool {
+        match message {
+            MessageType::NewTransaction { .. } | MessageType::GetMempool | MessageType::MempoolResponse { .. } => {
+                self.features.contains(Features::TX_RELAY)
+            },
+            MessageType::Inv(items) | MessageType::GetData(items) => {
+                self.features.contains(Features::TX_RELAY)
+                    || items.iter().all(|item| item.inv_type != InventoryType::Transaction)
+            },
+            _ => true,
+        }
+    }
+}
+
+impl Default for PeerCapabilities {
+    /// Capabilities of a peer that has not negotiated anything
+    fn default() -> Self {
+        PeerCapabilities {
+            version: MIN_PROTOCOL_VERSION,
+            features: Features::NONE,
+        }
+    }
+}
+
+/// Highest version inside both `[local_min, local_max]` and `[peer_min, peer_max]`.
+/// A `peer_min` of 0 means the peer only advertised a single version.
+pub fn negotiate_version(local_min: u32, local_max: u32, peer_min: u32, peer_max: u32) -> Option<u32> {
+    let peer_min = if peer_min == 0 { peer_max } else { peer_min };
+    let version = local_max.min(peer_max);
+    (version >= local_min.max(peer_min)).then_some(version)
+}
 
 /// Magic bytes for message identification
 pub const MAGIC_BYTES: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
@@ -21,6 +194,12 @@ pub const MAGIC_BYTES: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
 /// Maximum message size (1MB)
 pub const MAX_MESSAGE_SIZE: usize = 1_048_576;
 
+/// Most blocks sent in answer to one block request
+pub const MAX_BLOCKS_PER_REQUEST: u32 = 500;
+
+/// Most headers sent in answer to one header request
+pub const MAX_HEADERS_PER_REQUEST: u32 = 2_000;
+
 /// Network message types
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MessageType {
@@ -31,9 +210,23 @@ pub enum MessageType {
     /// Request for blocks starting from a specific hash
     GetBlocks { start_hash: String, count: u32 },
     /// Response with requested blocks
-    Blocks(Vec<Block>),
+    Blocks(#[serde(deserialize_with = "limits::deserialize_blocks")] Vec<Block>),
+    /// Request for the blocks of our best chain from `start_height` on, answered
+    /// with `Blocks`. Lets sync fetch different heights from different peers.
+    GetBlockRange { start_height: u64, count: u32 },
+    /// Request for blocks after the first locator hash the responder has on its chain.
+    /// The locator lists the requester's block hashes from its tip back to genesis.
+    GetBlocksByLocator { locator: Vec<String>, count: u32 },
+    /// Blocks following the fork point, or from genesis when no locator hash
+    /// matched, and how many more blocks the responder has after them
+    LocatorBlocks {
+        fork_height: Option<u64>,
+        #[serde(deserialize_with = "limits::deserialize_blocks")]
+        blocks: Vec<Block>,
+        remaining: u64,
+    },
     /// Announce a new block
-    NewBlock(Block),
+    NewBlock(#[serde(deserialize_with = "limits::deserialize_block")] Block),
     /// Request the latest block hash and height
     GetChainInfo,
     /// Response with chain information
@@ -42,12 +235,31 @@ pub enum MessageType {
     Ping,
     /// Pong response to ping
     Pong,
-    /// Handshake message with version and node info
+    /// Handshake message with version and node info.
+    /// `version` is the highest version the sender speaks, `min_version` the lowest.
+    /// `nonce` is a fresh challenge for the receiver to sign. The opening handshake
+    /// carries an empty `signature`; the reply carries the responder's signature
+    /// over the opener's challenge. `listen_port` is where the sender accepts
+    /// connections, 0 if it does not.
     Handshake {
         version: u32,
         node_id: String,
         chain_height: u64,
+        public_key: String,
+        nonce: String,
+        signature: String,
+        #[serde(default)]
+        min_version: u32,
+        #[serde(default)]
+        features: Features,
+        #[serde(default)]
+        services: Services,
+        #[serde(default)]
+        listen_port: u16,
     },
+    /// The opener's signature over the challenge in the reply handshake, which
+    /// completes the handshake
+    HandshakeAck { signature: String },
     /// **Phase 8 - Additional Message Types**
     /// Transaction broadcast message
     NewTransaction {
@@ -56,6 +268,10 @@ pub enum MessageType {
         to_address: String,
         amount: u64,
         signature: String,
+        /// Fee per byte the sender says the transaction pays. Receivers work
+        /// the rate out themselves and never rely on this one.
+        #[serde(default)]
+        fee_per_byte: f64,
     },
     /// Request for mempool contents
     GetMempool,
@@ -63,6 +279,9 @@ pub enum MessageType {
     MempoolResponse {
         transactions: Vec<String>,
         count: usize,
+        /// Fee rate, size and entry time of each transaction, for mempool diffs
+        #[serde(default)]
+        entries: Vec<SnapshotEntry>,
     },
     /// Request for specific transaction by hash
     GetTransaction { tx_hash: String },
@@ -92,6 +311,16 @@ pub enum MessageType {
         transactions_processed: u64,
         bandwidth_in: u64,
         bandwidth_out: u64,
+        /// Resident memory of the node process in bytes
+        #[serde(default)]
+        memory_usage: u64,
+        /// Average CPU use since startup, as a percentage of one core
+        #[serde(default)]
+        cpu_usage: f64,
+        #[serde(default)]
+        messages_in: u64,
+        #[serde(default)]
+        messages_out: u64,
     },
     /// Peer quality report
     PeerReport {
@@ -132,6 +361,73 @@ pub enum MessageType {
         addresses: Vec<PeerInfo>,
         timestamp: u64,
     },
+    /// Announce blocks/transactions by hash before sending full payloads
+    Inv(Vec<InventoryItem>),
+    /// Request full payloads for announced items we have not seen
+    GetData(Vec<InventoryItem>),
+}
+
+impl MessageType {
+    /// Name of the variant, used to count messages by type
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageType::GetPeers => "GetPeers",
+            MessageType::Peers(_) => "Peers",
+            MessageType::GetBlocks { .. } => "GetBlocks",
+            MessageType::Blocks(_) => "Blocks",
+            MessageType::GetBlockRange { .. } => "GetBlockRange",
+            MessageType::GetBlocksByLocator { .. } => "GetBlocksByLocator",
+            MessageType::LocatorBlocks { .. } => "LocatorBlocks",
+            MessageType::NewBlock(_) => "NewBlock",
+            MessageType::GetChainInfo => "GetChainInfo",
+            MessageType::ChainInfo { .. } => "ChainInfo",
+            MessageType::Ping => "Ping",
+            MessageType::Pong => "Pong",
+            MessageType::Handshake { .. } => "Handshake",
+            MessageType::HandshakeAck { .. } => "HandshakeAck",
+            MessageType::NewTransaction { .. } => "NewTransaction",
+            MessageType::GetMempool => "GetMempool",
+            MessageType::MempoolResponse { .. } => "MempoolResponse",
+            MessageType::GetTransaction { .. } => "GetTransaction",
+            MessageType::TransactionResponse { .. } => "TransactionResponse",
+            MessageType::NodeStatus { .. } => "NodeStatus",
+            MessageType::GetNodeStats => "GetNodeStats",
+            MessageType::NodeStatsResponse { .. } => "NodeStatsResponse",
+            MessageType::PeerReport { .. } => "PeerReport",
+            MessageType::SyncRequest { .. } => "SyncRequest",
+            MessageType::SyncResponse { .. } => "SyncResponse",
+            MessageType::BlockHeaders { .. } => "BlockHeaders",
+            MessageType::GetBlockHeaders { .. } => "GetBlockHeaders",
+            MessageType::VersionNegotiation { .. } => "VersionNegotiation",
+            MessageType::AddressBook { .. } => "AddressBook",
+            MessageType::Inv(_) => "Inv",
+            MessageType::GetData(_) => "GetData",
+        }
+    }
+}
+
+/// Kind of object referenced by an inventory item
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum InventoryType {
+    Block,
+    Transaction,
+}
+
+/// Hash reference to a block or transaction
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InventoryItem {
+    pub inv_type: InventoryType,
+    pub hash: String,
+}
+
+impl InventoryItem {
+    pub fn block(hash: String) -> Self {
+        InventoryItem { inv_type: InventoryType::Block, hash }
+    }
+
+    pub fn transaction(hash: String) -> Self {
+        InventoryItem { inv_type: InventoryType::Transaction, hash }
+    }
 }
 
 /// Peer information
@@ -142,6 +438,32 @@ pub struct PeerInfo {
     pub node_id: String,
     pub last_seen: u64,
     pub chain_height: u64,
+    /// Negotiated during the handshake
+    #[serde(default)]
+    pub capabilities: PeerCapabilities,
+    /// Services the peer advertised in its handshake
+    #[serde(default)]
+    pub services: Services,
+    /// Port the peer accepts connections on, which for a peer that connected to
+    /// us differs from `port`, the source port of its connection. 0 if unknown.
+    #[serde(default)]
+    pub listen_port: u16,
+}
+
+impl PeerInfo {
+    /// Where the connection to the peer goes; `address` may be an IPv4 or IPv6
+    /// address or a hostname
+    pub fn peer_address(&self) -> Result<PeerAddress, NetworkError> {
+        PeerAddress::new(&self.address, self.port).map_err(NetworkError::InvalidMessage)
+    }
+
+    /// Where the peer accepts new connections, as it advertised in its handshake
+    pub fn listen_address(&self) -> Result<PeerAddress, NetworkError> {
+        if self.listen_port == 0 {
+            return Err(NetworkError::ConnectionFailed(format!("Peer {} does not accept connections", self.address)));
+        }
+        PeerAddress::new(&self.address, self.listen_port).map_err(NetworkError::InvalidMessage)
+    }
 }
 
 /// Complete network message with header
@@ -177,11 +499,13 @@ impl NetworkMessage {
             .map_err(|e| format!("Failed to serialize message: {}", e))
     }
     
-    /// Deserialize message from bytes
+    /// Deserialize message from bytes, holding it to the size and count limits
+    /// of its type (see `network::limits`)
     pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
         if data.len() > MAX_MESSAGE_SIZE {
             return Err("Message too large".to_string());
         }
+        limits::check_message_size(data)?;
         
         serde_json::from_slice(data)
             .map_err(|e| format!("Failed to deserialize message: {}", e))
@@ -214,6 +538,8 @@ pub enum NetworkError {
     ProtocolError(String),
     Timeout,
     PeerDisconnected,
+    /// The peer took too long to deliver a message it had started sending
+    SlowPeer(String),
 }
 
 impl std::fmt::Display for NetworkError {
@@ -224,6 +550,7 @@ impl std::fmt::Display for NetworkError {
             NetworkError::ProtocolError(msg) => write!(f, "Protocol error: {}", msg),
             NetworkError::Timeout => write!(f, "Connection timeout"),
             NetworkError::PeerDisconnected => write!(f, "Peer disconnected"),
+            NetworkError::SlowPeer(msg) => write!(f, "Slow peer: {}", msg),
         }
     }
 }
@@ -249,8 +576,9 @@ impl NetworkMessage {
             MessageType::NewTransaction { .. } => MessagePriority::High,
             MessageType::GetChainInfo | MessageType::ChainInfo { .. } => MessagePriority::High,
             MessageType::SyncRequest { .. } | MessageType::SyncResponse { .. } => MessagePriority::High,
-            MessageType::Handshake { .. } => MessagePriority::Critical,
+            MessageType::Handshake { .. } | MessageType::HandshakeAck { .. } => MessagePriority::Critical,
             MessageType::NodeStatus { .. } => MessagePriority::Normal,
+            MessageType::Inv(_) | MessageType::GetData(_) => MessagePriority::High,
             _ => MessagePriority::Normal,
         }
     }
@@ -261,12 +589,15 @@ impl NetworkMessage {
             &self.message_type,
             MessageType::GetPeers
                 | MessageType::GetBlocks { .. }
+                | MessageType::GetBlockRange { .. }
+                | MessageType::GetBlocksByLocator { .. }
                 | MessageType::GetChainInfo
                 | MessageType::GetMempool
                 | MessageType::GetTransaction { .. }
                 | MessageType::GetNodeStats
                 | MessageType::SyncRequest { .. }
                 | MessageType::GetBlockHeaders { .. }
+                | MessageType::GetData(_)
                 | MessageType::Ping
         )
     }
@@ -275,10 +606,13 @@ impl NetworkMessage {
     pub fn estimated_size(&self) -> usize {
         match &self.message_type {
             MessageType::Blocks(blocks) => blocks.len() * 1000, // Rough estimate
+            MessageType::LocatorBlocks { blocks, .. } => blocks.len() * 1000,
+            MessageType::GetBlocksByLocator { locator, .. } => locator.len() * 70,
             MessageType::Peers(peers) => peers.len() * 100,
             MessageType::BlockHeaders { headers, .. } => headers.len() * 200,
             MessageType::AddressBook { addresses, .. } => addresses.len() * 100,
             MessageType::MempoolResponse { transactions, .. } => transactions.len() * 500,
+            MessageType::Inv(items) | MessageType::GetData(items) => items.len() * 80,
             _ => 200, // Base message size
         }
     }
@@ -306,11 +640,21 @@ pub enum NodeType {
 }
 
 impl NodeType {
+    /// Services a node of this type advertises
+    pub fn services(&self) -> Services {
+        match self {
+            NodeType::FullNode => Services::FULL_BLOCKS.union(Services::TX_RELAY).union(Services::HEADERS),
+            NodeType::LightClient => Services::NONE,
+            NodeType::MiningNode => Services::FULL_BLOCKS.union(Services::TX_RELAY),
+            NodeType::ArchiveNode => LOCAL_SERVICES,
+        }
+    }
+
     /// Check if this node type should handle a specific message
     pub fn should_handle(&self, message: &MessageType) -> bool {
         match (self, message) {
             // All nodes handle basic protocol messages
-            (_, MessageType::Ping | MessageType::Pong | MessageType::Handshake { .. }) => true,
+            (_, MessageType::Ping | MessageType::Pong | MessageType::Handshake { .. } | MessageType::HandshakeAck { .. }) => true,
             
             // Full nodes handle everything
             (NodeType::FullNode, _) => true,
@@ -323,12 +667,16 @@ impl NodeType {
             // Mining nodes prioritize new blocks and transactions
             (NodeType::MiningNode, MessageType::NewBlock(_)) => true,
             (NodeType::MiningNode, MessageType::NewTransaction { .. }) => true,
+            (NodeType::MiningNode, MessageType::Inv(_) | MessageType::GetData(_)) => true,
             (NodeType::MiningNode, MessageType::GetMempool) => true,
             (NodeType::MiningNode, MessageType::MempoolResponse { .. }) => true,
             
             // Archive nodes handle historical data requests
             (NodeType::ArchiveNode, MessageType::GetBlocks { .. }) => true,
             (NodeType::ArchiveNode, MessageType::Blocks(_)) => true,
+            (NodeType::ArchiveNode, MessageType::GetBlockRange { .. }) => true,
+            (NodeType::ArchiveNode, MessageType::GetBlocksByLocator { .. }) => true,
+            (NodeType::ArchiveNode, MessageType::LocatorBlocks { .. }) => true,
             (NodeType::ArchiveNode, MessageType::GetTransaction { .. }) => true,
             
             _ => false,
@@ -1,50 +1,528 @@
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream, SocketAddr};
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::thread;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
 
 use crate::blockchain::chain::Chain;
-use crate::blockchain::block::Block;
+use crate::blockchain::block::{Block, Transaction};
+use crate::alerts::{AlertConfig, AlertLog, AlertMonitor};
+use crate::blockchain::events::{ChainEvent, EventBus};
+use crate::clock::{self, SharedClock};
+use crate::config::NodeConfig;
+use crate::consensus::checkpoints::Checkpoints;
+use crate::consensus::fork_choice::{check_reorg_allowed, DEFAULT_FINALITY_DEPTH};
+use crate::consensus::pow::ProofOfWork;
+use crate::network::address::PeerAddress;
+use crate::network::download::{DownloadConfig, DownloadScheduler, WindowRequest};
+use crate::network::framing::{compress_frame, encode_frame, FrameReader, FramingConfig};
+use crate::network::height_poll::DEFAULT_SYNC_THRESHOLD;
+use crate::network::dialer::DEFAULT_OUTBOUND_TARGET;
+use crate::network::identity::{HandshakeRole, NodeIdentity, generate_nonce};
+use crate::mempool::policy::{paid_fee_per_byte, RelayPolicy};
+use crate::mempool::pool::Mempool;
+use crate::mempool::snapshot::{MempoolSnapshot, SnapshotEntry};
+use crate::network::DEFAULT_MAX_PEERS;
+use crate::network::inventory::{BlockClaim, InventoryRelay, SeenInventory, INV_BATCH_INTERVAL};
+use crate::network::keepalive::{KeepaliveAction, KeepaliveScheduler};
+use crate::network::peer_stats::{unix_now, PeerStatsDb};
+use crate::network::propagation::{now_millis, PropagationTracker};
+use crate::network::traffic::{NodeStats, TrafficMonitor};
+use crate::perf;
+use crate::progress::Progress;
+use crate::{detail, status};
 use crate::network::protocol::{
-    NetworkMessage, MessageType, MessageResult, NetworkError, PeerInfo, PROTOCOL_VERSION
+    BlockHeader, NetworkMessage, MessageType, MessageResult, NetworkError, PeerInfo, InventoryItem, InventoryType, Features,
+    PeerCapabilities, Services, LOCAL_FEATURES, LOCAL_SERVICES, DEEP_HISTORY_BLOCKS, MAX_BLOCKS_PER_REQUEST,
+    MAX_HEADERS_PER_REQUEST, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
 };
 
+/// Blocks requested per round trip while syncing
+const SYNC_BATCH_SIZE: u32 = 100;
+
+/// Longest peer fork we download to compare against our own branch
+const MAX_FORK_SYNC_BLOCKS: usize = 2000;
+
+/// Fewest peers ahead of us for sync to download from several at once
+const MIN_PARALLEL_SYNC_PEERS: usize = 2;
+
+/// How often a parallel download checks for finished windows and timeouts
+const DOWNLOAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What `NetworkServer::add_node` does with an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddNodeCommand {
+    /// Connect now and keep reconnecting while the node runs
+    Add,
+    /// Stop reconnecting; an open connection is kept
+    Remove,
+    /// Connect once without adding the node
+    OneTry,
+}
+
+impl FromStr for AddNodeCommand {
+    type Err = String;
+
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        match command {
+            "add" => Ok(AddNodeCommand::Add),
+            "remove" => Ok(AddNodeCommand::Remove),
+            "onetry" => Ok(AddNodeCommand::OneTry),
+            other => Err(format!("Unknown addnode command '{}' (expected add, remove or onetry)", other)),
+        }
+    }
+}
+
+/// Why a peer management request could not be carried out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerControlError {
+    AlreadyAdded(PeerAddress),
+    NotAdded(PeerAddress),
+    /// No connected peer has this address or node id
+    NotConnected(String),
+    ConnectFailed(PeerAddress, String),
+}
+
+impl fmt::Display for PeerControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerControlError::AlreadyAdded(address) => write!(f, "Node {} has already been added", address),
+            PeerControlError::NotAdded(address) => write!(f, "Node {} has not been added", address),
+            PeerControlError::NotConnected(address) => write!(f, "No connected peer matches {}", address),
+            PeerControlError::ConnectFailed(address, e) => write!(f, "Failed to connect to {}: {}", address, e),
+        }
+    }
+}
+
+/// Why a block from a peer was not connected
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BlockRejection {
+    /// Breaks a rule; the block is refused whenever it is offered again
+    Invalid(String),
+    /// The body does not match its hash, so the real block under that hash may
+    /// still be valid
+    Corrupt(String),
+    /// Does not extend our chain as it is now
+    Unconnected(String),
+}
+
+impl BlockRejection {
+    /// Whether the block itself is known to be invalid, rather than this copy
+    /// of it or our chain being the problem
+    fn is_permanent(&self) -> bool {
+        matches!(self, BlockRejection::Invalid(_))
+    }
+
+    /// Whether the peer sent something it should not have. A block that does
+    /// not extend our chain is routine while chains race and is not held
+    /// against the peer.
+    fn is_misbehavior(&self) -> bool {
+        !matches!(self, BlockRejection::Unconnected(_))
+    }
+}
+
+impl fmt::Display for BlockRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockRejection::Invalid(reason) | BlockRejection::Corrupt(reason) | BlockRejection::Unconnected(reason) => {
+                write!(f, "{}", reason)
+            },
+        }
+    }
+}
+
+/// State kept for one inbound connection
+struct PeerConnection {
+    addr: SocketAddr,
+    /// Set once the peer's handshake has been negotiated
+    capabilities: Option<PeerCapabilities>,
+    /// Services we advertise in our handshake reply
+    local_services: Services,
+    /// Port we advertise in our handshake reply
+    listen_port: u16,
+    /// Rules for transactions this peer sends us
+    relay_policy: Arc<RwLock<RelayPolicy>>,
+    /// Where blocks connected from this peer are announced
+    events: EventBus,
+    /// Checks blocks from this peer for future timestamps and invalid block spam
+    alerts: AlertMonitor,
+    /// Announcements queued for the other peers
+    relay: Arc<Mutex<InventoryRelay>>,
+    /// The node's pending transactions: those peers send us and the ones
+    /// reported to peers that ask for our mempool
+    mempool: Arc<Mutex<Mempool>>,
+    /// Counts messages exchanged with every peer
+    traffic: Arc<Mutex<TrafficMonitor>>,
+    /// Long-term records of every peer, if the node keeps them
+    peer_stats: Option<Arc<PeerStatsDb>>,
+    /// Time peers are last seen at
+    clock: SharedClock,
+    /// Set once we answered the peer's handshake, until it signs our challenge
+    pending_handshake: Option<PendingHandshake>,
+}
+
+/// A peer whose handshake we answered, waiting for its signature over our challenge
+struct PendingHandshake {
+    challenge: String,
+    public_key: String,
+    peer: PeerInfo,
+}
+
+impl PeerConnection {
+    /// The part of `items` to announce to this peer: nothing before the handshake,
+    /// and only blocks if it does not relay transactions
+    fn announceable(&self, mut items: Vec<InventoryItem>) -> Vec<InventoryItem> {
+        match self.capabilities {
+            Some(capabilities) if capabilities.features.contains(Features::TX_RELAY) => items,
+            Some(_) => {
+                items.retain(|item| item.inv_type != InventoryType::Transaction);
+                items
+            },
+            None => Vec::new(),
+        }
+    }
+    
+    /// A connection from `addr` that has not handshaken yet
+    fn new(addr: SocketAddr, context: &ConnectionContext) -> Self {
+        PeerConnection {
+            addr,
+            capabilities: None,
+            local_services: context.services,
+            listen_port: context.listen_port(),
+            relay_policy: Arc::clone(&context.relay_policy),
+            events: context.events.clone(),
+            alerts: context.alerts.clone(),
+            relay: Arc::clone(&context.relay),
+            mempool: Arc::clone(&context.mempool),
+            traffic: Arc::clone(&context.traffic),
+            peer_stats: context.peer_stats.clone(),
+            clock: Arc::clone(&context.clock),
+            pending_handshake: None,
+        }
+    }
+}
+
+/// An inbound connection driven without a socket, for the conformance vectors
+/// and the fuzzer: messages get the same checks and handling as ones read from a peer
+pub(crate) struct OfflineConnection {
+    context: ConnectionContext,
+    connection: PeerConnection,
+}
+
+impl OfflineConnection {
+    /// Handle one message as `run_connection` would after reading it
+    pub(crate) fn deliver(&mut self, message: NetworkMessage) -> MessageResult {
+        if !message.validate() {
+            return MessageResult::Error("Invalid message format".to_string());
+        }
+        let context = &self.context;
+        NetworkServer::handle_message(message, &context.chain, &context.peers, &context.identity, &mut self.connection, &context.checkpoints, &context.seen)
+    }
+
+    /// What the peer's handshake negotiated, once it completed one
+    pub(crate) fn capabilities(&self) -> Option<PeerCapabilities> {
+        self.connection.capabilities
+    }
+}
+
+/// Shared server state handed to each connection thread
+#[derive(Clone)]
+struct ConnectionContext {
+    chain: Arc<Mutex<Chain>>,
+    peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
+    identity: Arc<NodeIdentity>,
+    checkpoints: Arc<Checkpoints>,
+    seen: Arc<Mutex<SeenInventory>>,
+    services: Services,
+    relay_policy: Arc<RwLock<RelayPolicy>>,
+    keepalive: Arc<Mutex<KeepaliveScheduler>>,
+    framing: FramingConfig,
+    events: EventBus,
+    alerts: AlertMonitor,
+    relay: Arc<Mutex<InventoryRelay>>,
+    mempool: Arc<Mutex<Mempool>>,
+    traffic: Arc<Mutex<TrafficMonitor>>,
+    peer_stats: Option<Arc<PeerStatsDb>>,
+    clock: SharedClock,
+    disconnect_requests: Arc<Mutex<HashSet<SocketAddr>>>,
+    announcements: Arc<Mutex<HashMap<SocketAddr, Vec<InventoryItem>>>>,
+    local_addr: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl ConnectionContext {
+    /// Port the listener is bound to, 0 until it is
+    fn listen_port(&self) -> u16 {
+        self.local_addr.lock().unwrap().map(|addr| addr.port()).unwrap_or(0)
+    }
+}
+
 /// Network server for handling P2P connections
 pub struct NetworkServer {
     chain: Arc<Mutex<Chain>>,
     peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
-    node_id: String,
+    identity: Arc<NodeIdentity>,
     listen_address: String,
     listen_port: u16,
     running: Arc<Mutex<bool>>,
+    checkpoints: Arc<Checkpoints>,
+    /// Blocks this deep in our chain are never reorganized away for a peer's fork
+    finality_depth: u64,
+    seen: Arc<Mutex<SeenInventory>>,
+    services: Services,
+    relay_policy: Arc<RwLock<RelayPolicy>>,
+    /// Inbound connections are refused once this many peers are connected
+    max_peers: Arc<AtomicUsize>,
+    /// Blocks a polled peer may be ahead of us before we sync from it
+    sync_threshold: Arc<AtomicU64>,
+    /// Outbound connections the dialer keeps open
+    outbound_target: Arc<AtomicUsize>,
+    keepalive: Arc<Mutex<KeepaliveScheduler>>,
+    /// Read deadline and buffer limit for messages from peers
+    framing: FramingConfig,
+    propagation: Arc<Mutex<PropagationTracker>>,
+    events: EventBus,
+    alerts: AlertMonitor,
+    /// Blocks and transactions waiting to be announced in the next batch
+    relay: Arc<Mutex<InventoryRelay>>,
+    mempool: Arc<Mutex<Mempool>>,
+    /// Bytes and messages exchanged since the server was created
+    traffic: Arc<Mutex<TrafficMonitor>>,
+    /// Connections, contributions and misbehavior of peers across restarts
+    peer_stats: Option<Arc<PeerStatsDb>>,
+    /// Source of the time peers are last seen at
+    clock: SharedClock,
+    /// Nodes added with `add_node`, which the watchdog reconnects to
+    added_nodes: Mutex<Vec<PeerAddress>>,
+    /// Connections `disconnect_node` asked to close, taken by their connection loop
+    disconnect_requests: Arc<Mutex<HashSet<SocketAddr>>>,
+    /// Inventory batches waiting to be announced on each open connection, taken
+    /// by its connection loop
+    announcements: Arc<Mutex<HashMap<SocketAddr, Vec<InventoryItem>>>>,
+    /// Address the listener is bound to once started
+    local_addr: Arc<Mutex<Option<SocketAddr>>>,
+    /// Addresses we dialed whose connection is still open
+    outbound: Arc<Mutex<HashSet<PeerAddress>>>,
+    /// Socket address of each open outbound connection by node id, which the
+    /// dialed host and port need not match
+    outbound_sockets: Arc<Mutex<HashMap<String, SocketAddr>>>,
 }
 
 impl NetworkServer {
-    /// Create a new network server
+    /// Create a new network server with a throwaway identity
     pub fn new(chain: Chain, listen_address: String, listen_port: u16) -> Self {
-        let node_id = format!("node_{}", rand::random::<u32>());
-        
         NetworkServer {
             chain: Arc::new(Mutex::new(chain)),
             peers: Arc::new(Mutex::new(HashMap::new())),
-            node_id,
+            identity: Arc::new(NodeIdentity::generate()),
             listen_address,
             listen_port,
             running: Arc::new(Mutex::new(false)),
+            checkpoints: Arc::new(Checkpoints::mainnet()),
+            finality_depth: DEFAULT_FINALITY_DEPTH,
+            seen: Arc::new(Mutex::new(SeenInventory::new())),
+            services: LOCAL_SERVICES,
+            relay_policy: Arc::new(RwLock::new(RelayPolicy::default())),
+            max_peers: Arc::new(AtomicUsize::new(DEFAULT_MAX_PEERS)),
+            sync_threshold: Arc::new(AtomicU64::new(DEFAULT_SYNC_THRESHOLD)),
+            outbound_target: Arc::new(AtomicUsize::new(DEFAULT_OUTBOUND_TARGET)),
+            keepalive: Arc::new(Mutex::new(KeepaliveScheduler::default())),
+            framing: FramingConfig::default(),
+            propagation: Arc::new(Mutex::new(PropagationTracker::new())),
+            events: EventBus::new(),
+            alerts: AlertMonitor::new(AlertConfig::default(), AlertLog::new()),
+            relay: Arc::new(Mutex::new(InventoryRelay::default())),
+            mempool: Arc::new(Mutex::new(Mempool::new())),
+            traffic: Arc::new(Mutex::new(TrafficMonitor::new())),
+            peer_stats: None,
+            clock: clock::system(),
+            added_nodes: Mutex::new(Vec::new()),
+            disconnect_requests: Arc::new(Mutex::new(HashSet::new())),
+            announcements: Arc::new(Mutex::new(HashMap::new())),
+            local_addr: Arc::new(Mutex::new(None)),
+            outbound: Arc::new(Mutex::new(HashSet::new())),
+            outbound_sockets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    
+    /// Use the given checkpoints when accepting blocks from peers
+    pub fn with_checkpoints(mut self, checkpoints: Checkpoints) -> Self {
+        self.checkpoints = Arc::new(checkpoints);
+        self
+    }
+    
+    /// Refuse peer forks that would replace blocks this deep in our chain
+    pub fn with_finality_depth(mut self, finality_depth: u64) -> Self {
+        self.finality_depth = finality_depth;
+        self
+    }
+    
+    /// Time peers with `clock` instead of the system clock, as the chain and
+    /// mempool of a regtest node are
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+    
+    /// Use a persistent node identity so peers recognize us across restarts
+    pub fn with_identity(mut self, identity: NodeIdentity) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+    
+    /// Advertise a different set of services (e.g. a node that does not serve history)
+    pub fn with_services(mut self, services: Services) -> Self {
+        self.services = services;
+        self
+    }
+    
+    /// Apply a relay policy to transactions from peers. In blocks-only mode the
+    /// node also stops advertising transaction relay.
+    pub fn with_relay_policy(mut self, policy: RelayPolicy) -> Self {
+        if policy.blocks_only {
+            self.services = Services(self.services.0 & !Services::TX_RELAY.0);
+        }
+        self.relay_policy = Arc::new(RwLock::new(policy));
+        self
+    }
+    
+    /// Limit the number of connected peers
+    pub fn with_max_peers(self, max_peers: usize) -> Self {
+        self.max_peers.store(max_peers, Ordering::Relaxed);
+        self
+    }
+    
+    /// Sync once a polled peer is more than `blocks` ahead of us
+    pub fn with_sync_threshold(self, blocks: u64) -> Self {
+        self.sync_threshold.store(blocks, Ordering::Relaxed);
+        self
+    }
+    
+    /// Keep `count` outbound connections open while the dialer runs
+    pub fn with_outbound_target(self, count: usize) -> Self {
+        self.outbound_target.store(count, Ordering::Relaxed);
+        self
+    }
+    
+    /// Apply reloadable relay policy, mempool limit, peer limit, sync threshold and
+    /// outbound target settings while running. Existing connections above a lowered peer limit are kept.
+    pub fn apply_config(&self, config: &NodeConfig) {
+        let policy = config.relay_policy(&self.relay_policy.read().unwrap());
+        *self.relay_policy.write().unwrap() = policy;
+        self.mempool.lock().unwrap().apply_config(config);
+        if let Some(max_peers) = config.max_peers {
+            self.max_peers.store(max_peers, Ordering::Relaxed);
         }
+        if let Some(sync_threshold) = config.sync_threshold {
+            self.sync_threshold.store(sync_threshold, Ordering::Relaxed);
+        }
+        if let Some(outbound_target) = config.outbound_target {
+            self.outbound_target.store(outbound_target, Ordering::Relaxed);
+        }
+    }
+    
+    /// Blocks a polled peer may be ahead of us before we sync from it
+    pub fn sync_threshold(&self) -> u64 {
+        self.sync_threshold.load(Ordering::Relaxed)
+    }
+    
+    /// Outbound connections the dialer keeps open
+    pub fn outbound_target(&self) -> usize {
+        self.outbound_target.load(Ordering::Relaxed)
+    }
+    
+    /// The node's mempool, shared with whatever else serves the node (such as
+    /// its RPC server)
+    pub fn mempool(&self) -> Arc<Mutex<Mempool>> {
+        Arc::clone(&self.mempool)
+    }
+    
+    /// Use a keepalive scheduler with custom timing or a `PeerDiscovery` to report to
+    pub fn with_keepalive(mut self, keepalive: KeepaliveScheduler) -> Self {
+        self.keepalive = Arc::new(Mutex::new(keepalive));
+        self
+    }
+    
+    /// Use a custom message deadline or per-peer buffer limit
+    pub fn with_framing(mut self, framing: FramingConfig) -> Self {
+        self.framing = framing;
+        self
+    }
+    
+    /// Continue tracking propagation of previously mined blocks
+    pub fn with_propagation(mut self, tracker: PropagationTracker) -> Self {
+        self.propagation = Arc::new(Mutex::new(tracker));
+        self
+    }
+    
+    /// Publish blocks received from peers on an existing event bus
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+    
+    /// Raise alerts for suspicious blocks from peers through an existing monitor
+    pub fn with_alerts(mut self, alerts: AlertMonitor) -> Self {
+        self.alerts = alerts;
+        self
+    }
+    
+    /// Keep long-term peer records in this database and rank peers by them.
+    /// Propagation records are saved to it as blocks are relayed.
+    pub fn with_peer_stats(mut self, peer_stats: PeerStatsDb) -> Self {
+        self.peer_stats = Some(Arc::new(peer_stats));
+        self
+    }
+    
+    /// Add transactions from peers to, and answer mempool requests from peers
+    /// with, this pool
+    pub fn with_mempool(mut self, mempool: Arc<Mutex<Mempool>>) -> Self {
+        self.mempool = mempool;
+        self
+    }
+    
+    /// Batch relayed announcements on a custom interval
+    pub fn with_relay_interval(mut self, interval: Duration) -> Self {
+        self.relay = Arc::new(Mutex::new(InventoryRelay::new(interval)));
+        self
+    }
+    
+    /// Queue a transaction to be announced to peers in the next batch
+    pub fn announce_transaction(&self, transaction: &Transaction) {
+        self.seen.lock().unwrap().insert(&InventoryItem::transaction(transaction.hash()));
+        self.relay.lock().unwrap().queue_transaction(transaction.clone());
+    }
+    
+    /// Snapshot of the propagation records for our mined blocks
+    pub fn propagation(&self) -> PropagationTracker {
+        self.propagation.lock().unwrap().clone()
+    }
+    
+    /// Canonical node id (hash of the identity public key)
+    pub fn node_id(&self) -> String {
+        self.identity.node_id()
     }
     
     /// Start the server
     pub fn start(&self) -> Result<(), NetworkError> {
-        let bind_address = format!("{}:{}", self.listen_address, self.listen_port);
-        let listener = TcpListener::bind(&bind_address)
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to bind to {}: {}", bind_address, e)))?;
+        let listener = PeerAddress::new(&self.listen_address, self.listen_port)
+            .map_err(NetworkError::ConnectionFailed)?
+            .bind()?;
         
-        println!("Network server listening on {}", bind_address);
+        *self.local_addr.lock().unwrap() = listener.local_addr().ok();
+        match listener.local_addr() {
+            Ok(addr) if addr.is_ipv6() && addr.ip().is_unspecified() => {
+                status!("Network server listening on {} (IPv4 and IPv6 where supported)", addr)
+            },
+            Ok(addr) => println!("Network server listening on {}", addr),
+            Err(_) => println!("Network server listening on {}:{}", self.listen_address, self.listen_port),
+        }
         
         *self.running.lock().unwrap() = true;
+        self.spawn_relay_thread();
         
         for stream in listener.incoming() {
             if !*self.running.lock().unwrap() {
@@ -53,12 +531,20 @@ impl NetworkServer {
             
             match stream {
                 Ok(stream) => {
-                    let chain = Arc::clone(&self.chain);
-                    let peers = Arc::clone(&self.peers);
-                    let node_id = self.node_id.clone();
+                    let max_peers = self.max_peers.load(Ordering::Relaxed);
+                    if self.peers.lock().unwrap().len() >= max_peers {
+                        status!("Refusing connection from {:?}: peer limit of {} reached", stream.peer_addr().ok(), max_peers);
+                        continue;
+                    }
+                    if let Ok(addr) = stream.peer_addr() && self.is_banned(&addr.ip().to_string()) {
+                        status!("Refusing connection from {}: banned for misbehavior", addr);
+                        continue;
+                    }
+                    
+                    let context = self.connection_context();
                     
                     thread::spawn(move || {
-                        if let Err(e) = Self::handle_connection(stream, chain, peers, node_id) {
+                        if let Err(e) = Self::handle_connection(stream, context) {
                             eprintln!("Connection error: {}", e);
                         }
                     });
@@ -72,42 +558,181 @@ impl NetworkServer {
         Ok(())
     }
     
-    /// Stop the server
+    /// Stop the server, waking the accept loop so `start` returns
     pub fn stop(&self) {
         *self.running.lock().unwrap() = false;
+        // The accept loop only checks the flag when a connection arrives
+        if let Some(addr) = *self.local_addr.lock().unwrap() {
+            let ip: IpAddr = match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.into(),
+                IpAddr::V6(ip) if ip.is_unspecified() => Ipv6Addr::LOCALHOST.into(),
+                ip => ip,
+            };
+            let _ = TcpStream::connect_timeout(&SocketAddr::new(ip, addr.port()), Duration::from_secs(1));
+        }
+    }
+    
+    /// Whether the server is accepting connections
+    pub fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+    
+    /// Hand queued announcements to the open connections in batches until the server stops
+    fn spawn_relay_thread(&self) {
+        let running = Arc::clone(&self.running);
+        let relay = Arc::clone(&self.relay);
+        let announcements = Arc::clone(&self.announcements);
+        
+        thread::spawn(move || {
+            while *running.lock().unwrap() {
+                thread::sleep(INV_BATCH_INTERVAL / 2);
+                Self::flush_announcements(&relay, &announcements);
+            }
+        });
+    }
+    
+    /// Queue the next batch of inventory on every open connection, whose loop announces
+    /// it and answers the peer's `GetData` over the same socket.
+    /// Returns the number of items announced.
+    fn flush_announcements(
+        relay: &Mutex<InventoryRelay>,
+        announcements: &Mutex<HashMap<SocketAddr, Vec<InventoryItem>>>,
+    ) -> usize {
+        let Some(batch) = relay.lock().unwrap().take_batch(Instant::now()) else {
+            return 0;
+        };
+        for queued in announcements.lock().unwrap().values_mut() {
+            queued.extend(batch.iter().cloned());
+        }
+        
+        batch.len()
+    }
+    
+    fn connection_context(&self) -> ConnectionContext {
+        ConnectionContext {
+            chain: Arc::clone(&self.chain),
+            peers: Arc::clone(&self.peers),
+            identity: Arc::clone(&self.identity),
+            checkpoints: Arc::clone(&self.checkpoints),
+            seen: Arc::clone(&self.seen),
+            services: self.services,
+            relay_policy: Arc::clone(&self.relay_policy),
+            keepalive: Arc::clone(&self.keepalive),
+            framing: self.framing,
+            events: self.events.clone(),
+            alerts: self.alerts.clone(),
+            relay: Arc::clone(&self.relay),
+            mempool: Arc::clone(&self.mempool),
+            traffic: Arc::clone(&self.traffic),
+            peer_stats: self.peer_stats.clone(),
+            clock: Arc::clone(&self.clock),
+            disconnect_requests: Arc::clone(&self.disconnect_requests),
+            announcements: Arc::clone(&self.announcements),
+            local_addr: Arc::clone(&self.local_addr),
+        }
+    }
+    
+    /// A connection from `addr` whose messages are delivered by the caller
+    pub(crate) fn offline_connection(&self, addr: SocketAddr) -> OfflineConnection {
+        let context = self.connection_context();
+        let connection = PeerConnection::new(addr, &context);
+        OfflineConnection { context, connection }
     }
     
     /// Handle a single connection
-    fn handle_connection(
-        mut stream: TcpStream,
-        chain: Arc<Mutex<Chain>>,
-        peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
-        node_id: String,
-    ) -> Result<(), NetworkError> {
+    fn handle_connection(mut stream: TcpStream, context: ConnectionContext) -> Result<(), NetworkError> {
         let peer_addr = stream.peer_addr()
             .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to get peer address: {}", e)))?;
         
-        println!("New connection from {}", peer_addr);
+        status!("New connection from {}", peer_addr);
         
-        // Set read timeout
-        stream.set_read_timeout(Some(Duration::from_secs(30)))
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
+        let mut connection = PeerConnection::new(peer_addr, &context);
+        Self::serve_connection(&mut stream, &context, &mut connection)
+    }
+    
+    /// Run a connection until it closes, then forget the per-connection state
+    fn serve_connection(
+        stream: &mut TcpStream,
+        context: &ConnectionContext,
+        connection: &mut PeerConnection,
+    ) -> Result<(), NetworkError> {
+        let peer_addr = connection.addr;
+        context.keepalive.lock().unwrap().register(peer_addr, Instant::now());
+        context.announcements.lock().unwrap().insert(peer_addr, Vec::new());
+        let result = Self::run_connection(stream, context, connection);
+        context.traffic.lock().unwrap().set_compression(&peer_addr.to_string(), false);
+        context.keepalive.lock().unwrap().remove(&peer_addr);
+        context.announcements.lock().unwrap().remove(&peer_addr);
+        context.disconnect_requests.lock().unwrap().remove(&peer_addr);
+        context.seen.lock().unwrap().forget_peer(&peer_addr.to_string());
+        
+        result
+    }
+    
+    /// Read and answer messages until the peer disconnects, stops answering pings
+    /// or is too slow to finish a message
+    fn run_connection(
+        stream: &mut TcpStream,
+        context: &ConnectionContext,
+        connection: &mut PeerConnection,
+    ) -> Result<(), NetworkError> {
+        let peer_addr = connection.addr;
+        let peer = peer_addr.to_string();
+        let traffic = &context.traffic;
+        let interval = context.keepalive.lock().unwrap().config().interval;
+        let mut reader = FrameReader::new(context.framing);
         
         loop {
-            match Self::read_message(&mut stream) {
+            if context.disconnect_requests.lock().unwrap().remove(&peer_addr) {
+                status!("Disconnecting {}: disconnect requested", peer_addr);
+                break;
+            }
+            
+            // Blocks this peer announced that the peer asked before it did not deliver
+            let due = context.seen.lock().unwrap().rerequest(&peer, Instant::now());
+            if !due.is_empty() {
+                Self::send_message(stream, NetworkMessage::new(MessageType::GetData(due)), traffic)?;
+            }
+            
+            // Inventory the relay thread queued for us; the peer's `GetData` for it
+            // arrives as an ordinary message
+            let queued = context.announcements.lock().unwrap().get_mut(&peer_addr).map(std::mem::take).unwrap_or_default();
+            let items = connection.announceable(queued);
+            if !items.is_empty() {
+                Self::send_message(stream, NetworkMessage::new(MessageType::Inv(items)), traffic)?;
+            }
+            
+            // Wake up at least once per relay batch to pass on announcements and per
+            // keepalive interval to check whether a ping is due, and in time to catch
+            // a message that is overdue
+            stream.set_read_timeout(Some(reader.read_timeout(interval.min(INV_BATCH_INTERVAL))))
+                .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
+            
+            match reader.read_message(stream) {
                 Ok(message) => {
                     if !message.validate() {
+                        Self::update_peer_stats(&context.peer_stats, |stats, now| {
+                            stats.record_misbehavior(&peer_addr.ip().to_string(), "invalid message", now)
+                        });
                         return Err(NetworkError::InvalidMessage("Invalid message format".to_string()));
                     }
+                    context.keepalive.lock().unwrap().record_activity(peer_addr, Instant::now());
+                    Self::record_received(traffic, &peer, &message, &reader);
                     
-                    match Self::handle_message(message, &chain, &peers, &node_id, &peer_addr) {
+                    match Self::handle_message(message, &context.chain, &context.peers, &context.identity, connection, &context.checkpoints, &context.seen) {
                         MessageResult::Success => {},
                         MessageResult::Response(response) => {
-                            Self::send_message(&mut stream, response)?;
+                            // A version reply means negotiation failed, hang up after sending it
+                            let incompatible = matches!(response.message_type, MessageType::VersionNegotiation { .. });
+                            Self::send_message(stream, response, traffic)?;
+                            if incompatible {
+                                status!("Disconnecting {}: no common protocol version", peer_addr);
+                                break;
+                            }
                         },
                         MessageResult::MultipleResponses(responses) => {
                             for response in responses {
-                                Self::send_message(&mut stream, response)?;
+                                Self::send_message(stream, response, traffic)?;
                             }
                         },
                         MessageResult::Error(err) => {
@@ -117,12 +742,26 @@ impl NetworkServer {
                     }
                 },
                 Err(NetworkError::Timeout) => {
-                    // Send ping to check if connection is alive
-                    let ping = NetworkMessage::new(MessageType::Ping);
-                    Self::send_message(&mut stream, ping)?;
+                    let action = context.keepalive.lock().unwrap().poll(peer_addr, Instant::now());
+                    match action {
+                        KeepaliveAction::Wait => {},
+                        KeepaliveAction::SendPing => {
+                            Self::send_message(stream, NetworkMessage::new(MessageType::Ping), traffic)?;
+                        },
+                        KeepaliveAction::Disconnect => {
+                            status!("Disconnecting {}: no response to keepalive pings", peer_addr);
+                            Self::remove_peer(&context.peers, peer_addr);
+                            break;
+                        },
+                    }
+                },
+                Err(NetworkError::SlowPeer(reason)) => {
+                    status!("Disconnecting slow peer {}: {}", peer_addr, reason);
+                    Self::remove_peer(&context.peers, peer_addr);
+                    break;
                 },
                 Err(NetworkError::PeerDisconnected) => {
-                    println!("Peer {} disconnected", peer_addr);
+                    status!("Peer {} disconnected", peer_addr);
                     break;
                 },
                 Err(e) => {
@@ -135,49 +774,81 @@ impl NetworkServer {
         Ok(())
     }
     
-    /// Read a message from the stream
-    fn read_message(stream: &mut TcpStream) -> Result<NetworkMessage, NetworkError> {
-        let mut length_bytes = [0u8; 4];
-        stream.read_exact(&mut length_bytes)
-            .map_err(|e| {
-                if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                    NetworkError::PeerDisconnected
-                } else if e.kind() == std::io::ErrorKind::TimedOut {
-                    NetworkError::Timeout
-                } else {
-                    NetworkError::ConnectionFailed(format!("Failed to read message length: {}", e))
-                }
-            })?;
-        
-        let length = u32::from_be_bytes(length_bytes) as usize;
-        if length > crate::network::protocol::MAX_MESSAGE_SIZE {
-            return Err(NetworkError::InvalidMessage("Message too large".to_string()));
+    fn remove_peer(peers: &Mutex<HashMap<String, PeerInfo>>, peer_addr: SocketAddr) {
+        let ip = peer_addr.ip().to_string();
+        peers.lock().unwrap()
+            .retain(|_, peer| !(peer.address == ip && peer.port == peer_addr.port()));
+    }
+
+    
+    /// Read the reply on a request/response connection. The socket's read timeout
+    /// bounds the wait for the reply to start; once it has, the message deadline applies.
+    fn read_message(stream: &mut TcpStream, traffic: &Mutex<TrafficMonitor>) -> Result<NetworkMessage, NetworkError> {
+        let mut reader = FrameReader::new(FramingConfig::default());
+        loop {
+            match reader.read_message(stream) {
+                Err(NetworkError::Timeout) if reader.buffered() > 0 => continue,
+                Ok(message) => {
+                    Self::record_received(traffic, &Self::peer_of(stream), &message, &reader);
+                    return Ok(message);
+                },
+                result => return result,
+            }
         }
-        
-        let mut buffer = vec![0u8; length];
-        stream.read_exact(&mut buffer)
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to read message data: {}", e)))?;
-        
-        NetworkMessage::from_bytes(&buffer)
-            .map_err(|e| NetworkError::InvalidMessage(e))
     }
     
-    /// Send a message to the stream
-    fn send_message(stream: &mut TcpStream, message: NetworkMessage) -> Result<(), NetworkError> {
-        let data = message.to_bytes()
-            .map_err(|e| NetworkError::ProtocolError(e))?;
-        
-        let length = data.len() as u32;
-        stream.write_all(&length.to_be_bytes())
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to write message length: {}", e)))?;
+    /// Count a message read by `reader` in the traffic stats
+    fn record_received(traffic: &Mutex<TrafficMonitor>, peer: &str, message: &NetworkMessage, reader: &FrameReader) {
+        let mut traffic = traffic.lock().unwrap();
+        traffic.record_received(peer, &message.message_type, reader.last_frame_size());
+        if reader.last_raw_size() != reader.last_frame_size() {
+            traffic.record_compressed_received(reader.last_raw_size(), reader.last_frame_size());
+        }
+    }
+    
+    /// Send a message to the stream, compressed if it is large and the peer
+    /// negotiated compression, counting it in the traffic stats
+    fn send_message(stream: &mut TcpStream, message: NetworkMessage, traffic: &Mutex<TrafficMonitor>) -> Result<(), NetworkError> {
+        let peer = Self::peer_of(stream);
+        let frame = encode_frame(&message)?;
+        let raw_size = frame.len();
+        let compress = traffic.lock().unwrap().compresses(&peer);
+        let frame = if compress { compress_frame(frame)? } else { frame };
+        Self::write_frame(stream, &frame)?;
         
-        stream.write_all(&data)
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to write message data: {}", e)))?;
+        let mut traffic = traffic.lock().unwrap();
+        traffic.record_sent(&peer, &message.message_type, frame.len());
+        if frame.len() != raw_size {
+            traffic.record_compressed_sent(raw_size, frame.len());
+        }
+        Ok(())
+    }
+    
+    /// Write a message to the stream uncompressed, returning its size on the wire
+    fn write_message(stream: &mut TcpStream, message: &NetworkMessage) -> Result<usize, NetworkError> {
+        Self::write_frame(stream, &encode_frame(message)?)
+    }
+    
+    /// Write an encoded frame to the stream, returning its size
+    fn write_frame(stream: &mut TcpStream, frame: &[u8]) -> Result<usize, NetworkError> {
+        stream.write_all(frame)
+            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to write message: {}", e)))?;
         
         stream.flush()
             .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to flush stream: {}", e)))?;
         
-        Ok(())
+        Ok(frame.len())
+    }
+    
+    /// Compress large messages to `peer` from now on if both sides support it
+    fn negotiate_compression(traffic: &Mutex<TrafficMonitor>, peer: &str, peer_features: Features) {
+        let enabled = LOCAL_FEATURES.intersection(peer_features).contains(Features::COMPRESSION);
+        traffic.lock().unwrap().set_compression(peer, enabled);
+    }
+    
+    /// Key of the peer at the other end of a stream in the traffic stats
+    fn peer_of(stream: &TcpStream) -> String {
+        stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| "unknown".to_string())
     }
     
     /// Handle an incoming message
@@ -185,45 +856,90 @@ impl NetworkServer {
         message: NetworkMessage,
         chain: &Arc<Mutex<Chain>>,
         peers: &Arc<Mutex<HashMap<String, PeerInfo>>>,
-        node_id: &str,
-        peer_addr: &SocketAddr,
+        identity: &NodeIdentity,
+        connection: &mut PeerConnection,
+        checkpoints: &Checkpoints,
+        seen: &Arc<Mutex<SeenInventory>>,
     ) -> MessageResult {
-        println!("Received message: {:?}", message.message_type);
+        let _timer = perf::timer(perf::MESSAGE_HANDLING);
+        let peer_addr = connection.addr;
+        detail!("Received message: {:?}", message.message_type);
+        
+        // Peers that never negotiated get the baseline feature set
+        if !connection.capabilities.unwrap_or_default().allows(&message.message_type) {
+            detail!("Ignoring message from {}: feature not negotiated", peer_addr);
+            return MessageResult::Success;
+        }
         
         match message.message_type {
-            MessageType::Handshake { version, node_id: peer_node_id, chain_height } => {
-                if version > PROTOCOL_VERSION {
-                    return MessageResult::Error("Unsupported protocol version".to_string());
-                }
-                
-                // Add peer to peer list
-                let peer_info = PeerInfo {
-                    address: peer_addr.ip().to_string(),
-                    port: peer_addr.port(),
-                    node_id: peer_node_id,
-                    last_seen: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-                    chain_height,
+            MessageType::Handshake { version, min_version, features, services, node_id: claimed_id, chain_height, public_key, nonce, listen_port, .. } => {
+                let negotiated = match PeerCapabilities::negotiate(min_version, version, features) {
+                    Ok(negotiated) => negotiated,
+                    Err(e) => {
+                        status!("Handshake from {} failed: {}", peer_addr, e);
+                        return MessageResult::Response(NetworkMessage::new(MessageType::VersionNegotiation {
+                            supported_versions: (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).collect(),
+                            preferred_version: PROTOCOL_VERSION,
+                        }));
+                    }
                 };
                 
-                peers.lock().unwrap().insert(peer_info.node_id.clone(), peer_info);
+                let peer_node_id = match Self::check_node_id(&claimed_id, &public_key) {
+                    Ok(node_id) => node_id,
+                    Err(e) => {
+                        Self::update_peer_stats(&connection.peer_stats, |stats, now| {
+                            stats.record_misbehavior(&peer_addr.ip().to_string(), "invalid handshake", now)
+                        });
+                        return MessageResult::Error(e.to_string());
+                    },
+                };
+                Self::negotiate_compression(&connection.traffic, &peer_addr.to_string(), negotiated.features);
                 
-                // Respond with our handshake
-                let chain_guard = chain.lock().unwrap();
-                let our_height = chain_guard.blocks.len() as u64 - 1;
-                drop(chain_guard);
+                // The peer is registered once it signs the challenge in our reply
+                let our_height = chain.lock().unwrap().height();
+                let challenge = generate_nonce();
+                let reply = Self::build_handshake(identity, our_height, connection.local_services, connection.listen_port, &challenge, Some(&nonce));
+                connection.pending_handshake = Some(PendingHandshake {
+                    challenge,
+                    public_key,
+                    peer: PeerInfo {
+                        address: peer_addr.ip().to_string(),
+                        port: peer_addr.port(),
+                        node_id: peer_node_id,
+                        last_seen: connection.clock.now(),
+                        chain_height,
+                        capabilities: negotiated,
+                        services,
+                        listen_port,
+                    },
+                });
                 
-                let response = NetworkMessage::new(MessageType::Handshake {
-                    version: PROTOCOL_VERSION,
-                    node_id: node_id.to_string(),
-                    chain_height: our_height,
+                MessageResult::Response(reply)
+            },
+            
+            MessageType::HandshakeAck { signature } => {
+                let Some(pending) = connection.pending_handshake.take() else {
+                    return MessageResult::Error("Handshake acknowledgement without a handshake".to_string());
+                };
+                if let Err(e) = NodeIdentity::verify_challenge(&pending.public_key, HandshakeRole::Initiator, &pending.challenge, &signature) {
+                    Self::update_peer_stats(&connection.peer_stats, |stats, now| {
+                        stats.record_misbehavior(&peer_addr.ip().to_string(), "invalid handshake", now)
+                    });
+                    return MessageResult::Error(e);
+                }
+                connection.capabilities = Some(pending.peer.capabilities);
+                Self::update_peer_stats(&connection.peer_stats, |stats, now| {
+                    stats.record_connection(&peer_addr.ip().to_string(), true, now)
                 });
                 
-                MessageResult::Response(response)
+                // Add peer to peer list
+                peers.lock().unwrap().insert(pending.peer.node_id.clone(), pending.peer);
+                MessageResult::Success
             },
             
             MessageType::GetChainInfo => {
                 let chain_guard = chain.lock().unwrap();
-                let latest_block = chain_guard.blocks.last().unwrap();
+                let latest_block = chain_guard.tip().unwrap();
                 let response = NetworkMessage::new(MessageType::ChainInfo {
                     latest_hash: latest_block.header.hash.clone(),
                     height: latest_block.header.height,
@@ -235,23 +951,60 @@ impl NetworkServer {
             
             MessageType::GetBlocks { start_hash, count } => {
                 let chain_guard = chain.lock().unwrap();
-                let mut blocks = Vec::new();
-                let mut found_start = start_hash == "0"; // Genesis case
-                
-                for block in &chain_guard.blocks {
-                    if found_start && blocks.len() < count as usize {
-                        blocks.push(block.clone());
-                    }
-                    if block.header.hash == start_hash {
-                        found_start = true;
-                    }
-                }
+                // Genesis case: "0" starts from the first block
+                let from = if start_hash == "0" {
+                    Some(0)
+                } else {
+                    chain_guard.find_block(&start_hash).ok().flatten().map(|block| block.header.height + 1)
+                };
+                let count = count.min(MAX_BLOCKS_PER_REQUEST);
+                let blocks = match from {
+                    Some(from) if count > 0 => chain_guard
+                        .blocks_in_range(from, from.saturating_add(count as u64 - 1))
+                        .map(|blocks| blocks.into_owned())
+                        .unwrap_or_default(),
+                    _ => Vec::new(),
+                };
                 drop(chain_guard);
                 
                 let response = NetworkMessage::new(MessageType::Blocks(blocks));
                 MessageResult::Response(response)
             },
             
+            MessageType::GetBlockRange { start_height, count } => {
+                let count = count.min(MAX_BLOCKS_PER_REQUEST);
+                let blocks = match count {
+                    0 => Vec::new(),
+                    count => chain.lock().unwrap()
+                        .blocks_in_range(start_height, start_height.saturating_add(count as u64 - 1))
+                        .map(|blocks| blocks.into_owned())
+                        .unwrap_or_default(),
+                };
+                
+                MessageResult::Response(NetworkMessage::new(MessageType::Blocks(blocks)))
+            },
+            
+            MessageType::GetBlockHeaders { start_height, count } => {
+                let count = count.min(MAX_HEADERS_PER_REQUEST) as u64;
+                let chain_guard = chain.lock().unwrap();
+                let end = start_height.saturating_add(count).min(chain_guard.height().saturating_add(1));
+                let headers = (start_height..end)
+                    .map_while(|height| chain_guard.header_at(height).ok().flatten())
+                    .map(|header| BlockHeader::from(&header))
+                    .collect();
+                drop(chain_guard);
+                
+                MessageResult::Response(NetworkMessage::new(MessageType::BlockHeaders { headers, start_height }))
+            },
+            
+            MessageType::GetBlocksByLocator { locator, count } => {
+                let chain_guard = chain.lock().unwrap();
+                let response = Self::locator_response(&chain_guard, &locator, count);
+                drop(chain_guard);
+                
+                MessageResult::Response(NetworkMessage::new(response))
+            },
+            
             MessageType::GetPeers => {
                 let peers_guard = peers.lock().unwrap();
                 let peer_list: Vec<PeerInfo> = peers_guard.values().cloned().collect();
@@ -262,17 +1015,136 @@ impl NetworkServer {
             },
             
             MessageType::NewBlock(block) => {
-                // Simple validation and addition
+                // Peers may echo blocks back to us and several may deliver the same one
+                // at once; only the first delivery is processed
+                let Some(claim) = BlockClaim::acquire(seen, &block.header.hash, &peer_addr.to_string()) else {
+                    return MessageResult::Success;
+                };
+                
+                connection.alerts.check_block_time(&block, &peer_addr.to_string());
                 let mut chain_guard = chain.lock().unwrap();
-                if chain_guard.validate_block(&block) {
-                    chain_guard.add_block(block);
-                    println!("Added new block from peer");
+                if chain_guard.get_block_by_hash(&block.header.hash).is_some() {
+                    // A sync connected it while we waited for the chain
+                    claim.finish();
+                    return MessageResult::Success;
                 }
+                // Only a connected or invalid block is done with; otherwise another copy
+                // or announcer gets a chance
+                let processed = match Self::check_peer_block(&mut chain_guard, checkpoints, &block) {
+                    Ok(()) => {
+                        let added = chain_guard.add_block(block.clone());
+                        if added {
+                            status!("Added new block from peer");
+                            Self::update_peer_stats(&connection.peer_stats, |stats, now| {
+                                stats.record_blocks(&peer_addr.ip().to_string(), 1, now)
+                            });
+                            connection.relay.lock().unwrap().queue(InventoryItem::block(block.header.hash.clone()));
+                            connection.events.publish(ChainEvent::BlockConnected(block));
+                        }
+                        added
+                    },
+                    Err(e) => {
+                        status!("Rejected block from peer: {}", e);
+                        if e.is_misbehavior() {
+                            connection.alerts.record_invalid_block(&peer_addr.to_string());
+                            Self::update_peer_stats(&connection.peer_stats, |stats, now| {
+                                stats.record_misbehavior(&peer_addr.ip().to_string(), &format!("invalid block: {}", e), now)
+                            });
+                        }
+                        e.is_permanent()
+                    },
+                };
                 drop(chain_guard);
+                if processed {
+                    claim.finish();
+                }
+                
+                MessageResult::Success
+            },
+            
+            MessageType::NewTransaction { transaction_data, from_address, to_address, amount, signature, .. } => {
+                // The encoded transaction carries every field, including height bounds;
+                // older peers may only fill in the flat fields
+                let transaction = Transaction::from_hex(&transaction_data).unwrap_or_else(|_| Transaction {
+                    from: from_address,
+                    to: to_address,
+                    amount,
+                    signature: hex::decode(&signature).unwrap_or_default(),
+                    lock_height: None,
+                    expiry_height: None,
+                });
+                
+                if let Err(violation) = connection.relay_policy.read().unwrap().check(&transaction) {
+                    detail!("Ignoring transaction {} from {}: {}", transaction.hash(), peer_addr, violation);
+                    return MessageResult::Success;
+                }
+                
+                if seen.lock().unwrap().insert(&InventoryItem::transaction(transaction.hash())) {
+                    detail!("Received new transaction {} from peer", transaction.hash());
+                    Self::update_peer_stats(&connection.peer_stats, |stats, now| {
+                        stats.record_transaction(&peer_addr.ip().to_string(), now)
+                    });
+                    
+                    // Only transactions our mempool takes are passed on
+                    let utxo_state = chain.lock().unwrap().utxo_state();
+                    let added = utxo_state.and_then(|state| {
+                        connection.mempool.lock().unwrap()
+                            .add_network_transaction(transaction.clone(), &state)
+                            .map_err(|e| e.to_string())
+                    });
+                    match added {
+                        Ok(()) => connection.relay.lock().unwrap().queue_transaction(transaction),
+                        Err(e) => println!("Not relaying transaction {} from {}: {}", transaction.hash(), peer_addr, e),
+                    }
+                }
                 
                 MessageResult::Success
             },
             
+            MessageType::Inv(items) => {
+                // Always answer so the announcer knows whether to send bodies. A block
+                // already requested from another peer is left to that peer.
+                let mut wanted = seen.lock().unwrap().request(&items, &peer_addr.to_string(), Instant::now());
+                if connection.relay_policy.read().unwrap().blocks_only {
+                    wanted.retain(|item| item.inv_type != InventoryType::Transaction);
+                }
+                MessageResult::Response(NetworkMessage::new(MessageType::GetData(wanted)))
+            },
+            
+            MessageType::GetData(items) => {
+                let chain_guard = chain.lock().unwrap();
+                let relay = connection.relay.lock().unwrap();
+                let responses = items.iter()
+                    .filter_map(|item| Self::inventory_payload(&chain_guard, &relay, item))
+                    .map(NetworkMessage::new)
+                    .collect();
+                
+                MessageResult::MultipleResponses(responses)
+            },
+            
+            MessageType::GetMempool => {
+                let height = chain.lock().unwrap().height();
+                let mempool = connection.mempool.lock().unwrap();
+                let transactions: Vec<String> = mempool.get_pending_transactions().iter()
+                    .map(|transaction| transaction.to_hex())
+                    .collect();
+                let snapshot = MempoolSnapshot::from_mempool(&mempool, height);
+                drop(mempool);
+                
+                MessageResult::Response(NetworkMessage::new(MessageType::MempoolResponse {
+                    count: transactions.len(),
+                    transactions,
+                    entries: snapshot.entries,
+                }))
+            },
+            
+            MessageType::GetNodeStats => {
+                let connections = peers.lock().unwrap().len();
+                let blocks = chain.lock().unwrap().block_count();
+                let stats = connection.traffic.lock().unwrap().snapshot(connections, blocks);
+                MessageResult::Response(NetworkMessage::new(stats.to_response()))
+            },
+            
             MessageType::Ping => {
                 let response = NetworkMessage::new(MessageType::Pong);
                 MessageResult::Response(response)
@@ -289,181 +1161,1036 @@ impl NetworkServer {
         }
     }
     
-    /// Connect to a peer
-    pub fn connect_to_peer(&self, address: &str, port: u16) -> Result<(), NetworkError> {
-        let peer_address = format!("{}:{}", address, port);
-        let mut stream = TcpStream::connect(&peer_address)
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to connect to {}: {}", peer_address, e)))?;
+    /// Connect to a peer and keep the connection open on a background thread,
+    /// answering its messages like an inbound peer's. The peer is listed, and
+    /// counted as outbound, until the connection closes.
+    pub fn connect_to_peer(&self, peer_address: &PeerAddress) -> Result<(), NetworkError> {
+        if self.is_banned(&peer_address.host()) {
+            return Err(NetworkError::ConnectionFailed(format!("{} is banned for misbehavior", peer_address)));
+        }
+        let result = peer_address.connect()
+            .and_then(|mut stream| self.handshake(&mut stream, peer_address).map(|peer_info| (stream, peer_info)));
+        Self::update_peer_stats(&self.peer_stats, |stats, now| {
+            stats.record_connection(&peer_address.host(), result.is_ok(), now)
+        });
+        let (mut stream, peer_info) = result?;
+        let socket_addr = stream.peer_addr()
+            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to get peer address: {}", e)))?;
         
+        let context = self.connection_context();
+        let mut connection = PeerConnection::new(socket_addr, &context);
+        connection.capabilities = Some(peer_info.capabilities);
+        let node_id = peer_info.node_id.clone();
+        self.peers.lock().unwrap().insert(node_id.clone(), peer_info);
+        self.outbound.lock().unwrap().insert(peer_address.clone());
+        self.outbound_sockets.lock().unwrap().insert(node_id.clone(), socket_addr);
+        status!("Connected to peer at {} successfully", peer_address);
+        
+        let outbound = Arc::clone(&self.outbound);
+        let outbound_sockets = Arc::clone(&self.outbound_sockets);
+        let peer_address = peer_address.clone();
+        thread::spawn(move || {
+            if let Err(e) = Self::serve_connection(&mut stream, &context, &mut connection) {
+                eprintln!("Connection error: {}", e);
+            }
+            context.peers.lock().unwrap().remove(&node_id);
+            outbound.lock().unwrap().remove(&peer_address);
+            outbound_sockets.lock().unwrap().remove(&node_id);
+            status!("Outbound connection to {} closed", peer_address);
+        });
+        
+        Ok(())
+    }
+    
+    /// Exchange handshakes on a fresh connection, returning what the peer told us
+    fn handshake(&self, stream: &mut TcpStream, peer_address: &PeerAddress) -> Result<PeerInfo, NetworkError> {
         // Set timeout for handshake
         stream.set_read_timeout(Some(Duration::from_secs(10)))
             .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
         
-        // Send handshake
-        let chain_guard = self.chain.lock().unwrap();
-        let chain_height = chain_guard.blocks.len() as u64 - 1;
-        drop(chain_guard);
-        
-        let handshake = NetworkMessage::new(MessageType::Handshake {
-            version: PROTOCOL_VERSION,
-            node_id: self.node_id.clone(),
-            chain_height,
-        });
-        
-        Self::send_message(&mut stream, handshake)?;
+        let chain_height = self.chain.lock().unwrap().height();
+        let peer = Self::initiate_handshake(stream, peer_address, &self.identity, chain_height, self.services, self.listen_port(), &self.traffic)?;
+        status!("Received handshake response from peer {} (version: {}, features: {:?}, services: {:?}, height: {})", 
+            peer.node_id, peer.capabilities.version, peer.capabilities.features.names(), peer.services.names(), peer.chain_height);
+        Ok(peer)
+    }
+    
+    /// Open a handshake: send our challenge, check the peer's reply is signed over it,
+    /// then sign the challenge in the reply so the peer registers us
+    fn initiate_handshake(
+        stream: &mut TcpStream,
+        peer_address: &PeerAddress,
+        identity: &NodeIdentity,
+        chain_height: u64,
+        services: Services,
+        listen_port: u16,
+        traffic: &Mutex<TrafficMonitor>,
+    ) -> Result<PeerInfo, NetworkError> {
+        let challenge = generate_nonce();
+        Self::send_message(stream, Self::build_handshake(identity, chain_height, services, listen_port, &challenge, None), traffic)?;
         
         // Wait for handshake response
-        match Self::read_message(&mut stream) {
-            Ok(response) => {
-                if let MessageType::Handshake { version, node_id: peer_node_id, chain_height: peer_height } = response.message_type {
-                    println!("Received handshake response from peer {} (version: {}, height: {})", 
-                        peer_node_id, version, peer_height);
-                    
-                    // Add peer to our peer list
-                    let peer_info = PeerInfo {
-                        address: address.to_string(),
-                        port,
-                        node_id: peer_node_id,
-                        last_seen: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-                        chain_height: peer_height,
-                    };
-                    
-                    self.peers.lock().unwrap().insert(peer_info.node_id.clone(), peer_info);
-                    println!("Connected to peer at {} successfully", peer_address);
-                } else {
-                    return Err(NetworkError::ProtocolError("Expected handshake response".to_string()));
+        let response = Self::read_message(stream, traffic)
+            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to receive handshake response: {}", e)))?;
+        match response.message_type {
+            MessageType::VersionNegotiation { supported_versions, .. } => Err(NetworkError::ProtocolError(format!(
+                "Peer supports protocol versions {:?}, we support {}-{}",
+                supported_versions, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ))),
+            MessageType::Handshake { version, min_version, features, services, node_id: claimed_id, chain_height: peer_height, public_key, nonce, signature, .. } => {
+                let peer_node_id = Self::check_node_id(&claimed_id, &public_key)?;
+                NodeIdentity::verify_challenge(&public_key, HandshakeRole::Responder, &challenge, &signature)
+                    .map_err(NetworkError::InvalidMessage)?;
+                // A seed or learned address can point back at this node
+                if peer_node_id == identity.node_id() {
+                    return Err(NetworkError::ConnectionFailed(format!("{} is this node", peer_address)));
                 }
+                let capabilities = PeerCapabilities::negotiate(min_version, version, features)
+                    .map_err(NetworkError::ProtocolError)?;
+                Self::negotiate_compression(traffic, &Self::peer_of(stream), capabilities.features);
+                
+                let ack = MessageType::HandshakeAck { signature: identity.sign_challenge(HandshakeRole::Initiator, &nonce) };
+                Self::send_message(stream, NetworkMessage::new(ack), traffic)?;
+                
+                Ok(PeerInfo {
+                    address: peer_address.host(),
+                    port: peer_address.port(),
+                    node_id: peer_node_id,
+                    last_seen: self.clock.now(),
+                    chain_height: peer_height,
+                    capabilities,
+                    services,
+                    listen_port: peer_address.port(),
+                })
             },
-            Err(e) => {
-                return Err(NetworkError::ConnectionFailed(format!("Failed to receive handshake response: {}", e)));
-            }
+            _ => Err(NetworkError::ProtocolError("Expected handshake response".to_string())),
+        }
+    }
+    
+    /// Ask a peer for a snapshot of its mempool, handshaking first so the peer
+    /// answers mempool requests
+    pub fn fetch_mempool(&self, peer_address: &PeerAddress) -> Result<MempoolSnapshot, NetworkError> {
+        let (height, _, entries) = self.request_mempool(peer_address)?;
+        Ok(MempoolSnapshot::new(height, entries))
+    }
+    
+    /// The transactions in a peer's mempool, each with the fee rate and size
+    /// the peer gives it. Transactions the peer sent malformed are skipped.
+    pub fn fetch_mempool_transactions(&self, peer_address: &PeerAddress) -> Result<Vec<(Transaction, SnapshotEntry)>, NetworkError> {
+        let (_, transactions, entries) = self.request_mempool(peer_address)?;
+        let mut entries: HashMap<String, SnapshotEntry> = entries.into_iter()
+            .map(|entry| (entry.txid.clone(), entry))
+            .collect();
+        Ok(transactions.iter()
+            .filter_map(|data| Transaction::from_hex(data).ok())
+            .filter_map(|transaction| {
+                let entry = entries.remove(&transaction.hash())?;
+                Some((transaction, entry))
+            })
+            .collect())
+    }
+    
+    /// Peer height, transactions and entries of a peer's mempool
+    fn request_mempool(&self, peer_address: &PeerAddress) -> Result<(u64, Vec<String>, Vec<SnapshotEntry>), NetworkError> {
+        let mut stream = peer_address.connect()?;
+        let peer_info = self.handshake(&mut stream, peer_address)?;
+        if !peer_info.capabilities.features.contains(Features::TX_RELAY) {
+            return Err(NetworkError::ProtocolError(format!("Peer {} does not relay transactions", peer_address)));
         }
         
-        // Keep connection alive for a short time to establish the peer relationship
-        // In a real implementation, this would be managed by a connection pool
-        thread::sleep(Duration::from_millis(100));
+        Self::send_message(&mut stream, NetworkMessage::new(MessageType::GetMempool), &self.traffic)?;
+        match Self::read_message(&mut stream, &self.traffic)?.message_type {
+            MessageType::MempoolResponse { transactions, entries, .. } => Ok((peer_info.chain_height, transactions, entries)),
+            _ => Err(NetworkError::ProtocolError("Unexpected response to GetMempool".to_string())),
+        }
+    }
+
+    /// Blocks a peer has above `after_height`, oldest first and at most
+    /// `MAX_BLOCKS_PER_REQUEST` of them. They are not checked here; whoever
+    /// connects them validates them.
+    pub fn fetch_blocks_after(&self, peer_address: &PeerAddress, after_height: u64) -> Result<Vec<Block>, NetworkError> {
+        let mut stream = peer_address.connect()?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))
+            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
         
-        Ok(())
+        Self::send_message(&mut stream, NetworkMessage::new(MessageType::GetChainInfo), &self.traffic)?;
+        let height = match Self::read_message(&mut stream, &self.traffic)?.message_type {
+            MessageType::ChainInfo { height, .. } => height,
+            _ => return Err(NetworkError::ProtocolError("Unexpected response to GetChainInfo".to_string())),
+        };
+        if height <= after_height {
+            return Ok(Vec::new());
+        }
+        
+        Self::send_message(&mut stream, NetworkMessage::new(MessageType::GetBlockRange {
+            start_height: after_height + 1,
+            count: MAX_BLOCKS_PER_REQUEST,
+        }), &self.traffic)?;
+        match Self::read_message(&mut stream, &self.traffic)?.message_type {
+            MessageType::Blocks(blocks) => Ok(blocks),
+            _ => Err(NetworkError::ProtocolError("Unexpected response to GetBlockRange".to_string())),
+        }
+    }
+
+    /// Ask a peer for its chain tip, recording its height and the time of the
+    /// answer in the peer list. Returns the height.
+    ///
+    /// The request goes to the port the peer listens on. A height above ours
+    /// only counts once the peer backs it with the headers leading up to its
+    /// tip, linked and each meeting its proof of work, so a peer cannot set off
+    /// a sync by naming a height.
+    pub fn poll_chain_info(&self, peer: &PeerInfo) -> Result<u64, NetworkError> {
+        let mut stream = peer.listen_address()?.connect()?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))
+            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
+        
+        Self::send_message(&mut stream, NetworkMessage::new(MessageType::GetChainInfo), &self.traffic)?;
+        let (latest_hash, height) = match Self::read_message(&mut stream, &self.traffic)?.message_type {
+            MessageType::ChainInfo { latest_hash, height } => (latest_hash, height),
+            _ => return Err(NetworkError::ProtocolError("Unexpected response to GetChainInfo".to_string())),
+        };
+        
+        let (our_height, pow_limit_bits) = {
+            let chain_guard = self.chain.lock().unwrap();
+            (chain_guard.height(), chain_guard.params().pow_limit_bits)
+        };
+        if height > our_height {
+            let count = (height - our_height).min(MAX_HEADERS_PER_REQUEST as u64);
+            let start_height = height - count + 1;
+            Self::send_message(&mut stream, NetworkMessage::new(MessageType::GetBlockHeaders {
+                start_height,
+                count: count as u32,
+            }), &self.traffic)?;
+            let headers = match Self::read_message(&mut stream, &self.traffic)?.message_type {
+                MessageType::BlockHeaders { headers, .. } => headers,
+                _ => return Err(NetworkError::ProtocolError("Unexpected response to GetBlockHeaders".to_string())),
+            };
+            Self::check_header_chain(&headers, start_height, &latest_hash, height, pow_limit_bits)
+                .map_err(|e| NetworkError::ProtocolError(format!("Peer's claimed height {} is not backed by its headers: {}", height, e)))?;
+        }
+        
+        if let Some(known) = self.peers.lock().unwrap().get_mut(&peer.node_id) {
+            known.chain_height = height;
+            known.last_seen = self.clock.now();
+        }
+        Ok(height)
+    }
+
+    /// Check that `headers` run from `start_height` to a tip `tip_hash` at
+    /// `tip_height`, each linked to the one before and meeting its proof of
+    /// work within the limit `pow_limit_bits`
+    fn check_header_chain(
+        headers: &[BlockHeader],
+        start_height: u64,
+        tip_hash: &str,
+        tip_height: u64,
+        pow_limit_bits: u32,
+    ) -> Result<(), String> {
+        let pow = ProofOfWork::with_bits(pow_limit_bits);
+        let mut expected_height = start_height;
+        let mut previous: Option<&BlockHeader> = None;
+        for header in headers {
+            if header.height != expected_height {
+                return Err(format!("expected a header at height {}, got {}", expected_height, header.height));
+            }
+            if let Some(previous) = previous
+                && header.previous_hash != previous.hash
+            {
+                return Err(format!("header at height {} does not follow the one before", header.height));
+            }
+            if !pow.validate_hash(&header.hash, header.bits) {
+                return Err(format!("header at height {} does not meet its proof of work", header.height));
+            }
+            previous = Some(header);
+            expected_height += 1;
+        }
+        match previous {
+            Some(tip) if tip.height == tip_height && tip.hash == tip_hash => Ok(()),
+            _ => Err(format!("headers do not end at the tip {} at height {}", tip_hash, tip_height)),
+        }
+    }
+
+    /// Ask a connected peer for the peers it knows, for the dialer's address book.
+    /// The request goes to the port the peer listens on, which for a peer that
+    /// connected to us is not the one its connection comes from.
+    pub fn request_peers(&self, peer: &PeerInfo) -> Result<Vec<PeerInfo>, NetworkError> {
+        let mut stream = peer.listen_address()?.connect()?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))
+            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
+        
+        Self::send_message(&mut stream, NetworkMessage::new(MessageType::GetPeers), &self.traffic)?;
+        match Self::read_message(&mut stream, &self.traffic)?.message_type {
+            MessageType::Peers(peers) => Ok(peers),
+            _ => Err(NetworkError::ProtocolError("Unexpected response to GetPeers".to_string())),
+        }
+    }
+
+    /// Build a handshake for our identity issuing `challenge`, signed over the
+    /// peer's challenge when it answers one
+    fn build_handshake(
+        identity: &NodeIdentity,
+        chain_height: u64,
+        services: Services,
+        listen_port: u16,
+        challenge: &str,
+        answering: Option<&str>,
+    ) -> NetworkMessage {
+        NetworkMessage::new(MessageType::Handshake {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            features: LOCAL_FEATURES,
+            services,
+            node_id: identity.node_id(),
+            chain_height,
+            public_key: identity.public_key_hex(),
+            nonce: challenge.to_string(),
+            signature: answering
+                .map(|peer_challenge| identity.sign_challenge(HandshakeRole::Responder, peer_challenge))
+                .unwrap_or_default(),
+            listen_port,
+        })
+    }
+
+    /// Check a peer's claimed id matches its public key, returning the id
+    fn check_node_id(claimed_id: &str, public_key: &str) -> Result<String, NetworkError> {
+        let node_id = NodeIdentity::node_id_for_public_key(public_key)
+            .map_err(NetworkError::InvalidMessage)?;
+        if node_id != claimed_id {
+            return Err(NetworkError::InvalidMessage("Handshake node id does not match public key".to_string()));
+        }
+        Ok(node_id)
     }
 
     /// Synchronize blockchain with peers
     pub fn sync_blockchain(&self) -> Result<(), NetworkError> {
-        let peers_guard = self.peers.lock().unwrap();
-        if peers_guard.is_empty() {
+        let peers: Vec<PeerInfo> = self.peers.lock().unwrap().values().cloned().collect();
+        if peers.is_empty() {
             return Err(NetworkError::ConnectionFailed("No peers available for sync".to_string()));
         }
 
-        // Find the best peer (highest chain height)
-        let best_peer = peers_guard.values()
-            .max_by_key(|peer| peer.chain_height)
-            .cloned();
-        drop(peers_guard);
-
-        if let Some(peer) = best_peer {
-            let chain_guard = self.chain.lock().unwrap();
-            let our_height = chain_guard.blocks.len() as u64;
-            drop(chain_guard);
+        let chain_guard = self.chain.lock().unwrap();
+        let our_height = chain_guard.block_count();
+        drop(chain_guard);
 
+        if let Some(peer) = Self::select_sync_peer(&peers, our_height) {
             if peer.chain_height > our_height {
-                println!("Syncing with peer {} (height: {} vs our height: {})", 
-                    peer.address, peer.chain_height, our_height);
+                let ahead: Vec<PeerInfo> = peers.iter()
+                    .filter(|peer| peer.chain_height > our_height)
+                    .cloned()
+                    .collect();
+                if ahead.len() >= MIN_PARALLEL_SYNC_PEERS {
+                    status!("Downloading heights {} to {} from {} peers", our_height, peer.chain_height, ahead.len());
+                    self.download_in_parallel(&ahead, our_height, peer.chain_height, DownloadConfig::default())?;
+                }
+                
+                status!("Syncing with peer {} (height: {} vs our height: {})", 
+                    peer.address, peer.chain_height, self.chain.lock().unwrap().block_count());
                 
-                // Request blocks from where we left off
-                let peer_address = format!("{}:{}", peer.address, peer.port);
-                self.request_blocks_from_peer(&peer_address, our_height)?;
+                // Request blocks from where our chains split; after a parallel
+                // download this fetches whatever it left, and settles forks
+                self.request_blocks_from_peer(&peer.peer_address()?)?;
             } else {
-                println!("Blockchain is up to date");
+                status!("Blockchain is up to date");
             }
         }
 
         Ok(())
     }
 
-    /// Request blocks from a specific peer
-    fn request_blocks_from_peer(&self, peer_address: &str, _start_height: u64) -> Result<(), NetworkError> {
-        let mut stream = TcpStream::connect(peer_address)
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to connect for sync: {}", e)))?;
+    /// Download the blocks from `start_height` to `end_height` from several
+    /// peers at once, one thread per peer, connecting them in height order as
+    /// they arrive. Stops early when a block does not connect, for instance
+    /// because we are on a fork; the locator sync that follows takes over from
+    /// there. Returns the number of blocks connected.
+    fn download_in_parallel(&self, peers: &[PeerInfo], start_height: u64, end_height: u64, config: DownloadConfig) -> Result<usize, NetworkError> {
+        let scheduler = Arc::new(Mutex::new(DownloadScheduler::new(start_height, end_height, config)));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut addresses = HashMap::new();
+        for peer in peers {
+            let Ok(address) = peer.peer_address() else {
+                continue;
+            };
+            scheduler.lock().unwrap().add_peer(&address.to_string(), peer.chain_height);
+            let (scheduler, stop, traffic) = (Arc::clone(&scheduler), Arc::clone(&stop), Arc::clone(&self.traffic));
+            let worker_address = address.clone();
+            // Workers still waiting on a reply when the download ends exit once it arrives or times out
+            thread::spawn(move || Self::download_worker(worker_address, scheduler, stop, traffic, config.request_timeout));
+            addresses.insert(address.to_string(), address);
+        }
 
-        // Get our latest block hash
-        let chain_guard = self.chain.lock().unwrap();
-        let start_hash = if let Some(block) = chain_guard.blocks.last() {
-            block.header.hash.clone()
-        } else {
-            "0".repeat(64) // Genesis hash
+        let mut connected = 0;
+        let mut progress = Progress::new("Downloading blocks", end_height.saturating_sub(start_height)).with_unit("blocks");
+        let result = loop {
+            let (ready, finished) = {
+                let mut scheduler = scheduler.lock().unwrap();
+                for peer in scheduler.expire(Instant::now()) {
+                    println!("Warning: Peer {} did not send requested blocks in time", peer);
+                }
+                (scheduler.take_ready(), scheduler.is_finished())
+            };
+            let mut stalled = false;
+            for (peer, blocks) in ready {
+                let (first, count) = (blocks[0].header.height, blocks.len());
+                let synced = match self.process_sync_blocks(&addresses[&peer], blocks) {
+                    Ok(synced) => synced,
+                    Err(e) => {
+                        stop.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    },
+                };
+                connected += synced;
+                progress.inc(synced as u64);
+                if synced < count {
+                    status!("Blocks from {} at heights {}-{} did not all connect", peer, first, first + count as u64 - 1);
+                    stalled = true;
+                    break;
+                }
+            }
+            if stalled || finished {
+                progress.finish();
+                break Ok(connected);
+            }
+            thread::sleep(DOWNLOAD_POLL_INTERVAL);
         };
-        drop(chain_guard);
+        stop.store(true, Ordering::Relaxed);
+        result
+    }
+
+    /// Fetch windows from one peer for a parallel download until the download
+    /// ends or drops the peer. A failed request closes the connection; the next
+    /// one reconnects.
+    fn download_worker(
+        address: PeerAddress,
+        scheduler: Arc<Mutex<DownloadScheduler>>,
+        stop: Arc<AtomicBool>,
+        traffic: Arc<Mutex<TrafficMonitor>>,
+        timeout: Duration,
+    ) {
+        let peer = address.to_string();
+        let mut stream = None;
+        while !stop.load(Ordering::Relaxed) {
+            let request = {
+                let mut scheduler = scheduler.lock().unwrap();
+                if !scheduler.has_peer(&peer) || scheduler.is_finished() {
+                    return;
+                }
+                scheduler.next_request(&peer, Instant::now())
+            };
+            let Some(request) = request else {
+                thread::sleep(DOWNLOAD_POLL_INTERVAL);
+                continue;
+            };
 
-        // Request blocks
-        let get_blocks = NetworkMessage::new(MessageType::GetBlocks {
-            start_hash,
-            count: 100, // Request up to 100 blocks at a time
+            let result = Self::fetch_window(&address, &mut stream, request, &traffic, timeout);
+            let mut scheduler = scheduler.lock().unwrap();
+            match result {
+                Ok(blocks) => {
+                    if let Err(e) = scheduler.complete(&peer, request.start_height, blocks) {
+                        println!("Warning: {}", e);
+                    }
+                },
+                Err(e) => {
+                    println!("Warning: Failed to fetch blocks from {} at height {}: {}", peer, request.start_height, e);
+                    stream = None;
+                    scheduler.fail(&peer, request.start_height);
+                },
+            }
+        }
+    }
+
+    /// Ask a peer for one window of blocks, connecting first if needed
+    fn fetch_window(
+        address: &PeerAddress,
+        stream: &mut Option<TcpStream>,
+        request: WindowRequest,
+        traffic: &Mutex<TrafficMonitor>,
+        timeout: Duration,
+    ) -> Result<Vec<Block>, NetworkError> {
+        let stream = match stream {
+            Some(stream) => stream,
+            None => {
+                let connection = address.connect()?;
+                connection.set_read_timeout(Some(timeout))
+                    .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
+                stream.insert(connection)
+            },
+        };
+        Self::send_message(stream, NetworkMessage::new(MessageType::GetBlockRange {
+            start_height: request.start_height,
+            count: request.count,
+        }), traffic)?;
+        match Self::read_message(stream, traffic)?.message_type {
+            MessageType::Blocks(blocks) => Ok(blocks),
+            _ => Err(NetworkError::ProtocolError("Unexpected response to GetBlockRange".to_string())),
+        }
+    }
+
+    /// Pick the peer to sync from: the highest one, except that deep history is
+    /// fetched from the highest archive peer when one is available
+    pub fn select_sync_peer(peers: &[PeerInfo], our_height: u64) -> Option<PeerInfo> {
+        let best = peers.iter().max_by_key(|peer| peer.chain_height)?;
+        if best.chain_height.saturating_sub(our_height) <= DEEP_HISTORY_BLOCKS {
+            return Some(best.clone());
+        }
+
+        peers.iter()
+            .filter(|peer| peer.services.contains(Services::ARCHIVE) && peer.chain_height > our_height)
+            .max_by_key(|peer| peer.chain_height)
+            .or(Some(best))
+            .cloned()
+    }
+
+    /// Answer a block locator with the blocks after the last block we share with
+    /// the requester, at most `count` (capped at `MAX_BLOCKS_PER_REQUEST`)
+    pub fn locator_response(chain: &Chain, locator: &[String], count: u32) -> MessageType {
+        let fork_height = chain.locate_fork_point(locator).unwrap_or_else(|e| {
+            eprintln!("Failed to look up block locator: {}", e);
+            None
         });
+        let from = fork_height.map_or(0, |height| height + 1);
+        let count = count.min(MAX_BLOCKS_PER_REQUEST) as u64;
+        let blocks = match count {
+            0 => Vec::new(),
+            count => chain.blocks_in_range(from, from + count - 1)
+                .map(|blocks| blocks.into_owned())
+                .unwrap_or_default(),
+        };
+        let remaining = chain.block_count().saturating_sub(from + blocks.len() as u64);
+        
+        MessageType::LocatorBlocks { fork_height, blocks, remaining }
+    }
+
+    /// Fetch the blocks a peer has beyond ours, a batch at a time. Every request
+    /// carries our block locator, so the peer finds where our chains split even
+    /// when we are on a fork; a peer branch with more work then replaces ours.
+    fn request_blocks_from_peer(&self, peer_address: &PeerAddress) -> Result<(), NetworkError> {
+        let mut stream = peer_address.connect()?;
+        // A peer branch forking below our tip, and the height and hash it forks from
+        let mut branch: Vec<Block> = Vec::new();
+        let mut fork = None;
+        let mut progress = Progress::unbounded("Syncing blocks").with_unit("blocks");
+
+        loop {
+            let mut locator = self.chain.lock().unwrap().block_locator()
+                .map_err(NetworkError::ProtocolError)?;
+            // Continue the branch after its last block, which the peer has on its chain
+            if let Some(last) = branch.last() {
+                locator.insert(0, last.header.hash.clone());
+            }
+            Self::send_message(&mut stream, NetworkMessage::new(MessageType::GetBlocksByLocator {
+                locator,
+                count: SYNC_BATCH_SIZE,
+            }), &self.traffic)?;
 
-        Self::send_message(&mut stream, get_blocks)?;
+            let (fork_height, blocks, remaining) = match Self::read_message(&mut stream, &self.traffic)?.message_type {
+                MessageType::LocatorBlocks { fork_height, blocks, remaining } => (fork_height, blocks, remaining),
+                _ => return Err(NetworkError::ProtocolError("Unexpected response to GetBlocksByLocator".to_string())),
+            };
+            if blocks.is_empty() {
+                break;
+            }
+            let fork_height = fork_height
+                .ok_or_else(|| NetworkError::ProtocolError("Peer shares no blocks with us".to_string()))?;
+            progress.inc(blocks.len() as u64);
+            progress.set_total(progress.position().saturating_add(remaining));
 
-        // Read response
-        match Self::read_message(&mut stream)? {
-            message if matches!(message.message_type, MessageType::Blocks(_)) => {
-                if let MessageType::Blocks(blocks) = message.message_type {
-                    self.process_sync_blocks(blocks)?;
+            if let Some(last) = branch.last() {
+                if fork_height != last.header.height {
+                    return Err(NetworkError::ProtocolError("Peer's chain changed during sync".to_string()));
+                }
+                branch.extend(blocks);
+                if branch.len() > MAX_FORK_SYNC_BLOCKS {
+                    return Err(NetworkError::ProtocolError(format!(
+                        "Peer's fork is longer than {} blocks", MAX_FORK_SYNC_BLOCKS
+                    )));
+                }
+            } else {
+                let chain_guard = self.chain.lock().unwrap();
+                if fork_height < chain_guard.height() {
+                    let fork_hash = chain_guard.block_hash_at(fork_height)
+                        .map_err(NetworkError::ProtocolError)?
+                        .unwrap_or_default();
+                    drop(chain_guard);
+                    status!("Peer {} is on a fork from height {}", peer_address, fork_height);
+                    fork = Some((fork_height, fork_hash));
+                    branch = blocks;
+                } else {
+                    drop(chain_guard);
+                    if self.process_sync_blocks(peer_address, blocks)? == 0 {
+                        break;
+                    }
                 }
-            },
-            _ => {
-                return Err(NetworkError::ProtocolError("Unexpected response to GetBlocks".to_string()));
+            }
+
+            if remaining == 0 {
+                break;
             }
         }
+        progress.finish();
 
-        Ok(())
+        match fork {
+            Some((fork_height, fork_hash)) => self.switch_to_branch(peer_address, fork_height, &fork_hash, branch),
+            None => Ok(()),
+        }
     }
 
-    /// Process blocks received during sync
-    fn process_sync_blocks(&self, blocks: Vec<Block>) -> Result<(), NetworkError> {
+    /// Process blocks received during sync, returning how many were connected
+    fn process_sync_blocks(&self, peer_address: &PeerAddress, blocks: Vec<Block>) -> Result<usize, NetworkError> {
+        let peer = peer_address.to_string();
         let mut chain_guard = self.chain.lock().unwrap();
         let mut synced_count = 0;
 
         for block in blocks {
+            // A block announced meanwhile may already be processed on another connection
+            let Some(claim) = BlockClaim::acquire(&self.seen, &block.header.hash, &peer) else {
+                continue;
+            };
+            if chain_guard.get_block_by_hash(&block.header.hash).is_some() {
+                claim.finish();
+                continue;
+            }
+            self.alerts.check_block_time(&block, &peer);
+            
             // Validate and add block
-            if chain_guard.validate_block(&block) {
-                chain_guard.blocks.push(block.clone());
-                synced_count += 1;
-                println!("Synced block {} (height: {})", block.header.hash, block.header.height);
-            } else {
-                println!("Warning: Invalid block received during sync: {}", block.header.hash);
+            match Self::check_peer_block(&mut chain_guard, &self.checkpoints, &block) {
+                Ok(()) => {
+                    if !chain_guard.add_block(block.clone()) {
+                        println!("Warning: Failed to store synced block {}", block.header.hash);
+                        continue;
+                    }
+                    claim.finish();
+                    synced_count += 1;
+                    detail!("Synced block {} (height: {})", block.header.hash, block.header.height);
+                },
+                Err(e) if !e.is_misbehavior() => {
+                    detail!("Skipping synced block {} ({})", block.header.hash, e);
+                },
+                Err(e) => {
+                    println!("Warning: Invalid block received during sync: {} ({})", block.header.hash, e);
+                    self.alerts.record_invalid_block(&peer);
+                    self.record_misbehavior(peer_address, &format!("invalid block: {}", e));
+                    if e.is_permanent() {
+                        claim.finish();
+                    }
+                }
             }
         }
 
         drop(chain_guard);
-        println!("Successfully synced {} blocks", synced_count);
+        if synced_count > 0 {
+            Self::update_peer_stats(&self.peer_stats, |stats, now| {
+                stats.record_blocks(&peer_address.host(), synced_count as u64, now)
+            });
+        }
+        detail!("Successfully synced {} blocks", synced_count);
+        Ok(synced_count)
+    }
+
+    /// Replace our blocks above the fork point with a peer's branch if the
+    /// branch has more work and replaces no final block and nothing at or below
+    /// a passed checkpoint, as `ForkChoice` requires. Every block of the branch is checked before the
+    /// switch and connected through `Chain::reorganize`, which validates each in
+    /// turn. Subscribers hear of every disconnected and connected block and of
+    /// the reorg, and the disconnected transactions go back to the mempool where
+    /// they still validate.
+    fn switch_to_branch(&self, peer_address: &PeerAddress, fork_height: u64, fork_hash: &str, branch: Vec<Block>) -> Result<(), NetworkError> {
+        let peer = peer_address.to_string();
+        let known_invalid = {
+            let chain_guard = self.chain.lock().unwrap();
+            branch.iter().find_map(|block| chain_guard.rejected_reason(&block.header.hash)
+                .map(|reason| (block.header.height, reason.to_string())))
+        };
+        if let Some((height, reason)) = known_invalid {
+            self.alerts.record_invalid_block(&peer);
+            self.record_misbehavior(peer_address, &format!("known invalid block: {}", reason));
+            return Err(NetworkError::ProtocolError(format!(
+                "Peer's fork has a known invalid block at height {}: {}", height, reason
+            )));
+        }
+        if let Some(block) = branch.iter().find(|block| !self.checkpoints.check_block(block)) {
+            self.alerts.record_invalid_block(&peer);
+            self.record_misbehavior(peer_address, "fork conflicts with a checkpoint");
+            return Err(NetworkError::ProtocolError(format!(
+                "Peer's fork conflicts with checkpoint at height {}", block.header.height
+            )));
+        }
+        if let Some(block) = branch.iter().find(|block| !block.verify_integrity()) {
+            self.alerts.record_invalid_block(&peer);
+            self.record_misbehavior(peer_address, "fork has a hash or merkle root mismatch");
+            return Err(NetworkError::ProtocolError(format!(
+                "Peer's fork has a hash or merkle root mismatch at height {}", block.header.height
+            )));
+        }
+
+        let mut chain_guard = self.chain.lock().unwrap();
+        let mut our_work = 0u128;
+        chain_guard.scan_blocks(fork_height + 1, chain_guard.height(), |block| our_work = our_work.saturating_add(block.work()))
+            .map_err(NetworkError::ProtocolError)?;
+        let branch_work = branch.iter().fold(0u128, |work, block| work.saturating_add(block.work()));
+        if branch_work <= our_work {
+            status!("Keeping our chain: peer {}'s fork from height {} has less work", peer, fork_height);
+            return Ok(());
+        }
+        if let Err(e) = check_reorg_allowed(&self.checkpoints, self.finality_depth, fork_height + 1, chain_guard.height()) {
+            self.alerts.record_refused_reorg(&peer, &e);
+            return Err(NetworkError::ProtocolError(format!("Refusing peer's fork: {}", e)));
+        }
+
+        let old_tip = chain_guard.tip().map(|block| block.header.hash.clone()).unwrap_or_default();
+        let disconnected = chain_guard.reorganize(fork_hash, branch.clone()).map_err(|e| {
+            self.alerts.record_invalid_block(&peer);
+            self.record_misbehavior(peer_address, &format!("invalid fork: {}", e));
+            NetworkError::ProtocolError(e)
+        })?;
+        let (resubmitted, abandoned) = Self::resubmit_disconnected(&mut chain_guard, &self.mempool, &disconnected)
+            .map_err(NetworkError::ProtocolError)?;
+        drop(chain_guard);
+        Self::update_peer_stats(&self.peer_stats, |stats, now| {
+            stats.record_blocks(&peer_address.host(), branch.len() as u64, now)
+        });
+
+        for block in &disconnected {
+            self.events.publish(ChainEvent::BlockDisconnected(block.clone()));
+        }
+        let mut seen = self.seen.lock().unwrap();
+        for block in &branch {
+            seen.insert(&InventoryItem::block(block.header.hash.clone()));
+            self.events.publish(ChainEvent::BlockConnected(block.clone()));
+        }
+        drop(seen);
+        if let Some(new_tip) = branch.last() {
+            self.events.publish(ChainEvent::Reorg {
+                old_tip,
+                new_tip: new_tip.header.hash.clone(),
+                depth: disconnected.len() as u64,
+            });
+        }
+        status!("Switched to peer {}'s fork from height {}: disconnected {} blocks, connected {}; returned {} transaction(s) to the mempool, abandoned {}",
+            peer, fork_height, disconnected.len(), branch.len(), resubmitted, abandoned);
+        Ok(())
+    }
+
+    /// Return the transactions of blocks a reorg disconnected to the mempool,
+    /// marking the ones that no longer validate abandoned. Returns how many were
+    /// returned and how many abandoned.
+    fn resubmit_disconnected(chain: &mut Chain, mempool: &Mutex<Mempool>, disconnected: &[Block]) -> Result<(usize, usize), String> {
+        let (accepted, refused) = mempool.lock().unwrap().return_disconnected(chain, disconnected)?;
+        Ok((accepted.len(), refused.len()))
+    }
+
+    /// Remember that a peer we synced from broke the rules
+    fn record_misbehavior(&self, peer_address: &PeerAddress, reason: &str) {
+        Self::update_peer_stats(&self.peer_stats, |stats, now| stats.record_misbehavior(&peer_address.host(), reason, now));
+    }
+
+    /// Apply `update` to the peer stats database, if the node keeps one
+    fn update_peer_stats<F>(peer_stats: &Option<Arc<PeerStatsDb>>, update: F)
+    where
+        F: FnOnce(&PeerStatsDb, u64) -> Result<(), String>,
+    {
+        if let Some(peer_stats) = peer_stats
+            && let Err(e) = update(peer_stats, unix_now())
+        {
+            eprintln!("Warning: Failed to update peer stats: {}", e);
+        }
+    }
+
+    /// Check a block from a peer against our tip and the checkpoints. Every block
+    /// has its hash checked against its contents first, so a peer cannot get a
+    /// valid block refused by sending junk under its hash; checkpoints only add
+    /// a check on top. A block that conflicts with a checkpoint, breaks a rule
+    /// independent of the chain or does not meet its proof of work target is
+    /// remembered by the chain and refused straight away when it is offered again.
+    fn check_peer_block(chain: &mut Chain, checkpoints: &Checkpoints, block: &Block) -> Result<(), BlockRejection> {
+        if let Some(reason) = chain.rejected_reason(&block.header.hash) {
+            return Err(BlockRejection::Invalid(format!("known invalid block: {}", reason)));
+        }
+        if !block.verify_integrity() {
+            return Err(BlockRejection::Corrupt("hash or merkle root mismatch".to_string()));
+        }
+
+        let broken = if checkpoints.check_block(block) {
+            chain.check_block_rules(block).and_then(|_| chain.check_proof_of_work(block)).err()
+        } else {
+            Some(format!("conflicts with checkpoint at height {}", block.header.height))
+        };
+        if let Some(reason) = broken {
+            if let Err(e) = chain.record_rejected_block(&block.header.hash, &reason) {
+                eprintln!("Warning: Failed to record invalid block: {}", e);
+            }
+            return Err(BlockRejection::Invalid(reason));
+        }
+
+        if !chain.validate_block(block) {
+            return Err(BlockRejection::Unconnected("does not extend our chain".to_string()));
+        }
         Ok(())
     }
 
     /// Broadcast a block to all connected peers
     pub fn broadcast_block(&self, block: &Block) -> Result<(), NetworkError> {
+        self.seen.lock().unwrap().insert(&InventoryItem::block(block.header.hash.clone()));
+        
         let peers_guard = self.peers.lock().unwrap();
         let peers: Vec<_> = peers_guard.values().cloned().collect();
         drop(peers_guard);
 
         for peer in peers {
-            let peer_address = format!("{}:{}", peer.address, peer.port);
-            if let Err(e) = self.send_block_to_peer(&peer_address, block) {
-                eprintln!("Failed to broadcast block to peer {}: {}", peer_address, e);
-                // Continue with other peers
+            let acknowledged = match peer.peer_address().and_then(|peer_address| self.send_block_to_peer(&peer_address, block)) {
+                Ok(acknowledged) => acknowledged,
+                Err(e) => {
+                    eprintln!("Failed to broadcast block to peer {}: {}", peer.address, e);
+                    // Continue with other peers
+                    continue;
+                }
+            };
+            let mut propagation = self.propagation.lock().unwrap();
+            if propagation.record_relay(&block.header.hash, acknowledged, now_millis()) {
+                Self::update_peer_stats(&self.peer_stats, |stats, _| stats.save_propagation(&propagation));
             }
         }
 
         Ok(())
     }
 
-    /// Send a block to a specific peer
-    fn send_block_to_peer(&self, peer_address: &str, block: &Block) -> Result<(), NetworkError> {
-        let mut stream = TcpStream::connect(peer_address)
-            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to connect to peer: {}", e)))?;
+    /// Announce a block to a specific peer, sending the body only if the peer asks for it.
+    /// Returns whether the peer answered the announcement.
+    fn send_block_to_peer(&self, peer_address: &PeerAddress, block: &Block) -> Result<bool, NetworkError> {
+        let item = InventoryItem::block(block.header.hash.clone());
+        let lookup = |requested: &InventoryItem| {
+            (*requested == item).then(|| MessageType::NewBlock(block.clone()))
+        };
+        let our_height = self.chain.lock().unwrap().height();
+        Self::announce_to_peer(peer_address, &self.identity, our_height, self.services, self.listen_port(), vec![item.clone()], lookup, &self.traffic)
+    }
+    
+    /// Handshake so the peer knows which features we relay, then send an inventory announcement
+    /// and answer the peer's `GetData` with the bodies `lookup` finds.
+    /// Returns whether the peer answered the announcement.
+    #[allow(clippy::too_many_arguments)]
+    fn announce_to_peer<F>(
+        peer_address: &PeerAddress,
+        identity: &NodeIdentity,
+        our_height: u64,
+        services: Services,
+        listen_port: u16,
+        items: Vec<InventoryItem>,
+        lookup: F,
+        traffic: &Mutex<TrafficMonitor>,
+    ) -> Result<bool, NetworkError>
+    where
+        F: Fn(&InventoryItem) -> Option<MessageType>,
+    {
+        let mut stream = peer_address.connect()?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))
+            .map_err(|e| NetworkError::ConnectionFailed(format!("Failed to set timeout: {}", e)))?;
+        
+        Self::initiate_handshake(&mut stream, peer_address, identity, our_height, services, listen_port, traffic)?;
+        
+        let announced = items.len();
+        Self::send_message(&mut stream, NetworkMessage::new(MessageType::Inv(items)), traffic)?;
+        
+        match Self::read_message(&mut stream, traffic) {
+            Ok(NetworkMessage { message_type: MessageType::GetData(wanted), .. }) if !wanted.is_empty() => {
+                let mut sent = 0;
+                for payload in wanted.iter().filter_map(&lookup) {
+                    Self::send_message(&mut stream, NetworkMessage::new(payload), traffic)?;
+                    sent += 1;
+                }
+                detail!("Announced {} items to {}, sent {} requested", announced, peer_address, sent);
+                Ok(true)
+            },
+            Ok(_) => {
+                detail!("Peer {} already has all {} announced items", peer_address, announced);
+                Ok(true)
+            },
+            Err(NetworkError::Timeout) | Err(NetworkError::PeerDisconnected) => {
+                detail!("Peer {} did not answer the announcement of {} items", peer_address, announced);
+                Ok(false)
+            },
+            Err(e) => Err(e),
+        }
+    }
+    
+    /// Full message for an inventory item: blocks from the chain, transactions from the
+    /// chain or the ones we are relaying
+    fn inventory_payload(chain: &Chain, relay: &InventoryRelay, item: &InventoryItem) -> Option<MessageType> {
+        match item.inv_type {
+            InventoryType::Block => chain.find_block(&item.hash).ok().flatten()
+                .map(MessageType::NewBlock),
+            InventoryType::Transaction => {
+                let tx = match chain.get_transaction(&item.hash) {
+                    Ok(Some(tx)) => tx,
+                    _ => relay.transaction(&item.hash)?.clone(),
+                };
+                Some(MessageType::NewTransaction {
+                    transaction_data: tx.to_hex(),
+                    from_address: tx.from.clone(),
+                    to_address: tx.to.clone(),
+                    amount: tx.amount,
+                    signature: hex::encode(&tx.signature),
+                    fee_per_byte: paid_fee_per_byte(&tx),
+                })
+            },
+        }
+    }
+    
+    /// Send a transaction to a single peer without joining the network
+    pub fn relay_transaction(peer_address: &PeerAddress, transaction: &Transaction) -> Result<(), NetworkError> {
+        let mut stream = peer_address.connect()?;
+        
+        let message = NetworkMessage::new(MessageType::NewTransaction {
+            transaction_data: transaction.to_hex(),
+            from_address: transaction.from.clone(),
+            to_address: transaction.to.clone(),
+            amount: transaction.amount,
+            signature: hex::encode(&transaction.signature),
+            fee_per_byte: paid_fee_per_byte(transaction),
+        });
+        Self::write_message(&mut stream, &message)?;
+        detail!("Relayed transaction {} to {}", transaction.hash(), peer_address);
+        
+        Ok(())
+    }
 
-        let new_block = NetworkMessage::new(MessageType::NewBlock(block.clone()));
-        Self::send_message(&mut stream, new_block)?;
+    /// Uptime, resource use and traffic of the node right now
+    pub fn node_stats(&self) -> NodeStats {
+        let connections = self.peers.lock().unwrap().len();
+        let blocks = self.chain.lock().unwrap().block_count();
+        self.traffic.lock().unwrap().snapshot(connections, blocks)
+    }
 
-        println!("Broadcasted block {} to {}", block.header.hash, peer_address);
-        Ok(())
+    /// Add traffic counted since the last call to the peer stats database
+    pub fn save_peer_traffic(&self) {
+        let counts = self.traffic.lock().unwrap().peer_counts();
+        Self::update_peer_stats(&self.peer_stats, |stats, now| stats.record_traffic(&counts, now));
+    }
+
+    /// Order addresses to connect to by the peers' history, most reliable first,
+    /// leaving out ones that misbehaved lately. Without peer stats the order is kept.
+    pub fn rank_peers(&self, candidates: Vec<PeerAddress>) -> Vec<PeerAddress> {
+        let Some(peer_stats) = &self.peer_stats else {
+            return candidates;
+        };
+        match peer_stats.rank(candidates.clone(), unix_now()) {
+            Ok(ranked) => ranked,
+            Err(e) => {
+                eprintln!("Warning: Failed to rank peers: {}", e);
+                candidates
+            },
+        }
+    }
+
+    /// Number of connected peers
+    pub fn connection_count(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    /// Nodes added with `add_node`
+    pub fn added_nodes(&self) -> Vec<PeerAddress> {
+        self.added_nodes.lock().unwrap().clone()
+    }
+
+    /// Add a node to keep connected to, remove it again, or connect to it once.
+    /// An added node is connected to right away; if that fails it stays added
+    /// and the watchdog tries again.
+    pub fn add_node(&self, address: PeerAddress, command: AddNodeCommand) -> Result<(), PeerControlError> {
+        match command {
+            AddNodeCommand::Add => {
+                let mut added = self.added_nodes.lock().unwrap();
+                if added.contains(&address) {
+                    return Err(PeerControlError::AlreadyAdded(address));
+                }
+                added.push(address.clone());
+                drop(added);
+                if let Err(e) = self.connect_to_peer(&address) {
+                    eprintln!("Added node {} is not reachable yet: {}", address, e);
+                }
+                Ok(())
+            },
+            AddNodeCommand::Remove => {
+                let mut added = self.added_nodes.lock().unwrap();
+                let index = added.iter().position(|node| *node == address)
+                    .ok_or(PeerControlError::NotAdded(address))?;
+                added.remove(index);
+                Ok(())
+            },
+            AddNodeCommand::OneTry => self.connect_to_peer(&address)
+                .map_err(|e| PeerControlError::ConnectFailed(address, e.to_string())),
+        }
+    }
+
+    /// Drop the peers matching `address`, given as `host:port`, a bare host for
+    /// every port, or a node id. Their connections close at the next message or
+    /// keepalive check. Returns the peers dropped.
+    pub fn disconnect_node(&self, address: &str) -> Result<Vec<PeerInfo>, PeerControlError> {
+        let target = address.parse::<PeerAddress>().ok();
+        let matches = |peer: &PeerInfo| {
+            peer.node_id == address
+                || peer.address == address
+                || target.as_ref().is_some_and(|target| peer.peer_address().is_ok_and(|peer| peer == *target))
+        };
+        let mut peers = self.peers.lock().unwrap();
+        let removed: Vec<PeerInfo> = peers.values().filter(|peer| matches(peer)).cloned().collect();
+        if removed.is_empty() {
+            return Err(PeerControlError::NotConnected(address.to_string()));
+        }
+        peers.retain(|_, peer| !matches(peer));
+        drop(peers);
+        
+        // Only open connections get a request, and the announcements lock is held
+        // while adding it, so a connection closing meanwhile removes it on its way out
+        let outbound_sockets = self.outbound_sockets.lock().unwrap();
+        let open = self.announcements.lock().unwrap();
+        let mut requests = self.disconnect_requests.lock().unwrap();
+        for peer in &removed {
+            let socket_addr = outbound_sockets.get(&peer.node_id).copied()
+                .or_else(|| peer.address.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, peer.port)));
+            if let Some(socket_addr) = socket_addr.filter(|addr| open.contains_key(addr)) {
+                requests.insert(socket_addr);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Height of our chain tip
+    pub fn chain_height(&self) -> u64 {
+        self.chain.lock().unwrap().height()
+    }
+
+    /// The chain the server serves, for connecting blocks mined next to it
+    pub fn chain(&self) -> Arc<Mutex<Chain>> {
+        Arc::clone(&self.chain)
+    }
+
+    /// Address the listener is bound to, once `start` has bound it
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
+    }
+
+    /// Port peers are told we accept connections on, 0 until the listener is bound
+    fn listen_port(&self) -> u16 {
+        self.local_addr().map(|addr| addr.port()).unwrap_or(0)
+    }
+
+    /// Addresses we dialed whose connection is still open
+    pub fn outbound_connections(&self) -> Vec<PeerAddress> {
+        self.outbound.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Whether `host` misbehaved too often lately to be connected to, in either
+    /// direction. Without peer stats no host is banned.
+    pub fn is_banned(&self, host: &str) -> bool {
+        let Some(peer_stats) = &self.peer_stats else {
+            return false;
+        };
+        match peer_stats.get(host) {
+            Ok(record) => record.is_some_and(|record| record.is_avoided(unix_now())),
+            Err(e) => {
+                eprintln!("Warning: Failed to check ban status of {}: {}", host, e);
+                false
+            },
+        }
     }
 
     /// Get list of connected peers
@@ -478,7 +2205,7 @@ impl NetworkServer {
         let chain_guard = self.chain.lock().unwrap();
         
         let connected_peers = peers_guard.len();
-        let our_height = chain_guard.blocks.len() as u64;
+        let our_height = chain_guard.block_count();
         let max_peer_height = peers_guard.values()
             .map(|p| p.chain_height)
             .max()
@@ -489,7 +2216,7 @@ impl NetworkServer {
             our_chain_height: our_height,
             max_peer_height,
             is_synced: our_height >= max_peer_height,
-            node_id: self.node_id.clone(),
+            node_id: self.identity.node_id(),
         }
     }
 }
@@ -502,4 +2229,45 @@ pub struct NetworkStats {
     pub max_peer_height: u64,
     pub is_synced: bool,
     pub node_id: String,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertKind;
+
+    /// Blocks from `parent` up to `height`, told apart from other branches by `nonce`
+    fn branch(parent: &Block, height: u64, nonce: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for h in parent.header.height + 1..=height {
+            let previous_hash = blocks.last().unwrap_or(parent).header.hash.clone();
+            blocks.push(Block::new(previous_hash, vec![], nonce, h, h));
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_deep_fork_is_refused() {
+        let mut chain = Chain::new();
+        let genesis = chain.tip().unwrap().clone();
+        for block in branch(&genesis, 7, 0) {
+            assert!(chain.add_block(block));
+        }
+        let fork_point = chain.block_at(5).unwrap().unwrap();
+        let server = NetworkServer::new(chain, "127.0.0.1".to_string(), 0);
+        let peer: PeerAddress = "127.0.0.1:1".parse().unwrap();
+        let tip = || server.chain().lock().unwrap().tip().unwrap().header.clone();
+
+        // A heavier fork from genesis would replace final blocks
+        let old_tip = tip();
+        let result = server.switch_to_branch(&peer, 0, &genesis.header.hash, branch(&genesis, 8, 1));
+        assert!(result.unwrap_err().to_string().contains("final blocks"));
+        assert_eq!(tip().hash, old_tip.hash);
+        let kinds: Vec<AlertKind> = server.alerts.log().alerts().iter().map(|alert| alert.kind).collect();
+        assert_eq!(kinds, vec![AlertKind::DeepReorg]);
+
+        // Replacing the two blocks above height 5 is allowed
+        let shallow = branch(&fork_point, 8, 1);
+        server.switch_to_branch(&peer, 5, &fork_point.header.hash, shallow.clone()).unwrap();
+        assert_eq!(tip().hash, shallow.last().unwrap().header.hash);
+    }
+}
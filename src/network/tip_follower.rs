@@ -0,0 +1,95 @@
+//! Following a node's chain tip and mempool from another process
+//!
+//! A command such as `mine-mempool` runs in its own process and cannot see the
+//! blocks and transactions a running node receives. The follower asks one peer
+//! for its chain tip and mempool every interval. Each block the peer has above
+//! the height it last saw is published as `ChainEvent::BlockConnected` on an
+//! event bus, where a `TipWatcher` picks it up like a block connected locally,
+//! and each transaction not seen before is sent as
+//! `MempoolEvent::TransactionAdded`, as a local mempool would announce it.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::blockchain::events::{ChainEvent, EventBus};
+use crate::mempool::MempoolEvent;
+use crate::network::address::PeerAddress;
+use crate::network::server::NetworkServer;
+
+/// Default time between two requests for the peer's tip
+pub const DEFAULT_TIP_FOLLOW_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the follower thread checks whether it was stopped
+const TIP_FOLLOW_TICK: Duration = Duration::from_millis(100);
+
+/// Publishes the blocks a peer connects above our tip and the transactions that
+/// enter its mempool. The thread stops when the follower is dropped.
+pub struct TipFollower {
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TipFollower {
+    /// Follow `peer` from the height of the server's chain, asking through
+    /// `server` every `interval`. Transactions whose txid is in `known` are
+    /// not announced.
+    pub fn start(
+        server: NetworkServer,
+        peer: PeerAddress,
+        events: EventBus,
+        transactions: Sender<MempoolEvent>,
+        known: HashSet<String>,
+        interval: Duration,
+    ) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stopped = Arc::clone(&stopped);
+            thread::spawn(move || {
+                let mut height = server.chain_height();
+                let mut seen = known;
+                let mut next_poll = Instant::now() + interval;
+                while !stopped.load(Ordering::Relaxed) {
+                    if Instant::now() < next_poll {
+                        thread::sleep(TIP_FOLLOW_TICK);
+                        continue;
+                    }
+                    next_poll = Instant::now() + interval;
+                    match server.fetch_blocks_after(&peer, height) {
+                        Ok(blocks) => for block in blocks {
+                            height = height.max(block.header.height);
+                            events.publish(ChainEvent::BlockConnected(block));
+                        },
+                        Err(e) => eprintln!("Warning: Failed to ask {} for new blocks: {}", peer, e),
+                    }
+                    match server.fetch_mempool_transactions(&peer) {
+                        Ok(entries) => for (transaction, entry) in entries {
+                            if seen.insert(entry.txid) {
+                                let _ = transactions.send(MempoolEvent::TransactionAdded {
+                                    transaction,
+                                    fee_per_byte: entry.fee_per_byte,
+                                    size_bytes: entry.size,
+                                });
+                            }
+                        },
+                        Err(e) => eprintln!("Warning: Failed to ask {} for its mempool: {}", peer, e),
+                    }
+                }
+            })
+        };
+
+        TipFollower { stopped, handle: Some(handle) }
+    }
+}
+
+impl Drop for TipFollower {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Bandwidth and resource accounting for a running node
+//!
+//! The server counts every message it sends or receives, in bytes on the wire,
+//! per peer and per message type. Together with the process uptime, memory and
+//! CPU use these make up the node's stats, which answer `GetNodeStats` and are
+//! written to `node_stats.json` in the data directory periodically, where
+//! `network-stats` and the RPC metrics endpoint read them.
+//!
+//! The monitor also knows which peers negotiated compression, since every send
+//! already goes through it, and counts how much compressed messages saved.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::network::protocol::{MessageType, PROTOCOL_VERSION};
+use crate::network::server::NetworkServer;
+
+/// File in the data directory holding the running node's latest stats
+pub const NODE_STATS_FILE: &str = "node_stats.json";
+
+/// Default time between two writes of the stats file
+pub const DEFAULT_NODE_STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Clock ticks per second of the CPU times in `/proc/self/stat`, which Linux
+/// fixes at 100 for user space
+const CLOCK_TICKS_PER_SEC: f64 = 100.0;
+
+/// Messages and bytes exchanged with one peer, or of one message type
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficCounts {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+}
+
+impl TrafficCounts {
+    fn add_sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as u64;
+        self.messages_sent += 1;
+    }
+
+    fn add_received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
+        self.messages_received += 1;
+    }
+}
+
+/// Messages that went over the wire compressed, with their size before and after
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionCounts {
+    pub messages_sent: u64,
+    pub raw_bytes_sent: u64,
+    pub wire_bytes_sent: u64,
+    pub messages_received: u64,
+    pub raw_bytes_received: u64,
+    pub wire_bytes_received: u64,
+}
+
+impl CompressionCounts {
+    /// Bytes on the wire per uncompressed byte, in both directions; None until a
+    /// compressed message has been exchanged
+    pub fn ratio(&self) -> Option<f64> {
+        let raw = self.raw_bytes_sent + self.raw_bytes_received;
+        (raw > 0).then(|| (self.wire_bytes_sent + self.wire_bytes_received) as f64 / raw as f64)
+    }
+
+    /// Bytes compression kept off the wire
+    pub fn saved_bytes(&self) -> u64 {
+        (self.raw_bytes_sent + self.raw_bytes_received)
+            .saturating_sub(self.wire_bytes_sent + self.wire_bytes_received)
+    }
+}
+
+/// Traffic counted since the server was created
+#[derive(Debug)]
+pub struct TrafficMonitor {
+    started: Instant,
+    /// Unix time the monitor was created
+    started_at: u64,
+    totals: TrafficCounts,
+    /// By peer socket address, kept after the peer disconnects
+    peers: HashMap<String, TrafficCounts>,
+    messages: HashMap<&'static str, TrafficCounts>,
+    compression: CompressionCounts,
+    /// Socket addresses of peers that negotiated compression
+    compressing: HashSet<String>,
+}
+
+impl Default for TrafficMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrafficMonitor {
+    pub fn new() -> Self {
+        TrafficMonitor {
+            started: Instant::now(),
+            started_at: unix_now(),
+            totals: TrafficCounts::default(),
+            peers: HashMap::new(),
+            messages: HashMap::new(),
+            compression: CompressionCounts::default(),
+            compressing: HashSet::new(),
+        }
+    }
+
+    /// Count a message of `bytes` on the wire sent to `peer`
+    pub fn record_sent(&mut self, peer: &str, message: &MessageType, bytes: usize) {
+        self.totals.add_sent(bytes);
+        self.peers.entry(peer.to_string()).or_default().add_sent(bytes);
+        self.messages.entry(message.name()).or_default().add_sent(bytes);
+    }
+
+    /// Count a message of `bytes` on the wire received from `peer`
+    pub fn record_received(&mut self, peer: &str, message: &MessageType, bytes: usize) {
+        self.totals.add_received(bytes);
+        self.peers.entry(peer.to_string()).or_default().add_received(bytes);
+        self.messages.entry(message.name()).or_default().add_received(bytes);
+    }
+
+    /// Count a message sent compressed, `raw` bytes before and `wire` bytes after
+    pub fn record_compressed_sent(&mut self, raw: usize, wire: usize) {
+        self.compression.messages_sent += 1;
+        self.compression.raw_bytes_sent += raw as u64;
+        self.compression.wire_bytes_sent += wire as u64;
+    }
+
+    /// Count a message received compressed, `raw` bytes after inflating and `wire` bytes before
+    pub fn record_compressed_received(&mut self, raw: usize, wire: usize) {
+        self.compression.messages_received += 1;
+        self.compression.raw_bytes_received += raw as u64;
+        self.compression.wire_bytes_received += wire as u64;
+    }
+
+    /// Record whether the peer at `peer` negotiated compression in its handshake
+    pub fn set_compression(&mut self, peer: &str, enabled: bool) {
+        if enabled {
+            self.compressing.insert(peer.to_string());
+        } else {
+            self.compressing.remove(peer);
+        }
+    }
+
+    /// Whether large messages to `peer` are compressed
+    pub fn compresses(&self, peer: &str) -> bool {
+        self.compressing.contains(peer)
+    }
+
+    pub fn compression(&self) -> CompressionCounts {
+        self.compression
+    }
+
+    pub fn totals(&self) -> TrafficCounts {
+        self.totals
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Counts by peer socket address
+    pub fn peer_counts(&self) -> BTreeMap<String, TrafficCounts> {
+        self.peers.iter().map(|(peer, counts)| (peer.clone(), *counts)).collect()
+    }
+
+    /// Stats of the node right now
+    pub fn snapshot(&self, connections: usize, blocks: u64) -> NodeStats {
+        let uptime = self.uptime();
+        let cpu_usage = match process_cpu_seconds() {
+            Some(cpu) if uptime.as_secs_f64() > 0.0 => cpu / uptime.as_secs_f64() * 100.0,
+            _ => 0.0,
+        };
+        NodeStats {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            started_at: self.started_at,
+            taken_at: unix_now(),
+            uptime: uptime.as_secs(),
+            connections,
+            blocks,
+            memory_usage: process_memory_usage(),
+            cpu_usage,
+            totals: self.totals,
+            peers: self.peer_counts(),
+            messages: self.messages.iter().map(|(name, counts)| (name.to_string(), *counts)).collect(),
+            compression: self.compression,
+        }
+    }
+}
+
+/// Uptime, resource use and traffic of a node
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeStats {
+    pub version: String,
+    pub protocol_version: u32,
+    /// Unix time the node started
+    pub started_at: u64,
+    /// Unix time the stats were taken
+    pub taken_at: u64,
+    /// Seconds
+    pub uptime: u64,
+    pub connections: usize,
+    pub blocks: u64,
+    /// Resident memory in bytes, 0 where the platform does not report it
+    pub memory_usage: u64,
+    /// Average CPU use since startup, as a percentage of one core
+    pub cpu_usage: f64,
+    pub totals: TrafficCounts,
+    /// By peer socket address
+    pub peers: BTreeMap<String, TrafficCounts>,
+    /// By message type
+    pub messages: BTreeMap<String, TrafficCounts>,
+    /// Messages exchanged compressed; absent from stats written by older nodes
+    #[serde(default)]
+    pub compression: CompressionCounts,
+}
+
+impl NodeStats {
+    pub fn path_in(data_dir: &str) -> PathBuf {
+        Path::new(data_dir).join(NODE_STATS_FILE)
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize node stats: {}", e))?;
+        fs::write(path, data)
+            .map_err(|e| format!("Failed to write node stats: {}", e))
+    }
+
+    /// Load the stats a node wrote, or None if no node has run yet
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Option<Self>, String> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read node stats: {}", e))?;
+        serde_json::from_str(&data)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse node stats: {}", e))
+    }
+
+    /// Answer to a peer's `GetNodeStats`. Per-peer traffic is not shared.
+    pub fn to_response(&self) -> MessageType {
+        let transactions_processed = self.messages.get("NewTransaction")
+            .map_or(0, |counts| counts.messages_received);
+        MessageType::NodeStatsResponse {
+            version: self.version.clone(),
+            uptime: self.uptime,
+            connections: self.connections,
+            blocks: self.blocks,
+            transactions_processed,
+            bandwidth_in: self.totals.bytes_received,
+            bandwidth_out: self.totals.bytes_sent,
+            memory_usage: self.memory_usage,
+            cpu_usage: self.cpu_usage,
+            messages_in: self.totals.messages_received,
+            messages_out: self.totals.messages_sent,
+        }
+    }
+}
+
+/// Write the server's stats to `path` every `interval` on a background thread
+/// until the server stops
+pub fn write_node_stats(server: Arc<NetworkServer>, path: PathBuf, interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if !server.is_running() {
+            return;
+        }
+        if let Err(e) = server.node_stats().save_to_file(&path) {
+            eprintln!("Warning: {}", e);
+        }
+    })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Resident memory of this process in bytes, from `/proc/self/status` on Linux
+pub fn process_memory_usage() -> u64 {
+    fs::read_to_string("/proc/self/status").ok()
+        .and_then(|status| {
+            let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+            let kib = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+            Some(kib * 1024)
+        })
+        .unwrap_or(0)
+}
+
+/// User and system CPU seconds used by this process, from `/proc/self/stat` on Linux
+fn process_cpu_seconds() -> Option<f64> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name in parentheses may contain spaces; fields 14 and 15
+    // (utime, stime) follow it
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime = fields.get(11)?.parse::<u64>().ok()?;
+    let stime = fields.get(12)?.parse::<u64>().ok()?;
+    Some((utime + stime) as f64 / CLOCK_TICKS_PER_SEC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traffic_counts_and_response() {
+        let mut monitor = TrafficMonitor::new();
+        monitor.record_sent("10.0.0.1:8333", &MessageType::Ping, 40);
+        monitor.record_received("10.0.0.1:8333", &MessageType::Pong, 38);
+        monitor.record_received("10.0.0.2:8333", &MessageType::GetMempool, 45);
+
+        let stats = monitor.snapshot(2, 7);
+        assert_eq!(stats.totals, TrafficCounts { bytes_sent: 40, bytes_received: 83, messages_sent: 1, messages_received: 2 });
+        assert_eq!(stats.peers["10.0.0.1:8333"].bytes_received, 38);
+        assert_eq!(stats.peers["10.0.0.2:8333"].messages_received, 1);
+        assert_eq!(stats.messages["Ping"].messages_sent, 1);
+        assert_eq!(stats.messages["GetMempool"].bytes_received, 45);
+
+        assert_eq!(stats.compression.ratio(), None);
+        monitor.set_compression("10.0.0.1:8333", true);
+        assert!(monitor.compresses("10.0.0.1:8333") && !monitor.compresses("10.0.0.2:8333"));
+        monitor.record_compressed_sent(1_000, 200);
+        monitor.record_compressed_received(3_000, 800);
+        let compression = monitor.compression();
+        assert_eq!(compression.ratio(), Some(0.25));
+        assert_eq!(compression.saved_bytes(), 3_000);
+        monitor.set_compression("10.0.0.1:8333", false);
+        assert!(!monitor.compresses("10.0.0.1:8333"));
+
+        match stats.to_response() {
+            MessageType::NodeStatsResponse { connections, blocks, bandwidth_in, bandwidth_out, messages_in, .. } => {
+                assert_eq!((connections, blocks), (2, 7));
+                assert_eq!((bandwidth_in, bandwidth_out, messages_in), (83, 40, 2));
+            },
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+}
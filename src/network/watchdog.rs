@@ -0,0 +1,148 @@
+//! Chain stall and eclipse watchdog
+//!
+//! A node whose peers stop producing blocks, or whose connections have all been
+//! taken over by one operator, otherwise carries on quietly. The watchdog looks
+//! at the connected peers periodically and hands their addresses to the
+//! `AlertMonitor`, which raises an alert when they all sit in one subnet; stalls
+//! are detected by the monitor itself. While either lasts, the watchdog can
+//! reach out to the seed nodes again for peers outside the current set.
+//! Nodes added through `addnode` are reconnected on every look, stalled or not.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::alerts::AlertMonitor;
+use crate::network::address::PeerAddress;
+use crate::network::protocol::PeerInfo;
+use crate::network::server::NetworkServer;
+use crate::status;
+
+/// Default time between two looks at the connected peers
+pub const DEFAULT_WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default least time between two rounds of connecting to the seeds
+pub const DEFAULT_REDISCOVERY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Watches for stalls and a lack of peer diversity
+pub struct ChainWatchdog {
+    alerts: AlertMonitor,
+    /// Contacted while the node is stalled or eclipsed; none disables rediscovery
+    seeds: Vec<PeerAddress>,
+    rediscovery_interval: Duration,
+    last_rediscovery: Option<Instant>,
+}
+
+impl ChainWatchdog {
+    pub fn new(alerts: AlertMonitor) -> Self {
+        ChainWatchdog {
+            alerts,
+            seeds: Vec::new(),
+            rediscovery_interval: DEFAULT_REDISCOVERY_INTERVAL,
+            last_rediscovery: None,
+        }
+    }
+
+    /// Connect to these seeds while the node is stalled or eclipsed
+    pub fn with_rediscovery(mut self, seeds: Vec<PeerAddress>, interval: Duration) -> Self {
+        self.seeds = seeds;
+        self.rediscovery_interval = interval;
+        self
+    }
+
+    /// Check the connected peers, returning the seeds to connect to now. Peers
+    /// known by hostname only are left out of the diversity check.
+    pub fn check(&mut self, peers: &[PeerInfo], now: Instant) -> Vec<PeerAddress> {
+        let ips: Vec<IpAddr> = peers.iter().filter_map(|peer| peer.address.parse().ok()).collect();
+        let eclipsed = self.alerts.check_peer_diversity(&ips);
+        if !eclipsed && !self.alerts.is_stalled() {
+            return Vec::new();
+        }
+
+        let due = self.last_rediscovery
+            .is_none_or(|last| now.saturating_duration_since(last) >= self.rediscovery_interval);
+        if !due || self.seeds.is_empty() {
+            return Vec::new();
+        }
+        self.last_rediscovery = Some(now);
+        not_connected(&self.seeds, peers)
+    }
+
+    /// Check the server's peers every `interval` on a background thread,
+    /// connecting to the seeds when due, most reliable first, until the server stops
+    pub fn watch(mut self, server: Arc<NetworkServer>, interval: Duration) -> thread::JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if !server.is_running() {
+                return;
+            }
+            let peers = server.get_connected_peers();
+            for node in not_connected(&server.added_nodes(), &peers) {
+                status!("Watchdog: reconnecting to added node {}", node);
+                if let Err(e) = server.connect_to_peer(&node) {
+                    eprintln!("Watchdog: failed to connect to added node {}: {}", node, e);
+                }
+            }
+            let seeds = self.check(&peers, Instant::now());
+            for seed in server.rank_peers(seeds) {
+                status!("Watchdog: connecting to seed {} for more peers", seed);
+                if let Err(e) = server.connect_to_peer(&seed) {
+                    eprintln!("Watchdog: failed to connect to seed {}: {}", seed, e);
+                }
+            }
+        })
+    }
+}
+
+/// The addresses no connected peer is reached at
+fn not_connected(addresses: &[PeerAddress], peers: &[PeerInfo]) -> Vec<PeerAddress> {
+    addresses.iter()
+        .filter(|address| !peers.iter().any(|peer| peer.peer_address().is_ok_and(|peer| peer == **address)))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::{AlertConfig, AlertKind, AlertLog};
+
+    fn peer(address: &str, port: u16) -> PeerInfo {
+        PeerInfo {
+            address: address.to_string(),
+            port,
+            node_id: String::new(),
+            last_seen: 0,
+            chain_height: 0,
+            capabilities: Default::default(),
+            services: Default::default(),
+            listen_port: 0,
+        }
+    }
+
+    #[test]
+    fn test_eclipse_triggers_rediscovery() {
+        let alerts = AlertMonitor::new(AlertConfig::default(), AlertLog::new());
+        let seeds = vec![PeerAddress::new("10.1.0.1", 8333).unwrap(), PeerAddress::new("172.16.0.1", 8333).unwrap()];
+        let interval = Duration::from_secs(60);
+        let mut watchdog = ChainWatchdog::new(alerts.clone()).with_rediscovery(seeds.clone(), interval);
+        let now = Instant::now();
+
+        let diverse = vec![peer("10.1.0.1", 8333), peer("172.16.0.1", 8333), peer("192.168.1.1", 8333)];
+        assert!(watchdog.check(&diverse, now).is_empty());
+
+        // Every peer in 10.1.0.0/16: alert, and contact the seed not already connected
+        let eclipsed = vec![peer("10.1.0.1", 8333), peer("10.1.0.2", 8333), peer("10.1.3.4", 8333)];
+        assert_eq!(watchdog.check(&eclipsed, now), vec![seeds[1].clone()]);
+        assert!(watchdog.check(&eclipsed, now + interval / 2).is_empty());
+        assert_eq!(watchdog.check(&eclipsed, now + interval).len(), 1);
+
+        let kinds: Vec<AlertKind> = alerts.log().alerts().iter().map(|alert| alert.kind).collect();
+        assert_eq!(kinds, vec![AlertKind::PeerEclipse]);
+
+        // Without seeds the watchdog only raises the alert
+        let mut watchdog = ChainWatchdog::new(AlertMonitor::new(AlertConfig::default(), AlertLog::new()));
+        assert!(watchdog.check(&eclipsed, now).is_empty());
+    }
+}
@@ -0,0 +1,331 @@
+//! Chain notifications for external programs
+//!
+//! Like bitcoind's `-blocknotify`, hooks run a shell command or POST a JSON
+//! payload to a webhook when a block is connected, the chain reorganizes or a
+//! wallet transaction confirms. The notifier listens on the chain `EventBus`;
+//! a hook that fails or times out is retried before the failure is logged.
+
+use std::collections::HashSet;
+use std::process::Command;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::blockchain::events::ChainEvent;
+
+/// Default time a hook may run before it is abandoned
+pub const DEFAULT_NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of retries after a failed hook
+pub const DEFAULT_NOTIFY_RETRIES: u32 = 2;
+
+/// Pause before the first retry, doubled for each further one
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// What a hook is notified about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyKind {
+    Block,
+    Reorg,
+    WalletTransaction,
+}
+
+impl NotifyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotifyKind::Block => "block",
+            NotifyKind::Reorg => "reorg",
+            NotifyKind::WalletTransaction => "wallettx",
+        }
+    }
+}
+
+/// Where a notification is delivered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyTarget {
+    /// Shell command; `%s` is replaced by the block hash or transaction id
+    Command(String),
+    /// URL receiving the notification as a JSON POST
+    Webhook(String),
+}
+
+/// A target and the notifications it receives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifyHook {
+    pub kinds: Vec<NotifyKind>,
+    pub target: NotifyTarget,
+}
+
+/// Configured hooks with their timeout and retry handling
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifyConfig {
+    pub hooks: Vec<NotifyHook>,
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            hooks: Vec::new(),
+            timeout: DEFAULT_NOTIFY_TIMEOUT,
+            retries: DEFAULT_NOTIFY_RETRIES,
+        }
+    }
+}
+
+impl NotifyConfig {
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Run `command` for notifications of one kind
+    pub fn add_command(&mut self, kind: NotifyKind, command: &str) {
+        self.hooks.push(NotifyHook { kinds: vec![kind], target: NotifyTarget::Command(command.to_string()) });
+    }
+
+    /// POST every kind of notification to `url`
+    pub fn add_webhook(&mut self, url: &str) {
+        self.hooks.push(NotifyHook {
+            kinds: vec![NotifyKind::Block, NotifyKind::Reorg, NotifyKind::WalletTransaction],
+            target: NotifyTarget::Webhook(url.to_string()),
+        });
+    }
+}
+
+/// One notification to deliver
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub kind: NotifyKind,
+    /// Block hash or transaction id substituted for `%s` in commands
+    pub argument: String,
+    /// Body posted to webhooks
+    pub payload: Value,
+}
+
+/// Turns chain events into notifications and delivers them to the configured hooks
+pub struct Notifier {
+    config: NotifyConfig,
+    events: Receiver<ChainEvent>,
+    wallet_addresses: HashSet<String>,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig, events: Receiver<ChainEvent>, wallet_addresses: HashSet<String>) -> Self {
+        Notifier { config, events, wallet_addresses }
+    }
+
+    /// Notifications an event produces. Transactions in a connected block that
+    /// pay to or from a wallet address count as wallet confirmations.
+    pub fn notifications_for(&self, event: &ChainEvent) -> Vec<Notification> {
+        match event {
+            ChainEvent::BlockConnected(block) => {
+                let mut notifications = vec![Notification {
+                    kind: NotifyKind::Block,
+                    argument: block.header.hash.clone(),
+                    payload: json!({
+                        "event": NotifyKind::Block.name(),
+                        "hash": block.header.hash,
+                        "height": block.header.height,
+                    }),
+                }];
+                for tx in &block.transactions {
+                    if self.wallet_addresses.contains(&tx.to) || self.wallet_addresses.contains(&tx.from) {
+                        let txid = tx.hash();
+                        notifications.push(Notification {
+                            kind: NotifyKind::WalletTransaction,
+                            argument: txid.clone(),
+                            payload: json!({
+                                "event": NotifyKind::WalletTransaction.name(),
+                                "txid": txid,
+                                "from": tx.from,
+                                "to": tx.to,
+                                "amount": tx.amount,
+                                "block_hash": block.header.hash,
+                                "height": block.header.height,
+                            }),
+                        });
+                    }
+                }
+                notifications
+            },
+            ChainEvent::Reorg { old_tip, new_tip, depth } => vec![Notification {
+                kind: NotifyKind::Reorg,
+                argument: new_tip.clone(),
+                payload: json!({
+                    "event": NotifyKind::Reorg.name(),
+                    "old_tip": old_tip,
+                    "new_tip": new_tip,
+                    "depth": depth,
+                }),
+            }],
+            // Hooks hear about the new tip through the reorg or the next connected block
+            ChainEvent::BlockDisconnected(_) => Vec::new(),
+        }
+    }
+
+    /// Deliver notifications for every event received so far, returning how many events were handled
+    pub fn process_pending(&self) -> usize {
+        let events: Vec<ChainEvent> = self.events.try_iter().collect();
+        for event in &events {
+            self.dispatch(event);
+        }
+        events.len()
+    }
+
+    /// Deliver notifications for events arriving within `timeout`.
+    /// Returns false once every publisher is gone.
+    pub fn process_for(&self, timeout: Duration) -> bool {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => {
+                self.dispatch(&event);
+                self.process_pending();
+                true
+            },
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => false,
+        }
+    }
+
+    fn dispatch(&self, event: &ChainEvent) {
+        for notification in self.notifications_for(event) {
+            for hook in self.config.hooks.iter().filter(|hook| hook.kinds.contains(&notification.kind)) {
+                if let Err(e) = self.deliver(&hook.target, &notification) {
+                    eprintln!("Warning: {} notification for {} failed: {}", notification.kind.name(), notification.argument, e);
+                }
+            }
+        }
+    }
+
+    /// Deliver to one target, retrying with backoff
+    fn deliver(&self, target: &NotifyTarget, notification: &Notification) -> Result<(), String> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let result = match target {
+                NotifyTarget::Command(command) => run_command(command, &notification.argument, self.config.timeout),
+                NotifyTarget::Webhook(url) => post_webhook(url, &notification.payload, self.config.timeout),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.config.retries => {
+                    return Err(format!("{} (after {} attempts)", e, attempt + 1));
+                },
+                Err(_) => {
+                    attempt += 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                },
+            }
+        }
+    }
+}
+
+/// Run a hook command through the shell, killing it once `timeout` passes
+fn run_command(command: &str, argument: &str, timeout: Duration) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command.replace("%s", argument))
+        .spawn()
+        .map_err(|e| format!("Failed to run '{}': {}", command, e))?;
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => return Err(format!("'{}' exited with {}", command, status)),
+            None if started.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("'{}' timed out after {} ms", command, timeout.as_millis()));
+            },
+            None => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+/// POST a JSON payload, treating any non-2xx status as a failure
+fn post_webhook(url: &str, payload: &Value, timeout: Duration) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to create runtime: {}", e))?;
+
+    runtime.block_on(async {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let response = client.post(url).json(payload).send().await
+            .map_err(|e| format!("POST {} failed: {}", url, e))?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("POST {} returned {}", url, response.status()))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::block::{Block, Transaction};
+    use crate::blockchain::events::EventBus;
+    use std::fs;
+
+    fn block_paying(to: &str) -> Block {
+        let tx = Transaction {
+            from: "alice".to_string(),
+            to: to.to_string(),
+            amount: 5,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        Block::new("00".repeat(32), vec![tx], 0, 1, 1)
+    }
+
+    #[test]
+    fn test_wallet_transactions_are_notified() {
+        let bus = EventBus::new();
+        let wallet: HashSet<String> = ["mine".to_string()].into_iter().collect();
+        let notifier = Notifier::new(NotifyConfig::default(), bus.subscribe(), wallet);
+
+        let kinds = |block: Block| -> Vec<NotifyKind> {
+            notifier.notifications_for(&ChainEvent::BlockConnected(block)).iter().map(|n| n.kind).collect()
+        };
+        assert_eq!(kinds(block_paying("mine")), vec![NotifyKind::Block, NotifyKind::WalletTransaction]);
+        assert_eq!(kinds(block_paying("someone")), vec![NotifyKind::Block]);
+
+        let reorg = ChainEvent::Reorg { old_tip: "a".to_string(), new_tip: "b".to_string(), depth: 2 };
+        let notifications = notifier.notifications_for(&reorg);
+        assert_eq!(notifications[0].argument, "b");
+        assert_eq!(notifications[0].payload["depth"], 2);
+    }
+
+    #[test]
+    fn test_command_hook_retries_and_times_out() {
+        let out = std::env::temp_dir().join(format!("rust_chain_notify_{}", std::process::id()));
+        let _ = fs::remove_file(&out);
+
+        let bus = EventBus::new();
+        let mut config = NotifyConfig { timeout: Duration::from_millis(200), retries: 1, ..NotifyConfig::default() };
+        // Fails on the first attempt, succeeds on the retry
+        config.add_command(NotifyKind::Block, &format!(
+            "if [ -f {0} ]; then echo %s >> {0}; else touch {0}; exit 1; fi", out.display()
+        ));
+        let notifier = Notifier::new(config, bus.subscribe(), HashSet::new());
+
+        let block = block_paying("someone");
+        bus.publish(ChainEvent::BlockConnected(block.clone()));
+        assert_eq!(notifier.process_pending(), 1);
+        assert_eq!(fs::read_to_string(&out).unwrap().trim(), block.header.hash);
+        fs::remove_file(&out).unwrap();
+
+        let started = Instant::now();
+        assert!(run_command("sleep 5", "", Duration::from_millis(100)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
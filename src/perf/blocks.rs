@@ -0,0 +1,217 @@
+//! Per-block processing costs
+//!
+//! The area histograms show how validation and database writes perform overall,
+//! not what connecting one block costs. Every block a persistent chain connects
+//! is recorded here with its validation and persistence time and transaction
+//! count, in a process-wide window of the last `BLOCK_COST_WINDOW` blocks. A
+//! running node writes the window to `block_costs.json` alongside its perf
+//! report, and `perf-report` shows exact percentiles over it, so a slowdown in
+//! the validation pipeline shows up within a few hundred blocks.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::block::Block;
+
+/// File in the data directory holding the node's latest window of block costs
+pub const BLOCK_COSTS_FILE: &str = "block_costs.json";
+
+/// Blocks kept in the window
+pub const BLOCK_COST_WINDOW: usize = 1_000;
+
+/// What connecting one block cost
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockCost {
+    pub height: u64,
+    pub hash: String,
+    pub transactions: usize,
+    pub validation_micros: u64,
+    /// Writing the block, its indexes and chainstate
+    pub persistence_micros: u64,
+}
+
+impl BlockCost {
+    pub fn new(block: &Block, validation: Duration, persistence: Duration) -> Self {
+        BlockCost {
+            height: block.header.height,
+            hash: block.header.hash.clone(),
+            transactions: block.transactions.len(),
+            validation_micros: micros(validation),
+            persistence_micros: micros(persistence),
+        }
+    }
+}
+
+/// Percentiles of one measure over the window, by nearest rank
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CostPercentiles {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl CostPercentiles {
+    pub fn from_values(mut values: Vec<u64>) -> Self {
+        values.sort_unstable();
+        let at = |percentile: f64| {
+            let rank = ((percentile / 100.0) * values.len() as f64).ceil().max(1.0) as usize;
+            values.get(rank - 1).copied().unwrap_or(0)
+        };
+        CostPercentiles {
+            p50: at(50.0),
+            p95: at(95.0),
+            p99: at(99.0),
+            max: values.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Percentiles of every measure over the window
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BlockCostSummary {
+    pub blocks: usize,
+    pub validation_micros: CostPercentiles,
+    pub persistence_micros: CostPercentiles,
+    pub transactions: CostPercentiles,
+}
+
+/// The most recent block costs, oldest first
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BlockCostLog {
+    costs: VecDeque<BlockCost>,
+}
+
+impl BlockCostLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn path_in(data_dir: &str) -> PathBuf {
+        Path::new(data_dir).join(BLOCK_COSTS_FILE)
+    }
+
+    /// Add a block, dropping the oldest once the window is full
+    pub fn record(&mut self, cost: BlockCost) {
+        if self.costs.len() >= BLOCK_COST_WINDOW {
+            self.costs.pop_front();
+        }
+        self.costs.push_back(cost);
+    }
+
+    pub fn costs(&self) -> impl Iterator<Item = &BlockCost> {
+        self.costs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.costs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.costs.is_empty()
+    }
+
+    pub fn summary(&self) -> BlockCostSummary {
+        let values = |measure: fn(&BlockCost) -> u64| CostPercentiles::from_values(self.costs.iter().map(measure).collect());
+        BlockCostSummary {
+            blocks: self.costs.len(),
+            validation_micros: values(|cost| cost.validation_micros),
+            persistence_micros: values(|cost| cost.persistence_micros),
+            transactions: values(|cost| cost.transactions as u64),
+        }
+    }
+
+    /// The `count` blocks that took longest to validate and persist, slowest first
+    pub fn slowest(&self, count: usize) -> Vec<&BlockCost> {
+        let mut costs: Vec<&BlockCost> = self.costs.iter().collect();
+        costs.sort_by_key(|cost| std::cmp::Reverse(cost.validation_micros.saturating_add(cost.persistence_micros)));
+        costs.truncate(count);
+        costs
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let data = serde_json::to_string(self)
+            .map_err(|e| format!("Failed to serialize block costs: {}", e))?;
+        fs::write(path, data)
+            .map_err(|e| format!("Failed to write block costs: {}", e))
+    }
+
+    /// Load a saved window, or None if no node has written one yet
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Option<Self>, String> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read block costs: {}", e))?;
+        serde_json::from_str(&data)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse block costs: {}", e))
+    }
+}
+
+fn log() -> &'static Mutex<BlockCostLog> {
+    static LOG: OnceLock<Mutex<BlockCostLog>> = OnceLock::new();
+    LOG.get_or_init(|| Mutex::new(BlockCostLog::new()))
+}
+
+fn micros(elapsed: Duration) -> u64 {
+    elapsed.as_micros().min(u64::MAX as u128) as u64
+}
+
+/// Record what connecting a block cost this process
+pub fn record_block(cost: BlockCost) {
+    log().lock().unwrap().record(cost);
+}
+
+/// The window of block costs recorded by this process
+pub fn block_costs() -> BlockCostLog {
+    log().lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost(height: u64, validation_micros: u64, persistence_micros: u64) -> BlockCost {
+        BlockCost {
+            height,
+            hash: format!("{:064x}", height),
+            transactions: height as usize % 10,
+            validation_micros,
+            persistence_micros,
+        }
+    }
+
+    #[test]
+    fn test_window_percentiles() {
+        let mut log = BlockCostLog::new();
+        assert_eq!(log.summary(), BlockCostSummary::default());
+
+        for height in 1..=100 {
+            log.record(cost(height, height * 10, 5));
+        }
+        let summary = log.summary();
+        assert_eq!(summary.blocks, 100);
+        assert_eq!(summary.validation_micros, CostPercentiles { p50: 500, p95: 950, p99: 990, max: 1000 });
+        assert_eq!(summary.persistence_micros.p99, 5);
+        assert_eq!(summary.transactions.max, 9);
+
+        let slowest: Vec<u64> = log.slowest(2).iter().map(|cost| cost.height).collect();
+        assert_eq!(slowest, vec![100, 99]);
+    }
+
+    #[test]
+    fn test_window_drops_oldest() {
+        let mut log = BlockCostLog::new();
+        for height in 0..BLOCK_COST_WINDOW as u64 + 5 {
+            log.record(cost(height, 1, 1));
+        }
+        assert_eq!(log.len(), BLOCK_COST_WINDOW);
+        assert_eq!(log.costs().next().unwrap().height, 5);
+    }
+}
@@ -0,0 +1,263 @@
+//! Timing instrumentation
+//!
+//! Hot paths wrap their work in a `ScopedTimer`, which records the elapsed time
+//! into a per-area histogram when it goes out of scope. Histograms use
+//! power-of-two microsecond buckets, so recording is a lock and an increment and
+//! memory stays fixed however long the node runs. Percentiles are reported as
+//! the upper bound of the bucket they fall in.
+//!
+//! The histograms live in one process-wide registry, since the timed code is
+//! spread across storage, consensus and networking. A running node writes a
+//! report to `perf_stats.json` in its data directory periodically, which is
+//! what `perf-report` shows; `getperfstats` reports the RPC server's own timings.
+//! What each connected block cost is kept apart, in `blocks`.
+
+pub mod blocks;
+
+pub use blocks::{BlockCost, BlockCostLog, BlockCostSummary};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+/// Checking a block before connecting it to the chain
+pub const BLOCK_VALIDATION: &str = "block_validation";
+/// Writing blocks, indexes and chainstate to the database
+pub const DB_WRITE: &str = "db_write";
+/// Verifying transaction signatures
+pub const SIGNATURE_CHECK: &str = "signature_check";
+/// Handling one message from a peer
+pub const MESSAGE_HANDLING: &str = "message_handling";
+/// Searching for a proof of work
+pub const MINING: &str = "mining";
+
+/// File in the data directory holding the node's latest report
+pub const PERF_STATS_FILE: &str = "perf_stats.json";
+
+/// Default time between two reports written by a running node
+pub const DEFAULT_PERF_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Bucket `i` holds samples below 2^i microseconds; the last one holds the rest
+const BUCKET_COUNT: usize = 40;
+
+/// Durations recorded for one area
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: [u64; BUCKET_COUNT],
+    count: u64,
+    total_micros: u64,
+    max_micros: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: [0; BUCKET_COUNT],
+            count: 0,
+            total_micros: 0,
+            max_micros: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKET_COUNT - 1)] += 1;
+        self.count += 1;
+        self.total_micros = self.total_micros.saturating_add(micros);
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Upper bound in microseconds of the bucket holding the `percentile`th sample,
+    /// never more than the largest sample
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return (1u64 << bucket).saturating_sub(1).min(self.max_micros);
+            }
+        }
+        self.max_micros
+    }
+
+    /// Summary of the recorded durations
+    pub fn stats(&self, area: &str) -> PerfStats {
+        PerfStats {
+            area: area.to_string(),
+            count: self.count,
+            total_micros: self.total_micros,
+            mean_micros: self.total_micros.checked_div(self.count).unwrap_or(0),
+            p50_micros: self.percentile(50.0),
+            p90_micros: self.percentile(90.0),
+            p99_micros: self.percentile(99.0),
+            max_micros: self.max_micros,
+        }
+    }
+}
+
+/// Aggregated timings of one area
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PerfStats {
+    pub area: String,
+    pub count: u64,
+    pub total_micros: u64,
+    pub mean_micros: u64,
+    pub p50_micros: u64,
+    pub p90_micros: u64,
+    pub p99_micros: u64,
+    pub max_micros: u64,
+}
+
+/// Timings of every area recorded by one process
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PerfReport {
+    /// Unix time the process started recording
+    pub since: u64,
+    /// Unix time the report was taken
+    pub taken_at: u64,
+    /// Sorted by area
+    pub areas: Vec<PerfStats>,
+}
+
+impl PerfReport {
+    pub fn path_in(data_dir: &str) -> PathBuf {
+        Path::new(data_dir).join(PERF_STATS_FILE)
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize perf stats: {}", e))?;
+        fs::write(path, data)
+            .map_err(|e| format!("Failed to write perf stats: {}", e))
+    }
+
+    /// Load a saved report, or None if no node has written one yet
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Option<Self>, String> {
+        if !path.as_ref().exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read perf stats: {}", e))?;
+        serde_json::from_str(&data)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse perf stats: {}", e))
+    }
+}
+
+struct Registry {
+    since: u64,
+    histograms: BTreeMap<&'static str, Histogram>,
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(Registry {
+        since: unix_now(),
+        histograms: BTreeMap::new(),
+    }))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Record one duration for `area`
+pub fn record(area: &'static str, elapsed: Duration) {
+    registry().lock().unwrap().histograms.entry(area).or_default().record(elapsed);
+}
+
+/// Start timing `area` until the returned timer is dropped
+pub fn timer(area: &'static str) -> ScopedTimer {
+    ScopedTimer { area, start: Instant::now() }
+}
+
+/// Timings recorded by this process since it started
+pub fn report() -> PerfReport {
+    let registry = registry().lock().unwrap();
+    PerfReport {
+        since: registry.since,
+        taken_at: unix_now(),
+        areas: registry.histograms.iter().map(|(area, histogram)| histogram.stats(area)).collect(),
+    }
+}
+
+/// Write this process's report and block costs to `data_dir` every `interval`
+/// on a background thread
+pub fn write_reports(data_dir: &str, interval: Duration) -> thread::JoinHandle<()> {
+    let report_path = PerfReport::path_in(data_dir);
+    let costs_path = BlockCostLog::path_in(data_dir);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let result = report().save_to_file(&report_path)
+            .and_then(|_| blocks::block_costs().save_to_file(&costs_path));
+        if let Err(e) = result {
+            eprintln!("Warning: {}", e);
+        }
+    })
+}
+
+/// Records the time since it was created when dropped
+pub struct ScopedTimer {
+    area: &'static str,
+    start: Instant,
+}
+
+impl Drop for ScopedTimer {
+    fn drop(&mut self) {
+        record(self.area, self.start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), 0);
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        histogram.record(Duration::from_millis(5));
+
+        let stats = histogram.stats("test");
+        assert_eq!(stats.count, 101);
+        assert_eq!(stats.max_micros, 5000);
+        // The 51st sample, 51us, is in the bucket below 64us
+        assert_eq!(stats.p50_micros, 63);
+        assert_eq!(stats.p90_micros, 127);
+        assert_eq!(stats.p99_micros, 127);
+        assert_eq!(histogram.percentile(100.0), 5000);
+    }
+
+    #[test]
+    fn test_scoped_timer_records() {
+        const AREA: &str = "test_scoped_timer";
+        {
+            let _timer = timer(AREA);
+        }
+        record(AREA, Duration::from_millis(1));
+
+        let report = report();
+        let stats = report.areas.iter().find(|stats| stats.area == AREA).unwrap();
+        assert_eq!(stats.count, 2);
+        assert!(stats.max_micros >= 1000);
+    }
+}
@@ -0,0 +1,281 @@
+//! Progress reporting and output verbosity
+//!
+//! Long operations such as sync, reindexing, exports and backups report how far
+//! they are through a `Progress`. On a terminal it is a bar redrawn in place,
+//! with the percentage, the rate and the time left. When stderr is a file or a
+//! pipe, as for a daemonized node, it is a plain line every 10 seconds instead.
+//! Nothing is drawn for operations that finish within half a second. Progress
+//! goes to stderr so results on stdout can still be piped.
+//!
+//! `--quiet` and `--verbose` set the process-wide `Verbosity`. Quiet hides
+//! progress and the status lines printed with `status!`, leaving results and
+//! errors. Verbose adds the detail printed with `detail!` and a summary line
+//! for every finished operation.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long an operation runs before its progress is first drawn
+pub const PROGRESS_DELAY: Duration = Duration::from_millis(500);
+
+/// Least time between two redraws of a progress bar on a terminal
+pub const TERMINAL_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Least time between two progress lines when stderr is not a terminal
+pub const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Characters between the brackets of a progress bar
+const BAR_WIDTH: usize = 30;
+
+/// How much the CLI prints besides results and errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Results and errors only
+    Quiet,
+    Normal,
+    /// Also per-item detail and a summary of every long operation
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    }
+}
+
+/// Print a status line to stdout unless `--quiet` is set
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::progress::verbosity() > $crate::progress::Verbosity::Quiet {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print a line of detail to stdout only when `--verbose` is set
+#[macro_export]
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        if $crate::progress::verbosity() == $crate::progress::Verbosity::Verbose {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Where progress is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Hidden,
+    /// Redrawn in place on one line
+    Terminal,
+    /// A new line now and then
+    Lines,
+}
+
+/// Progress of one long operation, drawn on stderr as it advances
+pub struct Progress {
+    label: String,
+    unit: &'static str,
+    position: u64,
+    /// None until the size of the operation is known
+    total: Option<u64>,
+    started: Instant,
+    last_drawn: Option<Instant>,
+    output: Output,
+    finished: bool,
+}
+
+impl Progress {
+    /// Progress through `total` items
+    pub fn new(label: &str, total: u64) -> Self {
+        let output = if verbosity() == Verbosity::Quiet {
+            Output::Hidden
+        } else if io::stderr().is_terminal() {
+            Output::Terminal
+        } else {
+            Output::Lines
+        };
+        Progress {
+            label: label.to_string(),
+            unit: "items",
+            position: 0,
+            total: Some(total),
+            started: Instant::now(),
+            last_drawn: None,
+            output,
+            finished: false,
+        }
+    }
+
+    /// Progress through a number of items not known yet
+    pub fn unbounded(label: &str) -> Self {
+        let mut progress = Self::new(label, 0);
+        progress.total = None;
+        progress
+    }
+
+    /// Name the items counted, e.g. `blocks`
+    pub fn with_unit(mut self, unit: &'static str) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Count `count` more items done
+    pub fn inc(&mut self, count: u64) {
+        self.set_position(self.position.saturating_add(count));
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+        self.tick(Instant::now());
+    }
+
+    /// Change the number of items, once it is known or when it grows
+    pub fn set_total(&mut self, total: u64) {
+        self.total = Some(total);
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// End the operation, replacing the bar with a summary. Operations too
+    /// short to have drawn anything only print one with `--verbose`.
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        let drawn = self.last_drawn.is_some();
+        let summary = self.summary(Instant::now());
+        match self.output {
+            Output::Terminal if drawn => eprint!("\r{}\x1b[K\n", summary),
+            Output::Terminal | Output::Lines if drawn || verbosity() == Verbosity::Verbose => eprintln!("{}", summary),
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self, now: Instant) {
+        if self.finished || self.output == Output::Hidden
+            || now.saturating_duration_since(self.started) < PROGRESS_DELAY {
+            return;
+        }
+        let interval = match self.output {
+            Output::Terminal => TERMINAL_REDRAW_INTERVAL,
+            _ => PROGRESS_LOG_INTERVAL,
+        };
+        if self.last_drawn.is_some_and(|last| now.saturating_duration_since(last) < interval) {
+            return;
+        }
+        self.last_drawn = Some(now);
+
+        let line = self.render(now);
+        let mut stderr = io::stderr().lock();
+        let _ = match self.output {
+            Output::Terminal => write!(stderr, "\r{}\x1b[K", line).and_then(|_| stderr.flush()),
+            _ => writeln!(stderr, "{}", line),
+        };
+    }
+
+    /// The progress as one line, e.g.
+    /// `Syncing [=======>        ]  25% 250/1000 blocks, 25.0/s, ETA 30s`
+    pub fn render(&self, now: Instant) -> String {
+        let elapsed = now.saturating_duration_since(self.started).as_secs_f64();
+        let rate = if elapsed > 0.0 { self.position as f64 / elapsed } else { 0.0 };
+        match self.total {
+            Some(total) if total > 0 => {
+                let done = self.position.min(total);
+                let filled = (done as u128 * BAR_WIDTH as u128 / total as u128) as usize;
+                let bar = match filled {
+                    BAR_WIDTH => "=".repeat(BAR_WIDTH),
+                    filled => format!("{}>{}", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled - 1)),
+                };
+                let eta = if rate > 0.0 && done < total {
+                    format_duration(Duration::from_secs_f64((total - done) as f64 / rate))
+                } else {
+                    "-".to_string()
+                };
+                format!(
+                    "{} [{}] {:>3}% {}/{} {}, {:.1}/s, ETA {}",
+                    self.label, bar, done * 100 / total, self.position, total, self.unit, rate, eta
+                )
+            },
+            _ => format!("{} {} {}, {:.1}/s", self.label, self.position, self.unit, rate),
+        }
+    }
+
+    fn summary(&self, now: Instant) -> String {
+        let elapsed = now.saturating_duration_since(self.started);
+        let rate = match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.position as f64 / secs,
+            _ => 0.0,
+        };
+        format!("{}: {} {} in {} ({:.1}/s)", self.label, self.position, self.unit, format_duration(elapsed), rate)
+    }
+}
+
+impl Drop for Progress {
+    /// Move past a bar left on the terminal by an operation that failed
+    fn drop(&mut self) {
+        if self.output == Output::Terminal && self.last_drawn.is_some() && !self.finished {
+            eprintln!();
+        }
+    }
+}
+
+/// A duration in hours and minutes, minutes and seconds, or seconds: `1h02m`, `3m05s`, `12s`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        3600.. => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        60.. => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}s", secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_progress() {
+        let mut progress = Progress::new("Syncing", 1000).with_unit("blocks");
+        progress.inc(250);
+        let later = progress.started + Duration::from_secs(10);
+        assert_eq!(
+            progress.render(later),
+            format!("Syncing [=======>{}]  25% 250/1000 blocks, 25.0/s, ETA 30s", " ".repeat(22))
+        );
+
+        progress.set_position(1000);
+        assert_eq!(progress.render(later), format!("Syncing [{}] 100% 1000/1000 blocks, 100.0/s, ETA -", "=".repeat(30)));
+        // More items than expected fill the bar without overflowing it
+        progress.set_position(1200);
+        assert!(progress.render(later).contains("100% 1200/1000"));
+
+        let mut progress = Progress::unbounded("Downloading").with_unit("blocks");
+        progress.inc(40);
+        assert_eq!(progress.render(progress.started + Duration::from_secs(4)), "Downloading 40 blocks, 10.0/s");
+        progress.set_total(80);
+        assert!(progress.render(progress.started + Duration::from_secs(4)).contains(" 50% 40/80 blocks"));
+        progress.finish();
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(59)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+    }
+}
@@ -0,0 +1,92 @@
+//! Translation of mempool rejections into JSON-RPC errors
+//!
+//! Every reason the mempool or the relay policy can refuse a transaction has
+//! its own error code, so clients can tell a fee that is too low from a
+//! double spend without parsing messages. The message is the one the CLI
+//! prints, and `data.reason` carries the short reason name.
+
+use crate::mempool::{PolicyViolation, ValidationError};
+use crate::rpc::handlers::{error_codes, JsonRpcError};
+
+/// Error code reported for a rejected transaction
+pub fn validation_error_code(error: &ValidationError) -> i32 {
+    match error {
+        ValidationError::InvalidSignature => error_codes::INVALID_SIGNATURE,
+        ValidationError::InsufficientFunds => error_codes::INSUFFICIENT_FUNDS,
+        ValidationError::NegativeAmount => error_codes::NEGATIVE_AMOUNT,
+        ValidationError::SelfTransfer => error_codes::SELF_TRANSFER,
+        ValidationError::DuplicateTransaction => error_codes::DUPLICATE_TRANSACTION,
+        ValidationError::InvalidAddress => error_codes::INVALID_ADDRESS,
+        ValidationError::EmptyTransaction => error_codes::EMPTY_TRANSACTION,
+        ValidationError::ImmatureCoinbase => error_codes::IMMATURE_COINBASE,
+        ValidationError::NotYetValid => error_codes::TRANSACTION_NOT_YET_VALID,
+        ValidationError::Expired => error_codes::TRANSACTION_EXPIRED,
+        ValidationError::TooLongMempoolChain => error_codes::TOO_LONG_MEMPOOL_CHAIN,
+        ValidationError::VaultLocked => error_codes::VAULT_LOCKED,
+        ValidationError::AmountOutOfRange => error_codes::AMOUNT_OUT_OF_RANGE,
+        ValidationError::Policy(violation) => policy_error_code(violation),
+    }
+}
+
+/// Error code reported for a transaction refused by the relay policy
+pub fn policy_error_code(violation: &PolicyViolation) -> i32 {
+    match violation {
+        PolicyViolation::RelayDisabled => error_codes::RELAY_DISABLED,
+        PolicyViolation::FeeTooLow { .. } => error_codes::FEE_TOO_LOW,
+        PolicyViolation::TooLarge { .. } => error_codes::TRANSACTION_TOO_LARGE,
+        PolicyViolation::Dust { .. } => error_codes::DUST_AMOUNT,
+    }
+}
+
+impl From<ValidationError> for JsonRpcError {
+    fn from(error: ValidationError) -> Self {
+        JsonRpcError {
+            code: validation_error_code(&error),
+            message: format!("Transaction rejected: {}", error),
+            data: Some(serde_json::json!({ "reason": error.reason() })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_every_rejection_has_its_own_code() {
+        let errors = vec![
+            ValidationError::InvalidSignature,
+            ValidationError::InsufficientFunds,
+            ValidationError::NegativeAmount,
+            ValidationError::SelfTransfer,
+            ValidationError::DuplicateTransaction,
+            ValidationError::InvalidAddress,
+            ValidationError::EmptyTransaction,
+            ValidationError::ImmatureCoinbase,
+            ValidationError::NotYetValid,
+            ValidationError::Expired,
+            ValidationError::TooLongMempoolChain,
+            ValidationError::VaultLocked,
+            ValidationError::AmountOutOfRange,
+            ValidationError::Policy(PolicyViolation::RelayDisabled),
+            ValidationError::Policy(PolicyViolation::FeeTooLow { fee_per_byte: 0.5, minimum: 1.0 }),
+            ValidationError::Policy(PolicyViolation::TooLarge { size: 200, maximum: 100 }),
+            ValidationError::Policy(PolicyViolation::Dust { amount: 1, threshold: 5 }),
+        ];
+        let codes: HashSet<i32> = errors.iter().map(validation_error_code).collect();
+        let reasons: HashSet<&str> = errors.iter().map(|error| error.reason()).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(reasons.len(), errors.len());
+
+        // None of them collides with the codes for other failures
+        for code in [error_codes::INTERNAL_ERROR, error_codes::MEMPOOL_FULL, error_codes::DESERIALIZATION_ERROR] {
+            assert!(!codes.contains(&code));
+        }
+
+        let error = JsonRpcError::from(ValidationError::ImmatureCoinbase);
+        assert_eq!(error.code, error_codes::IMMATURE_COINBASE);
+        assert!(error.message.contains("confirmations"));
+        assert_eq!(error.data.unwrap()["reason"], "immature_coinbase");
+    }
+}
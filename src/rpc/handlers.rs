@@ -1,8 +1,24 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::blockchain::chain::Chain;
-use crate::mempool::Mempool;
+use crate::alerts::AlertLog;
+use crate::blockchain::amount::format_coins;
+use crate::blockchain::block::{Block, Transaction};
+use crate::blockchain::chain::{AddressSummary, Chain, TransactionStatus, UnspentOutput, DEFAULT_CHECK_BLOCKS, DEFAULT_CHECK_LEVEL, DEFAULT_RICHEST_ADDRESSES};
+use crate::clock::MockClock;
+use crate::consensus::fork_choice::ForkChoice;
+use crate::consensus::pow::{difficulty_info, estimate_network_hash_rate, DifficultyInfo, DEFAULT_HASHPS_WINDOW};
+use crate::mempool::{paid_fee_per_byte, BlockTemplate, FeeEstimator, Mempool, MempoolEntry};
+use crate::network::peer_stats::PeerStatsDb;
+use crate::network::propagation::PropagationTracker;
+use crate::network::server::{AddNodeCommand, NetworkServer, PeerControlError};
+use crate::rpc::server::RpcShutdown;
+use crate::network::PeerAddress;
+use crate::perf;
+use crate::wallet::history::wallet_history;
 use crate::wallet::keychain::Wallet;
 
 /// JSON-RPC 2.0 request structure
@@ -48,6 +64,61 @@ pub mod error_codes {
     pub const INSUFFICIENT_FUNDS: i32 = -1003;
     pub const INVALID_ADDRESS: i32 = -1004;
     pub const MEMPOOL_FULL: i32 = -1005;
+    pub const DESERIALIZATION_ERROR: i32 = -1006;
+    pub const INDEX_DISABLED: i32 = -1007;
+    pub const SERVER_BUSY: i32 = -1008;
+    pub const REQUEST_TIMEOUT: i32 = -1009;
+    pub const FORBIDDEN: i32 = -1010;
+    pub const WALLET_NOT_FOUND: i32 = -1011;
+    
+    // Transaction rejections, see `rpc::errors`
+    pub const INVALID_SIGNATURE: i32 = -1012;
+    pub const NEGATIVE_AMOUNT: i32 = -1013;
+    pub const SELF_TRANSFER: i32 = -1014;
+    pub const DUPLICATE_TRANSACTION: i32 = -1015;
+    pub const EMPTY_TRANSACTION: i32 = -1016;
+    pub const IMMATURE_COINBASE: i32 = -1017;
+    pub const TRANSACTION_NOT_YET_VALID: i32 = -1018;
+    pub const TRANSACTION_EXPIRED: i32 = -1019;
+    pub const TOO_LONG_MEMPOOL_CHAIN: i32 = -1020;
+    
+    // Relay policy rejections
+    pub const RELAY_DISABLED: i32 = -1021;
+    pub const FEE_TOO_LOW: i32 = -1022;
+    pub const TRANSACTION_TOO_LARGE: i32 = -1023;
+    pub const DUST_AMOUNT: i32 = -1024;
+    
+    // Spends from vaults before their unlock delay has passed
+    pub const VAULT_LOCKED: i32 = -1025;
+    
+    // Amounts or balances above the maximum money supply
+    pub const AMOUNT_OUT_OF_RANGE: i32 = -1026;
+    
+    // Peer management
+    pub const NETWORK_DISABLED: i32 = -1027;
+    pub const NODE_ALREADY_ADDED: i32 = -1028;
+    pub const NODE_NOT_ADDED: i32 = -1029;
+    pub const NODE_NOT_CONNECTED: i32 = -1030;
+}
+
+/// Methods that change node state. The server only accepts them from loopback
+/// addresses and runs them through `handle_privileged_request`.
+pub const PRIVILEGED_METHODS: &[&str] = &[
+    "invalidateblock", "reconsiderblock", "setmocktime", "addnode", "disconnectnode", "setmempoollimits", "stop",
+];
+
+/// Whether `method` is one of the `PRIVILEGED_METHODS`
+pub fn is_privileged(method: &str) -> bool {
+    PRIVILEGED_METHODS.contains(&method)
+}
+
+/// Methods that change the mempool. Anyone may call them, but like the
+/// privileged ones they run through `handle_privileged_request`.
+pub const MEMPOOL_WRITE_METHODS: &[&str] = &["sendrawtransaction"];
+
+/// Whether `method` needs mutable access to the node
+pub fn needs_write_access(method: &str) -> bool {
+    is_privileged(method) || MEMPOOL_WRITE_METHODS.contains(&method)
 }
 
 /// RPC method handler trait
@@ -58,48 +129,133 @@ pub trait RpcHandler: Send + Sync {
 /// Main RPC handler implementation
 pub struct BlockchainRpcHandler {
     pub chain: Chain,
-    pub mempool: Mempool,
+    /// Shared with the node's P2P server when the RPC server runs inside `start-node`
+    pub mempool: Arc<Mutex<Mempool>>,
     pub wallet: Wallet,
+    /// Named wallets served under `/wallet/<name>`
+    pub wallets: HashMap<String, Wallet>,
+    /// Node data directory, for records kept outside the chain (e.g. block propagation)
+    pub data_dir: Option<String>,
+    /// Clock of the chain and mempool on regtest nodes, set by `setmocktime`
+    pub mock_clock: Option<MockClock>,
+    /// P2P server of the node, when the RPC server runs inside `start-node`
+    pub network: Option<Arc<NetworkServer>>,
+    /// Competing branches seen by the node, reported by `getchaintips`
+    pub fork_choice: ForkChoice,
+    /// Stops the server answering requests, set by `RpcServer::start`
+    pub shutdown: Option<RpcShutdown>,
 }
 
 impl BlockchainRpcHandler {
     pub fn new(chain: Chain, mempool: Mempool, wallet: Wallet) -> Self {
         BlockchainRpcHandler {
             chain,
-            mempool,
+            mempool: Arc::new(Mutex::new(mempool)),
             wallet,
+            wallets: HashMap::new(),
+            data_dir: None,
+            mock_clock: None,
+            network: None,
+            fork_choice: ForkChoice::new(),
+            shutdown: None,
         }
     }
 
+    /// Serve named wallets alongside the default one
+    pub fn with_wallets(mut self, wallets: HashMap<String, Wallet>) -> Self {
+        self.wallets = wallets;
+        self
+    }
+
+    /// Read node records such as block propagation from `data_dir`
+    pub fn with_data_dir(mut self, data_dir: &str) -> Self {
+        self.data_dir = Some(data_dir.to_string());
+        self
+    }
+
+    /// Run the chain and mempool on `clock`, which `setmocktime` then sets
+    pub fn with_mock_clock(mut self, clock: MockClock) -> Self {
+        self.set_mock_clock(clock);
+        self
+    }
+
+    /// Answer peer methods such as `addnode` from `network`, and serve its mempool
+    pub fn with_network(mut self, network: Arc<NetworkServer>) -> Self {
+        self.set_network(network);
+        self
+    }
+
+    /// Use the P2P server of the node: its peers, and its mempool in place of
+    /// this handler's, so both serve the same pending transactions
+    pub fn set_network(&mut self, network: Arc<NetworkServer>) {
+        self.mempool = network.mempool();
+        self.network = Some(network);
+    }
+
+    fn mempool(&self) -> MutexGuard<'_, Mempool> {
+        self.mempool.lock().unwrap()
+    }
+
+    /// Report the branches tracked by `fork_choice` in `getchaintips`
+    pub fn with_fork_choice(mut self, fork_choice: ForkChoice) -> Self {
+        self.fork_choice = fork_choice;
+        self
+    }
+
+    pub fn set_mock_clock(&mut self, clock: MockClock) {
+        self.chain.set_clock(Arc::new(clock.clone()));
+        self.mempool().set_clock(Arc::new(clock.clone()));
+        self.mock_clock = Some(clock);
+    }
+
     /// Get blockchain info
     fn get_blockchain_info(&self) -> Result<Value, JsonRpcError> {
-        let block_count = self.chain.blocks.len();
-        let latest_block = self.chain.blocks.last();
+        let block_count = self.chain.block_count();
+        let latest_block = self.chain.tip();
         let latest_hash = if let Some(block) = latest_block {
             block.header.hash.clone()
         } else {
             "0".repeat(64)
         };
+        let difficulty = self.difficulty_info()?;
         
         let info = serde_json::json!({
             "chain": "rust-chain",
             "blocks": block_count,
             "headers": block_count,
             "bestblockhash": latest_hash,
-            "difficulty": 4,
+            "difficulty": difficulty.difficulty,
+            "bits": difficulty.bits,
+            "target": difficulty.target,
+            "nextretargetheight": difficulty.next_retarget_height,
+            "blocksuntilretarget": difficulty.blocks_until_retarget,
+            "timetoretarget": difficulty.estimated_seconds_to_retarget,
             "mediantime": 0,
             "verificationprogress": 1.0,
-            "chainwork": format!("{:016x}", block_count),
+            "chainwork": format!("{:032x}", self.chain.chain_work()),
             "size_on_disk": block_count * 1000, // Approximate
             "pruned": false
         });
         Ok(info)
     }
 
+    /// Get the difficulty required of the next block
+    fn get_difficulty(&self) -> Result<Value, JsonRpcError> {
+        Ok(serde_json::json!(self.difficulty_info()?.difficulty))
+    }
+
+    /// Difficulty the chain requires of the next block
+    fn difficulty_info(&self) -> Result<DifficultyInfo, JsonRpcError> {
+        difficulty_info(&self.chain).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        })
+    }
+
     /// Get block count
     fn get_block_count(&self) -> Result<Value, JsonRpcError> {
-        let height = self.chain.blocks.len().saturating_sub(1);
-        Ok(Value::Number(height.into()))
+        Ok(Value::Number(self.chain.height().into()))
     }
 
     /// Get block hash by height
@@ -120,8 +276,13 @@ impl BlockchainRpcHandler {
                 data: None,
             })?;
 
-        if let Some(block) = self.chain.blocks.get(height as usize) {
-            Ok(Value::String(block.header.hash.clone()))
+        let hash = self.chain.block_hash_at(height).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        })?;
+        if let Some(hash) = hash {
+            Ok(Value::String(hash))
         } else {
             Err(JsonRpcError {
                 code: error_codes::BLOCK_NOT_FOUND,
@@ -131,7 +292,9 @@ impl BlockchainRpcHandler {
         }
     }
 
-    /// Get block by hash
+    /// Get block by hash, as hex at verbosity 0, as JSON with transaction ids at
+    /// verbosity 1 (the default) or with every transaction decoded at verbosity 2.
+    /// `true` and `false` are taken as 1 and 0.
     fn get_block(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
         let params_array = params.as_ref()
             .and_then(|p| p.as_array())
@@ -148,23 +311,23 @@ impl BlockchainRpcHandler {
                 message: "Invalid hash parameter".to_string(),
                 data: None,
             })?;
+        let verbosity = match params_array.get(1) {
+            None => 1,
+            Some(Value::Bool(verbose)) => *verbose as u64,
+            Some(value) => value.as_u64().filter(|verbosity| *verbosity <= 2).ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Verbosity must be 0, 1 or 2".to_string(),
+                data: None,
+            })?,
+        };
 
-        for (i, block) in self.chain.blocks.iter().enumerate() {
-            if block.header.hash == hash_str {
-                let block_json = serde_json::json!({
-                    "hash": block.header.hash.clone(),
-                    "height": i,
-                    "previousblockhash": block.header.previous_hash.clone(),
-                    "merkleroot": block.header.merkle_root.clone(),
-                    "time": block.header.timestamp,
-                    "nonce": block.header.nonce,
-                    "difficulty": 4, // Fixed difficulty for now
-                    "tx": block.transactions.iter().enumerate().map(|(i, _)| format!("tx_{}", i)).collect::<Vec<_>>(),
-                    "size": 1000, // Approximate
-                    "weight": 4000 // Approximate
-                });
-                return Ok(block_json);
-            }
+        let block = self.chain.find_block(hash_str).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        })?;
+        if let Some(block) = block {
+            return Ok(Self::block_json(&block, verbosity));
         }
 
         Err(JsonRpcError {
@@ -174,197 +337,2352 @@ impl BlockchainRpcHandler {
         })
     }
 
-    /// Get mempool info
-    fn get_mempool_info(&self) -> Result<Value, JsonRpcError> {
-        let stats = self.mempool.get_stats();
-        let info = serde_json::json!({
-            "size": stats.pending_count,
-            "bytes": stats.total_size_bytes,
-            "usage": stats.total_size_bytes,
-            "maxmempool": 100_000_000, // 100MB limit
-            "mempoolminfee": 0.00001000,
-            "minrelaytxfee": 0.00001000
+    /// A block as `getblock` returns it at `verbosity`
+    pub fn block_json(block: &Block, verbosity: u64) -> Value {
+        if verbosity == 0 {
+            return Value::String(block.to_hex());
+        }
+        let tx: Vec<Value> = if verbosity >= 2 {
+            block.transactions.iter().map(Self::decode_transaction).collect()
+        } else {
+            block.transactions.iter().map(|tx| Value::String(tx.hash())).collect()
+        };
+        let mut block_json = serde_json::json!({
+            "hash": block.header.hash.clone(),
+            "height": block.header.height,
+            "previousblockhash": block.header.previous_hash.clone(),
+            "merkleroot": block.header.merkle_root.clone(),
+            "time": block.header.timestamp,
+            "nonce": block.header.nonce,
+            "difficulty": block.header.difficulty(&self.chain.params().pow_limit()),
+            "nTx": block.transactions.len(),
+            "tx": tx,
+            "size": block.serialize().len(),
         });
-        Ok(info)
+        if let Some(bits) = block.header.bits {
+            block_json["bits"] = Value::String(format!("{:08x}", bits));
+        }
+        block_json
     }
 
-    /// Get raw mempool
-    fn get_raw_mempool(&self) -> Result<Value, JsonRpcError> {
-        let transactions = self.mempool.get_pending_transactions();
-        let txids: Vec<String> = transactions.iter()
-            .enumerate()
-            .map(|(i, _)| format!("mempool_tx_{}", i))
-            .collect();
-        Ok(Value::Array(txids.into_iter().map(Value::String).collect()))
-    }
+    /// Get a block header by hash, as JSON or as hex when `verbose` is false.
+    /// Served from the header index, so the block's transactions are never loaded.
+    fn get_block_header(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let params_array = params.as_ref()
+            .and_then(|p| p.as_array())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [hash, verbose]".to_string(),
+                data: None,
+            })?;
+            
+        let hash_str = params_array.first()
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid hash parameter".to_string(),
+                data: None,
+            })?;
+        let verbose = params_array.get(1).and_then(|v| v.as_bool()).unwrap_or(true);
 
-    /// Get wallet balance
-    fn get_balance(&self) -> Result<Value, JsonRpcError> {
-        // Simplified balance - in a real implementation this would check UTXOs
-        let balance = 1000000; // 1 million satoshis
-        Ok(Value::Number(serde_json::Number::from(balance)))
-    }
+        let internal_error = |e: String| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        };
+        let header = self.chain.find_header(hash_str)
+            .map_err(internal_error)?
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::BLOCK_NOT_FOUND,
+                message: "Block not found".to_string(),
+                data: None,
+            })?;
 
-    /// Create a new address
-    fn get_new_address(&self) -> Result<Value, JsonRpcError> {
-        // Return a new address without mutating the wallet
-        Ok(Value::String(self.wallet.get_new_address_readonly()))
-    }
+        if !verbose {
+            return Ok(Value::String(header.to_hex()));
+        }
 
-    /// List transactions
-    fn list_transactions(&self) -> Result<Value, JsonRpcError> {
-        let mut transactions = Vec::new();
-        
-        // Add some sample transactions for demonstration
-        for (i, block) in self.chain.blocks.iter().enumerate() {
-            for (j, _tx) in block.transactions.iter().enumerate() {
-                transactions.push(serde_json::json!({
-                    "txid": format!("tx_{}_{}", i, j),
-                    "amount": 1000,
-                    "confirmations": self.chain.blocks.len() - i,
-                    "time": block.header.timestamp,
-                    "category": "receive"
-                }));
-            }
+        let next_hash = self.chain.block_hash_at(header.height + 1).map_err(internal_error)?;
+        let mut header_json = serde_json::json!({
+            "hash": header.hash,
+            "confirmations": self.chain.height() - header.height + 1,
+            "height": header.height,
+            "time": header.timestamp,
+            "nonce": header.nonce,
+            "difficulty": header.difficulty(&self.chain.params().pow_limit()),
+            "merkleroot": header.merkle_root,
+        });
+        if let Some(bits) = header.bits {
+            header_json["bits"] = Value::String(format!("{:08x}", bits));
         }
-        
-        Ok(Value::Array(transactions))
+        if header.height > 0 {
+            header_json["previousblockhash"] = Value::String(header.previous_hash);
+        }
+        if let Some(next_hash) = next_hash {
+            header_json["nextblockhash"] = Value::String(next_hash);
+        }
+        Ok(header_json)
     }
-}
 
-impl RpcHandler for BlockchainRpcHandler {
-    fn handle_request(&self, request: JsonRpcRequest) -> JsonRpcResponse {
-        let result = match request.method.as_str() {
-            "getblockchaininfo" => self.get_blockchain_info(),
-            "getblockcount" => self.get_block_count(),
-            "getblockhash" => self.get_block_hash(request.params),
-            "getblock" => self.get_block(request.params),
-            "getmempoolinfo" => self.get_mempool_info(),
-            "getrawmempool" => self.get_raw_mempool(),
-            "getbalance" => self.get_balance(),
-            "getnewaddress" => self.get_new_address(),
-            "listtransactions" => self.list_transactions(),
-            _ => Err(JsonRpcError {
-                code: error_codes::METHOD_NOT_FOUND,
-                message: format!("Method '{}' not found", request.method),
+    /// Get statistics for a block given by height or hash
+    fn get_block_stats(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let target = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|a| a.first())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [height] or [hash]".to_string(),
                 data: None,
-            }),
+            })?;
+
+        let height = if let Some(height) = target.as_u64() {
+            height
+        } else if let Some(hash) = target.as_str() {
+            self.chain.find_block(hash)
+                .ok()
+                .flatten()
+                .map(|block| block.header.height)
+                .ok_or_else(|| JsonRpcError {
+                    code: error_codes::BLOCK_NOT_FOUND,
+                    message: "Block not found".to_string(),
+                    data: None,
+                })?
+        } else {
+            return Err(JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [height] or [hash]".to_string(),
+                data: None,
+            });
         };
 
-        match result {
-            Ok(value) => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                result: Some(value),
-                error: None,
-                id: request.id,
-            },
-            Err(error) => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                result: None,
-                error: Some(error),
-                id: request.id,
-            },
-        }
+        let stats = self.chain.block_stats(height)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::BLOCK_NOT_FOUND,
+                message: e,
+                data: None,
+            })?;
+
+        Ok(serde_json::json!({
+            "height": stats.height,
+            "blockhash": stats.hash,
+            "time": stats.timestamp,
+            "txs": stats.transaction_count,
+            "total_size": stats.size_bytes,
+            "totalfee": stats.total_fees,
+            "totalfee_coins": format_coins(stats.total_fees),
+            "subsidy": stats.subsidy,
+            "subsidy_coins": format_coins(stats.subsidy),
+            "timedelta": stats.time_delta
+        }))
     }
-}
 
-/// Helper function to create error response
-pub fn create_error_response(code: i32, message: String, id: Option<Value>) -> JsonRpcResponse {
-    JsonRpcResponse {
-        jsonrpc: "2.0".to_string(),
-        result: None,
-        error: Some(JsonRpcError {
-            code,
-            message,
-            data: None,
-        }),
-        id,
+    /// Get the transaction rate over the last nblocks blocks
+    fn get_chain_tx_stats(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let nblocks = match params.as_ref().and_then(|p| p.as_array()).and_then(|a| a.first()) {
+            Some(value) => Some(value.as_u64().ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [nblocks]".to_string(),
+                data: None,
+            })?),
+            None => None,
+        };
+
+        let stats = self.chain.chain_tx_stats(nblocks)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: e,
+                data: None,
+            })?;
+
+        Ok(serde_json::json!({
+            "time": self.chain.tip().map(|b| b.header.timestamp),
+            "txcount": stats.total_tx_count,
+            "window_final_block_hash": stats.tip_hash,
+            "window_final_block_height": stats.tip_height,
+            "window_block_count": stats.window_block_count,
+            "window_tx_count": stats.window_tx_count,
+            "window_interval": stats.window_interval,
+            "txrate": stats.tx_rate
+        }))
     }
-}
 
-/// Helper function to create success response
-pub fn create_success_response(result: Value, id: Option<Value>) -> JsonRpcResponse {
-    JsonRpcResponse {
-        jsonrpc: "2.0".to_string(),
-        result: Some(result),
-        error: None,
-        id,
+    /// Get every known chain tip: the active one, forks tracked by fork choice and
+    /// the tips of invalidated branches, with how many blocks each has off the
+    /// active chain. The node keeps no headers without their blocks, so a tip is
+    /// never reported as "headers-only".
+    fn get_chain_tips(&self) -> Result<Value, JsonRpcError> {
+        let internal = |e: String| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        };
+        let on_active_chain = |hash: &str| self.chain.find_header(hash).map(|header| header.is_some());
+
+        let mut tips = Vec::new();
+        let mut listed = HashSet::new();
+        if let Some(tip) = self.chain.tip() {
+            listed.insert(tip.header.hash.clone());
+            tips.push(serde_json::json!({
+                "height": tip.header.height,
+                "hash": tip.header.hash,
+                "branchlen": 0,
+                "status": "active"
+            }));
+        }
+
+        for fork in self.fork_choice.get_all_chains() {
+            let Some(tip) = fork.tip() else {
+                continue;
+            };
+            if listed.contains(&tip.header.hash) || on_active_chain(&tip.header.hash).map_err(internal)? {
+                continue;
+            }
+            let mut branchlen = 0;
+            for block in fork.get_blocks().iter().rev() {
+                if on_active_chain(&block.header.hash).map_err(internal)? {
+                    break;
+                }
+                branchlen += 1;
+            }
+            let status = if self.chain.is_invalid(&tip.header.hash) { "invalid" } else { "valid-fork" };
+            listed.insert(tip.header.hash.clone());
+            tips.push(serde_json::json!({
+                "height": tip.header.height,
+                "hash": tip.header.hash,
+                "branchlen": branchlen,
+                "status": status
+            }));
+        }
+
+        // Invalidated blocks still in the block store that no other invalid block builds on
+        let mut invalid_headers = Vec::new();
+        for (hash, _) in self.chain.invalid_block_list() {
+            if self.chain.is_invalid(&hash)
+                && let Some(header) = self.chain.stored_header(&hash).map_err(internal)? {
+                invalid_headers.push(header);
+            }
+        }
+        let parents: HashSet<&str> = invalid_headers.iter().map(|h| h.previous_hash.as_str()).collect();
+        for header in invalid_headers.iter().filter(|h| !parents.contains(h.hash.as_str())) {
+            if listed.contains(&header.hash) {
+                continue;
+            }
+            let mut branchlen = 0;
+            let mut current = Some(header.clone());
+            while let Some(block) = current {
+                if on_active_chain(&block.hash).map_err(internal)? {
+                    break;
+                }
+                branchlen += 1;
+                current = self.chain.stored_header(&block.previous_hash).map_err(internal)?;
+            }
+            listed.insert(header.hash.clone());
+            tips.push(serde_json::json!({
+                "height": header.height,
+                "hash": header.hash,
+                "branchlen": branchlen,
+                "status": "invalid"
+            }));
+        }
+
+        Ok(Value::Array(tips))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::blockchain::chain::Chain;
-    use crate::mempool::Mempool;
-    use crate::wallet::keychain::Wallet;
+    /// Get mining info: difficulty, network hash rate and pending transactions
+    fn get_mining_info(&self) -> Result<Value, JsonRpcError> {
+        let difficulty = self.difficulty_info()?.difficulty;
+        let estimate = estimate_network_hash_rate(&self.chain, DEFAULT_HASHPS_WINDOW, None)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INTERNAL_ERROR,
+                message: e,
+                data: None,
+            })?;
 
-    fn create_test_handler() -> BlockchainRpcHandler {
-        let chain = Chain::new();
-        let mempool = Mempool::new();
-        let wallet = Wallet::new();
-        BlockchainRpcHandler::new(chain, mempool, wallet)
+        Ok(serde_json::json!({
+            "blocks": estimate.height,
+            "difficulty": difficulty,
+            "networkhashps": estimate.hashes_per_second,
+            "avgblocktime": estimate.average_block_time,
+            "pooledtx": self.mempool().get_stats().pending_count,
+            "chain": "rust-chain"
+        }))
     }
 
-    #[test]
-    fn test_get_blockchain_info() {
-        let handler = create_test_handler();
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "getblockchaininfo".to_string(),
-            params: None,
-            id: Some(Value::Number(1.into())),
+    /// Get propagation and orphan statistics for blocks this node mined
+    fn get_propagation_stats(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let count = match params.as_ref().and_then(|p| p.as_array()).and_then(|args| args.first()) {
+            Some(value) => value.as_u64().ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [count]".to_string(),
+                data: None,
+            })? as usize,
+            None => 10,
         };
 
-        let response = handler.handle_request(request);
-        assert!(response.result.is_some());
-        assert!(response.error.is_none());
+        // A node serving RPC holds the records and their database itself
+        let tracker = match (&self.network, &self.data_dir) {
+            (Some(network), _) => network.propagation(),
+            (None, Some(data_dir)) => PeerStatsDb::open(data_dir)
+                .and_then(|peer_stats| peer_stats.propagation())
+                .map_err(|e| JsonRpcError {
+                    code: error_codes::INTERNAL_ERROR,
+                    message: e,
+                    data: None,
+                })?,
+            (None, None) => PropagationTracker::new(),
+        };
+
+        let blocks: Vec<Value> = tracker.blocks().iter().rev().take(count).map(|block| serde_json::json!({
+            "hash": block.hash,
+            "height": block.height,
+            "mined_at": block.mined_at,
+            "first_ack_ms": block.first_ack_ms(),
+            "peers_relayed": block.peers_relayed,
+            "peers_acked": block.peers_acked,
+            "orphaned": PropagationTracker::is_orphaned(block, &self.chain),
+        })).collect();
+
+        let mut result = serde_json::to_value(tracker.stats(&self.chain)).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e.to_string(),
+            data: None,
+        })?;
+        result["blocks"] = Value::Array(blocks);
+        Ok(result)
     }
 
-    #[test]
-    fn test_get_block_count() {
-        let handler = create_test_handler();
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "getblockcount".to_string(),
-            params: None,
-            id: Some(Value::Number(1.into())),
+    /// Get the most recent consensus anomaly alerts, newest first
+    fn get_alerts(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let count = match params.as_ref().and_then(|p| p.as_array()).and_then(|args| args.first()) {
+            Some(value) => value.as_u64().ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [count]".to_string(),
+                data: None,
+            })? as usize,
+            None => 20,
         };
 
-        let response = handler.handle_request(request);
-        assert!(response.result.is_some());
-        assert!(response.error.is_none());
+        let alerts = match &self.data_dir {
+            Some(data_dir) => AlertLog::open(data_dir)
+                .map_err(|e| JsonRpcError {
+                    code: error_codes::INTERNAL_ERROR,
+                    message: e,
+                    data: None,
+                })?
+                .alerts(),
+            None => Vec::new(),
+        };
+
+        let recent: Vec<_> = alerts.iter().rev().take(count).collect();
+        serde_json::to_value(recent).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e.to_string(),
+            data: None,
+        })
     }
 
-    #[test]
-    fn test_unknown_method() {
-        let handler = create_test_handler();
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "unknownmethod".to_string(),
-            params: None,
-            id: Some(Value::Number(1.into())),
+    /// Timing percentiles recorded by this process since it started
+    fn get_perf_stats(&self) -> Result<Value, JsonRpcError> {
+        serde_json::to_value(perf::report()).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e.to_string(),
+            data: None,
+        })
+    }
+
+    /// Re-validate the last `nblocks` blocks (0 for all) at `checklevel` 0-4
+    fn verify_chain(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let args = params.as_ref().and_then(|p| p.as_array()).cloned().unwrap_or_default();
+        let invalid_params = || JsonRpcError {
+            code: error_codes::INVALID_PARAMS,
+            message: "Invalid params: expected [checklevel, nblocks]".to_string(),
+            data: None,
+        };
+        let level = match args.first() {
+            Some(value) => value.as_u64().and_then(|level| u8::try_from(level).ok()).ok_or_else(invalid_params)?,
+            None => DEFAULT_CHECK_LEVEL,
+        };
+        let nblocks = match args.get(1) {
+            Some(value) => value.as_u64().ok_or_else(invalid_params)?,
+            None => DEFAULT_CHECK_BLOCKS,
         };
 
-        let response = handler.handle_request(request);
-        assert!(response.result.is_none());
-        assert!(response.error.is_some());
-        assert_eq!(response.error.unwrap().code, error_codes::METHOD_NOT_FOUND);
+        let report = self.chain.verify_chain(level, nblocks, |_, _| {})
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: e,
+                data: None,
+            })?;
+
+        Ok(serde_json::json!({
+            "valid": report.is_ok(),
+            "checklevel": report.level,
+            "from_height": report.from_height,
+            "to_height": report.to_height,
+            "blocks_checked": report.blocks_checked,
+            "issues": report.issues
+        }))
     }
 
-    #[test]
-    fn test_invalid_params() {
-        let handler = create_test_handler();
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "getblockhash".to_string(),
-            params: Some(Value::String("invalid".to_string())),
-            id: Some(Value::Number(1.into())),
+    /// Estimate network hashes per second over the last `nblocks` blocks ending at `height` (-1 for the tip)
+    fn get_network_hash_ps(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let invalid = || JsonRpcError {
+            code: error_codes::INVALID_PARAMS,
+            message: "Invalid params: expected [nblocks, height]".to_string(),
+            data: None,
         };
+        let args = params.as_ref().and_then(|p| p.as_array()).cloned().unwrap_or_default();
 
-        let response = handler.handle_request(request);
-        assert!(response.result.is_none());
-        assert!(response.error.is_some());
-        assert_eq!(response.error.unwrap().code, error_codes::INVALID_PARAMS);
+        let nblocks = match args.first() {
+            Some(value) => value.as_u64().ok_or_else(invalid)?,
+            None => DEFAULT_HASHPS_WINDOW,
+        };
+        let height = match args.get(1).map(|value| value.as_i64().ok_or_else(invalid)).transpose()? {
+            Some(-1) | None => None,
+            Some(height) => Some(u64::try_from(height).map_err(|_| invalid())?),
+        };
+
+        let estimate = estimate_network_hash_rate(&self.chain, nblocks, height)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: e,
+                data: None,
+            })?;
+
+        Ok(serde_json::json!(estimate.hashes_per_second))
+    }
+
+    /// Get mempool info
+    fn get_mempool_info(&self) -> Result<Value, JsonRpcError> {
+        let stats = self.mempool().get_stats();
+        let info = serde_json::json!({
+            "size": stats.pending_count,
+            "bytes": stats.total_size_bytes,
+            "usage": stats.total_size_bytes,
+            "maxmempool": 100_000_000, // 100MB limit
+            "maxsize": stats.max_size,
+            "maxage": stats.max_age_seconds,
+            "mempoolminfee": 0.00001000,
+            "minrelaytxfee": 0.00001000
+        });
+        Ok(info)
+    }
+
+    /// Get pending transactions grouped by fee band
+    fn get_mempool_fee_histogram(&self) -> Result<Value, JsonRpcError> {
+        let mempool = self.mempool();
+        let buckets: Vec<Value> = mempool.fee_histogram().iter()
+            .map(|bucket| serde_json::json!({
+                "minfee": bucket.min_fee_per_byte,
+                "maxfee": bucket.max_fee_per_byte,
+                "count": bucket.transaction_count,
+                "size": bucket.total_size_bytes
+            }))
+            .collect();
+
+        Ok(serde_json::json!({
+            "histogram": buckets,
+            "pending": mempool.size(),
+            "blockstoclear": mempool.blocks_to_clear()
+        }))
+    }
+
+    /// Get the block that would be mined next on the current tip, without mining it
+    fn get_block_template(&self) -> Result<Value, JsonRpcError> {
+        let internal = |message: String| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message,
+            data: None,
+        };
+        let tip = self.chain.tip().ok_or_else(|| internal("Chain has no blocks".to_string()))?;
+        let utxo_state = self.chain.utxo_state().map_err(internal)?;
+        let median_time_past = self.chain.median_time_past().map_err(internal)?;
+        let template = BlockTemplate::build(tip, median_time_past, &self.mempool(), &utxo_state, self.chain.params());
+
+        let transactions: Vec<Value> = template.transactions.iter()
+            .map(|tx| serde_json::json!({
+                "txid": tx.txid,
+                "from": tx.from,
+                "to": tx.to,
+                "amount": tx.amount,
+                "fee": tx.fee,
+                "feerate": tx.fee_per_byte,
+                "size": tx.size_bytes,
+                "depends": tx.depends
+            }))
+            .collect();
+
+        Ok(serde_json::json!({
+            "previousblockhash": template.previous_hash,
+            "height": template.height,
+            "curtime": template.timestamp,
+            "transactions": transactions,
+            "fees": template.total_fees,
+            "size": template.size_bytes,
+            "subsidy": template.subsidy,
+            "coinbasevalue": template.coinbase_value
+        }))
+    }
+
+    /// Estimate the fee per byte needed to confirm within a number of blocks
+    fn estimate_fee(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let target_blocks = match params.as_ref().and_then(|p| p.as_array()).and_then(|a| a.first()) {
+            Some(value) => value.as_u64()
+                .filter(|blocks| *blocks > 0)
+                .ok_or_else(|| JsonRpcError {
+                    code: error_codes::INVALID_PARAMS,
+                    message: "Invalid params: expected [nblocks] with nblocks > 0".to_string(),
+                    data: None,
+                })?,
+            None => 1,
+        };
+
+        Ok(serde_json::json!({
+            "feerate": self.mempool().estimate_fee(target_blocks as usize),
+            "blocks": target_blocks
+        }))
+    }
+
+    /// Estimate the fee per byte to confirm within a number of blocks from the
+    /// waits of recently confirmed transactions
+    fn estimate_smart_fee(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let target_blocks = params.as_ref().and_then(|p| p.as_array()).and_then(|a| a.first())
+            .and_then(|value| value.as_u64())
+            .filter(|blocks| *blocks > 0)
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [conf_target] with conf_target > 0".to_string(),
+                data: None,
+            })?;
+
+        let estimator = match &self.data_dir {
+            Some(data_dir) => FeeEstimator::load_from_file(FeeEstimator::path_in(data_dir))
+                .map_err(|e| JsonRpcError {
+                    code: error_codes::INTERNAL_ERROR,
+                    message: e,
+                    data: None,
+                })?,
+            None => FeeEstimator::new(),
+        };
+
+        let estimate = estimator.estimate_smart_fee(target_blocks);
+        Ok(match estimate.fee_per_byte {
+            Some(feerate) => serde_json::json!({
+                "feerate": feerate,
+                "blocks": estimate.blocks
+            }),
+            None => serde_json::json!({
+                "errors": ["Insufficient data or no feerate found"],
+                "blocks": estimate.blocks
+            }),
+        })
+    }
+
+    /// Get the txids of pending transactions, or their entries keyed by txid when `verbose`
+    fn get_raw_mempool(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let verbose = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|args| args.first())
+            .map(|v| v.as_bool().unwrap_or_else(|| v.as_u64().unwrap_or(0) != 0))
+            .unwrap_or(false);
+
+        if !verbose {
+            return Ok(serde_json::json!(self.mempool().txids()));
+        }
+        let entries: serde_json::Map<String, Value> = self.mempool().entries().iter()
+            .map(|entry| (entry.txid.clone(), mempool_entry_json(entry)))
+            .collect();
+        Ok(Value::Object(entries))
+    }
+
+    /// Get fee, size, time in pool and in-pool relatives of a pending transaction
+    fn get_mempool_entry(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let txid = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|args| args.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [txid]".to_string(),
+                data: None,
+            })?;
+
+        let entry = self.mempool().get_entry(txid).ok_or_else(|| JsonRpcError {
+            code: error_codes::TRANSACTION_NOT_FOUND,
+            message: "Transaction not in mempool".to_string(),
+            data: None,
+        })?;
+        Ok(mempool_entry_json(&entry))
+    }
+
+    /// Spendable and immature coinbase balances summed over a wallet's addresses
+    fn wallet_balances(&self, wallet: &Wallet) -> Result<(u64, u64), JsonRpcError> {
+        let state = self.chain.utxo_state().map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        })?;
+        let addresses = wallet.get_all_addresses();
+        let balance: u64 = addresses.iter().map(|addr| state.get_balance(addr)).sum();
+        let immature: u64 = addresses.iter().map(|addr| state.get_immature_balance(addr)).sum();
+        Ok((balance, immature))
+    }
+
+    /// Get the wallet's spendable balance
+    fn get_balance(&self, wallet: &Wallet) -> Result<Value, JsonRpcError> {
+        let (balance, _) = self.wallet_balances(wallet)?;
+        Ok(Value::Number(serde_json::Number::from(balance)))
+    }
+
+    /// Get wallet balances, with coinbase rewards that are not spendable yet reported apart
+    fn get_balances(&self, wallet: &Wallet) -> Result<Value, JsonRpcError> {
+        let (balance, immature) = self.wallet_balances(wallet)?;
+        Ok(serde_json::json!({
+            "balance": balance,
+            "balance_coins": format_coins(balance),
+            "immature": immature,
+            "immature_coins": format_coins(immature)
+        }))
+    }
+
+    /// Create a new address
+    fn get_new_address(&self, wallet: &Wallet) -> Result<Value, JsonRpcError> {
+        // Return a new address without mutating the wallet
+        Ok(Value::String(wallet.get_new_address_readonly()))
+    }
+
+    /// Confirmed transactions of the wallet's addresses, oldest first, with block
+    /// rewards in the `mining_income` category
+    fn list_transactions(&self, wallet: &Wallet) -> Result<Value, JsonRpcError> {
+        let addresses: HashSet<String> = wallet.get_all_addresses().into_iter().collect();
+        let history = wallet_history(&self.chain, &addresses).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        })?;
+        serde_json::to_value(history).map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: format!("Failed to serialize history: {}", e),
+            data: None,
+        })
+    }
+
+    /// Get a transaction by id as canonical hex, or decoded when verbose
+    fn get_raw_transaction(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let params_array = params.as_ref()
+            .and_then(|p| p.as_array())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params format".to_string(),
+                data: None,
+            })?;
+
+        let txid = params_array.first()
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid txid parameter".to_string(),
+                data: None,
+            })?;
+
+        // Accept both `true` and bitcoind-style `1` for the verbose flag
+        let verbose = params_array.get(1)
+            .map(|v| v.as_bool().unwrap_or_else(|| v.as_u64().unwrap_or(0) != 0))
+            .unwrap_or(false);
+
+        let internal_error = |e: String| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        };
+
+        // Confirmed transactions come from the chain's tx index, unconfirmed ones from the
+        // mempool, and ones whose block was disconnected from their status record
+        let (transaction, index, disconnected) = match self.chain.get_transaction(txid).map_err(internal_error)? {
+            Some(transaction) => {
+                let index = self.chain.get_transaction_index(txid).map_err(internal_error)?;
+                (transaction, index, None)
+            },
+            None => {
+                let pending = self.mempool().get_pending_transactions()
+                    .into_iter()
+                    .find(|tx| tx.hash() == txid);
+                match pending {
+                    Some(pending) => (pending, None, None),
+                    None => match self.chain.transaction_status(txid).map_err(internal_error)? {
+                        Some(record) => (record.transaction.clone(), None, Some(record)),
+                        None if !self.chain.txindex_enabled() => {
+                        return Err(JsonRpcError {
+                            code: error_codes::INDEX_DISABLED,
+                            message: "No such mempool transaction. Transaction index is disabled, restart with --txindex=1 to look up confirmed transactions".to_string(),
+                            data: None,
+                        });
+                    },
+                        None => {
+                            return Err(JsonRpcError {
+                                code: error_codes::TRANSACTION_NOT_FOUND,
+                                message: "Transaction not found".to_string(),
+                                data: None,
+                            });
+                        }
+                    },
+                }
+            }
+        };
+
+        let hex = transaction.to_hex();
+        if !verbose {
+            return Ok(Value::String(hex));
+        }
+
+        let mut decoded = Self::decode_transaction(&transaction);
+        decoded["hex"] = Value::String(hex);
+        match index {
+            Some(index) => {
+                let tip_height = self.chain.height();
+                decoded["blockhash"] = Value::String(index.block_hash);
+                decoded["blockheight"] = Value::Number(index.block_height.into());
+                decoded["confirmations"] = Value::Number((tip_height.saturating_sub(index.block_height) + 1).into());
+                decoded["time"] = Value::Number(index.timestamp.into());
+                decoded["status"] = Value::String(TransactionStatus::Confirmed.name().to_string());
+            },
+            None => {
+                decoded["confirmations"] = Value::Number(0.into());
+                let status = match &disconnected {
+                    // Returned to the mempool, but no longer there
+                    Some(record) if record.status == TransactionStatus::Mempool && !self.mempool().contains_transaction(&transaction) => TransactionStatus::Abandoned,
+                    Some(record) => record.status,
+                    None => TransactionStatus::Mempool,
+                };
+                decoded["status"] = Value::String(status.name().to_string());
+                if let Some(record) = disconnected {
+                    decoded["lastblockhash"] = Value::String(record.block_hash);
+                    decoded["lastblockheight"] = Value::Number(record.block_height.into());
+                }
+            }
+        }
+
+        Ok(decoded)
+    }
+
+    /// List the ids of all confirmed transactions involving an address
+    fn get_address_txids(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let address = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|a| a.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid address parameter".to_string(),
+                data: None,
+            })?;
+
+        self.require_address_index()?;
+        let transactions = self.chain.get_transactions_for_address(address)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INTERNAL_ERROR,
+                message: e,
+                data: None,
+            })?;
+
+        Ok(Value::Array(transactions.into_iter().map(|(hash, _)| Value::String(hash)).collect()))
+    }
+
+    /// Balance and totals of an address from the address balance index
+    fn get_address_balance(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let address = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|a| a.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid address parameter".to_string(),
+                data: None,
+            })?;
+
+        let height = match params.as_ref().and_then(|p| p.as_array()).and_then(|a| a.get(1)) {
+            None | Some(Value::Null) => None,
+            Some(value) => Some(value.as_u64().ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid height parameter".to_string(),
+                data: None,
+            })?),
+        };
+
+        self.require_address_index()?;
+        let Some(height) = height else {
+            let summary = self.chain.get_address_summary(address)
+                .map_err(|e| JsonRpcError {
+                    code: error_codes::INTERNAL_ERROR,
+                    message: e,
+                    data: None,
+                })?;
+            return Ok(address_summary_json(address, &summary));
+        };
+
+        if height > self.chain.height() {
+            return Err(JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: format!("Height {} is beyond the tip at {}", height, self.chain.height()),
+                data: None,
+            });
+        }
+        let summary = self.chain.get_address_summary_at(address, height)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INTERNAL_ERROR,
+                message: e,
+                data: None,
+            })?;
+        let mut result = address_summary_json(address, &summary);
+        result["height"] = serde_json::json!(height);
+        Ok(result)
+    }
+
+    /// Synthetic unspent outputs of an address, including immature coinbase outputs
+    fn get_address_utxos(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let address = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|a| a.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid address parameter".to_string(),
+                data: None,
+            })?;
+
+        self.require_address_index()?;
+        let outputs = self.unspent_outputs(address)?;
+        Ok(Value::Array(outputs.iter().map(unspent_output_json).collect()))
+    }
+
+    /// Synthetic spendable outputs with at least `minconf` confirmations (default 1)
+    /// of the given addresses, or of every wallet address
+    fn list_unspent(&self, wallet: &Wallet, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let params = params.as_ref().and_then(|p| p.as_array());
+        let minconf = params
+            .and_then(|a| a.first())
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1);
+        let invalid_addresses = || JsonRpcError {
+            code: error_codes::INVALID_PARAMS,
+            message: "Invalid addresses parameter, expected an array of addresses".to_string(),
+            data: None,
+        };
+        let addresses: Vec<String> = match params.and_then(|a| a.get(1)) {
+            Some(Value::Array(addresses)) => addresses.iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or_else(invalid_addresses)?,
+            Some(_) => return Err(invalid_addresses()),
+            None => wallet.get_all_addresses(),
+        };
+
+        self.require_address_index()?;
+        let mut outputs = Vec::new();
+        for address in &addresses {
+            outputs.extend(self.unspent_outputs(address)?.into_iter()
+                .filter(|output| output.spendable && output.confirmations >= minconf));
+        }
+        Ok(Value::Array(outputs.iter().map(unspent_output_json).collect()))
+    }
+
+    fn unspent_outputs(&self, address: &str) -> Result<Vec<UnspentOutput>, JsonRpcError> {
+        self.chain.address_unspent_outputs(address)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INTERNAL_ERROR,
+                message: e,
+                data: None,
+            })
+    }
+
+    /// Addresses with the highest balances, richest first
+    fn get_richest_addresses(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let count = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|a| a.first())
+            .and_then(|v| v.as_u64())
+            .unwrap_or(DEFAULT_RICHEST_ADDRESSES as u64) as usize;
+
+        self.require_address_index()?;
+        let richest = self.chain.richest_addresses(count)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::INTERNAL_ERROR,
+                message: e,
+                data: None,
+            })?;
+
+        Ok(Value::Array(richest.iter().map(|(address, summary)| address_summary_json(address, summary)).collect()))
+    }
+
+    fn require_address_index(&self) -> Result<(), JsonRpcError> {
+        if !self.chain.address_index_enabled() {
+            return Err(JsonRpcError {
+                code: error_codes::INDEX_DISABLED,
+                message: "Address index is disabled, restart with --addressindex=1".to_string(),
+                data: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decode a canonical hex transaction without looking it up
+    fn decode_raw_transaction(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let hex_str = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|a| a.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid hex parameter".to_string(),
+                data: None,
+            })?;
+
+        let transaction = Transaction::from_hex(hex_str)
+            .map_err(|e| JsonRpcError {
+                code: error_codes::DESERIALIZATION_ERROR,
+                message: e,
+                data: None,
+            })?;
+
+        Ok(Self::decode_transaction(&transaction))
+    }
+
+    /// Build the decoded JSON view of a transaction
+    fn decode_transaction(transaction: &Transaction) -> Value {
+        let coinbase = transaction.from == "0000000000000000000000000000000000000000";
+        let vin = if coinbase {
+            serde_json::json!([{ "coinbase": true }])
+        } else {
+            serde_json::json!([{
+                "address": transaction.from.clone(),
+                "value": transaction.amount,
+                "value_coins": format_coins(transaction.amount)
+            }])
+        };
+
+        serde_json::json!({
+            "txid": transaction.hash(),
+            "size": transaction.serialize().len(),
+            // Transactions carry no fee field, so nothing is paid beyond the output
+            "fee": if coinbase { Value::Null } else { Value::from(0) },
+            "vin": vin,
+            "vout": [{
+                "n": 0,
+                "address": transaction.to.clone(),
+                "value": transaction.amount,
+                "value_coins": format_coins(transaction.amount)
+            }],
+            "signature": hex::encode(&transaction.signature)
+        })
+    }
+}
+
+impl BlockchainRpcHandler {
+    /// Submit a hex transaction to the mempool. The relay policy applies as it
+    /// does to transactions from peers, and so does the fee rate: the one the
+    /// transaction pays, never one the caller names.
+    fn send_raw_transaction(&mut self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let hex_str = params.as_ref()
+            .and_then(|p| p.as_array())
+            .filter(|a| a.len() == 1)
+            .and_then(|a| a.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid params: expected [hex]".to_string(),
+                data: None,
+            })?;
+
+        let transaction = Transaction::from_hex(hex_str).map_err(|e| JsonRpcError {
+            code: error_codes::DESERIALIZATION_ERROR,
+            message: e,
+            data: None,
+        })?;
+        let state = self.chain.utxo_state().map_err(|e| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        })?;
+        self.mempool().add_network_transaction(transaction.clone(), &state)?;
+
+        // A full pool evicts its lowest-priority transaction, which may be this one
+        if !self.mempool().contains_transaction(&transaction) {
+            return Err(JsonRpcError {
+                code: error_codes::MEMPOOL_FULL,
+                message: format!(
+                    "Mempool full: fee rate {} per byte is too low to stay in the pool",
+                    paid_fee_per_byte(&transaction)
+                ),
+                data: None,
+            });
+        }
+        Ok(serde_json::json!(transaction.hash()))
+    }
+
+    /// Hash parameter of `invalidateblock` and `reconsiderblock`
+    fn block_hash_param(params: Option<Value>) -> Result<String, JsonRpcError> {
+        params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|p| p.first())
+            .and_then(|v| v.as_str())
+            .map(|hash| hash.to_string())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid block hash parameter".to_string(),
+                data: None,
+            })
+    }
+
+    /// Mark a block invalid, disconnecting it and its descendants
+    fn invalidate_block(&mut self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let hash = Self::block_hash_param(params)?;
+        let internal_error = |e: String| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        };
+        let disconnected = self.chain.invalidate_block(&hash).map_err(internal_error)?;
+
+        // Disconnected transactions go back to the mempool where they still validate
+        let (accepted, refused) = self.mempool.lock().unwrap().return_disconnected(&mut self.chain, &disconnected)
+            .map_err(internal_error)?;
+
+        let mut result = self.tip_change_result("disconnected", &disconnected);
+        result["resubmitted"] = serde_json::json!(accepted);
+        result["abandoned"] = serde_json::json!(refused);
+        Ok(result)
+    }
+
+    /// Clear the invalid mark from a block and re-run fork choice, returning the
+    /// transactions of any blocks it disconnects to the mempool
+    fn reconsider_block(&mut self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let hash = Self::block_hash_param(params)?;
+        if !self.chain.is_invalid(&hash) {
+            return Err(JsonRpcError {
+                code: error_codes::BLOCK_NOT_FOUND,
+                message: format!("Block {} is not marked invalid", hash),
+                data: None,
+            });
+        }
+        let internal_error = |e: String| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: e,
+            data: None,
+        };
+        let (connected, disconnected) = self.chain.reconsider_block(&hash).map_err(internal_error)?;
+        for block in &connected {
+            self.mempool().remove_transactions(&block.transactions);
+        }
+        let (accepted, refused) = self.mempool.lock().unwrap().return_disconnected(&mut self.chain, &disconnected)
+            .map_err(internal_error)?;
+
+        let mut result = self.tip_change_result("connected", &connected);
+        result["resubmitted"] = serde_json::json!(accepted);
+        result["abandoned"] = serde_json::json!(refused);
+        Ok(result)
+    }
+
+    /// Stop the mock clock at the given Unix time, or let it follow the system
+    /// clock again with 0. Only regtest nodes have a mock clock.
+    fn set_mock_time(&mut self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let clock = self.mock_clock.as_ref().ok_or_else(|| JsonRpcError {
+            code: error_codes::FORBIDDEN,
+            message: "setmocktime is only available on regtest nodes".to_string(),
+            data: None,
+        })?;
+        let time = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|p| p.first())
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid time parameter".to_string(),
+                data: None,
+            })?;
+        clock.set(time);
+        Ok(Value::Null)
+    }
+
+    /// Stop the server once requests in flight, this one included, are answered
+    fn stop(&self) -> Result<Value, JsonRpcError> {
+        let shutdown = self.shutdown.as_ref().ok_or_else(|| JsonRpcError {
+            code: error_codes::INTERNAL_ERROR,
+            message: "The RPC server is not running".to_string(),
+            data: None,
+        })?;
+        shutdown.stop();
+        Ok(Value::String("rust-chain RPC server stopping".to_string()))
+    }
+
+    /// Change the mempool size limit and expiry, `[maxsize, maxage]`, either of
+    /// which may be null to keep it. Transactions that no longer fit are evicted
+    /// at once.
+    fn set_mempool_limits(&mut self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let invalid = |message: String| JsonRpcError {
+            code: error_codes::INVALID_PARAMS,
+            message,
+            data: None,
+        };
+        let params = params.as_ref().and_then(|p| p.as_array()).cloned().unwrap_or_default();
+        let limit = |index: usize, name: &str| match params.get(index) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value.as_u64().map(Some)
+                .ok_or_else(|| invalid(format!("{} must be a whole number", name))),
+        };
+        let max_size = limit(0, "maxsize")?.map(|size| size as usize);
+        let max_age = limit(1, "maxage")?;
+        if max_size.is_none() && max_age.is_none() {
+            return Err(invalid("Expected [maxsize, maxage] with at least one limit".to_string()));
+        }
+
+        let mut mempool = self.mempool();
+        let evicted = mempool.set_limits(max_size, max_age).map_err(invalid)?;
+        Ok(serde_json::json!({
+            "maxsize": mempool.max_size(),
+            "maxage": mempool.max_age_seconds(),
+            "evicted": evicted
+        }))
+    }
+
+    fn network(&self) -> Result<&NetworkServer, JsonRpcError> {
+        self.network.as_deref().ok_or_else(|| JsonRpcError {
+            code: error_codes::NETWORK_DISABLED,
+            message: "P2P networking is not available; run the RPC server with start-node --rpc".to_string(),
+            data: None,
+        })
+    }
+
+    /// Number of connected peers
+    fn get_connection_count(&self) -> Result<Value, JsonRpcError> {
+        Ok(serde_json::json!(self.network()?.connection_count()))
+    }
+
+    /// Add a node to keep connected to, remove it, or try it once: `[address, command]`
+    fn add_node(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let network = self.network()?;
+        let invalid = |message: String| JsonRpcError {
+            code: error_codes::INVALID_PARAMS,
+            message,
+            data: None,
+        };
+        let params = params.as_ref().and_then(|p| p.as_array());
+        let (Some(address), Some(command)) = (
+            params.and_then(|p| p.first()).and_then(|v| v.as_str()),
+            params.and_then(|p| p.get(1)).and_then(|v| v.as_str()),
+        ) else {
+            return Err(invalid("Expected [address, \"add\"|\"remove\"|\"onetry\"]".to_string()));
+        };
+        let address = address.parse::<PeerAddress>().map_err(invalid)?;
+        let command = command.parse::<AddNodeCommand>().map_err(invalid)?;
+        network.add_node(address, command).map_err(peer_control_error)?;
+        Ok(Value::Null)
+    }
+
+    /// Disconnect the peers at an address or with a node id
+    fn disconnect_node(&self, params: Option<Value>) -> Result<Value, JsonRpcError> {
+        let network = self.network()?;
+        let address = params.as_ref()
+            .and_then(|p| p.as_array())
+            .and_then(|p| p.first())
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| JsonRpcError {
+                code: error_codes::INVALID_PARAMS,
+                message: "Invalid address parameter".to_string(),
+                data: None,
+            })?;
+        let removed = network.disconnect_node(address).map_err(peer_control_error)?;
+        let peers: Vec<String> = removed.iter().map(|peer| format!("{}:{}", peer.address, peer.port)).collect();
+        Ok(serde_json::json!(peers))
+    }
+
+    fn tip_change_result(&self, key: &str, blocks: &[crate::blockchain::block::Block]) -> Value {
+        let hashes: Vec<&str> = blocks.iter().map(|b| b.header.hash.as_str()).collect();
+        let mut result = serde_json::json!({
+            "bestblockhash": self.chain.tip().map(|b| b.header.hash.clone()).unwrap_or_default(),
+            "height": self.chain.height(),
+        });
+        result[key] = serde_json::json!(hashes);
+        result
+    }
+
+    /// Handle one of the `PRIVILEGED_METHODS` or `MEMPOOL_WRITE_METHODS`, which
+    /// need mutable access to the node
+    pub fn handle_privileged_request(&mut self, request: JsonRpcRequest) -> JsonRpcResponse {
+        let result = match request.method.as_str() {
+            "invalidateblock" => self.invalidate_block(request.params),
+            "reconsiderblock" => self.reconsider_block(request.params),
+            "setmocktime" => self.set_mock_time(request.params),
+            "addnode" => self.add_node(request.params),
+            "disconnectnode" => self.disconnect_node(request.params),
+            "setmempoollimits" => self.set_mempool_limits(request.params),
+            "sendrawtransaction" => self.send_raw_transaction(request.params),
+            "stop" => self.stop(),
+            _ => return self.handle_request(request),
+        };
+        into_response(result, request.id)
+    }
+
+    /// Handle a request sent to `/wallet/<name>`, with wallet methods using that wallet
+    pub fn handle_wallet_request(&self, name: &str, request: JsonRpcRequest) -> JsonRpcResponse {
+        match self.wallets.get(name) {
+            Some(wallet) => self.dispatch(wallet, request),
+            None => create_error_response(
+                error_codes::WALLET_NOT_FOUND,
+                format!("Wallet '{}' is not loaded", name),
+                request.id,
+            ),
+        }
+    }
+
+    fn dispatch(&self, wallet: &Wallet, request: JsonRpcRequest) -> JsonRpcResponse {
+        let result = match request.method.as_str() {
+            "getblockchaininfo" => self.get_blockchain_info(),
+            "getblockcount" => self.get_block_count(),
+            "getblockhash" => self.get_block_hash(request.params),
+            "getblock" => self.get_block(request.params),
+            "getblockheader" => self.get_block_header(request.params),
+            "getblockstats" => self.get_block_stats(request.params),
+            "getchaintxstats" => self.get_chain_tx_stats(request.params),
+            "getchaintips" => self.get_chain_tips(),
+            "getdifficulty" => self.get_difficulty(),
+            "getmininginfo" => self.get_mining_info(),
+            "getnetworkhashps" => self.get_network_hash_ps(request.params),
+            "getpropagationstats" => self.get_propagation_stats(request.params),
+            "getalerts" => self.get_alerts(request.params),
+            "getperfstats" => self.get_perf_stats(),
+            "getconnectioncount" => self.get_connection_count(),
+            "verifychain" => self.verify_chain(request.params),
+            "getmempoolinfo" => self.get_mempool_info(),
+            "getrawmempool" => self.get_raw_mempool(request.params),
+            "getmempoolentry" => self.get_mempool_entry(request.params),
+            "getmempoolfeehistogram" => self.get_mempool_fee_histogram(),
+            "getblocktemplate" => self.get_block_template(),
+            "estimatefee" => self.estimate_fee(request.params),
+            "estimatesmartfee" => self.estimate_smart_fee(request.params),
+            "getbalance" => self.get_balance(wallet),
+            "getbalances" => self.get_balances(wallet),
+            "getnewaddress" => self.get_new_address(wallet),
+            "listtransactions" => self.list_transactions(wallet),
+            "getrawtransaction" => self.get_raw_transaction(request.params),
+            "decoderawtransaction" => self.decode_raw_transaction(request.params),
+            "getaddresstxids" => self.get_address_txids(request.params),
+            "getaddressbalance" => self.get_address_balance(request.params),
+            "getrichestaddresses" => self.get_richest_addresses(request.params),
+            "getaddressutxos" => self.get_address_utxos(request.params),
+            "listunspent" => self.list_unspent(wallet, request.params),
+            _ => Err(JsonRpcError {
+                code: error_codes::METHOD_NOT_FOUND,
+                message: format!("Method '{}' not found", request.method),
+                data: None,
+            }),
+        };
+
+        into_response(result, request.id)
+    }
+}
+
+impl RpcHandler for BlockchainRpcHandler {
+    fn handle_request(&self, request: JsonRpcRequest) -> JsonRpcResponse {
+        self.dispatch(&self.wallet, request)
+    }
+}
+
+/// RPC error for a refused peer management request
+fn peer_control_error(error: PeerControlError) -> JsonRpcError {
+    let code = match error {
+        PeerControlError::AlreadyAdded(_) => error_codes::NODE_ALREADY_ADDED,
+        PeerControlError::NotAdded(_) => error_codes::NODE_NOT_ADDED,
+        PeerControlError::NotConnected(_) | PeerControlError::ConnectFailed(..) => error_codes::NODE_NOT_CONNECTED,
+    };
+    JsonRpcError {
+        code,
+        message: error.to_string(),
+        data: None,
+    }
+}
+
+/// RPC form of a mempool entry
+fn mempool_entry_json(entry: &MempoolEntry) -> Value {
+    serde_json::json!({
+        "size": entry.size_bytes,
+        "fee": entry.fee,
+        "feerate": entry.fee_per_byte,
+        "time": entry.time,
+        "timeinpool": entry.seconds_in_pool,
+        "height": entry.entry_height,
+        "ancestorcount": entry.ancestor_count,
+        "ancestorsize": entry.ancestor_size,
+        "ancestorfees": entry.ancestor_fees,
+        "descendantcount": entry.descendant_count,
+        "descendantsize": entry.descendant_size,
+        "descendantfees": entry.descendant_fees,
+        "depends": entry.depends,
+        "spentby": entry.spent_by,
+    })
+}
+
+/// RPC form of an address balance index entry
+fn address_summary_json(address: &str, summary: &AddressSummary) -> Value {
+    serde_json::json!({
+        "address": address,
+        "balance": summary.balance(),
+        "balance_coins": format_coins(summary.balance()),
+        "received": summary.total_received,
+        "received_coins": format_coins(summary.total_received),
+        "sent": summary.total_sent,
+        "sent_coins": format_coins(summary.total_sent),
+        "txcount": summary.transaction_count,
+    })
+}
+
+fn unspent_output_json(output: &UnspentOutput) -> Value {
+    serde_json::json!({
+        "txid": output.txid,
+        "vout": output.vout,
+        "address": output.address,
+        "amount": output.amount,
+        "amount_coins": format_coins(output.amount),
+        "height": output.block_height,
+        "confirmations": output.confirmations,
+        "coinbase": output.coinbase,
+        "spendable": output.spendable,
+        // Outputs are derived from balances, see `UnspentOutput`
+        "synthetic": true,
+    })
+}
+
+fn into_response(result: Result<Value, JsonRpcError>, id: Option<Value>) -> JsonRpcResponse {
+    match result {
+        Ok(value) => JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(value),
+            error: None,
+            id,
+        },
+        Err(error) => JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: None,
+            error: Some(error),
+            id,
+        },
+    }
+}
+
+/// Helper function to create error response
+pub fn create_error_response(code: i32, message: String, id: Option<Value>) -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: None,
+        error: Some(JsonRpcError {
+            code,
+            message,
+            data: None,
+        }),
+        id,
+    }
+}
+
+/// Helper function to create success response
+pub fn create_success_response(result: Value, id: Option<Value>) -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(result),
+        error: None,
+        id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::amount::UNITS_PER_COIN;
+    use crate::blockchain::chain::Chain;
+    use crate::mempool::Mempool;
+    use crate::wallet::keychain::Wallet;
+
+    fn create_test_handler() -> BlockchainRpcHandler {
+        let chain = Chain::new();
+        let mempool = Mempool::new();
+        let wallet = Wallet::new();
+        BlockchainRpcHandler::new(chain, mempool, wallet)
+    }
+
+    #[test]
+    fn test_get_blockchain_info() {
+        let handler = create_test_handler();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockchaininfo".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+
+        let response = handler.handle_request(request);
+        assert!(response.result.is_some());
+        assert!(response.error.is_none());
+    }
+
+    #[test]
+    fn test_get_balance_and_balances() {
+        let handler = create_test_handler();
+        let call = |method: &str| handler.handle_request(JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        }).result.unwrap();
+
+        assert_eq!(call("getbalance"), Value::Number(0.into()));
+        let balances = call("getbalances");
+        assert_eq!(balances["balance"], 0);
+        assert_eq!(balances["immature"], 0);
+    }
+
+    #[test]
+    fn test_get_block_count() {
+        let handler = create_test_handler();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockcount".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+
+        let response = handler.handle_request(request);
+        assert!(response.result.is_some());
+        assert!(response.error.is_none());
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        let handler = create_test_handler();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "unknownmethod".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+
+        let response = handler.handle_request(request);
+        assert!(response.result.is_none());
+        assert!(response.error.is_some());
+        assert_eq!(response.error.unwrap().code, error_codes::METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let handler = create_test_handler();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockhash".to_string(),
+            params: Some(Value::String("invalid".to_string())),
+            id: Some(Value::Number(1.into())),
+        };
+
+        let response = handler.handle_request(request);
+        assert!(response.result.is_none());
+        assert!(response.error.is_some());
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_get_block_verbosity() {
+        let handler = create_test_handler();
+        let genesis = handler.chain.block_at(0).unwrap().unwrap();
+        let get_block = |verbosity: Value| handler.handle_request(JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblock".to_string(),
+            params: Some(serde_json::json!([genesis.header.hash, verbosity])),
+            id: Some(Value::Number(1.into())),
+        });
+
+        let hex = get_block(Value::from(0)).result.unwrap();
+        assert_eq!(hex.as_str().unwrap(), genesis.to_hex());
+
+        let block = get_block(Value::Bool(true)).result.unwrap();
+        assert_eq!(block["tx"][0], genesis.transactions[0].hash());
+        assert_eq!(block["size"], genesis.serialize().len());
+
+        let block = get_block(Value::from(2)).result.unwrap();
+        let tx = &genesis.transactions[0];
+        assert_eq!(block["tx"][0]["txid"], tx.hash());
+        assert_eq!(block["tx"][0]["size"], tx.serialize().len());
+        assert_eq!(block["tx"][0]["vout"][0]["address"], tx.to.as_str());
+
+        assert_eq!(get_block(Value::from(3)).error.unwrap().code, error_codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_get_raw_transaction_roundtrip() {
+        let handler = create_test_handler();
+        let genesis_tx = handler.chain.block_at(0).unwrap().unwrap().transactions[0].clone();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getrawtransaction".to_string(),
+            params: Some(serde_json::json!([genesis_tx.hash()])),
+            id: Some(Value::Number(1.into())),
+        };
+
+        let response = handler.handle_request(request);
+        let hex = response.result.unwrap();
+        let decoded = Transaction::from_hex(hex.as_str().unwrap()).unwrap();
+        assert_eq!(decoded.hash(), genesis_tx.hash());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getrawtransaction".to_string(),
+            params: Some(serde_json::json!([genesis_tx.hash(), true])),
+            id: Some(Value::Number(2.into())),
+        };
+
+        let verbose = handler.handle_request(request).result.unwrap();
+        assert_eq!(verbose["confirmations"], 1);
+        assert_eq!(verbose["blockheight"], 0);
+        assert_eq!(verbose["vout"][0]["address"], genesis_tx.to.as_str());
+    }
+
+    #[test]
+    fn test_decode_raw_transaction() {
+        let handler = create_test_handler();
+        let tx = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 42,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "decoderawtransaction".to_string(),
+            params: Some(serde_json::json!([tx.to_hex()])),
+            id: Some(Value::Number(1.into())),
+        };
+
+        let decoded = handler.handle_request(request).result.unwrap();
+        assert_eq!(decoded["txid"], tx.hash().as_str());
+        assert_eq!(decoded["vin"][0]["address"], "alice");
+        assert_eq!(decoded["vout"][0]["value"], 42);
+        assert_eq!(decoded["vout"][0]["value_coins"], "0.00000042");
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "decoderawtransaction".to_string(),
+            params: Some(serde_json::json!(["zz"])),
+            id: Some(Value::Number(2.into())),
+        };
+
+        let response = handler.handle_request(request);
+        assert_eq!(response.error.unwrap().code, error_codes::DESERIALIZATION_ERROR);
+    }
+
+    #[test]
+    fn test_fee_histogram_and_estimatefee() {
+        let handler = create_test_handler();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getmempoolfeehistogram".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+
+        let histogram = handler.handle_request(request).result.unwrap();
+        assert_eq!(histogram["pending"], 0);
+        assert!(!histogram["histogram"].as_array().unwrap().is_empty());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "estimatefee".to_string(),
+            params: Some(serde_json::json!([3])),
+            id: Some(Value::Number(2.into())),
+        };
+
+        let estimate = handler.handle_request(request).result.unwrap();
+        assert_eq!(estimate["blocks"], 3);
+        assert_eq!(estimate["feerate"], 0.0);
+    }
+
+    #[test]
+    fn test_list_transactions_tags_mining_income() {
+        use crate::blockchain::block::Block;
+        use crate::blockchain::genesis::coinbase_transaction;
+
+        let mut wallet = Wallet::new();
+        let miner = wallet.address();
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let block = Block::new(genesis.header.hash.clone(), vec![coinbase_transaction(&miner, 50, 1)], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block));
+        let handler = BlockchainRpcHandler::new(chain, Mempool::new(), wallet);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "listtransactions".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+        let history = handler.handle_request(request).result.unwrap();
+        let history = history.as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["category"], "mining_income");
+        assert_eq!(history[0]["address"], miner.as_str());
+        assert_eq!(history[0]["amount"], 50);
+        assert_eq!(history[0]["spendable"], false);
+    }
+
+    #[test]
+    fn test_block_template() {
+        let chain = Chain::new();
+        let mut mempool = Mempool::new();
+        let transaction = Transaction {
+            from: "1Community".to_string(),
+            to: "bob".to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        mempool.add_transaction_with_fee(transaction.clone(), 2.0, &chain.utxo_state().unwrap()).unwrap();
+        let tip_hash = chain.tip().unwrap().header.hash.clone();
+        let handler = BlockchainRpcHandler::new(chain, mempool, Wallet::new());
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblocktemplate".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+
+        let template = handler.handle_request(request).result.unwrap();
+        assert_eq!(template["previousblockhash"], tip_hash);
+        assert_eq!(template["height"], 1);
+        assert_eq!(template["transactions"][0]["txid"], transaction.hash());
+        let fees = template["fees"].as_f64().unwrap();
+        assert_eq!(template["transactions"][0]["fee"].as_f64().unwrap(), fees);
+        assert_eq!(template["coinbasevalue"], template["subsidy"]);
+        // Nothing is mined or taken out of the pool
+        assert_eq!(handler.chain.block_count(), 1);
+        assert_eq!(handler.mempool().size(), 1);
+    }
+
+    #[test]
+    fn test_raw_mempool_and_mempool_entry() {
+        use crate::blockchain::state::UTXOState;
+
+        let mut mempool = Mempool::new();
+        let mut state = UTXOState::new();
+        state.credit("alice", 100).unwrap();
+        let transaction = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        mempool.add_transaction_with_fee(transaction.clone(), 2.0, &state).unwrap();
+        let handler = BlockchainRpcHandler::new(Chain::new(), mempool, Wallet::new());
+        let request = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: Some(Value::Number(1.into())),
+        };
+        let txid = transaction.hash();
+
+        let txids = handler.handle_request(request("getrawmempool", serde_json::json!([]))).result.unwrap();
+        assert_eq!(txids, serde_json::json!([txid]));
+
+        let verbose = handler.handle_request(request("getrawmempool", serde_json::json!([true]))).result.unwrap();
+        assert_eq!(verbose[&txid]["feerate"], 2.0);
+        assert_eq!(verbose[&txid]["ancestorcount"], 1);
+
+        let entry = handler.handle_request(request("getmempoolentry", serde_json::json!([txid]))).result.unwrap();
+        assert_eq!(entry["time"], verbose[&txid]["time"]);
+        assert_eq!(entry["fee"].as_f64().unwrap(), 2.0 * entry["size"].as_f64().unwrap());
+        assert!(entry["depends"].as_array().unwrap().is_empty());
+
+        let response = handler.handle_request(request("getmempoolentry", serde_json::json!(["unknown"])));
+        assert_eq!(response.error.unwrap().code, error_codes::TRANSACTION_NOT_FOUND);
+        let response = handler.handle_request(request("getmempoolentry", serde_json::json!([])));
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_estimate_smart_fee() {
+        let dir = std::env::temp_dir().join(format!("rust_chain_rpc_fees_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let data_dir = dir.to_str().unwrap();
+        let request = |target: u64| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "estimatesmartfee".to_string(),
+            params: Some(serde_json::json!([target])),
+            id: Some(Value::Number(1.into())),
+        };
+
+        let handler = create_test_handler().with_data_dir(data_dir);
+        let estimate = handler.handle_request(request(2)).result.unwrap();
+        assert!(estimate["feerate"].is_null());
+        assert_eq!(estimate["errors"].as_array().unwrap().len(), 1);
+
+        let mut estimator = FeeEstimator::new();
+        estimator.process_block(1, &[(5.0, 1), (5.0, 1), (5.0, 2)]);
+        estimator.save_to_file(FeeEstimator::path_in(data_dir)).unwrap();
+
+        let estimate = handler.handle_request(request(2)).result.unwrap();
+        assert_eq!(estimate["blocks"], 2);
+        assert!(estimate["feerate"].as_f64().unwrap() <= 5.0);
+        assert_eq!(handler.handle_request(request(0)).error.unwrap().code, error_codes::INVALID_PARAMS);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_block_stats_and_chain_tx_stats() {
+        use crate::blockchain::block::Block;
+
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let tx = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let block = Block::new(genesis.header.hash.clone(), vec![tx], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block.clone()));
+        let handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockstats".to_string(),
+            params: Some(serde_json::json!([block.header.hash])),
+            id: Some(Value::Number(1.into())),
+        };
+        let stats = handler.handle_request(request).result.unwrap();
+        assert_eq!(stats["height"], 1);
+        assert_eq!(stats["txs"], 1);
+        assert_eq!(stats["subsidy"], 0);
+        assert_eq!(stats["timedelta"], 20);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockstats".to_string(),
+            params: Some(serde_json::json!([0])),
+            id: Some(Value::Number(2.into())),
+        };
+        let stats = handler.handle_request(request).result.unwrap();
+        assert!(stats["subsidy"].as_u64().unwrap() > 0);
+        assert!(stats["timedelta"].is_null());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getchaintxstats".to_string(),
+            params: Some(serde_json::json!([1])),
+            id: Some(Value::Number(3.into())),
+        };
+        let stats = handler.handle_request(request).result.unwrap();
+        assert_eq!(stats["window_tx_count"], 1);
+        assert_eq!(stats["window_interval"], 20);
+        assert_eq!(stats["txrate"], 0.05);
+
+        // Window longer than the chain
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getchaintxstats".to_string(),
+            params: Some(serde_json::json!([5])),
+            id: Some(Value::Number(4.into())),
+        };
+        assert_eq!(handler.handle_request(request).error.unwrap().code, error_codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_address_balance_and_richest_addresses() {
+        let handler = create_test_handler();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getaddressbalance".to_string(),
+            params: Some(serde_json::json!(["1Community"])),
+            id: Some(Value::Number(1.into())),
+        };
+
+        let balance = handler.handle_request(request).result.unwrap();
+        assert_eq!(balance["balance"], 15_750_000 * UNITS_PER_COIN);
+        assert_eq!(balance["balance_coins"], "15750000.00000000");
+        assert_eq!(balance["received"], 15_750_000 * UNITS_PER_COIN);
+        assert_eq!(balance["txcount"], 1);
+
+        // As of a height, which must be on the chain
+        let at_height = |height: u64| handler.handle_request(JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getaddressbalance".to_string(),
+            params: Some(serde_json::json!(["1Community", height])),
+            id: Some(Value::Number(1.into())),
+        });
+        let balance_at_genesis = at_height(0).result.unwrap();
+        assert_eq!(balance_at_genesis["balance"], 15_750_000 * UNITS_PER_COIN);
+        assert_eq!(balance_at_genesis["height"], 0);
+        assert_eq!(at_height(1).error.unwrap().code, error_codes::INVALID_PARAMS);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getrichestaddresses".to_string(),
+            params: Some(serde_json::json!([2])),
+            id: Some(Value::Number(2.into())),
+        };
+
+        let richest = handler.handle_request(request).result.unwrap();
+        let richest = richest.as_array().unwrap();
+        assert_eq!(richest.len(), 2);
+        assert_eq!(richest[0]["address"], "1Community");
+        // Equal balances are ordered by address
+        assert_eq!(richest[1]["address"], "1Development");
+    }
+
+    #[test]
+    fn test_unspent_outputs() {
+        use crate::blockchain::block::Block;
+
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let payment = Transaction {
+            from: "1Community".to_string(),
+            to: "bob".to_string(),
+            amount: 100,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let block = Block::new(genesis.header.hash.clone(), vec![payment.clone()], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block));
+        let handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        // The genesis allocation is partly spent
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getaddressutxos".to_string(),
+            params: Some(serde_json::json!(["1Community"])),
+            id: Some(Value::Number(1.into())),
+        };
+        let outputs = handler.handle_request(request).result.unwrap();
+        let outputs = outputs.as_array().unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0]["amount"], 15_750_000 * UNITS_PER_COIN - 100);
+        assert_eq!(outputs[0]["confirmations"], 2);
+        assert_eq!(outputs[0]["coinbase"], true);
+        assert_eq!(outputs[0]["spendable"], true);
+        assert_eq!(outputs[0]["synthetic"], true);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "listunspent".to_string(),
+            params: Some(serde_json::json!([1, ["bob", "1Community"]])),
+            id: Some(Value::Number(2.into())),
+        };
+        let outputs = handler.handle_request(request).result.unwrap();
+        let outputs = outputs.as_array().unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0]["txid"], payment.hash());
+        assert_eq!(outputs[0]["vout"], 0);
+        assert_eq!(outputs[0]["amount"], 100);
+
+        // Outputs of the block just mined do not have two confirmations yet
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "listunspent".to_string(),
+            params: Some(serde_json::json!([2, ["bob", "1Community"]])),
+            id: Some(Value::Number(3.into())),
+        };
+        let outputs = handler.handle_request(request).result.unwrap();
+        assert_eq!(outputs.as_array().unwrap().len(), 1);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "listunspent".to_string(),
+            params: Some(serde_json::json!([1, "bob"])),
+            id: Some(Value::Number(4.into())),
+        };
+        assert_eq!(handler.handle_request(request).error.unwrap().code, error_codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_get_block_header() {
+        use crate::blockchain::block::Block;
+
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let block = Block::new(genesis.header.hash.clone(), vec![], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block.clone()));
+        let handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockheader".to_string(),
+            params: Some(serde_json::json!([genesis.header.hash])),
+            id: Some(Value::Number(1.into())),
+        };
+        let header = handler.handle_request(request).result.unwrap();
+        assert_eq!(header["height"], 0);
+        assert_eq!(header["confirmations"], 2);
+        assert_eq!(header["nextblockhash"], block.header.hash.as_str());
+        assert!(header["previousblockhash"].is_null());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockheader".to_string(),
+            params: Some(serde_json::json!([block.header.hash, false])),
+            id: Some(Value::Number(2.into())),
+        };
+        let hex = handler.handle_request(request).result.unwrap();
+        assert_eq!(hex, block.header.to_hex().as_str());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockheader".to_string(),
+            params: Some(serde_json::json!(["missing"])),
+            id: Some(Value::Number(3.into())),
+        };
+        assert_eq!(handler.handle_request(request).error.unwrap().code, error_codes::BLOCK_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_named_wallet_requests() {
+        let savings = Wallet::new();
+        let address = savings.get_new_address_readonly();
+        let handler = create_test_handler()
+            .with_wallets([("savings".to_string(), savings)].into_iter().collect());
+
+        let request = || JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getnewaddress".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+        assert_eq!(handler.handle_wallet_request("savings", request()).result.unwrap(), address.as_str());
+        assert_ne!(handler.handle_request(request()).result.unwrap(), address.as_str());
+        assert_eq!(handler.handle_wallet_request("missing", request()).error.unwrap().code, error_codes::WALLET_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_invalidate_and_reconsider_block() {
+        use crate::blockchain::block::Block;
+
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let block = Block::new(genesis.header.hash.clone(), vec![], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block.clone()));
+        let mut handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let request = |method: &str, hash: &str| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(serde_json::json!([hash])),
+            id: Some(Value::Number(1.into())),
+        };
+
+        // Privileged methods are not served by the read-only path
+        let response = handler.handle_request(request("invalidateblock", &block.header.hash));
+        assert_eq!(response.error.unwrap().code, error_codes::METHOD_NOT_FOUND);
+
+        let result = handler.handle_privileged_request(request("invalidateblock", &block.header.hash)).result.unwrap();
+        assert_eq!(result["height"], 0);
+        assert_eq!(result["disconnected"][0], block.header.hash.as_str());
+        assert!(handler.chain.is_invalid(&block.header.hash));
+
+        let response = handler.handle_privileged_request(request("reconsiderblock", &genesis.header.hash));
+        assert_eq!(response.error.unwrap().code, error_codes::BLOCK_NOT_FOUND);
+
+        handler.handle_privileged_request(request("reconsiderblock", &block.header.hash)).result.unwrap();
+        assert!(!handler.chain.is_invalid(&block.header.hash));
+    }
+
+    #[test]
+    fn test_get_chain_tips() {
+        use crate::blockchain::block::Block;
+
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let block = Block::new(genesis.header.hash.clone(), vec![], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block.clone()));
+
+        // A competing block on top of genesis, known only to fork choice
+        let mut fork_choice = ForkChoice::with_genesis_chain(Chain::new());
+        let fork = Block::new(genesis.header.hash.clone(), vec![], 0, genesis.header.timestamp + 40, 1);
+        fork_choice.add_block(fork.clone()).unwrap();
+        let mut handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new())
+            .with_fork_choice(fork_choice);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getchaintips".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+        let tips = handler.handle_request(request.clone()).result.unwrap();
+        let tips = tips.as_array().unwrap();
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0]["hash"], block.header.hash.as_str());
+        assert_eq!(tips[0]["status"], "active");
+        assert_eq!(tips[0]["branchlen"], 0);
+        assert_eq!(tips[1]["hash"], fork.header.hash.as_str());
+        assert_eq!(tips[1]["height"], 1);
+        assert_eq!(tips[1]["branchlen"], 1);
+        assert_eq!(tips[1]["status"], "valid-fork");
+
+        handler.chain.invalidate_block(&fork.header.hash).unwrap();
+        let tips = handler.handle_request(request).result.unwrap();
+        assert_eq!(tips[1]["status"], "invalid");
+    }
+
+    #[test]
+    fn test_send_raw_transaction_rejections() {
+        use crate::blockchain::block::Block;
+        use crate::blockchain::genesis::COINBASE_ADDRESS;
+        use crate::mempool::RelayPolicy;
+
+        let tx = |from: &str, to: &str, amount| Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![1; 64],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let block = Block::new(genesis.header.hash.clone(), vec![tx(COINBASE_ADDRESS, "miner", 50)], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block));
+        let mut handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let send = |handler: &mut BlockchainRpcHandler, params: Value| {
+            let request = JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                method: "sendrawtransaction".to_string(),
+                params: Some(params),
+                id: Some(Value::Number(1.into())),
+            };
+            assert!(needs_write_access(&request.method) && !is_privileged(&request.method));
+            handler.handle_privileged_request(request).error.unwrap()
+        };
+        let code = |error: JsonRpcError| (error.code, error.data.unwrap()["reason"].as_str().unwrap().to_string());
+
+        assert_eq!(send(&mut handler, serde_json::json!(["zz"])).code, error_codes::DESERIALIZATION_ERROR);
+        assert_eq!(send(&mut handler, serde_json::json!([tx("alice", "bob", 5).to_hex(), 2.0])).code, error_codes::INVALID_PARAMS);
+        assert_eq!(
+            code(send(&mut handler, serde_json::json!([tx("alice", "alice", 5).to_hex()]))),
+            (error_codes::SELF_TRANSFER, "self_transfer".to_string())
+        );
+        assert_eq!(
+            code(send(&mut handler, serde_json::json!([tx("alice", "bob", 5).to_hex()]))),
+            (error_codes::INSUFFICIENT_FUNDS, "insufficient_funds".to_string())
+        );
+        assert_eq!(
+            code(send(&mut handler, serde_json::json!([tx("miner", "bob", 5).to_hex()]))),
+            (error_codes::IMMATURE_COINBASE, "immature_coinbase".to_string())
+        );
+
+        handler.mempool().set_policy(RelayPolicy { blocks_only: true, ..RelayPolicy::default() });
+        assert_eq!(
+            code(send(&mut handler, serde_json::json!([tx("alice", "bob", 5).to_hex()]))),
+            (error_codes::RELAY_DISABLED, "relay_disabled".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalidated_transactions_report_status() {
+        use crate::blockchain::block::Block;
+        use crate::blockchain::genesis::COINBASE_ADDRESS;
+
+        let tx = |from: &str, to: &str, amount| Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![1; 10],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let coinbase = tx(COINBASE_ADDRESS, "miner", 50);
+        let spend = tx("alice", "bob", 5);
+        let mut chain = Chain::new();
+        let genesis = chain.block_at(0).unwrap().unwrap();
+        let block = Block::new(genesis.header.hash.clone(), vec![coinbase.clone(), spend.clone()], 0, genesis.header.timestamp + 20, 1);
+        assert!(chain.add_block(block.clone()));
+        let mut handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let request = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: Some(Value::Number(1.into())),
+        };
+        let status = |handler: &BlockchainRpcHandler, txid: &str| {
+            handler.handle_request(request("getrawtransaction", serde_json::json!([txid, true]))).result.unwrap()
+        };
+        assert_eq!(status(&handler, &spend.hash())["status"], "confirmed");
+
+        // The spend no longer validates, so it cannot return to the mempool
+        let result = handler.handle_privileged_request(request("invalidateblock", serde_json::json!([block.header.hash]))).result.unwrap();
+        assert_eq!(result["abandoned"], serde_json::json!([spend.hash()]));
+        assert_eq!(result["resubmitted"], serde_json::json!([]));
+
+        let spent = status(&handler, &spend.hash());
+        assert_eq!(spent["status"], "abandoned");
+        assert_eq!(spent["confirmations"], 0);
+        assert_eq!(spent["lastblockhash"], block.header.hash.as_str());
+        assert_eq!(status(&handler, &coinbase.hash())["status"], "abandoned");
+
+        // Connecting the block again confirms its transactions
+        handler.handle_privileged_request(request("reconsiderblock", serde_json::json!([block.header.hash]))).result.unwrap();
+        assert!(handler.chain.add_block(block));
+        assert_eq!(status(&handler, &spend.hash())["status"], "confirmed");
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        use crate::blockchain::block::Block;
+
+        let mut chain = Chain::new();
+        let genesis_time = chain.block_at(0).unwrap().unwrap().header.timestamp;
+        let spend = Transaction {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            amount: 5,
+            signature: vec![1; 10],
+            lock_height: None,
+            expiry_height: None,
+        };
+        for height in 1..=3 {
+            let prev_hash = chain.tip().unwrap().header.hash.clone();
+            let transactions = if height == 3 { vec![spend.clone()] } else { vec![] };
+            assert!(chain.add_block(Block::new(prev_hash, transactions, 0, genesis_time + height * 10, height)));
+        }
+        let handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let verify = |params: Value| handler.handle_request(JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "verifychain".to_string(),
+            params: Some(params),
+            id: Some(Value::Number(1.into())),
+        });
+
+        let result = verify(serde_json::json!([1, 0])).result.unwrap();
+        assert_eq!(result["valid"], true);
+        assert_eq!(result["blocks_checked"], 4);
+
+        // None of the blocks were mined
+        let result = verify(serde_json::json!([2, 2])).result.unwrap();
+        assert_eq!(result["valid"], false);
+        assert_eq!(result["from_height"], 2);
+        assert_eq!(result["issues"].as_array().unwrap().len(), 2);
+
+        let result = verify(serde_json::json!([4, 1])).result.unwrap();
+        let issues: Vec<&str> = result["issues"].as_array().unwrap().iter().filter_map(|i| i.as_str()).collect();
+        assert!(issues.iter().any(|issue| issue.contains("malformed signature")));
+        assert!(issues.iter().any(|issue| issue.contains("alice spends 5 with a balance of 0")));
+
+        let response = verify(serde_json::json!([5]));
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_mining_info_and_network_hash_ps() {
+        use crate::consensus::params::ChainParams;
+        use crate::consensus::pow::ProofOfWork;
+
+        // The retarget schedule comes from the chain's parameters; each block
+        // is mined to a target worth 256 hashes
+        let mut chain = Chain::new();
+        chain.set_params(ChainParams { retarget_interval: 10, pow_limit_bits: 0x2000ffff, ..ChainParams::regtest() });
+        let genesis_time = chain.block_at(0).unwrap().unwrap().header.timestamp;
+        let clock = MockClock::new(genesis_time);
+        let mut pow = ProofOfWork::with_bits(0x2000ffff);
+        pow.set_clock(Arc::new(clock.clone()));
+        for height in 1..=4 {
+            let tip = chain.tip().unwrap().header.clone();
+            clock.set(genesis_time + height * 10);
+            pow.set_parent_timestamp(tip.timestamp);
+            let block = pow.mine_block(tip.hash, vec![], height).block;
+            assert_eq!(block.work(), 256);
+            assert!(chain.add_block(block));
+        }
+        let handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getmininginfo".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+        let info = handler.handle_request(request).result.unwrap();
+        assert_eq!(info["blocks"], 4);
+        assert_eq!(info["difficulty"], 1.0);
+        assert_eq!(info["avgblocktime"], 10.0);
+
+        // Two 10 second intervals ending at height 3, each ending in a block worth 256 hashes
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getnetworkhashps".to_string(),
+            params: Some(serde_json::json!([2, 3])),
+            id: Some(Value::Number(2.into())),
+        };
+        let hashps = handler.handle_request(request).result.unwrap();
+        assert_eq!(hashps.as_f64().unwrap(), 256.0 / 10.0);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getnetworkhashps".to_string(),
+            params: Some(serde_json::json!([2, 9])),
+            id: Some(Value::Number(3.into())),
+        };
+        assert_eq!(handler.handle_request(request).error.unwrap().code, error_codes::INVALID_PARAMS);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getdifficulty".to_string(),
+            params: None,
+            id: Some(Value::Number(4.into())),
+        };
+        assert_eq!(handler.handle_request(request).result.unwrap(), 1.0);
+
+        // The retarget estimate uses the 10 second average block time
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockchaininfo".to_string(),
+            params: None,
+            id: Some(Value::Number(5.into())),
+        };
+        let info = handler.handle_request(request).result.unwrap();
+        assert_eq!(info["difficulty"], 1.0);
+        assert_eq!(info["bits"], "2000ffff");
+        assert_eq!(info["target"], format!("00ffff{}", "0".repeat(58)));
+        assert_eq!(info["nextretargetheight"], 10);
+        assert_eq!(info["blocksuntilretarget"], 6);
+        assert_eq!(info["timetoretarget"], 60);
+    }
+
+    #[test]
+    fn test_get_perf_stats() {
+        use crate::blockchain::block::Block;
+
+        let mut chain = Chain::new();
+        let tip = chain.tip().unwrap().header.clone();
+        assert!(chain.add_block(Block::new(tip.hash, vec![], 0, tip.timestamp + 10, 1)));
+        let handler = BlockchainRpcHandler::new(chain, Mempool::new(), Wallet::new());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getperfstats".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        };
+        let report = handler.handle_request(request).result.unwrap();
+        let validation = report["areas"].as_array().unwrap().iter()
+            .find(|stats| stats["area"] == perf::BLOCK_VALIDATION)
+            .unwrap();
+        assert!(validation["count"].as_u64().unwrap() >= 1);
+        assert!(validation["p50_micros"].as_u64().unwrap() <= validation["max_micros"].as_u64().unwrap());
+    }
+
+    #[test]
+    fn test_set_mock_time() {
+        use crate::blockchain::block::Block;
+        use crate::blockchain::chain::MAX_FUTURE_BLOCK_TIME;
+
+        let request = |time: u64| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "setmocktime".to_string(),
+            params: Some(serde_json::json!([time])),
+            id: Some(Value::Number(1.into())),
+        };
+
+        // Nodes without a mock clock refuse to change the time
+        let mut handler = BlockchainRpcHandler::new(Chain::new(), Mempool::new(), Wallet::new());
+        let response = handler.handle_privileged_request(request(1_000));
+        assert_eq!(response.error.unwrap().code, error_codes::FORBIDDEN);
+
+        let clock = MockClock::default();
+        let mut handler = BlockchainRpcHandler::new(Chain::new(), Mempool::new(), Wallet::new())
+            .with_mock_clock(clock.clone());
+        handler.handle_privileged_request(request(1_000)).result.unwrap();
+        assert_eq!(clock.mock_time(), Some(1_000));
+
+        // The chain checks timestamps against the mock time
+        let genesis = handler.chain.block_at(0).unwrap().unwrap();
+        let block = Block::new(genesis.header.hash.clone(), vec![], 0, 1_000 + MAX_FUTURE_BLOCK_TIME + 1, 1);
+        assert!(!handler.chain.validate_block(&block));
+        handler.handle_privileged_request(request(1_001)).result.unwrap();
+        assert!(handler.chain.validate_block(&block));
+
+        handler.handle_privileged_request(request(0)).result.unwrap();
+        assert_eq!(clock.mock_time(), None);
+    }
+
+    #[test]
+    fn test_peer_methods() {
+        let request = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: Some(Value::Number(1.into())),
+        };
+
+        // A stand-alone RPC server has no peers to manage
+        let mut handler = create_test_handler();
+        let response = handler.handle_request(request("getconnectioncount", Value::Null));
+        assert_eq!(response.error.unwrap().code, error_codes::NETWORK_DISABLED);
+        let response = handler.handle_privileged_request(request("addnode", serde_json::json!(["127.0.0.1:1", "add"])));
+        assert_eq!(response.error.unwrap().code, error_codes::NETWORK_DISABLED);
+
+        let network = Arc::new(NetworkServer::new(Chain::new(), "127.0.0.1".to_string(), 0));
+        let mut handler = create_test_handler().with_network(Arc::clone(&network));
+        let response = handler.handle_request(request("getconnectioncount", Value::Null));
+        assert_eq!(response.result, Some(serde_json::json!(0)));
+        // The handler serves the node's own mempool
+        assert!(Arc::ptr_eq(&handler.mempool, &network.mempool()));
+
+        // Nothing listens on port 1, but the node stays added for the watchdog
+        let add = request("addnode", serde_json::json!(["127.0.0.1:1", "add"]));
+        assert!(handler.handle_privileged_request(add.clone()).error.is_none());
+        assert_eq!(network.added_nodes(), vec![PeerAddress::new("127.0.0.1", 1).unwrap()]);
+        let response = handler.handle_privileged_request(add);
+        assert_eq!(response.error.unwrap().code, error_codes::NODE_ALREADY_ADDED);
+
+        let remove = request("addnode", serde_json::json!(["127.0.0.1:1", "remove"]));
+        assert!(handler.handle_privileged_request(remove.clone()).error.is_none());
+        assert!(network.added_nodes().is_empty());
+        let response = handler.handle_privileged_request(remove);
+        assert_eq!(response.error.unwrap().code, error_codes::NODE_NOT_ADDED);
+
+        let response = handler.handle_privileged_request(request("addnode", serde_json::json!(["127.0.0.1:1", "always"])));
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_PARAMS);
+        let response = handler.handle_privileged_request(request("disconnectnode", serde_json::json!(["127.0.0.1:1"])));
+        assert_eq!(response.error.unwrap().code, error_codes::NODE_NOT_CONNECTED);
+
+        // Peer management is refused from remote clients
+        assert!(is_privileged("addnode") && is_privileged("disconnectnode"));
+        assert!(!is_privileged("getconnectioncount"));
+    }
+
+    #[test]
+    fn test_set_mempool_limits() {
+        let request = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: Some(Value::Number(1.into())),
+        };
+        let mut handler = create_test_handler();
+
+        let response = handler.handle_privileged_request(request("setmempoollimits", serde_json::json!([5, null])));
+        assert_eq!(response.result.unwrap(), serde_json::json!({ "maxsize": 5, "maxage": 3600, "evicted": 0 }));
+        let info = handler.handle_request(request("getmempoolinfo", Value::Null)).result.unwrap();
+        assert_eq!((info["maxsize"].clone(), info["maxage"].clone()), (serde_json::json!(5), serde_json::json!(3600)));
+
+        for params in [serde_json::json!([0]), serde_json::json!([null, 0]), serde_json::json!([]), serde_json::json!(["big"])] {
+            let response = handler.handle_privileged_request(request("setmempoollimits", params));
+            assert_eq!(response.error.unwrap().code, error_codes::INVALID_PARAMS);
+        }
+        assert_eq!(handler.mempool().max_size(), 5);
+        assert!(is_privileged("setmempoollimits"));
     }
 }
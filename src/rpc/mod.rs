@@ -8,7 +8,9 @@
 //! - Network statistics
 
 pub mod handlers;
+pub mod errors;
 pub mod server;
+pub mod unix_socket;
 
 pub use handlers::{
     BlockchainRpcHandler, 
@@ -24,10 +26,19 @@ pub use handlers::{
 pub use server::{
     RpcServer,
     RpcConfig,
+    RpcStats,
+    RequestControl,
     RpcClient,
-    handle_batch_request
+    RpcShutdown,
+    ShutdownRequest,
+    handle_batch_request,
+    parse_cli_params,
+    split_cli_args,
+    DEFAULT_RPC_URL
 };
 
+pub use unix_socket::UNIX_URL_PREFIX;
+
 /// Re-export common types for convenience
 pub type RpcResult<T> = Result<T, JsonRpcError>;
 
@@ -37,13 +48,40 @@ pub mod methods {
     pub const GET_BLOCK_COUNT: &str = "getblockcount";
     pub const GET_BLOCK_HASH: &str = "getblockhash";
     pub const GET_BLOCK: &str = "getblock";
+    pub const GET_BLOCK_HEADER: &str = "getblockheader";
+    pub const GET_BLOCK_STATS: &str = "getblockstats";
+    pub const GET_CHAIN_TX_STATS: &str = "getchaintxstats";
+    pub const GET_CHAIN_TIPS: &str = "getchaintips";
+    pub const GET_MINING_INFO: &str = "getmininginfo";
+    pub const GET_NETWORK_HASH_PS: &str = "getnetworkhashps";
+    pub const GET_PROPAGATION_STATS: &str = "getpropagationstats";
+    pub const GET_ALERTS: &str = "getalerts";
+    pub const VERIFY_CHAIN: &str = "verifychain";
     pub const GET_TRANSACTION: &str = "gettransaction";
     pub const GET_MEMPOOL_INFO: &str = "getmempoolinfo";
     pub const GET_RAW_MEMPOOL: &str = "getrawmempool";
+    pub const GET_MEMPOOL_ENTRY: &str = "getmempoolentry";
     pub const SEND_RAW_TRANSACTION: &str = "sendrawtransaction";
     pub const GET_BALANCE: &str = "getbalance";
+    pub const GET_BALANCES: &str = "getbalances";
     pub const GET_NEW_ADDRESS: &str = "getnewaddress";
     pub const LIST_TRANSACTIONS: &str = "listtransactions";
+    pub const GET_RAW_TRANSACTION: &str = "getrawtransaction";
+    pub const DECODE_RAW_TRANSACTION: &str = "decoderawtransaction";
+    pub const GET_ADDRESS_TXIDS: &str = "getaddresstxids";
+    pub const GET_ADDRESS_BALANCE: &str = "getaddressbalance";
+    pub const GET_RICHEST_ADDRESSES: &str = "getrichestaddresses";
+    pub const GET_MEMPOOL_FEE_HISTOGRAM: &str = "getmempoolfeehistogram";
+    pub const GET_BLOCK_TEMPLATE: &str = "getblocktemplate";
+    pub const ESTIMATE_FEE: &str = "estimatefee";
+    pub const ESTIMATE_SMART_FEE: &str = "estimatesmartfee";
+    pub const INVALIDATE_BLOCK: &str = "invalidateblock";
+    pub const RECONSIDER_BLOCK: &str = "reconsiderblock";
+    pub const GET_CONNECTION_COUNT: &str = "getconnectioncount";
+    pub const ADD_NODE: &str = "addnode";
+    pub const DISCONNECT_NODE: &str = "disconnectnode";
+    pub const SET_MEMPOOL_LIMITS: &str = "setmempoollimits";
+    pub const STOP: &str = "stop";
 }
 
 #[cfg(test)]
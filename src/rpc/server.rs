@@ -1,17 +1,47 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{broadcast, watch, RwLock};
+use warp::ws::{Message, WebSocket};
 use warp::{Filter, Reply};
 use serde_json::Value;
 
+use crate::alerts::{AlertConfig, AlertLog, AlertMonitor};
+use crate::rpc::unix_socket;
 use crate::rpc::handlers::{
     BlockchainRpcHandler, JsonRpcRequest, JsonRpcResponse, RpcHandler,
-    create_error_response, error_codes
+    create_error_response, error_codes, is_privileged, needs_write_access
 };
+use crate::blockchain::analytics::{AnalyticsSeries, ChartMetric, MempoolSample, DEFAULT_CHART_WINDOW};
 use crate::blockchain::chain::Chain;
-use crate::mempool::Mempool;
+use crate::clock::{Clock, MockClock, SystemClock};
+use crate::consensus::fork_choice::ForkChoice;
+use crate::mempool::{Mempool, MempoolEvent};
+use crate::network::{NetworkServer, NodeStats};
+use crate::config::{ConfigWatcher, NodeConfig, CONFIG_POLL_INTERVAL};
 use crate::wallet::keychain::Wallet;
+use crate::status;
+
+/// Server the `rpc` subcommand talks to when no `--url` is given
+pub const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8545";
+
+/// Mempool events buffered for a slow WebSocket client before it skips ahead
+const WS_EVENT_BUFFER: usize = 1024;
+
+/// Times binding the RPC port is tried before giving up, for a port still held
+/// by a server that is shutting down
+const BIND_ATTEMPTS: u32 = 5;
+
+/// Pause between attempts to bind the RPC port
+const BIND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How often new blocks and a mempool sample are added to the chart series
+const CHART_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
 
 /// JSON-RPC server configuration
 #[derive(Debug, Clone)]
@@ -20,6 +50,16 @@ pub struct RpcConfig {
     pub max_request_size: usize,
     pub enable_cors: bool,
     pub allowed_origins: Vec<String>,
+    /// Requests taking longer than this are answered with a timeout error
+    pub request_timeout: Duration,
+    /// Requests beyond this many in flight are rejected as busy
+    pub max_concurrent_requests: usize,
+    /// Requests taking at least this long are written to the slow request log
+    pub slow_request_threshold: Duration,
+    /// Log one line per request with method, status and duration
+    pub access_log: bool,
+    /// Serve on this Unix domain socket instead of `bind_address`
+    pub unix_socket: Option<PathBuf>,
 }
 
 impl Default for RpcConfig {
@@ -29,42 +69,386 @@ impl Default for RpcConfig {
             max_request_size: 1024 * 1024, // 1MB
             enable_cors: true,
             allowed_origins: vec!["*".to_string()],
+            request_timeout: Duration::from_secs(30),
+            max_concurrent_requests: 16,
+            slow_request_threshold: Duration::from_secs(1),
+            access_log: true,
+            unix_socket: None,
+        }
+    }
+}
+
+/// Request counters reported on the metrics endpoint
+#[derive(Debug, Default)]
+pub struct RpcStats {
+    pub requests: AtomicU64,
+    pub errors: AtomicU64,
+    pub timeouts: AtomicU64,
+    pub rejected: AtomicU64,
+    pub slow: AtomicU64,
+}
+
+/// Limits and logging settings of a `RequestControl`, replaceable while serving
+#[derive(Debug, Clone, Copy)]
+struct RequestSettings {
+    max_concurrent_requests: usize,
+    request_timeout: Duration,
+    slow_request_threshold: Duration,
+    access_log: bool,
+}
+
+/// Releases an in-flight slot when the request's handler finishes
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Admission control, timeouts and logging shared by all requests of a server
+pub struct RequestControl {
+    in_flight: Arc<AtomicUsize>,
+    settings: std::sync::RwLock<RequestSettings>,
+    pub stats: RpcStats,
+}
+
+impl RequestControl {
+    pub fn new(config: &RpcConfig) -> Self {
+        RequestControl {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            settings: std::sync::RwLock::new(RequestSettings {
+                max_concurrent_requests: config.max_concurrent_requests,
+                request_timeout: config.request_timeout,
+                slow_request_threshold: config.slow_request_threshold,
+                access_log: config.access_log,
+            }),
+            stats: RpcStats::default(),
         }
     }
+
+    fn settings(&self) -> RequestSettings {
+        *self.settings.read().unwrap()
+    }
+
+    /// Number of requests currently being handled
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Maximum number of requests handled at once
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.settings().max_concurrent_requests
+    }
+
+    /// Apply reloadable limits and logging settings from a config file. A lower
+    /// in-flight limit only turns away new requests; running ones are not cut short.
+    pub fn apply_config(&self, config: &NodeConfig) {
+        let mut settings = self.settings.write().unwrap();
+        settings.max_concurrent_requests = config.rpc_max_inflight.unwrap_or(settings.max_concurrent_requests);
+        if let Some(secs) = config.rpc_timeout_secs {
+            settings.request_timeout = Duration::from_secs(secs);
+        }
+        if let Some(ms) = config.slow_request_ms {
+            settings.slow_request_threshold = Duration::from_millis(ms);
+        }
+        settings.access_log = config.access_log.unwrap_or(settings.access_log);
+    }
+
+    /// Take an in-flight slot unless the limit is reached
+    fn admit(&self, limit: usize) -> Option<InFlightGuard> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| (count < limit).then_some(count + 1))
+            .ok()
+            .map(|_| InFlightGuard(Arc::clone(&self.in_flight)))
+    }
+
+    /// Run a request on the blocking pool, bounded by the in-flight limit and timeout.
+    /// Wallet methods use the named `wallet` when one is given.
+    pub async fn execute(
+        &self,
+        handler: Arc<RwLock<BlockchainRpcHandler>>,
+        request: JsonRpcRequest,
+        wallet: Option<String>,
+    ) -> JsonRpcResponse {
+        let id = request.id.clone();
+        let settings = self.settings();
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+
+        let Some(guard) = self.admit(settings.max_concurrent_requests) else {
+            self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+            return create_error_response(
+                error_codes::SERVER_BUSY,
+                format!("Server busy: {} requests already in flight", settings.max_concurrent_requests),
+                id,
+            );
+        };
+
+        // The guard moves into the task, so a timed-out request still counts
+        // against the limit until its handler actually finishes
+        let task = tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            if needs_write_access(&request.method) {
+                handler.blocking_write().handle_privileged_request(request)
+            } else if let Some(wallet) = wallet {
+                handler.blocking_read().handle_wallet_request(&wallet, request)
+            } else {
+                handler.blocking_read().handle_request(request)
+            }
+        });
+
+        match tokio::time::timeout(settings.request_timeout, task).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => create_error_response(
+                error_codes::INTERNAL_ERROR,
+                format!("Request handler failed: {}", e),
+                id,
+            ),
+            Err(_) => {
+                self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+                create_error_response(
+                    error_codes::REQUEST_TIMEOUT,
+                    format!("Request timed out after {} ms", settings.request_timeout.as_millis()),
+                    id,
+                )
+            }
+        }
+    }
+
+    /// Record a finished request in the counters, access log and slow request log
+    fn record(&self, method: &str, remote: Option<SocketAddr>, response: &JsonRpcResponse, elapsed: Duration) {
+        let status = match &response.error {
+            Some(error) => {
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                format!("error({})", error.code)
+            },
+            None => "ok".to_string(),
+        };
+        let remote = remote.map(|addr| addr.to_string()).unwrap_or_else(|| "-".to_string());
+        let settings = self.settings();
+
+        if settings.access_log {
+            println!("rpc remote={} method={} status={} duration_ms={}", remote, method, status, elapsed.as_millis());
+        }
+        if elapsed >= settings.slow_request_threshold {
+            self.stats.slow.fetch_add(1, Ordering::Relaxed);
+            eprintln!(
+                "rpc slow request remote={} method={} status={} duration_ms={} threshold_ms={}",
+                remote, method, status, elapsed.as_millis(), settings.slow_request_threshold.as_millis()
+            );
+        }
+    }
+}
+
+/// What a running `RpcServer` was asked to do once its listener is closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownRequest {
+    /// Return from `RpcServer::start`
+    Stop,
+    /// Bind the listener again and keep serving
+    Restart,
+}
+
+/// Asks a running `RpcServer` to stop accepting connections. Requests in flight
+/// are answered before the listener closes. Clones control the same server.
+#[derive(Debug, Clone)]
+pub struct RpcShutdown {
+    sender: Arc<watch::Sender<Option<ShutdownRequest>>>,
+}
+
+impl RpcShutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(None);
+        RpcShutdown { sender: Arc::new(sender) }
+    }
+
+    /// Stop the server, making `start` return
+    pub fn stop(&self) {
+        self.sender.send_replace(Some(ShutdownRequest::Stop));
+    }
+
+    /// Close the listener and bind it again, e.g. after the daemon changed settings
+    pub fn restart(&self) {
+        self.sender.send_replace(Some(ShutdownRequest::Restart));
+    }
+
+    /// The pending request, if the server was asked to stop or restart
+    pub fn requested(&self) -> Option<ShutdownRequest> {
+        *self.sender.borrow()
+    }
+
+    fn clear(&self) {
+        self.sender.send_replace(None);
+    }
+
+    /// Wait until a stop or restart is requested
+    async fn requested_shutdown(&self) -> ShutdownRequest {
+        let mut receiver = self.sender.subscribe();
+        let requested = receiver.wait_for(Option::is_some).await.map(|request| *request);
+        requested.ok().flatten().unwrap_or(ShutdownRequest::Stop)
+    }
+}
+
+impl Default for RpcShutdown {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// JSON-RPC server
 pub struct RpcServer {
     config: RpcConfig,
     handler: Arc<RwLock<BlockchainRpcHandler>>,
+    control: Arc<RequestControl>,
+    config_watcher: Option<ConfigWatcher>,
+    shutdown: RpcShutdown,
+    /// Series the `/charts` endpoints are drawn from
+    charts: Arc<Mutex<AnalyticsSeries>>,
 }
 
 impl RpcServer {
     /// Create a new RPC server
     pub fn new(config: RpcConfig, chain: Chain, mempool: Mempool, wallet: Wallet) -> Self {
         let handler = BlockchainRpcHandler::new(chain, mempool, wallet);
+        let control = Arc::new(RequestControl::new(&config));
         
         RpcServer {
             config,
             handler: Arc::new(RwLock::new(handler)),
+            control,
+            config_watcher: None,
+            shutdown: RpcShutdown::new(),
+            charts: Arc::new(Mutex::new(AnalyticsSeries::new())),
+        }
+    }
+
+    /// Handle for stopping or restarting the server once it is started
+    pub fn shutdown_handle(&self) -> RpcShutdown {
+        self.shutdown.clone()
+    }
+
+    /// Let RPC methods read node records kept in the data directory
+    pub fn with_data_dir(mut self, data_dir: &str) -> Self {
+        if let Some(handler) = Arc::get_mut(&mut self.handler) {
+            handler.get_mut().data_dir = Some(data_dir.to_string());
+        }
+        self
+    }
+
+    /// Run the chain and mempool on a mock clock that `setmocktime` sets
+    pub fn with_mock_clock(mut self, clock: MockClock) -> Self {
+        if let Some(handler) = Arc::get_mut(&mut self.handler) {
+            handler.get_mut().set_mock_clock(clock);
+        }
+        self
+    }
+
+    /// Manage the peers of a running node through `addnode` and `disconnectnode`,
+    /// serving the node's mempool
+    pub fn with_network(mut self, network: Arc<NetworkServer>) -> Self {
+        if let Some(handler) = Arc::get_mut(&mut self.handler) {
+            handler.get_mut().set_network(network);
         }
+        self
     }
 
-    /// Start the RPC server
-    pub async fn start(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        println!("Starting JSON-RPC server on {}", self.config.bind_address);
+    /// Report the branches tracked by `fork_choice` in `getchaintips`
+    pub fn with_fork_choice(mut self, fork_choice: ForkChoice) -> Self {
+        if let Some(handler) = Arc::get_mut(&mut self.handler) {
+            handler.get_mut().fork_choice = fork_choice;
+        }
+        self
+    }
+
+    /// Serve named wallets under `/wallet/<name>`
+    pub fn with_wallets(mut self, wallets: HashMap<String, Wallet>) -> Self {
+        if let Some(handler) = Arc::get_mut(&mut self.handler) {
+            handler.get_mut().wallets = wallets;
+        }
+        self
+    }
+
+    /// Apply a config file now and again whenever it changes while serving
+    pub fn with_config_watcher(mut self, watcher: ConfigWatcher) -> Self {
+        self.control.apply_config(watcher.current());
+        if let Some(handler) = Arc::get_mut(&mut self.handler) {
+            handler.get_mut().mempool.lock().unwrap().apply_config(watcher.current());
+        }
+        self.config_watcher = Some(watcher);
+        self
+    }
+
+    /// Start the RPC server and serve until the handle from `shutdown_handle`
+    /// or the `stop` method stops it, binding the listener again whenever a
+    /// restart is requested
+    pub async fn start(mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(watcher) = self.config_watcher.take() {
+            tokio::spawn(Self::watch_config(watcher, self.handler.clone(), self.control.clone()));
+        }
+
+        let events = self.forward_mempool_events().await?;
+        self.handler.write().await.shutdown = Some(self.shutdown.clone());
+        self.load_charts().await;
+        tokio::spawn(Self::record_charts(self.handler.clone(), self.charts.clone()));
+
+        loop {
+            match self.serve(events.clone()).await? {
+                ShutdownRequest::Stop => {
+                    status!("JSON-RPC server stopped");
+                    return Ok(());
+                },
+                ShutdownRequest::Restart => {
+                    self.shutdown.clear();
+                    status!("Restarting JSON-RPC server");
+                },
+            }
+        }
+    }
+
+    /// Serve on the configured socket or address until a stop or restart is
+    /// requested, then let requests in flight finish and close the listener
+    async fn serve(
+        &self,
+        events: broadcast::Sender<MempoolEvent>,
+    ) -> Result<ShutdownRequest, Box<dyn std::error::Error + Send + Sync>> {
+        match &self.config.unix_socket {
+            Some(path) => status!("Starting JSON-RPC server on {}", path.display()),
+            None => status!("Starting JSON-RPC server on {}", self.config.bind_address),
+        }
+        let listener = self.config.unix_socket.as_deref().map(unix_socket::bind).transpose()?;
+        // Only the socket's owner can connect to it, so its requests are local
+        let over_socket = listener.is_some();
 
         let handler = self.handler.clone();
+        let control = self.control.clone();
         
         // JSON-RPC endpoint
         let rpc = warp::path("rpc")
             .and(warp::post())
+            .and(warp::addr::remote())
             .and(warp::body::content_length_limit(self.config.max_request_size as u64))
             .and(warp::body::json())
-            .and_then(move |request: JsonRpcRequest| {
+            .and_then(move |remote: Option<SocketAddr>, request: JsonRpcRequest| {
                 let handler = handler.clone();
+                let control = control.clone();
                 async move {
-                    Self::handle_rpc_request(handler, request).await
+                    Self::handle_rpc_request(handler, control, request, remote, over_socket, None).await
+                }
+            });
+
+        // The same endpoint with wallet methods bound to a named wallet
+        let wallet_handler = self.handler.clone();
+        let wallet_control = self.control.clone();
+        let wallet_rpc = warp::path!("wallet" / String)
+            .and(warp::post())
+            .and(warp::addr::remote())
+            .and(warp::body::content_length_limit(self.config.max_request_size as u64))
+            .and(warp::body::json())
+            .and_then(move |wallet: String, remote: Option<SocketAddr>, request: JsonRpcRequest| {
+                let handler = wallet_handler.clone();
+                let control = wallet_control.clone();
+                async move {
+                    Self::handle_rpc_request(handler, control, request, remote, over_socket, Some(wallet)).await
                 }
             });
 
@@ -78,72 +462,372 @@ impl RpcServer {
                 }))
             });
 
+        // A block with its transactions decoded, as `getblock` at verbosity 2
+        let block_handler = self.handler.clone();
+        let block = warp::path!("block" / String)
+            .and(warp::get())
+            .and_then(move |hash: String| {
+                let handler = block_handler.clone();
+                async move {
+                    Self::handle_block_request(handler, hash).await
+                }
+            });
+
+        // Merkle proof that a confirmed transaction is in its block, for
+        // `verify-payment` on the wallet side
+        let proof_handler = self.handler.clone();
+        let tx_proof = warp::path!("tx" / String / "proof")
+            .and(warp::get())
+            .and_then(move |txid: String| {
+                let handler = proof_handler.clone();
+                async move {
+                    Self::handle_tx_proof_request(handler, txid).await
+                }
+            });
+
         // Metrics endpoint
+        let metrics_handler = self.handler.clone();
+        let metrics_control = self.control.clone();
         let metrics = warp::path("metrics")
             .and(warp::get())
             .and_then(move || {
-                let handler = self.handler.clone();
+                let handler = metrics_handler.clone();
+                let control = metrics_control.clone();
+                async move {
+                    Self::handle_metrics_request(handler, control).await
+                }
+            });
+
+        // A chain or mempool metric over `?window=` seconds, downsampled for dashboards
+        let charts_handler = self.handler.clone();
+        let charts_series = self.charts.clone();
+        let charts = warp::path!("charts" / String)
+            .and(warp::get())
+            .and(warp::query::<HashMap<String, String>>())
+            .and_then(move |metric: String, query: HashMap<String, String>| {
+                let handler = charts_handler.clone();
+                let series = charts_series.clone();
                 async move {
-                    Self::handle_metrics_request(handler).await
+                    Self::handle_chart_request(handler, series, metric, query).await
                 }
             });
 
+        // Mempool events pushed to WebSocket subscribers, optionally only the
+        // kinds listed in `?events=`
+        let ws = warp::path("ws")
+            .and(warp::ws())
+            .and(warp::query::<HashMap<String, String>>())
+            .map(move |upgrade: warp::ws::Ws, query: HashMap<String, String>| {
+                let receiver = events.subscribe();
+                let kinds: Option<Vec<String>> = query.get("events")
+                    .map(|kinds| kinds.split(',').map(|kind| kind.trim().to_string()).collect());
+                upgrade.on_upgrade(move |socket| Self::stream_events(socket, receiver, kinds))
+            });
+
         // Combine all routes with CORS
         let cors = warp::cors()
             .allow_any_origin()
             .allow_headers(vec!["content-type"])
             .allow_methods(vec!["POST", "GET", "OPTIONS"]);
             
-        let routes = rpc.or(health).or(metrics).with(cors);
+        let routes = rpc.or(wallet_rpc).or(health).or(block).or(tx_proof).or(metrics).or(charts).or(ws).with(cors);
+
+        let shutdown = self.shutdown.clone();
+        let signal = move || {
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown.requested_shutdown().await;
+            }
+        };
 
         // Start the server
-        warp::serve(routes)
-            .run(self.config.bind_address)
-            .await;
+        #[cfg(unix)]
+        if let (Some(listener), Some(path)) = (listener, &self.config.unix_socket) {
+            warp::serve(routes)
+                .serve_incoming_with_graceful_shutdown(unix_socket::incoming(listener), signal())
+                .await;
+            let _ = std::fs::remove_file(path);
+            return Ok(self.shutdown.requested().unwrap_or(ShutdownRequest::Stop));
+        }
+
+        // The standard library sets SO_REUSEADDR on Unix listeners, so a port left
+        // in TIME_WAIT by a previous server is free at once; a port still held
+        // by a server that is shutting down is retried for a moment
+        let mut attempt = 1;
+        let server = loop {
+            match warp::serve(routes.clone()).try_bind_with_graceful_shutdown(self.config.bind_address, signal()) {
+                Ok((_, server)) => break server,
+                Err(e) if attempt < BIND_ATTEMPTS => {
+                    eprintln!("Warning: Failed to bind {}, retrying: {}", self.config.bind_address, e);
+                    tokio::time::sleep(BIND_RETRY_DELAY).await;
+                    attempt += 1;
+                },
+                Err(e) => return Err(format!("Failed to bind {}: {}", self.config.bind_address, e).into()),
+            }
+        };
+        server.await;
+
+        Ok(self.shutdown.requested().unwrap_or(ShutdownRequest::Stop))
+    }
+
+    /// Subscribe to the handler's mempool and republish its events to WebSocket
+    /// clients, raising double-spend alerts on the way when there is a data directory
+    async fn forward_mempool_events(&self) -> Result<broadcast::Sender<MempoolEvent>, String> {
+        let mut handler = self.handler.write().await;
+        let receiver = handler.mempool.lock().unwrap().subscribe();
+        let monitor = match &handler.data_dir {
+            Some(data_dir) => Some(AlertMonitor::new(AlertConfig::default(), AlertLog::open(data_dir)?)),
+            None => None,
+        };
+        let (sender, _) = broadcast::channel(WS_EVENT_BUFFER);
+        let publisher = sender.clone();
+        std::thread::spawn(move || {
+            for event in receiver {
+                if let Some(monitor) = &monitor {
+                    monitor.check_mempool_event(&event);
+                }
+                // No WebSocket client listening is not an error
+                let _ = publisher.send(event);
+            }
+        });
+        Ok(sender)
+    }
+
+    /// Start from the chart series saved in the data directory, if any
+    async fn load_charts(&self) {
+        let handler = self.handler.read().await;
+        let Some(data_dir) = &handler.data_dir else {
+            return;
+        };
+        match AnalyticsSeries::load_from_file(AnalyticsSeries::path_in(data_dir)) {
+            Ok(Some(series)) => *self.charts.lock().unwrap() = series,
+            Ok(None) => {},
+            Err(e) => eprintln!("Warning: Starting a new chart series: {}", e),
+        }
+    }
+
+    /// Every `CHART_SAMPLE_INTERVAL`, add the blocks connected since the last
+    /// sample and the mempool size to the chart series, saving it to the data
+    /// directory when there is one. Syncing can scan many blocks, so each sample
+    /// runs on the blocking pool, and the handler is released before the save.
+    async fn record_charts(handler: Arc<RwLock<BlockchainRpcHandler>>, series: Arc<Mutex<AnalyticsSeries>>) {
+        let mut interval = tokio::time::interval(CHART_SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let (handler, series) = (handler.clone(), series.clone());
+            let sample = tokio::task::spawn_blocking(move || {
+                let path = {
+                    let handler = handler.blocking_read();
+                    let mut series = series.lock().unwrap();
+                    if let Err(e) = series.sync(&handler.chain) {
+                        eprintln!("Warning: Failed to add blocks to the chart series: {}", e);
+                    }
+                    let stats = handler.mempool.lock().unwrap().get_stats();
+                    series.record_mempool(MempoolSample {
+                        time: Self::now(&handler),
+                        transactions: stats.pending_count,
+                        size_bytes: stats.total_size_bytes,
+                    });
+                    handler.data_dir.as_deref().map(AnalyticsSeries::path_in)
+                };
+                if let Some(path) = path
+                    && let Err(e) = series.lock().unwrap().save_to_file(path)
+                {
+                    eprintln!("Warning: {}", e);
+                }
+            });
+            if let Err(e) = sample.await {
+                eprintln!("Warning: Chart sample failed: {}", e);
+            }
+        }
+    }
 
-        Ok(())
+    /// Current time on the handler's clock
+    fn now(handler: &BlockchainRpcHandler) -> u64 {
+        handler.mock_clock.as_ref().map_or_else(|| SystemClock.now(), |clock| clock.now())
+    }
+
+    /// Send mempool events to one WebSocket client as JSON until it disconnects
+    async fn stream_events(
+        socket: WebSocket,
+        mut events: broadcast::Receiver<MempoolEvent>,
+        kinds: Option<Vec<String>>,
+    ) {
+        let (mut outgoing, mut incoming) = socket.split();
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        if kinds.as_ref().is_some_and(|kinds| !kinds.iter().any(|kind| kind == event.name())) {
+                            continue;
+                        }
+                        let Ok(json) = serde_json::to_string(&event) else {
+                            continue;
+                        };
+                        if outgoing.send(Message::text(json)).await.is_err() {
+                            return;
+                        }
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => {},
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                message = incoming.next() => match message {
+                    Some(Ok(message)) if !message.is_close() => {},
+                    _ => return,
+                },
+            }
+        }
+    }
+
+    /// Reload the config file whenever it changes, applying RPC limits, logging
+    /// and mempool settings to the running server
+    async fn watch_config(
+        mut watcher: ConfigWatcher,
+        handler: Arc<RwLock<BlockchainRpcHandler>>,
+        control: Arc<RequestControl>,
+    ) {
+        let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match watcher.poll() {
+                Some(Ok(reload)) => {
+                    control.apply_config(&reload.config);
+                    handler.write().await.mempool.lock().unwrap().apply_config(&reload.config);
+                    status!("Config reloaded from {}: {}", watcher.path().display(), reload.changes);
+                },
+                Some(Err(e)) => eprintln!("Config reload failed, keeping previous settings: {}", e),
+                None => {},
+            }
+        }
     }
 
     /// Handle a JSON-RPC request
     async fn handle_rpc_request(
         handler: Arc<RwLock<BlockchainRpcHandler>>,
+        control: Arc<RequestControl>,
         request: JsonRpcRequest,
+        remote: Option<SocketAddr>,
+        over_socket: bool,
+        wallet: Option<String>,
     ) -> Result<impl Reply, Infallible> {
+        let started = Instant::now();
+        let method = request.method.clone();
+
         // Validate JSON-RPC version
-        if request.jsonrpc != "2.0" {
-            let error_response = create_error_response(
+        let response = if request.jsonrpc != "2.0" {
+            create_error_response(
                 error_codes::INVALID_REQUEST,
                 "Invalid JSON-RPC version".to_string(),
                 request.id,
-            );
-            return Ok(warp::reply::json(&error_response));
-        }
-
-        // Handle the request
-        let response = {
-            let handler = handler.read().await;
-            handler.handle_request(request)
+            )
+        } else if is_privileged(&request.method) && !over_socket && !remote.is_some_and(|addr| addr.ip().is_loopback()) {
+            create_error_response(
+                error_codes::FORBIDDEN,
+                format!("Method '{}' is only available from localhost", request.method),
+                request.id,
+            )
+        } else {
+            control.execute(handler, request, wallet).await
         };
 
+        control.record(&method, remote, &response, started.elapsed());
         Ok(warp::reply::json(&response))
     }
 
+    /// Serve `GET /block/<hash>` as the block's JSON with its transactions
+    async fn handle_block_request(
+        handler: Arc<RwLock<BlockchainRpcHandler>>,
+        hash: String,
+    ) -> Result<impl Reply, Infallible> {
+        let handler = handler.read().await;
+        let (body, status) = match handler.chain.find_block(&hash) {
+            Ok(Some(block)) => (BlockchainRpcHandler::block_json(&block, 2), warp::http::StatusCode::OK),
+            Ok(None) => (serde_json::json!({ "error": "Block not found" }), warp::http::StatusCode::NOT_FOUND),
+            Err(e) => (serde_json::json!({ "error": e }), warp::http::StatusCode::INTERNAL_SERVER_ERROR),
+        };
+        Ok(warp::reply::with_status(warp::reply::json(&body), status))
+    }
+
+    async fn handle_tx_proof_request(
+        handler: Arc<RwLock<BlockchainRpcHandler>>,
+        txid: String,
+    ) -> Result<impl Reply, Infallible> {
+        let handler = handler.read().await;
+        let (body, status) = match handler.chain.transaction_proof(&txid) {
+            Ok(Some(proof)) => (serde_json::json!({
+                "txid": proof.txid,
+                "blockhash": proof.block_hash,
+                "height": proof.block_height,
+                "index": proof.index,
+                "branch": proof.branch,
+                "confirmations": handler.chain.height() - proof.block_height + 1,
+                "hex": proof.to_hex(),
+            }), warp::http::StatusCode::OK),
+            Ok(None) => (serde_json::json!({ "error": "Transaction not found in a block" }), warp::http::StatusCode::NOT_FOUND),
+            Err(e) => (serde_json::json!({ "error": e }), warp::http::StatusCode::INTERNAL_SERVER_ERROR),
+        };
+        Ok(warp::reply::with_status(warp::reply::json(&body), status))
+    }
+
+    async fn handle_chart_request(
+        handler: Arc<RwLock<BlockchainRpcHandler>>,
+        series: Arc<Mutex<AnalyticsSeries>>,
+        metric: String,
+        query: HashMap<String, String>,
+    ) -> Result<impl Reply, Infallible> {
+        let window = query.get("window")
+            .map(|window| window.parse::<u64>().ok().filter(|window| *window > 0)
+                .ok_or_else(|| format!("Invalid window '{}', expected a number of seconds", window)))
+            .transpose()
+            .map(|window| window.unwrap_or(DEFAULT_CHART_WINDOW));
+        let (body, status) = match (metric.parse::<ChartMetric>(), window) {
+            (Ok(metric), Ok(window)) => {
+                let now = Self::now(&*handler.read().await);
+                // The series is locked while a sample syncs it, so wait on the blocking pool
+                let chart = tokio::task::spawn_blocking(move || series.lock().unwrap().chart(metric, window, now)).await;
+                match chart {
+                    Ok(chart) => (serde_json::to_value(&chart).unwrap_or(Value::Null), warp::http::StatusCode::OK),
+                    Err(e) => (serde_json::json!({ "error": e.to_string() }), warp::http::StatusCode::INTERNAL_SERVER_ERROR),
+                }
+            },
+            (Err(e), _) => (serde_json::json!({ "error": e }), warp::http::StatusCode::NOT_FOUND),
+            (_, Err(e)) => (serde_json::json!({ "error": e }), warp::http::StatusCode::BAD_REQUEST),
+        };
+        Ok(warp::reply::with_status(warp::reply::json(&body), status))
+    }
+
     /// Handle a metrics request
     async fn handle_metrics_request(
         handler: Arc<RwLock<BlockchainRpcHandler>>,
+        control: Arc<RequestControl>,
     ) -> Result<impl Reply, Infallible> {
         let handler = handler.read().await;
         
         // Get simplified metrics
-        let block_count = handler.chain.blocks.len();
-        let mempool_stats = handler.mempool.get_stats();
+        let block_count = handler.chain.block_count();
+        let mempool_stats = handler.mempool.lock().unwrap().get_stats();
+        
+        // Traffic and resource use as last saved by a node sharing the data directory
+        let node = handler.data_dir.as_deref()
+            .and_then(|data_dir| NodeStats::load_from_file(NodeStats::path_in(data_dir)).ok().flatten())
+            .map_or(Value::Null, |stats| serde_json::json!({
+                "uptime": stats.uptime,
+                "connections": stats.connections,
+                "memory_usage": stats.memory_usage,
+                "cpu_usage": stats.cpu_usage,
+                "bandwidth_in": stats.totals.bytes_received,
+                "bandwidth_out": stats.totals.bytes_sent,
+                "messages_in": stats.totals.messages_received,
+                "messages_out": stats.totals.messages_sent,
+                "messages": stats.messages,
+            }));
         
         let metrics = serde_json::json!({
             "blockchain": {
                 "blocks": block_count,
-                "height": block_count.saturating_sub(1),
-                "total_difficulty": 4 * block_count,
-                "latest_block_hash": if let Some(block) = handler.chain.blocks.last() {
+                "height": handler.chain.height(),
+                "total_difficulty": handler.chain.chain_work().to_string(),
+                "latest_block_hash": if let Some(block) = handler.chain.tip() {
                     block.header.hash.clone()
                 } else {
                     "0".repeat(64)
@@ -157,7 +841,17 @@ impl RpcServer {
             "wallet": {
                 "address": handler.wallet.get_current_address().unwrap_or_else(|| "No address generated".to_string()),
                 "balance": 1000000 // Simplified
-            }
+            },
+            "rpc": {
+                "requests": control.stats.requests.load(Ordering::Relaxed),
+                "errors": control.stats.errors.load(Ordering::Relaxed),
+                "timeouts": control.stats.timeouts.load(Ordering::Relaxed),
+                "rejected": control.stats.rejected.load(Ordering::Relaxed),
+                "slow": control.stats.slow.load(Ordering::Relaxed),
+                "in_flight": control.in_flight(),
+                "max_in_flight": control.max_concurrent_requests()
+            },
+            "node": node
         });
 
         Ok(warp::reply::json(&metrics))
@@ -170,6 +864,7 @@ impl RpcServer {
             max_request_size: 1_048_576, // 1MB
             enable_cors: true,
             allowed_origins: vec!["*".to_string()], // In production, restrict this
+            ..Default::default()
         };
         
         // Use persistent blockchain and mempool
@@ -178,22 +873,17 @@ impl RpcServer {
         
         let mut mempool = Mempool::new_persistent(format!("{}/mempool.json", data_path));
         
-        // Create basic UTXO state from chain
-        let mut utxo_state = crate::blockchain::state::UTXOState::new();
-        
-        // Initialize UTXO state from blockchain
-        for block in &chain.blocks {
-            for transaction in &block.transactions {
-                utxo_state.update_balance(&transaction.from, -(transaction.amount as i64));
-                utxo_state.update_balance(&transaction.to, transaction.amount as i64);
-            }
-        }
+        // Replay the chain into a UTXO state
+        let utxo_state = chain.utxo_state()?;
         
         // Load mempool state if it exists
         let mempool_path = format!("{}/mempool.json", data_path);
         if let Err(e) = mempool.load_from_file(&mempool_path, &utxo_state) {
             eprintln!("Warning: Could not load mempool state: {}", e);
         }
+        if let Some(reconciliation) = mempool.reconcile_with_chain(&chain, &utxo_state)? {
+            status!("Mempool reconciled with chain at height {}: {}", chain.height(), reconciliation);
+        }
         
         // Use persistent wallet
         let wallet_path = format!("{}/wallet.json", data_path);
@@ -245,20 +935,36 @@ pub async fn handle_batch_request(
     responses
 }
 
-/// JSON-RPC client for testing and integration
+/// JSON-RPC client, used by the `rpc` subcommand to control a running node
 pub struct RpcClient {
     base_url: String,
+    /// Path requests are posted to, `rpc` or `wallet/<name>`
+    endpoint: String,
+    /// Socket of a `unix:<path>` base URL, used instead of HTTP over TCP
+    socket: Option<PathBuf>,
     client: reqwest::Client,
 }
 
 impl RpcClient {
     pub fn new(base_url: String) -> Self {
         RpcClient {
-            base_url,
+            socket: unix_socket::socket_path(&base_url),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            endpoint: "rpc".to_string(),
             client: reqwest::Client::new(),
         }
     }
 
+    /// Send wallet methods to the named wallet
+    pub fn with_wallet(mut self, name: &str) -> Self {
+        self.endpoint = format!("wallet/{}", name);
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
     pub async fn call(&self, method: &str, params: Option<Value>) -> Result<JsonRpcResponse, Box<dyn std::error::Error + Send + Sync>> {
         let request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
@@ -267,8 +973,13 @@ impl RpcClient {
             id: Some(Value::Number(1.into())),
         };
 
+        if let Some(socket) = &self.socket {
+            let body = unix_socket::post(socket, &self.endpoint, &serde_json::to_vec(&request)?).await?;
+            return Ok(serde_json::from_slice(&body)?);
+        }
+
         let response = self.client
-            .post(&format!("{}/rpc", self.base_url))
+            .post(format!("{}/{}", self.base_url, self.endpoint))
             .json(&request)
             .send()
             .await?;
@@ -312,11 +1023,64 @@ impl RpcClient {
     }
 }
 
+/// Split the arguments after an `rpc` or peer command into the server URL and
+/// the positional arguments. The URL is given as `--url=<url>` or `--url <url>`
+/// and is taken whole, spaces included; other flags are ignored.
+pub fn split_cli_args(args: &[String]) -> Result<(Option<String>, Vec<String>), String> {
+    let mut url = None;
+    let mut positional = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--url" {
+            url = Some(rest.next().ok_or("--url needs a value")?.clone());
+        } else if let Some(value) = arg.strip_prefix("--url=") {
+            url = Some(value.to_string());
+        } else if !arg.starts_with("--") {
+            positional.push(arg.clone());
+        }
+    }
+    Ok((url, positional))
+}
+
+/// Parameters of an `rpc` command line: each argument that parses as JSON is
+/// passed as that value, anything else as a string. None without arguments.
+pub fn parse_cli_params(args: &[String]) -> Option<Value> {
+    if args.is_empty() {
+        return None;
+    }
+    Some(Value::Array(args.iter()
+        .map(|arg| serde_json::from_str(arg).unwrap_or_else(|_| Value::String(arg.clone())))
+        .collect()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use tokio::time::{sleep, Duration};
 
+    #[test]
+    fn test_parse_cli_params() {
+        assert_eq!(parse_cli_params(&[]), None);
+        let args: Vec<String> = ["10", "true", "abc123", "[\"alice\"]", "{\"a\":1}"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_cli_params(&args), Some(serde_json::json!([10, true, "abc123", ["alice"], {"a": 1}])));
+        assert_eq!(RpcClient::new("http://127.0.0.1:8545/".to_string()).base_url(), DEFAULT_RPC_URL);
+    }
+
+    #[test]
+    fn test_split_cli_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+        assert_eq!(
+            split_cli_args(&args(&["getblock", "--url", "http://node a:8545", "abc"])).unwrap(),
+            (Some("http://node a:8545".to_string()), args(&["getblock", "abc"]))
+        );
+        assert_eq!(
+            split_cli_args(&args(&["getblockcount", "--url=http://10.0.0.5:8545"])).unwrap(),
+            (Some("http://10.0.0.5:8545".to_string()), args(&["getblockcount"]))
+        );
+        assert_eq!(split_cli_args(&args(&["getblockcount"])).unwrap(), (None, args(&["getblockcount"])));
+        assert!(split_cli_args(&args(&["getblockcount", "--url"])).is_err());
+    }
+
     #[tokio::test]
     async fn test_rpc_server_startup() {
         let server = RpcServer::simple(8546);
@@ -342,6 +1106,70 @@ mod tests {
         server_handle.abort();
     }
 
+    #[tokio::test]
+    async fn test_stop_restart_and_rebind() {
+        let config = RpcConfig {
+            bind_address: "127.0.0.1:8547".parse().unwrap(),
+            access_log: false,
+            ..Default::default()
+        };
+        let server = RpcServer::new(config.clone(), Chain::new(), Mempool::new(), Wallet::new());
+        let shutdown = server.shutdown_handle();
+        let server_handle = tokio::spawn(server.start());
+        sleep(Duration::from_millis(100)).await;
+        let client = RpcClient::new("http://127.0.0.1:8547".to_string());
+        assert_eq!(client.get_block_count().await.unwrap(), 0);
+
+        shutdown.restart();
+        sleep(Duration::from_millis(300)).await;
+        assert!(!server_handle.is_finished());
+        let client = RpcClient::new("http://127.0.0.1:8547".to_string());
+        assert_eq!(client.get_block_count().await.unwrap(), 0);
+
+        // `stop` is answered before the listener closes
+        let response = client.call("stop", None).await.unwrap();
+        assert_eq!(response.result, Some(Value::String("rust-chain RPC server stopping".to_string())));
+        tokio::time::timeout(Duration::from_secs(5), server_handle).await.unwrap().unwrap().unwrap();
+
+        // The port can be bound again straight away
+        let server = RpcServer::new(config, Chain::new(), Mempool::new(), Wallet::new());
+        let shutdown = server.shutdown_handle();
+        let server_handle = tokio::spawn(server.start());
+        sleep(Duration::from_millis(100)).await;
+        let client = RpcClient::new("http://127.0.0.1:8547".to_string());
+        assert_eq!(client.get_block_count().await.unwrap(), 0);
+        shutdown.stop();
+        tokio::time::timeout(Duration::from_secs(5), server_handle).await.unwrap().unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rpc_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("rust_chain_rpc_{}.sock", std::process::id()));
+        let config = RpcConfig {
+            unix_socket: Some(path.clone()),
+            access_log: false,
+            ..Default::default()
+        };
+        let server = RpcServer::new(config, Chain::new(), Mempool::new(), Wallet::new());
+        let server_handle = tokio::spawn(server.start());
+        sleep(Duration::from_millis(100)).await;
+
+        let client = RpcClient::new(format!("unix:{}", path.display()));
+        assert_eq!(client.get_block_count().await.unwrap(), 0);
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        // Privileged methods are open to the socket's owner
+        let response = client.call("setmempoollimits", None).await.unwrap();
+        assert_ne!(response.error.map(|error| error.code), Some(error_codes::FORBIDDEN));
+
+        server_handle.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[test]
     fn test_rpc_config_default() {
         let config = RpcConfig::default();
@@ -377,4 +1205,92 @@ mod tests {
         assert!(responses[0].result.is_some());
         assert!(responses[1].result.is_some());
     }
+
+    async fn chart_status(
+        handler: &Arc<RwLock<BlockchainRpcHandler>>,
+        series: &Arc<Mutex<AnalyticsSeries>>,
+        metric: &str,
+        window: Option<&str>,
+    ) -> warp::http::StatusCode {
+        let query = window.map(|window| HashMap::from([("window".to_string(), window.to_string())])).unwrap_or_default();
+        RpcServer::handle_chart_request(handler.clone(), series.clone(), metric.to_string(), query).await
+            .unwrap()
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_chart_requests() {
+        let handler = Arc::new(RwLock::new(BlockchainRpcHandler::new(Chain::new(), Mempool::new(), Wallet::new())));
+        let series = Arc::new(Mutex::new(AnalyticsSeries::new()));
+        assert_eq!(series.lock().unwrap().sync(&handler.read().await.chain).unwrap(), 1);
+
+        assert_eq!(chart_status(&handler, &series, "difficulty", None).await, warp::http::StatusCode::OK);
+        assert_eq!(chart_status(&handler, &series, "tx-rate", Some("3600")).await, warp::http::StatusCode::OK);
+        assert_eq!(chart_status(&handler, &series, "hashrate", None).await, warp::http::StatusCode::NOT_FOUND);
+        assert_eq!(chart_status(&handler, &series, "mempool-size", Some("0")).await, warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(chart_status(&handler, &series, "mempool-size", Some("1d")).await, warp::http::StatusCode::BAD_REQUEST);
+    }
+
+    fn block_count_request() -> JsonRpcRequest {
+        JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockcount".to_string(),
+            params: None,
+            id: Some(Value::Number(1.into())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_and_concurrency_limit() {
+        let handler = Arc::new(RwLock::new(BlockchainRpcHandler::new(Chain::new(), Mempool::new(), Wallet::new())));
+        let config = RpcConfig {
+            request_timeout: Duration::from_millis(50),
+            max_concurrent_requests: 1,
+            access_log: false,
+            ..Default::default()
+        };
+        let control = RequestControl::new(&config);
+
+        let response = control.execute(handler.clone(), block_count_request(), None).await;
+        assert!(response.result.is_some());
+
+        // Hold the handler so the next request cannot finish in time
+        let write_guard = handler.write().await;
+        let response = control.execute(handler.clone(), block_count_request(), None).await;
+        assert_eq!(response.error.unwrap().code, error_codes::REQUEST_TIMEOUT);
+
+        // The timed-out request is still running and occupies the only slot
+        assert_eq!(control.in_flight(), 1);
+        let response = control.execute(handler.clone(), block_count_request(), None).await;
+        assert_eq!(response.error.unwrap().code, error_codes::SERVER_BUSY);
+
+        drop(write_guard);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(control.in_flight(), 0);
+        assert_eq!(control.stats.timeouts.load(Ordering::Relaxed), 1);
+        assert_eq!(control.stats.rejected.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_config_changes_limits() {
+        let handler = Arc::new(RwLock::new(BlockchainRpcHandler::new(Chain::new(), Mempool::new(), Wallet::new())));
+        let config = RpcConfig {
+            max_concurrent_requests: 1,
+            access_log: false,
+            ..Default::default()
+        };
+        let control = RequestControl::new(&config);
+
+        // Keep the only slot busy
+        let _held = control.admit(1).unwrap();
+        let response = control.execute(handler.clone(), block_count_request(), None).await;
+        assert_eq!(response.error.unwrap().code, error_codes::SERVER_BUSY);
+
+        control.apply_config(&NodeConfig::parse(r#"{ "rpc_max_inflight": 2 }"#).unwrap());
+        assert_eq!(control.max_concurrent_requests(), 2);
+        let response = control.execute(handler.clone(), block_count_request(), None).await;
+        assert!(response.result.is_some());
+        assert_eq!(control.in_flight(), 1);
+    }
 }
@@ -0,0 +1,166 @@
+//! JSON-RPC over a Unix domain socket
+//!
+//! Local automation can reach the node without a TCP port: with
+//! `RpcConfig::unix_socket` set the server listens on that path instead of
+//! `bind_address`, and `RpcClient` posts to it when given a `unix:<path>` URL.
+//! The socket file is only accessible to its owner from the moment it appears,
+//! and connections from other users are refused, so requests arriving on it
+//! count as local and may call privileged methods.
+
+use std::path::{Path, PathBuf};
+
+/// Scheme prefix of RPC URLs naming a socket path, as in `unix:/run/node/rpc.sock`
+pub const UNIX_URL_PREFIX: &str = "unix:";
+
+/// The socket path named by an RPC URL, or None for an HTTP URL
+pub fn socket_path(url: &str) -> Option<PathBuf> {
+    url.strip_prefix(UNIX_URL_PREFIX)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// A listening socket and the user allowed to connect to it
+#[cfg(unix)]
+pub struct SocketListener {
+    listener: tokio::net::UnixListener,
+    owner: u32,
+}
+
+/// Listen on `path`, replacing a socket left behind by a previous run. The socket
+/// is created in a private directory, restricted to the current user and only
+/// then moved to `path`, so nobody else can connect while it is being set up.
+#[cfg(unix)]
+pub fn bind(path: &Path) -> Result<SocketListener, String> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        std::fs::remove_file(path)
+            .map_err(|e| format!("Failed to remove stale socket {}: {}", path.display(), e))?;
+    }
+
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let staging = parent.join(format!(".rpc-socket-{}", rand::random::<u64>()));
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)
+        .map_err(|e| format!("Failed to create {}: {}", staging.display(), e))?;
+    let staged = staging.join("rpc.sock");
+    let bound = tokio::net::UnixListener::bind(&staged)
+        .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Failed to restrict {}: {}", path.display(), e))?;
+            let owner = std::fs::metadata(&staged)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+                .uid();
+            std::fs::rename(&staged, path)
+                .map_err(|e| format!("Failed to move socket to {}: {}", path.display(), e))?;
+            Ok(SocketListener { listener, owner })
+        });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+    bound
+}
+
+#[cfg(not(unix))]
+pub fn bind(path: &Path) -> Result<(), String> {
+    Err(format!("Cannot listen on {}: Unix domain sockets are not supported on this platform", path.display()))
+}
+
+/// Connections accepted on `listener` from the user that owns it, for
+/// `warp::Server::run_incoming`. Connections from other users are closed.
+#[cfg(unix)]
+pub fn incoming(listener: SocketListener)
+    -> impl futures_util::Stream<Item = std::io::Result<tokio::net::UnixStream>> + Send {
+    futures_util::stream::unfold(listener, |listener| async move {
+        loop {
+            let stream = match listener.listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => return Some((Err(e), listener)),
+            };
+            match stream.peer_cred() {
+                Ok(credentials) if credentials.uid() == listener.owner => return Some((Ok(stream), listener)),
+                Ok(credentials) => eprintln!("Refusing RPC socket connection from uid {}", credentials.uid()),
+                Err(e) => eprintln!("Refusing RPC socket connection: {}", e),
+            }
+        }
+    })
+}
+
+/// POST a JSON body to `endpoint` on the server listening at `path` and return
+/// the response body
+#[cfg(unix)]
+pub async fn post(path: &Path, endpoint: &str, body: &[u8]) -> Result<Vec<u8>, String> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::UnixStream::connect(path).await
+        .map_err(|e| format!("Failed to connect to {}: {}", path.display(), e))?;
+    let head = format!(
+        "POST /{} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        endpoint, body.len()
+    );
+    let send_error = |e: std::io::Error| format!("Failed to send request: {}", e);
+    stream.write_all(head.as_bytes()).await.map_err(send_error)?;
+    stream.write_all(body).await.map_err(send_error)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+    response_body(&response)
+}
+
+#[cfg(not(unix))]
+pub async fn post(path: &Path, _endpoint: &str, _body: &[u8]) -> Result<Vec<u8>, String> {
+    Err(format!("Cannot connect to {}: Unix domain sockets are not supported on this platform", path.display()))
+}
+
+/// The body of a complete HTTP/1.1 response, decoding chunked transfer encoding
+fn response_body(response: &[u8]) -> Result<Vec<u8>, String> {
+    let split = response.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or("Incomplete HTTP response")?;
+    let head = String::from_utf8_lossy(&response[..split]).to_ascii_lowercase();
+    let body = &response[split + 4..];
+    let chunked = head.lines()
+        .any(|line| line.starts_with("transfer-encoding:") && line.contains("chunked"));
+    if !chunked {
+        return Ok(body.to_vec());
+    }
+
+    let mut decoded = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest.windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or("Truncated chunked response")?;
+        let size_line = String::from_utf8_lossy(&rest[..line_end]);
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| format!("Invalid chunk size '{}'", size_line))?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk = rest.get(..size).ok_or("Truncated chunked response")?;
+        decoded.extend_from_slice(chunk);
+        rest = rest.get(size + 2..).ok_or("Truncated chunked response")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_urls_and_response_bodies() {
+        assert_eq!(socket_path("unix:/tmp/node.sock"), Some(PathBuf::from("/tmp/node.sock")));
+        assert_eq!(socket_path("unix:"), None);
+        assert_eq!(socket_path("http://127.0.0.1:8545"), None);
+
+        let plain = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}";
+        assert_eq!(response_body(plain).unwrap(), b"{}");
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{\"a\r\n4\r\n\":1}\r\n0\r\n\r\n";
+        assert_eq!(response_body(chunked).unwrap(), b"{\"a\":1}");
+        assert!(response_body(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+}
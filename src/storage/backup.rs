@@ -0,0 +1,294 @@
+//! Full node backups
+//!
+//! A backup is a directory holding a copy of the data directory in `data/`,
+//! files kept outside it (such as `wallet.json`) in `external/`, and a manifest
+//! with the chain tip, the schema version and the size and SHA-256 checksum of
+//! every file. The databases are copied as RocksDB checkpoints, so the copy is
+//! consistent while the node keeps running. A restore checks the whole backup
+//! against its manifest before anything in the data directory is touched.
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::progress::Progress;
+use crate::storage::lock::LOCK_FILE;
+use crate::storage::schema::SCHEMA_VERSION;
+
+/// Manifest file at the top of a backup
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Directory in a backup holding the copy of the data directory
+pub const DATA_DIR: &str = "data";
+
+/// Directory in a backup holding files that live outside the data directory
+pub const EXTERNAL_DIR: &str = "external";
+
+/// Layout version of backups written by this build
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// One file in a backup
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackupFile {
+    /// Path relative to the backup directory, with `/` separators
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// A file kept outside the data directory and where it is restored to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExternalFile {
+    /// Name in the backup's `external` directory
+    pub name: String,
+    pub restore_path: String,
+}
+
+/// Description of a backup, checked before it is restored
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    /// Storage schema version of the build that wrote the backup
+    pub schema_version: u8,
+    /// Unix time the backup was taken
+    pub created_at: u64,
+    pub height: u64,
+    pub tip_hash: String,
+    pub external_files: Vec<ExternalFile>,
+    pub files: Vec<BackupFile>,
+}
+
+impl BackupManifest {
+    /// Checksum every file in `backup_dir` and write the manifest next to them
+    pub fn create<P: AsRef<Path>>(backup_dir: P, height: u64, tip_hash: &str, external_files: Vec<ExternalFile>) -> Result<Self, String> {
+        let backup_dir = backup_dir.as_ref();
+        let mut files = Vec::new();
+        let paths = list_files(backup_dir)?;
+        let mut progress = Progress::new("Checksumming backup", paths.len() as u64).with_unit("files");
+        for path in paths {
+            let (size, sha256) = file_checksum(&backup_dir.join(&path))?;
+            files.push(BackupFile { path, size, sha256 });
+            progress.inc(1);
+        }
+        progress.finish();
+
+        let manifest = BackupManifest {
+            format_version: BACKUP_FORMAT_VERSION,
+            schema_version: SCHEMA_VERSION,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            height,
+            tip_hash: tip_hash.to_string(),
+            external_files,
+            files,
+        };
+        let data = serde_json::to_string_pretty(&manifest)
+            .map_err(|e| format!("Failed to serialize backup manifest: {}", e))?;
+        fs::write(backup_dir.join(MANIFEST_FILE), data)
+            .map_err(|e| format!("Failed to write backup manifest: {}", e))?;
+        Ok(manifest)
+    }
+
+    /// Read the manifest of a backup
+    pub fn load<P: AsRef<Path>>(backup_dir: P) -> Result<Self, String> {
+        let path = backup_dir.as_ref().join(MANIFEST_FILE);
+        let data = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read backup manifest {}: {}", path.display(), e))?;
+        serde_json::from_str(&data)
+            .map_err(|e| format!("Failed to parse backup manifest: {}", e))
+    }
+
+    /// Check that this build can restore the backup, that every path it lists
+    /// stays where it is joined to, and that every file is present with the size
+    /// and checksum the manifest lists
+    pub fn verify<P: AsRef<Path>>(&self, backup_dir: P) -> Result<(), String> {
+        if self.format_version != BACKUP_FORMAT_VERSION {
+            return Err(format!("Unsupported backup format version {} (expected {})", self.format_version, BACKUP_FORMAT_VERSION));
+        }
+        // Older records are migrated when read, newer ones cannot be read at all
+        if self.schema_version > SCHEMA_VERSION {
+            return Err(format!(
+                "Backup uses storage schema version {}, newer than the {} this build supports",
+                self.schema_version, SCHEMA_VERSION
+            ));
+        }
+
+        for file in &self.files {
+            check_relative(&file.path)?;
+        }
+        for external in &self.external_files {
+            if !is_file_name(&external.name) {
+                return Err(format!("Backup external file name {} is not a plain file name", external.name));
+            }
+            check_relative(&external.restore_path)?;
+        }
+
+        let backup_dir = backup_dir.as_ref();
+        let mut progress = Progress::new("Verifying backup", self.files.len() as u64).with_unit("files");
+        for file in &self.files {
+            let (size, sha256) = file_checksum(&backup_dir.join(&file.path))
+                .map_err(|e| format!("Backup file {} is unreadable: {}", file.path, e))?;
+            if size != file.size || sha256 != file.sha256 {
+                return Err(format!("Backup file {} does not match its checksum", file.path));
+            }
+            progress.inc(1);
+        }
+        progress.finish();
+        for external in &self.external_files {
+            let path = format!("{}/{}", EXTERNAL_DIR, external.name);
+            if !self.files.iter().any(|file| file.path == path) {
+                return Err(format!("Backup manifest does not list {}", path));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copy `paths` into a backup: files inside `data_dir` keep their place under
+/// `data/`, others go to `external/`. Missing paths are skipped.
+pub fn copy_files<P: AsRef<Path>, Q: AsRef<Path>>(backup_dir: P, data_dir: Q, paths: &[PathBuf]) -> Result<Vec<ExternalFile>, String> {
+    let backup_dir = backup_dir.as_ref();
+    let mut external_files = Vec::new();
+    for path in paths {
+        if !path.exists() {
+            continue;
+        }
+        let target = match path.strip_prefix(data_dir.as_ref()) {
+            Ok(relative) => backup_dir.join(DATA_DIR).join(relative),
+            Err(_) => {
+                let name = path.file_name()
+                    .ok_or_else(|| format!("Cannot back up {}", path.display()))?
+                    .to_string_lossy()
+                    .into_owned();
+                // Refused now rather than when the backup is restored
+                let restore_path = path.to_string_lossy().into_owned();
+                check_relative(&restore_path)?;
+                external_files.push(ExternalFile { name: name.clone(), restore_path });
+                backup_dir.join(EXTERNAL_DIR).join(name)
+            },
+        };
+        copy_recursive(path, &target)?;
+    }
+    Ok(external_files)
+}
+
+/// Replace the contents of `data_dir` and the external files with a verified
+/// backup. What they held before is moved to `previous_dir` rather than deleted.
+/// The data directory lock file is left in place.
+pub fn restore<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(backup_dir: P, data_dir: Q, previous_dir: R) -> Result<BackupManifest, String> {
+    let (backup_dir, data_dir, previous_dir) = (backup_dir.as_ref(), data_dir.as_ref(), previous_dir.as_ref());
+    let manifest = BackupManifest::load(backup_dir)?;
+    manifest.verify(backup_dir)?;
+
+    fs::create_dir_all(previous_dir.join(EXTERNAL_DIR))
+        .map_err(|e| format!("Failed to create directory: {}", e))?;
+    fs::create_dir_all(data_dir)
+        .map_err(|e| format!("Failed to create directory: {}", e))?;
+    for entry in read_dir(data_dir)? {
+        if entry.file_name() != LOCK_FILE {
+            move_path(&entry.path(), &previous_dir.join(entry.file_name()))?;
+        }
+    }
+    for external in &manifest.external_files {
+        let current = Path::new(&external.restore_path);
+        if current.exists() {
+            move_path(current, &previous_dir.join(EXTERNAL_DIR).join(&external.name))?;
+        }
+    }
+
+    for entry in read_dir(&backup_dir.join(DATA_DIR))? {
+        copy_recursive(&entry.path(), &data_dir.join(entry.file_name()))?;
+    }
+    for external in &manifest.external_files {
+        copy_recursive(&backup_dir.join(EXTERNAL_DIR).join(&external.name), Path::new(&external.restore_path))?;
+    }
+    Ok(manifest)
+}
+
+/// Refuse a manifest path that could leave the directory it is joined to:
+/// absolute paths and ones with `..` components
+fn check_relative(path: &str) -> Result<(), String> {
+    let contained = !path.is_empty()
+        && Path::new(path).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !contained {
+        return Err(format!("Backup path {} is absolute or leaves its directory", path));
+    }
+    Ok(())
+}
+
+/// Whether `name` is a single file name, with no directories
+fn is_file_name(name: &str) -> bool {
+    matches!(Path::new(name).components().collect::<Vec<_>>()[..], [Component::Normal(_)])
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<fs::DirEntry>, String> {
+    fs::read_dir(dir)
+        .and_then(|entries| entries.collect())
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))
+}
+
+fn move_path(from: &Path, to: &Path) -> Result<(), String> {
+    fs::rename(from, to)
+        .map_err(|e| format!("Failed to move {} to {}: {}", from.display(), to.display(), e))
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> Result<(), String> {
+    if from.is_dir() {
+        fs::create_dir_all(to)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+        for entry in read_dir(from)? {
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+    }
+    fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to copy {} to {}: {}", from.display(), to.display(), e))
+}
+
+/// Files under `dir` except the manifest, relative and sorted
+fn list_files(dir: &Path) -> Result<Vec<String>, String> {
+    fn walk(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<(), String> {
+        for entry in read_dir(dir)? {
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if entry.path().is_dir() {
+                walk(&entry.path(), &format!("{}/", name), files)?;
+            } else if name != MANIFEST_FILE {
+                files.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(dir, "", &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Size and hex SHA-256 of a file
+fn file_checksum(path: &Path) -> Result<(u64, String), String> {
+    let mut file = File::open(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => "file is missing".to_string(),
+        _ => e.to_string(),
+    })?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
@@ -1,9 +1,38 @@
-use crate::blockchain::block::Block;
+use crate::blockchain::block::{Block, BlockHeader};
+use crate::storage::cache::{LruCache, CacheStats, DEFAULT_BLOCK_CACHE_BYTES, DEFAULT_HEADER_CACHE_BYTES};
 use crate::storage::db::Database;
+use crate::storage::schema::{self, RecordKind};
+use crate::storage::undo::{BlockUndo, apply_change, balance_changes};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
 
-/// Block storage interface using RocksDB
+/// Key recording a stored block whose transaction indexes are not yet written
+const INDEX_JOURNAL_KEY: &str = "journal:pending_index";
+
+/// Key of the chain metadata record
+const CHAIN_METADATA_KEY: &str = "meta:chain";
+
+/// Key marking a store whose chainstate and undo records cover every block
+const CHAINSTATE_KEY: &str = "meta:chainstate";
+
+/// Summary of the stored chain, written together with every block so a node
+/// can start from the tip without reading the whole chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChainMetadata {
+    pub tip_hash: String,
+    pub height: u64,
+    /// Sum of `Block::work` over every block up to the tip
+    pub cumulative_work: u128,
+    /// Blocks below this height have been pruned (None while the full history is kept)
+    pub pruned_height: Option<u64>,
+}
+
+/// Block storage interface using RocksDB, with LRU caches for hot blocks and headers
 pub struct BlockStore {
     db: Database,
+    block_cache: Mutex<LruCache<Block>>,
+    header_cache: Mutex<LruCache<BlockHeader>>,
 }
 
 impl BlockStore {
@@ -12,7 +41,7 @@ impl BlockStore {
         let db = Database::new()
             .map_err(|e| format!("Failed to create database: {}", e))?;
         
-        Ok(BlockStore { db })
+        Self::from_database(db)
     }
     
     /// Create a new BlockStore with custom database path
@@ -20,38 +49,447 @@ impl BlockStore {
         let db = Database::new_with_path(path)
             .map_err(|e| format!("Failed to create database at {}: {}", path, e))?;
         
-        Ok(BlockStore { db })
+        Self::from_database(db)
+    }
+    
+    fn from_database(db: Database) -> Result<Self, String> {
+        let store = BlockStore {
+            db,
+            block_cache: Mutex::new(LruCache::new(DEFAULT_BLOCK_CACHE_BYTES)),
+            header_cache: Mutex::new(LruCache::new(DEFAULT_HEADER_CACHE_BYTES)),
+        };
+        // Marks a new store as current; an older store keeps its version until migrated
+        store.schema_version()?;
+        Ok(store)
+    }
+    
+    /// Oldest schema version of the records in this store
+    pub fn schema_version(&self) -> Result<u8, String> {
+        schema::store_version(&self.db, self.get_latest_height()?.is_none())
+    }
+    
+    /// Rewrite records older than the current schema version. Returns the
+    /// number of records rewritten.
+    pub fn migrate_schema(&self) -> Result<usize, String> {
+        schema::migrate_database(&self.db, RecordKind::BLOCK_STORE)
+    }
+    
+    /// Write a consistent snapshot of the store to `path`, which must not exist
+    pub fn checkpoint(&self, path: &str) -> Result<(), String> {
+        self.db.checkpoint(path)
+            .map_err(|e| format!("Failed to back up block store to {}: {}", path, e))
+    }
+    
+    /// Set the byte budgets of the block and header caches
+    pub fn with_cache_capacity(self, block_bytes: usize, header_bytes: usize) -> Self {
+        self.set_cache_capacity(block_bytes, header_bytes);
+        self
+    }
+    
+    /// Resize the block and header caches, evicting entries if they shrink
+    pub fn set_cache_capacity(&self, block_bytes: usize, header_bytes: usize) {
+        self.block_cache.lock().unwrap().set_capacity(block_bytes);
+        self.header_cache.lock().unwrap().set_capacity(header_bytes);
+    }
+    
+    /// Hit/miss counters and usage of the block and header caches
+    pub fn cache_stats(&self) -> (CacheStats, CacheStats) {
+        (self.block_cache.lock().unwrap().stats(), self.header_cache.lock().unwrap().stats())
+    }
+    
+    /// Remember a block (and its header) we just read or wrote
+    fn cache_block(&self, block: &Block, size_bytes: usize) {
+        self.block_cache.lock().unwrap().insert(block.header.hash.clone(), block.clone(), size_bytes);
+        self.cache_header(&block.header);
+    }
+    
+    fn cache_header(&self, header: &BlockHeader) {
+        let size_bytes = serde_json::to_vec(header).map(|data| data.len()).unwrap_or(0);
+        self.header_cache.lock().unwrap().insert(header.hash.clone(), header.clone(), size_bytes);
     }
     
-    /// Store a block by its hash
+    /// Store a block by its hash, together with its height mapping, in one atomic write
     pub fn store_block(&self, block: &Block) -> Result<(), String> {
-        let block_data = serde_json::to_vec(block)
-            .map_err(|e| format!("Failed to serialize block: {}", e))?;
+        let operations = self.block_operations(block)?;
+        let block_size = operations[0].1.len();
         
-        let key = format!("block:{}", block.header.hash);
-        self.db.put(key, block_data)
+        self.db.batch_put(operations)
             .map_err(|e| format!("Failed to store block: {}", e))?;
+        self.cache_block(block, block_size);
         
-        // Also store height mapping
-        let height_key = format!("height:{}", block.header.height);
-        self.db.put(height_key, block.header.hash.as_bytes().to_vec())
-            .map_err(|e| format!("Failed to store height mapping: {}", e))?;
+        Ok(())
+    }
+    
+    /// Store a block and record in the same atomic write that its transaction
+    /// indexes still have to be written. The journal entry is cleared with
+    /// `clear_index_journal` once the indexes are durable.
+    pub fn store_block_journaled(&self, block: &Block) -> Result<(), String> {
+        let mut operations = self.block_operations(block)?;
+        let block_size = operations[0].1.len();
+        operations.push((INDEX_JOURNAL_KEY.to_string(), block.header.hash.as_bytes().to_vec()));
         
-        // Store latest block height
-        let latest_key = "latest_height".to_string();
-        self.db.put(latest_key, block.header.height.to_be_bytes().to_vec())
-            .map_err(|e| format!("Failed to store latest height: {}", e))?;
+        self.db.batch_put(operations)
+            .map_err(|e| format!("Failed to store block: {}", e))?;
+        self.cache_block(block, block_size);
         
         Ok(())
     }
     
-    /// Retrieve a block by its hash
+    /// Hash of a block whose index write may not have completed
+    pub fn pending_index_journal(&self) -> Result<Option<String>, String> {
+        match self.db.get(INDEX_JOURNAL_KEY) {
+            Ok(Some(hash_bytes)) => {
+                let hash = String::from_utf8(hash_bytes)
+                    .map_err(|e| format!("Invalid journal entry: {}", e))?;
+                Ok(Some(hash))
+            },
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("Database error: {}", e)),
+        }
+    }
+    
+    /// Mark the journaled block's indexes as written
+    pub fn clear_index_journal(&self) -> Result<(), String> {
+        self.db.delete(INDEX_JOURNAL_KEY)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to clear index journal: {}", e))
+    }
+    
+    /// Block record, header index, height mapping, latest height and chain metadata
+    /// for one block. The serialized block is always the first operation.
+    fn block_operations(&self, block: &Block) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let block_data = schema::encode(block)
+            .map_err(|e| format!("Failed to serialize block: {}", e))?;
+        let metadata = ChainMetadata {
+            tip_hash: block.header.hash.clone(),
+            height: block.header.height,
+            cumulative_work: self.work_before(block)?.saturating_add(block.work()),
+            pruned_height: self.get_chain_metadata()?.and_then(|metadata| metadata.pruned_height),
+        };
+        
+        let mut operations = vec![
+            (format!("block:{}", block.header.hash), block_data),
+            Self::header_operation(&block.header)?,
+            (format!("height:{}", block.header.height), block.header.hash.as_bytes().to_vec()),
+            ("latest_height".to_string(), block.header.height.to_be_bytes().to_vec()),
+            Self::metadata_operation(&metadata)?,
+        ];
+        operations.extend(self.chainstate_operations(block, &mut HashMap::new())?);
+        Ok(operations)
+    }
+    
+    /// Balance updates and the undo record for connecting `block`. `pending` holds
+    /// balances written earlier in the same batch. A block already stored at its
+    /// height was connected before and changes nothing.
+    fn chainstate_operations(&self, block: &Block, pending: &mut HashMap<String, u64>) -> Result<Vec<(String, Vec<u8>)>, String> {
+        if self.get_hash_by_height(block.header.height)?.as_deref() == Some(block.header.hash.as_str()) {
+            return Ok(Vec::new());
+        }
+        
+        let undo = Self::connect_balances(block, pending, |address| self.get_balance(address))?;
+        let mut operations: Vec<(String, Vec<u8>)> = undo.prior_balances.iter()
+            .map(|(address, _)| (format!("balance:{}", address), pending[address].to_be_bytes().to_vec()))
+            .collect();
+        let undo_data = schema::encode(&undo)
+            .map_err(|e| format!("Failed to serialize undo record: {}", e))?;
+        operations.push((format!("undo:{}", block.header.hash), undo_data));
+        if block.header.height == 0 {
+            operations.push((CHAINSTATE_KEY.to_string(), vec![1]));
+        }
+        Ok(operations)
+    }
+    
+    /// Apply a block's balance changes to `balances`, reading addresses not in it
+    /// with `stored`, and record the balances it overwrote
+    fn connect_balances<F>(block: &Block, balances: &mut HashMap<String, u64>, stored: F) -> Result<BlockUndo, String>
+    where
+        F: Fn(&str) -> Result<u64, String>,
+    {
+        let mut undo = BlockUndo {
+            block_hash: block.header.hash.clone(),
+            height: block.header.height,
+            prior_balances: Vec::new(),
+        };
+        for (address, change) in balance_changes(block) {
+            let balance = match balances.get(&address) {
+                Some(balance) => *balance,
+                None => stored(&address)?,
+            };
+            if !undo.prior_balances.iter().any(|(touched, _)| *touched == address) {
+                undo.prior_balances.push((address.clone(), balance));
+            }
+            let updated = apply_change(balance, change)
+                .map_err(|e| format!("Block {} balance of {}: {}", block.header.hash, address, e))?;
+            balances.insert(address, updated);
+        }
+        Ok(undo)
+    }
+    
+    /// Balance of an address after the stored tip, coinbase maturity ignored
+    pub fn get_balance(&self, address: &str) -> Result<u64, String> {
+        match self.db.get(&format!("balance:{}", address)) {
+            Ok(Some(data)) => {
+                let bytes: [u8; 8] = data.try_into()
+                    .map_err(|_| format!("Invalid balance data for {}", address))?;
+                Ok(u64::from_be_bytes(bytes))
+            },
+            Ok(None) => Ok(0),
+            Err(e) => Err(format!("Database error: {}", e)),
+        }
+    }
+    
+    /// Every balance in the chainstate, by address
+    pub fn get_all_balances(&self) -> Result<HashMap<String, u64>, String> {
+        let entries = self.db.entries_with_prefix("balance:")
+            .map_err(|e| format!("Database error: {}", e))?;
+        entries.into_iter()
+            .map(|(key, data)| {
+                let address = key["balance:".len()..].to_string();
+                let bytes: [u8; 8] = data.try_into()
+                    .map_err(|_| format!("Invalid balance data for {}", address))?;
+                Ok((address, u64::from_be_bytes(bytes)))
+            })
+            .collect()
+    }
+    
+    /// Undo record of a connected block
+    pub fn get_undo(&self, hash: &str) -> Result<Option<BlockUndo>, String> {
+        match self.db.get(&format!("undo:{}", hash)) {
+            Ok(Some(data)) => schema::decode(RecordKind::Undo, &data)
+                .map(Some)
+                .map_err(|e| format!("Invalid undo record: {}", e)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("Database error: {}", e)),
+        }
+    }
+    
+    /// Whether the chainstate and undo records cover every stored block
+    pub fn has_chainstate(&self) -> Result<bool, String> {
+        self.db.exists(CHAINSTATE_KEY)
+            .map_err(|e| format!("Database error: {}", e))
+    }
+    
+    /// Rebuild the chainstate and undo records from the stored blocks, for stores
+    /// written before they were kept. Returns the number of blocks replayed.
+    pub fn rebuild_chainstate(&self) -> Result<u64, String> {
+        let latest_height = match self.get_latest_height()? {
+            Some(height) => height,
+            None => return Ok(0),
+        };
+        
+        let mut balances: HashMap<String, u64> = HashMap::new();
+        let mut operations = Vec::new();
+        for block in self.iter_blocks(0, latest_height) {
+            let block = block?;
+            let undo = Self::connect_balances(&block, &mut balances, |_| Ok(0))?;
+            let undo_data = schema::encode(&undo)
+                .map_err(|e| format!("Failed to serialize undo record: {}", e))?;
+            operations.push((format!("undo:{}", block.header.hash), undo_data));
+        }
+        
+        operations.extend(balances.into_iter()
+            .map(|(address, balance)| (format!("balance:{}", address), balance.to_be_bytes().to_vec())));
+        operations.push((CHAINSTATE_KEY.to_string(), vec![1]));
+        self.db.batch_put(operations)
+            .map_err(|e| format!("Failed to store chainstate: {}", e))?;
+        Ok(latest_height + 1)
+    }
+    
+    /// Record that a block must not be connected again. `root` is the block that
+    /// was invalidated; descendants disconnected with it carry its hash.
+    pub fn mark_invalid(&self, hash: &str, root: &str) -> Result<(), String> {
+        self.db.put(format!("invalid:{}", hash), root.as_bytes().to_vec())
+            .map_err(|e| format!("Failed to mark block invalid: {}", e))
+    }
+    
+    /// Allow a block marked invalid to be connected again
+    pub fn clear_invalid(&self, hash: &str) -> Result<(), String> {
+        self.db.delete(&format!("invalid:{}", hash))
+            .map(|_| ())
+            .map_err(|e| format!("Failed to clear invalid block: {}", e))
+    }
+    
+    /// Every block marked invalid, with the invalidated block it descends from
+    pub fn invalid_blocks(&self) -> Result<Vec<(String, String)>, String> {
+        let keys = self.db.keys_with_prefix("invalid:")
+            .map_err(|e| format!("Database error: {}", e))?;
+        
+        let mut blocks = Vec::new();
+        for key in keys {
+            let Some(hash) = key.strip_prefix("invalid:") else {
+                continue;
+            };
+            let root = match self.db.get(&key) {
+                Ok(Some(root)) => String::from_utf8(root)
+                    .map_err(|e| format!("Invalid block marker for {}: {}", hash, e))?,
+                Ok(None) => continue,
+                Err(e) => return Err(format!("Database error: {}", e)),
+            };
+            blocks.push((hash.to_string(), root));
+        }
+        Ok(blocks)
+    }
+    
+    /// Record that a block broke a rule independent of the chain, and which
+    pub fn mark_rejected(&self, hash: &str, reason: &str) -> Result<(), String> {
+        self.db.put(format!("rejected:{}", hash), reason.as_bytes().to_vec())
+            .map_err(|e| format!("Failed to record rejected block: {}", e))
+    }
+    
+    /// Every block recorded with `mark_rejected`, with the rule it broke
+    pub fn rejected_blocks(&self) -> Result<Vec<(String, String)>, String> {
+        let keys = self.db.keys_with_prefix("rejected:")
+            .map_err(|e| format!("Database error: {}", e))?;
+        
+        let mut blocks = Vec::new();
+        for key in keys {
+            let Some(hash) = key.strip_prefix("rejected:") else {
+                continue;
+            };
+            let reason = match self.db.get(&key) {
+                Ok(Some(reason)) => String::from_utf8(reason)
+                    .map_err(|e| format!("Invalid rejection reason for {}: {}", hash, e))?,
+                Ok(None) => continue,
+                Err(e) => return Err(format!("Database error: {}", e)),
+            };
+            blocks.push((hash.to_string(), reason));
+        }
+        Ok(blocks)
+    }
+    
+    /// Disconnect the stored tip: restore the balances it overwrote and move the
+    /// tip, height and work back to its parent in one atomic write. The block
+    /// record itself is kept. Returns the undo record that was applied.
+    pub fn disconnect_block(&self, block: &Block) -> Result<BlockUndo, String> {
+        let metadata = self.get_chain_metadata()?
+            .ok_or_else(|| "No chain is stored".to_string())?;
+        if metadata.tip_hash != block.header.hash {
+            return Err(format!("Block {} is not the stored tip", block.header.hash));
+        }
+        if block.header.height == 0 {
+            return Err("The genesis block cannot be disconnected".to_string());
+        }
+        let undo = self.get_undo(&block.header.hash)?
+            .ok_or_else(|| format!("No undo data for block {}", block.header.hash))?;
+        
+        let parent_height = block.header.height - 1;
+        let mut operations: Vec<(String, Vec<u8>)> = undo.prior_balances.iter()
+            .map(|(address, balance)| (format!("balance:{}", address), balance.to_be_bytes().to_vec()))
+            .collect();
+        operations.push(("latest_height".to_string(), parent_height.to_be_bytes().to_vec()));
+        operations.push(Self::metadata_operation(&ChainMetadata {
+            tip_hash: block.header.previous_hash.clone(),
+            height: parent_height,
+            cumulative_work: metadata.cumulative_work.saturating_sub(block.work()),
+            pruned_height: metadata.pruned_height,
+        })?);
+        let deletes = vec![
+            format!("height:{}", block.header.height),
+            format!("undo:{}", block.header.hash),
+        ];
+        
+        self.db.batch_write(operations, deletes)
+            .map_err(|e| format!("Failed to disconnect block: {}", e))?;
+        Ok(undo)
+    }
+    
+    fn header_operation(header: &BlockHeader) -> Result<(String, Vec<u8>), String> {
+        let data = schema::encode(header)
+            .map_err(|e| format!("Failed to serialize block header: {}", e))?;
+        Ok((format!("header:{}", header.hash), data))
+    }
+    
+    fn metadata_operation(metadata: &ChainMetadata) -> Result<(String, Vec<u8>), String> {
+        let data = schema::encode(metadata)
+            .map_err(|e| format!("Failed to serialize chain metadata: {}", e))?;
+        Ok((CHAIN_METADATA_KEY.to_string(), data))
+    }
+    
+    /// Cumulative work of the blocks below `block`. Extending the recorded tip is
+    /// O(1); replacing blocks falls back to adding up the stored blocks, skipping
+    /// heights that were pruned or never stored.
+    fn work_before(&self, block: &Block) -> Result<u128, String> {
+        if block.header.height == 0 {
+            return Ok(0);
+        }
+        if let Some(metadata) = self.get_chain_metadata()?
+            && metadata.tip_hash == block.header.previous_hash {
+            return Ok(metadata.cumulative_work);
+        }
+        let mut work = 0u128;
+        for height in 0..block.header.height {
+            if let Some(stored) = self.get_block_by_height(height)? {
+                work = work.saturating_add(stored.work());
+            }
+        }
+        Ok(work)
+    }
+    
+    /// Tip, height and cumulative work of the stored chain
+    pub fn get_chain_metadata(&self) -> Result<Option<ChainMetadata>, String> {
+        match self.db.get(CHAIN_METADATA_KEY) {
+            Ok(Some(data)) => schema::decode(RecordKind::ChainMetadata, &data)
+                .map(Some)
+                .map_err(|e| format!("Invalid chain metadata: {}", e)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("Database error: {}", e)),
+        }
+    }
+    
+    /// Recompute the chain metadata from the stored blocks, for stores written
+    /// before metadata was kept. Returns None for an empty store.
+    pub fn rebuild_chain_metadata(&self) -> Result<Option<ChainMetadata>, String> {
+        let latest_height = match self.get_latest_height()? {
+            Some(height) => height,
+            None => return Ok(None),
+        };
+        
+        let mut metadata = ChainMetadata {
+            tip_hash: String::new(),
+            height: latest_height,
+            cumulative_work: 0,
+            pruned_height: None,
+        };
+        for height in 0..=latest_height {
+            if let Some(block) = self.get_block_by_height(height)? {
+                metadata.cumulative_work = metadata.cumulative_work.saturating_add(block.work());
+                metadata.tip_hash = block.header.hash;
+            }
+        }
+        
+        let (key, data) = Self::metadata_operation(&metadata)?;
+        self.db.put(key, data)
+            .map_err(|e| format!("Failed to store chain metadata: {}", e))?;
+        Ok(Some(metadata))
+    }
+    
+    /// Retrieve a block by its hash, serving recent blocks from the cache
     pub fn get_block(&self, hash: &str) -> Result<Option<Block>, String> {
+        if let Some(block) = self.block_cache.lock().unwrap().get(hash) {
+            return Ok(Some(block));
+        }
+        
         let key = format!("block:{}", hash);
         
         match self.db.get(&key) {
             Ok(Some(block_data)) => {
-                let block: Block = serde_json::from_slice(&block_data)
+                let block: Block = schema::decode(RecordKind::Block, &block_data)
+                    .map_err(|e| format!("Failed to deserialize block: {}", e))?;
+                self.cache_block(&block, block_data.len());
+                Ok(Some(block))
+            },
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("Database error: {}", e)),
+        }
+    }
+    
+    /// Read a block straight from the database, bypassing the cache
+    pub fn get_block_uncached(&self, hash: &str) -> Result<Option<Block>, String> {
+        let key = format!("block:{}", hash);
+        
+        match self.db.get(&key) {
+            Ok(Some(block_data)) => {
+                let block: Block = schema::decode(RecordKind::Block, &block_data)
                     .map_err(|e| format!("Failed to deserialize block: {}", e))?;
                 Ok(Some(block))
             },
@@ -62,13 +500,59 @@ impl BlockStore {
     
     /// Get block by height
     pub fn get_block_by_height(&self, height: u64) -> Result<Option<Block>, String> {
+        match self.get_hash_by_height(height)? {
+            Some(hash) => self.get_block(&hash),
+            None => Ok(None),
+        }
+    }
+    
+    /// Iterate over the blocks at heights `from..=to`, loading one block at a time
+    pub fn iter_blocks(&self, from: u64, to: u64) -> BlockRange<'_> {
+        BlockRange {
+            store: self,
+            next: from,
+            end: to,
+            done: from > to,
+        }
+    }
+    
+    /// Get a block header by hash from the cache or the header index, without
+    /// loading the block's transactions
+    pub fn get_header(&self, hash: &str) -> Result<Option<BlockHeader>, String> {
+        if let Some(header) = self.header_cache.lock().unwrap().get(hash) {
+            return Ok(Some(header));
+        }
+        
+        // Stores written before the header index fall back to the full block
+        match self.db.get(&format!("header:{}", hash)) {
+            Ok(Some(data)) => {
+                let header: BlockHeader = schema::decode(RecordKind::Header, &data)
+                    .map_err(|e| format!("Failed to deserialize block header: {}", e))?;
+                self.cache_header(&header);
+                Ok(Some(header))
+            },
+            Ok(None) => Ok(self.get_block(hash)?.map(|block| block.header)),
+            Err(e) => Err(format!("Database error: {}", e)),
+        }
+    }
+    
+    /// Get a block header by height
+    pub fn get_header_by_height(&self, height: u64) -> Result<Option<BlockHeader>, String> {
+        match self.get_hash_by_height(height)? {
+            Some(hash) => self.get_header(&hash),
+            None => Ok(None),
+        }
+    }
+    
+    /// Get the block hash recorded for a height
+    pub fn get_hash_by_height(&self, height: u64) -> Result<Option<String>, String> {
         let height_key = format!("height:{}", height);
         
         match self.db.get(&height_key) {
             Ok(Some(hash_bytes)) => {
                 let hash = String::from_utf8(hash_bytes)
                     .map_err(|e| format!("Invalid hash encoding: {}", e))?;
-                self.get_block(&hash)
+                Ok(Some(hash))
             },
             Ok(None) => Ok(None),
             Err(e) => Err(format!("Database error: {}", e)),
@@ -111,24 +595,41 @@ impl BlockStore {
     
     /// Store multiple blocks in a batch operation
     pub fn store_blocks_batch(&self, blocks: &[Block]) -> Result<(), String> {
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+            return Ok(());
+        };
         let mut operations = Vec::new();
         let mut latest_height = 0;
+        let mut work = self.work_before(first)?;
+        let mut balances = HashMap::new();
         
         for block in blocks {
-            let block_data = serde_json::to_vec(block)
+            operations.extend(self.chainstate_operations(block, &mut balances)?);
+            
+            let block_data = schema::encode(block)
                 .map_err(|e| format!("Failed to serialize block: {}", e))?;
             
+            self.cache_block(block, block_data.len());
             let key = format!("block:{}", block.header.hash);
             operations.push((key, block_data));
             
+            operations.push(Self::header_operation(&block.header)?);
+            
             let height_key = format!("height:{}", block.header.height);
             operations.push((height_key, block.header.hash.as_bytes().to_vec()));
             
             latest_height = latest_height.max(block.header.height);
+            work = work.saturating_add(block.work());
         }
         
-        // Add latest height update
+        // Add latest height and metadata update
         operations.push(("latest_height".to_string(), latest_height.to_be_bytes().to_vec()));
+        operations.push(Self::metadata_operation(&ChainMetadata {
+            tip_hash: last.header.hash.clone(),
+            height: last.header.height,
+            cumulative_work: work,
+            pruned_height: self.get_chain_metadata()?.and_then(|metadata| metadata.pruned_height),
+        })?);
         
         self.db.batch_put(operations)
             .map_err(|e| format!("Failed to store blocks in batch: {}", e))
@@ -152,3 +653,31 @@ impl Default for BlockStore {
         Self::new().expect("Failed to create default BlockStore")
     }
 }
+
+/// Blocks in ascending height order, read lazily from a `BlockStore`
+pub struct BlockRange<'a> {
+    store: &'a BlockStore,
+    next: u64,
+    end: u64,
+    done: bool,
+}
+
+impl Iterator for BlockRange<'_> {
+    type Item = Result<Block, String>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        
+        let height = self.next;
+        if height == self.end {
+            self.done = true;
+        } else {
+            self.next += 1;
+        }
+        
+        Some(self.store.get_block_by_height(height)
+            .and_then(|block| block.ok_or_else(|| format!("Missing block at height {}", height))))
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Default byte budget for cached blocks (16 MiB)
+pub const DEFAULT_BLOCK_CACHE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Default byte budget for cached headers (2 MiB)
+pub const DEFAULT_HEADER_CACHE_BYTES: usize = 2 * 1024 * 1024;
+
+/// Least-recently-used cache bounded by the total size of its values in bytes
+#[derive(Debug)]
+pub struct LruCache<V> {
+    entries: HashMap<String, (V, usize)>,
+    /// Keys from least to most recently used
+    order: VecDeque<String>,
+    capacity_bytes: usize,
+    used_bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl<V: Clone> LruCache<V> {
+    pub fn new(capacity_bytes: usize) -> Self {
+        LruCache {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity_bytes,
+            used_bytes: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Look up a value, marking it as recently used
+    pub fn get(&mut self, key: &str) -> Option<V> {
+        match self.entries.get(key) {
+            Some((value, _)) => {
+                let value = value.clone();
+                self.hits += 1;
+                self.touch(key);
+                Some(value)
+            },
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Insert a value of the given size, evicting old entries to stay within capacity.
+    /// Values larger than the whole cache are not stored.
+    pub fn insert(&mut self, key: String, value: V, size_bytes: usize) {
+        self.remove(&key);
+        if size_bytes > self.capacity_bytes {
+            return;
+        }
+
+        while self.used_bytes + size_bytes > self.capacity_bytes {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    if let Some((_, size)) = self.entries.remove(&oldest) {
+                        self.used_bytes -= size;
+                    }
+                },
+                None => break,
+            }
+        }
+
+        self.used_bytes += size_bytes;
+        self.order.push_back(key.clone());
+        self.entries.insert(key, (value, size_bytes));
+    }
+
+    /// Drop an entry if present
+    pub fn remove(&mut self, key: &str) {
+        if let Some((_, size)) = self.entries.remove(key) {
+            self.used_bytes -= size;
+            self.order.retain(|k| k != key);
+        }
+    }
+
+    /// Change the byte budget, evicting entries if it shrank
+    pub fn set_capacity(&mut self, capacity_bytes: usize) {
+        self.capacity_bytes = capacity_bytes;
+        while self.used_bytes > self.capacity_bytes {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    if let Some((_, size)) = self.entries.remove(&oldest) {
+                        self.used_bytes -= size;
+                    }
+                },
+                None => break,
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.used_bytes = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            used_bytes: self.used_bytes,
+            capacity_bytes: self.capacity_bytes,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    /// Move a key to the most recently used position
+    fn touch(&mut self, key: &str) {
+        if let Some(k) = self.order.iter().position(|k| k == key).and_then(|pos| self.order.remove(pos)) {
+            self.order.push_back(k);
+        }
+    }
+}
+
+/// Cache usage counters
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub used_bytes: usize,
+    pub capacity_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(30);
+        cache.insert("a".to_string(), 1, 10);
+        cache.insert("b".to_string(), 2, 10);
+        cache.insert("c".to_string(), 3, 10);
+
+        // Touch "a" so "b" becomes the oldest
+        assert_eq!(cache.get("a"), Some(1));
+        cache.insert("d".to_string(), 4, 10);
+
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("d"), Some(4));
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.used_bytes, 30);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn test_oversized_and_resize() {
+        let mut cache = LruCache::new(20);
+        cache.insert("big".to_string(), 1, 50);
+        assert_eq!(cache.stats().entries, 0);
+
+        cache.insert("a".to_string(), 1, 10);
+        cache.insert("b".to_string(), 2, 10);
+        cache.set_capacity(10);
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.get("b"), Some(2));
+    }
+}
@@ -1,6 +1,8 @@
 use rocksdb::{DB, Options, Error};
 use std::path::Path;
 
+use crate::perf;
+
 /// RocksDB-based persistent key-value storage
 /// Upgraded from in-memory HashMap to persistent RocksDB
 pub struct Database {
@@ -30,6 +32,7 @@ impl Database {
     
     /// Store a key-value pair
     pub fn put(&self, key: String, value: Vec<u8>) -> Result<(), Error> {
+        let _timer = perf::timer(perf::DB_WRITE);
         self.db.put(key.as_bytes(), value)
     }
     
@@ -40,6 +43,7 @@ impl Database {
     
     /// Delete a key-value pair
     pub fn delete(&self, key: &str) -> Result<bool, Error> {
+        let _timer = perf::timer(perf::DB_WRITE);
         match self.db.delete(key.as_bytes()) {
             Ok(_) => Ok(true),
             Err(e) => Err(e),
@@ -68,6 +72,14 @@ impl Database {
         Ok(keys)
     }
     
+    /// Whether the database holds no keys
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        match self.db.iterator(rocksdb::IteratorMode::Start).next() {
+            Some(item) => item.map(|_| false),
+            None => Ok(true),
+        }
+    }
+    
     /// Get keys with a specific prefix
     pub fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Error> {
         let mut keys = Vec::new();
@@ -75,15 +87,36 @@ impl Database {
         
         for item in iter {
             let (key, _) = item?;
+            // Without a prefix extractor the iterator runs past the prefix
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
             keys.push(String::from_utf8_lossy(&key).to_string());
         }
         
         Ok(keys)
     }
     
+    /// Get keys and values with a specific prefix
+    pub fn entries_with_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let mut entries = Vec::new();
+        let iter = self.db.prefix_iterator(prefix.as_bytes());
+        
+        for item in iter {
+            let (key, value) = item?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            entries.push((String::from_utf8_lossy(&key).to_string(), value.to_vec()));
+        }
+        
+        Ok(entries)
+    }
+    
     /// Batch operations for better performance
     pub fn batch_put(&self, operations: Vec<(String, Vec<u8>)>) -> Result<(), Error> {
         use rocksdb::WriteBatch;
+        let _timer = perf::timer(perf::DB_WRITE);
         
         let mut batch = WriteBatch::default();
         for (key, value) in operations {
@@ -93,6 +126,27 @@ impl Database {
         self.db.write(batch)
     }
     
+    /// Write puts and deletes as one atomic batch
+    pub fn batch_write(&self, puts: Vec<(String, Vec<u8>)>, deletes: Vec<String>) -> Result<(), Error> {
+        use rocksdb::WriteBatch;
+        let _timer = perf::timer(perf::DB_WRITE);
+        
+        let mut batch = WriteBatch::default();
+        for (key, value) in puts {
+            batch.put(key.as_bytes(), value);
+        }
+        for key in deletes {
+            batch.delete(key.as_bytes());
+        }
+        
+        self.db.write(batch)
+    }
+    
+    /// Write a consistent snapshot of the database to `path`, which must not exist
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        rocksdb::checkpoint::Checkpoint::new(&self.db)?.create_checkpoint(path)
+    }
+    
     /// Get database statistics
     pub fn stats(&self) -> Result<DatabaseStats, Error> {
         let db_stats = self.db.property_value("rocksdb.stats")?;
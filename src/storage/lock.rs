@@ -0,0 +1,159 @@
+//! Data directory lock
+//!
+//! Two CLI invocations working on the same data directory can corrupt RocksDB or
+//! the wallet file. The first one writes a lock file holding its PID into the
+//! directory and removes it on exit; any other process is refused with an error
+//! naming the holder. A lock left behind by a process that crashed is cleared
+//! with `--force-unlock`.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+/// Lock file name inside the data directory
+pub const LOCK_FILE: &str = "rust_chain.lock";
+
+/// Process holding a data directory lock
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    pub pid: u32,
+    /// Unix time the lock was taken
+    pub acquired_at: u64,
+}
+
+/// Exclusive use of a data directory, released when dropped
+#[derive(Debug)]
+pub struct DataDirLock {
+    path: PathBuf,
+    owner: LockOwner,
+    /// False when this process already held the lock, so dropping this handle
+    /// must leave the file to the first one
+    owns_file: bool,
+}
+
+impl DataDirLock {
+    /// Take the lock on `data_dir`, creating the directory if needed. Fails if
+    /// another process holds it; the same process may take it again.
+    pub fn acquire<P: AsRef<Path>>(data_dir: P) -> Result<Self, String> {
+        let dir = data_dir.as_ref();
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+        let path = dir.join(LOCK_FILE);
+        let owner = LockOwner {
+            pid: std::process::id(),
+            acquired_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let data = serde_json::to_vec(&owner)
+                    .map_err(|e| format!("Failed to serialize lock owner: {}", e))?;
+                file.write_all(&data)
+                    .map_err(|e| format!("Failed to write lock file: {}", e))?;
+                Ok(DataDirLock { path, owner, owns_file: true })
+            },
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => match Self::current_owner(dir)? {
+                Some(holder) if holder.pid == owner.pid => Ok(DataDirLock { path, owner: holder, owns_file: false }),
+                Some(holder) => {
+                    let state = match process_running(holder.pid) {
+                        Some(false) => " (that process is no longer running)",
+                        _ => "",
+                    };
+                    Err(format!(
+                        "Data directory {} is in use by process {} since {}{}. If no other instance is running, retry with --force-unlock",
+                        dir.display(), holder.pid, holder.acquired_at, state
+                    ))
+                },
+                None => Err(format!(
+                    "Data directory {} has an unreadable lock file, retry with --force-unlock",
+                    dir.display()
+                )),
+            },
+            Err(e) => Err(format!("Failed to create lock file: {}", e)),
+        }
+    }
+
+    /// Current holder of the lock on `data_dir`, if it is locked and the lock
+    /// file can be read
+    pub fn current_owner<P: AsRef<Path>>(data_dir: P) -> Result<Option<LockOwner>, String> {
+        match fs::read(data_dir.as_ref().join(LOCK_FILE)) {
+            Ok(data) => Ok(serde_json::from_slice(&data).ok()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read lock file: {}", e)),
+        }
+    }
+
+    /// Remove the lock on `data_dir` whoever holds it, returning the previous
+    /// holder. Only for locks left behind by a process that is gone.
+    pub fn force_unlock<P: AsRef<Path>>(data_dir: P) -> Result<Option<LockOwner>, String> {
+        let owner = Self::current_owner(&data_dir)?;
+        match fs::remove_file(data_dir.as_ref().join(LOCK_FILE)) {
+            Ok(()) => Ok(owner),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to remove lock file: {}", e)),
+        }
+    }
+
+    /// Process holding this lock
+    pub fn owner(&self) -> &LockOwner {
+        &self.owner
+    }
+}
+
+impl Drop for DataDirLock {
+    fn drop(&mut self) {
+        if !self.owns_file {
+            return;
+        }
+        // Leave a lock that was forced away and taken by someone else alone
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        if matches!(Self::current_owner(dir), Ok(Some(owner)) if owner == self.owner) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Whether a process with this PID exists, where that can be told
+pub fn process_running(pid: u32) -> Option<bool> {
+    let proc_dir = Path::new("/proc");
+    if cfg!(target_os = "linux") && proc_dir.is_dir() {
+        Some(proc_dir.join(pid.to_string()).exists())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_refuses_other_process_until_forced() {
+        let dir = std::env::temp_dir().join(format!("rust_chain_lock_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let lock = DataDirLock::acquire(&dir).unwrap();
+        assert_eq!(DataDirLock::current_owner(&dir).unwrap().unwrap().pid, std::process::id());
+        // Taking it again from the same process leaves the file to the first handle
+        drop(DataDirLock::acquire(&dir).unwrap());
+        assert!(dir.join(LOCK_FILE).exists());
+        drop(lock);
+        assert!(!dir.join(LOCK_FILE).exists());
+
+        // A lock left by another process is refused until forced away
+        let stale = LockOwner { pid: u32::MAX, acquired_at: 1 };
+        fs::write(dir.join(LOCK_FILE), serde_json::to_vec(&stale).unwrap()).unwrap();
+        let err = DataDirLock::acquire(&dir).unwrap_err();
+        assert!(err.contains(&u32::MAX.to_string()) && err.contains("--force-unlock"));
+
+        assert_eq!(DataDirLock::force_unlock(&dir).unwrap(), Some(stale));
+        let lock = DataDirLock::acquire(&dir).unwrap();
+        assert_eq!(lock.owner().pid, std::process::id());
+
+        drop(lock);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
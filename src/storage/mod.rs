@@ -1,5 +1,15 @@
 pub mod db;
 pub mod block_store;
+pub mod cache;
+pub mod undo;
+pub mod schema;
+pub mod lock;
+pub mod backup;
 
-pub use block_store::BlockStore;
-pub use db::Database;
\ No newline at end of file
+pub use block_store::{BlockStore, BlockRange, ChainMetadata};
+pub use cache::{LruCache, CacheStats};
+pub use db::Database;
+pub use undo::BlockUndo;
+pub use schema::{SchemaMigration, SCHEMA_VERSION};
+pub use lock::{DataDirLock, LockOwner};
+pub use backup::BackupManifest;
\ No newline at end of file
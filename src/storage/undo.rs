@@ -0,0 +1,81 @@
+//! Undo data for disconnecting blocks
+//!
+//! Alongside every block the store keeps the chainstate (the balance of each
+//! address after the stored tip) and an undo record with the balances the block
+//! overwrote. Disconnecting the tip restores those balances directly instead of
+//! replaying the chain from genesis.
+
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::amount::checked_money_add;
+use crate::blockchain::block::Block;
+use crate::blockchain::vault::is_unvault_transaction;
+
+/// Balances a block overwrote, enough to disconnect it again
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockUndo {
+    pub block_hash: String,
+    pub height: u64,
+    /// Balance of every address the block touched, before the block was connected
+    pub prior_balances: Vec<(String, u64)>,
+}
+
+/// One change a transaction makes to an address balance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceChange {
+    Credit(u64),
+    Debit(u64),
+}
+
+/// Balance changes a block makes, in transaction order. Coinbase maturity is
+/// ignored: the chainstate tracks what each address has received, spendable or not.
+/// Vault unlock declarations move no funds and are left out.
+pub fn balance_changes(block: &Block) -> Vec<(String, BalanceChange)> {
+    let mut changes = Vec::new();
+    for tx in block.transactions.iter().filter(|tx| !is_unvault_transaction(tx)) {
+        if !tx.from.is_empty() && tx.from != "genesis" {
+            changes.push((tx.from.clone(), BalanceChange::Debit(tx.amount)));
+        }
+        changes.push((tx.to.clone(), BalanceChange::Credit(tx.amount)));
+    }
+    changes
+}
+
+/// Balance after applying `change`, the same way `UTXOState` does: debits are
+/// clamped at zero and credits may not take the balance past `MAX_MONEY`
+pub fn apply_change(balance: u64, change: BalanceChange) -> Result<u64, String> {
+    match change {
+        BalanceChange::Credit(amount) => checked_money_add(balance, amount),
+        BalanceChange::Debit(amount) => Ok(balance.saturating_sub(amount)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::amount::MAX_MONEY;
+    use crate::blockchain::block::Transaction;
+
+    #[test]
+    fn test_balance_changes() {
+        let tx = |from: &str, to: &str, amount| Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let block = Block::new("0".to_string(), vec![tx("genesis", "alice", 100), tx("alice", "bob", 30)], 0, 0, 0);
+
+        assert_eq!(balance_changes(&block), vec![
+            ("alice".to_string(), BalanceChange::Credit(100)),
+            ("alice".to_string(), BalanceChange::Debit(30)),
+            ("bob".to_string(), BalanceChange::Credit(30)),
+        ]);
+        assert_eq!(apply_change(10, BalanceChange::Debit(30)), Ok(0));
+        assert_eq!(apply_change(MAX_MONEY - 1, BalanceChange::Credit(1)), Ok(MAX_MONEY));
+        assert!(apply_change(MAX_MONEY, BalanceChange::Credit(1)).is_err());
+        assert!(apply_change(1, BalanceChange::Credit(u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Merging small outputs into one address
+//!
+//! Payments received at many addresses leave a wallet with its funds spread
+//! thin, and a send from an account needs one address that can pay on its own.
+//! `consolidate-utxos` moves the outputs of every other address into the one
+//! holding the most. Transactions have a single sender, so each address is
+//! swept by its own transaction; together they leave one balance behind. They
+//! pay the fee rate for the slowest confirmation target, and only while that
+//! rate is below a maximum, so consolidation waits for a quiet mempool.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::blockchain::chain::UnspentOutput;
+
+/// Highest fee per byte `consolidate-utxos` pays unless told otherwise
+pub const DEFAULT_CONSOLIDATION_MAX_FEE_RATE: f64 = 1.0;
+
+/// Most addresses swept in one run; the smallest balances go first
+pub const MAX_CONSOLIDATION_SWEEPS: usize = 50;
+
+/// One address emptied into the destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sweep {
+    pub address: String,
+    pub amount: u64,
+    /// Outputs merged by the sweep
+    pub outputs: usize,
+}
+
+/// Which addresses to empty into which
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsolidationPlan {
+    /// The address holding the most, which receives everything else
+    pub destination: String,
+    pub sweeps: Vec<Sweep>,
+    /// Addresses left alone because their total is dust the relay policy refuses
+    pub dust: Vec<String>,
+}
+
+impl ConsolidationPlan {
+    pub fn amount(&self) -> u64 {
+        self.sweeps.iter().map(|sweep| sweep.amount).sum()
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.sweeps.iter().map(|sweep| sweep.outputs).sum()
+    }
+}
+
+/// Plan merging the spendable `outputs` of a wallet. Addresses with a spend
+/// waiting in the mempool are skipped, since a sweep would double-spend it,
+/// and so are totals below `dust_threshold`.
+pub fn plan_consolidation(outputs: &[UnspentOutput], pending_senders: &HashSet<String>, dust_threshold: u64) -> ConsolidationPlan {
+    let mut holdings: BTreeMap<&str, (u64, usize)> = BTreeMap::new();
+    for output in outputs.iter().filter(|output| output.spendable) {
+        let (amount, count) = holdings.entry(output.address.as_str()).or_default();
+        *amount += output.amount;
+        *count += 1;
+    }
+
+    // Largest first, by address among equals so the plan does not depend on output order
+    let mut holdings: Vec<(&str, u64, usize)> = holdings.into_iter()
+        .map(|(address, (amount, count))| (address, amount, count))
+        .collect();
+    holdings.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let Some(((destination, _, _), rest)) = holdings.split_first() else {
+        return ConsolidationPlan::default();
+    };
+
+    let mut plan = ConsolidationPlan { destination: destination.to_string(), ..ConsolidationPlan::default() };
+    for &(address, amount, outputs) in rest.iter().rev() {
+        if pending_senders.contains(address) {
+            continue;
+        }
+        if amount < dust_threshold {
+            plan.dust.push(address.to_string());
+        } else if plan.sweeps.len() < MAX_CONSOLIDATION_SWEEPS {
+            plan.sweeps.push(Sweep { address: address.to_string(), amount, outputs });
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(address: &str, amount: u64, spendable: bool) -> UnspentOutput {
+        UnspentOutput {
+            txid: format!("{}-{}", address, amount),
+            vout: 0,
+            address: address.to_string(),
+            amount,
+            block_height: 1,
+            confirmations: 1,
+            coinbase: !spendable,
+            spendable,
+        }
+    }
+
+    #[test]
+    fn test_plan_consolidation() {
+        let outputs = vec![
+            output("alice", 30, true),
+            output("bob", 500, true),
+            output("carol", 20, true),
+            output("carol", 15, true),
+            output("dave", 4, true),
+            output("erin", 60, true),
+            // Immature rewards cannot be swept and do not make frank the destination
+            output("frank", 10_000, false),
+        ];
+        let pending: HashSet<String> = ["erin".to_string()].into();
+
+        let plan = plan_consolidation(&outputs, &pending, 5);
+        assert_eq!(plan.destination, "bob");
+        assert_eq!(plan.sweeps, vec![
+            Sweep { address: "alice".to_string(), amount: 30, outputs: 1 },
+            Sweep { address: "carol".to_string(), amount: 35, outputs: 2 },
+        ]);
+        assert_eq!(plan.dust, vec!["dave".to_string()]);
+        assert_eq!((plan.amount(), plan.outputs()), (65, 3));
+
+        assert_eq!(plan_consolidation(&[], &pending, 5), ConsolidationPlan::default());
+        assert_eq!(plan_consolidation(&outputs[..2], &HashSet::new(), 5).sweeps.len(), 1);
+    }
+}
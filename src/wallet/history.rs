@@ -0,0 +1,180 @@
+//! Wallet transaction history
+//!
+//! The confirmed transactions touching the wallet's addresses, oldest first, each
+//! tagged with what it meant for the wallet: coins received, coins sent, or mining
+//! income from a coinbase paying one of its addresses. The genesis allocation is
+//! received like any payment; only block rewards count as mining income. A
+//! transfer between two of the wallet's own addresses shows up once on each side.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::blockchain::block::Block;
+use crate::blockchain::chain::Chain;
+use crate::blockchain::genesis::is_coinbase_transaction;
+use crate::blockchain::state::is_coinbase_mature;
+
+/// First line of an exported history
+pub const HISTORY_CSV_HEADER: &str =
+    "txid,block_height,timestamp,category,address,counterparty,amount,confirmations,spendable,replaces";
+
+/// What a transaction meant for the wallet
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryCategory {
+    Receive,
+    Send,
+    MiningIncome,
+}
+
+impl HistoryCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryCategory::Receive => "receive",
+            HistoryCategory::Send => "send",
+            HistoryCategory::MiningIncome => "mining_income",
+        }
+    }
+}
+
+/// One side of a transaction, as seen by the wallet
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub txid: String,
+    pub block_hash: String,
+    pub block_height: u64,
+    pub timestamp: u64,
+    pub category: HistoryCategory,
+    /// The wallet's address
+    pub address: String,
+    /// The other side: the sender of a receipt or the recipient of a payment
+    pub counterparty: String,
+    pub amount: u64,
+    pub confirmations: u64,
+    /// False for mining income that has not matured yet
+    pub spendable: bool,
+    /// The send this one replaced at a higher fee, known only to the wallet that bumped it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaces: Option<String>,
+}
+
+impl HistoryEntry {
+    /// Entries for the wallet's side of every transaction in `block`, with a chain tip at `tip_height`
+    pub fn from_block(block: &Block, addresses: &HashSet<String>, tip_height: u64) -> Vec<HistoryEntry> {
+        let height = block.header.height;
+        let mut entries = Vec::new();
+        for transaction in &block.transactions {
+            let coinbase = is_coinbase_transaction(transaction);
+            let entry = |category, address: &str, counterparty: &str| HistoryEntry {
+                txid: transaction.hash(),
+                block_hash: block.header.hash.clone(),
+                block_height: height,
+                timestamp: block.header.timestamp,
+                category,
+                address: address.to_string(),
+                counterparty: counterparty.to_string(),
+                amount: transaction.amount,
+                confirmations: tip_height.saturating_sub(height) + 1,
+                spendable: !coinbase || is_coinbase_mature(height, tip_height + 1),
+                replaces: None,
+            };
+            if addresses.contains(&transaction.from) && !coinbase {
+                entries.push(entry(HistoryCategory::Send, &transaction.from, &transaction.to));
+            }
+            if addresses.contains(&transaction.to) {
+                let category = if coinbase && height > 0 { HistoryCategory::MiningIncome } else { HistoryCategory::Receive };
+                entries.push(entry(category, &transaction.to, &transaction.from));
+            }
+        }
+        entries
+    }
+
+    pub fn to_csv(&self) -> String {
+        format!("{},{},{},{},{},{},{},{},{},{}",
+            self.txid, self.block_height, self.timestamp, self.category.as_str(), self.address,
+            self.counterparty, self.amount, self.confirmations, self.spendable,
+            self.replaces.as_deref().unwrap_or_default())
+    }
+}
+
+/// The history of `addresses` over the whole of `chain`, oldest first
+pub fn wallet_history(chain: &Chain, addresses: &HashSet<String>) -> Result<Vec<HistoryEntry>, String> {
+    let tip_height = chain.height();
+    let mut history = Vec::new();
+    chain.scan_blocks(0, tip_height, |block| history.extend(HistoryEntry::from_block(block, addresses, tip_height)))?;
+    Ok(history)
+}
+
+/// Block rewards paid to the wallet
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct MiningIncome {
+    pub blocks: usize,
+    pub total: u64,
+    /// Part of the total that has not matured yet
+    pub immature: u64,
+}
+
+impl MiningIncome {
+    pub fn from_history(entries: &[HistoryEntry]) -> Self {
+        let mut income = MiningIncome::default();
+        for entry in entries.iter().filter(|entry| entry.category == HistoryCategory::MiningIncome) {
+            income.blocks += 1;
+            income.total = income.total.saturating_add(entry.amount);
+            if !entry.spendable {
+                income.immature = income.immature.saturating_add(entry.amount);
+            }
+        }
+        income
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::block::Transaction;
+    use crate::blockchain::genesis::{coinbase_transaction, COINBASE_ADDRESS};
+    use crate::blockchain::state::COINBASE_MATURITY;
+
+    fn payment(from: &str, to: &str, amount: u64) -> Transaction {
+        Transaction {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        }
+    }
+
+    #[test]
+    fn test_history_tags_mining_income() {
+        let addresses: HashSet<String> = ["miner".to_string(), "savings".to_string()].into();
+        let genesis = Block::new("0".to_string(), vec![payment(COINBASE_ADDRESS, "miner", 1_000)], 0, 0, 0);
+        let block = Block::new(genesis.header.hash.clone(), vec![
+            coinbase_transaction("miner", 50, 1),
+            payment("miner", "savings", 20),
+            payment("alice", "bob", 5),
+        ], 0, 10, 1);
+
+        let tip = 10;
+        let mut history = HistoryEntry::from_block(&genesis, &addresses, tip);
+        history.extend(HistoryEntry::from_block(&block, &addresses, tip));
+        let tags: Vec<(HistoryCategory, &str, u64)> = history.iter()
+            .map(|entry| (entry.category, entry.address.as_str(), entry.amount))
+            .collect();
+        assert_eq!(tags, vec![
+            (HistoryCategory::Receive, "miner", 1_000),
+            (HistoryCategory::MiningIncome, "miner", 50),
+            (HistoryCategory::Send, "miner", 20),
+            (HistoryCategory::Receive, "savings", 20),
+        ]);
+        assert_eq!(history[1].confirmations, 10);
+        assert!(!history[1].spendable);
+        assert!(history[1].to_csv().contains(",mining_income,miner,"));
+
+        assert_eq!(MiningIncome::from_history(&history), MiningIncome { blocks: 1, total: 50, immature: 50 });
+        let matured = HistoryEntry::from_block(&block, &addresses, COINBASE_MATURITY);
+        assert_eq!(MiningIncome::from_history(&matured).immature, 0);
+    }
+}
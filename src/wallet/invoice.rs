@@ -0,0 +1,161 @@
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+/// Scheme of payment request URIs
+pub const URI_SCHEME: &str = "rustchain";
+
+/// Default time an invoice stays open, in seconds
+pub const DEFAULT_INVOICE_EXPIRY: u64 = 24 * 60 * 60;
+
+/// A payment request to a fresh wallet address
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Invoice {
+    /// Sequential invoice number within the wallet
+    pub id: u32,
+    pub address: String,
+    pub amount: u64,
+    pub label: Option<String>,
+    /// Creation time (seconds since the epoch)
+    pub created_at: u64,
+    /// Time after which an unpaid invoice is expired (seconds since the epoch)
+    pub expires_at: u64,
+}
+
+/// Payment state of an invoice
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceStatus {
+    Paid,
+    Unpaid,
+    Expired,
+}
+
+impl fmt::Display for InvoiceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvoiceStatus::Paid => write!(f, "paid"),
+            InvoiceStatus::Unpaid => write!(f, "unpaid"),
+            InvoiceStatus::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+impl Invoice {
+    /// Payment URI, e.g. `rustchain:<address>?amount=50&label=Coffee&expires=1700000000`.
+    /// Only the characters a QR code's alphanumeric mode handles well are left unescaped.
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!("{}:{}?amount={}", URI_SCHEME, self.address, self.amount);
+        if let Some(label) = &self.label {
+            uri.push_str(&format!("&label={}", percent_encode(label)));
+        }
+        uri.push_str(&format!("&expires={}", self.expires_at));
+        uri
+    }
+
+    /// Parse a payment URI back into an invoice. The id and creation time are not
+    /// part of the URI and are left at zero.
+    pub fn from_uri(uri: &str) -> Result<Self, String> {
+        let rest = uri.strip_prefix(&format!("{}:", URI_SCHEME))
+            .ok_or_else(|| format!("Payment URI must start with '{}:'", URI_SCHEME))?;
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if address.is_empty() {
+            return Err("Payment URI has no address".to_string());
+        }
+
+        let mut invoice = Invoice {
+            id: 0,
+            address: address.to_string(),
+            amount: 0,
+            label: None,
+            created_at: 0,
+            expires_at: 0,
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "amount" => invoice.amount = value.parse()
+                    .map_err(|_| format!("Invalid amount '{}'", value))?,
+                "label" => invoice.label = Some(percent_decode(value)?),
+                "expires" => invoice.expires_at = value.parse()
+                    .map_err(|_| format!("Invalid expiry '{}'", value))?,
+                _ => {},
+            }
+        }
+        Ok(invoice)
+    }
+
+    /// Status given the amount received at the invoice address before it expired
+    pub fn status(&self, received: u64, now: u64) -> InvoiceStatus {
+        if received >= self.amount {
+            InvoiceStatus::Paid
+        } else if now > self.expires_at {
+            InvoiceStatus::Expired
+        } else {
+            InvoiceStatus::Unpaid
+        }
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Result<String, String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3)
+                .ok_or_else(|| format!("Truncated escape in '{}'", value))?;
+            decoded.push(u8::from_str_radix(hex, 16)
+                .map_err(|_| format!("Invalid escape '%{}'", hex))?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|e| format!("Invalid label encoding: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uri_round_trip() {
+        let invoice = Invoice {
+            id: 3,
+            address: "ab12".to_string(),
+            amount: 50,
+            label: Some("Coffee & cake".to_string()),
+            created_at: 1_000,
+            expires_at: 2_000,
+        };
+        let uri = invoice.to_uri();
+        assert_eq!(uri, "rustchain:ab12?amount=50&label=Coffee%20%26%20cake&expires=2000");
+
+        let parsed = Invoice::from_uri(&uri).unwrap();
+        assert_eq!(parsed.address, "ab12");
+        assert_eq!(parsed.amount, 50);
+        assert_eq!(parsed.label.as_deref(), Some("Coffee & cake"));
+        assert_eq!(parsed.expires_at, 2_000);
+        assert!(Invoice::from_uri("bitcoin:ab12").is_err());
+    }
+
+    #[test]
+    fn test_invoice_status() {
+        let invoice = Invoice::from_uri("rustchain:ab12?amount=50&expires=2000").unwrap();
+        assert_eq!(invoice.status(0, 1_500), InvoiceStatus::Unpaid);
+        assert_eq!(invoice.status(20, 2_500), InvoiceStatus::Expired);
+        assert_eq!(invoice.status(50, 2_500), InvoiceStatus::Paid);
+    }
+}
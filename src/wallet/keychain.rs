@@ -1,4 +1,7 @@
-use crate::crypto::keys::generate_keypair;
+use crate::crypto::keys::{generate_keypair, parse_private_key};
+use crate::wallet::invoice::Invoice;
+use crate::wallet::policy::{spent_since, SpendPolicy, SpendRecord, SPEND_WINDOW_SECS};
+use ed25519_dalek::SigningKey;
 use std::collections::HashMap;
 use sha2::{Sha256, Digest};
 use bip39::{Mnemonic, Language};
@@ -6,6 +9,60 @@ use serde::{Serialize, Deserialize};
 use std::fs;
 use std::path::Path;
 
+/// Name of the account backed by the wallet's original address chain
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// A named account deriving its addresses from its own BIP-44 style account index
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub name: String,
+    /// Account index (the `account'` level of m/44'/coin'/account'/0/index)
+    pub index: u32,
+    /// Generated addresses with their derivation index within the account
+    addresses: HashMap<String, u32>,
+    /// Next address index within the account
+    next_index: u32,
+}
+
+impl Account {
+    /// Addresses in derivation order
+    pub fn addresses(&self) -> Vec<String> {
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by_key(|(_, index)| *index);
+        addresses.into_iter().map(|(addr, _)| addr.clone()).collect()
+    }
+}
+
+/// How a wallet address has been used
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressState {
+    /// Not seen on chain yet
+    #[default]
+    Unused,
+    /// Received or sent funds on chain
+    Used,
+    /// Reserved for change and never handed out for payments
+    Change,
+}
+
+impl AddressState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AddressState::Unused => "unused",
+            AddressState::Used => "used",
+            AddressState::Change => "change",
+        }
+    }
+}
+
+/// Block the wallet's address usage was last brought up to date with
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UsageScan {
+    pub height: u64,
+    pub block_hash: String,
+}
+
 /// HD Wallet implementing simplified hierarchical deterministic key generation
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Wallet {
@@ -20,6 +77,34 @@ pub struct Wallet {
     current_index: u32,
     /// Mnemonic-like seed phrase (simplified)
     seed_phrase: String,
+    /// Named accounts besides the default one (account index 0)
+    #[serde(default)]
+    accounts: Vec<Account>,
+    /// Payment requests issued by this wallet
+    #[serde(default)]
+    invoices: Vec<Invoice>,
+    /// Addresses that are no longer unused
+    #[serde(default)]
+    address_states: HashMap<String, AddressState>,
+    /// Address handed out for payments until one arrives
+    #[serde(default)]
+    receive_address: Option<String>,
+    /// Limits checked before the wallet sends
+    #[serde(default)]
+    spend_policy: SpendPolicy,
+    /// Sends still counting towards the daily limit, oldest first
+    #[serde(default)]
+    spends: Vec<SpendRecord>,
+    /// Keys imported from outside the wallet as hex, by address. They are not
+    /// derived from the seed, so the seed phrase alone does not restore them.
+    #[serde(default)]
+    imported_keys: HashMap<String, String>,
+    /// Sends replaced at a higher fee: the replacement's txid to the one it replaced
+    #[serde(default)]
+    replacements: HashMap<String, String>,
+    /// Where the last scan for used addresses stopped; blocks up to it need no rescan
+    #[serde(default)]
+    usage_scan: Option<UsageScan>,
 }
 
 impl Wallet {
@@ -38,6 +123,15 @@ impl Wallet {
             addresses: HashMap::new(),
             current_index: 0,
             seed_phrase: mnemonic.to_string(),
+            accounts: Vec::new(),
+            invoices: Vec::new(),
+            address_states: HashMap::new(),
+            receive_address: None,
+            spend_policy: SpendPolicy::default(),
+            spends: Vec::new(),
+            imported_keys: HashMap::new(),
+            replacements: HashMap::new(),
+            usage_scan: None,
         }
     }
 
@@ -55,6 +149,15 @@ impl Wallet {
             addresses: HashMap::new(),
             current_index: 0,
             seed_phrase: mnemonic.to_string(),
+            accounts: Vec::new(),
+            invoices: Vec::new(),
+            address_states: HashMap::new(),
+            receive_address: None,
+            spend_policy: SpendPolicy::default(),
+            spends: Vec::new(),
+            imported_keys: HashMap::new(),
+            replacements: HashMap::new(),
+            usage_scan: None,
         }
     }
 
@@ -71,6 +174,15 @@ impl Wallet {
             addresses: HashMap::new(),
             current_index: 0,
             seed_phrase: phrase.to_string(),
+            accounts: Vec::new(),
+            invoices: Vec::new(),
+            address_states: HashMap::new(),
+            receive_address: None,
+            spend_policy: SpendPolicy::default(),
+            spends: Vec::new(),
+            imported_keys: HashMap::new(),
+            replacements: HashMap::new(),
+            usage_scan: None,
         })
     }
 
@@ -79,12 +191,19 @@ impl Wallet {
         &self.seed_phrase
     }
 
+    /// Seed phrase as a Standard SeedQR payload: the BIP-39 index of each word as a
+    /// four-digit decimal number, which QR encoders pack in numeric mode
+    pub fn seed_qr_payload(&self) -> Result<String, String> {
+        let mnemonic = Mnemonic::parse_in(Language::English, &self.seed_phrase)
+            .map_err(|e| format!("Seed phrase is not a BIP-39 mnemonic: {}", e))?;
+        Ok(mnemonic.word_indices().map(|index| format!("{:04}", index)).collect())
+    }
+
     // ...existing code...
 
     /// Generate a new address using deterministic key derivation
     pub fn generate_address(&mut self) -> Result<String, String> {
-        let derived_key = self.derive_key(self.current_index)?;
-        let address = hex::encode(&derived_key);
+        let address = key_address(&self.derive_key(self.current_index)?);
         
         self.addresses.insert(address.clone(), self.current_index);
         self.current_index += 1;
@@ -105,6 +224,228 @@ impl Wallet {
         Ok(key)
     }
 
+    /// Derive a key for an address of a named account. Account 0 is the default
+    /// account and keeps the original derivation so existing addresses stay valid.
+    fn derive_account_key(&self, account: u32, index: u32) -> Result<[u8; 32], String> {
+        if account == 0 {
+            return self.derive_key(index);
+        }
+        
+        let mut hasher = Sha256::new();
+        hasher.update(self.master_seed);
+        hasher.update(b"m/44'");
+        hasher.update(account.to_be_bytes());
+        hasher.update(index.to_be_bytes());
+        hasher.update(b"blockchain_wallet_derivation");
+        
+        let hash = hasher.finalize();
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&hash);
+        Ok(key)
+    }
+
+    /// Create a named account on the next unused account index
+    pub fn create_account(&mut self, name: &str) -> Result<u32, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Account name cannot be empty".to_string());
+        }
+        if name == DEFAULT_ACCOUNT || self.accounts.iter().any(|account| account.name == name) {
+            return Err(format!("Account '{}' already exists", name));
+        }
+        
+        let index = self.accounts.iter().map(|account| account.index).max().unwrap_or(0) + 1;
+        self.accounts.push(Account {
+            name: name.to_string(),
+            index,
+            addresses: HashMap::new(),
+            next_index: 0,
+        });
+        Ok(index)
+    }
+
+    /// All accounts, starting with the default one
+    pub fn list_accounts(&self) -> Vec<Account> {
+        let default = Account {
+            name: DEFAULT_ACCOUNT.to_string(),
+            index: 0,
+            addresses: self.addresses.clone(),
+            next_index: self.current_index,
+        };
+        std::iter::once(default).chain(self.accounts.iter().cloned()).collect()
+    }
+
+    /// Generate a new address in the named account
+    pub fn generate_account_address(&mut self, name: &str) -> Result<String, String> {
+        if name == DEFAULT_ACCOUNT {
+            return self.generate_address();
+        }
+        
+        let position = self.accounts.iter().position(|account| account.name == name)
+            .ok_or_else(|| format!("Account '{}' not found", name))?;
+        let (account_index, address_index) = {
+            let account = &self.accounts[position];
+            (account.index, account.next_index)
+        };
+        let address = key_address(&self.derive_account_key(account_index, address_index)?);
+        
+        let account = &mut self.accounts[position];
+        account.addresses.insert(address.clone(), address_index);
+        account.next_index += 1;
+        Ok(address)
+    }
+
+    /// Addresses of the named account in derivation order
+    pub fn get_account_addresses(&self, name: &str) -> Result<Vec<String>, String> {
+        self.list_accounts().into_iter()
+            .find(|account| account.name == name)
+            .map(|account| account.addresses())
+            .ok_or_else(|| format!("Account '{}' not found", name))
+    }
+
+    /// Name of the account an address belongs to
+    pub fn account_of(&self, address: &str) -> Option<String> {
+        if self.addresses.contains_key(address) {
+            return Some(DEFAULT_ACCOUNT.to_string());
+        }
+        self.accounts.iter()
+            .find(|account| account.addresses.contains_key(address))
+            .map(|account| account.name.clone())
+    }
+
+    /// Add a private key given as hex or WIF, returning its address. The key is
+    /// kept apart from the seed-derived ones and counts towards the wallet's funds.
+    pub fn import_private_key(&mut self, private_key: &str) -> Result<String, String> {
+        let key = parse_private_key(private_key)?;
+        let address = key_address(&key);
+        if self.is_mine(&address) {
+            return Err(format!("Address {} is already in the wallet", address));
+        }
+        self.imported_keys.insert(address.clone(), hex::encode(key));
+        // The key may have been used before, in blocks already scanned
+        self.usage_scan = None;
+        Ok(address)
+    }
+
+    /// Addresses of imported keys, sorted
+    pub fn imported_addresses(&self) -> Vec<String> {
+        let mut addresses: Vec<String> = self.imported_keys.keys().cloned().collect();
+        addresses.sort();
+        addresses
+    }
+
+    /// Whether an address holds an imported key rather than a seed-derived one
+    pub fn is_imported(&self, address: &str) -> bool {
+        self.imported_keys.contains_key(address)
+    }
+
+    /// Whether the wallet holds the key of an address, derived or imported
+    pub fn is_mine(&self, address: &str) -> bool {
+        self.account_of(address).is_some() || self.is_imported(address)
+    }
+
+    /// Issue an invoice paying to a fresh address of the default account
+    pub fn create_invoice(&mut self, amount: u64, label: Option<&str>, created_at: u64, expiry: u64) -> Result<Invoice, String> {
+        if amount == 0 {
+            return Err("Invoice amount must be greater than zero".to_string());
+        }
+        let expires_at = created_at.checked_add(expiry)
+            .ok_or_else(|| format!("Invoice expiry of {} seconds is too long", expiry))?;
+        
+        let invoice = Invoice {
+            id: self.invoices.len() as u32 + 1,
+            address: self.generate_address()?,
+            amount,
+            label: label.map(str::to_string).filter(|label| !label.is_empty()),
+            created_at,
+            expires_at,
+        };
+        self.invoices.push(invoice.clone());
+        Ok(invoice)
+    }
+
+    /// Issued invoices, oldest first
+    pub fn invoices(&self) -> &[Invoice] {
+        &self.invoices
+    }
+
+    /// Usage state of a wallet address
+    pub fn address_state(&self, address: &str) -> AddressState {
+        self.address_states.get(address).copied().unwrap_or_default()
+    }
+
+    /// Record that a wallet address appeared on chain. Change addresses keep
+    /// their state. Returns whether the state changed.
+    pub fn mark_address_used(&mut self, address: &str) -> bool {
+        if !self.is_mine(address) || self.address_state(address) != AddressState::Unused {
+            return false;
+        }
+        self.address_states.insert(address.to_string(), AddressState::Used);
+        true
+    }
+
+    pub fn usage_scan(&self) -> Option<&UsageScan> {
+        self.usage_scan.as_ref()
+    }
+
+    /// Record that the addresses' usage is up to date with `scan`
+    pub fn set_usage_scan(&mut self, scan: UsageScan) {
+        self.usage_scan = Some(scan);
+    }
+
+    /// Address to hand out for the next payment: the current receive address
+    /// until a payment to it is seen, then a freshly generated one
+    pub fn receive_address(&mut self) -> Result<String, String> {
+        if let Some(address) = &self.receive_address
+            && self.address_state(address) == AddressState::Unused {
+            return Ok(address.clone());
+        }
+        let address = self.generate_address()?;
+        self.receive_address = Some(address.clone());
+        Ok(address)
+    }
+
+    pub fn spend_policy(&self) -> &SpendPolicy {
+        &self.spend_policy
+    }
+
+    pub fn set_spend_policy(&mut self, policy: SpendPolicy) {
+        self.spend_policy = policy;
+    }
+
+    /// Amount sent within the daily window ending at `now`
+    pub fn spent_since(&self, now: u64) -> u64 {
+        spent_since(&self.spends, now)
+    }
+
+    /// Count a send towards the daily limit, forgetting sends outside the window
+    pub fn record_spend(&mut self, txid: &str, amount: u64, now: u64) {
+        self.spends.retain(|record| record.time + SPEND_WINDOW_SECS > now);
+        self.spends.push(SpendRecord { txid: txid.to_string(), amount, time: now, fee_bumped_at: None });
+    }
+
+    /// Record that the send `txid` was replaced by `replacement` paying a higher
+    /// fee. Its spend record, if still kept, follows the replacement.
+    pub fn record_replacement(&mut self, txid: &str, replacement: &str, now: u64) {
+        if let Some(record) = self.spends.iter_mut().find(|record| record.txid == txid) {
+            record.txid = replacement.to_string();
+            record.fee_bumped_at = Some(now);
+        }
+        self.replacements.insert(replacement.to_string(), txid.to_string());
+    }
+
+    /// The send that `txid` replaced at a higher fee, if any
+    pub fn replaced_txid(&self, txid: &str) -> Option<&str> {
+        self.replacements.get(txid).map(String::as_str)
+    }
+
+    /// Generate an address reserved for change
+    pub fn change_address(&mut self) -> Result<String, String> {
+        let address = self.generate_address()?;
+        self.address_states.insert(address.clone(), AddressState::Change);
+        Ok(address)
+    }
+
     /// Get the current primary address (generates one if none exists)
     pub fn address(&mut self) -> String {
         if self.addresses.is_empty() {
@@ -126,8 +467,7 @@ impl Wallet {
 
     /// Get a new address for read-only contexts (generates deterministically)
     pub fn get_new_address_readonly(&self) -> String {
-        let derived_key = self.derive_key(self.current_index).expect("Key derivation failed");
-        hex::encode(&derived_key)
+        key_address(&self.derive_key(self.current_index).expect("Key derivation failed"))
     }
 
     /// Get master seed (for internal use)
@@ -142,27 +482,38 @@ impl Wallet {
             .map(|(address, _)| address.clone())
     }
 
-    /// Get all generated addresses
+    /// Get all addresses, default account first and imported keys last
     pub fn get_all_addresses(&self) -> Vec<String> {
-        let mut addresses: Vec<_> = self.addresses.iter().collect();
-        addresses.sort_by_key(|(_, index)| *index);
-        addresses.into_iter().map(|(addr, _)| addr.clone()).collect()
+        self.list_accounts().iter()
+            .flat_map(|account| account.addresses())
+            .chain(self.imported_addresses())
+            .collect()
     }
 
-    /// Get the derived private key for a specific address
+    /// Get the derived or imported private key for a specific address
     pub fn get_private_key(&self, address: &str) -> Result<[u8; 32], String> {
-        let index = self.addresses.get(address)
-            .ok_or_else(|| "Address not found in wallet".to_string())?;
+        if let Some(key) = self.imported_keys.get(address) {
+            return parse_private_key(key);
+        }
+        if let Some(index) = self.addresses.get(address) {
+            return self.derive_key(*index);
+        }
         
-        self.derive_key(*index)
+        self.accounts.iter()
+            .find_map(|account| account.addresses.get(address).map(|index| (account.index, *index)))
+            .ok_or_else(|| "Address not found in wallet".to_string())
+            .and_then(|(account, index)| self.derive_account_key(account, index))
     }
 
     /// Get wallet statistics
     pub fn get_stats(&self) -> WalletStats {
         WalletStats {
-            total_addresses: self.addresses.len(),
+            total_addresses: self.addresses.len() + self.accounts.iter().map(|a| a.addresses.len()).sum::<usize>()
+                + self.imported_keys.len(),
             next_index: self.current_index,
             master_fingerprint: hex::encode(&self.master_seed[..8]),
+            accounts: self.accounts.len() + 1,
+            imported_keys: self.imported_keys.len(),
         }
     }
 
@@ -207,6 +558,13 @@ impl Wallet {
     }
 }
 
+/// Address of the key `key`: the hex of its ed25519 public key, so an address
+/// never gives the key away. Derived and imported keys share one scheme, so a
+/// key exported from one wallet imports into another under the same address.
+fn key_address(key: &[u8; 32]) -> String {
+    hex::encode(SigningKey::from_bytes(key).verifying_key().as_bytes())
+}
+
 /// Legacy wallet structure for backwards compatibility
 pub struct LegacyWallet {
     pub address: String,
@@ -218,4 +576,8 @@ pub struct WalletStats {
     pub total_addresses: usize,
     pub next_index: u32,
     pub master_fingerprint: String,
+    /// Number of accounts, including the default one
+    pub accounts: usize,
+    /// Addresses of keys imported rather than derived from the seed
+    pub imported_keys: usize,
 }
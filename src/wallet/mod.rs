@@ -1,2 +1,8 @@
+pub mod consolidate;
+pub mod history;
+pub mod invoice;
 pub mod keychain;
+pub mod offline;
+pub mod policy;
+pub mod registry;
 pub mod signer;
@@ -0,0 +1,261 @@
+use crate::blockchain::block::Transaction;
+use crate::blockchain::multisig::{parse_multisig_address, parse_public_key};
+use crate::blockchain::vault::vault_signer;
+use crate::crypto::signature::verify_signature;
+use crate::wallet::keychain::Wallet;
+use crate::wallet::signer::sign_message;
+use ed25519_dalek::SigningKey;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Default file written by `create-unsigned-tx`
+pub const DEFAULT_UNSIGNED_TX_FILE: &str = "unsigned_tx.json";
+
+/// A transaction moving between an online and an air-gapped machine while it
+/// collects signatures. The wrapped transaction never carries a signature itself;
+/// partial signatures are kept alongside it until enough have been gathered.
+/// Only the keys allowed to spend from the sender may sign: the cosigners of a
+/// multisig address, the owner of a vault (its recovery key for clawbacks), or
+/// the sender itself.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnsignedTransaction {
+    pub transaction: Transaction,
+    /// Number of signatures needed before the transaction can be broadcast, set
+    /// by the sender address
+    pub required_signatures: usize,
+    /// Partial signatures (hex) keyed by the signer's public key (hex)
+    #[serde(default)]
+    pub signatures: BTreeMap<String, String>,
+}
+
+impl UnsignedTransaction {
+    /// Wrap a transaction for offline signing
+    pub fn new(mut transaction: Transaction) -> Self {
+        transaction.signature.clear();
+        UnsignedTransaction {
+            required_signatures: required_signatures(&transaction.from),
+            transaction,
+            signatures: BTreeMap::new(),
+        }
+    }
+
+    /// Whether `public_key` (hex) may sign for the sender
+    pub fn is_authorized(&self, public_key: &str) -> bool {
+        let from = &self.transaction.from;
+        match parse_multisig_address(from) {
+            Some((_, cosigners)) => cosigners.contains(&public_key),
+            None => vault_signer(&self.transaction).unwrap_or(from.as_str()) == public_key,
+        }
+    }
+
+    /// Serialize as pretty JSON
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize unsigned transaction: {}", e))
+    }
+
+    /// Serialize as hex of the compact JSON encoding
+    pub fn to_hex(&self) -> Result<String, String> {
+        serde_json::to_vec(self)
+            .map(hex::encode)
+            .map_err(|e| format!("Failed to serialize unsigned transaction: {}", e))
+    }
+
+    /// Decode from either the JSON or the hex encoding
+    pub fn decode(data: &str) -> Result<Self, String> {
+        let data = data.trim();
+        let bytes = if data.starts_with('{') {
+            data.as_bytes().to_vec()
+        } else {
+            hex::decode(data).map_err(|e| format!("Invalid hex encoding: {}", e))?
+        };
+
+        let unsigned: Self = serde_json::from_slice(&bytes)
+            .map_err(|e| format!("Failed to decode unsigned transaction: {}", e))?;
+        if !unsigned.transaction.signature.is_empty() {
+            return Err("Unsigned transaction must not carry a final signature".to_string());
+        }
+        let required = required_signatures(&unsigned.transaction.from);
+        if unsigned.required_signatures != required {
+            return Err(format!(
+                "{} needs {} signature(s), not {}",
+                unsigned.transaction.from, required, unsigned.required_signatures
+            ));
+        }
+        Ok(unsigned)
+    }
+
+    /// Load from a file holding either encoding
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let data = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+        Self::decode(&data)
+    }
+
+    /// Save as JSON
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        fs::write(&path, self.to_json()?)
+            .map_err(|e| format!("Failed to write {}: {}", path.as_ref().display(), e))
+    }
+
+    /// Sign with the key of one of the wallet's addresses, returning the signer's public key
+    pub fn sign(&mut self, wallet: &Wallet, address: &str) -> Result<String, String> {
+        let signing_key = SigningKey::from_bytes(&wallet.get_private_key(address)?);
+        let public_key = hex::encode(signing_key.verifying_key().as_bytes());
+        if !self.is_authorized(&public_key) {
+            return Err(format!("Key {} may not sign for {}", public_key, self.transaction.from));
+        }
+        let signature = sign_message(&signing_key, self.transaction.signing_message().as_bytes());
+
+        self.signatures.insert(public_key.clone(), hex::encode(signature));
+        Ok(public_key)
+    }
+
+    /// Check every collected signature is by a key allowed to sign and matches
+    /// the transaction
+    pub fn verify(&self) -> Result<(), String> {
+        let message = self.transaction.signing_message();
+
+        for (public_key, signature) in &self.signatures {
+            if !self.is_authorized(public_key) {
+                return Err(format!("Key {} may not sign for {}", public_key, self.transaction.from));
+            }
+            let verifying_key = parse_public_key(public_key)
+                .ok_or_else(|| format!("Invalid public key {}", public_key))?;
+            let signature = hex::decode(signature)
+                .map_err(|e| format!("Invalid signature from {}: {}", public_key, e))?;
+
+            if !verify_signature(&verifying_key, message.as_bytes(), &signature) {
+                return Err(format!("Signature from {} does not match the transaction", public_key));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merge partial signatures collected on another copy of the same transaction,
+    /// returning how many new signatures were added
+    pub fn merge(&mut self, other: &UnsignedTransaction) -> Result<usize, String> {
+        if other.transaction.hash() != self.transaction.hash() {
+            return Err("Cannot merge signatures for a different transaction".to_string());
+        }
+        if other.required_signatures != self.required_signatures {
+            return Err(format!(
+                "Signature threshold mismatch ({} vs {})",
+                self.required_signatures, other.required_signatures
+            ));
+        }
+
+        let before = self.signatures.len();
+        for (public_key, signature) in &other.signatures {
+            self.signatures.entry(public_key.clone()).or_insert_with(|| signature.clone());
+        }
+        Ok(self.signatures.len() - before)
+    }
+
+    /// Whether enough signatures have been collected
+    pub fn is_complete(&self) -> bool {
+        self.signatures.len() >= self.required_signatures
+    }
+
+    /// Produce the fully signed transaction. Multisig transactions carry the
+    /// required signatures in public key order, each after its public key.
+    pub fn finalize(&self) -> Result<Transaction, String> {
+        self.verify()?;
+        if !self.is_complete() {
+            return Err(format!(
+                "Transaction has {} of {} required signatures",
+                self.signatures.len(), self.required_signatures
+            ));
+        }
+
+        let mut transaction = self.transaction.clone();
+        let multisig = parse_multisig_address(&transaction.from).is_some();
+        for (public_key, signature) in self.signatures.iter().take(self.required_signatures) {
+            if multisig {
+                transaction.signature.extend(hex::decode(public_key).map_err(|e| e.to_string())?);
+            }
+            transaction.signature.extend(hex::decode(signature).map_err(|e| e.to_string())?);
+        }
+        Ok(transaction)
+    }
+}
+
+/// Signatures a spend from `address` needs
+fn required_signatures(address: &str) -> usize {
+    parse_multisig_address(address).map_or(1, |(required, _)| required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::multisig::{check_cosignatures, multisig_address, COSIGNATURE_LEN};
+
+    fn unsigned(from: &str) -> UnsignedTransaction {
+        UnsignedTransaction::new(Transaction {
+            from: from.to_string(),
+            to: "bob".to_string(),
+            amount: 25,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: Some(100),
+        })
+    }
+
+    #[test]
+    fn test_sign_and_finalize() {
+        let mut wallet = Wallet::new();
+        let address = wallet.generate_address().unwrap();
+        let mut tx = unsigned(&address);
+        assert!(tx.finalize().is_err());
+
+        // Another wallet's key cannot sign for the sender
+        let mut other = Wallet::new();
+        let other_address = other.generate_address().unwrap();
+        assert!(tx.sign(&other, &other_address).is_err());
+
+        tx.sign(&wallet, &address).unwrap();
+        let decoded = UnsignedTransaction::decode(&tx.to_hex().unwrap()).unwrap();
+        let signed = decoded.finalize().unwrap();
+        assert_eq!(signed.signature.len(), 64);
+        assert_eq!(signed.expiry_height, Some(100));
+    }
+
+    #[test]
+    fn test_merge_partial_signatures() {
+        let (mut alice, mut carol) = (Wallet::new(), Wallet::new());
+        let alice_key = alice.generate_address().unwrap();
+        let carol_key = carol.generate_address().unwrap();
+        let mut mallory = Wallet::new();
+        let mallory_key = mallory.generate_address().unwrap();
+        let address = multisig_address(2, &[alice_key.clone(), carol_key.clone()]).unwrap();
+
+        let base = unsigned(&address);
+        assert_eq!(base.required_signatures, 2);
+        assert!(base.clone().sign(&mallory, &mallory_key).is_err());
+        let (mut first, mut second) = (base.clone(), base.clone());
+        first.sign(&alice, &alice_key).unwrap();
+        second.sign(&carol, &carol_key).unwrap();
+        assert!(first.finalize().is_err());
+
+        assert_eq!(first.merge(&second).unwrap(), 1);
+        assert_eq!(first.merge(&second).unwrap(), 0);
+        let signed = first.finalize().unwrap();
+        assert_eq!(signed.signature.len(), 2 * COSIGNATURE_LEN);
+        assert_eq!(check_cosignatures(&signed), Ok(()));
+
+        // A signature smuggled in from outside the cosigner set is refused
+        let mut forged = base.clone();
+        forged.signatures.insert(mallory_key.clone(), hex::encode([0u8; 64]));
+        assert!(forged.verify().is_err());
+
+        let mut other = unsigned(&address);
+        other.transaction.amount = 26;
+        assert!(first.merge(&other).is_err());
+
+        first.transaction.amount = 26;
+        assert!(first.verify().is_err());
+    }
+}
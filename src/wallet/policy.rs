@@ -0,0 +1,134 @@
+//! Spend limits guarding a wallet against mistyped amounts
+//!
+//! A policy can cap a single transaction and the total sent in any 24 hours,
+//! and ask for confirmation above a threshold. A send that breaks a cap is
+//! refused unless the caps are explicitly overridden; an override, like any
+//! amount above the threshold, must then be confirmed at the prompt or with
+//! `--yes`. Every override is recorded in the alert log.
+
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+use crate::blockchain::amount::format_coins;
+
+/// Window of the daily spend limit, in seconds
+pub const SPEND_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/// Limits set on a wallet; `None` leaves that check off
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpendPolicy {
+    /// Largest amount one transaction may send
+    pub max_per_transaction: Option<u64>,
+    /// Largest total sent within `SPEND_WINDOW_SECS`
+    pub max_daily: Option<u64>,
+    /// Amounts above this need confirmation
+    pub confirm_above: Option<u64>,
+}
+
+/// An amount sent by the wallet, kept while it counts towards the daily limit
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SpendRecord {
+    pub txid: String,
+    pub amount: u64,
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    /// When its pending entry was last replaced by one paying a higher fee
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_bumped_at: Option<u64>,
+}
+
+/// A cap a send would break
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitBreach {
+    PerTransaction { amount: u64, limit: u64 },
+    Daily { spent: u64, amount: u64, limit: u64 },
+}
+
+impl fmt::Display for LimitBreach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitBreach::PerTransaction { amount, limit } =>
+                write!(f, "amount {} exceeds the per-transaction limit of {} coins", format_coins(*amount), format_coins(*limit)),
+            LimitBreach::Daily { spent, amount, limit } =>
+                write!(f, "amount {} on top of {} sent in the last 24 hours exceeds the daily limit of {} coins",
+                    format_coins(*amount), format_coins(*spent), format_coins(*limit)),
+        }
+    }
+}
+
+/// What a send needs before it may go ahead
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendCheck {
+    /// Caps broken; the send needs an override
+    pub breaches: Vec<LimitBreach>,
+    /// Whether the user has to confirm the send
+    pub needs_confirmation: bool,
+}
+
+impl SpendPolicy {
+    /// Whether no limit is set
+    pub fn is_empty(&self) -> bool {
+        self.max_per_transaction.is_none() && self.max_daily.is_none() && self.confirm_above.is_none()
+    }
+
+    /// Check sending `amount` after `spent` within the daily window
+    pub fn check(&self, amount: u64, spent: u64) -> SpendCheck {
+        let mut breaches = Vec::new();
+        if let Some(limit) = self.max_per_transaction && amount > limit {
+            breaches.push(LimitBreach::PerTransaction { amount, limit });
+        }
+        if let Some(limit) = self.max_daily && spent.saturating_add(amount) > limit {
+            breaches.push(LimitBreach::Daily { spent, amount, limit });
+        }
+        let needs_confirmation = !breaches.is_empty() || self.confirm_above.is_some_and(|threshold| amount > threshold);
+        SpendCheck { breaches, needs_confirmation }
+    }
+}
+
+impl fmt::Display for SpendPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = |value: Option<u64>| value.map_or_else(|| "none".to_string(), format_coins);
+        write!(f, "max per transaction {}, max daily {}, confirm above {}",
+            limit(self.max_per_transaction), limit(self.max_daily), limit(self.confirm_above))
+    }
+}
+
+/// Total of the records within the daily window ending at `now`
+pub fn spent_since(records: &[SpendRecord], now: u64) -> u64 {
+    records.iter()
+        .filter(|record| record.time + SPEND_WINDOW_SECS > now)
+        .map(|record| record.amount)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spend_policy_check() {
+        let policy = SpendPolicy { max_per_transaction: Some(100), max_daily: Some(250), confirm_above: Some(50) };
+        assert_eq!(policy.check(40, 0), SpendCheck::default());
+
+        let check = policy.check(60, 0);
+        assert!(check.breaches.is_empty());
+        assert!(check.needs_confirmation);
+
+        let check = policy.check(120, 200);
+        assert_eq!(check.breaches, vec![
+            LimitBreach::PerTransaction { amount: 120, limit: 100 },
+            LimitBreach::Daily { spent: 200, amount: 120, limit: 250 },
+        ]);
+        assert!(check.needs_confirmation);
+
+        // Nothing is checked without limits
+        assert_eq!(SpendPolicy::default().check(u64::MAX, u64::MAX), SpendCheck::default());
+
+        let records = vec![
+            SpendRecord { txid: "a".to_string(), amount: 70, time: 1_000, fee_bumped_at: None },
+            SpendRecord { txid: "b".to_string(), amount: 30, time: 1_000 + SPEND_WINDOW_SECS, fee_bumped_at: None },
+        ];
+        assert_eq!(spent_since(&records, 1_000 + SPEND_WINDOW_SECS - 1), 100);
+        assert_eq!(spent_since(&records, 1_000 + SPEND_WINDOW_SECS), 30);
+    }
+}
@@ -0,0 +1,146 @@
+//! Named wallets kept in the data directory
+//!
+//! Each named wallet is a `<name>.json` file in `<data_dir>/wallets`. One of
+//! them can be made the default for commands that do not pass `--wallet`;
+//! without a default the node keeps using the unnamed `wallet.json`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::wallet::keychain::Wallet;
+
+/// Directory inside the data directory holding named wallets
+pub const WALLETS_DIR: &str = "wallets";
+
+/// File in the wallets directory naming the default wallet
+const DEFAULT_WALLET_FILE: &str = "default";
+
+/// Named wallets in one data directory
+pub struct WalletRegistry {
+    dir: PathBuf,
+}
+
+impl WalletRegistry {
+    pub fn in_data_dir<P: AsRef<Path>>(data_dir: P) -> Self {
+        WalletRegistry { dir: data_dir.as_ref().join(WALLETS_DIR) }
+    }
+
+    /// Wallet names may only use letters, digits, `-` and `_`, so they are safe
+    /// as file names and in RPC paths
+    pub fn validate_name(name: &str) -> Result<(), String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Invalid wallet name '{}': use letters, digits, '-' and '_'", name));
+        }
+        Ok(())
+    }
+
+    /// File holding a named wallet
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).exists()
+    }
+
+    /// Create and save a new wallet with its first address, refusing to
+    /// overwrite an existing one
+    pub fn create(&self, name: &str) -> Result<Wallet, String> {
+        Self::validate_name(name)?;
+        if self.exists(name) {
+            return Err(format!("Wallet '{}' already exists", name));
+        }
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create wallets directory: {}", e))?;
+
+        let mut wallet = Wallet::new();
+        wallet.generate_address()?;
+        wallet.save_to_file(self.path(name))?;
+        Ok(wallet)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Wallet, String> {
+        Self::validate_name(name)?;
+        if !self.exists(name) {
+            return Err(format!("Wallet '{}' not found; create it with create-wallet", name));
+        }
+        Wallet::load_from_file(self.path(name))
+    }
+
+    /// Names of all wallets, sorted
+    pub fn list(&self) -> Result<Vec<String>, String> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = fs::read_dir(&self.dir)
+            .map_err(|e| format!("Failed to read wallets directory: {}", e))?;
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                Some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Every wallet by name
+    pub fn load_all(&self) -> Result<HashMap<String, Wallet>, String> {
+        self.list()?.into_iter()
+            .map(|name| self.load(&name).map(|wallet| (name, wallet)))
+            .collect()
+    }
+
+    /// Wallet used when no `--wallet` is given
+    pub fn default_wallet(&self) -> Option<String> {
+        let name = fs::read_to_string(self.dir.join(DEFAULT_WALLET_FILE)).ok()?;
+        let name = name.trim();
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    /// Make an existing wallet the default
+    pub fn set_default(&self, name: &str) -> Result<(), String> {
+        if !self.exists(name) {
+            return Err(format!("Wallet '{}' not found; create it with create-wallet", name));
+        }
+        fs::write(self.dir.join(DEFAULT_WALLET_FILE), name)
+            .map_err(|e| format!("Failed to save default wallet: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_wallets() {
+        let dir = std::env::temp_dir().join(format!("rust_chain_wallets_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let registry = WalletRegistry::in_data_dir(&dir);
+
+        assert!(registry.create("../escape").is_err());
+        let savings = registry.create("savings").unwrap();
+        registry.create("spending").unwrap();
+        assert!(registry.create("savings").is_err());
+        assert_eq!(registry.list().unwrap(), vec!["savings", "spending"]);
+
+        assert_eq!(registry.default_wallet(), None);
+        assert!(registry.set_default("missing").is_err());
+        registry.set_default("savings").unwrap();
+        assert_eq!(registry.default_wallet().as_deref(), Some("savings"));
+
+        let loaded = registry.load("savings").unwrap();
+        assert_eq!(loaded.get_seed_phrase(), savings.get_seed_phrase());
+        // The first address is saved with the wallet
+        assert_eq!(loaded.get_all_addresses().len(), 1);
+        assert_eq!(loaded.get_current_address(), savings.get_current_address());
+        assert_eq!(registry.load_all().unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
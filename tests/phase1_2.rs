@@ -1,11 +1,14 @@
+use rust_chain::blockchain::amount::MAX_MONEY;
 use rust_chain::blockchain::block::{Block, Transaction};
-use rust_chain::blockchain::chain::Chain;
-use rust_chain::blockchain::genesis::genesis_block;
-use rust_chain::blockchain::state::{State, UTXO};
+use rust_chain::blockchain::chain::{Chain, MAX_FUTURE_BLOCK_TIME};
+use rust_chain::blockchain::genesis::{genesis_block, COINBASE_ADDRESS};
+use rust_chain::blockchain::state::{State, UTXO, UTXOState, COINBASE_MATURITY};
 use rust_chain::wallet::keychain::Wallet;
 use rust_chain::wallet::signer::sign_message;
 use rust_chain::crypto::keys::generate_keypair;
+use rust_chain::clock::MockClock;
 use rust_chain::verify_signature;
+use std::sync::Arc;
 
 #[test]
 fn test_genesis_block() {
@@ -24,11 +27,13 @@ fn test_chain_add_block() {
         to: "bob".to_string(),
         amount: 10,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
-    let prev_hash = chain.blocks.last().unwrap().header.hash.clone();
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
     let block = Block::new(prev_hash, vec![tx], 1, 12345, 1);
     assert!(chain.add_block(block));
-    assert_eq!(chain.blocks.len(), 2);
+    assert_eq!(chain.block_count(), 2);
 }
 
 #[test]
@@ -66,3 +71,160 @@ fn test_utxo_state() {
     assert_eq!(spent.unwrap().amount, 100);
     assert_eq!(state.get_balance("alice"), 0);
 }
+
+#[test]
+fn test_coinbase_maturity() {
+    let mut chain = Chain::new();
+    let reward = Transaction {
+        from: COINBASE_ADDRESS.to_string(),
+        to: "miner".to_string(),
+        amount: 50,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(chain.add_block(Block::new(prev_hash, vec![reward], 0, 12345, 1)));
+
+    let state = UTXOState::from_blocks(chain.get_blocks()).unwrap();
+    assert_eq!(state.get_balance("miner"), 0);
+    assert_eq!(state.get_immature_balance("miner"), 50);
+
+    // Spending the fresh reward is refused
+    let spend = Transaction {
+        from: "miner".to_string(),
+        to: "bob".to_string(),
+        amount: 20,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    let early = Block::new(prev_hash, vec![spend.clone()], 0, 12346, 2);
+    assert!(!chain.add_block(early));
+
+    // Pad the chain until the reward matures
+    while chain.block_count() < 1 + COINBASE_MATURITY {
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        let height = chain.block_count();
+        assert!(chain.add_block(Block::new(prev_hash, vec![], 0, 12345 + height, height)));
+    }
+
+    let state = UTXOState::from_blocks(chain.get_blocks()).unwrap();
+    assert_eq!(state.get_balance("miner"), 50);
+    assert_eq!(state.get_immature_balance("miner"), 0);
+
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    let height = chain.block_count();
+    assert!(chain.add_block(Block::new(prev_hash, vec![spend], 0, 12345 + height, height)));
+}
+
+#[test]
+fn test_transaction_height_bounds() {
+    let mut chain = Chain::new();
+    let locked = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 10,
+        signature: vec![],
+        lock_height: Some(2),
+        expiry_height: Some(2),
+    };
+
+    // Locked until height 2, so block 1 cannot include it
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), vec![locked.clone()], 0, 12345, 1)));
+    assert!(chain.add_block(Block::new(prev_hash, vec![], 0, 12345, 1)));
+
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(chain.add_block(Block::new(prev_hash, vec![locked.clone()], 0, 12346, 2)));
+
+    // Past its expiry height the transaction is refused
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(!chain.add_block(Block::new(prev_hash, vec![locked.clone()], 0, 12347, 3)));
+
+    // The bounds are part of the signed message, and unset bounds leave it unchanged
+    let plain = Transaction { lock_height: None, expiry_height: None, ..locked.clone() };
+    assert_eq!(plain.signing_message(), "alice:bob:10");
+    assert_ne!(locked.signing_message(), plain.signing_message());
+    assert_ne!(locked.hash(), plain.hash());
+}
+
+#[test]
+fn test_duplicate_transactions_rejected() {
+    let mut chain = Chain::new();
+    let tx = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 10,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+
+    // The same transaction twice in one block
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), vec![tx.clone(), tx.clone()], 0, 12345, 1)));
+    assert!(chain.add_block(Block::new(prev_hash, vec![tx.clone()], 0, 12345, 1)));
+
+    // A transaction that is already confirmed
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(!chain.add_block(Block::new(prev_hash, vec![tx], 0, 12346, 2)));
+}
+
+#[test]
+fn test_amounts_above_max_money_rejected() {
+    let mut chain = Chain::new();
+    let tx = |from: &str, amount| Transaction {
+        from: from.to_string(),
+        to: "miner".to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), vec![tx("alice", MAX_MONEY + 1)], 0, 12345, 1)));
+
+    // Rewards that are each in range but add up to more than the maximum
+    let rewards = vec![tx(COINBASE_ADDRESS, MAX_MONEY), tx(COINBASE_ADDRESS, 1)];
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), rewards, 0, 12345, 1)));
+
+    // Fees are never paid out, so a coinbase may claim the subsidy and no more
+    let subsidy = chain.params().subsidy_at(1);
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), vec![tx(COINBASE_ADDRESS, MAX_MONEY)], 0, 12345, 1)));
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), vec![tx(COINBASE_ADDRESS, subsidy + 1)], 0, 12345, 1)));
+    assert!(chain.add_block(Block::new(prev_hash, vec![tx(COINBASE_ADDRESS, subsidy)], 0, 12345, 1)));
+}
+
+#[test]
+fn test_future_timestamps_follow_clock() {
+    let clock = MockClock::new(1_000_000);
+    let mut chain = Chain::new();
+    chain.set_clock(Arc::new(clock.clone()));
+
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    let ahead = 1_000_000 + MAX_FUTURE_BLOCK_TIME + 1;
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), vec![], 0, ahead, 1)));
+
+    // The same block is fine once the clock has caught up
+    clock.advance(1);
+    assert!(chain.add_block(Block::new(prev_hash, vec![], 0, ahead, 1)));
+}
+
+#[test]
+fn test_timestamps_not_before_median_time_past() {
+    let mut chain = Chain::new();
+    chain.set_clock(Arc::new(MockClock::new(1_000_000)));
+
+    for (height, timestamp) in [(1, 1_000), (2, 2_000), (3, 3_000)] {
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        assert!(chain.add_block(Block::new(prev_hash, vec![], 0, timestamp, height)));
+    }
+    assert_eq!(chain.median_time_past().unwrap(), 2_000);
+
+    // Earlier than the parent is allowed, earlier than the median is not
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(!chain.add_block(Block::new(prev_hash.clone(), vec![], 0, 1_999, 4)));
+    assert!(chain.add_block(Block::new(prev_hash, vec![], 0, 2_000, 4)));
+}
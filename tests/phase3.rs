@@ -1,7 +1,8 @@
 use rust_chain::blockchain::block::{Block, Transaction};
 use rust_chain::blockchain::chain::Chain;
-use rust_chain::storage::{block_store::BlockStore, db::Database};
-use rust_chain::cli::{CLI, BlockchainCommands};
+use rust_chain::consensus::params::ChainParams;
+use rust_chain::storage::{block_store::BlockStore, db::Database, SCHEMA_VERSION};
+use rust_chain::cli::{CLI, BlockQuery, BlockchainCommands};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 fn get_unique_test_path(base_name: &str) -> String {
@@ -42,6 +43,8 @@ fn test_block_store() {
         to: "bob".to_string(),
         amount: 50,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let block = Block::new("prev_hash".to_string(), vec![tx], 0, 0, 1);
@@ -64,6 +67,323 @@ fn test_block_store() {
     assert!(!store.block_exists("nonexistent").unwrap());
 }
 
+#[test]
+fn test_block_store_cache() {
+    let test_path = get_unique_test_path("test_block_store_cache");
+    let store = BlockStore::new_with_path(&test_path).expect("Failed to create block store");
+    
+    let block = Block::new("prev_hash".to_string(), vec![], 0, 0, 1);
+    store.store_block(&block).unwrap();
+    
+    // Stored blocks are served from the cache
+    assert!(store.get_block(&block.header.hash).unwrap().is_some());
+    assert_eq!(store.get_header_by_height(1).unwrap().unwrap().hash, block.header.hash);
+    let (blocks, headers) = store.cache_stats();
+    assert_eq!(blocks.hits, 1);
+    assert_eq!(headers.hits, 1);
+    assert!(blocks.used_bytes > 0);
+    
+    // With no room in the cache every read goes to disk
+    store.set_cache_capacity(0, 0);
+    assert!(store.get_block(&block.header.hash).unwrap().is_some());
+    let (blocks, _) = store.cache_stats();
+    assert_eq!(blocks.entries, 0);
+    assert_eq!(blocks.misses, 1);
+}
+
+#[test]
+fn test_scan_blocks_and_block_query() {
+    let test_path = get_unique_test_path("test_scan_blocks");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    for height in 1..=5 {
+        let tx = Transaction {
+            from: "alice".to_string(),
+            to: format!("user{}", height),
+            amount: height * 10,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        assert!(chain.add_block(Block::new(prev_hash, vec![tx], 0, height, height)));
+    }
+    
+    // Ranges are read from the block store in height order and clamped to the tip
+    let mut heights = Vec::new();
+    chain.scan_blocks(2, 100, |block| heights.push(block.header.height)).unwrap();
+    assert_eq!(heights, vec![2, 3, 4, 5]);
+    
+    let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let query = BlockQuery::parse(&args(&["--last", "2", "--min-amount", "0.0000004", "--summary"])).unwrap();
+    assert_eq!(query.height_range(5), (4, 5));
+    assert!(query.summary);
+    
+    let (from, to) = query.height_range(5);
+    let mut matching = Vec::new();
+    chain.scan_blocks(from, to, |block| {
+        matching.extend(block.transactions.iter().filter(|tx| query.matches(tx)).map(|tx| tx.amount));
+    }).unwrap();
+    assert_eq!(matching, vec![40, 50]);
+    
+    let query = BlockQuery::parse(&args(&["--from", "1", "--to", "3", "--address", "user2"])).unwrap();
+    assert_eq!(query.height_range(5), (1, 3));
+    assert!(BlockQuery::parse(&args(&["--last", "2", "--from", "1"])).is_err());
+    assert!(BlockQuery::parse(&args(&["--to"])).is_err());
+}
+
+#[test]
+fn test_chain_reload_from_metadata() {
+    let test_path = get_unique_test_path("test_chain_reload");
+    let (tip_hash, work) = {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        for height in 1..=3 {
+            let prev_hash = chain.tip().unwrap().header.hash.clone();
+            assert!(chain.add_block(Block::new(prev_hash, vec![], 0, height, height)));
+        }
+        (chain.tip().unwrap().header.hash.clone(), chain.chain_work())
+    };
+    
+    // The tip and cumulative work come from the stored metadata record
+    let chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reload chain");
+    let metadata = chain.metadata();
+    assert_eq!(metadata.tip_hash, tip_hash);
+    assert_eq!(metadata.height, 3);
+    assert_eq!(chain.chain_work(), work);
+    assert_eq!(chain.block_count(), 4);
+    
+    let block = chain.block_at(1).unwrap().unwrap();
+    assert_eq!(chain.find_block(&block.header.hash).unwrap().unwrap().header.height, 1);
+    assert_eq!(chain.block_hash_at(3).unwrap(), Some(tip_hash));
+    assert!(chain.block_at(4).unwrap().is_none());
+}
+
+#[test]
+fn test_memory_window_pages_older_blocks() {
+    let test_path = get_unique_test_path("test_memory_window");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    chain.set_memory_window(2);
+    for height in 1..=5 {
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        assert!(chain.add_block(Block::new(prev_hash, vec![], 0, height, height)));
+    }
+    
+    // Only the window stays in memory; older blocks are paged from the store
+    let recent: Vec<u64> = chain.get_blocks().iter().map(|b| b.header.height).collect();
+    assert_eq!(recent, vec![4, 5]);
+    assert_eq!(chain.block_count(), 6);
+    assert_eq!(chain.block_at(1).unwrap().unwrap().header.height, 1);
+    
+    let mut heights = Vec::new();
+    chain.scan_blocks(0, 5, |block| heights.push(block.header.height)).unwrap();
+    assert_eq!(heights, vec![0, 1, 2, 3, 4, 5]);
+    assert_eq!(chain.blocks_in_range(2, 4).unwrap().len(), 3);
+    
+    // A branch shares the stored history below its in-memory blocks
+    let tip_before = chain.block_hash_at(4).unwrap().unwrap();
+    let branch = chain.branch_at(&tip_before).unwrap();
+    assert_eq!(branch.height(), 4);
+    assert_eq!(branch.block_hash_at(2).unwrap(), chain.block_hash_at(2).unwrap());
+    assert!(chain.branch_at(&chain.block_hash_at(1).unwrap().unwrap()).is_none());
+}
+
+#[test]
+fn test_disconnect_with_undo_data() {
+    let test_path = get_unique_test_path("test_undo_data");
+    let payment = |from: &str, to: &str, amount| Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let block_on = |chain: &Chain, txs, timestamp| {
+        let tip = chain.tip().unwrap();
+        Block::new(tip.header.hash.clone(), txs, 0, timestamp, tip.header.height + 1)
+    };
+    let balance = |chain: &Chain, address: &str| chain.utxo_state().unwrap().get_balance(address);
+    
+    let (block2, fork_hash) = {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let block1 = block_on(&chain, vec![payment("genesis", "carol", 100)], 1);
+        assert!(chain.add_block(block1.clone()));
+        let block2 = block_on(&chain, vec![payment("carol", "dave", 40)], 2);
+        assert!(chain.add_block(block2.clone()));
+        assert_eq!(balance(&chain, "dave"), 40);
+        
+        // Invalidating block 2 restores the balances from its undo record
+        let disconnected = chain.invalidate_block(&block2.header.hash).unwrap();
+        assert_eq!(disconnected.len(), 1);
+        assert_eq!(chain.height(), 1);
+        assert_eq!(balance(&chain, "carol"), 100);
+        assert_eq!(balance(&chain, "dave"), 0);
+        assert!(chain.get_transaction(&block2.transactions[0].hash()).unwrap().is_none());
+        assert!(!chain.add_block(block2.clone()));
+        
+        // Reorganize onto a two-block branch from the same fork point
+        let branch_a = block_on(&chain, vec![payment("carol", "erin", 10)], 3);
+        assert!(chain.add_block(branch_a));
+        let fork = chain.block_at(1).unwrap().unwrap();
+        let branch_b1 = Block::new(fork.header.hash.clone(), vec![payment("carol", "frank", 20)], 0, 4, 2);
+        let branch_b2 = Block::new(branch_b1.header.hash.clone(), vec![], 0, 5, 3);
+        let disconnected = chain.reorganize(&fork.header.hash, vec![branch_b1, branch_b2.clone()]).unwrap();
+        assert_eq!(disconnected.len(), 1);
+        assert_eq!(chain.tip().unwrap().header.hash, branch_b2.header.hash);
+        assert_eq!(balance(&chain, "erin"), 0);
+        assert_eq!(balance(&chain, "frank"), 20);
+        (block2, fork.header.hash)
+    };
+    
+    // The new tip and the invalid marker survive a restart
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reload chain");
+    chain.set_params(ChainParams::regtest());
+    assert_eq!(chain.height(), 3);
+    assert!(chain.is_invalid(&block2.header.hash));
+    
+    // A rejected branch leaves the current chain in place
+    let tip_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(chain.reorganize(&fork_hash, vec![block2]).is_err());
+    assert_eq!(chain.tip().unwrap().header.hash, tip_hash);
+    assert_eq!(chain.metadata().height, 3);
+}
+
+#[test]
+fn test_legacy_records_read_and_migrate() {
+    let test_path = get_unique_test_path("test_migrate_db");
+    let payment = Transaction {
+        from: "genesis".to_string(),
+        to: "carol".to_string(),
+        amount: 100,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let tip_hash = {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let genesis_hash = chain.tip().unwrap().header.hash.clone();
+        assert!(chain.add_block(Block::new(genesis_hash, vec![payment.clone()], 0, 1, 1)));
+        assert_eq!(chain.schema_version().unwrap(), SCHEMA_VERSION);
+        chain.tip().unwrap().header.hash.clone()
+    };
+    
+    // Rewrite the stores as they were before versioning: plain JSON, no version key
+    for (path, prefixes) in [(test_path.clone(), &["block:", "header:", "undo:"][..]), (format!("{}/transactions", test_path), &["tx:", "tx_index:"][..])] {
+        let db = Database::new_with_path(&path).expect("Failed to open database");
+        for prefix in prefixes {
+            for key in db.keys_with_prefix(prefix).unwrap() {
+                let data = db.get(&key).unwrap().unwrap();
+                db.put(key, data[1..].to_vec()).unwrap();
+            }
+        }
+        db.delete("meta:schema").unwrap();
+    }
+    
+    let chain = Chain::new_persistent_with_path(&test_path).expect("Failed to open legacy chain");
+    assert_eq!(chain.schema_version().unwrap(), 0);
+    let tx_hash = payment.hash();
+    assert_eq!(chain.find_block(&tip_hash).unwrap().unwrap().transactions[0].hash(), tx_hash);
+    assert_eq!(chain.get_transaction(&tx_hash).unwrap().unwrap().hash(), tx_hash);
+    
+    let backup_path = format!("{}_backup", test_path);
+    let migration = chain.migrate_schema(&backup_path).unwrap();
+    assert_eq!((migration.from_version, migration.to_version), (0, SCHEMA_VERSION));
+    assert!(migration.records_migrated > 0);
+    assert_eq!(migration.backup_path.as_deref(), Some(backup_path.as_str()));
+    assert!(std::path::Path::new(&format!("{}/transactions", backup_path)).exists());
+    assert_eq!(chain.schema_version().unwrap(), SCHEMA_VERSION);
+    assert_eq!(chain.get_transaction(&tx_hash).unwrap().unwrap().hash(), tx_hash);
+    
+    // A current store is left alone
+    assert_eq!(chain.migrate_schema(&backup_path).unwrap().backup_path, None);
+}
+
+#[test]
+fn test_invalidate_and_reconsider_descendants() {
+    let test_path = get_unique_test_path("test_reconsider_block");
+    let block_on = |chain: &Chain, timestamp| {
+        let tip = chain.tip().unwrap();
+        Block::new(tip.header.hash.clone(), vec![], 0, timestamp, tip.header.height + 1)
+    };
+    
+    let (block1, block3) = {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let block1 = block_on(&chain, 1);
+        assert!(chain.add_block(block1.clone()));
+        let block2 = block_on(&chain, 2);
+        assert!(chain.add_block(block2.clone()));
+        let block3 = block_on(&chain, 3);
+        assert!(chain.add_block(block3.clone()));
+        
+        // Invalidating block 1 marks the blocks built on it as well
+        assert_eq!(chain.invalidate_block(&block1.header.hash).unwrap().len(), 3);
+        assert_eq!(chain.height(), 0);
+        assert!(chain.is_invalid(&block2.header.hash));
+        assert!(chain.is_invalid(&block3.header.hash));
+        
+        let replacement = block_on(&chain, 4);
+        assert!(chain.add_block(replacement));
+        (block1, block3)
+    };
+    
+    // Reconsidering any block of the branch clears the whole branch and switches
+    // back to it, since it has more work than the replacement
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reload chain");
+    chain.set_params(ChainParams::regtest());
+    assert!(chain.is_invalid(&block3.header.hash));
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash;
+    assert!(chain.reconsider_block(&genesis_hash).is_err());
+    let (connected, disconnected) = chain.reconsider_block(&block3.header.hash).unwrap();
+    assert_eq!(connected.len(), 3);
+    assert_eq!(disconnected.len(), 1);
+    assert!(!chain.is_invalid(&block1.header.hash));
+    assert_eq!(chain.tip().unwrap().header.hash, block3.header.hash);
+    assert_eq!(chain.metadata().height, 3);
+}
+
+#[test]
+fn test_rejected_blocks_are_remembered() {
+    let test_path = get_unique_test_path("test_rejected_blocks");
+    let early_spend = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 10,
+        signature: vec![],
+        lock_height: Some(100),
+        expiry_height: None,
+    };
+    
+    let (rejected, invalidated) = {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let tip = chain.tip().unwrap().clone();
+        let rejected = Block::new(tip.header.hash.clone(), vec![early_spend], 0, 1, 1);
+        let reason = chain.check_block_rules(&rejected).unwrap_err();
+        chain.record_rejected_block(&rejected.header.hash, &reason).unwrap();
+        assert!(!chain.validate_block(&rejected));
+        
+        let invalidated = Block::new(tip.header.hash.clone(), vec![], 0, 2, 1);
+        assert!(chain.add_block(invalidated.clone()));
+        chain.invalidate_block(&invalidated.header.hash).unwrap();
+        (rejected, invalidated)
+    };
+    
+    // Both survive a restart, the rejected block with the rule it broke
+    let chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reload chain");
+    let reason = chain.rejected_reason(&rejected.header.hash).unwrap();
+    assert_eq!(chain.check_block_rules(&rejected).unwrap_err(), reason);
+    assert!(chain.rejected_reason(&invalidated.header.hash).is_none());
+    let listed: Vec<String> = chain.invalid_block_list().into_iter().map(|(hash, _)| hash).collect();
+    let mut expected = vec![rejected.header.hash.clone(), invalidated.header.hash.clone()];
+    expected.sort();
+    assert_eq!(listed, expected);
+}
+
 #[test]
 fn test_cli_initialization() {
     let test_path = get_unique_test_path("test_cli_initialization");
@@ -78,6 +398,8 @@ fn test_cli_initialization() {
         to: "test_receiver".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     assert!(cli.add_block(vec![tx]).is_ok());
@@ -90,7 +412,7 @@ fn test_chain_with_storage() {
     let store = BlockStore::new_with_path(&test_path).expect("Failed to create block store");
     
     // Store genesis block
-    let genesis = &chain.blocks[0];
+    let genesis = &chain.block_at(0).unwrap().unwrap();
     assert!(store.store_block(genesis).is_ok());
     
     // Create and add a new block
@@ -99,6 +421,8 @@ fn test_chain_with_storage() {
         to: "bob".to_string(),
         amount: 30,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let new_block = Block::new(genesis.header.hash.clone(), vec![tx], 0, 0, 1);
@@ -110,3 +434,60 @@ fn test_chain_with_storage() {
     assert_eq!(stored_block.header.hash, new_block.header.hash);
     assert_eq!(stored_block.transactions.len(), 1);
 }
+
+#[test]
+fn test_node_backup_and_restore() {
+    use rust_chain::cli::AnalyticsCommands;
+    use rust_chain::storage::backup::{self, BackupManifest, ExternalFile};
+    
+    let test_path = get_unique_test_path("test_node_backup");
+    let backup_path = format!("{}_backup", test_path);
+    let restored_path = format!("{}_restored", test_path);
+    
+    let mut cli = CLI::new_with_path(&test_path).expect("Failed to create CLI");
+    cli.chain.set_params(ChainParams::regtest());
+    let tx = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 30,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = cli.chain.tip().unwrap().header.hash.clone();
+    assert!(cli.chain.add_block(Block::new(prev_hash, vec![tx], 0, 1, 1)));
+    let tip_hash = cli.chain.tip().unwrap().header.hash.clone();
+    
+    let manifest = cli.backup_node(&backup_path).expect("Failed to back up node");
+    assert_eq!((manifest.height, manifest.tip_hash.as_str()), (1, tip_hash.as_str()));
+    assert!(manifest.files.iter().any(|file| file.path == "data/wallet.json"));
+    assert!(cli.backup_node(&backup_path).is_err(), "existing backups are not overwritten");
+    drop(cli);
+    
+    backup::restore(&backup_path, &restored_path, format!("{}_previous", restored_path)).expect("Failed to restore");
+    let chain = Chain::new_persistent_with_path(&restored_path).expect("Failed to open restored chain");
+    assert_eq!(chain.tip().unwrap().header.hash, tip_hash);
+    assert!(chain.get_transaction(&chain.tip().unwrap().transactions[0].hash()).unwrap().is_some());
+    
+    // A damaged file is caught before the data directory is touched
+    std::fs::write(format!("{}/data/wallet.json", backup_path), "{}").unwrap();
+    let err = backup::restore(&backup_path, &restored_path, format!("{}_previous2", restored_path)).unwrap_err();
+    assert!(err.contains("data/wallet.json"));
+    assert!(!std::path::Path::new(&format!("{}_previous2", restored_path)).exists());
+    
+    // Backups written by a newer storage schema are refused
+    let mut manifest = BackupManifest::load(&backup_path).unwrap();
+    manifest.schema_version = SCHEMA_VERSION + 1;
+    assert!(manifest.verify(&backup_path).unwrap_err().contains("schema"));
+    
+    // Paths that would leave the directories they are joined to are refused
+    let manifest = BackupManifest::load(&backup_path).unwrap();
+    let mut escaping = manifest.clone();
+    escaping.files[0].path = "data/../../escape".to_string();
+    assert!(escaping.verify(&backup_path).unwrap_err().contains("leaves its directory"));
+    for (name, restore_path) in [("../escape", "wallet.json"), ("wallet.json", "/tmp/wallet.json"), ("wallet.json", "../wallet.json")] {
+        let mut escaping = manifest.clone();
+        escaping.external_files.push(ExternalFile { name: name.to_string(), restore_path: restore_path.to_string() });
+        assert!(escaping.verify(&backup_path).is_err(), "{} restored to {} must be refused", name, restore_path);
+    }
+}
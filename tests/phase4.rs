@@ -1,5 +1,6 @@
 use rust_chain::network::protocol::{
-    NetworkMessage, MessageType, PeerInfo, PROTOCOL_VERSION, MAGIC_BYTES
+    NetworkMessage, MessageType, PeerInfo, PeerCapabilities, Features, Services, InventoryItem,
+    negotiate_version, LOCAL_SERVICES, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, MAGIC_BYTES
 };
 use rust_chain::network::server::NetworkServer;
 use rust_chain::blockchain::chain::Chain;
@@ -47,6 +48,9 @@ fn test_peer_info_serialization() {
         node_id: "test_node_123".to_string(),
         last_seen: 1640995200,
         chain_height: 42,
+        capabilities: PeerCapabilities::default(),
+        services: Services::NONE,
+        listen_port: 8333,
     };
     
     let message = NetworkMessage::new(MessageType::Peers(vec![peer.clone()]));
@@ -71,6 +75,8 @@ fn test_block_message_serialization() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![1, 2, 3, 4],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let block = Block::new(
@@ -100,14 +106,25 @@ fn test_handshake_message() {
         version: PROTOCOL_VERSION,
         node_id: "test_node".to_string(),
         chain_height: 10,
+        public_key: "ab".repeat(32),
+        nonce: "nonce".to_string(),
+        signature: "cd".repeat(64),
+        min_version: MIN_PROTOCOL_VERSION,
+        features: Features::TX_RELAY,
+        services: LOCAL_SERVICES,
+        listen_port: 8333,
     };
     
     let message = NetworkMessage::new(handshake);
     let bytes = message.to_bytes().unwrap();
     let deserialized = NetworkMessage::from_bytes(&bytes).unwrap();
     
-    if let MessageType::Handshake { version, node_id, chain_height } = deserialized.message_type {
+    if let MessageType::Handshake { version, min_version, features, services, node_id, chain_height, listen_port, .. } = deserialized.message_type {
         assert_eq!(version, PROTOCOL_VERSION);
+        assert_eq!(listen_port, 8333);
+        assert_eq!(min_version, MIN_PROTOCOL_VERSION);
+        assert_eq!(features, Features::TX_RELAY);
+        assert!(services.contains(Services::ARCHIVE));
         assert_eq!(node_id, "test_node");
         assert_eq!(chain_height, 10);
     } else {
@@ -162,3 +179,167 @@ fn test_chain_info_message() {
         panic!("Expected ChainInfo message type");
     }
 }
+
+#[test]
+fn test_version_and_feature_negotiation() {
+    // Overlapping ranges settle on the highest shared version
+    assert_eq!(negotiate_version(1, 3, 2, 5), Some(3));
+    assert_eq!(negotiate_version(1, 2, 0, 1), Some(1)); // single-version peer
+    assert_eq!(negotiate_version(2, 3, 0, 1), None);
+
+    let caps = PeerCapabilities::negotiate(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION + 5,
+        Features::TX_RELAY.union(Features::COMPACT_BLOCKS)).unwrap();
+    assert_eq!(caps.version, PROTOCOL_VERSION);
+    // Only features both sides support survive
+    assert!(caps.features.contains(Features::TX_RELAY));
+    assert!(!caps.features.contains(Features::COMPACT_BLOCKS));
+    assert!(PeerCapabilities::negotiate(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2, Features::NONE).is_err());
+
+    // Transaction relay is gated on the negotiated feature
+    let tx_inv = MessageType::Inv(vec![InventoryItem::transaction("abc".to_string())]);
+    let block_inv = MessageType::Inv(vec![InventoryItem::block("def".to_string())]);
+    assert!(caps.allows(&tx_inv));
+    let no_relay = PeerCapabilities::default();
+    assert!(!no_relay.allows(&tx_inv));
+    assert!(no_relay.allows(&block_inv));
+    assert!(no_relay.allows(&MessageType::Ping));
+}
+
+#[test]
+fn test_legacy_handshake_defaults() {
+    // Version 1 handshakes carry no range or features
+    let legacy = r#"{"Handshake":{"version":1,"node_id":"n","chain_height":0,"public_key":"","nonce":"","signature":""}}"#;
+    let message: MessageType = serde_json::from_str(legacy).unwrap();
+    if let MessageType::Handshake { version, min_version, features, services, listen_port, .. } = message {
+        assert_eq!(min_version, 0);
+        assert_eq!(listen_port, 0);
+        assert_eq!(features, Features::NONE);
+        assert_eq!(services, Services::NONE);
+        let caps = PeerCapabilities::negotiate(min_version, version, features).unwrap();
+        assert_eq!(caps.version, 1);
+    } else {
+        panic!("Expected Handshake message type");
+    }
+}
+
+#[test]
+fn test_sync_prefers_archive_peers_for_deep_history() {
+    let peer = |port: u16, chain_height: u64, services: Services| PeerInfo {
+        address: "127.0.0.1".to_string(),
+        port,
+        node_id: format!("node_{}", port),
+        last_seen: 0,
+        chain_height,
+        capabilities: PeerCapabilities::default(),
+        services,
+        listen_port: port,
+    };
+    let peers = vec![
+        peer(8001, 500, Services::FULL_BLOCKS),
+        peer(8002, 450, LOCAL_SERVICES),
+    ];
+
+    // Far behind: fetch history from the archive peer
+    assert_eq!(NetworkServer::select_sync_peer(&peers, 10).unwrap().port, 8002);
+    // Close to the tip: the highest peer is fine
+    assert_eq!(NetworkServer::select_sync_peer(&peers, 480).unwrap().port, 8001);
+    // No archive peer: fall back to the highest one
+    assert_eq!(NetworkServer::select_sync_peer(&peers[..1], 10).unwrap().port, 8001);
+    assert!(NetworkServer::select_sync_peer(&[], 10).is_none());
+}
+
+#[test]
+fn test_block_propagation_tracking() {
+    use rust_chain::network::{PeerStatsDb, PropagationTracker};
+
+    let chain = Chain::new();
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash.clone();
+
+    let mut tracker = PropagationTracker::new();
+    tracker.record_mined(&genesis_hash, 0, 1_000);
+    tracker.record_mined("replaced_by_reorg", 0, 2_000);
+    tracker.record_mined("not_yet_synced", 5, 3_000);
+
+    // Only our own blocks are tracked; the first answer sets the ack time
+    assert!(tracker.record_relay(&genesis_hash, false, 1_100));
+    assert!(tracker.record_relay(&genesis_hash, true, 1_250));
+    assert!(tracker.record_relay(&genesis_hash, true, 1_400));
+    assert!(tracker.record_relay("replaced_by_reorg", true, 2_050));
+    assert!(!tracker.record_relay("someone_elses_block", true, 2_100));
+
+    let genesis = tracker.get(&genesis_hash).unwrap();
+    assert_eq!(genesis.first_ack_ms(), Some(250));
+    assert_eq!((genesis.peers_relayed, genesis.peers_acked), (3, 2));
+
+    let stats = tracker.stats(&chain);
+    assert_eq!(stats.blocks_mined, 3);
+    assert_eq!(stats.blocks_relayed, 2);
+    assert_eq!(stats.blocks_acknowledged, 2);
+    assert_eq!(stats.avg_first_ack_ms, Some(150.0));
+    assert_eq!(stats.max_first_ack_ms, Some(250));
+    assert_eq!(stats.avg_peers_relayed, 2.0);
+    // A different block now sits at height 0; height 5 is above the tip
+    assert_eq!(stats.orphaned, 1);
+
+    let dir = std::env::temp_dir().join(format!("propagation_test_{}", std::process::id()));
+    let peer_stats = PeerStatsDb::open_path(&dir).unwrap();
+    peer_stats.save_propagation(&tracker).unwrap();
+    drop(peer_stats);
+    let reloaded = PeerStatsDb::open_path(&dir).unwrap().propagation().unwrap();
+    assert_eq!(reloaded.blocks(), tracker.blocks());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_locator_sync_finds_fork() {
+    let mut chain = Chain::new();
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash.clone();
+    let mut ours = Chain::new();
+    let mut previous = genesis_hash.clone();
+    for height in 1..=15 {
+        let block = Block::new(previous, vec![], height, 1640995200 + height * 60, height);
+        previous = block.header.hash.clone();
+        assert!(chain.add_block(block.clone()));
+        if height <= 5 {
+            assert!(ours.add_block(block));
+        }
+    }
+    // We share the first five blocks, then went our own way for three
+    let mut previous = ours.tip().unwrap().header.hash.clone();
+    for height in 6..=8 {
+        let block = Block::new(previous, vec![], height * 100, 1640995200 + height * 61, height);
+        previous = block.header.hash.clone();
+        assert!(ours.add_block(block));
+    }
+    
+    // Tip back ten blocks one by one, then doubling steps down to genesis
+    let locator = chain.block_locator().unwrap();
+    assert_eq!(locator.len(), 12);
+    assert_eq!(locator[0], chain.tip().unwrap().header.hash);
+    assert_eq!(locator[10], chain.block_hash_at(4).unwrap().unwrap());
+    assert_eq!(locator.last(), Some(&genesis_hash));
+    
+    let locator = ours.block_locator().unwrap();
+    assert_eq!(chain.locate_fork_point(&locator).unwrap(), Some(5));
+    assert_eq!(chain.locate_fork_point(&["unknown".to_string()]).unwrap(), None);
+    
+    // Four blocks after the fork point, with six more to come
+    match NetworkServer::locator_response(&chain, &locator, 4) {
+        MessageType::LocatorBlocks { fork_height, blocks, remaining } => {
+            assert_eq!(fork_height, Some(5));
+            let heights: Vec<u64> = blocks.iter().map(|block| block.header.height).collect();
+            assert_eq!(heights, vec![6, 7, 8, 9]);
+            assert_eq!(remaining, 6);
+        },
+        other => panic!("Expected LocatorBlocks, got {:?}", other),
+    }
+    
+    // Continuing from the last block received serves the rest
+    let last = chain.block_hash_at(9).unwrap().unwrap();
+    match NetworkServer::locator_response(&chain, &[last], 100) {
+        MessageType::LocatorBlocks { fork_height, blocks, remaining } => {
+            assert_eq!((fork_height, blocks.len(), remaining), (Some(9), 6, 0));
+        },
+        other => panic!("Expected LocatorBlocks, got {:?}", other),
+    }
+}
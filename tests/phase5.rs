@@ -1,7 +1,19 @@
-use rust_chain::consensus::pow::{ProofOfWork, MiningPool, DEFAULT_DIFFICULTY};
-use rust_chain::consensus::fork_choice::{ForkChoice, ForkChoiceWithReorg};
+use rust_chain::consensus::pow::{ProofOfWork, MiningPool, CancellationToken, TemplateRefreshPolicy, TipWatcher, DEFAULT_BITS, DEFAULT_DIFFICULTY};
+use rust_chain::consensus::params::REGTEST_POW_LIMIT_BITS;
+use rust_chain::consensus::target::Target;
+use rust_chain::mempool::Mempool;
+use rust_chain::blockchain::state::UTXOState;
+use std::time::Duration;
+use rust_chain::consensus::fork_choice::{ForkChoice, ForkChoiceWithReorg, is_final};
+use rust_chain::consensus::checkpoints::Checkpoints;
 use rust_chain::blockchain::chain::Chain;
 use rust_chain::blockchain::block::{Block, Transaction};
+use rust_chain::blockchain::events::{ChainEvent, EventBus};
+use rust_chain::clock::{Clock, MockClock};
+use rust_chain::mempool::MempoolEvent;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
 
 #[test]
 fn test_proof_of_work_creation() {
@@ -12,6 +24,27 @@ fn test_proof_of_work_creation() {
     assert_eq!(pow_custom.get_difficulty(), 2);
 }
 
+#[test]
+fn test_mining_with_mock_clock_is_reproducible() {
+    let clock = MockClock::new(1_700_000_000);
+    let mut pool = MiningPool::new(1);
+    pool.set_clock(Arc::new(clock.clone()));
+    
+    let first = pool.mine_block("previous_hash".to_string(), vec![], 1);
+    let second = pool.mine_block("previous_hash".to_string(), vec![], 1);
+    assert_eq!(first.block.header.timestamp, 1_700_000_000);
+    assert_eq!(first.hash, second.hash);
+    
+    clock.advance(60);
+    let later = pool.mine_block("previous_hash".to_string(), vec![], 1);
+    assert_eq!(later.block.header.timestamp, 1_700_000_060);
+    
+    // A clock behind the parent never stamps a block before it
+    pool.set_parent_timestamp(1_700_000_100);
+    let after_parent = pool.mine_block("previous_hash".to_string(), vec![], 1);
+    assert_eq!(after_parent.block.header.timestamp, 1_700_000_100);
+}
+
 #[test]
 fn test_mining_simple_block() {
     let pow = ProofOfWork::with_difficulty(2); // Low difficulty for fast testing
@@ -21,6 +54,8 @@ fn test_mining_simple_block() {
         to: "recipient".to_string(),
         amount: 50,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let result = pow.mine_block(
@@ -36,6 +71,154 @@ fn test_mining_simple_block() {
     assert!(pow.validate_block(&result.block));
 }
 
+/// Clock that moves forward ten seconds every time it is read
+#[derive(Debug, Default)]
+struct TickingClock {
+    time: AtomicU64,
+}
+
+impl Clock for TickingClock {
+    fn now(&self) -> u64 {
+        1_700_000_000 + self.time.fetch_add(10, Ordering::SeqCst)
+    }
+}
+
+/// Mempool with one transaction in the template and one that arrives after the
+/// template was built, before the first refresh check
+fn mempool_with_late_transaction() -> (Mempool, UTXOState, Receiver<MempoolEvent>, Vec<Transaction>, Transaction) {
+    let mut mempool = Mempool::new();
+    let mut state = UTXOState::new();
+    state.credit("alice", 100).unwrap();
+    
+    let first = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 10,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let late = Transaction {
+        from: "alice".to_string(),
+        to: "carol".to_string(),
+        amount: 20,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    mempool.add_transaction_with_fee(first, 1.0, &state).unwrap();
+    let template = mempool.get_transactions_for_block(10, &state);
+    let events = mempool.subscribe();
+    mempool.add_transaction_with_fee(late.clone(), 5.0, &state).unwrap();
+    (mempool, state, events, template, late)
+}
+
+#[test]
+fn test_mining_refreshes_template_on_mempool_add() {
+    let (mempool, state, events, template, late) = mempool_with_late_transaction();
+    let policy = TemplateRefreshPolicy {
+        min_added_fees: 0.0,
+        max_template_age: Duration::from_secs(60),
+    };
+    // The clock never moves, so only the added fees can trigger the refresh.
+    // The target is unreachable and the refresh cancels the run, so the outcome
+    // does not depend on the nonces tried.
+    let mut pow = ProofOfWork::with_difficulty(64);
+    pow.set_clock(Arc::new(MockClock::new(1_700_000_000)));
+    let cancel = CancellationToken::new();
+    let mut refreshed_with = Vec::new();
+    let result = pow.mine_block_with_refresh(
+        "genesis".to_string(),
+        template,
+        1,
+        &events,
+        &policy,
+        &cancel,
+        |added| {
+            refreshed_with.extend(added.iter().map(|event| match event {
+                MempoolEvent::TransactionAdded { transaction, .. } => transaction.hash(),
+                other => panic!("unexpected event {}", other.name()),
+            }));
+            cancel.cancel();
+            mempool.get_transactions_for_block(10, &state)
+        },
+    );
+    
+    assert!(result.is_none());
+    assert_eq!(refreshed_with, vec![late.hash()]);
+}
+
+#[test]
+fn test_mining_refreshes_old_template() {
+    let (mempool, state, events, template, late) = mempool_with_late_transaction();
+    let policy = TemplateRefreshPolicy {
+        min_added_fees: f64::INFINITY,
+        max_template_age: Duration::from_secs(30),
+    };
+    // Fees never reach the threshold; the template ages ten seconds per check
+    let clock = Arc::new(TickingClock::default());
+    let mut pow = ProofOfWork::with_difficulty(64);
+    pow.set_clock(clock.clone());
+    let cancel = CancellationToken::new();
+    let mut refreshed = Vec::new();
+    let result = pow.mine_block_with_refresh(
+        "genesis".to_string(),
+        template,
+        1,
+        &events,
+        &policy,
+        &cancel,
+        |added| {
+            let hashes: Vec<String> = added.iter().map(|event| match event {
+                MempoolEvent::TransactionAdded { transaction, .. } => transaction.hash(),
+                other => panic!("unexpected event {}", other.name()),
+            }).collect();
+            refreshed.push((hashes, clock.time.load(Ordering::SeqCst)));
+            cancel.cancel();
+            mempool.get_transactions_for_block(10, &state)
+        },
+    );
+    
+    assert!(result.is_none());
+    // The template is built at the clock's second reading and refreshed at the
+    // third check, the fifth reading, once it is 30 seconds old
+    assert_eq!(refreshed, vec![(vec![late.hash()], 50)]);
+}
+
+#[test]
+fn test_new_tip_cancels_mining() {
+    let mut mempool = Mempool::new();
+    let events = mempool.subscribe();
+    let bus = EventBus::new();
+    let watcher = TipWatcher::start(bus.subscribe());
+    
+    let chain = Chain::new();
+    let genesis = chain.tip().unwrap().clone();
+    let new_tip = Block::new(genesis.header.hash.clone(), vec![], 0, genesis.header.timestamp + 10, 1);
+    
+    let (parent, token) = (genesis.header.hash.clone(), watcher.token().clone());
+    let miner = std::thread::spawn(move || {
+        // Unreachable target, so only cancellation ends the run
+        ProofOfWork::with_difficulty(64).mine_block_with_refresh(
+            parent,
+            vec![],
+            1,
+            &events,
+            &TemplateRefreshPolicy::default(),
+            &token,
+            |_| vec![],
+        )
+    });
+    bus.publish(ChainEvent::BlockConnected(new_tip.clone()));
+    let result = miner.join().unwrap();
+    
+    assert!(result.is_none());
+    let new_blocks = watcher.take_new_blocks();
+    assert_eq!(new_blocks.len(), 1);
+    assert_eq!(new_blocks[0].header.hash, new_tip.header.hash);
+    assert!(!watcher.token().is_cancelled());
+}
+
 #[test]
 fn test_mining_pool() {
     let mut pool = MiningPool::new(2); // Low difficulty
@@ -45,6 +228,8 @@ fn test_mining_pool() {
         to: "bob".to_string(),
         amount: 25,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let result = pool.mine_block(
@@ -74,6 +259,8 @@ fn test_difficulty_adjustment() {
             to: "test".to_string(),
             amount: 1,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         let block = Block::new(
@@ -98,6 +285,8 @@ fn test_difficulty_adjustment() {
             to: "test".to_string(),
             amount: 1,
             signature: vec![],
+            lock_height: None,
+            expiry_height: None,
         };
         
         let block = Block::new(
@@ -110,9 +299,45 @@ fn test_difficulty_adjustment() {
         blocks.push(block);
     }
     
-    let new_difficulty = pow.adjust_difficulty(&blocks, 60);
-    // Average is 15s, target is 60s, so 15 < 30 should trigger increase
-    assert_eq!(new_difficulty, 4); // Should increase difficulty from 3 to 4
+    let new_bits = pow.retarget(&blocks, 0, 60);
+    // Four intervals took 60s instead of 240s, so the target shrinks fourfold
+    assert_eq!(new_bits, 0x1f03ffff);
+    assert_eq!(pow.get_bits(), new_bits);
+    
+    // Slow blocks ease the target back, but not past where it started
+    let slow: Vec<Block> = (0..5u64)
+        .map(|i| Block::new(format!("hash_{}", i), vec![], 0, base_time + i * 600, i))
+        .collect();
+    assert_eq!(pow.retarget(&slow, 0, 60), 0x1f0ffffc);
+    assert_eq!(pow.retarget(&slow, 0, 60), 0x1f0fffff);
+}
+
+#[test]
+fn test_validate_block_bits() {
+    let pow = ProofOfWork::new();
+    assert_eq!(pow.get_bits(), DEFAULT_BITS);
+    
+    // Regtest targets are met by about every other hash, but are easier than mainnet allows
+    let regtest = ProofOfWork::with_bits(REGTEST_POW_LIMIT_BITS);
+    let result = regtest.mine_block("previous_hash".to_string(), vec![], 1);
+    assert_eq!(result.block.header.bits, Some(REGTEST_POW_LIMIT_BITS));
+    assert!(result.block.verify_integrity());
+    assert!(regtest.validate_block(&result.block));
+    assert!(!pow.validate_block(&result.block));
+    
+    let mined = ProofOfWork::with_difficulty(2).mine_block("previous_hash".to_string(), vec![], 1);
+    assert_eq!(mined.block.header.bits, Some(0x2000ffff));
+    assert!(!pow.validate_block(&mined.block));
+    assert_eq!(mined.block.work(), 1 << 8);
+    
+    // Blocks stored before headers had bits are held to leading zeros
+    let legacy = (0..).map(|nonce| Block::new("previous_hash".to_string(), vec![], nonce, 1_700_000_000, 1))
+        .find(|block| block.header.hash.starts_with("00"))
+        .unwrap();
+    assert_eq!(legacy.header.bits, None);
+    assert!(ProofOfWork::with_difficulty(2).validate_block(&legacy));
+    assert_eq!(legacy.work(), 1);
+    assert_eq!(legacy.header.difficulty(&Target::from_compact(DEFAULT_BITS).unwrap()), 1.0 / 65537.0);
 }
 
 #[test]
@@ -132,7 +357,7 @@ fn test_fork_choice_add_block() {
     
     let genesis_hash = fork_choice.get_best_chain()
         .unwrap()
-        .blocks.last()
+        .tip()
         .unwrap()
         .header.hash.clone();
     
@@ -141,6 +366,8 @@ fn test_fork_choice_add_block() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let new_block = Block::new(
@@ -156,8 +383,8 @@ fn test_fork_choice_add_block() {
     assert_eq!(result.unwrap(), true); // Should be new best
     
     let best_chain = fork_choice.get_best_chain().unwrap();
-    assert_eq!(best_chain.blocks.len(), 2); // Genesis + new block
-    assert_eq!(best_chain.blocks.last().unwrap().header.hash, new_block.header.hash);
+    assert_eq!(best_chain.block_count(), 2); // Genesis + new block
+    assert_eq!(best_chain.tip().unwrap().header.hash, new_block.header.hash);
 }
 
 #[test]
@@ -167,7 +394,7 @@ fn test_fork_choice_longer_chain_wins() {
     
     let genesis_hash = fork_choice.get_best_chain()
         .unwrap()
-        .blocks.last()
+        .tip()
         .unwrap()
         .header.hash.clone();
     
@@ -177,6 +404,8 @@ fn test_fork_choice_longer_chain_wins() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let block1 = Block::new(
@@ -195,6 +424,8 @@ fn test_fork_choice_longer_chain_wins() {
         to: "charlie".to_string(),
         amount: 50,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let block2 = Block::new(
@@ -213,6 +444,8 @@ fn test_fork_choice_longer_chain_wins() {
         to: "mallory".to_string(),
         amount: 25,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let fork_block = Block::new(
@@ -228,8 +461,8 @@ fn test_fork_choice_longer_chain_wins() {
     
     // Main chain should still be the best
     let best_chain = fork_choice.get_best_chain().unwrap();
-    assert_eq!(best_chain.blocks.len(), 3); // Genesis + 2 blocks
-    assert_eq!(best_chain.blocks.last().unwrap().header.hash, block2.header.hash);
+    assert_eq!(best_chain.block_count(), 3); // Genesis + 2 blocks
+    assert_eq!(best_chain.tip().unwrap().header.hash, block2.header.hash);
 }
 
 #[test]
@@ -245,7 +478,7 @@ fn test_fork_choice_stats() {
     // Add a block
     let genesis_hash = fork_choice.get_best_chain()
         .unwrap()
-        .blocks.last()
+        .tip()
         .unwrap()
         .header.hash.clone();
     
@@ -254,6 +487,8 @@ fn test_fork_choice_stats() {
         to: "test".to_string(),
         amount: 1,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let block = Block::new(genesis_hash, vec![tx], 1, 1640995200, 1);
@@ -275,6 +510,8 @@ fn test_fork_choice_with_reorg() {
         to: "genesis_address".to_string(),
         amount: 50,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let genesis_block = Block::new("0".to_string(), vec![tx_genesis], 0, 0, 0);
@@ -290,6 +527,8 @@ fn test_fork_choice_with_reorg() {
         to: "bob".to_string(),
         amount: 25,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let block = Block::new(genesis_block.header.hash, vec![tx], 1, 1640995200, 1);
@@ -310,6 +549,8 @@ fn test_block_validation_in_fork_choice() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     let invalid_block = Block::new(
@@ -324,3 +565,147 @@ fn test_block_validation_in_fork_choice() {
     assert!(result.is_err());
     assert!(result.unwrap_err().contains("Parent block not found"));
 }
+
+#[test]
+fn test_fork_choice_respects_checkpoints() {
+    let chain = Chain::new();
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash.clone();
+    
+    let block1 = Block::new(genesis_hash.clone(), vec![], 1, 1640995200, 1);
+    let block2 = Block::new(block1.header.hash.clone(), vec![], 2, 1640995260, 2);
+    
+    let mut checkpoints = Checkpoints::mainnet();
+    checkpoints.add(1, block1.header.hash.clone());
+    let mut fork_choice = ForkChoice::with_genesis_chain(chain).with_checkpoints(checkpoints);
+    
+    // A block contradicting the checkpoint is rejected outright
+    let conflicting = Block::new(genesis_hash.clone(), vec![], 99, 1640995300, 1);
+    assert!(fork_choice.add_block(conflicting).unwrap_err().contains("checkpoint"));
+    
+    assert!(fork_choice.add_block(block1).unwrap());
+    assert!(fork_choice.add_block(block2).unwrap());
+    
+    // Once the checkpoint is passed, forks at or below it are refused
+    let deep_fork = Block::new(genesis_hash, vec![], 7, 1640995400, 1);
+    assert!(fork_choice.add_block(deep_fork).is_err());
+    assert_eq!(fork_choice.get_best_chain().unwrap().block_count(), 3);
+}
+
+#[test]
+fn test_fork_choice_refuses_reorg_past_finality() {
+    let chain = Chain::new();
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash.clone();
+    let mut fork_choice = ForkChoice::with_genesis_chain(chain).with_finality_depth(2);
+    
+    let mut prev_hash = genesis_hash.clone();
+    let mut hashes = Vec::new();
+    for height in 1..=3 {
+        let block = Block::new(prev_hash.clone(), vec![], height, 1640995200 + height * 60, height);
+        prev_hash = block.header.hash.clone();
+        hashes.push(prev_hash.clone());
+        assert!(fork_choice.add_block(block).unwrap());
+    }
+    
+    // Block 1 has two blocks on top of it and is final
+    assert!(is_final(1, 3, 2));
+    assert!(!is_final(2, 3, 2));
+    
+    let deep_fork = Block::new(genesis_hash, vec![], 100, 1640999999, 1);
+    assert!(fork_choice.add_block(deep_fork).unwrap_err().contains("final"));
+    
+    // Forking off block 1 only replaces non-final blocks
+    let shallow_fork = Block::new(hashes[0].clone(), vec![], 101, 1640999999, 2);
+    assert!(fork_choice.add_block(shallow_fork).is_ok());
+}
+
+#[test]
+fn test_fork_choice_finality_counts_from_fork_point() {
+    let chain = Chain::new();
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash.clone();
+    let mut fork_choice = ForkChoice::with_genesis_chain(chain).with_finality_depth(2);
+    
+    // A side branch starts off genesis while nothing is final yet
+    let main1 = Block::new(genesis_hash.clone(), vec![], 1, 1640995260, 1);
+    assert!(fork_choice.add_block(main1.clone()).unwrap());
+    let side1 = Block::new(genesis_hash, vec![], 50, 1640995250, 1);
+    assert!(!fork_choice.add_block(side1.clone()).unwrap());
+    
+    let main2 = Block::new(main1.header.hash.clone(), vec![], 2, 1640995320, 2);
+    let main3 = Block::new(main2.header.hash.clone(), vec![], 3, 1640995380, 3);
+    assert!(fork_choice.add_block(main2).unwrap());
+    assert!(fork_choice.add_block(main3).unwrap());
+    
+    // Extending it at height 2 would still replace block 1, which is now final
+    let side2 = Block::new(side1.header.hash.clone(), vec![], 51, 1640995330, 2);
+    assert!(fork_choice.add_block(side2).unwrap_err().contains("height 1"));
+}
+
+#[test]
+fn test_fork_choice_publishes_reorgs() {
+    let chain = Chain::new();
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash.clone();
+    let events = EventBus::new();
+    let receiver = events.subscribe();
+    let mut fork_choice = ForkChoice::with_genesis_chain(chain).with_events(events);
+    
+    let main_block = Block::new(genesis_hash.clone(), vec![], 1, 1640995300, 1);
+    assert!(fork_choice.add_block(main_block.clone()).unwrap());
+    assert!(receiver.try_recv().is_err()); // Extending the tip is not a reorg
+    
+    // An older block at the same height does not displace the tip
+    let fork1 = Block::new(genesis_hash, vec![], 2, 1640995260, 1);
+    let fork2 = Block::new(fork1.header.hash.clone(), vec![], 3, 1640995360, 2);
+    assert!(!fork_choice.add_block(fork1).unwrap());
+    assert!(fork_choice.add_block(fork2.clone()).unwrap());
+    
+    match receiver.try_recv().unwrap() {
+        ChainEvent::Reorg { old_tip, new_tip, depth } => {
+            assert_eq!(old_tip, main_block.header.hash);
+            assert_eq!(new_tip, fork2.header.hash);
+            assert_eq!(depth, 1);
+        },
+        other => panic!("Expected a reorg, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_fork_tree_export() {
+    let chain = Chain::new();
+    let genesis_hash = chain.block_at(0).unwrap().unwrap().header.hash.clone();
+    let mut fork_choice = ForkChoice::with_genesis_chain(chain);
+    
+    // Best chain genesis -> a1 -> a2 -> a3, with a branch of two blocks off a1
+    let a1 = Block::new(genesis_hash.clone(), vec![], 1, 1640995200, 1);
+    let a2 = Block::new(a1.header.hash.clone(), vec![], 2, 1640995260, 2);
+    let a3 = Block::new(a2.header.hash.clone(), vec![], 3, 1640995320, 3);
+    for block in [&a1, &a2, &a3] {
+        fork_choice.add_block(block.clone()).unwrap();
+    }
+    let b2 = Block::new(a1.header.hash.clone(), vec![], 20, 1640995270, 2);
+    let b3 = Block::new(b2.header.hash.clone(), vec![], 30, 1640995300, 3);
+    assert!(!fork_choice.add_block(b2.clone()).unwrap());
+    assert!(!fork_choice.add_block(b3.clone()).unwrap());
+    
+    let tree = fork_choice.fork_tree();
+    assert_eq!(tree.branches.len(), 2);
+    let best = tree.best().unwrap();
+    assert!(best.is_best);
+    assert_eq!((best.tip_hash.as_str(), best.tip_height, best.length), (a3.header.hash.as_str(), 3, 3));
+    assert_eq!(best.fork_hash, genesis_hash);
+    
+    let branch = &tree.branches[1];
+    assert_eq!(branch.tip_hash, b3.header.hash);
+    assert_eq!((branch.fork_height, branch.fork_hash.as_str()), (1, a1.header.hash.as_str()));
+    assert_eq!(branch.length, 2);
+    assert_eq!(branch.branch_work, b2.work() + b3.work());
+    
+    let json = tree.to_json();
+    assert_eq!(json["branches"][1]["forkheight"], 1);
+    assert_eq!(json["branches"][0]["best"], true);
+    
+    let dot = tree.to_dot();
+    assert!(dot.starts_with("digraph forks {"));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\"", a1.header.hash, b3.header.hash)));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\"", genesis_hash, a1.header.hash)));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"2 blocks\"", a1.header.hash, a3.header.hash)));
+}
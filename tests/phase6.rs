@@ -1,6 +1,7 @@
+use rust_chain::blockchain::amount::MAX_MONEY;
 use rust_chain::blockchain::block::Transaction;
 use rust_chain::blockchain::state::UTXOState;
-use rust_chain::mempool::{Mempool, TransactionValidator, ValidationError};
+use rust_chain::mempool::{Mempool, TransactionValidator, ValidationError, RelayPolicy, PolicyViolation};
 
 fn create_test_transaction(from: &str, to: &str, amount: u64) -> Transaction {
     Transaction {
@@ -8,6 +9,8 @@ fn create_test_transaction(from: &str, to: &str, amount: u64) -> Transaction {
         to: to.to_string(),
         amount,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     }
 }
 
@@ -17,7 +20,7 @@ fn test_transaction_validator_basic() {
     let mut state = UTXOState::new();
     
     // Add some balance to alice
-    state.update_balance("alice", 100);
+    state.credit("alice", 100).unwrap();
     
     let valid_tx = create_test_transaction("alice", "bob", 50);
     assert!(validator.validate_transaction(&valid_tx, &state).is_ok());
@@ -51,7 +54,7 @@ fn test_transaction_validator_self_transfer() {
 fn test_transaction_validator_duplicate() {
     let mut validator = TransactionValidator::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 100);
+    state.credit("alice", 100).unwrap();
     
     let tx = create_test_transaction("alice", "bob", 50);
     
@@ -87,6 +90,8 @@ fn test_transaction_validator_invalid_address() {
         to: "bob".to_string(),
         amount: 50,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     assert_eq!(
@@ -99,7 +104,7 @@ fn test_transaction_validator_invalid_address() {
 fn test_mempool_add_transaction() {
     let mut mempool = Mempool::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 100);
+    state.credit("alice", 100).unwrap();
     
     let tx = create_test_transaction("alice", "bob", 50);
     
@@ -112,7 +117,7 @@ fn test_mempool_add_transaction() {
 fn test_mempool_duplicate_prevention() {
     let mut mempool = Mempool::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 100);
+    state.credit("alice", 100).unwrap();
     
     let tx = create_test_transaction("alice", "bob", 50);
     
@@ -132,7 +137,7 @@ fn test_mempool_duplicate_prevention() {
 fn test_mempool_get_transactions_for_block() {
     let mut mempool = Mempool::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 1000);
+    state.credit("alice", 1000).unwrap();
     
     // Add multiple transactions
     let tx1 = create_test_transaction("alice", "bob", 100);
@@ -154,7 +159,7 @@ fn test_mempool_get_transactions_for_block() {
 fn test_mempool_remove_transactions() {
     let mut mempool = Mempool::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 1000);
+    state.credit("alice", 1000).unwrap();
     
     let tx1 = create_test_transaction("alice", "bob", 100);
     let tx2 = create_test_transaction("alice", "charlie", 200);
@@ -174,7 +179,7 @@ fn test_mempool_remove_transactions() {
 fn test_mempool_stats() {
     let mut mempool = Mempool::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 1000);
+    state.credit("alice", 1000).unwrap();
     
     // Empty mempool
     let stats = mempool.get_stats();
@@ -195,7 +200,7 @@ fn test_mempool_stats() {
 fn test_mempool_clear() {
     let mut mempool = Mempool::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 1000);
+    state.credit("alice", 1000).unwrap();
     
     let tx = create_test_transaction("alice", "bob", 50);
     mempool.add_transaction(tx, &state).unwrap();
@@ -224,9 +229,9 @@ fn test_mempool_invalid_transaction_insufficient_funds() {
 fn test_mempool_transaction_ordering() {
     let mut mempool = Mempool::new();
     let mut state = UTXOState::new();
-    state.update_balance("alice", 1000);
+    state.credit("alice", 1000).unwrap();
     
-    // Add transactions (same fee, so should be ordered by timestamp)
+    // Add transactions (same fee, so should be ordered by txid)
     let tx1 = create_test_transaction("alice", "bob", 100);
     let tx2 = create_test_transaction("alice", "charlie", 200);
     
@@ -235,9 +240,15 @@ fn test_mempool_transaction_ordering() {
     
     let block_txs = mempool.get_transactions_for_block(10, &state);
     assert_eq!(block_txs.len(), 2);
-    // Should maintain order (first added, first in block)
-    assert_eq!(block_txs[0].amount, 100);
-    assert_eq!(block_txs[1].amount, 200);
+    assert!(block_txs[0].hash() < block_txs[1].hash());
+    
+    // Arrival order doesn't change the template
+    let mut reversed = Mempool::new();
+    reversed.add_transaction(tx2, &state).unwrap();
+    reversed.add_transaction(tx1, &state).unwrap();
+    let reversed_txs = reversed.get_transactions_for_block(10, &state);
+    let hashes = |txs: &[Transaction]| txs.iter().map(|tx| tx.hash()).collect::<Vec<_>>();
+    assert_eq!(hashes(&block_txs), hashes(&reversed_txs));
 }
 
 #[test]
@@ -248,27 +259,33 @@ fn test_utxo_state_operations() {
     assert_eq!(state.get_balance("alice"), 0);
     
     // Update balance
-    state.update_balance("alice", 100);
+    state.credit("alice", 100).unwrap();
     assert_eq!(state.get_balance("alice"), 100);
     
     // Deduct balance
-    state.update_balance("alice", -50);
+    state.debit("alice", 50).unwrap();
     assert_eq!(state.get_balance("alice"), 50);
     
     // Can't go negative
-    state.update_balance("alice", -100);
-    assert_eq!(state.get_balance("alice"), 0);
+    assert!(state.debit("alice", 100).is_err());
+    assert_eq!(state.get_balance("alice"), 50);
     
     // Set balance directly
     state.set_balance("bob", 200);
     assert_eq!(state.get_balance("bob"), 200);
+    
+    // Credits stop at the maximum money supply and leave the balance alone
+    assert!(state.credit("bob", MAX_MONEY - 200).is_ok());
+    assert!(state.credit("bob", 1).is_err());
+    assert!(state.credit("bob", u64::MAX).is_err());
+    assert_eq!(state.get_balance("bob"), MAX_MONEY);
 }
 
 #[test]
 fn test_mempool_with_limits() {
     let mut mempool = Mempool::with_limits(2, 3600); // Max 2 transactions
     let mut state = UTXOState::new();
-    state.update_balance("alice", 1000);
+    state.credit("alice", 1000).unwrap();
     
     let tx1 = create_test_transaction("alice", "bob", 100);
     let tx2 = create_test_transaction("alice", "charlie", 200);
@@ -282,3 +299,38 @@ fn test_mempool_with_limits() {
     // Should only keep 2 transactions (highest priority)
     assert_eq!(mempool.size(), 2);
 }
+
+#[test]
+fn test_mempool_relay_policy() {
+    let mut mempool = Mempool::new();
+    mempool.set_policy(RelayPolicy {
+        min_relay_fee: 1.0,
+        dust_threshold: 10,
+        ..RelayPolicy::default()
+    });
+    let mut state = UTXOState::new();
+    state.credit("alice", 1000).unwrap();
+    
+    // Network transactions are held to the relay policy
+    assert_eq!(
+        mempool.add_network_transaction(create_test_transaction("alice", "bob", 5), &state),
+        Err(ValidationError::Policy(PolicyViolation::Dust { amount: 5, threshold: 10 }))
+    );
+    // Transactions are held to the fee rate they pay, which is nothing
+    assert_eq!(
+        mempool.add_network_transaction(create_test_transaction("alice", "bob", 50), &state),
+        Err(ValidationError::Policy(PolicyViolation::FeeTooLow { fee_per_byte: 0.0, minimum: 1.0 }))
+    );
+    mempool.set_policy(RelayPolicy { dust_threshold: 10, ..RelayPolicy::default() });
+    assert!(mempool.add_network_transaction(create_test_transaction("alice", "bob", 50), &state).is_ok());
+    
+    // Local submissions are not
+    assert!(mempool.add_transaction(create_test_transaction("alice", "carol", 5), &state).is_ok());
+    assert_eq!(mempool.size(), 2);
+    
+    mempool.set_policy(RelayPolicy { blocks_only: true, ..RelayPolicy::default() });
+    assert_eq!(
+        mempool.add_network_transaction(create_test_transaction("alice", "dave", 50), &state),
+        Err(ValidationError::Policy(PolicyViolation::RelayDisabled))
+    );
+}
@@ -39,7 +39,7 @@ fn test_peer_discovery_with_multiple_peers() {
     assert_eq!(stats.avg_chain_height, 123); // (100 + 150 + 120) / 3 = 123
     
     // Test getting best peers
-    let best_peers = discovery.get_best_peers(2);
+    let best_peers = discovery.get_best_peers(2, rust_chain::network::Services::NONE);
     assert_eq!(best_peers.len(), 2);
     assert_eq!(best_peers[0].chain_height, 150);
     assert_eq!(best_peers[1].chain_height, 120);
@@ -208,6 +208,7 @@ fn test_rpc_config_creation() {
         max_request_size: 2048,
         enable_cors: false,
         allowed_origins: vec!["localhost".to_string()],
+        ..Default::default()
     };
     
     assert_eq!(custom_config.bind_address.port(), 3000);
@@ -1,6 +1,8 @@
 use rust_chain::cli::{CLI, WalletCommands, AnalyticsCommands, BlockchainCommands, MiningCommands};
+use rust_chain::alerts::{AlertKind, AlertLog};
 use rust_chain::wallet::keychain::Wallet;
-use rust_chain::blockchain::block::Transaction;
+use rust_chain::blockchain::block::{Block, Transaction};
+use rust_chain::consensus::params::ChainParams;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 fn get_unique_test_path(base_name: &str) -> String {
@@ -98,6 +100,32 @@ fn test_seed_phrase_backup_restore() {
     assert_eq!(cli1.show_seed_phrase(), cli2.show_seed_phrase());
 }
 
+#[test]
+fn test_seed_reveal_is_logged() {
+    let test_path = get_unique_test_path("test_seed_reveal");
+    let cli = CLI::new_with_path(&test_path).expect("Failed to create CLI");
+    
+    assert_eq!(cli.reveal_seed_phrase().unwrap(), cli.show_seed_phrase());
+    let qr_path = format!("{}/seed_qr.txt", test_path);
+    cli.export_seed_qr(&qr_path).expect("Failed to export seed");
+    
+    // Each of the 24 words becomes its four-digit BIP-39 index
+    let payload = std::fs::read_to_string(&qr_path).unwrap();
+    assert_eq!(payload.len(), 24 * 4);
+    assert!(payload.chars().all(|c| c.is_ascii_digit()));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&qr_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    
+    let alerts = AlertLog::open(&cli.data_dir).unwrap().alerts();
+    let seed_alerts: Vec<_> = alerts.iter().filter(|alert| alert.kind == AlertKind::SeedRevealed).collect();
+    assert_eq!(seed_alerts.len(), 2);
+    assert!(seed_alerts[1].message.contains(&qr_path));
+}
+
 #[test]
 fn test_wallet_backup() {
     let test_path = get_unique_test_path("test_wallet_backup");
@@ -116,6 +144,92 @@ fn test_wallet_backup() {
     std::fs::remove_file(backup_path).ok();
 }
 
+#[test]
+fn test_private_key_export_and_import() {
+    let test_path = get_unique_test_path("test_private_key_export_import");
+    let mut cli = CLI::new_with_path(&test_path).expect("Failed to create CLI");
+    let mut source = Wallet::new();
+    let address = source.generate_address().unwrap();
+    let key = source.get_private_key(&address).unwrap();
+    // Addresses are public keys and never the key itself
+    assert_ne!(address, hex::encode(key));
+    
+    // The same key as WIF or hex registers the same address, once
+    let wif = rust_chain::crypto::keys::encode_wif(&key);
+    assert_eq!(cli.import_private_key(&wif).unwrap(), address);
+    assert!(cli.import_private_key(&hex::encode(key)).is_err());
+    assert!(cli.import_private_key("not a key").is_err());
+    assert!(cli.wallet.is_imported(&address));
+    assert!(cli.list_addresses().contains(&address));
+    assert_eq!(cli.get_wallet_stats().imported_keys, 1);
+    
+    // Dumped keys are logged, and the backup carries the key the seed cannot restore
+    assert_eq!(cli.dump_private_key(&address).unwrap(), wif);
+    assert!(cli.dump_private_key("unknown").is_err());
+    let alerts = AlertLog::open(&cli.data_dir).unwrap().alerts();
+    assert_eq!(alerts.iter().filter(|alert| alert.kind == AlertKind::KeyRevealed).count(), 1);
+    
+    let backup_path = format!("{}/wallet_backup.json", test_path);
+    cli.backup_wallet(&backup_path).unwrap();
+    let backup: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&backup_path).unwrap()).unwrap();
+    assert_eq!(backup["imported_keys"][0]["key"], wif);
+    
+    let reloaded = Wallet::load_from_file(&cli.wallet_path).unwrap();
+    assert_eq!(reloaded.get_private_key(&address).unwrap(), key);
+}
+
+#[test]
+fn test_receive_address_rotates_after_payment() {
+    use rust_chain::wallet::keychain::AddressState;
+    
+    let test_path = get_unique_test_path("test_receive_address_rotation");
+    let mut cli = CLI::new_with_path(&test_path).expect("Failed to create CLI");
+    cli.init_chain().expect("Failed to initialize chain");
+    
+    // The same address is handed out until it is paid
+    let first = cli.get_receive_address(false).unwrap();
+    assert_eq!(cli.get_receive_address(false).unwrap(), first);
+    
+    let payment = |to: &str| Transaction {
+        from: "alice".to_string(),
+        to: to.to_string(),
+        amount: 10,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    cli.mine_block(vec![payment(&first)]).expect("Failed to mine block");
+    let second = cli.get_receive_address(false).unwrap();
+    assert_ne!(second, first);
+    
+    let change = cli.get_receive_address(true).unwrap();
+    let states: Vec<(String, AddressState)> = cli.list_address_states();
+    assert!(states.contains(&(first.clone(), AddressState::Used)));
+    assert!(states.contains(&(second.clone(), AddressState::Unused)));
+    assert!(states.contains(&(change, AddressState::Change)));
+    
+    // Later refreshes only read blocks above the recorded scan
+    let scan = cli.wallet.usage_scan().cloned().unwrap();
+    assert_eq!(scan.height, cli.chain.height());
+    assert_eq!(scan.block_hash, cli.chain.tip().unwrap().header.hash);
+    
+    // An imported key may have been paid below it, so importing restarts the scan
+    let mut source = Wallet::new();
+    let imported = source.generate_address().unwrap();
+    cli.mine_block(vec![payment(&imported)]).expect("Failed to mine block");
+    assert_eq!(cli.refresh_address_usage().unwrap(), 0);
+    cli.import_private_key(&hex::encode(source.get_private_key(&imported).unwrap())).unwrap();
+    assert!(cli.wallet.usage_scan().is_none());
+    assert_eq!(cli.refresh_address_usage().unwrap(), 1);
+    assert_eq!(cli.wallet.address_state(&imported), AddressState::Used);
+    
+    // Paying the used address again is flagged as reuse
+    cli.mine_block(vec![payment(&first)]).expect("Failed to mine block");
+    let analytics = cli.analyze_chain();
+    assert!(analytics.reused_addresses >= 1);
+    assert_eq!(analytics.wallet_reused_addresses, vec![first]);
+}
+
 #[test]
 fn test_chain_analytics() {
     let test_path = get_unique_test_path("test_chain_analytics");
@@ -128,12 +242,16 @@ fn test_chain_analytics() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     let tx2 = Transaction {
         from: "bob".to_string(),
         to: "charlie".to_string(),
         amount: 50,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     cli.mine_block(vec![tx1]).expect("Failed to mine block 1");
@@ -157,6 +275,8 @@ fn test_block_statistics() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     cli.mine_block(vec![tx]).expect("Failed to mine block");
@@ -181,18 +301,24 @@ fn test_transaction_statistics() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     let tx2 = Transaction {
         from: "bob".to_string(),
         to: "charlie".to_string(),
         amount: 50,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     let tx3 = Transaction {
         from: "alice".to_string(),
         to: "charlie".to_string(),
         amount: 25,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     cli.mine_block(vec![tx1, tx2]).expect("Failed to mine block 1");
@@ -217,6 +343,8 @@ fn test_chain_integrity_validation() {
         to: "bob".to_string(),
         amount: 100,
         signature: vec![],
+        lock_height: None,
+        expiry_height: None,
     };
     
     cli.mine_block(vec![tx]).expect("Failed to mine block");
@@ -263,6 +391,185 @@ fn test_wallet_deterministic_generation() {
     assert_eq!(addr2, addr3);
 }
 
+#[test]
+fn test_wallet_accounts() {
+    let mut wallet = Wallet::new();
+    let default_addr = wallet.generate_address().expect("Failed to generate address");
+    
+    assert_eq!(wallet.create_account("savings").unwrap(), 1);
+    assert_eq!(wallet.create_account("trading").unwrap(), 2);
+    assert!(wallet.create_account("savings").is_err());
+    assert!(wallet.create_account("default").is_err());
+    
+    let savings_addr = wallet.generate_account_address("savings").unwrap();
+    let trading_addr = wallet.generate_account_address("trading").unwrap();
+    assert!(wallet.generate_account_address("missing").is_err());
+    
+    // Accounts derive distinct keys, and the default account is unchanged
+    assert_ne!(savings_addr, trading_addr);
+    assert_eq!(wallet.get_address_by_index(0), Some(default_addr.clone()));
+    assert_eq!(wallet.account_of(&savings_addr).as_deref(), Some("savings"));
+    assert_eq!(wallet.account_of(&default_addr).as_deref(), Some("default"));
+    assert!(wallet.get_private_key(&trading_addr).is_ok());
+    assert_eq!(wallet.get_all_addresses().len(), 3);
+    
+    // Accounts are rederived identically from the same seed
+    let mut restored = Wallet::from_seed_phrase(wallet.get_seed_phrase()).unwrap();
+    restored.create_account("savings").unwrap();
+    assert_eq!(restored.generate_account_address("savings").unwrap(), savings_addr);
+    
+    let accounts = wallet.list_accounts();
+    assert_eq!(accounts.len(), 3);
+    assert_eq!(accounts[1].name, "savings");
+    assert_eq!(accounts[1].addresses(), vec![savings_addr]);
+    assert_eq!(wallet.get_stats().accounts, 3);
+}
+
+#[test]
+fn test_invoices() {
+    use rust_chain::clock::MockClock;
+    use rust_chain::wallet::invoice::{Invoice, InvoiceStatus};
+    
+    let test_path = get_unique_test_path("test_invoices");
+    let mut cli = CLI::new_with_path(&test_path).expect("Failed to create CLI");
+    cli.chain.set_params(ChainParams::regtest());
+    let clock = MockClock::new(1_000);
+    cli.mock_clock = Some(clock.clone());
+    
+    let paid = cli.create_invoice(100, Some("Order 1"), 3600).expect("Failed to create invoice");
+    let open = cli.create_invoice(40, None, 3600).expect("Failed to create invoice");
+    let expired = cli.create_invoice(10, None, 0).expect("Failed to create invoice");
+    assert!(cli.create_invoice(0, None, 3600).is_err());
+    assert!(cli.create_invoice(10, None, u64::MAX).is_err());
+    assert_ne!(paid.address, open.address);
+    
+    let parsed = Invoice::from_uri(&paid.to_uri()).unwrap();
+    assert_eq!((parsed.address.as_str(), parsed.amount), (paid.address.as_str(), 100));
+    
+    // Pay the first invoice in full and the second only partly
+    let payment = |to: &str, amount: u64| Transaction {
+        from: "alice".to_string(),
+        to: to.to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = cli.chain.tip().unwrap().header.hash.clone();
+    let block = Block::new(prev_hash, vec![payment(&paid.address, 100), payment(&open.address, 15)], 0, 1, 1);
+    assert!(cli.chain.add_block(block));
+    
+    // Paying the expired invoice after its expiry does not settle it
+    let prev_hash = cli.chain.tip().unwrap().header.hash.clone();
+    let late = Block::new(prev_hash, vec![payment(&expired.address, 10)], 0, 1_500, 2);
+    assert!(cli.chain.add_block(late));
+    clock.advance(1_000);
+    
+    let invoices = cli.list_invoices().expect("Failed to list invoices");
+    assert_eq!(invoices.len(), 3);
+    assert_eq!(invoices[0].status, InvoiceStatus::Paid);
+    assert_eq!(invoices[1].status, InvoiceStatus::Unpaid);
+    assert_eq!(invoices[1].received, 15);
+    assert_eq!(invoices[2].invoice.id, expired.id);
+    assert_eq!(invoices[2].status, InvoiceStatus::Expired);
+    assert_eq!((invoices[2].received, invoices[2].late), (0, 10));
+}
+
+#[test]
+fn test_spend_limits() {
+    use rust_chain::wallet::policy::{LimitBreach, SpendPolicy};
+    
+    let test_path = get_unique_test_path("test_spend_limits");
+    let mut cli = CLI::new_with_path(&test_path).expect("Failed to create CLI");
+    cli.set_spend_policy(SpendPolicy { max_per_transaction: Some(100), max_daily: Some(150), confirm_above: Some(50) })
+        .expect("Failed to set spend policy");
+    
+    // Below the threshold nothing is asked
+    assert_eq!(cli.authorize_spend(40, false, |_| panic!("no prompt expected")), Ok(vec![]));
+    // Above it the send waits for confirmation
+    assert_eq!(cli.authorize_spend(60, false, |_| true), Ok(vec![]));
+    assert!(cli.authorize_spend(60, false, |_| false).is_err());
+    
+    let payment = |amount: u64| Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    cli.record_spend(&payment(90), &[]).expect("Failed to record spend");
+    assert_eq!(cli.spent_today(), 90);
+    
+    // 90 + 80 breaks the daily limit: refused without an override, logged with one
+    let error = cli.authorize_spend(80, false, |_| true).unwrap_err();
+    assert!(error.contains("--override-limits"));
+    let overridden = cli.authorize_spend(80, true, |_| true).expect("Override should be allowed");
+    assert_eq!(overridden, vec![LimitBreach::Daily { spent: 90, amount: 80, limit: 150 }]);
+    cli.record_spend(&payment(80), &overridden).expect("Failed to record spend");
+    
+    let alerts = AlertLog::open(&cli.data_dir).unwrap().alerts();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].kind, AlertKind::SpendLimitOverride);
+    assert!(alerts[0].message.contains(&payment(80).hash()));
+    
+    // The send path itself refuses a payment over the limits before it reaches the mempool
+    let error = cli.send_transaction(payment(120), None, false, |_| true).unwrap_err();
+    assert!(error.contains("Spend limit exceeded"));
+    assert_eq!(cli.mempool.size(), 0);
+    
+    // The policy and spends are saved with the wallet
+    let mut wallet = Wallet::load_from_file(&cli.wallet_path).expect("Failed to load wallet");
+    assert_eq!(wallet.spend_policy().max_daily, Some(150));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    assert_eq!(wallet.spent_since(now), 170);
+    
+    // A fee bump moves the spend to its replacement rather than counting it twice
+    wallet.record_replacement(&payment(80).hash(), "replacement", now);
+    assert_eq!(wallet.spent_since(now), 170);
+    assert_eq!(wallet.replaced_txid("replacement"), Some(payment(80).hash().as_str()));
+}
+
+#[test]
+fn test_monetary_stats() {
+    use rust_chain::blockchain::genesis::COINBASE_ADDRESS;
+    
+    let test_path = get_unique_test_path("test_monetary_stats");
+    let mut cli = CLI::new_with_path(&test_path).expect("Failed to create CLI");
+    cli.chain.set_params(ChainParams::regtest());
+    let genesis_allocation = cli.params.genesis_allocation;
+    
+    let stats = cli.get_monetary_stats();
+    assert_eq!(stats.height, 0);
+    assert_eq!(stats.issued, genesis_allocation);
+    assert_eq!(stats.expected_issued, genesis_allocation);
+    assert_eq!(stats.current_subsidy, cli.params.initial_subsidy);
+    assert_eq!(stats.next_halving_height, Some(cli.params.halving_interval + 1));
+    assert_eq!(stats.blocks_until_halving, Some(cli.params.halving_interval + 1));
+    
+    // A block paying the scheduled subsidy keeps issuance on schedule
+    let reward = Transaction {
+        from: COINBASE_ADDRESS.to_string(),
+        to: "miner".to_string(),
+        amount: cli.params.subsidy_at(1),
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = cli.chain.tip().unwrap().header.hash.clone();
+    assert!(cli.chain.add_block(Block::new(prev_hash, vec![reward], 0, 1, 1)));
+    
+    let stats = cli.analyze_chain().monetary;
+    assert_eq!(stats.height, 1);
+    assert_eq!(stats.issued, stats.expected_issued);
+    assert_eq!(stats.blocks_until_halving, Some(cli.params.halving_interval));
+    
+    let schedule = cli.get_emission_schedule();
+    assert_eq!(schedule[0].subsidy, cli.params.initial_subsidy);
+    assert_eq!(schedule[1].subsidy, cli.params.initial_subsidy / 2);
+    assert_eq!(schedule.last().unwrap().supply_at_end, stats.max_supply);
+}
+
 #[test]
 fn test_advanced_wallet_operations() {
     let test_path = get_unique_test_path("test_advanced_wallet_operations");
@@ -277,3 +584,551 @@ fn test_advanced_wallet_operations() {
     let addresses = cli.list_addresses();
     assert!(!addresses.is_empty());
 }
+
+#[test]
+fn test_address_index_opt_out_and_rebuild() {
+    use rust_chain::blockchain::chain::{Chain, IndexConfig};
+    
+    let test_path = get_unique_test_path("test_address_index_opt_out");
+    let lean = IndexConfig { txindex: true, addressindex: false };
+    
+    let tx = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 100,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let tx_hash = tx.hash();
+    
+    {
+        let mut chain = Chain::new_persistent_with_config(&test_path, lean).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        assert!(chain.add_block(Block::new(prev_hash, vec![tx], 0, 1, 1)));
+        
+        // Hash lookups still work, address lookups are refused
+        assert!(chain.get_transaction(&tx_hash).unwrap().is_some());
+        assert!(chain.get_transactions_for_address("alice").is_err());
+    }
+    
+    // Re-enabling the address index rebuilds it from stored blocks
+    let chain = Chain::new_persistent_with_config(&test_path, IndexConfig::default()).expect("Failed to reopen chain");
+    let alice_txs = chain.get_transactions_for_address("alice").expect("Address index should be available");
+    assert!(alice_txs.iter().any(|(hash, _)| hash == &tx_hash));
+    
+    // addressindex without txindex is rejected
+    let invalid = IndexConfig { txindex: false, addressindex: true };
+    assert!(invalid.validate().is_err());
+}
+
+#[test]
+fn test_address_balance_index_follows_connect_and_disconnect() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::blockchain::genesis::COINBASE_ADDRESS;
+    
+    let test_path = get_unique_test_path("test_address_balance_index");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    
+    let tx = |from: &str, to: &str, amount| Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    let block = Block::new(prev_hash, vec![tx(COINBASE_ADDRESS, "alice", 500), tx("alice", "bob", 100), tx("bob", "bob", 5)], 0, 1, 1);
+    assert!(chain.add_block(block));
+    
+    let alice = chain.get_address_summary("alice").unwrap();
+    assert_eq!((alice.total_received, alice.total_sent, alice.transaction_count), (500, 100, 2));
+    assert_eq!(alice.balance(), 400);
+    // Self-transfers count as transactions but move no funds
+    let bob = chain.get_address_summary("bob").unwrap();
+    assert_eq!((bob.balance(), bob.transaction_count), (100, 2));
+    
+    let richest = chain.richest_addresses(2).unwrap();
+    assert_eq!(richest.len(), 2);
+    assert_eq!(richest[0].0, "1Community");
+    assert!(richest[0].1.balance() >= richest[1].1.balance());
+    
+    // A rebuild from stored blocks gives the same totals as the running index
+    chain.rebuild_address_index().unwrap();
+    assert_eq!(chain.get_address_summary("alice").unwrap(), alice);
+    
+    chain.disconnect_tip().unwrap();
+    let alice = chain.get_address_summary("alice").unwrap();
+    assert_eq!((alice.balance(), alice.transaction_count), (0, 0));
+    assert!(chain.richest_addresses(usize::MAX).unwrap().iter().all(|(address, _)| address != "bob"));
+}
+
+#[test]
+fn test_address_balances_backfilled_for_old_stores() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::blockchain::genesis::COINBASE_ADDRESS;
+    use rust_chain::storage::db::Database;
+    
+    let test_path = get_unique_test_path("test_address_balance_backfill");
+    let alice = {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        let coinbase = Transaction {
+            from: COINBASE_ADDRESS.to_string(),
+            to: "alice".to_string(),
+            amount: 500,
+            signature: vec![],
+            lock_height: None,
+            expiry_height: None,
+        };
+        assert!(chain.add_block(Block::new(prev_hash, vec![coinbase], 0, 1, 1)));
+        chain.get_address_summary("alice").unwrap()
+    };
+    
+    // A store written before address balances were indexed has none of them
+    {
+        let tx_db = Database::new_with_path(format!("{}/transactions", test_path)).expect("Failed to open tx db");
+        for key in tx_db.keys_with_prefix("addr_balance:").unwrap() {
+            tx_db.delete(&key).expect("Failed to delete");
+        }
+        tx_db.delete("meta:addressbalances").expect("Failed to delete");
+    }
+    
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reopen chain");
+    chain.set_params(ChainParams::regtest());
+    assert_eq!(chain.get_address_summary("alice").unwrap(), alice);
+    
+    // Disconnecting takes the balance back to nothing rather than below it
+    chain.disconnect_tip().unwrap();
+    assert_eq!(chain.get_address_summary("alice").unwrap().balance(), 0);
+}
+
+#[test]
+fn test_address_balance_at_height() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::blockchain::genesis::COINBASE_ADDRESS;
+    
+    let test_path = get_unique_test_path("test_address_balance_at_height");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    
+    let tx = |from: &str, to: &str, amount| Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    let block = Block::new(prev_hash, vec![tx(COINBASE_ADDRESS, "alice", 500), tx("alice", "bob", 100), tx("bob", "bob", 5), tx("alice", "bobby", 10)], 0, 1, 1);
+    assert!(chain.add_block(block));
+    let bob_at_1 = chain.get_address_summary("bob").unwrap();
+    let alice_at_1 = chain.get_address_summary("alice").unwrap();
+    
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(chain.add_block(Block::new(prev_hash, vec![tx("bob", "carol", 30)], 0, 2, 2)));
+    
+    // Replayed totals match what the index held at that height, without the
+    // payments to an address that merely starts with the same characters
+    assert_eq!(chain.get_address_summary_at("bob", 1).unwrap(), bob_at_1);
+    assert_eq!(chain.get_transactions_for_address("bob").unwrap().len(), 3);
+    assert_eq!(chain.get_address_summary_at("alice", 1).unwrap(), alice_at_1);
+    let bob = chain.get_address_summary_at("bob", 2).unwrap();
+    assert_eq!((bob.balance(), bob.transaction_count), (70, 3));
+    assert_eq!(bob, chain.get_address_summary("bob").unwrap());
+    let carol = chain.get_address_summary_at("carol", 1).unwrap();
+    assert_eq!((carol.balance(), carol.transaction_count), (0, 0));
+    assert_eq!(chain.get_address_summary_at("bob", 0).unwrap().balance(), 0);
+    
+    assert!(chain.get_address_summary_at("bob", 3).is_err());
+}
+
+#[test]
+fn test_transaction_status_follows_reorgs() {
+    use rust_chain::blockchain::chain::{Chain, TransactionStatus};
+    use rust_chain::blockchain::genesis::COINBASE_ADDRESS;
+    
+    let test_path = get_unique_test_path("test_transaction_status");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    
+    let tx = |from: &str, to: &str, amount| Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let coinbase = tx(COINBASE_ADDRESS, "alice", 50);
+    let spend = tx("alice", "bob", 10);
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    let block = Block::new(prev_hash, vec![coinbase.clone(), spend.clone()], 0, 1, 1);
+    assert!(chain.add_block(block.clone()));
+    assert_eq!(chain.transaction_status(&spend.hash()).unwrap().unwrap().status, TransactionStatus::Confirmed);
+    
+    // Disconnected transactions are reorged, except coinbases which can never return
+    chain.disconnect_tip().unwrap();
+    let record = chain.transaction_status(&spend.hash()).unwrap().unwrap();
+    assert_eq!((record.status, record.block_hash.as_str()), (TransactionStatus::Reorged, block.header.hash.as_str()));
+    assert_eq!(record.transaction.hash(), spend.hash());
+    assert_eq!(chain.transaction_status(&coinbase.hash()).unwrap().unwrap().status, TransactionStatus::Abandoned);
+    
+    chain.set_transaction_status(&spend.hash(), TransactionStatus::Mempool).unwrap();
+    assert_eq!(chain.transaction_status(&spend.hash()).unwrap().unwrap().status, TransactionStatus::Mempool);
+    assert!(chain.set_transaction_status("unknown", TransactionStatus::Mempool).is_err());
+    
+    // Confirmed again once a block holding it is connected
+    assert!(chain.add_block(block));
+    assert_eq!(chain.transaction_status(&spend.hash()).unwrap().unwrap().status, TransactionStatus::Confirmed);
+    chain.disconnect_tip().unwrap();
+    assert_eq!(chain.transaction_status(&spend.hash()).unwrap().unwrap().status, TransactionStatus::Reorged);
+}
+
+#[test]
+fn test_reorg_settles_disconnected_transaction_status() {
+    use rust_chain::blockchain::chain::{Chain, TransactionStatus};
+    use rust_chain::blockchain::genesis::COINBASE_ADDRESS;
+    use rust_chain::mempool::pool::Mempool;
+    
+    let test_path = get_unique_test_path("test_reorg_transaction_status");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    let mut mempool = Mempool::new();
+    
+    let tx = |from: &str, to: &str, amount| Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let spend = tx("alice", "bob", 10);
+    let genesis_hash = chain.tip().unwrap().header.hash.clone();
+    let block = Block::new(genesis_hash.clone(), vec![tx(COINBASE_ADDRESS, "alice", 50), spend.clone()], 0, 1, 1);
+    assert!(chain.add_block(block));
+    
+    // A longer branch without the spend replaces the block; with its funding
+    // coinbase gone the spend is abandoned rather than left reorged
+    let first = Block::new(genesis_hash.clone(), vec![], 0, 2, 1);
+    let second = Block::new(first.header.hash.clone(), vec![], 0, 3, 2);
+    let disconnected = chain.reorganize(&genesis_hash, vec![first, second]).unwrap();
+    assert_eq!(chain.transaction_status(&spend.hash()).unwrap().unwrap().status, TransactionStatus::Reorged);
+    
+    let (accepted, refused) = mempool.return_disconnected(&mut chain, &disconnected).unwrap();
+    assert!(accepted.is_empty());
+    assert_eq!(refused, vec![spend.hash()]);
+    assert_eq!(chain.transaction_status(&spend.hash()).unwrap().unwrap().status, TransactionStatus::Abandoned);
+}
+
+#[test]
+fn test_fork_tree_from_stored_branches() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::consensus::fork_choice::ForkTree;
+    
+    let test_path = get_unique_test_path("test_fork_tree_stored");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    let genesis_hash = chain.tip().unwrap().header.hash.clone();
+    let a1 = Block::new(genesis_hash.clone(), vec![], 1, 1, 1);
+    let a2 = Block::new(a1.header.hash.clone(), vec![], 2, 2, 2);
+    assert!(chain.add_block(a1.clone()));
+    assert!(chain.add_block(a2.clone()));
+    assert_eq!(ForkTree::from_stored_branches(&chain).unwrap().branches.len(), 1);
+    
+    // A longer branch from a1 takes over; a2 stays in the store
+    let b2 = Block::new(a1.header.hash.clone(), vec![], 20, 3, 2);
+    let b3 = Block::new(b2.header.hash.clone(), vec![], 30, 4, 3);
+    chain.reorganize(&a1.header.hash, vec![b2.clone(), b3.clone()]).unwrap();
+    drop(chain);
+    
+    // A later process reading the store sees the fork
+    let chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reload chain");
+    let tree = ForkTree::from_stored_branches(&chain).unwrap();
+    assert_eq!(tree.branches.len(), 2);
+    assert_eq!(tree.best().unwrap().tip_hash, b3.header.hash);
+    let branch = &tree.branches[1];
+    assert_eq!(branch.tip_hash, a2.header.hash);
+    assert_eq!((branch.fork_height, branch.fork_hash.as_str()), (1, a1.header.hash.as_str()));
+    assert_eq!((branch.length, branch.branch_work), (1, a2.work()));
+    assert_eq!(branch.chain_work, chain.chain_work() - b2.work() - b3.work() + a2.work());
+}
+
+#[test]
+fn test_lucky_bitless_fork_loses_to_chain_with_bits() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::consensus::params::REGTEST_POW_LIMIT_BITS;
+    use rust_chain::consensus::pow::ProofOfWork;
+    
+    let test_path = get_unique_test_path("test_lucky_bitless_fork");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    let genesis_hash = chain.tip().unwrap().header.hash.clone();
+    
+    // Regtest lets a block leave out bits; its hash happens to start with three zeros
+    let lucky = (0..).map(|nonce| Block::new(genesis_hash.clone(), vec![], nonce, 1, 1))
+        .find(|block| block.header.hash.starts_with("000"))
+        .unwrap();
+    assert_eq!(lucky.work(), 1);
+    assert!(chain.add_block(lucky.clone()));
+    chain.invalidate_block(&lucky.header.hash).unwrap();
+    
+    let mined = ProofOfWork::with_bits(REGTEST_POW_LIMIT_BITS).mine_block(genesis_hash, vec![], 1).block;
+    assert!(mined.work() > lucky.work());
+    assert!(chain.add_block(mined.clone()));
+    
+    // Its luck counts for nothing, so reconsidering it keeps the block with bits
+    let (connected, disconnected) = chain.reconsider_block(&lucky.header.hash).unwrap();
+    assert!(connected.is_empty() && disconnected.is_empty());
+    assert_eq!(chain.tip().unwrap().header.hash, mined.header.hash);
+    
+    // Above genesis, mainnet requires bits
+    let mut mainnet = Chain::new();
+    mainnet.set_params(ChainParams { pow_limit_bits: REGTEST_POW_LIMIT_BITS, ..ChainParams::mainnet() });
+    assert!(!mainnet.add_block(lucky));
+    assert!(mainnet.add_block(mined));
+}
+
+#[test]
+fn test_verify_storage_detects_and_repairs_index() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::storage::db::Database;
+    
+    let test_path = get_unique_test_path("test_verify_storage");
+    let tx = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 100,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let tx_hash = tx.hash();
+    
+    {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        assert!(chain.add_block(Block::new(prev_hash, vec![tx], 0, 1, 1)));
+        
+        let report = chain.verify_storage(3, false).expect("Verification failed");
+        assert!(report.is_ok(), "unexpected issues: {:?}", report.issues);
+        assert_eq!(report.blocks_checked, 2);
+    }
+    
+    // Corrupt the transaction index behind the chain's back
+    {
+        let tx_db = Database::new_with_path(format!("{}/transactions", test_path)).expect("Failed to open tx db");
+        tx_db.delete(&format!("tx:{}", tx_hash)).expect("Failed to delete");
+    }
+    
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reopen chain");
+    chain.set_params(ChainParams::regtest());
+    let report = chain.verify_storage(2, true).expect("Verification failed");
+    assert!(!report.is_ok());
+    assert!(report.repaired);
+    
+    let report = chain.verify_storage(3, false).expect("Verification failed");
+    assert!(report.is_ok(), "unexpected issues after repair: {:?}", report.issues);
+    assert!(chain.get_transaction(&tx_hash).unwrap().is_some());
+}
+
+#[test]
+fn test_verify_storage_detects_divergent_chainstate() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::storage::db::Database;
+    
+    let test_path = get_unique_test_path("test_verify_chainstate");
+    let tx = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 100,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    {
+        let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.set_params(ChainParams::regtest());
+        let prev_hash = chain.tip().unwrap().header.hash.clone();
+        assert!(chain.add_block(Block::new(prev_hash, vec![tx], 0, 1, 1)));
+    }
+    
+    // Change bob's balance and invent one for mallory behind the chain's back
+    {
+        let db = Database::new_with_path(&test_path).expect("Failed to open block db");
+        db.put("balance:bob".to_string(), 5u64.to_be_bytes().to_vec()).expect("Failed to write");
+        db.put("balance:mallory".to_string(), 7u64.to_be_bytes().to_vec()).expect("Failed to write");
+    }
+    
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reopen chain");
+    chain.set_params(ChainParams::regtest());
+    assert!(chain.verify_storage(2, false).expect("Verification failed").is_ok());
+    let report = chain.verify_storage(3, false).expect("Verification failed");
+    assert_eq!(report.issues, vec![
+        "Chainstate mismatch for bob: replay gives 100, store has 5".to_string(),
+        "Chainstate mismatch for mallory: replay gives 0, store has 7".to_string(),
+    ]);
+}
+
+#[test]
+fn test_verify_storage_accepts_multisig_spends() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::blockchain::multisig::{multisig_address, COSIGNATURE_LEN};
+    use rust_chain::wallet::offline::UnsignedTransaction;
+    
+    let test_path = get_unique_test_path("test_verify_storage_multisig");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    
+    // A 1-of-1 spend carries one 96-byte cosignature, not whole 64-byte signatures
+    let mut wallet = Wallet::new();
+    let key = wallet.generate_address().unwrap();
+    let address = multisig_address(1, &[key.clone()]).unwrap();
+    let mut unsigned = UnsignedTransaction::new(Transaction {
+        from: address,
+        to: "bob".to_string(),
+        amount: 25,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    });
+    unsigned.sign(&wallet, &key).unwrap();
+    let spend = unsigned.finalize().unwrap();
+    assert_eq!(spend.signature.len(), COSIGNATURE_LEN);
+    
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(chain.add_block(Block::new(prev_hash, vec![spend.clone()], 0, 1, 1)));
+    let report = chain.verify_storage(3, false).expect("Verification failed");
+    assert!(report.is_ok(), "unexpected issues: {:?}", report.issues);
+    
+    // A cosignature that does not sign the transaction is reported
+    let forged = Transaction { amount: 26, ..spend };
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(chain.add_block(Block::new(prev_hash, vec![forged], 0, 2, 2)));
+    let report = chain.verify_storage(3, false).expect("Verification failed");
+    assert_eq!(report.issues.len(), 1);
+    assert!(report.issues[0].contains("invalid cosignatures"));
+}
+
+#[test]
+fn test_vault_spends_wait_for_unlock_in_blocks() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::blockchain::vault::vault_address;
+    
+    let vault = vault_address("alice", 2, None).unwrap();
+    let from_vault = |to: &str, amount: u64| Transaction {
+        from: vault.clone(),
+        to: to.to_string(),
+        amount,
+        signature: vec![0; 64],
+        lock_height: None,
+        expiry_height: None,
+    };
+    
+    // Unlocks are found through the address index on disk and by scanning in memory
+    let test_path = get_unique_test_path("test_vault_spends_in_blocks");
+    let mut persistent = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    persistent.set_params(ChainParams::regtest());
+    for mut chain in [persistent, Chain::new()] {
+        let add = |chain: &mut Chain, tx: Option<Transaction>| {
+            let tip = chain.tip().unwrap().header.clone();
+            chain.add_block(Block::new(tip.hash, tx.into_iter().collect(), 0, tip.height + 1, tip.height + 1))
+        };
+        
+        // Declared at height 1, so spendable from height 3
+        assert!(add(&mut chain, Some(from_vault(&vault, 10))));
+        assert!(!add(&mut chain, Some(from_vault("bob", 5))));
+        assert!(add(&mut chain, None));
+        assert!(add(&mut chain, Some(from_vault("bob", 5))));
+        
+        // Only what is left of the declaration can follow
+        assert!(!add(&mut chain, Some(from_vault("bob", 6))));
+        assert!(add(&mut chain, Some(from_vault("bob", 4))));
+        assert!(!add(&mut chain, Some(from_vault("bob", 2))));
+    }
+}
+
+#[test]
+fn test_index_journal_recovery() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::storage::block_store::BlockStore;
+    
+    let test_path = get_unique_test_path("test_index_journal");
+    let tx = Transaction {
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        amount: 100,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let tx_hash = tx.hash();
+    
+    let genesis_hash = {
+        let chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+        chain.tip().unwrap().header.hash.clone()
+    };
+    
+    // Simulate a crash after the block write but before the index write
+    {
+        let store = BlockStore::new_with_path(&test_path).expect("Failed to open block store");
+        store.store_block_journaled(&Block::new(genesis_hash, vec![tx], 0, 1, 1)).expect("Failed to store block");
+        assert!(store.pending_index_journal().unwrap().is_some());
+    }
+    
+    let chain = Chain::new_persistent_with_path(&test_path).expect("Failed to reopen chain");
+    assert_eq!(chain.block_count(), 2);
+    assert!(chain.get_transaction(&tx_hash).unwrap().is_some());
+    drop(chain);
+    
+    let store = BlockStore::new_with_path(&test_path).expect("Failed to open block store");
+    assert!(store.pending_index_journal().unwrap().is_none());
+}
+
+#[test]
+fn test_transaction_proof_against_headers() {
+    use rust_chain::blockchain::chain::Chain;
+    use rust_chain::blockchain::genesis::COINBASE_ADDRESS;
+    use rust_chain::blockchain::merkle::MerkleProof;
+    
+    let test_path = get_unique_test_path("test_transaction_proof_against_headers");
+    let mut chain = Chain::new_persistent_with_path(&test_path).expect("Failed to create chain");
+    chain.set_params(ChainParams::regtest());
+    
+    let tx = |from: &str, to: &str, amount| Transaction {
+        from: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: vec![],
+        lock_height: None,
+        expiry_height: None,
+    };
+    let payment = tx("alice", "bob", 100);
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    let block = Block::new(prev_hash, vec![tx(COINBASE_ADDRESS, "alice", 500), payment.clone(), tx("alice", "carol", 5)], 0, 1, 1);
+    assert!(chain.add_block(block));
+    let prev_hash = chain.tip().unwrap().header.hash.clone();
+    assert!(chain.add_block(Block::new(prev_hash, vec![tx(COINBASE_ADDRESS, "dave", 500)], 0, 2, 2)));
+    
+    let proof = chain.transaction_proof(&payment.hash()).unwrap().expect("Proof for a confirmed transaction");
+    assert_eq!((proof.block_height, proof.index), (1, 1));
+    // The proof survives being passed around as hex
+    let proof = MerkleProof::from_hex(&proof.to_hex()).unwrap();
+    assert_eq!(chain.verify_transaction_proof(&proof), Ok(2));
+    
+    // A proof naming a block the local chain does not have is refused
+    let mut unknown = proof.clone();
+    unknown.block_hash = "00".repeat(32);
+    assert!(chain.verify_transaction_proof(&unknown).is_err());
+    let mut inflated = proof;
+    inflated.transaction.amount = 1_000;
+    assert!(chain.verify_transaction_proof(&inflated).is_err());
+    
+    assert!(chain.transaction_proof(&tx("alice", "erin", 1).hash()).unwrap().is_none());
+}
@@ -0,0 +1,22 @@
+use rust_chain::scenario::{Scenario, ScenarioRunner};
+
+/// Every scenario in tests/scenarios runs to the end on fresh nodes
+#[test]
+fn test_scenario_files() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scenarios");
+    let mut paths: Vec<_> = std::fs::read_dir(dir).expect("Failed to read scenarios")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    
+    for path in paths {
+        let path = path.to_str().unwrap();
+        let scenario = Scenario::load_from_file(path).unwrap();
+        let mut runner = ScenarioRunner::new();
+        if let Err(e) = runner.run(&scenario, |_, _| {}) {
+            panic!("Scenario {} failed: {}", path, e);
+        }
+    }
+}